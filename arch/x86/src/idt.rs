@@ -109,6 +109,14 @@ pub fn init() {
         virtualization_exception_handler as *const () as usize as u64,
     );
 
+    // Hardware interrupts from the PIC
+    for (irq, handler) in IRQ_HANDLERS.iter().enumerate() {
+        idt.set_handler(
+            crate::interrupts::PIC_VECTOR_BASE + irq as u8,
+            *handler as usize as u64,
+        );
+    }
+
     let pointer = idt.pointer();
 
     unsafe {
@@ -244,3 +252,35 @@ extern "x86-interrupt" fn simd_exception_handler(_stack_frame: InterruptStackFra
 extern "x86-interrupt" fn virtualization_exception_handler(_stack_frame: InterruptStackFrame) {
     panic!("EXCEPTION: Virtualization exception");
 }
+
+// Hardware interrupt entry points, one per PIC line
+macro_rules! irq_handlers {
+    ($($name:ident => $irq:expr),* $(,)?) => {
+        $(
+            extern "x86-interrupt" fn $name(_stack_frame: InterruptStackFrame) {
+                crate::interrupts::dispatch($irq);
+            }
+        )*
+
+        const IRQ_HANDLERS: [HandlerFunc; crate::interrupts::IRQ_LINES] = [$($name),*];
+    };
+}
+
+irq_handlers! {
+    irq0_handler => 0,
+    irq1_handler => 1,
+    irq2_handler => 2,
+    irq3_handler => 3,
+    irq4_handler => 4,
+    irq5_handler => 5,
+    irq6_handler => 6,
+    irq7_handler => 7,
+    irq8_handler => 8,
+    irq9_handler => 9,
+    irq10_handler => 10,
+    irq11_handler => 11,
+    irq12_handler => 12,
+    irq13_handler => 13,
+    irq14_handler => 14,
+    irq15_handler => 15,
+}
@@ -1,6 +1,40 @@
 //! Interrupt Management
 //!
 //! Interrupt handling and management.
+//!
+//! Device drivers attach handlers to the legacy PIC lines with
+//! [`register_handler`]. Lines may be shared, as PCI INTx lines often are,
+//! so every handler on a line runs and checks its own device.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Vector of the first PIC interrupt
+pub const PIC_VECTOR_BASE: u8 = 0x20;
+
+/// Number of PIC interrupt lines
+pub const IRQ_LINES: usize = 16;
+
+/// Handlers that may share a line
+const HANDLERS_PER_LINE: usize = 4;
+
+/// Interrupt handler, called with the IRQ number
+pub type IrqHandler = fn(u8);
+
+/// Interrupt registration errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// No such line
+    InvalidIrq,
+    /// Every handler slot of the line is in use
+    LineFull,
+}
+
+/// Registered handlers, stored as function addresses (0 = free slot)
+///
+/// Atomics rather than a lock, so dispatch never waits on code it
+/// interrupted.
+static HANDLERS: [[AtomicUsize; HANDLERS_PER_LINE]; IRQ_LINES] =
+    [const { [const { AtomicUsize::new(0) }; HANDLERS_PER_LINE] }; IRQ_LINES];
 
 /// Initialize interrupt controllers
 pub fn init() {
@@ -39,6 +73,62 @@ fn init_pic() {
     }
 }
 
+/// Attach a handler to an interrupt line and unmask it
+pub fn register_handler(irq: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    let line = HANDLERS.get(irq as usize).ok_or(IrqError::InvalidIrq)?;
+    let address = handler as usize;
+    if !line
+        .iter()
+        .any(|slot| slot.load(Ordering::Acquire) == address)
+    {
+        line.iter()
+            .find(|slot| {
+                slot.compare_exchange(0, address, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+            })
+            .ok_or(IrqError::LineFull)?;
+    }
+
+    // Lines on the slave PIC arrive through the cascade
+    if irq >= 8 {
+        enable_irq(2);
+    }
+    enable_irq(irq);
+    Ok(())
+}
+
+/// Detach a handler, masking the line once it has none left
+pub fn unregister_handler(irq: u8, handler: IrqHandler) {
+    let Some(line) = HANDLERS.get(irq as usize) else {
+        return;
+    };
+    let address = handler as usize;
+    for slot in line {
+        let _ = slot.compare_exchange(address, 0, Ordering::AcqRel, Ordering::Acquire);
+    }
+
+    if line.iter().all(|slot| slot.load(Ordering::Acquire) == 0) {
+        disable_irq(irq);
+    }
+}
+
+/// Run the handlers of an interrupt line and acknowledge it
+///
+/// Called from the IDT entry of each PIC vector.
+pub fn dispatch(irq: u8) {
+    if let Some(line) = HANDLERS.get(irq as usize) {
+        for slot in line {
+            let address = slot.load(Ordering::Acquire);
+            if address != 0 {
+                // Only addresses of `IrqHandler`s are ever stored
+                let handler: IrqHandler = unsafe { core::mem::transmute(address) };
+                handler(irq);
+            }
+        }
+    }
+    send_eoi(irq);
+}
+
 /// Enable an IRQ
 pub fn enable_irq(irq: u8) {
     use crate::io::{inb, outb};
@@ -68,6 +68,17 @@ pub fn halt() -> ! {
     }
 }
 
+/// Enable interrupts and sleep until the next one arrives
+///
+/// `sti` takes effect after the following instruction, so no interrupt
+/// can slip in between and leave `hlt` waiting forever.
+#[inline(always)]
+pub fn wait_for_interrupt() {
+    unsafe {
+        core::arch::asm!("sti; hlt", options(nomem, nostack));
+    }
+}
+
 /// Enable interrupts
 #[inline(always)]
 pub fn enable_interrupts() {
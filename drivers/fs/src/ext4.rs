@@ -1,23 +1,59 @@
 //! ext4 Filesystem Support
 //!
-//! Read/write support for the fourth extended filesystem (ext4)
+//! Support for the fourth extended filesystem (ext4)
 //! ext4 is mostly backwards compatible with ext2/ext3 but adds several improvements:
 //! - Extent trees instead of indirect blocks
-//! - Journaling (required)
+//! - Journaling
 //! - 48-bit block addresses (16TB+ filesystems)
 //! - Metadata checksums
-//! - Delayed allocation
-//! - Multi-block allocation
+//! - Hashed directory indexes
+//!
+//! A filesystem is mounted from a [`BlockDevice`], the same way as ext2.
+//! Files are mapped through extent trees of any depth (or ext2-style
+//! indirect blocks for inodes without `EXT4_EXTENTS_FL`), lookups in
+//! indexed directories go through the htree, and with `metadata_csum`
+//! every superblock, group descriptor, inode, extent block and directory
+//! block is checked before it is trusted.
+//!
+//! Every mutating VNode operation runs as one transaction. Metadata blocks
+//! it changes are kept in memory and committed to the journal together
+//! when it finishes (or dropped if it fails); file data is written in
+//! place before the commit, as in the kernel's `data=ordered` mode.
+//! Committed blocks reach their home locations at checkpoints, which
+//! happen when the log runs low on space, on sync and on unmount. A
+//! journal left with committed transactions by an unclean shutdown is
+//! replayed at mount. Filesystems without a journal write metadata home
+//! directly at the end of each operation.
+//!
+//! Only files mapped by extents can be written, and `rename` is not
+//! supported. Filesystems with read-only compatible features this
+//! driver does not know are mounted read-only.
+
+mod bitmap;
+mod checksum;
+mod dirent;
+mod extent;
+mod htree;
+mod journal;
 
+use crate::ext2::BlockDevice;
+use crate::vfs::{DirEntry, FileAttr, FileMode, FileType, Filesystem, StatFs, VNode};
 use crate::{FsError, FsType};
-use crate::vfs::{VNode, Filesystem, FileAttr, FileType, FileMode, DirEntry, StatFs};
-use alloc::sync::Arc;
-use alloc::vec::Vec;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::String;
-use spin::RwLock;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::mem;
+use extent::Extent;
+use journal::Journal;
+use spin::{Mutex, RwLock};
+
+pub use extent::EXT4_EXT_MAGIC;
+pub use htree::dirhash;
 
 /// ext4 Superblock (extended from ext2)
 #[repr(C, packed)]
+#[derive(Clone, Copy)]
 struct Ext4Superblock {
     // First 1024 bytes are identical to ext2
     s_inodes_count: u32,
@@ -45,7 +81,7 @@ struct Ext4Superblock {
     s_rev_level: u32,
     s_def_resuid: u16,
     s_def_resgid: u16,
-    
+
     // Extended superblock fields (ext4)
     s_first_ino: u32,
     s_inode_size: u16,
@@ -57,12 +93,12 @@ struct Ext4Superblock {
     s_volume_name: [u8; 16],
     s_last_mounted: [u8; 64],
     s_algorithm_usage_bitmap: u32,
-    
+
     // Performance hints
     s_prealloc_blocks: u8,
     s_prealloc_dir_blocks: u8,
     s_reserved_gdt_blocks: u16,
-    
+
     // Journaling support
     s_journal_uuid: [u8; 16],
     s_journal_inum: u32,
@@ -76,7 +112,7 @@ struct Ext4Superblock {
     s_first_meta_bg: u32,
     s_mkfs_time: u32,
     s_jnl_blocks: [u32; 17],
-    
+
     // 64-bit support
     s_blocks_count_hi: u32,
     s_r_blocks_count_hi: u32,
@@ -122,16 +158,9 @@ struct Ext4Superblock {
     s_checksum: u32,
 }
 
-/// ext4 magic number (same as ext2/ext3)
-const EXT4_MAGIC: u16 = 0xEF53;
-
-/// ext4 feature flags
-const EXT4_FEATURE_INCOMPAT_EXTENTS: u32 = 0x0040;
-const EXT4_FEATURE_INCOMPAT_64BIT: u32 = 0x0080;
-const EXT4_FEATURE_INCOMPAT_FLEX_BG: u32 = 0x0200;
-
 /// ext4 Inode (extended from ext2)
 #[repr(C, packed)]
+#[derive(Clone, Copy)]
 struct Ext4Inode {
     i_mode: u16,
     i_uid: u16,
@@ -151,7 +180,7 @@ struct Ext4Inode {
     i_size_high: u32,
     i_obso_faddr: u32,
     i_osd2: [u8; 12],
-    
+
     // Extended fields (for larger inodes)
     i_extra_isize: u16,
     i_checksum_hi: u16,
@@ -164,354 +193,2550 @@ struct Ext4Inode {
     i_projid: u32,
 }
 
-/// ext4 Extent Header
-#[repr(C, packed)]
-struct Ext4ExtentHeader {
-    eh_magic: u16,       // Magic number (0xF30A)
-    eh_entries: u16,     // Number of valid entries
-    eh_max: u16,         // Capacity of store
-    eh_depth: u16,       // Tree depth (0 = leaf node)
-    eh_generation: u32,  // Generation of the tree
+/// ext4 magic number (same as ext2/ext3)
+const EXT4_MAGIC: u16 = 0xEF53;
+
+/// Superblock location and size
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+
+/// Root directory inode
+const EXT4_ROOT_INO: u32 = 2;
+
+/// Inode size of revision 0 filesystems
+const EXT4_GOOD_OLD_INODE_SIZE: usize = 128;
+
+/// Group descriptor size without `64bit`
+const EXT4_MIN_DESC_SIZE: usize = 32;
+/// Group descriptor size with `64bit`
+const EXT4_MIN_DESC_SIZE_64BIT: usize = 64;
+
+/// ext4 compatible feature flags
+const EXT4_FEATURE_COMPAT_HAS_JOURNAL: u32 = 0x0004;
+const EXT4_FEATURE_COMPAT_DIR_INDEX: u32 = 0x0020;
+
+/// ext4 incompatible feature flags
+const EXT4_FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+const EXT4_FEATURE_INCOMPAT_RECOVER: u32 = 0x0004;
+const EXT4_FEATURE_INCOMPAT_EXTENTS: u32 = 0x0040;
+const EXT4_FEATURE_INCOMPAT_64BIT: u32 = 0x0080;
+const EXT4_FEATURE_INCOMPAT_MMP: u32 = 0x0100;
+const EXT4_FEATURE_INCOMPAT_FLEX_BG: u32 = 0x0200;
+const EXT4_FEATURE_INCOMPAT_CSUM_SEED: u32 = 0x2000;
+const EXT4_FEATURE_INCOMPAT_LARGEDIR: u32 = 0x4000;
+
+/// Incompatible features this driver understands
+const EXT4_FEATURE_INCOMPAT_SUPP: u32 = EXT4_FEATURE_INCOMPAT_FILETYPE
+    | EXT4_FEATURE_INCOMPAT_RECOVER
+    | EXT4_FEATURE_INCOMPAT_EXTENTS
+    | EXT4_FEATURE_INCOMPAT_64BIT
+    | EXT4_FEATURE_INCOMPAT_MMP
+    | EXT4_FEATURE_INCOMPAT_FLEX_BG
+    | EXT4_FEATURE_INCOMPAT_CSUM_SEED
+    | EXT4_FEATURE_INCOMPAT_LARGEDIR;
+
+/// ext4 read-only compatible feature flags
+const EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+const EXT4_FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+const EXT4_FEATURE_RO_COMPAT_HUGE_FILE: u32 = 0x0008;
+const EXT4_FEATURE_RO_COMPAT_DIR_NLINK: u32 = 0x0020;
+const EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE: u32 = 0x0040;
+const EXT4_FEATURE_RO_COMPAT_BIGALLOC: u32 = 0x0200;
+const EXT4_FEATURE_RO_COMPAT_METADATA_CSUM: u32 = 0x0400;
+
+/// Read-only compatible features this driver can keep up to date;
+/// filesystems with any other are mounted read-only
+const EXT4_FEATURE_RO_COMPAT_WRITE_SUPP: u32 = EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER
+    | EXT4_FEATURE_RO_COMPAT_LARGE_FILE
+    | EXT4_FEATURE_RO_COMPAT_HUGE_FILE
+    | EXT4_FEATURE_RO_COMPAT_DIR_NLINK
+    | EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE
+    | EXT4_FEATURE_RO_COMPAT_METADATA_CSUM;
+
+/// `s_checksum_type` for CRC32C
+const EXT4_CRC32C_CHKSUM: u8 = 1;
+
+/// `s_flags` bit selecting unsigned directory hashes
+const EXT2_FLAGS_UNSIGNED_HASH: u32 = 0x0002;
+
+/// Inode flags
+const EXT4_INDEX_FL: u32 = 0x0000_1000;
+const EXT4_HUGE_FILE_FL: u32 = 0x0004_0000;
+const EXT4_EXTENTS_FL: u32 = 0x0008_0000;
+
+/// Inode file types
+const EXT4_S_IFREG: u16 = 0x8000;
+const EXT4_S_IFDIR: u16 = 0x4000;
+const EXT4_S_IFLNK: u16 = 0xA000;
+const EXT4_S_IFCHR: u16 = 0x2000;
+const EXT4_S_IFBLK: u16 = 0x6000;
+const EXT4_S_IFIFO: u16 = 0x1000;
+const EXT4_S_IFSOCK: u16 = 0xC000;
+
+/// Directory entry file types
+const EXT4_FT_REG_FILE: u8 = 1;
+const EXT4_FT_DIR: u8 = 2;
+const EXT4_FT_CHRDEV: u8 = 3;
+const EXT4_FT_BLKDEV: u8 = 4;
+const EXT4_FT_FIFO: u8 = 5;
+const EXT4_FT_SOCK: u8 = 6;
+const EXT4_FT_SYMLINK: u8 = 7;
+
+/// Pseudo file type marking the checksum tail of a directory block
+const EXT4_FT_DIR_CSUM: u8 = 0xDE;
+
+/// Number of direct blocks in an indirect-mapped inode
+const EXT4_NDIR_BLOCKS: usize = 12;
+
+/// Symlink targets shorter than this live in `i_block`
+const EXT4_FAST_SYMLINK_MAX: u64 = 60;
+
+/// Links a directory can count before `dir_nlink` pins it at 1
+const EXT4_LINK_MAX: u16 = 65000;
+
+/// Read a little-endian u16 at `off`
+fn le16(buf: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([buf[off], buf[off + 1]])
 }
 
-/// ext4 Extent Index (internal node)
-#[repr(C, packed)]
-struct Ext4ExtentIdx {
-    ei_block: u32,       // Logical block number
-    ei_leaf_lo: u32,     // Physical block of next level (low 32 bits)
-    ei_leaf_hi: u16,     // Physical block of next level (high 16 bits)
-    ei_unused: u16,
+/// Read a little-endian u32 at `off`
+fn le32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
 }
 
-/// ext4 Extent (leaf node)
+/// ext4 Group Descriptor
+///
+/// Filesystems without `64bit` use only the first 32 bytes; the high
+/// halves then read as zero.
 #[repr(C, packed)]
-struct Ext4Extent {
-    ee_block: u32,       // First logical block
-    ee_len: u16,         // Number of blocks
-    ee_start_hi: u16,    // Physical block (high 16 bits)
-    ee_start_lo: u32,    // Physical block (low 32 bits)
+#[derive(Clone, Copy)]
+struct Ext4GroupDesc {
+    bg_block_bitmap_lo: u32,
+    bg_inode_bitmap_lo: u32,
+    bg_inode_table_lo: u32,
+    bg_free_blocks_count_lo: u16,
+    bg_free_inodes_count_lo: u16,
+    bg_used_dirs_count_lo: u16,
+    bg_flags: u16,
+    bg_exclude_bitmap_lo: u32,
+    bg_block_bitmap_csum_lo: u16,
+    bg_inode_bitmap_csum_lo: u16,
+    bg_itable_unused_lo: u16,
+    bg_checksum: u16,
+    bg_block_bitmap_hi: u32,
+    bg_inode_bitmap_hi: u32,
+    bg_inode_table_hi: u32,
+    bg_free_blocks_count_hi: u16,
+    bg_free_inodes_count_hi: u16,
+    bg_used_dirs_count_hi: u16,
+    bg_itable_unused_hi: u16,
+    bg_exclude_bitmap_hi: u32,
+    bg_block_bitmap_csum_hi: u16,
+    bg_inode_bitmap_csum_hi: u16,
+    bg_reserved: u32,
 }
 
-/// ext4 extent magic
-const EXT4_EXT_MAGIC: u16 = 0xF30A;
+impl Ext4GroupDesc {
+    fn block_bitmap(&self) -> u64 {
+        self.bg_block_bitmap_lo as u64 | (self.bg_block_bitmap_hi as u64) << 32
+    }
 
-/// ext4 VNode
-pub struct Ext4VNode {
-    fs: Arc<Ext4Filesystem>,
-    ino: u64,
+    fn inode_bitmap(&self) -> u64 {
+        self.bg_inode_bitmap_lo as u64 | (self.bg_inode_bitmap_hi as u64) << 32
+    }
+
+    fn inode_table(&self) -> u64 {
+        self.bg_inode_table_lo as u64 | (self.bg_inode_table_hi as u64) << 32
+    }
+
+    fn free_blocks(&self) -> u32 {
+        self.bg_free_blocks_count_lo as u32 | (self.bg_free_blocks_count_hi as u32) << 16
+    }
+
+    fn set_free_blocks(&mut self, count: u32) {
+        self.bg_free_blocks_count_lo = count as u16;
+        self.bg_free_blocks_count_hi = (count >> 16) as u16;
+    }
+
+    fn free_inodes(&self) -> u32 {
+        self.bg_free_inodes_count_lo as u32 | (self.bg_free_inodes_count_hi as u32) << 16
+    }
+
+    fn set_free_inodes(&mut self, count: u32) {
+        self.bg_free_inodes_count_lo = count as u16;
+        self.bg_free_inodes_count_hi = (count >> 16) as u16;
+    }
+
+    fn used_dirs(&self) -> u32 {
+        self.bg_used_dirs_count_lo as u32 | (self.bg_used_dirs_count_hi as u32) << 16
+    }
+
+    fn set_used_dirs(&mut self, count: u32) {
+        self.bg_used_dirs_count_lo = count as u16;
+        self.bg_used_dirs_count_hi = (count >> 16) as u16;
+    }
+
+    fn itable_unused(&self) -> u32 {
+        self.bg_itable_unused_lo as u32 | (self.bg_itable_unused_hi as u32) << 16
+    }
+
+    fn set_itable_unused(&mut self, count: u32) {
+        self.bg_itable_unused_lo = count as u16;
+        self.bg_itable_unused_hi = (count >> 16) as u16;
+    }
 }
 
-impl Ext4VNode {
-    fn new(fs: Arc<Ext4Filesystem>, ino: u64) -> Self {
-        Ext4VNode { fs, ino }
+/// An inode as read from disk
+struct Inode {
+    ino: u32,
+    raw: Ext4Inode,
+    /// Seed for checksums of the inode's own metadata blocks
+    csum_seed: u32,
+}
+
+impl Inode {
+    fn mode(&self) -> u16 {
+        self.raw.i_mode
     }
 
-    fn read_inode(&self) -> Result<Ext4Inode, FsError> {
-        // Calculate block group and inode table offset
-        // Read inode from device
-        // For now, this is a stub
-        Err(FsError::NotFound)
+    fn is_dir(&self) -> bool {
+        self.mode() & 0xF000 == EXT4_S_IFDIR
     }
 
-    /// Get physical block number from logical block using extent tree
-    fn map_block(&self, logical_block: u64) -> Result<u64, FsError> {
-        let inode = self.read_inode()?;
+    fn flags(&self) -> u32 {
+        self.raw.i_flags
+    }
 
-        // Check if inode uses extents
-        if inode.i_flags & 0x80000 != 0 {
-            // EXT4_EXTENTS_FL flag set - use extent tree
-            self.map_block_extent(logical_block, &inode)
-        } else {
-            // Old-style indirect blocks
-            self.map_block_indirect(logical_block, &inode)
-        }
+    fn size(&self) -> u64 {
+        self.raw.i_size_lo as u64 | (self.raw.i_size_high as u64) << 32
     }
 
-    fn map_block_extent(&self, logical_block: u64, inode: &Ext4Inode) -> Result<u64, FsError> {
-        // i_block contains the extent tree root
-        let extent_header = unsafe {
-            &*(inode.i_block.as_ptr() as *const Ext4ExtentHeader)
-        };
+    fn set_size(&mut self, size: u64) {
+        self.raw.i_size_lo = size as u32;
+        self.raw.i_size_high = (size >> 32) as u32;
+    }
 
-        if extent_header.eh_magic != EXT4_EXT_MAGIC {
-            return Err(FsError::InvalidData);
+    fn file_type(&self) -> FileType {
+        match self.mode() & 0xF000 {
+            EXT4_S_IFREG => FileType::Regular,
+            EXT4_S_IFDIR => FileType::Directory,
+            EXT4_S_IFLNK => FileType::Symlink,
+            EXT4_S_IFCHR => FileType::CharDevice,
+            EXT4_S_IFBLK => FileType::BlockDevice,
+            EXT4_S_IFIFO => FileType::Fifo,
+            EXT4_S_IFSOCK => FileType::Socket,
+            _ => FileType::Regular,
         }
+    }
 
-        // For now, only handle depth 0 (single-level extents)
-        if extent_header.eh_depth != 0 {
-            return Err(FsError::NotSupported);
+    /// `i_block` as the bytes it holds on disk
+    fn i_block(&self) -> [u8; 60] {
+        let words = self.raw.i_block;
+        let mut bytes = [0u8; 60];
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
         }
+        bytes
+    }
 
-        // Search extents
-        let extents = unsafe {
-            core::slice::from_raw_parts(
-                (inode.i_block.as_ptr() as usize + core::mem::size_of::<Ext4ExtentHeader>()) as *const Ext4Extent,
-                extent_header.eh_entries as usize
-            )
-        };
-
-        for extent in extents {
-            let start = extent.ee_block as u64;
-            let len = (extent.ee_len & 0x7FFF) as u64; // Clear initialized flag
-            
-            if logical_block >= start && logical_block < start + len {
-                // Found the extent
-                let phys_start = ((extent.ee_start_hi as u64) << 32) | (extent.ee_start_lo as u64);
-                let offset = logical_block - start;
-                return Ok(phys_start + offset);
-            }
+    /// Replace `i_block` with the bytes to store on disk
+    fn set_i_block(&mut self, bytes: &[u8; 60]) {
+        let mut words = [0u32; 15];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = le32(chunk, 0);
         }
+        self.raw.i_block = words;
+    }
 
-        // Block not found (sparse file)
-        Ok(0)
+    /// Field of `i_osd2` (Linux layout) at `off`
+    fn osd2(&self, off: usize) -> u16 {
+        let osd2 = self.raw.i_osd2;
+        le16(&osd2, off)
     }
 
-    fn map_block_indirect(&self, logical_block: u64, inode: &Ext4Inode) -> Result<u64, FsError> {
-        // Handle old-style indirect blocks (like ext2)
-        if logical_block < 12 {
-            // Direct blocks
-            Ok(inode.i_block[logical_block as usize] as u64)
-        } else if logical_block < 12 + 256 {
-            // Single indirect
-            // Would need to read the indirect block
-            Err(FsError::NotSupported)
-        } else {
-            // Double/triple indirect
-            Err(FsError::NotSupported)
-        }
+    fn set_osd2(&mut self, off: usize, value: u16) {
+        let mut osd2 = self.raw.i_osd2;
+        osd2[off..off + 2].copy_from_slice(&value.to_le_bytes());
+        self.raw.i_osd2 = osd2;
     }
 
-    fn get_file_size(&self, inode: &Ext4Inode) -> u64 {
-        // ext4 supports 64-bit file sizes
-        ((inode.i_size_high as u64) << 32) | (inode.i_size_lo as u64)
+    /// Stamp the change time, and the modification time with it
+    fn touch(&mut self, modified: bool) {
+        let now = now();
+        self.raw.i_ctime = now;
+        if modified {
+            self.raw.i_mtime = now;
+        }
     }
 }
 
-impl VNode for Ext4VNode {
-    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
-        let inode = self.read_inode()?;
-        let file_size = self.get_file_size(&inode);
+/// Current time for timestamps
+fn now() -> u32 {
+    rinux_kernel::time::SystemTime::now().seconds as u32
+}
 
-        if offset >= file_size {
-            return Ok(0);
+/// A used entry of a directory block
+struct RawDirEntry<'a> {
+    ino: u32,
+    file_type: u8,
+    name: &'a [u8],
+}
+
+/// Parse the used entries of a directory block
+fn parse_dir_block(block: &[u8]) -> Result<Vec<RawDirEntry<'_>>, FsError> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos < block.len() {
+        if pos + 8 > block.len() {
+            return Err(FsError::InvalidData);
+        }
+        let ino = le32(block, pos);
+        let rec_len = le16(block, pos + 4) as usize;
+        let name_len = block[pos + 6] as usize;
+        if rec_len < 8
+            || !rec_len.is_multiple_of(4)
+            || pos + rec_len > block.len()
+            || name_len + 8 > rec_len
+        {
+            return Err(FsError::InvalidData);
+        }
+        if ino != 0 {
+            entries.push(RawDirEntry {
+                ino,
+                file_type: block[pos + 7],
+                name: &block[pos + 8..pos + 8 + name_len],
+            });
         }
+        pos += rec_len;
+    }
+    Ok(entries)
+}
 
-        let block_size = self.fs.block_size as u64;
-        let max_read = ((file_size - offset).min(buffer.len() as u64)) as usize;
-        let mut bytes_read = 0;
+/// Map a directory entry file type
+fn dirent_file_type(file_type: u8) -> Option<FileType> {
+    match file_type {
+        EXT4_FT_REG_FILE => Some(FileType::Regular),
+        EXT4_FT_DIR => Some(FileType::Directory),
+        EXT4_FT_CHRDEV => Some(FileType::CharDevice),
+        EXT4_FT_BLKDEV => Some(FileType::BlockDevice),
+        EXT4_FT_FIFO => Some(FileType::Fifo),
+        EXT4_FT_SOCK => Some(FileType::Socket),
+        EXT4_FT_SYMLINK => Some(FileType::Symlink),
+        _ => None,
+    }
+}
+
+/// Read `len` bytes at byte `offset` of a device
+fn read_bytes(device: &dyn BlockDevice, offset: u64, len: usize) -> Result<Vec<u8>, FsError> {
+    let sector = device.block_size();
+    let skip = (offset % sector as u64) as usize;
+    let mut buffer = vec![0u8; (skip + len).div_ceil(sector) * sector];
+    device
+        .read_blocks(offset / sector as u64, &mut buffer)
+        .map_err(|_| FsError::IoError)?;
+    buffer.drain(..skip);
+    buffer.truncate(len);
+    Ok(buffer)
+}
 
-        while bytes_read < max_read {
-            let current_offset = offset + bytes_read as u64;
-            let logical_block = current_offset / block_size;
-            let block_offset = (current_offset % block_size) as usize;
+/// Write `data` at byte `offset` of a device, keeping the rest of the
+/// sectors it touches
+fn write_bytes(device: &dyn BlockDevice, offset: u64, data: &[u8]) -> Result<(), FsError> {
+    let sector = device.block_size() as u64;
+    let start = offset / sector * sector;
+    let skip = (offset - start) as usize;
+    let span = (skip + data.len()).div_ceil(sector as usize) * sector as usize;
+    let mut buffer = read_bytes(device, start, span)?;
+    buffer[skip..skip + data.len()].copy_from_slice(data);
+    device
+        .write_blocks(start / sector, &buffer)
+        .map_err(|_| FsError::IoError)?;
+    Ok(())
+}
 
-            // Map logical to physical block
-            let physical_block = self.map_block(logical_block)?;
+/// Metadata blocks whose latest contents are not at their home location yet
+#[derive(Default)]
+struct BlockCache {
+    /// Changed by the running transaction
+    running: BTreeMap<u64, Vec<u8>>,
+    /// Freed by the running transaction after being used as metadata, so
+    /// older copies in the journal must not be replayed
+    revoked: BTreeSet<u64>,
+    /// Freed by the running transaction; not reused until it commits
+    freed: Vec<(u64, u64)>,
+    /// Committed to the journal and waiting for a checkpoint
+    committed: BTreeMap<u64, Vec<u8>>,
+}
 
-            if physical_block == 0 {
-                // Sparse block - fill with zeros
-                let bytes_in_block = ((block_size - block_offset as u64) as usize).min(max_read - bytes_read);
-                buffer[bytes_read..bytes_read + bytes_in_block].fill(0);
-                bytes_read += bytes_in_block;
-                continue;
-            }
+impl BlockCache {
+    fn get(&self, block: u64) -> Option<&Vec<u8>> {
+        self.running
+            .get(&block)
+            .or_else(|| self.committed.get(&block))
+    }
+
+    fn is_freed(&self, block: u64) -> bool {
+        self.freed
+            .iter()
+            .any(|&(start, count)| block >= start && block < start + count)
+    }
+}
+
+/// ext4 VNode
+pub struct Ext4VNode {
+    fs: Arc<Ext4Filesystem>,
+    ino: u32,
+}
+
+impl Ext4VNode {
+    fn new(fs: Arc<Ext4Filesystem>, ino: u32) -> Self {
+        Ext4VNode { fs, ino }
+    }
+
+    fn read_inode(&self) -> Result<Inode, FsError> {
+        self.fs.read_inode(self.ino)
+    }
+
+    /// Read this directory before adding or removing `name` in it
+    fn read_dir_for_update(&self, name: &str) -> Result<Inode, FsError> {
+        let dir = self.read_inode()?;
+        if !dir.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        if name == "." || name == ".." {
+            return Err(FsError::InvalidArgument);
+        }
+        Ok(dir)
+    }
 
-            // Read block from device
-            // TODO: Integrate with actual block device
-            // For now, fill with zeros as stub
-            let bytes_in_block = ((block_size - block_offset as u64) as usize).min(max_read - bytes_read);
-            buffer[bytes_read..bytes_read + bytes_in_block].fill(0);
-            bytes_read += bytes_in_block;
+    /// Read this directory before creating `name` in it
+    fn read_dir_for_create(&self, name: &str) -> Result<Inode, FsError> {
+        let dir = self.read_dir_for_update(name)?;
+        if self.fs.find_entry(&dir, name.as_bytes())?.is_some() {
+            return Err(FsError::AlreadyExists);
         }
+        Ok(dir)
+    }
+}
 
-        Ok(bytes_read)
+impl VNode for Ext4VNode {
+    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let inode = self.read_inode()?;
+        self.fs.read_data(&inode, offset, buffer)
     }
 
     fn write(&self, offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
-        // Similar to read but for writing
-        let _ = (offset, buffer);
-        Err(FsError::NotSupported)
+        self.fs.transaction(|| {
+            let mut inode = self.read_inode()?;
+            if inode.is_dir() {
+                return Err(FsError::IsADirectory);
+            }
+            let written = self.fs.write_data(&mut inode, offset, buffer)?;
+            inode.touch(true);
+            self.fs.write_inode(&inode)?;
+            Ok(written)
+        })
     }
 
     fn getattr(&self) -> Result<FileAttr, FsError> {
         let inode = self.read_inode()?;
+        let raw = &inode.raw;
 
-        let file_type = match inode.i_mode & 0xF000 {
-            0x8000 => FileType::Regular,
-            0x4000 => FileType::Directory,
-            0xA000 => FileType::Symlink,
-            0x2000 => FileType::CharDevice,
-            0x6000 => FileType::BlockDevice,
-            0x1000 => FileType::Fifo,
-            0xC000 => FileType::Socket,
-            _ => FileType::Regular,
-        };
+        let mut blocks = raw.i_blocks_lo as u64;
+        if self
+            .fs
+            .has_feature_ro_compat(EXT4_FEATURE_RO_COMPAT_HUGE_FILE)
+        {
+            blocks |= (inode.osd2(0) as u64) << 32;
+            if inode.flags() & EXT4_HUGE_FILE_FL != 0 {
+                // Counted in filesystem blocks rather than sectors
+                blocks *= self.fs.block_size as u64 / 512;
+            }
+        }
 
         Ok(FileAttr {
-            file_type,
-            mode: FileMode::new((inode.i_mode & 0x0FFF) as u32),
-            size: self.get_file_size(&inode),
-            nlink: inode.i_links_count as u32,
-            uid: inode.i_uid as u32,
-            gid: inode.i_gid as u32,
-            ino: self.ino,
-            blocks: inode.i_blocks_lo as u64,
-            atime: inode.i_atime as u64,
-            mtime: inode.i_mtime as u64,
-            ctime: inode.i_ctime as u64,
+            file_type: inode.file_type(),
+            mode: FileMode::new((inode.mode() & 0x0FFF) as u32),
+            size: inode.size(),
+            nlink: raw.i_links_count as u32,
+            uid: raw.i_uid as u32 | (inode.osd2(4) as u32) << 16,
+            gid: raw.i_gid as u32 | (inode.osd2(6) as u32) << 16,
+            ino: self.ino as u64,
+            blocks,
+            atime: raw.i_atime as u64,
+            mtime: raw.i_mtime as u64,
+            ctime: raw.i_ctime as u64,
         })
     }
 
-    fn setattr(&self, _attr: &FileAttr) -> Result<(), FsError> {
-        Err(FsError::NotSupported)
+    fn setattr(&self, attr: &FileAttr) -> Result<(), FsError> {
+        self.fs.transaction(|| {
+            let mut inode = self.read_inode()?;
+            if attr.size != inode.size() {
+                if inode.is_dir() {
+                    return Err(FsError::IsADirectory);
+                }
+                self.fs.truncate_inode(&mut inode, attr.size)?;
+            }
+
+            let raw = &mut inode.raw;
+            raw.i_mode = (raw.i_mode & 0xF000) | (attr.mode.0 & 0x0FFF) as u16;
+            raw.i_uid = attr.uid as u16;
+            raw.i_gid = attr.gid as u16;
+            raw.i_atime = attr.atime as u32;
+            raw.i_mtime = attr.mtime as u32;
+            raw.i_ctime = attr.ctime as u32;
+            inode.set_osd2(4, (attr.uid >> 16) as u16);
+            inode.set_osd2(6, (attr.gid >> 16) as u16);
+            self.fs.write_inode(&inode)
+        })
     }
 
     fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
-        // Read directory using htree or linear format
-        Err(FsError::NotSupported)
+        let inode = self.read_inode()?;
+        if !inode.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+
+        let mut entries = Vec::new();
+        for logical in 0..self.fs.block_count(&inode) {
+            let block = self.fs.read_dir_block(&inode, logical)?;
+            self.fs.verify_dir_block(&inode, logical, &block)?;
+            for entry in parse_dir_block(&block)? {
+                let Ok(name) = core::str::from_utf8(entry.name) else {
+                    continue;
+                };
+                let file_type = match self.fs.dirent_type(entry.file_type) {
+                    Some(file_type) => file_type,
+                    None => self.fs.read_inode(entry.ino)?.file_type(),
+                };
+                entries.push(DirEntry {
+                    ino: entry.ino as u64,
+                    name: String::from(name),
+                    file_type,
+                });
+            }
+        }
+
+        Ok(entries)
     }
 
-    fn lookup(&self, _name: &str) -> Result<Arc<dyn VNode>, FsError> {
-        Err(FsError::NotFound)
+    fn lookup(&self, name: &str) -> Result<Arc<dyn VNode>, FsError> {
+        let inode = self.read_inode()?;
+        if !inode.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+
+        let ino = self
+            .fs
+            .find_entry(&inode, name.as_bytes())?
+            .ok_or(FsError::NotFound)?;
+        Ok(Arc::new(Ext4VNode::new(Arc::clone(&self.fs), ino)))
     }
 
-    fn create(&self, _name: &str, _mode: FileMode) -> Result<Arc<dyn VNode>, FsError> {
-        Err(FsError::NotSupported)
+    fn create(&self, name: &str, mode: FileMode) -> Result<Arc<dyn VNode>, FsError> {
+        let ino = self.fs.transaction(|| {
+            let mut dir = self.read_dir_for_create(name)?;
+            let inode = self
+                .fs
+                .new_inode(&dir, EXT4_S_IFREG | (mode.0 & 0x0FFF) as u16)?;
+            self.fs.write_inode(&inode)?;
+            self.fs
+                .add_entry(&mut dir, name.as_bytes(), inode.ino, EXT4_FT_REG_FILE)?;
+            dir.touch(true);
+            self.fs.write_inode(&dir)?;
+            Ok(inode.ino)
+        })?;
+        Ok(Arc::new(Ext4VNode::new(Arc::clone(&self.fs), ino)))
     }
 
-    fn mkdir(&self, _name: &str, _mode: FileMode) -> Result<Arc<dyn VNode>, FsError> {
-        Err(FsError::NotSupported)
+    fn mkdir(&self, name: &str, mode: FileMode) -> Result<Arc<dyn VNode>, FsError> {
+        let ino = self.fs.transaction(|| {
+            let mut dir = self.read_dir_for_create(name)?;
+            let mut inode = self
+                .fs
+                .new_inode(&dir, EXT4_S_IFDIR | (mode.0 & 0x0FFF) as u16)?;
+            inode.raw.i_links_count = 2;
+            let (_, block) = self.fs.append_block(&mut inode)?;
+            self.fs.init_dir_block(&inode, block, inode.ino, dir.ino);
+            self.fs.write_inode(&inode)?;
+
+            self.fs
+                .add_entry(&mut dir, name.as_bytes(), inode.ino, EXT4_FT_DIR)?;
+            self.fs.link_dir(&mut dir)?;
+            dir.touch(true);
+            self.fs.write_inode(&dir)?;
+            Ok(inode.ino)
+        })?;
+        Ok(Arc::new(Ext4VNode::new(Arc::clone(&self.fs), ino)))
     }
 
-    fn unlink(&self, _name: &str) -> Result<(), FsError> {
-        Err(FsError::NotSupported)
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        self.fs.transaction(|| {
+            let mut dir = self.read_dir_for_update(name)?;
+            let ino = self
+                .fs
+                .find_entry(&dir, name.as_bytes())?
+                .ok_or(FsError::NotFound)?;
+            let mut inode = self.fs.read_inode(ino)?;
+            if inode.is_dir() {
+                return Err(FsError::IsADirectory);
+            }
+
+            self.fs.remove_entry(&dir, name.as_bytes())?;
+            dir.touch(true);
+            self.fs.write_inode(&dir)?;
+            inode.raw.i_links_count = inode.raw.i_links_count.saturating_sub(1);
+            inode.touch(false);
+            self.fs.drop_link(inode)
+        })
     }
 
-    fn rmdir(&self, _name: &str) -> Result<(), FsError> {
-        Err(FsError::NotSupported)
+    fn rmdir(&self, name: &str) -> Result<(), FsError> {
+        self.fs.transaction(|| {
+            let mut dir = self.read_dir_for_update(name)?;
+            let ino = self
+                .fs
+                .find_entry(&dir, name.as_bytes())?
+                .ok_or(FsError::NotFound)?;
+            let mut inode = self.fs.read_inode(ino)?;
+            if !inode.is_dir() {
+                return Err(FsError::NotADirectory);
+            }
+            if !self.fs.dir_is_empty(&inode)? {
+                return Err(FsError::NotEmpty);
+            }
+
+            self.fs.remove_entry(&dir, name.as_bytes())?;
+            self.fs.unlink_dir(&mut dir);
+            dir.touch(true);
+            self.fs.write_inode(&dir)?;
+            inode.raw.i_links_count = 0;
+            self.fs.drop_link(inode)
+        })
     }
 
-    fn rename(&self, _old_name: &str, _new_parent: Arc<dyn VNode>, _new_name: &str) -> Result<(), FsError> {
+    fn rename(
+        &self,
+        _old_name: &str,
+        _new_parent: Arc<dyn VNode>,
+        _new_name: &str,
+    ) -> Result<(), FsError> {
         Err(FsError::NotSupported)
     }
 
-    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn VNode>, FsError> {
-        Err(FsError::NotSupported)
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn VNode>, FsError> {
+        let ino = self.fs.transaction(|| {
+            let mut dir = self.read_dir_for_create(name)?;
+            let mut inode = self.fs.new_inode(&dir, EXT4_S_IFLNK | 0o777)?;
+            if (target.len() as u64) < EXT4_FAST_SYMLINK_MAX {
+                // Fast symlinks keep the target in place of the block map
+                let mut i_block = [0u8; 60];
+                i_block[..target.len()].copy_from_slice(target.as_bytes());
+                inode.raw.i_flags &= !EXT4_EXTENTS_FL;
+                inode.set_i_block(&i_block);
+                inode.set_size(target.len() as u64);
+            } else {
+                self.fs.write_data(&mut inode, 0, target.as_bytes())?;
+            }
+            self.fs.write_inode(&inode)?;
+
+            self.fs
+                .add_entry(&mut dir, name.as_bytes(), inode.ino, EXT4_FT_SYMLINK)?;
+            dir.touch(true);
+            self.fs.write_inode(&dir)?;
+            Ok(inode.ino)
+        })?;
+        Ok(Arc::new(Ext4VNode::new(Arc::clone(&self.fs), ino)))
     }
 
     fn readlink(&self) -> Result<String, FsError> {
-        Err(FsError::NotSupported)
+        let inode = self.read_inode()?;
+        if inode.mode() & 0xF000 != EXT4_S_IFLNK {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let size = inode.size();
+        let target = if size < EXT4_FAST_SYMLINK_MAX {
+            inode.i_block()[..size as usize].to_vec()
+        } else {
+            let mut target = vec![0u8; size as usize];
+            let len = self.fs.read_data(&inode, 0, &mut target)?;
+            target.truncate(len);
+            target
+        };
+        String::from_utf8(target).map_err(|_| FsError::InvalidData)
     }
 
-    fn truncate(&self, _size: u64) -> Result<(), FsError> {
-        Err(FsError::NotSupported)
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        self.fs.transaction(|| {
+            let mut inode = self.read_inode()?;
+            if inode.is_dir() {
+                return Err(FsError::IsADirectory);
+            }
+            self.fs.truncate_inode(&mut inode, size)?;
+            inode.touch(true);
+            self.fs.write_inode(&inode)
+        })
     }
 
     fn fsync(&self) -> Result<(), FsError> {
+        // Every operation commits before it returns
         Ok(())
     }
 }
 
 /// ext4 Filesystem
 pub struct Ext4Filesystem {
+    device: Arc<dyn BlockDevice>,
+    superblock: RwLock<Ext4Superblock>,
+    groups: RwLock<Vec<Ext4GroupDesc>>,
     block_size: u32,
-    root_ino: u64,
+    blocks_count: u64,
+    first_data_block: u64,
+    blocks_per_group: u32,
+    desc_size: usize,
+    inode_size: usize,
+    inodes_per_group: u32,
     features_compat: u32,
     features_incompat: u32,
     features_ro_compat: u32,
+    /// Seed for all metadata checksums
+    csum_seed: u32,
+    /// Seed for directory name hashes
+    hash_seed: [u32; 4],
+    /// Whether directory hashes widen name bytes as unsigned
+    unsigned_hash: bool,
+    /// Whether every read-only compatible feature is understood
+    writable: bool,
+    /// Metadata not yet at its home location
+    cache: Mutex<BlockCache>,
+    journal: Option<Mutex<Journal>>,
+    /// Held for the whole of each transaction; one runs at a time
+    transaction_lock: Mutex<()>,
+    /// Handle to ourselves for the VNodes we hand out
+    this: Weak<Ext4Filesystem>,
 }
 
 impl Ext4Filesystem {
     /// Mount an ext4 filesystem from a block device
-    pub fn mount() -> Result<Arc<Self>, FsError> {
-        // Read superblock from block 1 (1024 bytes offset)
-        // Verify magic number
-        // Check feature flags
-        // Verify journal
-        
-        Ok(Arc::new(Ext4Filesystem {
-            block_size: 4096,
-            root_ino: 2,
-            features_compat: 0,
-            features_incompat: EXT4_FEATURE_INCOMPAT_EXTENTS | EXT4_FEATURE_INCOMPAT_64BIT,
-            features_ro_compat: 0,
+    ///
+    /// A journal holding committed transactions is replayed first. Fails
+    /// with [`FsError::NotSupported`] for incompatible features this
+    /// driver does not implement, and with [`FsError::InvalidData`] when a
+    /// metadata checksum does not match.
+    pub fn mount(device: Arc<dyn BlockDevice>) -> Result<Arc<Self>, FsError> {
+        let mut fs = Self::load(Arc::clone(&device))?;
+
+        if fs.has_feature_compat(EXT4_FEATURE_COMPAT_HAS_JOURNAL) {
+            let mut journal = fs.open_journal()?;
+            if journal.recover()? > 0 {
+                // Replay may have rewritten the superblock and descriptors
+                fs = Self::load(device)?;
+            }
+            if fs.writable {
+                let mut features = journal::JBD2_FEATURE_INCOMPAT_REVOKE;
+                if fs.has_feature_incompat(EXT4_FEATURE_INCOMPAT_64BIT) {
+                    features |= journal::JBD2_FEATURE_INCOMPAT_64BIT;
+                }
+                if fs.metadata_csum() {
+                    features |= journal::JBD2_FEATURE_INCOMPAT_CSUM_V3;
+                }
+                journal.set_features(features)?;
+            }
+            fs.journal = Some(Mutex::new(journal));
+        } else if fs.has_feature_incompat(EXT4_FEATURE_INCOMPAT_RECOVER) {
+            return Err(FsError::InvalidFs);
+        }
+
+        // While mounted writable the journal may hold the only copy of
+        // committed metadata, which the superblock records
+        fs.write_superblock(fs.writable && fs.journal.is_some())?;
+
+        Ok(Arc::new_cyclic(|this| Ext4Filesystem {
+            this: this.clone(),
+            ..fs
         }))
     }
 
-    /// Check if filesystem has a specific feature
-    pub fn has_feature_incompat(&self, feature: u32) -> bool {
-        (self.features_incompat & feature) != 0
-    }
-}
+    /// Read the superblock and group descriptors
+    fn load(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        let raw = read_bytes(&*device, SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE)?;
+        let sb = unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const Ext4Superblock) };
 
-impl Filesystem for Ext4Filesystem {
-    fn fs_type(&self) -> FsType {
-        FsType::Ext4
-    }
+        if sb.s_magic != EXT4_MAGIC {
+            return Err(FsError::InvalidFs);
+        }
 
-    fn root(&self) -> Arc<dyn VNode> {
-        Arc::new(Ext4VNode::new(
-            Arc::new(Self::mount().unwrap()),
-            self.root_ino
-        ))
-    }
+        let incompat = sb.s_feature_incompat;
+        let ro_compat = sb.s_feature_ro_compat;
+        if incompat & !EXT4_FEATURE_INCOMPAT_SUPP != 0
+            || ro_compat & EXT4_FEATURE_RO_COMPAT_BIGALLOC != 0
+        {
+            return Err(FsError::NotSupported);
+        }
 
-    fn sync(&self) -> Result<(), FsError> {
-        // Flush journal
-        // Flush all cached data
-        Ok(())
-    }
+        if sb.s_log_block_size > 6 {
+            return Err(FsError::InvalidFs);
+        }
+        let block_size = 1024u32 << sb.s_log_block_size;
+        let inode_size = if sb.s_rev_level == 0 {
+            EXT4_GOOD_OLD_INODE_SIZE
+        } else {
+            sb.s_inode_size as usize
+        };
+        if !(block_size as usize).is_multiple_of(device.block_size())
+            || !inode_size.is_power_of_two()
+            || inode_size < EXT4_GOOD_OLD_INODE_SIZE
+            || inode_size > block_size as usize
+            || sb.s_blocks_per_group == 0
+            || sb.s_inodes_per_group == 0
+        {
+            return Err(FsError::InvalidFs);
+        }
 
-    fn statfs(&self) -> Result<StatFs, FsError> {
-        Ok(StatFs {
-            fs_type: 0xEF53,
-            block_size: self.block_size as u64,
-            blocks: 0,
-            blocks_free: 0,
-            blocks_available: 0,
-            files: 0,
-            files_free: 0,
-            name_max: 255,
+        let metadata_csum = ro_compat & EXT4_FEATURE_RO_COMPAT_METADATA_CSUM != 0;
+        if metadata_csum {
+            if sb.s_checksum_type != EXT4_CRC32C_CHKSUM {
+                return Err(FsError::NotSupported);
+            }
+            if checksum::superblock(&raw) != sb.s_checksum {
+                return Err(FsError::InvalidData);
+            }
+        }
+        let csum_seed = if incompat & EXT4_FEATURE_INCOMPAT_CSUM_SEED != 0 {
+            sb.s_checksum_seed
+        } else {
+            checksum::crc32c(!0, &sb.s_uuid)
+        };
+
+        let is_64bit = incompat & EXT4_FEATURE_INCOMPAT_64BIT != 0;
+        let desc_size = if is_64bit {
+            sb.s_desc_size as usize
+        } else {
+            EXT4_MIN_DESC_SIZE
+        };
+        if is_64bit
+            && (desc_size < EXT4_MIN_DESC_SIZE_64BIT
+                || desc_size > block_size as usize
+                || !desc_size.is_power_of_two())
+        {
+            return Err(FsError::InvalidFs);
+        }
+
+        let mut blocks_count = sb.s_blocks_count_lo as u64;
+        if is_64bit {
+            blocks_count |= (sb.s_blocks_count_hi as u64) << 32;
+        }
+        let first_data_block = sb.s_first_data_block as u64;
+        if blocks_count <= first_data_block {
+            return Err(FsError::InvalidFs);
+        }
+        let group_count = (blocks_count - first_data_block).div_ceil(sb.s_blocks_per_group as u64);
+        if group_count * sb.s_inodes_per_group as u64 != sb.s_inodes_count as u64 {
+            return Err(FsError::InvalidFs);
+        }
+
+        // The descriptor table follows the superblock's block
+        let table = read_bytes(
+            &*device,
+            (first_data_block + 1) * block_size as u64,
+            group_count as usize * desc_size,
+        )?;
+        let mut groups = Vec::with_capacity(group_count as usize);
+        for (group, desc) in table.chunks_exact(desc_size).enumerate() {
+            if metadata_csum
+                && checksum::group_desc(csum_seed, group as u32, desc)
+                    != le16(desc, checksum::GROUP_DESC_CSUM_OFFSET)
+            {
+                return Err(FsError::InvalidData);
+            }
+
+            let mut bytes = [0u8; mem::size_of::<Ext4GroupDesc>()];
+            let len = desc_size.min(bytes.len());
+            bytes[..len].copy_from_slice(&desc[..len]);
+            let desc = unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const Ext4GroupDesc) };
+            if desc.inode_table() == 0 || desc.inode_table() >= blocks_count {
+                return Err(FsError::InvalidData);
+            }
+            groups.push(desc);
+        }
+
+        Ok(Ext4Filesystem {
+            device,
+            superblock: RwLock::new(sb),
+            groups: RwLock::new(groups),
+            block_size,
+            blocks_count,
+            first_data_block,
+            blocks_per_group: sb.s_blocks_per_group,
+            desc_size,
+            inode_size,
+            inodes_per_group: sb.s_inodes_per_group,
+            features_compat: sb.s_feature_compat,
+            features_incompat: incompat,
+            features_ro_compat: ro_compat,
+            csum_seed,
+            hash_seed: sb.s_hash_seed,
+            unsigned_hash: sb.s_flags & EXT2_FLAGS_UNSIGNED_HASH != 0,
+            writable: ro_compat & !EXT4_FEATURE_RO_COMPAT_WRITE_SUPP == 0,
+            cache: Mutex::new(BlockCache::default()),
+            journal: None,
+            transaction_lock: Mutex::new(()),
+            this: Weak::new(),
         })
     }
 
-    fn unmount(&self) -> Result<(), FsError> {
-        // Commit journal
-        // Write back all cached data
-        // Mark filesystem as cleanly unmounted
-        Ok(())
+    /// Open the journal stored in the journal inode
+    fn open_journal(&self) -> Result<Journal, FsError> {
+        let inode = self.read_inode(self.superblock.read().s_journal_inum)?;
+        let mut map = Vec::new();
+        for logical in 0..self.block_count(&inode) {
+            map.push(self.map_block(&inode, logical)?.ok_or(FsError::InvalidFs)?);
+        }
+        Journal::load(
+            Arc::clone(&self.device),
+            self.block_size as usize,
+            self.blocks_count,
+            map,
+        )
     }
-}
 
-/// Initialize ext4 driver
-pub fn init() {
-    // ext4 filesystems are mounted on demand
-}
+    /// Check if filesystem has a specific compatible feature
+    pub fn has_feature_compat(&self, feature: u32) -> bool {
+        (self.features_compat & feature) != 0
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Check if filesystem has a specific feature
+    pub fn has_feature_incompat(&self, feature: u32) -> bool {
+        (self.features_incompat & feature) != 0
+    }
 
-    #[test]
-    fn test_ext4_magic() {
-        assert_eq!(EXT4_MAGIC, 0xEF53);
+    /// Check if filesystem has a specific read-only compatible feature
+    pub fn has_feature_ro_compat(&self, feature: u32) -> bool {
+        (self.features_ro_compat & feature) != 0
     }
 
-    #[test]
-    fn test_extent_magic() {
-        assert_eq!(EXT4_EXT_MAGIC, 0xF30A);
+    /// Whether metadata carries CRC32C checksums
+    fn metadata_csum(&self) -> bool {
+        self.has_feature_ro_compat(EXT4_FEATURE_RO_COMPAT_METADATA_CSUM)
     }
 
-    #[test]
-    fn test_feature_flags() {
-        let fs = Ext4Filesystem {
-            block_size: 4096,
-            root_ino: 2,
-            features_compat: 0,
-            features_incompat: EXT4_FEATURE_INCOMPAT_EXTENTS,
-            features_ro_compat: 0,
+    /// Levels an htree may have below its root
+    fn max_dx_levels(&self) -> u8 {
+        if self.has_feature_incompat(EXT4_FEATURE_INCOMPAT_LARGEDIR) {
+            3
+        } else {
+            2
+        }
+    }
+
+    /// Read a filesystem block
+    ///
+    /// Block numbers come from on-disk metadata, so ones outside the
+    /// filesystem are reported as corruption.
+    fn read_block(&self, block: u64) -> Result<Vec<u8>, FsError> {
+        if block == 0 || block >= self.blocks_count {
+            return Err(FsError::InvalidData);
+        }
+        if let Some(data) = self.cache.lock().get(block) {
+            return Ok(data.clone());
+        }
+        let block_size = self.block_size as usize;
+        let mut buffer = vec![0u8; block_size];
+        self.device
+            .read_blocks(
+                block * (block_size / self.device.block_size()) as u64,
+                &mut buffer,
+            )
+            .map_err(|_| FsError::IoError)?;
+        Ok(buffer)
+    }
+
+    /// Read an inode and verify its checksum
+    fn read_inode(&self, ino: u32) -> Result<Inode, FsError> {
+        let (block, offset) = self.inode_location(ino)?;
+        let block = self.read_block(block)?;
+        let raw = &block[offset..offset + self.inode_size];
+
+        // Extra fields exist only as far as i_extra_isize says
+        let mut bytes = [0u8; mem::size_of::<Ext4Inode>()];
+        let extra = if self.inode_size > EXT4_GOOD_OLD_INODE_SIZE {
+            le16(raw, EXT4_GOOD_OLD_INODE_SIZE) as usize
+        } else {
+            0
+        };
+        if EXT4_GOOD_OLD_INODE_SIZE + extra > self.inode_size {
+            return Err(FsError::InvalidData);
+        }
+        let len = (EXT4_GOOD_OLD_INODE_SIZE + extra).min(bytes.len());
+        bytes[..len].copy_from_slice(&raw[..len]);
+        let inode = unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const Ext4Inode) };
+
+        let csum_seed = checksum::inode_seed(self.csum_seed, ino, inode.i_generation);
+        if self.metadata_csum() {
+            let has_hi = extra >= checksum::INODE_CSUM_HI_OFFSET + 2 - EXT4_GOOD_OLD_INODE_SIZE;
+            let mut stored = le16(raw, checksum::INODE_CSUM_LO_OFFSET) as u32;
+            if has_hi {
+                stored |= (le16(raw, checksum::INODE_CSUM_HI_OFFSET) as u32) << 16;
+            }
+            if checksum::inode(csum_seed, raw, has_hi) != stored {
+                return Err(FsError::InvalidData);
+            }
+        }
+
+        if inode.i_links_count == 0 {
+            return Err(FsError::NotFound);
+        }
+
+        Ok(Inode {
+            ino,
+            raw: inode,
+            csum_seed,
+        })
+    }
+
+    /// Block of the inode table holding an inode, and its offset there
+    fn inode_location(&self, ino: u32) -> Result<(u64, usize), FsError> {
+        let inodes_count = self.superblock.read().s_inodes_count;
+        if ino == 0 || ino > inodes_count {
+            return Err(FsError::InvalidData);
+        }
+
+        let group = ((ino - 1) / self.inodes_per_group) as usize;
+        let index = ((ino - 1) % self.inodes_per_group) as usize;
+        let inode_table = self.groups.read()[group].inode_table();
+        let offset = index * self.inode_size;
+        let block_size = self.block_size as usize;
+        Ok((
+            inode_table + (offset / block_size) as u64,
+            offset % block_size,
+        ))
+    }
+
+    /// Map a logical block of an inode to a physical block
+    ///
+    /// Returns `None` for holes.
+    fn map_block(&self, inode: &Inode, logical: u32) -> Result<Option<u64>, FsError> {
+        if inode.flags() & EXT4_EXTENTS_FL != 0 {
+            extent::map_block(self, inode, logical)
+        } else {
+            self.map_block_indirect(inode, logical)
+        }
+    }
+
+    /// Map a logical block through ext2-style indirect blocks
+    fn map_block_indirect(&self, inode: &Inode, logical: u32) -> Result<Option<u64>, FsError> {
+        let i_block = inode.raw.i_block;
+        let mut logical = logical as u64;
+        if logical < EXT4_NDIR_BLOCKS as u64 {
+            return Ok(Some(i_block[logical as usize] as u64).filter(|&b| b != 0));
+        }
+        logical -= EXT4_NDIR_BLOCKS as u64;
+
+        let per_block = self.block_size as u64 / 4;
+        let mut span = per_block;
+        for (depth, &root) in i_block[EXT4_NDIR_BLOCKS..].iter().enumerate() {
+            if logical >= span {
+                logical -= span;
+                span *= per_block;
+                continue;
+            }
+
+            let mut block = root as u64;
+            let mut stride = span;
+            for _ in 0..=depth {
+                if block == 0 {
+                    return Ok(None);
+                }
+                stride /= per_block;
+                let table = self.read_block(block)?;
+                block = le32(&table, (logical / stride % per_block) as usize * 4) as u64;
+            }
+            return Ok(Some(block).filter(|&b| b != 0));
+        }
+
+        Ok(None)
+    }
+
+    /// Read file data, zero-filling holes
+    fn read_data(&self, inode: &Inode, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let size = inode.size();
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let block_size = self.block_size as u64;
+        let len = (size - offset).min(buffer.len() as u64) as usize;
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let in_block = (pos % block_size) as usize;
+            let chunk = (block_size as usize - in_block).min(len - done);
+            let dst = &mut buffer[done..done + chunk];
+            match self.map_block(inode, (pos / block_size) as u32)? {
+                Some(block) => dst.copy_from_slice(&self.read_block(block)?[in_block..][..chunk]),
+                None => dst.fill(0),
+            }
+            done += chunk;
+        }
+
+        Ok(len)
+    }
+
+    /// Number of blocks covered by an inode's size
+    fn block_count(&self, inode: &Inode) -> u32 {
+        inode.size().div_ceil(self.block_size as u64) as u32
+    }
+
+    /// Read a block of a directory; directories have no holes
+    fn read_dir_block(&self, dir: &Inode, logical: u32) -> Result<Vec<u8>, FsError> {
+        let block = self.map_block(dir, logical)?.ok_or(FsError::InvalidData)?;
+        self.read_block(block)
+    }
+
+    /// Verify the checksum of an extent tree block ending in a tail at `tail`
+    fn verify_extent_block(&self, inode: &Inode, block: &[u8], tail: usize) -> Result<(), FsError> {
+        if !self.metadata_csum() {
+            return Ok(());
+        }
+        if tail + 4 > block.len()
+            || le32(block, tail) != checksum::extent_block(inode.csum_seed, block, tail)
+        {
+            return Err(FsError::InvalidData);
+        }
+        Ok(())
+    }
+
+    /// Fill in the checksum tail of an extent tree block
+    fn seal_extent_block(&self, inode: &Inode, block: &mut [u8], tail: usize) {
+        if self.metadata_csum() {
+            let sum = checksum::extent_block(inode.csum_seed, block, tail);
+            block[tail..tail + 4].copy_from_slice(&sum.to_le_bytes());
+        }
+    }
+
+    /// Verify the checksum of a directory block
+    ///
+    /// Index blocks of an htree carry their own tail; everything else is a
+    /// leaf whose last 12 bytes are a fake entry holding the checksum.
+    fn verify_dir_block(&self, dir: &Inode, logical: u32, block: &[u8]) -> Result<(), FsError> {
+        if !self.metadata_csum() {
+            return Ok(());
+        }
+        if dir.flags() & EXT4_INDEX_FL != 0 && (logical == 0 || htree::is_index_node(block)) {
+            return htree::verify_index_block(self, dir, block, logical == 0);
+        }
+
+        let tail = block.len() - checksum::DIRENT_TAIL_SIZE;
+        if le32(block, tail) != 0
+            || le16(block, tail + 4) as usize != checksum::DIRENT_TAIL_SIZE
+            || le16(block, tail + 6) != (EXT4_FT_DIR_CSUM as u16) << 8
+            || le32(block, tail + 8) != checksum::dirent_block(dir.csum_seed, block)
+        {
+            return Err(FsError::InvalidData);
+        }
+        Ok(())
+    }
+
+    /// Search one directory block for a name
+    fn search_dir_block(
+        &self,
+        dir: &Inode,
+        logical: u32,
+        name: &[u8],
+    ) -> Result<Option<u32>, FsError> {
+        let block = self.read_dir_block(dir, logical)?;
+        self.verify_dir_block(dir, logical, &block)?;
+        Ok(parse_dir_block(&block)?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.ino))
+    }
+
+    /// Find the inode a directory maps `name` to
+    ///
+    /// Indexed directories only search the leaves the htree points at;
+    /// an index this driver cannot use falls back to a linear scan.
+    fn find_entry(&self, dir: &Inode, name: &[u8]) -> Result<Option<u32>, FsError> {
+        let indexed = dir.flags() & EXT4_INDEX_FL != 0
+            && self.has_feature_compat(EXT4_FEATURE_COMPAT_DIR_INDEX);
+        let leaves = match indexed {
+            true => htree::lookup(self, dir, name)?,
+            false => None,
+        };
+
+        match leaves {
+            Some(leaves) => {
+                for logical in leaves {
+                    if let Some(ino) = self.search_dir_block(dir, logical, name)? {
+                        return Ok(Some(ino));
+                    }
+                }
+            }
+            None => {
+                for logical in 0..self.block_count(dir) {
+                    if let Some(ino) = self.search_dir_block(dir, logical, name)? {
+                        return Ok(Some(ino));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// File type recorded in a directory entry, if the filesystem stores one
+    fn dirent_type(&self, file_type: u8) -> Option<FileType> {
+        if !self.has_feature_incompat(EXT4_FEATURE_INCOMPAT_FILETYPE) {
+            return None;
+        }
+        dirent_file_type(file_type)
+    }
+
+    /// Write a block to its home location, bypassing the journal
+    ///
+    /// Used for file data, and for metadata once it is committed.
+    fn write_block(&self, block: u64, data: &[u8]) -> Result<(), FsError> {
+        if block == 0 || block >= self.blocks_count {
+            return Err(FsError::InvalidData);
+        }
+        self.device
+            .write_blocks(
+                block * (self.block_size as usize / self.device.block_size()) as u64,
+                data,
+            )
+            .map_err(|_| FsError::IoError)?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), FsError> {
+        self.device.flush().map_err(|_| FsError::IoError)
+    }
+
+    /// Queue the new contents of a metadata block in the running transaction
+    fn dirty_block(&self, block: u64, data: Vec<u8>) {
+        let mut cache = self.cache.lock();
+        cache.revoked.remove(&block);
+        cache.running.insert(block, data);
+    }
+
+    fn group_count(&self) -> u32 {
+        self.groups.read().len() as u32
+    }
+
+    /// Descriptor of a group, including changes of the running transaction
+    fn group_desc(&self, group: u32) -> Ext4GroupDesc {
+        self.groups.read()[group as usize]
+    }
+
+    /// Store a group descriptor with a fresh checksum
+    fn write_group_desc(&self, group: u32, desc: Ext4GroupDesc) -> Result<(), FsError> {
+        let block_size = self.block_size as usize;
+        let offset = group as usize * self.desc_size;
+        let table = self.first_data_block + 1 + (offset / block_size) as u64;
+        let at = offset % block_size;
+
+        let mut block = self.read_block(table)?;
+        let bytes = struct_bytes(&desc);
+        let len = self.desc_size.min(bytes.len());
+        block[at..at + len].copy_from_slice(&bytes[..len]);
+        if self.metadata_csum() {
+            let csum_at = at + checksum::GROUP_DESC_CSUM_OFFSET;
+            let sum = checksum::group_desc(self.csum_seed, group, &block[at..at + self.desc_size]);
+            block[csum_at..csum_at + 2].copy_from_slice(&sum.to_le_bytes());
+        }
+
+        self.groups.write()[group as usize] = desc;
+        self.dirty_block(table, block);
+        Ok(())
+    }
+
+    /// Store an inode with a fresh checksum
+    fn write_inode(&self, inode: &Inode) -> Result<(), FsError> {
+        let (block, offset) = self.inode_location(inode.ino)?;
+        let mut data = self.read_block(block)?;
+        let raw = &mut data[offset..offset + self.inode_size];
+
+        let extra = if self.inode_size > EXT4_GOOD_OLD_INODE_SIZE {
+            inode.raw.i_extra_isize as usize
+        } else {
+            0
+        };
+        let bytes = struct_bytes(&inode.raw);
+        let len = (EXT4_GOOD_OLD_INODE_SIZE + extra).min(bytes.len());
+        raw[..len].copy_from_slice(&bytes[..len]);
+
+        if self.metadata_csum() {
+            let has_hi = extra >= checksum::INODE_CSUM_HI_OFFSET + 2 - EXT4_GOOD_OLD_INODE_SIZE;
+            let sum = checksum::inode(inode.csum_seed, raw, has_hi);
+            let lo = checksum::INODE_CSUM_LO_OFFSET;
+            raw[lo..lo + 2].copy_from_slice(&(sum as u16).to_le_bytes());
+            if has_hi {
+                let hi = checksum::INODE_CSUM_HI_OFFSET;
+                raw[hi..hi + 2].copy_from_slice(&((sum >> 16) as u16).to_le_bytes());
+            }
+        }
+
+        self.dirty_block(block, data);
+        Ok(())
+    }
+
+    /// Allocate an inode near `dir` and set it up empty; the caller
+    /// writes it
+    fn new_inode(&self, dir: &Inode, mode: u16) -> Result<Inode, FsError> {
+        let ino = self.alloc_inode(dir.ino, mode & 0xF000 == EXT4_S_IFDIR)?;
+
+        // Clear the slot, but bump the generation so handles to an
+        // earlier inode there go stale
+        let (block, offset) = self.inode_location(ino)?;
+        let mut data = self.read_block(block)?;
+        let generation = le32(&data, offset + 0x64).wrapping_add(1);
+        data[offset..offset + self.inode_size].fill(0);
+        self.dirty_block(block, data);
+
+        let mut raw: Ext4Inode = unsafe { mem::zeroed() };
+        let now = now();
+        raw.i_mode = mode;
+        raw.i_links_count = 1;
+        raw.i_atime = now;
+        raw.i_ctime = now;
+        raw.i_mtime = now;
+        raw.i_generation = generation;
+        if self.inode_size > EXT4_GOOD_OLD_INODE_SIZE {
+            let extra = mem::size_of::<Ext4Inode>() - EXT4_GOOD_OLD_INODE_SIZE;
+            raw.i_extra_isize = extra.min(self.inode_size - EXT4_GOOD_OLD_INODE_SIZE) as u16;
+        }
+
+        let mut inode = Inode {
+            ino,
+            raw,
+            csum_seed: checksum::inode_seed(self.csum_seed, ino, generation),
+        };
+        if self.has_feature_incompat(EXT4_FEATURE_INCOMPAT_EXTENTS) {
+            inode.raw.i_flags = EXT4_EXTENTS_FL;
+            extent::store(self, &mut inode, &[], Vec::new())?;
+        }
+        Ok(inode)
+    }
+
+    /// Count the `..` link of a new subdirectory in its parent
+    ///
+    /// With `dir_nlink` a parent with too many to count is pinned at 1.
+    fn link_dir(&self, dir: &mut Inode) -> Result<(), FsError> {
+        let links = dir.raw.i_links_count;
+        dir.raw.i_links_count = if links == 1 {
+            1
+        } else if links + 1 < EXT4_LINK_MAX {
+            links + 1
+        } else if self.has_feature_ro_compat(EXT4_FEATURE_RO_COMPAT_DIR_NLINK) {
+            1
+        } else {
+            return Err(FsError::TooManyLinks);
+        };
+        Ok(())
+    }
+
+    /// Drop the `..` link of a removed subdirectory from its parent
+    fn unlink_dir(&self, dir: &mut Inode) {
+        let links = dir.raw.i_links_count;
+        if links > 2 {
+            dir.raw.i_links_count = links - 1;
+        }
+    }
+
+    /// Write back an inode that lost a link, releasing it with the last one
+    fn drop_link(&self, mut inode: Inode) -> Result<(), FsError> {
+        if inode.raw.i_links_count > 0 {
+            return self.write_inode(&inode);
+        }
+
+        // Blocks outside an extent tree cannot be freed by this driver
+        let extents = inode.flags() & EXT4_EXTENTS_FL != 0;
+        if inode.raw.i_file_acl_lo != 0 || (!extents && inode.raw.i_blocks_lo != 0) {
+            return Err(FsError::NotSupported);
+        }
+        if extents {
+            self.truncate_inode(&mut inode, 0)?;
+        }
+
+        inode.raw.i_dtime = now();
+        self.write_inode(&inode)?;
+        self.free_inode(inode.ino, inode.is_dir())
+    }
+
+    /// Adjust `i_blocks` by a number of filesystem blocks
+    fn add_blocks(&self, inode: &mut Inode, count: i64) {
+        let huge_file = self.has_feature_ro_compat(EXT4_FEATURE_RO_COMPAT_HUGE_FILE);
+        let mut blocks = inode.raw.i_blocks_lo as u64;
+        if huge_file {
+            blocks |= (inode.osd2(0) as u64) << 32;
+        }
+        let unit = if inode.flags() & EXT4_HUGE_FILE_FL != 0 {
+            1
+        } else {
+            self.block_size as i64 / 512
+        };
+
+        let blocks = (blocks as i64 + count * unit).max(0) as u64;
+        inode.raw.i_blocks_lo = blocks as u32;
+        if huge_file {
+            inode.set_osd2(0, (blocks >> 32) as u16);
+        }
+    }
+
+    /// Read the extents of an inode about to be changed
+    ///
+    /// An inode without any blocks is switched over to extents.
+    fn load_extents(&self, inode: &mut Inode) -> Result<FileExtents, FsError> {
+        if inode.flags() & EXT4_EXTENTS_FL == 0 {
+            let i_block = inode.raw.i_block;
+            if !self.has_feature_incompat(EXT4_FEATURE_INCOMPAT_EXTENTS)
+                || inode.raw.i_blocks_lo != 0
+                || i_block != [0; 15]
+            {
+                return Err(FsError::NotSupported);
+            }
+            inode.raw.i_flags |= EXT4_EXTENTS_FL;
+            extent::store(self, inode, &[], Vec::new())?;
+        }
+
+        let (extents, nodes) = extent::collect(self, inode)?;
+        Ok(FileExtents {
+            extents,
+            nodes,
+            allocated: 0,
+            changed: false,
+        })
+    }
+
+    /// Physical block backing `logical`, allocated if there is none
+    ///
+    /// Also returns whether the block is new, in which case none of its
+    /// old contents may show through.
+    fn map_for_write(
+        &self,
+        inode: &Inode,
+        file: &mut FileExtents,
+        logical: u32,
+    ) -> Result<(u64, bool), FsError> {
+        match extent::find(&file.extents, logical) {
+            Ok(i) if !file.extents[i].unwritten => {
+                let extent = file.extents[i];
+                Ok((extent.start + (logical - extent.first) as u64, false))
+            }
+            Ok(i) => {
+                file.changed = true;
+                Ok((extent::mark_written(&mut file.extents, i, logical), true))
+            }
+            Err(pos) => {
+                // Keep the file in line with the extent before the hole
+                let goal = match pos.checked_sub(1).map(|prev| file.extents[prev]) {
+                    Some(prev) => prev.start + prev.len as u64 + (logical - prev.end()) as u64,
+                    None => {
+                        let group = (inode.ino - 1) / self.inodes_per_group;
+                        self.first_data_block + group as u64 * self.blocks_per_group as u64
+                    }
+                };
+                let block = self.alloc_block(goal)?;
+                extent::insert(&mut file.extents, logical, block);
+                file.allocated += 1;
+                file.changed = true;
+                Ok((block, true))
+            }
+        }
+    }
+
+    /// Store changed extents back into the inode's tree
+    fn store_extents(&self, inode: &mut Inode, file: FileExtents) -> Result<(), FsError> {
+        if !file.changed {
+            return Ok(());
+        }
+        let nodes = extent::store(self, inode, &file.extents, file.nodes)?;
+        self.add_blocks(inode, file.allocated + nodes);
+        Ok(())
+    }
+
+    /// Add a block at the end of an inode, growing its size to cover it
+    fn append_block(&self, inode: &mut Inode) -> Result<(u32, u64), FsError> {
+        let logical = self.block_count(inode);
+        let mut file = self.load_extents(inode)?;
+        let (block, _) = self.map_for_write(inode, &mut file, logical)?;
+        self.store_extents(inode, file)?;
+        inode.set_size((logical as u64 + 1) * self.block_size as u64);
+        Ok((logical, block))
+    }
+
+    /// Write file data, allocating blocks as needed
+    ///
+    /// Data goes straight to its blocks; only the mapping and size are
+    /// journaled, by the caller writing the inode.
+    fn write_data(&self, inode: &mut Inode, offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
+        let block_size = self.block_size as u64;
+        let end = offset
+            .checked_add(buffer.len() as u64)
+            .ok_or(FsError::InvalidArgument)?;
+        if end.div_ceil(block_size) > u32::MAX as u64 {
+            return Err(FsError::NoSpaceLeft);
+        }
+
+        let mut file = self.load_extents(inode)?;
+        let mut done = 0;
+        while done < buffer.len() {
+            let pos = offset + done as u64;
+            let in_block = (pos % block_size) as usize;
+            let chunk = (block_size as usize - in_block).min(buffer.len() - done);
+            let (block, fresh) = self.map_for_write(inode, &mut file, (pos / block_size) as u32)?;
+
+            let mut data = if fresh || chunk == block_size as usize {
+                vec![0u8; block_size as usize]
+            } else {
+                self.read_block(block)?
+            };
+            data[in_block..in_block + chunk].copy_from_slice(&buffer[done..done + chunk]);
+            self.write_block(block, &data)?;
+            done += chunk;
+        }
+
+        self.store_extents(inode, file)?;
+        if end > inode.size() {
+            inode.set_size(end);
+        }
+        Ok(buffer.len())
+    }
+
+    /// Change the size of an inode, freeing blocks past the new end
+    fn truncate_inode(&self, inode: &mut Inode, size: u64) -> Result<(), FsError> {
+        let block_size = self.block_size as u64;
+        if size < inode.size() {
+            let mut file = self.load_extents(inode)?;
+            let keep = size.div_ceil(block_size) as u32;
+            for (start, count) in extent::truncate(&mut file.extents, keep) {
+                self.free_blocks(start, count, inode.is_dir())?;
+                file.allocated -= count as i64;
+                file.changed = true;
+            }
+
+            // The rest of the last block must read as zeros if the file grows
+            if !size.is_multiple_of(block_size) {
+                let logical = (size / block_size) as u32;
+                if let Ok(i) = extent::find(&file.extents, logical) {
+                    let extent = file.extents[i];
+                    if !extent.unwritten {
+                        let block = extent.start + (logical - extent.first) as u64;
+                        let mut data = self.read_block(block)?;
+                        data[(size % block_size) as usize..].fill(0);
+                        self.write_block(block, &data)?;
+                    }
+                }
+            }
+            self.store_extents(inode, file)?;
+        }
+        inode.set_size(size);
+        Ok(())
+    }
+
+    /// Run a mutating operation as one transaction
+    ///
+    /// The metadata `op` changes commits as a unit, or is dropped if `op`
+    /// fails.
+    fn transaction<T>(&self, op: impl FnOnce() -> Result<T, FsError>) -> Result<T, FsError> {
+        if !self.writable {
+            return Err(FsError::ReadOnly);
+        }
+        let _guard = self.transaction_lock.lock();
+        let groups = self.groups.read().clone();
+
+        let result = op().and_then(|value| self.commit().map(|()| value));
+        if result.is_err() {
+            let mut cache = self.cache.lock();
+            cache.running.clear();
+            cache.revoked.clear();
+            cache.freed.clear();
+            *self.groups.write() = groups;
+        }
+        result
+    }
+
+    /// Commit the running transaction
+    fn commit(&self) -> Result<(), FsError> {
+        let (blocks, revoked): (Vec<(u64, Vec<u8>)>, Vec<u64>) = {
+            let cache = self.cache.lock();
+            (
+                cache.running.iter().map(|(&b, d)| (b, d.clone())).collect(),
+                cache.revoked.iter().copied().collect(),
+            )
         };
+
+        let Some(journal) = &self.journal else {
+            for (block, data) in &blocks {
+                self.write_block(*block, data)?;
+            }
+            self.flush()?;
+            let mut cache = self.cache.lock();
+            cache.running.clear();
+            cache.revoked.clear();
+            cache.freed.clear();
+            return Ok(());
+        };
+
+        let mut journal = journal.lock();
+        if !journal.has_room(blocks.len(), revoked.len()) {
+            self.checkpoint(&mut journal)?;
+        }
+        let logged: Vec<(u64, &[u8])> = blocks.iter().map(|(b, d)| (*b, d.as_slice())).collect();
+        journal.commit(&logged, &revoked, now() as u64)?;
+
+        {
+            let mut cache = self.cache.lock();
+            let cache = &mut *cache;
+            // Freed blocks must not be checkpointed over their next user
+            for block in mem::take(&mut cache.revoked) {
+                cache.committed.remove(&block);
+            }
+            cache.committed.append(&mut cache.running);
+            cache.freed.clear();
+        }
+
+        if journal.wants_checkpoint() {
+            self.checkpoint(&mut journal)?;
+        }
+        Ok(())
+    }
+
+    /// Write committed metadata home and empty the log
+    fn checkpoint(&self, journal: &mut Journal) -> Result<(), FsError> {
+        let blocks = self.cache.lock().committed.clone();
+        for (block, data) in &blocks {
+            self.write_block(*block, data)?;
+        }
+        self.flush()?;
+        if !journal.is_empty() {
+            journal.checkpointed()?;
+        }
+        self.cache.lock().committed.clear();
+        Ok(())
+    }
+
+    /// Bring the superblock's free counts and recovery flag up to date
+    fn write_superblock(&self, needs_recovery: bool) -> Result<(), FsError> {
+        let mut sb = self.superblock.write();
+        let mut new = *sb;
+        {
+            let groups = self.groups.read();
+            let free_blocks: u64 = groups.iter().map(|g| g.free_blocks() as u64).sum();
+            new.s_free_blocks_count_lo = free_blocks as u32;
+            if self.has_feature_incompat(EXT4_FEATURE_INCOMPAT_64BIT) {
+                new.s_free_blocks_count_hi = (free_blocks >> 32) as u32;
+            }
+            new.s_free_inodes_count = groups.iter().map(|g| g.free_inodes()).sum();
+        }
+        if needs_recovery {
+            new.s_feature_incompat |= EXT4_FEATURE_INCOMPAT_RECOVER;
+        } else {
+            new.s_feature_incompat &= !EXT4_FEATURE_INCOMPAT_RECOVER;
+        }
+
+        if struct_bytes(&new) == struct_bytes(&*sb) {
+            return Ok(());
+        }
+        if self.metadata_csum() {
+            new.s_checksum = checksum::superblock(&struct_bytes(&new));
+        }
+        write_bytes(&*self.device, SUPERBLOCK_OFFSET, &struct_bytes(&new))?;
+        self.flush()?;
+        *sb = new;
+        Ok(())
+    }
+
+    /// Checkpoint everything committed so far
+    fn checkpoint_all(&self) -> Result<(), FsError> {
+        if let Some(journal) = &self.journal {
+            self.checkpoint(&mut journal.lock())?;
+        }
+        Ok(())
+    }
+}
+
+/// The extents of an inode being changed
+struct FileExtents {
+    extents: Vec<Extent>,
+    /// Blocks holding the tree below the root
+    nodes: Vec<u64>,
+    /// Data blocks allocated, less those freed, since the extents were read
+    allocated: i64,
+    changed: bool,
+}
+
+/// The on-disk bytes of a packed structure
+fn struct_bytes<T: Copy>(value: &T) -> Vec<u8> {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+        .to_vec()
+}
+
+impl Filesystem for Ext4Filesystem {
+    fn fs_type(&self) -> FsType {
+        FsType::Ext4
+    }
+
+    fn root(&self) -> Arc<dyn VNode> {
+        let fs = self
+            .this
+            .upgrade()
+            .expect("ext4 is always reference counted");
+        Arc::new(Ext4VNode::new(fs, EXT4_ROOT_INO))
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        if !self.writable {
+            return Ok(());
+        }
+        let _guard = self.transaction_lock.lock();
+        self.checkpoint_all()?;
+        self.write_superblock(self.journal.is_some())
+    }
+
+    fn statfs(&self) -> Result<StatFs, FsError> {
+        let sb = self.superblock.read();
+        let groups = self.groups.read();
+        let free: u64 = groups.iter().map(|g| g.free_blocks() as u64).sum();
+        let mut reserved = sb.s_r_blocks_count_lo as u64;
+        if self.has_feature_incompat(EXT4_FEATURE_INCOMPAT_64BIT) {
+            reserved |= (sb.s_r_blocks_count_hi as u64) << 32;
+        }
+
+        Ok(StatFs {
+            fs_type: EXT4_MAGIC as u64,
+            block_size: self.block_size as u64,
+            blocks: self.blocks_count,
+            blocks_free: free,
+            blocks_available: free.saturating_sub(reserved),
+            files: sb.s_inodes_count as u64,
+            files_free: groups.iter().map(|g| g.free_inodes() as u64).sum(),
+            name_max: 255,
+        })
+    }
+
+    fn unmount(&self) -> Result<(), FsError> {
+        if !self.writable {
+            return Ok(());
+        }
+        let _guard = self.transaction_lock.lock();
+        self.checkpoint_all()?;
+        self.write_superblock(false)
+    }
+}
+
+/// Initialize ext4 driver
+pub fn init() {
+    // ext4 filesystems are mounted on demand
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::os::unix::fs::FileExt;
+    use std::path::PathBuf;
+
+    /// Block device backed by an image file, with 512-byte sectors
+    struct FileDisk {
+        file: std::fs::File,
+        path: PathBuf,
+    }
+
+    impl FileDisk {
+        fn create(name: &str, image: &[u8]) -> Arc<dyn BlockDevice> {
+            let path = std::env::temp_dir().join(format!(
+                "rinux-ext4-{}-{}.img",
+                std::process::id(),
+                name
+            ));
+            std::fs::write(&path, image).unwrap();
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            Arc::new(FileDisk { file, path })
+        }
+    }
+
+    impl Drop for FileDisk {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    impl BlockDevice for FileDisk {
+        fn read_blocks(&self, block_offset: u64, buffer: &mut [u8]) -> Result<usize, ()> {
+            self.file
+                .read_exact_at(buffer, block_offset * 512)
+                .map_err(|_| ())?;
+            Ok(buffer.len())
+        }
+
+        fn write_blocks(&self, block_offset: u64, buffer: &[u8]) -> Result<usize, ()> {
+            self.file
+                .write_all_at(buffer, block_offset * 512)
+                .map_err(|_| ())?;
+            Ok(buffer.len())
+        }
+
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn flush(&self) -> Result<(), ()> {
+            self.file.sync_data().map_err(|_| ())
+        }
+    }
+
+    /// Block device over an in-memory image that records every write
+    struct MemDisk {
+        image: Mutex<Vec<u8>>,
+        writes: Mutex<Vec<(usize, Vec<u8>)>>,
+    }
+
+    impl MemDisk {
+        fn new(image: Vec<u8>) -> Arc<Self> {
+            Arc::new(MemDisk {
+                image: Mutex::new(image),
+                writes: Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    impl BlockDevice for MemDisk {
+        fn read_blocks(&self, block_offset: u64, buffer: &mut [u8]) -> Result<usize, ()> {
+            let offset = block_offset as usize * 512;
+            buffer.copy_from_slice(&self.image.lock()[offset..offset + buffer.len()]);
+            Ok(buffer.len())
+        }
+
+        fn write_blocks(&self, block_offset: u64, buffer: &[u8]) -> Result<usize, ()> {
+            let offset = block_offset as usize * 512;
+            self.image.lock()[offset..offset + buffer.len()].copy_from_slice(buffer);
+            self.writes.lock().push((offset, buffer.to_vec()));
+            Ok(buffer.len())
+        }
+
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn flush(&self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    const TEST_BLOCK: usize = 1024;
+    const TEST_INODE_SIZE: usize = 256;
+    const INODE_TABLE: usize = 5;
+    const ROOT_DIR_BLOCK: usize = 13;
+    const DX_ROOT_BLOCK: usize = 14;
+    const EXTENT_LEAF_BLOCK: usize = 17;
+    const BIG_INO: u32 = 12;
+    const DATA_INO: u32 = 13;
+    const LINK_INO: u32 = 14;
+    const JOURNAL_INO: u32 = 8;
+    const JOURNAL_START: usize = 64;
+    const JOURNAL_BLOCKS: usize = 64;
+    const EXT4_FEATURE_INCOMPAT_INLINE_DATA: u32 = 0x8000;
+    const UUID: [u8; 16] = *b"rinux-ext4-test!";
+    const HASH_SEED: [u32; 4] = [1, 2, 3, 4];
+
+    fn put<T>(image: &mut [u8], offset: usize, value: T) {
+        assert!(offset + mem::size_of::<T>() <= image.len());
+        unsafe { core::ptr::write_unaligned(image[offset..].as_mut_ptr() as *mut T, value) }
+    }
+
+    fn inode_seed(ino: u32) -> u32 {
+        checksum::inode_seed(checksum::crc32c(!0, &UUID), ino, ino * 0x1111)
+    }
+
+    fn name_hash(name: &str) -> u32 {
+        dirhash(name.as_bytes(), htree::DX_HASH_HALF_MD4, &HASH_SEED)
+            .unwrap()
+            .0
+    }
+
+    /// Write an extent node of (first logical block, physical block,
+    /// length) entries; lengths are ignored in index nodes
+    fn put_extent_node(buf: &mut [u8], max: u16, depth: u16, entries: &[(u32, u64, u16)]) {
+        put(buf, 0, EXT4_EXT_MAGIC);
+        put(buf, 2, entries.len() as u16);
+        put(buf, 4, max);
+        put(buf, 6, depth);
+        for (i, &(first, phys, len)) in entries.iter().enumerate() {
+            let off = 12 * (i + 1);
+            put(buf, off, first);
+            if depth == 0 {
+                put(buf, off + 4, len);
+                put(buf, off + 6, (phys >> 32) as u16);
+                put(buf, off + 8, phys as u32);
+            } else {
+                put(buf, off + 4, phys as u32);
+                put(buf, off + 8, (phys >> 32) as u16);
+            }
+        }
+    }
+
+    fn put_inode(image: &mut [u8], ino: u32, mode: u16, size: u64, flags: u32, i_block: &[u8]) {
+        let mut inode: Ext4Inode = unsafe { mem::zeroed() };
+        inode.i_mode = mode;
+        inode.i_size_lo = size as u32;
+        inode.i_size_high = (size >> 32) as u32;
+        inode.i_links_count = if mode & 0xF000 == EXT4_S_IFDIR { 2 } else { 1 };
+        if flags & EXT4_EXTENTS_FL != 0 {
+            inode.i_blocks_lo = (size.div_ceil(TEST_BLOCK as u64) * 2) as u32;
+        }
+        inode.i_flags = flags;
+        inode.i_generation = ino * 0x1111;
+        inode.i_extra_isize = 32;
+        let mut words = [0u32; 15];
+        for (word, chunk) in words.iter_mut().zip(i_block.chunks(4)) {
+            let mut bytes = [0u8; 4];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            *word = u32::from_le_bytes(bytes);
+        }
+        inode.i_block = words;
+
+        let off = INODE_TABLE * TEST_BLOCK + (ino as usize - 1) * TEST_INODE_SIZE;
+        put(image, off, inode);
+        let sum = checksum::inode(inode_seed(ino), &image[off..off + TEST_INODE_SIZE], true);
+        put(image, off + checksum::INODE_CSUM_LO_OFFSET, sum as u16);
+        put(
+            image,
+            off + checksum::INODE_CSUM_HI_OFFSET,
+            (sum >> 16) as u16,
+        );
+    }
+
+    fn put_dirent(image: &mut [u8], offset: usize, ino: u32, rec_len: usize, ft: u8, name: &str) {
+        put(image, offset, ino);
+        put(image, offset + 4, rec_len as u16);
+        put(image, offset + 6, name.len() as u8);
+        put(image, offset + 7, ft);
+        image[offset + 8..offset + 8 + name.len()].copy_from_slice(name.as_bytes());
+    }
+
+    /// Write a directory leaf ending in a checksum tail
+    fn put_dir(image: &mut [u8], block: usize, dir: u32, entries: &[(u32, u8, &str)]) {
+        let base = block * TEST_BLOCK;
+        let end = TEST_BLOCK - checksum::DIRENT_TAIL_SIZE;
+        let mut pos = 0;
+        for (i, &(ino, ft, name)) in entries.iter().enumerate() {
+            let rec_len = if i + 1 == entries.len() {
+                end - pos
+            } else {
+                (8 + name.len() + 3) & !3
+            };
+            put_dirent(image, base + pos, ino, rec_len, ft, name);
+            pos += rec_len;
+        }
+        put_dirent(image, base + end, 0, 12, EXT4_FT_DIR_CSUM, "");
+        let sum = checksum::dirent_block(inode_seed(dir), &image[base..base + TEST_BLOCK]);
+        put(image, base + end + 8, sum);
+    }
+
+    /// Block of file `data` at logical block `i`
+    fn data_pattern(i: usize) -> u8 {
+        b'a' + i as u8
+    }
+
+    /// Build a one-group image with metadata_csum, 64bit, extents and
+    /// dir_index enabled, holding:
+    /// - `data`: 8 KiB mapped through a depth 1 extent tree, with a hole at
+    ///   blocks 3-4 and an unwritten extent at block 7
+    /// - `link`: fast symlink to `data`
+    /// - `big`: htree directory of 30 links to `data` across two leaves,
+    ///   plus `misfiled`, stored in the leaf its hash does not select
+    fn build_image() -> Vec<u8> {
+        let mut image = vec![0u8; 256 * TEST_BLOCK];
+
+        let mut sb: Ext4Superblock = unsafe { mem::zeroed() };
+        sb.s_inodes_count = 32;
+        sb.s_blocks_count_lo = 256;
+        sb.s_r_blocks_count_lo = 12;
+        sb.s_free_blocks_count_lo = 223;
+        sb.s_free_inodes_count = 18;
+        sb.s_first_data_block = 1;
+        sb.s_blocks_per_group = 8192;
+        sb.s_clusters_per_group = 8192;
+        sb.s_inodes_per_group = 32;
+        sb.s_magic = EXT4_MAGIC;
+        sb.s_state = 1;
+        sb.s_rev_level = 1;
+        sb.s_first_ino = 11;
+        sb.s_inode_size = TEST_INODE_SIZE as u16;
+        sb.s_feature_compat = EXT4_FEATURE_COMPAT_DIR_INDEX;
+        sb.s_feature_incompat = EXT4_FEATURE_INCOMPAT_FILETYPE
+            | EXT4_FEATURE_INCOMPAT_EXTENTS
+            | EXT4_FEATURE_INCOMPAT_64BIT;
+        sb.s_feature_ro_compat =
+            EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE | EXT4_FEATURE_RO_COMPAT_METADATA_CSUM;
+        sb.s_uuid = UUID;
+        sb.s_hash_seed = HASH_SEED;
+        sb.s_def_hash_version = htree::DX_HASH_HALF_MD4;
+        sb.s_desc_size = 64;
+        sb.s_min_extra_isize = 32;
+        sb.s_want_extra_isize = 32;
+        sb.s_checksum_type = EXT4_CRC32C_CHKSUM;
+        put(&mut image, SUPERBLOCK_OFFSET as usize, sb);
+        reseal_superblock(&mut image);
+
+        let mut desc: Ext4GroupDesc = unsafe { mem::zeroed() };
+        desc.bg_block_bitmap_lo = 3;
+        desc.bg_inode_bitmap_lo = 4;
+        desc.bg_inode_table_lo = INODE_TABLE as u32;
+        desc.bg_free_blocks_count_lo = 223;
+        desc.bg_free_inodes_count_lo = 18;
+        desc.bg_used_dirs_count_lo = 2;
+        put(&mut image, 2 * TEST_BLOCK, desc);
+
+        // Blocks 1-32 and inodes 1-14 are in use
+        image[3 * TEST_BLOCK..3 * TEST_BLOCK + 4].fill(0xFF);
+        image[4 * TEST_BLOCK] = 0xFF;
+        image[4 * TEST_BLOCK + 1] = 0x3F;
+        reseal_group(&mut image);
+
+        let mut root = [0u8; 60];
+        put_extent_node(&mut root, 4, 0, &[(0, ROOT_DIR_BLOCK as u64, 1)]);
+        put_inode(
+            &mut image,
+            EXT4_ROOT_INO,
+            EXT4_S_IFDIR | 0o755,
+            TEST_BLOCK as u64,
+            EXT4_EXTENTS_FL,
+            &root,
+        );
+        put_dir(
+            &mut image,
+            ROOT_DIR_BLOCK,
+            EXT4_ROOT_INO,
+            &[
+                (EXT4_ROOT_INO, EXT4_FT_DIR, "."),
+                (EXT4_ROOT_INO, EXT4_FT_DIR, ".."),
+                (BIG_INO, EXT4_FT_DIR, "big"),
+                (DATA_INO, EXT4_FT_REG_FILE, "data"),
+                (LINK_INO, EXT4_FT_SYMLINK, "link"),
+            ],
+        );
+
+        // `data` has one index entry in the inode pointing at a leaf block
+        let mut data = [0u8; 60];
+        put_extent_node(&mut data, 4, 1, &[(0, EXTENT_LEAF_BLOCK as u64, 0)]);
+        put_inode(
+            &mut image,
+            DATA_INO,
+            EXT4_S_IFREG | 0o644,
+            8 * TEST_BLOCK as u64,
+            EXT4_EXTENTS_FL,
+            &data,
+        );
+        let leaf = EXTENT_LEAF_BLOCK * TEST_BLOCK;
+        let max = (TEST_BLOCK - 12 - 4) / 12;
+        put_extent_node(
+            &mut image[leaf..leaf + TEST_BLOCK],
+            max as u16,
+            0,
+            &[(0, 20, 3), (5, 30, 2), (7, 32, 32768 + 1)],
+        );
+        let tail = 12 * (max + 1);
+        let sum =
+            checksum::extent_block(inode_seed(DATA_INO), &image[leaf..leaf + TEST_BLOCK], tail);
+        put(&mut image, leaf + tail, sum);
+        for (logical, phys) in [(0, 20), (1, 21), (2, 22), (5, 30), (6, 31)] {
+            image[phys * TEST_BLOCK..(phys + 1) * TEST_BLOCK].fill(data_pattern(logical));
+        }
+        image[32 * TEST_BLOCK..33 * TEST_BLOCK].fill(0xEE);
+
+        put_inode(&mut image, LINK_INO, EXT4_S_IFLNK | 0o777, 4, 0, b"data");
+
+        let mut big = [0u8; 60];
+        put_extent_node(&mut big, 4, 0, &[(0, DX_ROOT_BLOCK as u64, 3)]);
+        put_inode(
+            &mut image,
+            BIG_INO,
+            EXT4_S_IFDIR | 0o755,
+            3 * TEST_BLOCK as u64,
+            EXT4_EXTENTS_FL | EXT4_INDEX_FL,
+            &big,
+        );
+        build_htree(&mut image);
+
+        image
+    }
+
+    /// Names stored in `big`, in hash order
+    fn big_names() -> Vec<String> {
+        let mut names: Vec<String> = (0..30).map(|i| format!("file-{:03}", i)).collect();
+        names.sort_by_key(|name| name_hash(name));
+        names
+    }
+
+    fn build_htree(image: &mut [u8]) {
+        let names = big_names();
+        let (low, high) = names.split_at(names.len() / 2);
+        let split = name_hash(&high[0]);
+        assert!(name_hash(low.last().unwrap()) < split);
+
+        let mut low: Vec<(u32, u8, &str)> = low
+            .iter()
+            .map(|name| (DATA_INO, EXT4_FT_REG_FILE, name.as_str()))
+            .collect();
+        let mut high: Vec<(u32, u8, &str)> = high
+            .iter()
+            .map(|name| (DATA_INO, EXT4_FT_REG_FILE, name.as_str()))
+            .collect();
+        let misfiled = (DATA_INO, EXT4_FT_REG_FILE, "misfiled");
+        if name_hash("misfiled") < split {
+            high.push(misfiled);
+        } else {
+            low.push(misfiled);
+        }
+        put_dir(image, DX_ROOT_BLOCK + 1, BIG_INO, &low);
+        put_dir(image, DX_ROOT_BLOCK + 2, BIG_INO, &high);
+
+        let base = DX_ROOT_BLOCK * TEST_BLOCK;
+        put_dirent(image, base, BIG_INO, 12, EXT4_FT_DIR, ".");
+        put_dirent(
+            image,
+            base + 12,
+            EXT4_ROOT_INO,
+            TEST_BLOCK - 12,
+            EXT4_FT_DIR,
+            "..",
+        );
+        image[base + 28] = htree::DX_HASH_HALF_MD4;
+        image[base + 29] = 8;
+        let limit = (TEST_BLOCK - 32 - 8) / 8;
+        put(image, base + 32, limit as u16);
+        put(image, base + 34, 2u16);
+        put(image, base + 36, 1u32);
+        put(image, base + 40, split);
+        put(image, base + 44, 2u32);
+        let tail = 32 + limit * 8;
+        let sum = checksum::dx_node(
+            inode_seed(BIG_INO),
+            &image[base..base + TEST_BLOCK],
+            32 + 2 * 8,
+            tail,
+        );
+        put(image, base + tail + 4, sum);
+    }
+
+    /// Recompute the superblock checksum after editing it
+    fn reseal_superblock(image: &mut [u8]) {
+        let sb = SUPERBLOCK_OFFSET as usize;
+        let sum = checksum::superblock(&image[sb..sb + SUPERBLOCK_SIZE]);
+        put(image, sb + checksum::SUPERBLOCK_CSUM_OFFSET, sum);
+    }
+
+    /// Recompute the bitmap and descriptor checksums of the only group
+    fn reseal_group(image: &mut [u8]) {
+        let seed = checksum::crc32c(!0, &UUID);
+        let desc = 2 * TEST_BLOCK;
+        let blocks = checksum::crc32c(seed, &image[3 * TEST_BLOCK..4 * TEST_BLOCK]);
+        let inodes = checksum::crc32c(seed, &image[4 * TEST_BLOCK..4 * TEST_BLOCK + 4]);
+        put(image, desc + 0x18, blocks as u16);
+        put(image, desc + 0x1A, inodes as u16);
+        put(image, desc + 0x38, (blocks >> 16) as u16);
+        put(image, desc + 0x3A, (inodes >> 16) as u16);
+        let sum = checksum::group_desc(seed, 0, &image[desc..desc + 64]);
+        put(image, desc + checksum::GROUP_DESC_CSUM_OFFSET, sum);
+    }
+
+    fn read_superblock(image: &[u8]) -> Ext4Superblock {
+        unsafe {
+            core::ptr::read_unaligned(image[SUPERBLOCK_OFFSET as usize..].as_ptr() as *const _)
+        }
+    }
+
+    /// Give an image from [`build_image`] an empty 64-block journal in
+    /// blocks 64-127
+    fn add_journal(image: &mut [u8]) {
+        let mut sb = read_superblock(image);
+        sb.s_feature_compat |= EXT4_FEATURE_COMPAT_HAS_JOURNAL;
+        sb.s_journal_inum = JOURNAL_INO;
+        sb.s_free_blocks_count_lo -= JOURNAL_BLOCKS as u32;
+        put(image, SUPERBLOCK_OFFSET as usize, sb);
+        reseal_superblock(image);
+
+        let desc = 2 * TEST_BLOCK;
+        let free = u16::from_le_bytes([image[desc + 0x0C], image[desc + 0x0D]]);
+        put(image, desc + 0x0C, free - JOURNAL_BLOCKS as u16);
+        for block in JOURNAL_START..JOURNAL_START + JOURNAL_BLOCKS {
+            image[3 * TEST_BLOCK + (block - 1) / 8] |= 1 << ((block - 1) % 8);
+        }
+        reseal_group(image);
+
+        let mut root = [0u8; 60];
+        put_extent_node(
+            &mut root,
+            4,
+            0,
+            &[(0, JOURNAL_START as u64, JOURNAL_BLOCKS as u16)],
+        );
+        put_inode(
+            image,
+            JOURNAL_INO,
+            EXT4_S_IFREG | 0o600,
+            (JOURNAL_BLOCKS * TEST_BLOCK) as u64,
+            EXT4_EXTENTS_FL,
+            &root,
+        );
+
+        let jsb = JOURNAL_START * TEST_BLOCK;
+        for (offset, value) in [
+            (0x00, 0xC03B_3998u32),
+            (0x04, 4),
+            (0x0C, TEST_BLOCK as u32),
+            (0x10, JOURNAL_BLOCKS as u32),
+            (0x14, 1),
+            (0x18, 1),
+        ] {
+            image[jsb + offset..jsb + offset + 4].copy_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    fn mount_image(name: &str, image: &[u8]) -> Result<Arc<Ext4Filesystem>, FsError> {
+        Ext4Filesystem::mount(FileDisk::create(name, image))
+    }
+
+    fn read_all(node: &Arc<dyn VNode>) -> Vec<u8> {
+        let mut buf = vec![0u8; node.getattr().unwrap().size as usize];
+        let n = node.read(0, &mut buf).unwrap();
+        buf.truncate(n);
+        buf
+    }
+
+    #[test]
+    fn test_ext4_magic() {
+        assert_eq!(EXT4_MAGIC, 0xEF53);
+    }
+
+    #[test]
+    fn test_extent_magic() {
+        assert_eq!(EXT4_EXT_MAGIC, 0xF30A);
+    }
+
+    #[test]
+    fn test_on_disk_sizes() {
+        assert_eq!(mem::size_of::<Ext4Superblock>(), SUPERBLOCK_SIZE);
+        assert_eq!(mem::size_of::<Ext4Inode>(), 160);
+        assert_eq!(mem::size_of::<Ext4GroupDesc>(), EXT4_MIN_DESC_SIZE_64BIT);
+    }
+
+    #[test]
+    fn test_feature_flags() {
+        let fs = mount_image("features", &build_image()).unwrap();
         assert!(fs.has_feature_incompat(EXT4_FEATURE_INCOMPAT_EXTENTS));
-        assert!(!fs.has_feature_incompat(EXT4_FEATURE_INCOMPAT_64BIT));
+        assert!(fs.has_feature_incompat(EXT4_FEATURE_INCOMPAT_64BIT));
+        assert!(!fs.has_feature_incompat(EXT4_FEATURE_INCOMPAT_FLEX_BG));
+        assert!(fs.has_feature_compat(EXT4_FEATURE_COMPAT_DIR_INDEX));
+    }
+
+    #[test]
+    fn test_read_through_extent_tree() {
+        let fs = mount_image("extents", &build_image()).unwrap();
+        let root = fs.root();
+
+        let mut names: Vec<String> = root
+            .readdir()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, [".", "..", "big", "data", "link"]);
+
+        let data = root.lookup("data").unwrap();
+        let attr = data.getattr().unwrap();
+        assert_eq!(attr.file_type, FileType::Regular);
+        assert_eq!(attr.ino, DATA_INO as u64);
+        assert_eq!(attr.size, 8 * TEST_BLOCK as u64);
+
+        // Holes and unwritten extents read as zeros
+        let contents = read_all(&data);
+        for (i, block) in contents.chunks(TEST_BLOCK).enumerate() {
+            let expected = match i {
+                3 | 4 | 7 => 0,
+                _ => data_pattern(i),
+            };
+            assert!(block.iter().all(|&b| b == expected), "block {}", i);
+        }
+
+        let mut buf = [0u8; 100];
+        assert_eq!(
+            data.read(2 * TEST_BLOCK as u64 - 50, &mut buf).unwrap(),
+            100
+        );
+        assert_eq!(buf[49], data_pattern(1));
+        assert_eq!(buf[50], data_pattern(2));
+        assert_eq!(data.read(8 * TEST_BLOCK as u64, &mut buf).unwrap(), 0);
+
+        let link = root.lookup("link").unwrap();
+        assert_eq!(link.getattr().unwrap().file_type, FileType::Symlink);
+        assert_eq!(link.readlink().unwrap(), "data");
+
+        let statfs = fs.statfs().unwrap();
+        assert_eq!(statfs.blocks, 256);
+        assert_eq!(statfs.blocks_free, 223);
+        assert_eq!(statfs.blocks_available, 211);
+    }
+
+    #[test]
+    fn test_htree_lookup() {
+        let fs = mount_image("htree", &build_image()).unwrap();
+        let big = fs.root().lookup("big").unwrap();
+
+        for name in big_names() {
+            assert_eq!(big.lookup(&name).unwrap().getattr().unwrap().ino, 13);
+        }
+        assert_eq!(big.lookup("file-999").err(), Some(FsError::NotFound));
+
+        // A linear scan sees every name, but the index only searches the
+        // leaf a name hashes to
+        let entries = big.readdir().unwrap();
+        assert_eq!(entries.len(), 2 + 30 + 1);
+        assert!(entries.iter().any(|e| e.name == "misfiled"));
+        assert_eq!(big.lookup("misfiled").err(), Some(FsError::NotFound));
+    }
+
+    #[test]
+    fn test_checksum_mismatch_is_rejected() {
+        let inode_off = |ino: u32| INODE_TABLE * TEST_BLOCK + (ino as usize - 1) * TEST_INODE_SIZE;
+
+        // Superblock and group descriptors are checked at mount
+        for (name, offset) in [
+            ("sb", SUPERBLOCK_OFFSET as usize + 0x78),
+            ("gdt", 2 * TEST_BLOCK + 0x0C),
+        ] {
+            let mut image = build_image();
+            image[offset] ^= 1;
+            assert_eq!(mount_image(name, &image).err(), Some(FsError::InvalidData));
+        }
+
+        let mut image = build_image();
+        image[inode_off(DATA_INO) + 0x10] ^= 1;
+        let fs = mount_image("inode", &image).unwrap();
+        let data = fs.root().lookup("data").unwrap();
+        assert_eq!(data.getattr().err(), Some(FsError::InvalidData));
+
+        let mut image = build_image();
+        image[EXTENT_LEAF_BLOCK * TEST_BLOCK + 12] ^= 1;
+        let fs = mount_image("extent", &image).unwrap();
+        let data = fs.root().lookup("data").unwrap();
+        assert!(data.getattr().is_ok());
+        assert_eq!(
+            data.read(0, &mut [0u8; 16]).err(),
+            Some(FsError::InvalidData)
+        );
+
+        let mut image = build_image();
+        image[ROOT_DIR_BLOCK * TEST_BLOCK + 30] ^= 1;
+        let fs = mount_image("dirent", &image).unwrap();
+        assert_eq!(fs.root().lookup("data").err(), Some(FsError::InvalidData));
+
+        // The index's hashes are covered too
+        let mut image = build_image();
+        image[DX_ROOT_BLOCK * TEST_BLOCK + 40] ^= 2;
+        let fs = mount_image("dx", &image).unwrap();
+        let big = fs.root().lookup("big").unwrap();
+        assert_eq!(big.lookup("file-000").err(), Some(FsError::InvalidData));
+    }
+
+    #[test]
+    fn test_mount_rejects_unsupported() {
+        let mut image = build_image();
+        image[SUPERBLOCK_OFFSET as usize + 0x38] = 0;
+        assert_eq!(mount_image("magic", &image).err(), Some(FsError::InvalidFs));
+
+        let mut image = build_image();
+        let mut sb = read_superblock(&image);
+        sb.s_feature_incompat |= EXT4_FEATURE_INCOMPAT_INLINE_DATA;
+        put(&mut image, SUPERBLOCK_OFFSET as usize, sb);
+        reseal_superblock(&mut image);
+        assert_eq!(
+            mount_image("inline", &image).err(),
+            Some(FsError::NotSupported)
+        );
+    }
+
+    #[test]
+    fn test_write_and_remount() {
+        let device = FileDisk::create("write", &build_image());
+        let fs = Ext4Filesystem::mount(Arc::clone(&device)).unwrap();
+        let root = fs.root();
+        let data = root.lookup("data").unwrap();
+
+        // Into a hole, over an unwritten extent and past the end
+        data.write(3 * TEST_BLOCK as u64 + 10, b"hole").unwrap();
+        data.write(7 * TEST_BLOCK as u64, b"unwritten").unwrap();
+        data.write(9 * TEST_BLOCK as u64, b"tail").unwrap();
+
+        let new = root.create("new", FileMode::new(0o600)).unwrap();
+        assert_eq!(new.write(0, &[b'n'; 3000]).unwrap(), 3000);
+        let dir = root.mkdir("dir", FileMode::new(0o755)).unwrap();
+        dir.symlink("up", "../new").unwrap();
+        root.unlink("link").unwrap();
+        assert_eq!(
+            root.create("data", FileMode::new(0o644)).err(),
+            Some(FsError::AlreadyExists)
+        );
+        assert_eq!(root.rmdir("big").err(), Some(FsError::NotEmpty));
+        assert_eq!(root.unlink("dir").err(), Some(FsError::IsADirectory));
+        fs.unmount().unwrap();
+
+        let fs = Ext4Filesystem::mount(device).unwrap();
+        let root = fs.root();
+        let mut names: Vec<String> = root
+            .readdir()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, [".", "..", "big", "data", "dir", "new"]);
+        assert_eq!(root.getattr().unwrap().nlink, 3);
+
+        let data = root.lookup("data").unwrap();
+        let contents = read_all(&data);
+        assert_eq!(contents.len(), 9 * TEST_BLOCK + 4);
+        let mut expected = vec![0u8; 9 * TEST_BLOCK + 4];
+        for i in [0, 1, 2, 5, 6] {
+            expected[i * TEST_BLOCK..(i + 1) * TEST_BLOCK].fill(data_pattern(i));
+        }
+        expected[3 * TEST_BLOCK + 10..3 * TEST_BLOCK + 14].copy_from_slice(b"hole");
+        expected[7 * TEST_BLOCK..7 * TEST_BLOCK + 9].copy_from_slice(b"unwritten");
+        expected[9 * TEST_BLOCK..].copy_from_slice(b"tail");
+        assert!(contents == expected);
+
+        assert_eq!(read_all(&root.lookup("new").unwrap()), [b'n'; 3000]);
+        let dir = root.lookup("dir").unwrap();
+        assert_eq!(dir.getattr().unwrap().nlink, 2);
+        assert_eq!(dir.lookup("up").unwrap().readlink().unwrap(), "../new");
+
+        // Removing everything again returns every block and inode, and the
+        // old extent leaf of `data` with them
+        dir.unlink("up").unwrap();
+        root.rmdir("dir").unwrap();
+        root.unlink("new").unwrap();
+        data.truncate(0).unwrap();
+        assert_eq!(data.getattr().unwrap().size, 0);
+        let statfs = fs.statfs().unwrap();
+        assert_eq!(statfs.blocks_free, 223 + 7);
+        assert_eq!(statfs.files_free, 19);
+    }
+
+    #[test]
+    fn test_unknown_ro_compat_mounts_read_only() {
+        let mut image = build_image();
+        let mut sb = read_superblock(&image);
+        sb.s_feature_ro_compat |= 0x8000_0000;
+        put(&mut image, SUPERBLOCK_OFFSET as usize, sb);
+        reseal_superblock(&mut image);
+
+        let fs = mount_image("ro", &image).unwrap();
+        let root = fs.root();
+        let data = root.lookup("data").unwrap();
+        assert_eq!(read_all(&data).len(), 8 * TEST_BLOCK);
+        assert_eq!(data.write(0, b"x").err(), Some(FsError::ReadOnly));
+        assert_eq!(
+            root.create("new", FileMode::new(0o644)).err(),
+            Some(FsError::ReadOnly)
+        );
+    }
+
+    /// Everything in the root directory of a mounted image, with file
+    /// contents, link targets and directory listings
+    fn snapshot(fs: &Arc<Ext4Filesystem>) -> Vec<(String, Vec<u8>)> {
+        let root = fs.root();
+        let mut entries = Vec::new();
+        for entry in root.readdir().unwrap() {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            let node = root.lookup(&entry.name).unwrap();
+            let contents = match entry.file_type {
+                FileType::Symlink => node.readlink().unwrap().into_bytes(),
+                FileType::Directory => {
+                    let mut names: Vec<String> = node
+                        .readdir()
+                        .unwrap()
+                        .into_iter()
+                        .map(|e| e.name)
+                        .collect();
+                    names.sort();
+                    names.join("/").into_bytes()
+                }
+                _ => read_all(&node),
+            };
+            entries.push((entry.name, contents));
+        }
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn test_replay_after_interrupted_writes() {
+        let mut base = build_image();
+        add_journal(&mut base);
+        let device = MemDisk::new(base.clone());
+        let fs = Ext4Filesystem::mount(device.clone()).unwrap();
+        let root = fs.root();
+        let file = |name: &str| root.lookup(name).unwrap();
+
+        // Each step is one transaction; the extent leaf of `data` is logged,
+        // then freed and revoked, and likely reused for `again`'s data
+        let long_target = "../".repeat(30) + "data";
+        let steps: [&dyn Fn(); 9] = [
+            &|| drop(root.create("new", FileMode::new(0o644)).unwrap()),
+            &|| assert_eq!(file("new").write(0, &[b'n'; 3000]), Ok(3000)),
+            &|| drop(root.mkdir("dir", FileMode::new(0o755)).unwrap()),
+            &|| drop(file("dir").symlink("long", &long_target).unwrap()),
+            &|| root.unlink("link").unwrap(),
+            &|| assert_eq!(file("data").write(9 * TEST_BLOCK as u64, b"tail"), Ok(4)),
+            &|| file("data").truncate(TEST_BLOCK as u64).unwrap(),
+            &|| drop(root.create("again", FileMode::new(0o644)).unwrap()),
+            &|| assert_eq!(file("again").write(0, &[b'a'; 2500]), Ok(2500)),
+        ];
+
+        let mut states = vec![snapshot(&fs)];
+        let mut marks = Vec::new();
+        for step in steps {
+            step();
+            states.push(snapshot(&fs));
+            marks.push(device.writes.lock().len());
+        }
+
+        // Cut the writes off at every point, as a crash would, and mount
+        // what reached the disk
+        let writes = device.writes.lock().clone();
+        for cut in 0..=writes.len() {
+            let mut image = base.clone();
+            for (offset, data) in &writes[..cut] {
+                image[*offset..offset + data.len()].copy_from_slice(data);
+            }
+            let fs = Ext4Filesystem::mount(MemDisk::new(image))
+                .unwrap_or_else(|e| panic!("cut {}: {:?}", cut, e));
+
+            // Steps that returned are durable; the one in flight is all or
+            // nothing
+            let done = marks.iter().filter(|&&mark| mark <= cut).count();
+            let state = snapshot(&fs);
+            assert!(
+                state == states[done] || (done < steps.len() && state == states[done + 1]),
+                "cut {} after {} steps",
+                cut,
+                done
+            );
+
+            // Bitmaps and counts still agree well enough to allocate
+            let after = fs.root().create("after", FileMode::new(0o644)).unwrap();
+            after.write(0, &[1; 2000]).unwrap();
+        }
     }
 }
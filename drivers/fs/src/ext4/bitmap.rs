@@ -0,0 +1,273 @@
+//! ext4 Block and Inode Bitmaps
+//!
+//! Each group tracks its free blocks and inodes in a bitmap block and in
+//! the counters of its descriptor. Both are changed through the running
+//! transaction, so an allocation commits together with the metadata that
+//! points at it. Groups whose block bitmap was never initialized
+//! (`BLOCK_UNINIT`) are skipped; an uninitialized inode bitmap is simply
+//! all free.
+
+use super::{checksum, Ext4Filesystem, Ext4GroupDesc};
+use crate::FsError;
+use alloc::vec::Vec;
+
+/// Group descriptor flags
+const EXT4_BG_INODE_UNINIT: u16 = 0x1;
+const EXT4_BG_BLOCK_UNINIT: u16 = 0x2;
+
+/// Find a clear bit in `bitmap[..bits]` that is also `usable`, searching
+/// from `start` and wrapping
+fn find_clear_bit(
+    bitmap: &[u8],
+    bits: usize,
+    start: usize,
+    usable: impl Fn(usize) -> bool,
+) -> Option<usize> {
+    (start..bits)
+        .chain(0..start.min(bits))
+        .find(|&bit| bitmap[bit / 8] & (1 << (bit % 8)) == 0 && usable(bit))
+}
+
+impl Ext4Filesystem {
+    /// Number of blocks in a group; the last one may be short
+    fn blocks_in_group(&self, group: u32) -> u64 {
+        let start = self.first_data_block + group as u64 * self.blocks_per_group as u64;
+        (self.blocks_count - start).min(self.blocks_per_group as u64)
+    }
+
+    /// Read a group's block bitmap and verify its checksum
+    fn read_block_bitmap(&self, desc: &Ext4GroupDesc) -> Result<Vec<u8>, FsError> {
+        let bitmap = self.read_block(desc.block_bitmap())?;
+        let stored =
+            desc.bg_block_bitmap_csum_lo as u32 | (desc.bg_block_bitmap_csum_hi as u32) << 16;
+        if self.metadata_csum() && self.block_bitmap_csum(&bitmap) != stored {
+            return Err(FsError::InvalidData);
+        }
+        Ok(bitmap)
+    }
+
+    /// Read a group's inode bitmap and verify its checksum
+    fn read_inode_bitmap(&self, desc: &Ext4GroupDesc) -> Result<Vec<u8>, FsError> {
+        if desc.bg_flags & EXT4_BG_INODE_UNINIT != 0 {
+            return Ok(vec![0u8; self.block_size as usize]);
+        }
+        let bitmap = self.read_block(desc.inode_bitmap())?;
+        let stored =
+            desc.bg_inode_bitmap_csum_lo as u32 | (desc.bg_inode_bitmap_csum_hi as u32) << 16;
+        if self.metadata_csum() && self.inode_bitmap_csum(&bitmap) != stored {
+            return Err(FsError::InvalidData);
+        }
+        Ok(bitmap)
+    }
+
+    /// Checksum of a block bitmap, truncated to what the descriptor stores
+    fn block_bitmap_csum(&self, bitmap: &[u8]) -> u32 {
+        let len = self.blocks_per_group as usize / 8;
+        self.truncate_bitmap_csum(checksum::crc32c(self.csum_seed, &bitmap[..len]))
+    }
+
+    /// Checksum of an inode bitmap, truncated to what the descriptor stores
+    fn inode_bitmap_csum(&self, bitmap: &[u8]) -> u32 {
+        let len = self.inodes_per_group as usize / 8;
+        self.truncate_bitmap_csum(checksum::crc32c(self.csum_seed, &bitmap[..len]))
+    }
+
+    /// Small descriptors only hold the low half of bitmap checksums
+    fn truncate_bitmap_csum(&self, sum: u32) -> u32 {
+        if self.desc_size >= super::EXT4_MIN_DESC_SIZE_64BIT {
+            sum
+        } else {
+            sum & 0xFFFF
+        }
+    }
+
+    /// Store a block bitmap and its checksum
+    fn write_block_bitmap(&self, desc: &mut Ext4GroupDesc, bitmap: Vec<u8>) {
+        if self.metadata_csum() {
+            let sum = self.block_bitmap_csum(&bitmap);
+            desc.bg_block_bitmap_csum_lo = sum as u16;
+            desc.bg_block_bitmap_csum_hi = (sum >> 16) as u16;
+        }
+        self.dirty_block(desc.block_bitmap(), bitmap);
+    }
+
+    /// Store an inode bitmap and its checksum
+    fn write_inode_bitmap(&self, desc: &mut Ext4GroupDesc, bitmap: Vec<u8>) {
+        if self.metadata_csum() {
+            let sum = self.inode_bitmap_csum(&bitmap);
+            desc.bg_inode_bitmap_csum_lo = sum as u16;
+            desc.bg_inode_bitmap_csum_hi = (sum >> 16) as u16;
+        }
+        desc.bg_flags &= !EXT4_BG_INODE_UNINIT;
+        self.dirty_block(desc.inode_bitmap(), bitmap);
+    }
+
+    /// Allocate a block, as close after `goal` as possible
+    pub(super) fn alloc_block(&self, goal: u64) -> Result<u64, FsError> {
+        let group_count = self.group_count();
+        let goal = goal.clamp(self.first_data_block, self.blocks_count - 1) - self.first_data_block;
+        let goal_group = (goal / self.blocks_per_group as u64) as u32;
+
+        for i in 0..group_count {
+            let group = (goal_group + i) % group_count;
+            let mut desc = self.group_desc(group);
+            if desc.free_blocks() == 0 || desc.bg_flags & EXT4_BG_BLOCK_UNINIT != 0 {
+                continue;
+            }
+
+            let mut bitmap = self.read_block_bitmap(&desc)?;
+            let start = match group == goal_group {
+                true => (goal % self.blocks_per_group as u64) as usize,
+                false => 0,
+            };
+            let bits = self.blocks_in_group(group) as usize;
+            let base = self.first_data_block + group as u64 * self.blocks_per_group as u64;
+            // Blocks freed by the running transaction still hold committed
+            // contents until it commits
+            let found = {
+                let cache = self.cache.lock();
+                find_clear_bit(&bitmap, bits, start, |bit| {
+                    !cache.is_freed(base + bit as u64)
+                })
+            };
+            let Some(bit) = found else {
+                continue;
+            };
+
+            bitmap[bit / 8] |= 1 << (bit % 8);
+            self.write_block_bitmap(&mut desc, bitmap);
+            desc.set_free_blocks(desc.free_blocks() - 1);
+            self.write_group_desc(group, desc)?;
+            return Ok(base + bit as u64);
+        }
+
+        Err(FsError::NoSpaceLeft)
+    }
+
+    /// Free `count` blocks starting at `start`
+    ///
+    /// Freed `metadata` blocks are revoked, so an older copy in the journal
+    /// can never be replayed over whatever reuses them.
+    pub(super) fn free_blocks(
+        &self,
+        start: u64,
+        count: u64,
+        metadata: bool,
+    ) -> Result<(), FsError> {
+        let mut block = start;
+        let end = start + count;
+        while block < end {
+            if block < self.first_data_block || block >= self.blocks_count {
+                return Err(FsError::InvalidData);
+            }
+            let offset = block - self.first_data_block;
+            let group = (offset / self.blocks_per_group as u64) as u32;
+            let first_bit = (offset % self.blocks_per_group as u64) as usize;
+            let run = (end - block).min(self.blocks_per_group as u64 - first_bit as u64) as usize;
+
+            let mut desc = self.group_desc(group);
+            if desc.bg_flags & EXT4_BG_BLOCK_UNINIT != 0 {
+                return Err(FsError::InvalidData);
+            }
+            let mut bitmap = self.read_block_bitmap(&desc)?;
+            for bit in first_bit..first_bit + run {
+                if bitmap[bit / 8] & (1 << (bit % 8)) == 0 {
+                    return Err(FsError::InvalidData);
+                }
+                bitmap[bit / 8] &= !(1 << (bit % 8));
+            }
+            self.write_block_bitmap(&mut desc, bitmap);
+            desc.set_free_blocks(desc.free_blocks() + run as u32);
+            self.write_group_desc(group, desc)?;
+
+            let mut cache = self.cache.lock();
+            cache.freed.push((block, run as u64));
+            if metadata {
+                for freed in block..block + run as u64 {
+                    cache.running.remove(&freed);
+                    cache.revoked.insert(freed);
+                }
+            }
+            block += run as u64;
+        }
+        Ok(())
+    }
+
+    /// Allocate an inode, preferring the group of `parent`
+    pub(super) fn alloc_inode(&self, parent: u32, is_dir: bool) -> Result<u32, FsError> {
+        let group_count = self.group_count();
+        let ipg = self.inodes_per_group;
+        let first_ino = self.superblock.read().s_first_ino;
+        let parent_group = (parent - 1) / ipg;
+
+        for i in 0..group_count {
+            let group = (parent_group + i) % group_count;
+            let mut desc = self.group_desc(group);
+            if desc.free_inodes() == 0 {
+                continue;
+            }
+
+            let mut bitmap = self.read_inode_bitmap(&desc)?;
+            // Inodes below s_first_ino are reserved
+            let reserved = first_ino.saturating_sub(group * ipg + 1) as usize;
+            let Some(bit) =
+                find_clear_bit(&bitmap, ipg as usize, reserved.min(ipg as usize), |bit| {
+                    bit >= reserved
+                })
+            else {
+                continue;
+            };
+
+            bitmap[bit / 8] |= 1 << (bit % 8);
+            self.write_inode_bitmap(&mut desc, bitmap);
+            desc.set_free_inodes(desc.free_inodes() - 1);
+            if is_dir {
+                desc.set_used_dirs(desc.used_dirs() + 1);
+            }
+            // Everything up to the new inode now counts as used table space
+            let unused = ipg - bit as u32 - 1;
+            if desc.itable_unused() > unused {
+                desc.set_itable_unused(unused);
+            }
+            self.write_group_desc(group, desc)?;
+            return Ok(group * ipg + bit as u32 + 1);
+        }
+
+        Err(FsError::NoSpaceLeft)
+    }
+
+    /// Return an inode to its group
+    pub(super) fn free_inode(&self, ino: u32, is_dir: bool) -> Result<(), FsError> {
+        let group = (ino - 1) / self.inodes_per_group;
+        let bit = ((ino - 1) % self.inodes_per_group) as usize;
+
+        let mut desc = self.group_desc(group);
+        let mut bitmap = self.read_inode_bitmap(&desc)?;
+        if bitmap[bit / 8] & (1 << (bit % 8)) == 0 {
+            return Err(FsError::InvalidData);
+        }
+        bitmap[bit / 8] &= !(1 << (bit % 8));
+        self.write_inode_bitmap(&mut desc, bitmap);
+        desc.set_free_inodes(desc.free_inodes() + 1);
+        if is_dir {
+            desc.set_used_dirs(desc.used_dirs().saturating_sub(1));
+        }
+        self.write_group_desc(group, desc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_clear_bit_wraps() {
+        let bitmap = [0xFF, 0x0F, 0xFF];
+        let any = |_| true;
+        assert_eq!(find_clear_bit(&bitmap, 24, 0, any), Some(12));
+        assert_eq!(find_clear_bit(&bitmap, 24, 14, any), Some(14));
+        assert_eq!(find_clear_bit(&bitmap, 24, 16, any), Some(12));
+        assert_eq!(find_clear_bit(&bitmap, 12, 0, any), None);
+        assert_eq!(find_clear_bit(&bitmap, 24, 0, |bit| bit > 13), Some(14));
+    }
+}
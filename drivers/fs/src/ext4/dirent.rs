@@ -0,0 +1,257 @@
+//! ext4 Directory Entry Updates
+//!
+//! A leaf block is a chain of entries whose record lengths add up to the
+//! block (minus the checksum tail with `metadata_csum`). New entries take
+//! the slack at the end of an existing record; removed entries are folded
+//! into the record before them, or just cleared when they come first.
+
+use super::{checksum, htree, le16, le32, Ext4Filesystem, Inode};
+use super::{EXT4_FEATURE_INCOMPAT_FILETYPE, EXT4_FT_DIR, EXT4_FT_DIR_CSUM};
+use crate::FsError;
+use alloc::vec::Vec;
+
+/// Longest name an entry can hold
+pub(super) const EXT4_NAME_LEN: usize = 255;
+
+/// Record length an entry with a name of `name_len` bytes needs
+pub(super) fn size(name_len: usize) -> usize {
+    (8 + name_len + 3) & !3
+}
+
+/// Write an entry header and name at `pos`
+pub(super) fn put(
+    block: &mut [u8],
+    pos: usize,
+    ino: u32,
+    rec_len: usize,
+    file_type: u8,
+    name: &[u8],
+) {
+    block[pos..pos + 4].copy_from_slice(&ino.to_le_bytes());
+    block[pos + 4..pos + 6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+    block[pos + 6] = name.len() as u8;
+    block[pos + 7] = file_type;
+    block[pos + 8..pos + 8 + name.len()].copy_from_slice(name);
+}
+
+/// Put an entry into the first record of `block[..end]` with room for it
+///
+/// Returns `false` if none has.
+pub(super) fn insert(block: &mut [u8], end: usize, name: &[u8], ino: u32, file_type: u8) -> bool {
+    let needed = size(name.len());
+    let mut pos = 0;
+    while pos + 8 <= end {
+        let rec_len = le16(block, pos + 4) as usize;
+        if rec_len < 8 || pos + rec_len > end {
+            return false;
+        }
+        let used = match le32(block, pos) {
+            0 => 0,
+            _ => size(block[pos + 6] as usize),
+        };
+        if rec_len >= used + needed {
+            if used == 0 {
+                put(block, pos, ino, rec_len, file_type, name);
+            } else {
+                block[pos + 4..pos + 6].copy_from_slice(&(used as u16).to_le_bytes());
+                put(block, pos + used, ino, rec_len - used, file_type, name);
+            }
+            return true;
+        }
+        pos += rec_len;
+    }
+    false
+}
+
+/// Remove the entry for `name` from `block[..end]`, returning its inode
+fn remove(block: &mut [u8], end: usize, name: &[u8]) -> Option<u32> {
+    let mut prev = None;
+    let mut pos = 0;
+    while pos + 8 <= end {
+        let rec_len = le16(block, pos + 4) as usize;
+        if rec_len < 8 {
+            return None;
+        }
+        let ino = le32(block, pos);
+        let name_len = block[pos + 6] as usize;
+        if ino != 0 && &block[pos + 8..pos + 8 + name_len] == name {
+            match prev {
+                Some(prev) => {
+                    let merged = le16(block, prev + 4) as usize + rec_len;
+                    block[prev + 4..prev + 6].copy_from_slice(&(merged as u16).to_le_bytes());
+                }
+                None => block[pos..pos + 4].fill(0),
+            }
+            return Some(ino);
+        }
+        prev = Some(pos);
+        pos += rec_len;
+    }
+    None
+}
+
+impl Ext4Filesystem {
+    /// End of the entries in a leaf block, before any checksum tail
+    pub(super) fn dir_leaf_end(&self) -> usize {
+        let block_size = self.block_size as usize;
+        if self.metadata_csum() {
+            block_size - checksum::DIRENT_TAIL_SIZE
+        } else {
+            block_size
+        }
+    }
+
+    /// File type to store in an entry
+    fn stored_file_type(&self, file_type: u8) -> u8 {
+        if self.has_feature_incompat(EXT4_FEATURE_INCOMPAT_FILETYPE) {
+            file_type
+        } else {
+            0
+        }
+    }
+
+    /// Whether lookups in `dir` go through its htree
+    pub(super) fn is_indexed(&self, dir: &Inode) -> bool {
+        dir.flags() & super::EXT4_INDEX_FL != 0
+            && self.has_feature_compat(super::EXT4_FEATURE_COMPAT_DIR_INDEX)
+    }
+
+    /// Seal a leaf block with its checksum tail and queue it for the journal
+    pub(super) fn write_dir_leaf(&self, dir: &Inode, block: u64, mut data: Vec<u8>) {
+        if self.metadata_csum() {
+            let tail = self.dir_leaf_end();
+            put(
+                &mut data,
+                tail,
+                0,
+                checksum::DIRENT_TAIL_SIZE,
+                EXT4_FT_DIR_CSUM,
+                b"",
+            );
+            let sum = checksum::dirent_block(dir.csum_seed, &data);
+            data[tail + 8..tail + 12].copy_from_slice(&sum.to_le_bytes());
+        }
+        self.dirty_block(block, data);
+    }
+
+    /// The first block of a new directory, holding `.` and `..`
+    pub(super) fn init_dir_block(&self, dir: &Inode, block: u64, ino: u32, parent: u32) {
+        let mut data = vec![0u8; self.block_size as usize];
+        let file_type = self.stored_file_type(EXT4_FT_DIR);
+        put(&mut data, 0, ino, 12, file_type, b".");
+        put(
+            &mut data,
+            12,
+            parent,
+            self.dir_leaf_end() - 12,
+            file_type,
+            b"..",
+        );
+        self.write_dir_leaf(dir, block, data);
+    }
+
+    /// Add an entry to a directory
+    ///
+    /// The directory grows by a block when no leaf has room. The caller
+    /// writes `dir` back afterwards.
+    pub(super) fn add_entry(
+        &self,
+        dir: &mut Inode,
+        name: &[u8],
+        ino: u32,
+        file_type: u8,
+    ) -> Result<(), FsError> {
+        if name.is_empty() || name.len() > EXT4_NAME_LEN {
+            return Err(FsError::InvalidArgument);
+        }
+        let file_type = self.stored_file_type(file_type);
+
+        if self.is_indexed(dir) {
+            // Names placed outside the leaf their hash selects could never
+            // be found again
+            return match htree::add_entry(self, dir, name, ino, file_type)? {
+                true => Ok(()),
+                false => Err(FsError::NotSupported),
+            };
+        }
+
+        let end = self.dir_leaf_end();
+        for logical in 0..self.block_count(dir) {
+            let block = self.map_block(dir, logical)?.ok_or(FsError::InvalidData)?;
+            let mut data = self.read_block(block)?;
+            self.verify_dir_block(dir, logical, &data)?;
+            if insert(&mut data, end, name, ino, file_type) {
+                self.write_dir_leaf(dir, block, data);
+                return Ok(());
+            }
+        }
+
+        let (_, block) = self.append_block(dir)?;
+        let mut data = vec![0u8; self.block_size as usize];
+        put(&mut data, 0, ino, end, file_type, name);
+        self.write_dir_leaf(dir, block, data);
+        Ok(())
+    }
+
+    /// Remove the entry for `name` from a directory, returning its inode
+    pub(super) fn remove_entry(&self, dir: &Inode, name: &[u8]) -> Result<u32, FsError> {
+        let leaves = match self.is_indexed(dir) {
+            true => htree::lookup(self, dir, name)?,
+            false => None,
+        };
+        let leaves = leaves.unwrap_or_else(|| (0..self.block_count(dir)).collect());
+
+        let end = self.dir_leaf_end();
+        for logical in leaves {
+            let block = self.map_block(dir, logical)?.ok_or(FsError::InvalidData)?;
+            let mut data = self.read_block(block)?;
+            self.verify_dir_block(dir, logical, &data)?;
+            if let Some(ino) = remove(&mut data, end, name) {
+                self.write_dir_leaf(dir, block, data);
+                return Ok(ino);
+            }
+        }
+        Err(FsError::NotFound)
+    }
+
+    /// Whether a directory holds nothing but `.` and `..`
+    pub(super) fn dir_is_empty(&self, dir: &Inode) -> Result<bool, FsError> {
+        for logical in 0..self.block_count(dir) {
+            let block = self.read_dir_block(dir, logical)?;
+            self.verify_dir_block(dir, logical, &block)?;
+            let entries = super::parse_dir_block(&block)?;
+            if entries.iter().any(|e| e.name != b"." && e.name != b"..") {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_remove_reuse_space() {
+        let mut block = [0u8; 64];
+        put(&mut block, 0, 0, 64, 0, b"");
+        assert!(insert(&mut block, 64, b"alpha", 11, 1));
+        assert!(insert(&mut block, 64, b"beta", 12, 1));
+        assert_eq!(le16(&block, 4) as usize, size(5));
+
+        // 16 + 12 bytes are used, so a 40-byte record no longer fits
+        assert!(!insert(&mut block, 64, &[b'x'; 32], 13, 1));
+
+        // Removing the first entry clears it; the space is reused in place
+        assert_eq!(remove(&mut block, 64, b"alpha"), Some(11));
+        assert_eq!(le32(&block, 0), 0);
+        assert_eq!(remove(&mut block, 64, b"alpha"), None);
+        assert!(insert(&mut block, 64, b"gamma", 14, 1));
+        assert_eq!(le32(&block, 0), 14);
+
+        // Removing a later one folds it into its predecessor
+        assert_eq!(remove(&mut block, 64, b"beta"), Some(12));
+        assert_eq!(le16(&block, 4), 64);
+    }
+}
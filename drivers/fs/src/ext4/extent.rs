@@ -0,0 +1,481 @@
+//! ext4 Extent Trees
+//!
+//! Inodes with `EXT4_EXTENTS_FL` map logical blocks through a B-tree whose
+//! root lives in `i_block`. Index nodes point one level down; leaves hold
+//! runs of contiguous physical blocks. Tree blocks below the root end in a
+//! checksum tail when `metadata_csum` is enabled.
+//!
+//! Writers work on the flat list of a file's extents and then store the
+//! whole tree again, rebuilding as many levels as the list needs.
+
+use super::{le16, le32, Ext4Filesystem, Inode};
+use crate::FsError;
+use alloc::vec::Vec;
+
+/// Extent node magic
+pub const EXT4_EXT_MAGIC: u16 = 0xF30A;
+
+/// Size of the node header and of each index or extent entry
+const ENTRY_SIZE: usize = 12;
+
+/// Extents longer than this are unwritten (preallocated) and read as zeros
+const EXT_INIT_MAX_LEN: u32 = 32768;
+
+/// Deepest tree the on-disk format allows
+const EXT4_MAX_DEPTH: u16 = 5;
+
+/// Entries that fit in the root inside `i_block`
+const ROOT_MAX_ENTRIES: usize = 4;
+
+/// Parsed extent node header
+struct NodeHeader {
+    entries: usize,
+    max: usize,
+    depth: u16,
+}
+
+impl NodeHeader {
+    /// Parse and sanity check the header at the start of `node`
+    fn parse(node: &[u8]) -> Result<Self, FsError> {
+        if node.len() < ENTRY_SIZE || le16(node, 0) != EXT4_EXT_MAGIC {
+            return Err(FsError::InvalidData);
+        }
+        let header = NodeHeader {
+            entries: le16(node, 2) as usize,
+            max: le16(node, 4) as usize,
+            depth: le16(node, 6),
+        };
+        if header.entries > header.max
+            || ENTRY_SIZE * (header.max + 1) > node.len()
+            || header.depth > EXT4_MAX_DEPTH
+        {
+            return Err(FsError::InvalidData);
+        }
+        Ok(header)
+    }
+
+    /// Offset of entry `i`
+    fn entry(i: usize) -> usize {
+        ENTRY_SIZE * (i + 1)
+    }
+
+    /// Write a header at the start of `node`
+    fn put(node: &mut [u8], entries: usize, max: usize, depth: u16) {
+        node[0..2].copy_from_slice(&EXT4_EXT_MAGIC.to_le_bytes());
+        node[2..4].copy_from_slice(&(entries as u16).to_le_bytes());
+        node[4..6].copy_from_slice(&(max as u16).to_le_bytes());
+        node[6..8].copy_from_slice(&depth.to_le_bytes());
+        node[8..12].fill(0);
+    }
+}
+
+/// A run of contiguous blocks in an extent leaf
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) struct Extent {
+    /// First logical block
+    pub(super) first: u32,
+    pub(super) len: u32,
+    /// First physical block
+    pub(super) start: u64,
+    /// Allocated but never written; reads as zeros
+    pub(super) unwritten: bool,
+}
+
+impl Extent {
+    fn parse(node: &[u8], off: usize) -> Result<Self, FsError> {
+        let raw_len = le16(node, off + 4) as u32;
+        let (len, unwritten) = if raw_len > EXT_INIT_MAX_LEN {
+            (raw_len - EXT_INIT_MAX_LEN, true)
+        } else {
+            (raw_len, false)
+        };
+        let first = le32(node, off);
+        let start = le32(node, off + 8) as u64 | (le16(node, off + 6) as u64) << 32;
+
+        // Both runs must end within range, so `end` and `joins` cannot overflow
+        if first.checked_add(len).is_none() || start.checked_add(len as u64).is_none() {
+            return Err(FsError::InvalidData);
+        }
+        Ok(Extent {
+            first,
+            len,
+            start,
+            unwritten,
+        })
+    }
+
+    fn encode(&self) -> [u8; ENTRY_SIZE] {
+        let raw_len = if self.unwritten {
+            self.len + EXT_INIT_MAX_LEN
+        } else {
+            self.len
+        };
+        let mut entry = [0u8; ENTRY_SIZE];
+        entry[0..4].copy_from_slice(&self.first.to_le_bytes());
+        entry[4..6].copy_from_slice(&(raw_len as u16).to_le_bytes());
+        entry[6..8].copy_from_slice(&((self.start >> 32) as u16).to_le_bytes());
+        entry[8..12].copy_from_slice(&(self.start as u32).to_le_bytes());
+        entry
+    }
+
+    /// Logical block following the extent
+    pub(super) fn end(&self) -> u32 {
+        self.first + self.len
+    }
+
+    /// Longest run the on-disk length can describe
+    fn max_len(&self) -> u32 {
+        if self.unwritten {
+            EXT_INIT_MAX_LEN - 1
+        } else {
+            EXT_INIT_MAX_LEN
+        }
+    }
+
+    /// Whether `next` continues this extent both logically and physically
+    fn joins(&self, next: &Extent) -> bool {
+        self.unwritten == next.unwritten
+            && self.end() == next.first
+            && self.start + self.len as u64 == next.start
+            && self.len + next.len <= self.max_len()
+    }
+}
+
+/// Encode an index entry pointing at `child`
+fn index_entry(first: u32, child: u64) -> [u8; ENTRY_SIZE] {
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[0..4].copy_from_slice(&first.to_le_bytes());
+    entry[4..8].copy_from_slice(&(child as u32).to_le_bytes());
+    entry[8..10].copy_from_slice(&((child >> 32) as u16).to_le_bytes());
+    entry
+}
+
+/// Map a logical block through the extent tree rooted in `inode`
+///
+/// Returns `None` for holes and for unwritten extents.
+pub(super) fn map_block(
+    fs: &Ext4Filesystem,
+    inode: &Inode,
+    logical: u32,
+) -> Result<Option<u64>, FsError> {
+    let mut node = inode.i_block().to_vec();
+    let mut header = NodeHeader::parse(&node)?;
+
+    while header.depth > 0 {
+        // The last index starting at or before the block covers it
+        let mut child = None;
+        for i in 0..header.entries {
+            let off = NodeHeader::entry(i);
+            if le32(&node, off) > logical {
+                break;
+            }
+            child = Some(le32(&node, off + 4) as u64 | (le16(&node, off + 8) as u64) << 32);
+        }
+        let Some(child) = child else {
+            return Ok(None);
+        };
+
+        let block = fs.read_block(child)?;
+        let next = NodeHeader::parse(&block)?;
+        if next.depth + 1 != header.depth {
+            return Err(FsError::InvalidData);
+        }
+        fs.verify_extent_block(inode, &block, NodeHeader::entry(next.max))?;
+        node = block;
+        header = next;
+    }
+
+    for i in 0..header.entries {
+        let extent = Extent::parse(&node, NodeHeader::entry(i))?;
+        if logical < extent.first || logical >= extent.end() {
+            continue;
+        }
+        if extent.unwritten {
+            return Ok(None);
+        }
+        return Ok(Some(extent.start + (logical - extent.first) as u64));
+    }
+
+    Ok(None)
+}
+
+/// Every extent of an inode in logical order, and the blocks holding the
+/// tree below the root
+pub(super) fn collect(
+    fs: &Ext4Filesystem,
+    inode: &Inode,
+) -> Result<(Vec<Extent>, Vec<u64>), FsError> {
+    let mut extents = Vec::new();
+    let mut nodes = Vec::new();
+    let root = inode.i_block();
+    let header = NodeHeader::parse(&root)?;
+    collect_node(fs, inode, &root, &header, &mut extents, &mut nodes)?;
+
+    if extents
+        .windows(2)
+        .any(|pair| pair[0].end() > pair[1].first || pair[1].len == 0)
+    {
+        return Err(FsError::InvalidData);
+    }
+    Ok((extents, nodes))
+}
+
+fn collect_node(
+    fs: &Ext4Filesystem,
+    inode: &Inode,
+    node: &[u8],
+    header: &NodeHeader,
+    extents: &mut Vec<Extent>,
+    nodes: &mut Vec<u64>,
+) -> Result<(), FsError> {
+    for i in 0..header.entries {
+        let off = NodeHeader::entry(i);
+        if header.depth == 0 {
+            extents.push(Extent::parse(node, off)?);
+            continue;
+        }
+
+        let child = le32(node, off + 4) as u64 | (le16(node, off + 8) as u64) << 32;
+        let block = fs.read_block(child)?;
+        let next = NodeHeader::parse(&block)?;
+        if next.depth + 1 != header.depth {
+            return Err(FsError::InvalidData);
+        }
+        fs.verify_extent_block(inode, &block, NodeHeader::entry(next.max))?;
+        nodes.push(child);
+        collect_node(fs, inode, &block, &next, extents, nodes)?;
+    }
+    Ok(())
+}
+
+/// Rewrite the extent tree of `inode` to hold exactly `extents`
+///
+/// Blocks of the old tree in `nodes` are reused before new ones are
+/// allocated, and any left over are freed. Returns the change in the
+/// number of tree blocks.
+pub(super) fn store(
+    fs: &Ext4Filesystem,
+    inode: &mut Inode,
+    extents: &[Extent],
+    mut nodes: Vec<u64>,
+) -> Result<i64, FsError> {
+    let old = nodes.len() as i64;
+    let block_size = fs.block_size as usize;
+    // Leaves the tail word free for the checksum
+    let per_block = (block_size - ENTRY_SIZE) / ENTRY_SIZE;
+    let goal = extents.first().map_or(0, |e| e.start);
+
+    let mut level: Vec<(u32, [u8; ENTRY_SIZE])> =
+        extents.iter().map(|e| (e.first, e.encode())).collect();
+    let mut depth = 0;
+    let mut used = 0;
+    while level.len() > ROOT_MAX_ENTRIES {
+        if depth == EXT4_MAX_DEPTH {
+            return Err(FsError::NoSpaceLeft);
+        }
+
+        let mut parents = Vec::new();
+        for chunk in level.chunks(per_block) {
+            let block = match nodes.get(used) {
+                Some(&block) => block,
+                None => {
+                    let block = fs.alloc_block(goal)?;
+                    nodes.push(block);
+                    block
+                }
+            };
+            used += 1;
+
+            let mut node = vec![0u8; block_size];
+            NodeHeader::put(&mut node, chunk.len(), per_block, depth);
+            for (i, (_, entry)) in chunk.iter().enumerate() {
+                let off = NodeHeader::entry(i);
+                node[off..off + ENTRY_SIZE].copy_from_slice(entry);
+            }
+            fs.seal_extent_block(inode, &mut node, NodeHeader::entry(per_block));
+            fs.dirty_block(block, node);
+            parents.push((chunk[0].0, index_entry(chunk[0].0, block)));
+        }
+        level = parents;
+        depth += 1;
+    }
+
+    let mut root = [0u8; 60];
+    NodeHeader::put(&mut root, level.len(), ROOT_MAX_ENTRIES, depth);
+    for (i, (_, entry)) in level.iter().enumerate() {
+        let off = NodeHeader::entry(i);
+        root[off..off + ENTRY_SIZE].copy_from_slice(entry);
+    }
+    inode.set_i_block(&root);
+
+    for &block in &nodes[used..] {
+        fs.free_blocks(block, 1, true)?;
+    }
+    Ok(used as i64 - old)
+}
+
+/// Find the extent covering `logical`, or where one covering it would go
+pub(super) fn find(extents: &[Extent], logical: u32) -> Result<usize, usize> {
+    let pos = extents.partition_point(|e| e.end() <= logical);
+    match extents.get(pos) {
+        Some(e) if e.first <= logical => Ok(pos),
+        _ => Err(pos),
+    }
+}
+
+/// Map the unmapped `logical` to `block`, merging with neighbours that line up
+pub(super) fn insert(extents: &mut Vec<Extent>, logical: u32, block: u64) {
+    let Err(pos) = find(extents, logical) else {
+        return;
+    };
+    let new = Extent {
+        first: logical,
+        len: 1,
+        start: block,
+        unwritten: false,
+    };
+
+    if pos > 0 && extents[pos - 1].joins(&new) {
+        extents[pos - 1].len += 1;
+        if pos < extents.len() && extents[pos - 1].joins(&extents[pos]) {
+            extents[pos - 1].len += extents[pos].len;
+            extents.remove(pos);
+        }
+    } else if pos < extents.len() && new.joins(&extents[pos]) {
+        let next = &mut extents[pos];
+        next.first -= 1;
+        next.start -= 1;
+        next.len += 1;
+    } else {
+        extents.insert(pos, new);
+    }
+}
+
+/// Convert one block of the unwritten extent at `index` to written
+///
+/// Returns the physical block.
+pub(super) fn mark_written(extents: &mut Vec<Extent>, index: usize, logical: u32) -> u64 {
+    let extent = extents.remove(index);
+    let offset = logical - extent.first;
+    let block = extent.start + offset as u64;
+
+    let after = Extent {
+        first: logical + 1,
+        len: extent.len - offset - 1,
+        start: block + 1,
+        unwritten: true,
+    };
+    if after.len > 0 {
+        extents.insert(index, after);
+    }
+    if offset > 0 {
+        extents.insert(
+            index,
+            Extent {
+                len: offset,
+                ..extent
+            },
+        );
+    }
+    insert(extents, logical, block);
+    block
+}
+
+/// Unmap every block from `logical` on
+///
+/// Returns the freed physical runs as (start, count).
+pub(super) fn truncate(extents: &mut Vec<Extent>, logical: u32) -> Vec<(u64, u64)> {
+    let mut freed = Vec::new();
+    while let Some(last) = extents.last_mut() {
+        if last.end() <= logical {
+            break;
+        }
+        if last.first >= logical {
+            freed.push((last.start, last.len as u64));
+            extents.pop();
+        } else {
+            let cut = last.end() - logical;
+            last.len -= cut;
+            freed.push((last.start + last.len as u64, cut as u64));
+        }
+    }
+    freed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(first: u32, len: u32, start: u64, unwritten: bool) -> Extent {
+        Extent {
+            first,
+            len,
+            start,
+            unwritten,
+        }
+    }
+
+    #[test]
+    fn test_insert_merges_neighbours() {
+        let mut extents = Vec::new();
+        insert(&mut extents, 0, 100);
+        insert(&mut extents, 1, 101);
+        insert(&mut extents, 3, 103);
+        assert_eq!(
+            extents,
+            [extent(0, 2, 100, false), extent(3, 1, 103, false)]
+        );
+
+        // Filling the gap joins both sides; a block out of line does not
+        insert(&mut extents, 2, 102);
+        insert(&mut extents, 5, 200);
+        assert_eq!(
+            extents,
+            [extent(0, 4, 100, false), extent(5, 1, 200, false)]
+        );
+        assert_eq!(find(&extents, 4), Err(1));
+        assert_eq!(find(&extents, 5), Ok(1));
+    }
+
+    #[test]
+    fn test_mark_written_splits_unwritten() {
+        let mut extents = vec![extent(0, 2, 50, false), extent(2, 4, 52, true)];
+        assert_eq!(mark_written(&mut extents, 1, 4), 54);
+        assert_eq!(
+            extents,
+            [
+                extent(0, 2, 50, false),
+                extent(2, 2, 52, true),
+                extent(4, 1, 54, false),
+                extent(5, 1, 55, true),
+            ]
+        );
+
+        assert_eq!(mark_written(&mut extents, 1, 2), 52);
+        assert_eq!(extents[0], extent(0, 3, 50, false));
+    }
+
+    #[test]
+    fn test_parse_rejects_overflowing_extent() {
+        let mut node = [0u8; 2 * ENTRY_SIZE];
+        node[ENTRY_SIZE..2 * ENTRY_SIZE]
+            .copy_from_slice(&extent(u32::MAX - 10, 8, 7, false).encode());
+        assert_eq!(
+            Extent::parse(&node, ENTRY_SIZE),
+            Ok(extent(u32::MAX - 10, 8, 7, false))
+        );
+
+        // The logical run would wrap past the last block
+        node[ENTRY_SIZE..2 * ENTRY_SIZE]
+            .copy_from_slice(&extent(u32::MAX - 2, 8, 7, false).encode());
+        assert_eq!(Extent::parse(&node, ENTRY_SIZE), Err(FsError::InvalidData));
+    }
+
+    #[test]
+    fn test_truncate_returns_freed_runs() {
+        let mut extents = vec![extent(0, 4, 10, false), extent(8, 2, 40, false)];
+        assert_eq!(truncate(&mut extents, 2), [(40, 2), (12, 2)]);
+        assert_eq!(extents, [extent(0, 2, 10, false)]);
+        assert!(truncate(&mut extents, 2).is_empty());
+    }
+}
@@ -0,0 +1,838 @@
+//! ext4 Journal (JBD2)
+//!
+//! The journal is a circular log stored in an ordinary inode. Each
+//! transaction is a run of descriptor blocks, each followed by copies of
+//! the metadata blocks it tags, revoke blocks listing blocks whose older
+//! copies must not be replayed, and finally a commit block. Only a
+//! transaction whose commit block made it to disk counts. Once its blocks
+//! have been written to their home locations (checkpointed) the log space
+//! can be reused. The journal superblock records where the oldest live
+//! transaction starts; zero means the log is empty.
+//!
+//! All journal fields are big-endian, unlike the rest of ext4.
+
+use super::checksum::crc32c;
+use crate::ext2::BlockDevice;
+use crate::FsError;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Magic number at the start of every journal metadata block
+const JBD2_MAGIC_NUMBER: u32 = 0xC03B_3998;
+
+/// Journal block types
+const JBD2_DESCRIPTOR_BLOCK: u32 = 1;
+const JBD2_COMMIT_BLOCK: u32 = 2;
+const JBD2_SUPERBLOCK_V1: u32 = 3;
+const JBD2_SUPERBLOCK_V2: u32 = 4;
+const JBD2_REVOKE_BLOCK: u32 = 5;
+
+/// Journal incompatible feature flags
+pub(super) const JBD2_FEATURE_INCOMPAT_REVOKE: u32 = 0x1;
+pub(super) const JBD2_FEATURE_INCOMPAT_64BIT: u32 = 0x2;
+const JBD2_FEATURE_INCOMPAT_ASYNC_COMMIT: u32 = 0x4;
+const JBD2_FEATURE_INCOMPAT_CSUM_V2: u32 = 0x8;
+pub(super) const JBD2_FEATURE_INCOMPAT_CSUM_V3: u32 = 0x10;
+
+/// Incompatible journal features this driver understands
+const JBD2_FEATURE_INCOMPAT_SUPP: u32 = JBD2_FEATURE_INCOMPAT_REVOKE
+    | JBD2_FEATURE_INCOMPAT_64BIT
+    | JBD2_FEATURE_INCOMPAT_ASYNC_COMMIT
+    | JBD2_FEATURE_INCOMPAT_CSUM_V2
+    | JBD2_FEATURE_INCOMPAT_CSUM_V3;
+
+/// Descriptor tag flags
+const JBD2_FLAG_ESCAPE: u32 = 0x1;
+const JBD2_FLAG_SAME_UUID: u32 = 0x2;
+const JBD2_FLAG_LAST_TAG: u32 = 0x8;
+
+/// `s_checksum_type` for CRC32C
+const JBD2_CRC32C_CHKSUM: u8 = 4;
+
+/// Journal superblock field offsets
+const SB_BLOCKSIZE: usize = 0x0C;
+const SB_MAXLEN: usize = 0x10;
+const SB_FIRST: usize = 0x14;
+const SB_SEQUENCE: usize = 0x18;
+const SB_START: usize = 0x1C;
+const SB_FEATURE_INCOMPAT: usize = 0x28;
+const SB_UUID: usize = 0x30;
+const SB_CHECKSUM_TYPE: usize = 0x50;
+const SB_CHECKSUM: usize = 0xFC;
+/// Size of the journal superblock proper
+const SB_SIZE: usize = 0x400;
+
+/// Size of the common block header
+const HEADER_SIZE: usize = 12;
+/// Offset of `r_count` in a revoke block
+const REVOKE_COUNT: usize = HEADER_SIZE;
+/// Offset of the first checksum word and of the commit time in a commit block
+const COMMIT_CHKSUM: usize = 0x10;
+const COMMIT_SEC: usize = 0x30;
+/// Size of the checksum tail of descriptor and revoke blocks
+const BLOCK_TAIL_SIZE: usize = 4;
+/// Size of the UUID following a tag without `SAME_UUID`
+const UUID_SIZE: usize = 16;
+
+/// Read a big-endian u32 at `off`
+fn be32(buf: &[u8], off: usize) -> u32 {
+    u32::from_be_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
+}
+
+/// Write a big-endian u32 at `off`
+fn put_be32(buf: &mut [u8], off: usize, value: u32) {
+    buf[off..off + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Whether transaction ID `a` is at or after `b`, allowing for wraparound
+fn tid_geq(a: u32, b: u32) -> bool {
+    a.wrapping_sub(b) as i32 >= 0
+}
+
+/// A block tagged by a descriptor, as found in the log
+struct Tag {
+    /// Home location of the block
+    home: u64,
+    /// Log position of the copy
+    pos: u32,
+    flags: u32,
+    checksum: u32,
+}
+
+/// A committed transaction found in the log
+struct LoggedTransaction {
+    sequence: u32,
+    tags: Vec<Tag>,
+}
+
+/// An open journal
+pub(super) struct Journal {
+    device: Arc<dyn BlockDevice>,
+    block_size: usize,
+    /// Home blocks beyond the filesystem are corruption
+    blocks_count: u64,
+    /// Physical location of each journal block
+    map: Vec<u64>,
+    /// The journal superblock as stored in journal block 0
+    superblock: Vec<u8>,
+    first: u32,
+    maxlen: u32,
+    /// Sequence number of the next transaction
+    sequence: u32,
+    /// Log position of the oldest transaction not yet checkpointed, or 0
+    start: u32,
+    /// Log position the next transaction is written at
+    head: u32,
+}
+
+impl Journal {
+    /// Open the journal whose blocks live at `map`
+    pub(super) fn load(
+        device: Arc<dyn BlockDevice>,
+        block_size: usize,
+        blocks_count: u64,
+        map: Vec<u64>,
+    ) -> Result<Self, FsError> {
+        let first_block = *map.first().ok_or(FsError::InvalidFs)?;
+        let mut journal = Journal {
+            device,
+            block_size,
+            blocks_count,
+            map,
+            superblock: Vec::new(),
+            first: 0,
+            maxlen: 0,
+            sequence: 0,
+            start: 0,
+            head: 0,
+        };
+        let sb = journal.read_physical(first_block)?;
+
+        let blocktype = be32(&sb, 4);
+        if be32(&sb, 0) != JBD2_MAGIC_NUMBER
+            || !(blocktype == JBD2_SUPERBLOCK_V1 || blocktype == JBD2_SUPERBLOCK_V2)
+        {
+            return Err(FsError::InvalidFs);
+        }
+        let maxlen = be32(&sb, SB_MAXLEN);
+        let first = be32(&sb, SB_FIRST);
+        let start = be32(&sb, SB_START);
+        if be32(&sb, SB_BLOCKSIZE) as usize != block_size
+            || maxlen as usize > journal.map.len()
+            || first == 0
+            || first >= maxlen
+            || (start != 0 && (start < first || start >= maxlen))
+        {
+            return Err(FsError::InvalidFs);
+        }
+
+        journal.superblock = sb;
+        if blocktype == JBD2_SUPERBLOCK_V2 {
+            if journal.incompat() & !JBD2_FEATURE_INCOMPAT_SUPP != 0 {
+                return Err(FsError::NotSupported);
+            }
+            if journal.has_csum() {
+                if journal.superblock[SB_CHECKSUM_TYPE] != JBD2_CRC32C_CHKSUM {
+                    return Err(FsError::NotSupported);
+                }
+                if be32(&journal.superblock, SB_CHECKSUM) != journal.superblock_csum() {
+                    return Err(FsError::InvalidData);
+                }
+            }
+        } else {
+            // Version 1 has no feature words
+            put_be32(&mut journal.superblock, SB_FEATURE_INCOMPAT, 0);
+        }
+
+        journal.first = first;
+        journal.maxlen = maxlen;
+        journal.sequence = be32(&journal.superblock, SB_SEQUENCE);
+        journal.start = start;
+        journal.head = if start == 0 { first } else { start };
+        Ok(journal)
+    }
+
+    fn incompat(&self) -> u32 {
+        be32(&self.superblock, SB_FEATURE_INCOMPAT)
+    }
+
+    fn has_incompat(&self, feature: u32) -> bool {
+        self.incompat() & feature != 0
+    }
+
+    /// Whether log blocks carry CRC32C checksums
+    fn has_csum(&self) -> bool {
+        self.has_incompat(JBD2_FEATURE_INCOMPAT_CSUM_V2 | JBD2_FEATURE_INCOMPAT_CSUM_V3)
+    }
+
+    /// Seed for checksums of log blocks
+    fn csum_seed(&self) -> u32 {
+        crc32c(!0, &self.superblock[SB_UUID..SB_UUID + UUID_SIZE])
+    }
+
+    fn superblock_csum(&self) -> u32 {
+        let mut sb = self.superblock[..SB_SIZE].to_vec();
+        put_be32(&mut sb, SB_CHECKSUM, 0);
+        crc32c(!0, &sb)
+    }
+
+    /// Size of a descriptor tag, without the UUID that may follow it
+    fn tag_bytes(&self) -> usize {
+        if self.has_incompat(JBD2_FEATURE_INCOMPAT_CSUM_V3) {
+            return 16;
+        }
+        // The v2 checksum grew the tag by two bytes for compatibility
+        let mut size = 12;
+        if self.has_incompat(JBD2_FEATURE_INCOMPAT_CSUM_V2) {
+            size += 2;
+        }
+        if self.has_incompat(JBD2_FEATURE_INCOMPAT_64BIT) {
+            size
+        } else {
+            size - 4
+        }
+    }
+
+    /// Size of a block number in a revoke block
+    fn revoke_bytes(&self) -> usize {
+        if self.has_incompat(JBD2_FEATURE_INCOMPAT_64BIT) {
+            8
+        } else {
+            4
+        }
+    }
+
+    /// Usable end of descriptor and revoke blocks
+    fn block_end(&self) -> usize {
+        if self.has_csum() {
+            self.block_size - BLOCK_TAIL_SIZE
+        } else {
+            self.block_size
+        }
+    }
+
+    /// Log position following `pos`, wrapping at the end of the log
+    fn next(&self, pos: u32) -> u32 {
+        if pos + 1 >= self.maxlen {
+            self.first
+        } else {
+            pos + 1
+        }
+    }
+
+    fn read_physical(&self, block: u64) -> Result<Vec<u8>, FsError> {
+        let mut buffer = vec![0u8; self.block_size];
+        self.device
+            .read_blocks(
+                block * (self.block_size / self.device.block_size()) as u64,
+                &mut buffer,
+            )
+            .map_err(|_| FsError::IoError)?;
+        Ok(buffer)
+    }
+
+    fn write_physical(&self, block: u64, data: &[u8]) -> Result<(), FsError> {
+        self.device
+            .write_blocks(
+                block * (self.block_size / self.device.block_size()) as u64,
+                data,
+            )
+            .map_err(|_| FsError::IoError)?;
+        Ok(())
+    }
+
+    fn read_log(&self, pos: u32) -> Result<Vec<u8>, FsError> {
+        self.read_physical(self.map[pos as usize])
+    }
+
+    fn write_log(&self, pos: u32, data: &[u8]) -> Result<(), FsError> {
+        self.write_physical(self.map[pos as usize], data)
+    }
+
+    fn flush(&self) -> Result<(), FsError> {
+        self.device.flush().map_err(|_| FsError::IoError)
+    }
+
+    /// Write the superblock with the current log start and sequence
+    fn write_superblock(&mut self) -> Result<(), FsError> {
+        let (sequence, start) = (self.sequence, self.start);
+        put_be32(&mut self.superblock, SB_SEQUENCE, sequence);
+        put_be32(&mut self.superblock, SB_START, start);
+        if self.has_csum() {
+            let sum = self.superblock_csum();
+            put_be32(&mut self.superblock, SB_CHECKSUM, sum);
+        }
+        self.write_physical(self.map[0], &self.superblock)?;
+        self.flush()
+    }
+
+    /// Turn on incompatible features before the first transaction is written
+    ///
+    /// Version 1 superblocks are upgraded, since they cannot record any.
+    pub(super) fn set_features(&mut self, incompat: u32) -> Result<(), FsError> {
+        if self.start != 0 {
+            return Err(FsError::InvalidArgument);
+        }
+        let mut features = self.incompat() | incompat;
+        if features & JBD2_FEATURE_INCOMPAT_CSUM_V3 != 0 {
+            features &= !JBD2_FEATURE_INCOMPAT_CSUM_V2;
+        }
+        if features == self.incompat() && be32(&self.superblock, 4) == JBD2_SUPERBLOCK_V2 {
+            return Ok(());
+        }
+        put_be32(&mut self.superblock, 4, JBD2_SUPERBLOCK_V2);
+        put_be32(&mut self.superblock, SB_FEATURE_INCOMPAT, features);
+        if self.has_csum() {
+            self.superblock[SB_CHECKSUM_TYPE] = JBD2_CRC32C_CHKSUM;
+        }
+        self.write_superblock()
+    }
+
+    /// Checksum of a descriptor or revoke block, with its tail zeroed
+    fn block_tail_csum(&self, block: &[u8]) -> u32 {
+        let end = self.block_size - BLOCK_TAIL_SIZE;
+        let crc = crc32c(self.csum_seed(), &block[..end]);
+        crc32c(crc, &[0; BLOCK_TAIL_SIZE])
+    }
+
+    fn verify_block_tail(&self, block: &[u8]) -> bool {
+        !self.has_csum()
+            || be32(block, self.block_size - BLOCK_TAIL_SIZE) == self.block_tail_csum(block)
+    }
+
+    fn commit_csum(&self, block: &[u8]) -> u32 {
+        let crc = crc32c(self.csum_seed(), &block[..COMMIT_CHKSUM]);
+        let crc = crc32c(crc, &[0; 4]);
+        crc32c(crc, &block[COMMIT_CHKSUM + 4..])
+    }
+
+    /// Checksum of a logged copy of a block, as stored in its tag
+    fn data_csum(&self, sequence: u32, block: &[u8]) -> u32 {
+        let crc = crc32c(self.csum_seed(), &sequence.to_be_bytes());
+        let crc = crc32c(crc, block);
+        if self.has_incompat(JBD2_FEATURE_INCOMPAT_CSUM_V3) {
+            crc
+        } else {
+            crc & 0xFFFF
+        }
+    }
+
+    /// Start a block of the given type for the next transaction
+    fn new_block(&self, blocktype: u32) -> Vec<u8> {
+        let mut block = vec![0u8; self.block_size];
+        put_be32(&mut block, 0, JBD2_MAGIC_NUMBER);
+        put_be32(&mut block, 4, blocktype);
+        put_be32(&mut block, 8, self.sequence);
+        block
+    }
+
+    /// Whether a block of the log belongs to the transaction being scanned
+    fn is_log_block(block: &[u8], sequence: u32) -> bool {
+        be32(block, 0) == JBD2_MAGIC_NUMBER && be32(block, 8) == sequence
+    }
+
+    /// Parse the tags of a descriptor block
+    fn parse_tags(&self, block: &[u8], mut pos: u32) -> Result<Vec<Tag>, FsError> {
+        let tag_bytes = self.tag_bytes();
+        let is_64bit = self.has_incompat(JBD2_FEATURE_INCOMPAT_64BIT);
+        let csum_v3 = self.has_incompat(JBD2_FEATURE_INCOMPAT_CSUM_V3);
+
+        let mut tags = Vec::new();
+        let mut off = HEADER_SIZE;
+        while off + tag_bytes <= self.block_end() {
+            let (flags, checksum, high) = if csum_v3 {
+                (be32(block, off + 4), be32(block, off + 12), off + 8)
+            } else {
+                let word = be32(block, off + 4);
+                (word & 0xFFFF, word >> 16, off + 8)
+            };
+            let mut home = be32(block, off) as u64;
+            if is_64bit {
+                home |= (be32(block, high) as u64) << 32;
+            }
+            if home == 0 || home >= self.blocks_count {
+                return Err(FsError::InvalidData);
+            }
+
+            pos = self.next(pos);
+            tags.push(Tag {
+                home,
+                pos,
+                flags,
+                checksum,
+            });
+            off += tag_bytes;
+            if flags & JBD2_FLAG_SAME_UUID == 0 {
+                off += UUID_SIZE;
+            }
+            if flags & JBD2_FLAG_LAST_TAG != 0 {
+                break;
+            }
+        }
+        Ok(tags)
+    }
+
+    /// Parse the block numbers of a revoke block
+    fn parse_revokes(&self, block: &[u8]) -> Result<Vec<u64>, FsError> {
+        let size = self.revoke_bytes();
+        let count = be32(block, REVOKE_COUNT) as usize;
+        if count < HEADER_SIZE + 4 || count > self.block_end() {
+            return Err(FsError::InvalidData);
+        }
+
+        let mut off = HEADER_SIZE + 4;
+        let mut revoked = Vec::new();
+        while off + size <= count {
+            revoked.push(if size == 8 {
+                (be32(block, off) as u64) << 32 | be32(block, off + 4) as u64
+            } else {
+                be32(block, off) as u64
+            });
+            off += size;
+        }
+        Ok(revoked)
+    }
+
+    /// Find every committed transaction in the log and the latest
+    /// transaction that revoked each block
+    fn scan(&self) -> Result<(Vec<LoggedTransaction>, BTreeMap<u64, u32>), FsError> {
+        let mut committed = Vec::new();
+        let mut revoked = BTreeMap::new();
+
+        let mut sequence = self.sequence;
+        let mut pos = self.start;
+        let mut tags = Vec::new();
+        let mut revokes: Vec<u64> = Vec::new();
+        // Walking further than the log is long means it is corrupt
+        let mut scanned = 0;
+
+        loop {
+            let block = self.read_log(pos)?;
+            if !Self::is_log_block(&block, sequence) {
+                break;
+            }
+            match be32(&block, 4) {
+                JBD2_DESCRIPTOR_BLOCK => {
+                    if !self.verify_block_tail(&block) {
+                        break;
+                    }
+                    let found = self.parse_tags(&block, pos)?;
+                    if let Some(last) = found.last() {
+                        pos = last.pos;
+                    }
+                    scanned += found.len() as u32;
+                    tags.extend(found);
+                }
+                JBD2_REVOKE_BLOCK => {
+                    if !self.verify_block_tail(&block) {
+                        break;
+                    }
+                    revokes.extend(self.parse_revokes(&block)?);
+                }
+                JBD2_COMMIT_BLOCK => {
+                    if self.has_csum() && be32(&block, COMMIT_CHKSUM) != self.commit_csum(&block) {
+                        break;
+                    }
+                    for block in revokes.drain(..) {
+                        let latest = revoked.entry(block).or_insert(sequence);
+                        if tid_geq(sequence, *latest) {
+                            *latest = sequence;
+                        }
+                    }
+                    committed.push(LoggedTransaction {
+                        sequence,
+                        tags: core::mem::take(&mut tags),
+                    });
+                    sequence = sequence.wrapping_add(1);
+                }
+                _ => break,
+            }
+
+            pos = self.next(pos);
+            scanned += 1;
+            if scanned > self.maxlen {
+                return Err(FsError::InvalidData);
+            }
+        }
+
+        Ok((committed, revoked))
+    }
+
+    /// Replay committed transactions left behind by an unclean shutdown
+    ///
+    /// Returns the number of transactions replayed. The log is empty
+    /// afterwards.
+    pub(super) fn recover(&mut self) -> Result<usize, FsError> {
+        if self.start == 0 {
+            return Ok(0);
+        }
+
+        let (committed, revoked) = self.scan()?;
+        for transaction in &committed {
+            for tag in &transaction.tags {
+                let is_revoked = revoked
+                    .get(&tag.home)
+                    .is_some_and(|&latest| tid_geq(latest, transaction.sequence));
+                if is_revoked {
+                    continue;
+                }
+
+                let mut block = self.read_log(tag.pos)?;
+                // A copy damaged in the log is skipped rather than written
+                if self.has_csum() && self.data_csum(transaction.sequence, &block) != tag.checksum {
+                    continue;
+                }
+                if tag.flags & JBD2_FLAG_ESCAPE != 0 {
+                    put_be32(&mut block, 0, JBD2_MAGIC_NUMBER);
+                }
+                self.write_physical(tag.home, &block)?;
+            }
+        }
+        self.flush()?;
+
+        // Skip the ID of any transaction that was cut short, so its stray
+        // blocks can never look like part of a later one
+        let end = match committed.last() {
+            Some(last) => last.sequence.wrapping_add(1),
+            None => self.sequence,
+        };
+        self.sequence = end.wrapping_add(1);
+        self.start = 0;
+        self.head = self.first;
+        self.write_superblock()?;
+        Ok(committed.len())
+    }
+
+    /// Log blocks needed for a transaction
+    fn transaction_size(&self, blocks: usize, revokes: usize) -> u32 {
+        let per_revoke = (self.block_end() - HEADER_SIZE - 4) / self.revoke_bytes();
+        // The first tag of each descriptor carries the UUID
+        let per_descriptor =
+            (self.block_end() - HEADER_SIZE - UUID_SIZE - self.tag_bytes()) / self.tag_bytes() + 1;
+        (revokes.div_ceil(per_revoke) + blocks.div_ceil(per_descriptor) + blocks + 1) as u32
+    }
+
+    /// Whether a transaction fits in the log without wrapping
+    pub(super) fn has_room(&self, blocks: usize, revokes: usize) -> bool {
+        self.head + self.transaction_size(blocks, revokes) <= self.maxlen
+    }
+
+    /// Whether the log is past the point where it should be checkpointed
+    pub(super) fn wants_checkpoint(&self) -> bool {
+        (self.maxlen - self.head) * 4 < self.maxlen - self.first
+    }
+
+    /// Whether the log holds transactions not yet checkpointed
+    pub(super) fn is_empty(&self) -> bool {
+        self.start == 0
+    }
+
+    /// Write a transaction to the log and commit it
+    ///
+    /// `blocks` are the new contents of metadata blocks by home location;
+    /// `revoked` are blocks freed since they were last logged. The caller
+    /// must have made room with a checkpoint if [`has_room`] said so.
+    ///
+    /// [`has_room`]: Journal::has_room
+    pub(super) fn commit(
+        &mut self,
+        blocks: &[(u64, &[u8])],
+        revoked: &[u64],
+        time: u64,
+    ) -> Result<(), FsError> {
+        if blocks.is_empty() && revoked.is_empty() {
+            return Ok(());
+        }
+        if !self.has_room(blocks.len(), revoked.len()) {
+            return Err(FsError::NoSpaceLeft);
+        }
+
+        // Point the superblock at the log before the first transaction
+        if self.start == 0 {
+            self.start = self.first;
+            self.head = self.first;
+            self.write_superblock()?;
+        }
+
+        let mut pos = self.head;
+        let end = self.block_end();
+
+        let revoke_bytes = self.revoke_bytes();
+        for chunk in revoked.chunks((end - HEADER_SIZE - 4) / revoke_bytes) {
+            let mut block = self.new_block(JBD2_REVOKE_BLOCK);
+            let mut off = HEADER_SIZE + 4;
+            for &revoked in chunk {
+                if revoke_bytes == 8 {
+                    put_be32(&mut block, off, (revoked >> 32) as u32);
+                    put_be32(&mut block, off + 4, revoked as u32);
+                } else {
+                    put_be32(&mut block, off, revoked as u32);
+                }
+                off += revoke_bytes;
+            }
+            put_be32(&mut block, REVOKE_COUNT, off as u32);
+            self.seal_block_tail(&mut block);
+            self.write_log(pos, &block)?;
+            pos += 1;
+        }
+
+        let tag_bytes = self.tag_bytes();
+        let csum_v3 = self.has_incompat(JBD2_FEATURE_INCOMPAT_CSUM_V3);
+        let mut rest = blocks;
+        while !rest.is_empty() {
+            let descriptor_pos = pos;
+            let mut descriptor = self.new_block(JBD2_DESCRIPTOR_BLOCK);
+            let mut off = HEADER_SIZE;
+            let mut last_tag = off;
+            while let Some(&(home, data)) = rest.first() {
+                let uuid = if off == HEADER_SIZE { UUID_SIZE } else { 0 };
+                if off + tag_bytes + uuid > end {
+                    break;
+                }
+
+                let mut copy = data.to_vec();
+                let mut flags = 0;
+                if off != HEADER_SIZE {
+                    flags |= JBD2_FLAG_SAME_UUID;
+                }
+                if be32(&copy, 0) == JBD2_MAGIC_NUMBER {
+                    put_be32(&mut copy, 0, 0);
+                    flags |= JBD2_FLAG_ESCAPE;
+                }
+                let sum = self.data_csum(self.sequence, &copy);
+
+                put_be32(&mut descriptor, off, home as u32);
+                if csum_v3 {
+                    put_be32(&mut descriptor, off + 4, flags);
+                    put_be32(&mut descriptor, off + 12, sum);
+                } else {
+                    put_be32(&mut descriptor, off + 4, sum << 16 | flags);
+                }
+                if self.has_incompat(JBD2_FEATURE_INCOMPAT_64BIT) {
+                    put_be32(&mut descriptor, off + 8, (home >> 32) as u32);
+                }
+                if uuid != 0 {
+                    descriptor[off + tag_bytes..off + tag_bytes + UUID_SIZE]
+                        .copy_from_slice(&self.superblock[SB_UUID..SB_UUID + UUID_SIZE]);
+                }
+
+                pos += 1;
+                self.write_log(pos, &copy)?;
+                last_tag = off;
+                off += tag_bytes + uuid;
+                rest = &rest[1..];
+            }
+
+            // Flags sit in the low half of the second word either way
+            let flags = be32(&descriptor, last_tag + 4) | JBD2_FLAG_LAST_TAG;
+            put_be32(&mut descriptor, last_tag + 4, flags);
+            self.seal_block_tail(&mut descriptor);
+            self.write_log(descriptor_pos, &descriptor)?;
+            pos += 1;
+        }
+
+        // Everything before the commit block must be durable first
+        self.flush()?;
+        let mut commit = self.new_block(JBD2_COMMIT_BLOCK);
+        commit[COMMIT_SEC..COMMIT_SEC + 8].copy_from_slice(&time.to_be_bytes());
+        if self.has_csum() {
+            let sum = self.commit_csum(&commit);
+            put_be32(&mut commit, COMMIT_CHKSUM, sum);
+        }
+        self.write_log(pos, &commit)?;
+        self.flush()?;
+
+        self.head = pos + 1;
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    fn seal_block_tail(&self, block: &mut [u8]) {
+        if self.has_csum() {
+            let sum = self.block_tail_csum(block);
+            put_be32(block, self.block_size - BLOCK_TAIL_SIZE, sum);
+        }
+    }
+
+    /// Mark the log empty once every committed block is home
+    pub(super) fn checkpointed(&mut self) -> Result<(), FsError> {
+        if self.start == 0 {
+            return Ok(());
+        }
+        self.start = 0;
+        self.head = self.first;
+        self.write_superblock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spin::Mutex;
+
+    const BLOCK: usize = 1024;
+    /// Journal blocks follow 32 home blocks
+    const LOG_START: u64 = 32;
+    const LOG_BLOCKS: u32 = 32;
+
+    struct MemDisk(Mutex<Vec<u8>>);
+
+    impl BlockDevice for MemDisk {
+        fn read_blocks(&self, block_offset: u64, buffer: &mut [u8]) -> Result<usize, ()> {
+            let offset = block_offset as usize * 512;
+            buffer.copy_from_slice(&self.0.lock()[offset..offset + buffer.len()]);
+            Ok(buffer.len())
+        }
+
+        fn write_blocks(&self, block_offset: u64, buffer: &[u8]) -> Result<usize, ()> {
+            let offset = block_offset as usize * 512;
+            self.0.lock()[offset..offset + buffer.len()].copy_from_slice(buffer);
+            Ok(buffer.len())
+        }
+
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn flush(&self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    impl MemDisk {
+        fn block(&self, block: u64) -> Vec<u8> {
+            let offset = block as usize * BLOCK;
+            self.0.lock()[offset..offset + BLOCK].to_vec()
+        }
+    }
+
+    /// A device holding an empty version 2 journal
+    fn new_device() -> Arc<MemDisk> {
+        let mut image = vec![0u8; (LOG_START as usize + LOG_BLOCKS as usize) * BLOCK];
+        let sb = &mut image[LOG_START as usize * BLOCK..];
+        put_be32(sb, 0, JBD2_MAGIC_NUMBER);
+        put_be32(sb, 4, JBD2_SUPERBLOCK_V2);
+        put_be32(sb, SB_BLOCKSIZE, BLOCK as u32);
+        put_be32(sb, SB_MAXLEN, LOG_BLOCKS);
+        put_be32(sb, SB_FIRST, 1);
+        put_be32(sb, SB_SEQUENCE, 1);
+        sb[SB_UUID..SB_UUID + UUID_SIZE].copy_from_slice(b"rinux-jbd2-test!");
+        Arc::new(MemDisk(Mutex::new(image)))
+    }
+
+    fn open(device: &Arc<MemDisk>) -> Result<Journal, FsError> {
+        let map = (LOG_START..LOG_START + LOG_BLOCKS as u64).collect();
+        Journal::load(device.clone(), BLOCK, LOG_START, map)
+    }
+
+    #[test]
+    fn test_recover_honours_revokes_and_commits() {
+        let device = new_device();
+        let mut journal = open(&device).unwrap();
+        journal
+            .set_features(
+                JBD2_FEATURE_INCOMPAT_REVOKE
+                    | JBD2_FEATURE_INCOMPAT_64BIT
+                    | JBD2_FEATURE_INCOMPAT_CSUM_V3,
+            )
+            .unwrap();
+
+        let filled = |byte| vec![byte; BLOCK];
+        // A block starting with the magic number is logged escaped
+        let mut magic = filled(3);
+        put_be32(&mut magic, 0, JBD2_MAGIC_NUMBER);
+
+        journal
+            .commit(&[(1, &filled(1)), (2, &filled(2)), (3, &magic)], &[], 0)
+            .unwrap();
+        // Revoked blocks stay as they are, unless logged again later
+        journal.commit(&[(4, &filled(4))], &[1, 2], 0).unwrap();
+        journal.commit(&[(1, &filled(11))], &[], 0).unwrap();
+
+        // A transaction whose commit block never made it is ignored
+        let torn = journal.head;
+        journal.commit(&[(5, &filled(5))], &[], 0).unwrap();
+        let commit_block = LOG_START + torn as u64 + 2;
+        assert_eq!(be32(&device.block(commit_block), 4), JBD2_COMMIT_BLOCK);
+        device
+            .write_blocks(commit_block * 2, &[0u8; BLOCK])
+            .unwrap();
+
+        let mut journal = open(&device).unwrap();
+        assert_eq!(journal.recover().unwrap(), 3);
+        assert_eq!(device.block(1), filled(11));
+        assert_eq!(device.block(2), filled(0));
+        assert_eq!(device.block(3), magic);
+        assert_eq!(device.block(4), filled(4));
+        assert_eq!(device.block(5), filled(0));
+
+        // The log is empty afterwards, and the torn ID is never reused
+        let journal = open(&device).unwrap();
+        assert!(journal.is_empty());
+        assert_eq!(journal.sequence, 5);
+    }
+
+    #[test]
+    fn test_damaged_log_is_rejected() {
+        let device = new_device();
+        let mut journal = open(&device).unwrap();
+        journal
+            .set_features(JBD2_FEATURE_INCOMPAT_REVOKE | JBD2_FEATURE_INCOMPAT_CSUM_V3)
+            .unwrap();
+        journal.commit(&[(1, &[9u8; BLOCK])], &[], 0).unwrap();
+
+        // A logged copy that fails its checksum is not written home
+        let copy = LOG_START + journal.first as u64 + 1;
+        device.write_blocks(copy * 2, &[8u8; BLOCK]).unwrap();
+        let mut journal = open(&device).unwrap();
+        assert_eq!(journal.recover().unwrap(), 1);
+        assert_eq!(device.block(1), vec![0u8; BLOCK]);
+
+        // Nor is a journal superblock that fails its own
+        let mut sb = device.block(LOG_START);
+        sb[SB_SEQUENCE] ^= 1;
+        device.write_blocks(LOG_START * 2, &sb).unwrap();
+        assert_eq!(open(&device).err(), Some(FsError::InvalidData));
+    }
+}
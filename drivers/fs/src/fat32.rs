@@ -2,98 +2,112 @@
 //!
 //! Read/write support for the FAT32 (File Allocation Table) filesystem.
 //! FAT32 is commonly used on USB drives, SD cards, and for interoperability.
+//!
+//! A filesystem is mounted from a [`BlockDevice`], the same way as ext2.
+//! File data is read by following cluster chains through the FAT;
+//! directory parsing and writes are not implemented yet.
 
+use crate::ext2::BlockDevice;
+use crate::vfs::{DirEntry, FileAttr, FileMode, FileType, Filesystem, StatFs, VNode};
 use crate::{FsError, FsType};
-use crate::vfs::{VNode, Filesystem, FileAttr, FileType, FileMode, DirEntry, StatFs};
-use alloc::sync::Arc;
-use alloc::vec::Vec;
 use alloc::string::String;
-use spin::RwLock;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 
 /// FAT32 Boot Sector (BPB - BIOS Parameter Block)
+#[allow(dead_code)]
 #[repr(C, packed)]
 struct Fat32BootSector {
-    jmp_boot: [u8; 3],         // Jump instruction
-    oem_name: [u8; 8],         // OEM name
-    bytes_per_sector: u16,     // Bytes per logical sector (usually 512)
-    sectors_per_cluster: u8,   // Sectors per cluster
-    reserved_sectors: u16,     // Reserved sectors (including boot sector)
-    num_fats: u8,              // Number of FAT copies (usually 2)
-    root_entry_count: u16,     // Root directory entries (0 for FAT32)
-    total_sectors_16: u16,     // Total sectors (0 if > 65535)
-    media: u8,                 // Media descriptor
-    fat_size_16: u16,          // FAT size in sectors (0 for FAT32)
-    sectors_per_track: u16,    // Sectors per track
-    num_heads: u16,            // Number of heads
-    hidden_sectors: u32,       // Hidden sectors
-    total_sectors_32: u32,     // Total sectors (if total_sectors_16 is 0)
-    
+    jmp_boot: [u8; 3],       // Jump instruction
+    oem_name: [u8; 8],       // OEM name
+    bytes_per_sector: u16,   // Bytes per logical sector (usually 512)
+    sectors_per_cluster: u8, // Sectors per cluster
+    reserved_sectors: u16,   // Reserved sectors (including boot sector)
+    num_fats: u8,            // Number of FAT copies (usually 2)
+    root_entry_count: u16,   // Root directory entries (0 for FAT32)
+    total_sectors_16: u16,   // Total sectors (0 if > 65535)
+    media: u8,               // Media descriptor
+    fat_size_16: u16,        // FAT size in sectors (0 for FAT32)
+    sectors_per_track: u16,  // Sectors per track
+    num_heads: u16,          // Number of heads
+    hidden_sectors: u32,     // Hidden sectors
+    total_sectors_32: u32,   // Total sectors (if total_sectors_16 is 0)
+
     // FAT32-specific fields
-    fat_size_32: u32,          // FAT size in sectors
-    ext_flags: u16,            // Extended flags
-    fs_version: u16,           // Filesystem version
-    root_cluster: u32,         // Root directory cluster (usually 2)
-    fs_info: u16,              // FSInfo sector
-    backup_boot_sector: u16,   // Backup boot sector location
-    reserved: [u8; 12],        // Reserved
-    drive_number: u8,          // Drive number
-    reserved1: u8,             // Reserved
-    boot_signature: u8,        // Extended boot signature (0x29)
-    volume_id: u32,            // Volume serial number
-    volume_label: [u8; 11],    // Volume label
-    fs_type: [u8; 8],          // Filesystem type ("FAT32   ")
+    fat_size_32: u32,        // FAT size in sectors
+    ext_flags: u16,          // Extended flags
+    fs_version: u16,         // Filesystem version
+    root_cluster: u32,       // Root directory cluster (usually 2)
+    fs_info: u16,            // FSInfo sector
+    backup_boot_sector: u16, // Backup boot sector location
+    reserved: [u8; 12],      // Reserved
+    drive_number: u8,        // Drive number
+    reserved1: u8,           // Reserved
+    boot_signature: u8,      // Extended boot signature (0x29)
+    volume_id: u32,          // Volume serial number
+    volume_label: [u8; 11],  // Volume label
+    fs_type: [u8; 8],        // Filesystem type ("FAT32   ")
 }
 
 /// FAT32 FSInfo Sector
+#[allow(dead_code)]
 #[repr(C, packed)]
 struct Fat32FSInfo {
-    lead_sig: u32,             // Lead signature (0x41615252)
-    reserved1: [u8; 480],      // Reserved
-    struct_sig: u32,           // Structure signature (0x61417272)
-    free_count: u32,           // Last known free cluster count
-    next_free: u32,            // Next free cluster hint
-    reserved2: [u8; 12],       // Reserved
-    trail_sig: u32,            // Trail signature (0xAA550000)
+    lead_sig: u32,        // Lead signature (0x41615252)
+    reserved1: [u8; 480], // Reserved
+    struct_sig: u32,      // Structure signature (0x61417272)
+    free_count: u32,      // Last known free cluster count
+    next_free: u32,       // Next free cluster hint
+    reserved2: [u8; 12],  // Reserved
+    trail_sig: u32,       // Trail signature (0xAA550000)
 }
 
 /// FAT32 Directory Entry
+#[allow(dead_code)]
 #[repr(C, packed)]
 struct Fat32DirEntry {
-    name: [u8; 11],            // Short filename (8.3 format)
-    attr: u8,                  // File attributes
-    nt_reserved: u8,           // Reserved  for Windows NT
-    create_time_tenth: u8,     // Creation time (tenths of second)
-    create_time: u16,          // Creation time
-    create_date: u16,          // Creation date
-    last_access_date: u16,     // Last access date
-    first_cluster_hi: u16,     // High word of first cluster
-    write_time: u16,           // Last write time
-    write_date: u16,           // Last write date
-    first_cluster_lo: u16,     // Low word of first cluster
-    file_size: u32,            // File size in bytes
+    name: [u8; 11],        // Short filename (8.3 format)
+    attr: u8,              // File attributes
+    nt_reserved: u8,       // Reserved  for Windows NT
+    create_time_tenth: u8, // Creation time (tenths of second)
+    create_time: u16,      // Creation time
+    create_date: u16,      // Creation date
+    last_access_date: u16, // Last access date
+    first_cluster_hi: u16, // High word of first cluster
+    write_time: u16,       // Last write time
+    write_date: u16,       // Last write date
+    first_cluster_lo: u16, // Low word of first cluster
+    file_size: u32,        // File size in bytes
 }
 
 /// FAT32 Long File Name Entry
+#[allow(dead_code)]
 #[repr(C, packed)]
 struct Fat32LFNEntry {
-    order: u8,                 // Order/sequence number
-    name1: [u16; 5],           // First 5 characters
-    attr: u8,                  // Attributes (always 0x0F for LFN)
-    lfn_type: u8,              // Type (always 0)
-    checksum: u8,              // Checksum of short name
-    name2: [u16; 6],           // Next 6 characters
-    first_cluster_lo: u16,     // Always 0 for LFN
-    name3: [u16; 2],           // Final 2 characters
+    order: u8,             // Order/sequence number
+    name1: [u16; 5],       // First 5 characters
+    attr: u8,              // Attributes (always 0x0F for LFN)
+    lfn_type: u8,          // Type (always 0)
+    checksum: u8,          // Checksum of short name
+    name2: [u16; 6],       // Next 6 characters
+    first_cluster_lo: u16, // Always 0 for LFN
+    name3: [u16; 2],       // Final 2 characters
 }
 
 /// File attributes
-const ATTR_READ_ONLY: u8 = 0x01;
-const ATTR_HIDDEN: u8 = 0x02;
-const ATTR_SYSTEM: u8 = 0x04;
-const ATTR_VOLUME_ID: u8 = 0x08;
-const ATTR_DIRECTORY: u8 = 0x10;
-const ATTR_ARCHIVE: u8 = 0x20;
-const ATTR_LONG_NAME: u8 = 0x0F;
+#[allow(dead_code)]
+impl Fat32DirEntry {
+    const ATTR_READ_ONLY: u8 = 0x01;
+    const ATTR_HIDDEN: u8 = 0x02;
+    const ATTR_SYSTEM: u8 = 0x04;
+    const ATTR_VOLUME_ID: u8 = 0x08;
+    const ATTR_DIRECTORY: u8 = 0x10;
+    const ATTR_ARCHIVE: u8 = 0x20;
+    const ATTR_LONG_NAME: u8 = 0x0F;
+}
+
+/// Boot sector signature at offset 510
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
 
 /// End of cluster chain marker
 const FAT32_EOC: u32 = 0x0FFFFFF8;
@@ -124,24 +138,13 @@ impl Fat32VNode {
 
     /// Get the next cluster in the chain
     fn get_next_cluster(&self, cluster: u32) -> Result<u32, FsError> {
-        // Read from FAT
-        // FAT entry is 4 bytes per cluster
-        let fat_offset = cluster * 4;
-        let fat_sector = self.fs.reserved_sectors as u32 + fat_offset / self.fs.bytes_per_sector as u32;
-        let sector_offset = (fat_offset % self.fs.bytes_per_sector as u32) as usize;
-
-        // Read FAT sector (stub)
-        // let sector_data = self.fs.read_sector(fat_sector)?;
-        // let next_cluster = u32::from_le_bytes([
-        //     sector_data[sector_offset],
-        //     sector_data[sector_offset + 1],
-        //     sector_data[sector_offset + 2],
-        //     sector_data[sector_offset + 3],
-        // ]) & 0x0FFFFFFF; // Mask off high 4 bits
-
-        // For now, return end of chain
-        let _ = (fat_sector, sector_offset);
-        Ok(FAT32_EOC)
+        // FAT entry is 4 bytes per cluster, in the first FAT copy
+        let fat_offset = cluster as u64 * 4;
+        let offset = self.fs.reserved_sectors as u64 * self.fs.bytes_per_sector as u64 + fat_offset;
+        let entry = self.fs.read_bytes(offset, 4)?;
+
+        // Mask off the reserved high 4 bits
+        Ok(u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) & 0x0FFFFFFF)
     }
 
     /// Get the cluster at a specific offset in the file
@@ -151,19 +154,23 @@ impl Fat32VNode {
 
         let mut current_cluster = self.first_cluster;
         for _ in 0..cluster_index {
-            current_cluster = self.get_next_cluster(current_cluster)?;
-            if current_cluster >= FAT32_EOC {
+            if !is_data_cluster(current_cluster) {
                 return Err(FsError::IoError);
             }
+            current_cluster = self.get_next_cluster(current_cluster)?;
         }
 
+        // A chain may not end early or run through free or bad clusters
+        if !is_data_cluster(current_cluster) {
+            return Err(FsError::IoError);
+        }
         Ok(current_cluster)
     }
 
     /// Convert cluster number to logical sector
     fn cluster_to_sector(&self, cluster: u32) -> u32 {
-        let first_data_sector = self.fs.reserved_sectors as u32 
-            + (self.fs.num_fats as u32 * self.fs.fat_size_32);
+        let first_data_sector =
+            self.fs.reserved_sectors as u32 + (self.fs.num_fats as u32 * self.fs.fat_size_32);
         ((cluster - 2) * self.fs.sectors_per_cluster as u32) + first_data_sector
     }
 }
@@ -188,9 +195,12 @@ impl VNode for Fat32VNode {
             let cluster_offset = (current_offset % cluster_size) as usize;
 
             // Read from cluster
-            // TODO: Integrate with block device
-            let bytes_in_cluster = ((cluster_size - cluster_offset as u64) as usize).min(max_read - bytes_read);
-            buffer[bytes_read..bytes_read + bytes_in_cluster].fill(0); // Stub
+            let bytes_in_cluster =
+                ((cluster_size - cluster_offset as u64) as usize).min(max_read - bytes_read);
+            let start = self.cluster_to_sector(cluster) as u64 * self.fs.bytes_per_sector as u64
+                + cluster_offset as u64;
+            let data = self.fs.read_bytes(start, bytes_in_cluster)?;
+            buffer[bytes_read..bytes_read + bytes_in_cluster].copy_from_slice(&data);
             bytes_read += bytes_in_cluster;
         }
 
@@ -209,14 +219,18 @@ impl VNode for Fat32VNode {
 
     fn getattr(&self) -> Result<FileAttr, FsError> {
         Ok(FileAttr {
-            file_type: if self.is_dir { FileType::Directory } else { FileType::Regular },
+            file_type: if self.is_dir {
+                FileType::Directory
+            } else {
+                FileType::Regular
+            },
             mode: FileMode::new(0o755),
             size: self.size,
             nlink: 1,
             uid: 0,
             gid: 0,
             ino: self.ino,
-            blocks: (self.size + 511) / 512,
+            blocks: self.size.div_ceil(512),
             atime: 0,
             mtime: 0,
             ctime: 0,
@@ -232,11 +246,11 @@ impl VNode for Fat32VNode {
             return Err(FsError::NotADirectory);
         }
 
-        let mut entries = Vec::new();
-        
+        let entries = Vec::new();
+
         // Read directory entries from clusters
         // TODO: Implement directory reading with LFN support
-        
+
         Ok(entries)
     }
 
@@ -265,7 +279,12 @@ impl VNode for Fat32VNode {
         Err(FsError::NotSupported)
     }
 
-    fn rename(&self, _old_name: &str, _new_parent: Arc<dyn VNode>, _new_name: &str) -> Result<(), FsError> {
+    fn rename(
+        &self,
+        _old_name: &str,
+        _new_parent: Arc<dyn VNode>,
+        _new_name: &str,
+    ) -> Result<(), FsError> {
         Err(FsError::NotSupported)
     }
 
@@ -286,47 +305,89 @@ impl VNode for Fat32VNode {
     }
 }
 
+/// Check if a FAT entry names a cluster holding data
+fn is_data_cluster(cluster: u32) -> bool {
+    cluster >= 2
+        && cluster != FAT32_FREE_CLUSTER
+        && cluster != FAT32_BAD_CLUSTER
+        && cluster < FAT32_EOC
+}
+
 /// FAT32 Filesystem
 pub struct Fat32Filesystem {
+    device: Arc<dyn BlockDevice>,
     bytes_per_sector: u16,
     sectors_per_cluster: u8,
     reserved_sectors: u16,
     num_fats: u8,
     fat_size_32: u32,
     root_cluster: u32,
+    /// Handle to ourselves for the VNodes we hand out
+    this: Weak<Fat32Filesystem>,
 }
 
 impl Fat32Filesystem {
     /// Mount a FAT32 filesystem from a block device
-    pub fn mount() -> Result<Arc<Self>, FsError> {
-        // Read boot sector
-        // Validate FAT32 signature
-        // Read FSInfo sector
-        
-        Ok(Arc::new(Fat32Filesystem {
-            bytes_per_sector: 512,
-            sectors_per_cluster: 8,
-            reserved_sectors: 32,
-            num_fats: 2,
-            fat_size_32: 1024,
-            root_cluster: 2,
+    pub fn mount(device: Arc<dyn BlockDevice>) -> Result<Arc<Self>, FsError> {
+        // Read and validate the boot sector
+        let sector = read_device_bytes(&*device, 0, 512)?;
+        if sector[510..512] != BOOT_SIGNATURE {
+            return Err(FsError::InvalidFs);
+        }
+        let boot = unsafe { core::ptr::read_unaligned(sector.as_ptr() as *const Fat32BootSector) };
+
+        let bytes_per_sector = boot.bytes_per_sector;
+        let sectors_per_cluster = boot.sectors_per_cluster;
+        let fat_size_16 = boot.fat_size_16;
+        let fat_size_32 = boot.fat_size_32;
+        let root_cluster = boot.root_cluster;
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || boot.reserved_sectors == 0
+            || boot.num_fats == 0
+            || fat_size_16 != 0
+            || fat_size_32 == 0
+            || !is_data_cluster(root_cluster)
+        {
+            return Err(FsError::InvalidFs);
+        }
+
+        Ok(Arc::new_cyclic(|this| Fat32Filesystem {
+            device,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors: boot.reserved_sectors,
+            num_fats: boot.num_fats,
+            fat_size_32,
+            root_cluster,
+            this: this.clone(),
         }))
     }
 
-    /// Allocate a new cluster
-    fn allocate_cluster(&self) -> Result<u32, FsError> {
-        // Read FSInfo to get hint
-        // Search FAT for free cluster
-        // Mark cluster as used
-        Err(FsError::NoSpaceLeft)
+    /// Read `len` bytes starting at byte `offset` of the volume
+    fn read_bytes(&self, offset: u64, len: usize) -> Result<Vec<u8>, FsError> {
+        read_device_bytes(&*self.device, offset, len)
     }
+}
 
-    /// Free a cluster chain
-    fn free_cluster_chain(&self, _start_cluster: u32) -> Result<(), FsError> {
-        // Follow chain and mark all clusters as free
-        // Update FSInfo
-        Ok(())
-    }
+/// Read a byte range from a device, whatever its block size
+fn read_device_bytes(
+    device: &dyn BlockDevice,
+    offset: u64,
+    len: usize,
+) -> Result<Vec<u8>, FsError> {
+    let block_size = device.block_size() as u64;
+    let first_block = offset / block_size;
+    let end = offset + len as u64;
+    let blocks = end.div_ceil(block_size) - first_block;
+
+    let mut buffer = vec![0u8; (blocks * block_size) as usize];
+    device
+        .read_blocks(first_block, &mut buffer)
+        .map_err(|_| FsError::IoError)?;
+
+    let start = (offset - first_block * block_size) as usize;
+    Ok(buffer[start..start + len].to_vec())
 }
 
 impl Filesystem for Fat32Filesystem {
@@ -335,12 +396,11 @@ impl Filesystem for Fat32Filesystem {
     }
 
     fn root(&self) -> Arc<dyn VNode> {
-        Arc::new(Fat32VNode::new(
-            Arc::new(Self::mount().unwrap()),
-            self.root_cluster,
-            0,
-            true,
-        ))
+        let fs = self
+            .this
+            .upgrade()
+            .expect("fat32 is always reference counted");
+        Arc::new(Fat32VNode::new(fs, self.root_cluster, 0, true))
     }
 
     fn sync(&self) -> Result<(), FsError> {
@@ -351,11 +411,11 @@ impl Filesystem for Fat32Filesystem {
 
     fn statfs(&self) -> Result<StatFs, FsError> {
         let cluster_size = (self.sectors_per_cluster as u64) * (self.bytes_per_sector as u64);
-        
+
         Ok(StatFs {
             fs_type: 0x4d44, // FAT magic
             block_size: cluster_size,
-            blocks: 0, // Would calculate from total sectors
+            blocks: 0,      // Would calculate from total sectors
             blocks_free: 0, // Would read from FSInfo
             blocks_available: 0,
             files: 0, // FAT doesn't track inode count
@@ -389,25 +449,99 @@ mod tests {
 
     #[test]
     fn test_fat32_attributes() {
-        assert_eq!(ATTR_LONG_NAME, 0x0F);
-        assert_eq!(ATTR_DIRECTORY, 0x10);
-        assert_eq!(ATTR_READ_ONLY, 0x01);
+        assert_eq!(Fat32DirEntry::ATTR_LONG_NAME, 0x0F);
+        assert_eq!(Fat32DirEntry::ATTR_DIRECTORY, 0x10);
+        assert_eq!(Fat32DirEntry::ATTR_READ_ONLY, 0x01);
+    }
+
+    /// In-memory block device with 512-byte sectors
+    struct RamDisk(Vec<u8>);
+
+    impl BlockDevice for RamDisk {
+        fn read_blocks(&self, block_offset: u64, buffer: &mut [u8]) -> Result<usize, ()> {
+            let start = block_offset as usize * 512;
+            let src = self.0.get(start..start + buffer.len()).ok_or(())?;
+            buffer.copy_from_slice(src);
+            Ok(buffer.len())
+        }
+
+        fn write_blocks(&self, _block_offset: u64, _buffer: &[u8]) -> Result<usize, ()> {
+            Err(())
+        }
+
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn flush(&self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    /// Volume with one sector per cluster: boot sector, one FAT sector,
+    /// then clusters 2 to 7
+    fn image() -> Vec<u8> {
+        let mut image = vec![0u8; 8 * 512];
+        image[11..13].copy_from_slice(&512u16.to_le_bytes());
+        image[13] = 1; // sectors per cluster
+        image[14..16].copy_from_slice(&1u16.to_le_bytes()); // reserved sectors
+        image[16] = 1; // FATs
+        image[36..40].copy_from_slice(&1u32.to_le_bytes()); // FAT size
+        image[44..48].copy_from_slice(&2u32.to_le_bytes()); // root cluster
+        image[510..512].copy_from_slice(&BOOT_SIGNATURE);
+        image
+    }
+
+    /// Set the FAT entry of a cluster
+    fn set_fat(image: &mut [u8], cluster: u32, next: u32) {
+        let offset = 512 + cluster as usize * 4;
+        image[offset..offset + 4].copy_from_slice(&next.to_le_bytes());
+    }
+
+    #[test]
+    fn test_read_follows_cluster_chain() {
+        // File in clusters 3 then 5
+        let mut image = image();
+        set_fat(&mut image, 3, 5);
+        set_fat(&mut image, 5, 0x0FFFFFFF);
+        image[3 * 512..4 * 512].fill(b'a');
+        image[5 * 512..6 * 512].fill(b'b');
+
+        let fs = Fat32Filesystem::mount(Arc::new(RamDisk(image.clone()))).unwrap();
+        let file = Fat32VNode::new(fs.clone(), 3, 700, false);
+        let mut buffer = [0u8; 20];
+        assert_eq!(file.read(500, &mut buffer), Ok(20));
+        assert_eq!(&buffer[..12], &[b'a'; 12]);
+        assert_eq!(&buffer[12..], &[b'b'; 8]);
+        assert_eq!(file.read(690, &mut buffer), Ok(10));
+
+        // A chain ending before the file size is an error
+        let short = Fat32VNode::new(fs, 5, 700, false);
+        assert_eq!(short.read(600, &mut buffer), Err(FsError::IoError));
+
+        image[510] = 0;
+        assert!(matches!(
+            Fat32Filesystem::mount(Arc::new(RamDisk(image))),
+            Err(FsError::InvalidFs)
+        ));
     }
 
     #[test]
     fn test_cluster_to_sector() {
         let fs = Fat32Filesystem {
+            device: Arc::new(RamDisk(Vec::new())),
             bytes_per_sector: 512,
             sectors_per_cluster: 8,
             reserved_sectors: 32,
             num_fats: 2,
             fat_size_32: 1024,
             root_cluster: 2,
+            this: Weak::new(),
         };
 
         let vnode = Fat32VNode::new(Arc::new(fs), 2, 0, true);
         let sector = vnode.cluster_to_sector(2);
-        
+
         // First data sector = reserved_sectors + (num_fats * fat_size_32)
         // = 32 + (2 * 1024) = 2080
         // cluster 2 is at first data sector (cluster 0 and 1 are reserved)
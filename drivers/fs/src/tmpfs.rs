@@ -2,12 +2,12 @@
 //!
 //! A simple RAM-based filesystem
 
+use crate::vfs::{DirEntry, FileAttr, FileMode, FileType, Filesystem, StatFs, VNode};
 use crate::{FsError, FsType};
-use crate::vfs::{VNode, Filesystem, FileAttr, FileType, FileMode, DirEntry, StatFs};
-use alloc::sync::Arc;
-use alloc::vec::Vec;
-use alloc::string::String;
 use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 use spin::RwLock;
 
 /// TmpFS inode
@@ -76,7 +76,9 @@ impl TmpFsVNode {
     }
 
     fn get_inode(&self) -> Result<alloc::sync::Arc<RwLock<TmpFsInode>>, FsError> {
-        self.fs.inodes.read()
+        self.fs
+            .inodes
+            .read()
             .get(&self.ino)
             .cloned()
             .ok_or(FsError::NotFound)
@@ -138,7 +140,7 @@ impl VNode for TmpFsVNode {
             uid: inode.uid,
             gid: inode.gid,
             ino: inode.ino,
-            blocks: (inode.size + 511) / 512,
+            blocks: inode.size.div_ceil(512),
             atime: inode.atime,
             mtime: inode.mtime,
             ctime: inode.ctime,
@@ -232,7 +234,11 @@ impl VNode for TmpFsVNode {
 
         // Create new inode
         let new_ino = self.fs.allocate_inode();
-        let new_inode = Arc::new(RwLock::new(TmpFsInode::new(new_ino, FileType::Regular, mode)));
+        let new_inode = Arc::new(RwLock::new(TmpFsInode::new(
+            new_ino,
+            FileType::Regular,
+            mode,
+        )));
 
         // Add to parent
         inode.entries.insert(String::from(name), new_ino);
@@ -335,7 +341,12 @@ impl VNode for TmpFsVNode {
         Ok(())
     }
 
-    fn rename(&self, _old_name: &str, _new_parent: Arc<dyn VNode>, _new_name: &str) -> Result<(), FsError> {
+    fn rename(
+        &self,
+        _old_name: &str,
+        _new_parent: Arc<dyn VNode>,
+        _new_name: &str,
+    ) -> Result<(), FsError> {
         // TODO: Implement rename
         Err(FsError::NotFound)
     }
@@ -406,14 +417,17 @@ impl VNode for TmpFsVNode {
 pub struct TmpFsFilesystem {
     inodes: RwLock<BTreeMap<u64, Arc<RwLock<TmpFsInode>>>>,
     next_ino: RwLock<u64>,
+    /// Handle to ourselves for the VNodes we hand out
+    this: Weak<TmpFsFilesystem>,
 }
 
 impl TmpFsFilesystem {
     /// Create a new TmpFS
     pub fn new() -> Arc<Self> {
-        let fs = Arc::new(TmpFsFilesystem {
+        let fs = Arc::new_cyclic(|this| TmpFsFilesystem {
             inodes: RwLock::new(BTreeMap::new()),
             next_ino: RwLock::new(1),
+            this: this.clone(),
         });
 
         // Create root directory
@@ -444,7 +458,11 @@ impl Filesystem for TmpFsFilesystem {
     }
 
     fn root(&self) -> Arc<dyn VNode> {
-        Arc::new(TmpFsVNode::new(Arc::new(Self::new()), 1))
+        let fs = self
+            .this
+            .upgrade()
+            .expect("tmpfs is always reference counted");
+        Arc::new(TmpFsVNode::new(fs, 1))
     }
 
     fn sync(&self) -> Result<(), FsError> {
@@ -454,9 +472,6 @@ impl Filesystem for TmpFsFilesystem {
 
     fn statfs(&self) -> Result<StatFs, FsError> {
         let inodes = self.inodes.read();
-        let total_size: u64 = inodes.values()
-            .map(|inode| inode.read().size)
-            .sum();
 
         Ok(StatFs {
             fs_type: 1,
@@ -21,6 +21,7 @@ pub mod timer;
 pub mod touchpad;
 pub mod usb;
 pub mod vga;
+pub mod virtio;
 
 /// Initialize all drivers
 pub fn init() {
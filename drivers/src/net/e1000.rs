@@ -2,18 +2,28 @@
 //!
 //! This driver supports Intel 8254x (e1000) and 8257x (e1000e) network adapters.
 //! It implements full TX/RX functionality with DMA descriptor rings.
+//!
+//! Devices with an interrupt line acknowledge causes in the handler:
+//! transmit completions reclaim descriptors and receive interrupts refill
+//! the ring. Received frames reach the stack through the receive poll and
+//! link changes are reported to the network device layer from a kernel
+//! timer, outside interrupt context. Interrupts are moderated by the ITR
+//! register. Devices without a line are polled.
 
 #![allow(dead_code)] // Allow unused register constants for future use
 
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::ptr::{read_volatile, write_volatile};
 use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
-use rinux_arch_x86::memory::{phys_to_virt, virt_to_phys};
+use rinux_arch_x86::interrupts;
+use rinux_arch_x86::memory::phys_to_virt;
 use rinux_kernel::net::ethernet::MacAddress;
 use rinux_kernel::net::netdev::{
     DeviceCapabilities, DeviceStats, LinkState, NetDevError, NetDevice,
 };
+use rinux_mm::frame::{self, Frame, FRAME_SIZE};
 use spin::Mutex;
 
 use crate::pci::{PciAddress, PciDevice};
@@ -33,6 +43,7 @@ const REG_ICR: u32 = 0x00C0; // Interrupt Cause Read
 const REG_ICS: u32 = 0x00C8; // Interrupt Cause Set
 const REG_IMS: u32 = 0x00D0; // Interrupt Mask Set
 const REG_IMC: u32 = 0x00D8; // Interrupt Mask Clear
+const REG_ITR: u32 = 0x00C4; // Interrupt Throttling
 
 // Receive Registers
 const REG_RCTL: u32 = 0x0100; // Receive Control
@@ -169,7 +180,7 @@ impl TxDesc {
     }
 
     fn is_done(&self) -> bool {
-        (self.status & 0x01) != 0
+        (self.status & TX_STA_DD) != 0
     }
 }
 
@@ -178,6 +189,11 @@ const TX_CMD_EOP: u8 = 0x01; // End of Packet
 const TX_CMD_IFCS: u8 = 0x02; // Insert FCS
 const TX_CMD_RS: u8 = 0x08; // Report Status
 
+// TX Status bits
+const TX_STA_DD: u8 = 0x01; // Descriptor Done
+const TX_STA_EC: u8 = 0x02; // Excess Collisions
+const TX_STA_LC: u8 = 0x04; // Late Collision
+
 // ============================================================================
 // Device IDs
 // ============================================================================
@@ -211,36 +227,88 @@ const TX_RING_SIZE: usize = 256;
 const RX_BUFFER_SIZE: usize = 2048;
 const TX_BUFFER_SIZE: usize = 2048;
 
+/// Received descriptors handed back to the hardware at once
+const RX_REFILL_BATCH: usize = 16;
+
+/// Default interrupt rate limit (interrupts per second)
+pub const DEFAULT_INTERRUPT_RATE: u32 = 8000;
+
+// ============================================================================
+// Interrupt Causes
+// ============================================================================
+
+/// Interrupt causes decoded from ICR
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterruptCauses(u32);
+
+impl InterruptCauses {
+    /// Decode an ICR value
+    pub const fn from_icr(icr: u32) -> Self {
+        Self(icr)
+    }
+
+    /// Get the raw ICR bits
+    pub const fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Check if no cause is set (the interrupt came from another device)
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Transmit descriptors were written back
+    pub const fn tx_done(&self) -> bool {
+        self.0 & (INT_TXDW | INT_TXQE) != 0
+    }
+
+    /// Frames were received
+    pub const fn rx(&self) -> bool {
+        self.0 & (INT_RXT0 | INT_RXDMT0 | INT_RXO) != 0
+    }
+
+    /// Free receive descriptors fell below the threshold
+    pub const fn rx_low(&self) -> bool {
+        self.0 & INT_RXDMT0 != 0
+    }
+
+    /// Frames were lost for lack of receive descriptors
+    pub const fn rx_overrun(&self) -> bool {
+        self.0 & INT_RXO != 0
+    }
+
+    /// The link may have changed state
+    pub const fn link_changed(&self) -> bool {
+        self.0 & (INT_LSC | INT_RXSEQ) != 0
+    }
+}
+
 // ============================================================================
 // Driver State
 // ============================================================================
 
-/// DMA buffer for packet data
+/// Physically contiguous DMA memory for descriptor rings and packet data
 struct DmaBuffer {
+    first: Frame,
+    frames: usize,
     virt_addr: u64,
-    phys_addr: u64,
 }
 
 impl DmaBuffer {
     fn new(size: usize) -> Result<Self, NetDevError> {
-        // Allocate physical frames for the buffer
-        // TODO: Support multi-frame allocation for larger buffers
-        // Currently only allocates a single frame regardless of size
-        let _num_frames = size.div_ceil(4096);
-
-        // Allocate first frame
-        let frame = rinux_mm::frame::allocate_frame().ok_or(NetDevError::NoMemory)?;
-        let phys_addr = frame.start_address();
-        let virt_addr = phys_to_virt(phys_addr);
+        let frames = size.div_ceil(FRAME_SIZE).max(1);
+        let first = frame::allocate_contiguous(frames).ok_or(NetDevError::NoMemory)?;
+        let virt_addr = phys_to_virt(first.start_address());
 
         // Initialize memory to zero
         unsafe {
-            core::ptr::write_bytes(virt_addr as *mut u8, 0, size);
+            core::ptr::write_bytes(virt_addr as *mut u8, 0, frames * FRAME_SIZE);
         }
 
         Ok(Self {
+            first,
+            frames,
             virt_addr,
-            phys_addr,
         })
     }
 
@@ -249,7 +317,13 @@ impl DmaBuffer {
     }
 
     fn phys_addr(&self) -> u64 {
-        self.phys_addr
+        self.first.start_address()
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        frame::deallocate_contiguous(self.first, self.frames);
     }
 }
 
@@ -261,48 +335,37 @@ struct E1000State {
     device_up: AtomicBool,
 
     // RX Ring
-    rx_descs: Vec<RxDesc>,
-    rx_buffers: Vec<DmaBuffer>,
-    rx_tail: AtomicU32,
+    rx_ring: DmaBuffer,
+    rx_buffers: DmaBuffer,
+    /// Next descriptor to check for a received frame
+    rx_next: usize,
+    /// Last value written to RDT
+    rx_tail: usize,
 
     // TX Ring
-    tx_descs: Vec<TxDesc>,
-    tx_buffers: Vec<DmaBuffer>,
-    tx_tail: AtomicU32,
-    tx_head: AtomicU32,
+    tx_ring: DmaBuffer,
+    tx_buffers: DmaBuffer,
+    /// Next descriptor to fill
+    tx_tail: usize,
+    /// Oldest descriptor not yet reclaimed
+    tx_clean: usize,
 }
 
 impl E1000State {
     fn new(mmio_base: u64) -> Result<Self, NetDevError> {
-        let mut rx_descs = Vec::new();
-        let mut rx_buffers = Vec::new();
-        let mut tx_descs = Vec::new();
-        let mut tx_buffers = Vec::new();
-
-        // Allocate RX descriptors and buffers
-        for _ in 0..RX_RING_SIZE {
-            rx_descs.push(RxDesc::new());
-            rx_buffers.push(DmaBuffer::new(RX_BUFFER_SIZE)?);
-        }
-
-        // Allocate TX descriptors and buffers
-        for _ in 0..TX_RING_SIZE {
-            tx_descs.push(TxDesc::new());
-            tx_buffers.push(DmaBuffer::new(TX_BUFFER_SIZE)?);
-        }
-
         Ok(Self {
             mmio_base,
             mac_address: MacAddress::ZERO,
             link_up: AtomicBool::new(false),
             device_up: AtomicBool::new(false),
-            rx_descs,
-            rx_buffers,
-            rx_tail: AtomicU32::new(0),
-            tx_descs,
-            tx_buffers,
-            tx_tail: AtomicU32::new(0),
-            tx_head: AtomicU32::new(0),
+            rx_ring: DmaBuffer::new(RX_RING_SIZE * core::mem::size_of::<RxDesc>())?,
+            rx_buffers: DmaBuffer::new(RX_RING_SIZE * RX_BUFFER_SIZE)?,
+            rx_next: 0,
+            rx_tail: 0,
+            tx_ring: DmaBuffer::new(TX_RING_SIZE * core::mem::size_of::<TxDesc>())?,
+            tx_buffers: DmaBuffer::new(TX_RING_SIZE * TX_BUFFER_SIZE)?,
+            tx_tail: 0,
+            tx_clean: 0,
         })
     }
 
@@ -315,6 +378,23 @@ impl E1000State {
         unsafe { write_volatile((self.mmio_base + offset as u64) as *mut u32, value) }
     }
 
+    // Descriptor access (the hardware writes descriptors back)
+    fn rx_desc(&self, index: usize) -> RxDesc {
+        unsafe { read_volatile((self.rx_ring.as_ptr() as *const RxDesc).add(index)) }
+    }
+
+    fn set_rx_desc(&self, index: usize, desc: RxDesc) {
+        unsafe { write_volatile((self.rx_ring.as_ptr() as *mut RxDesc).add(index), desc) }
+    }
+
+    fn tx_desc(&self, index: usize) -> TxDesc {
+        unsafe { read_volatile((self.tx_ring.as_ptr() as *const TxDesc).add(index)) }
+    }
+
+    fn set_tx_desc(&self, index: usize, desc: TxDesc) {
+        unsafe { write_volatile((self.tx_ring.as_ptr() as *mut TxDesc).add(index), desc) }
+    }
+
     // Device Reset
     fn reset(&mut self) -> Result<(), NetDevError> {
         // Disable interrupts
@@ -358,19 +438,15 @@ impl E1000State {
 
     // Initialize RX ring
     fn init_rx(&mut self) -> Result<(), NetDevError> {
-        // Setup RX descriptors
+        // Point each descriptor at its buffer
         for i in 0..RX_RING_SIZE {
-            self.rx_descs[i].addr = self.rx_buffers[i].phys_addr();
-            self.rx_descs[i].status = 0;
+            let mut desc = RxDesc::new();
+            desc.addr = self.rx_buffers.phys_addr() + (i * RX_BUFFER_SIZE) as u64;
+            self.set_rx_desc(i, desc);
         }
 
-        // Get physical address of descriptor ring
-        let rx_desc_phys = {
-            let rx_desc_virt = self.rx_descs.as_ptr() as u64;
-            virt_to_phys(rx_desc_virt)
-        };
-
         // Program descriptor base address
+        let rx_desc_phys = self.rx_ring.phys_addr();
         self.write_reg(REG_RDBAL, (rx_desc_phys & 0xFFFFFFFF) as u32);
         self.write_reg(REG_RDBAH, (rx_desc_phys >> 32) as u32);
 
@@ -380,11 +456,14 @@ impl E1000State {
             (RX_RING_SIZE * core::mem::size_of::<RxDesc>()) as u32,
         );
 
-        // Initialize head and tail
+        // Hand all but one descriptor to the hardware
         self.write_reg(REG_RDH, 0);
-        self.write_reg(REG_RDT, (RX_RING_SIZE - 1) as u32);
-        self.rx_tail
-            .store((RX_RING_SIZE - 1) as u32, Ordering::Release);
+        self.rx_next = 0;
+        self.rx_tail = RX_RING_SIZE - 1;
+        self.write_reg(REG_RDT, self.rx_tail as u32);
+
+        // Interrupts are moderated by ITR instead of the receive timer
+        self.write_reg(REG_RDTR, 0);
 
         // Enable receiver
         let mut rctl = RCTL_EN | RCTL_BAM | RCTL_BSIZE_2048 | RCTL_SECRC;
@@ -396,20 +475,15 @@ impl E1000State {
 
     // Initialize TX ring
     fn init_tx(&mut self) -> Result<(), NetDevError> {
-        // Setup TX descriptors
+        // Point each descriptor at its buffer
         for i in 0..TX_RING_SIZE {
-            self.tx_descs[i].addr = self.tx_buffers[i].phys_addr();
-            self.tx_descs[i].cmd = 0;
-            self.tx_descs[i].status = 1; // Mark as done initially
+            let mut desc = TxDesc::new();
+            desc.addr = self.tx_buffers.phys_addr() + (i * TX_BUFFER_SIZE) as u64;
+            self.set_tx_desc(i, desc);
         }
 
-        // Get physical address of descriptor ring
-        let tx_desc_phys = {
-            let tx_desc_virt = self.tx_descs.as_ptr() as u64;
-            virt_to_phys(tx_desc_virt)
-        };
-
         // Program descriptor base address
+        let tx_desc_phys = self.tx_ring.phys_addr();
         self.write_reg(REG_TDBAL, (tx_desc_phys & 0xFFFFFFFF) as u32);
         self.write_reg(REG_TDBAH, (tx_desc_phys >> 32) as u32);
 
@@ -422,8 +496,8 @@ impl E1000State {
         // Initialize head and tail
         self.write_reg(REG_TDH, 0);
         self.write_reg(REG_TDT, 0);
-        self.tx_head.store(0, Ordering::Release);
-        self.tx_tail.store(0, Ordering::Release);
+        self.tx_tail = 0;
+        self.tx_clean = 0;
 
         // Enable transmitter
         let mut tctl = TCTL_EN | TCTL_PSP;
@@ -433,11 +507,21 @@ impl E1000State {
         Ok(())
     }
 
-    // Check and update link status
-    fn update_link_status(&self) {
+    // Limit the interrupt rate (0 disables moderation)
+    fn set_interrupt_rate(&self, per_second: u32) {
+        // ITR counts in 256 ns units
+        let interval = match per_second {
+            0 => 0,
+            rate => (1_000_000_000 / (u64::from(rate) * 256)).min(0xFFFF) as u32,
+        };
+        self.write_reg(REG_ITR, interval);
+    }
+
+    // Check and update link status, returning whether it changed
+    fn update_link_status(&self) -> bool {
         let status = self.read_reg(REG_STATUS);
         let link_up = (status & STATUS_LU) != 0;
-        self.link_up.store(link_up, Ordering::Release);
+        self.link_up.swap(link_up, Ordering::AcqRel) != link_up
     }
 
     // Simple delay (busy wait)
@@ -447,6 +531,39 @@ impl E1000State {
         }
     }
 
+    // Reclaim transmit descriptors the hardware has finished with
+    fn reclaim_tx(&mut self, stats: &DeviceStats) -> usize {
+        let mut reclaimed = 0;
+        while self.tx_clean != self.tx_tail {
+            let desc = self.tx_desc(self.tx_clean);
+            if !desc.is_done() {
+                break;
+            }
+            if desc.status & (TX_STA_EC | TX_STA_LC) != 0 {
+                stats.inc_tx_errors();
+            }
+            self.tx_clean = (self.tx_clean + 1) % TX_RING_SIZE;
+            reclaimed += 1;
+        }
+        reclaimed
+    }
+
+    // Give cleaned receive descriptors back to the hardware
+    fn refill_rx(&mut self) {
+        // The descriptor before the next one to check stays with software,
+        // so a full ring never looks empty to the hardware
+        let tail = (self.rx_next + RX_RING_SIZE - 1) % RX_RING_SIZE;
+        if tail != self.rx_tail {
+            self.rx_tail = tail;
+            self.write_reg(REG_RDT, tail as u32);
+        }
+    }
+
+    // Number of cleaned receive descriptors not yet given back
+    fn rx_pending_refill(&self) -> usize {
+        (self.rx_next + RX_RING_SIZE - 1 - self.rx_tail) % RX_RING_SIZE
+    }
+
     // Transmit packet
     fn transmit(&mut self, packet: &[u8], stats: &DeviceStats) -> Result<(), NetDevError> {
         if packet.len() > TX_BUFFER_SIZE {
@@ -454,12 +571,10 @@ impl E1000State {
             return Err(NetDevError::BufferTooSmall);
         }
 
-        let tail = self.tx_tail.load(Ordering::Acquire) as usize;
-        let head = self.read_reg(REG_TDH) as usize;
-
-        // Check if ring is full
+        // Check if ring is full, reclaiming sent descriptors first
+        let tail = self.tx_tail;
         let next_tail = (tail + 1) % TX_RING_SIZE;
-        if next_tail == head {
+        if next_tail == self.tx_clean && self.reclaim_tx(stats) == 0 {
             stats.inc_tx_dropped();
             return Err(NetDevError::Busy);
         }
@@ -468,18 +583,20 @@ impl E1000State {
         unsafe {
             core::ptr::copy_nonoverlapping(
                 packet.as_ptr(),
-                self.tx_buffers[tail].as_ptr(),
+                self.tx_buffers.as_ptr().add(tail * TX_BUFFER_SIZE),
                 packet.len(),
             );
         }
 
         // Setup descriptor
-        self.tx_descs[tail].length = packet.len() as u16;
-        self.tx_descs[tail].cmd = TX_CMD_EOP | TX_CMD_IFCS | TX_CMD_RS;
-        self.tx_descs[tail].status = 0;
+        let mut desc = self.tx_desc(tail);
+        desc.length = packet.len() as u16;
+        desc.cmd = TX_CMD_EOP | TX_CMD_IFCS | TX_CMD_RS;
+        desc.status = 0;
+        self.set_tx_desc(tail, desc);
 
         // Update tail pointer
-        self.tx_tail.store(next_tail as u32, Ordering::Release);
+        self.tx_tail = next_tail;
         self.write_reg(REG_TDT, next_tail as u32);
 
         // Update statistics
@@ -491,52 +608,48 @@ impl E1000State {
 
     // Receive packet
     fn receive(&mut self, buffer: &mut [u8], stats: &DeviceStats) -> Result<usize, NetDevError> {
-        let tail = self.rx_tail.load(Ordering::Acquire) as usize;
-        let next_tail = (tail + 1) % RX_RING_SIZE;
+        let index = self.rx_next;
+        let desc = self.rx_desc(index);
 
         // Check if packet is available
-        if !self.rx_descs[next_tail].is_done() {
+        if !desc.is_done() {
+            self.refill_rx();
             return Err(NetDevError::WouldBlock);
         }
 
-        let desc = &self.rx_descs[next_tail];
-
-        // Check for errors
-        if desc.has_error() {
-            stats.inc_rx_errors();
-            // Clear descriptor and move to next
-            self.rx_descs[next_tail].status = 0;
-            self.rx_tail.store(next_tail as u32, Ordering::Release);
-            self.write_reg(REG_RDT, next_tail as u32);
-            return Err(NetDevError::InvalidParam);
-        }
-
         let length = desc.length as usize;
-
-        if length > buffer.len() {
+        let result = if desc.has_error() {
+            stats.inc_rx_errors();
+            Err(NetDevError::InvalidParam)
+        } else if length > buffer.len() {
             stats.inc_rx_dropped();
-            return Err(NetDevError::BufferTooSmall);
-        }
-
-        // Copy packet data to buffer
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                self.rx_buffers[next_tail].as_ptr(),
-                buffer.as_mut_ptr(),
-                length,
-            );
-        }
+            Err(NetDevError::BufferTooSmall)
+        } else {
+            // Copy packet data to buffer
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    self.rx_buffers.as_ptr().add(index * RX_BUFFER_SIZE),
+                    buffer.as_mut_ptr(),
+                    length,
+                );
+            }
 
-        // Clear descriptor and give buffer back to hardware
-        self.rx_descs[next_tail].status = 0;
-        self.rx_tail.store(next_tail as u32, Ordering::Release);
-        self.write_reg(REG_RDT, next_tail as u32);
+            // Update statistics
+            stats.inc_rx_packets(1);
+            stats.inc_rx_bytes(length as u32);
+            Ok(length)
+        };
 
-        // Update statistics
-        stats.inc_rx_packets(1);
-        stats.inc_rx_bytes(length as u32);
+        // Clear descriptor; it returns to the hardware with the next refill
+        let mut cleared = RxDesc::new();
+        cleared.addr = desc.addr;
+        self.set_rx_desc(index, cleared);
+        self.rx_next = (index + 1) % RX_RING_SIZE;
+        if self.rx_pending_refill() >= RX_REFILL_BATCH {
+            self.refill_rx();
+        }
 
-        Ok(length)
+        result
     }
 }
 
@@ -584,17 +697,14 @@ impl E1000Driver {
         let ctrl = state.read_reg(REG_CTRL);
         state.write_reg(REG_CTRL, ctrl | CTRL_SLU | CTRL_ASDE);
 
-        // Enable interrupts
-        state.write_reg(
-            REG_IMS,
-            INT_RXT0 | INT_TXDW | INT_LSC | INT_RXDMT0 | INT_RXO,
-        );
+        // Moderate interrupts; they stay masked until a handler is attached
+        state.set_interrupt_rate(DEFAULT_INTERRUPT_RATE);
 
         // Update link status
         state.update_link_status();
 
         // Create device name
-        let name = String::from("eth0");
+        let name = rinux_kernel::net::netdev::alloc_name("eth");
 
         Ok(Self {
             name,
@@ -603,23 +713,55 @@ impl E1000Driver {
         })
     }
 
-    /// Handle device interrupt
-    pub fn handle_interrupt(&self) {
-        let state = self.state.lock();
+    /// Unmask the interrupts the driver services
+    pub fn enable_interrupts(&self) {
+        self.state.lock().write_reg(REG_IMS, INTERRUPT_MASK);
+    }
 
-        // Read interrupt cause
-        let icr = state.read_reg(REG_ICR);
+    /// Mask all device interrupts
+    pub fn disable_interrupts(&self) {
+        self.state.lock().write_reg(REG_IMC, 0xFFFFFFFF);
+    }
 
-        if icr == 0 {
-            return; // Not our interrupt
+    /// Limit the interrupt rate (0 disables moderation)
+    pub fn set_interrupt_rate(&self, per_second: u32) {
+        self.state.lock().set_interrupt_rate(per_second);
+    }
+
+    /// Read and clear the pending interrupt causes
+    pub fn read_interrupt_causes(&self) -> InterruptCauses {
+        InterruptCauses::from_icr(self.state.lock().read_reg(REG_ICR))
+    }
+
+    /// Handle device interrupt causes
+    ///
+    /// Reclaims sent descriptors, refills the receive ring and tracks the
+    /// link. Received frames are left for [`NetDevice::recv`]. Returns the
+    /// new link state if it changed.
+    pub fn handle_interrupt(&self, causes: InterruptCauses) -> Option<LinkState> {
+        let mut state = self.state.lock();
+
+        if causes.tx_done() {
+            state.reclaim_tx(&self.stats);
+        }
+
+        // Frames were lost: count them and give the hardware room
+        if causes.rx_overrun() {
+            self.stats.inc_rx_dropped();
+        }
+        if causes.rx_low() || causes.rx_overrun() {
+            state.refill_rx();
         }
 
-        // Handle link status change
-        if (icr & INT_LSC) != 0 {
-            state.update_link_status();
+        if causes.link_changed() && state.update_link_status() {
+            return Some(if state.link_up.load(Ordering::Acquire) {
+                LinkState::Up
+            } else {
+                LinkState::Down
+            });
         }
 
-        // RX and TX interrupts are handled by polling for now
+        None
     }
 }
 
@@ -717,6 +859,139 @@ impl NetDevice for E1000Driver {
 // Device Detection and Initialization
 // ============================================================================
 
+/// Interrupts serviced by the driver
+const INTERRUPT_MASK: u32 = INT_RXT0 | INT_TXDW | INT_LSC | INT_RXSEQ | INT_RXDMT0 | INT_RXO;
+
+/// Device serviced from the interrupt handler
+struct InterruptSource {
+    irq: u8,
+    mmio_base: u64,
+    /// Causes read while the driver was busy, serviced by `service_deferred`
+    deferred: AtomicU32,
+    /// Link change waiting to be reported from process context
+    link_changed: AtomicBool,
+    driver: Arc<Mutex<E1000Driver>>,
+}
+
+/// Devices with an interrupt line
+///
+/// Only locked with interrupts disabled outside the handler, so the
+/// handler can always take the lock.
+static INTERRUPT_SOURCES: Mutex<Vec<InterruptSource>> = Mutex::new(Vec::new());
+
+/// Deferred interrupt work timer started flag
+static DEFERRED_TIMER_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Interrupt handler for every e1000 device
+///
+/// Only acknowledges the device, reclaims and refills descriptors and
+/// records what else happened. Received frames are picked up by the
+/// receive poll, and link changes are reported by `service_deferred`,
+/// since the network stack takes locks that are not interrupt-safe.
+fn interrupt_handler(irq: u8) {
+    let sources = INTERRUPT_SOURCES.lock();
+    for source in sources.iter().filter(|source| source.irq == irq) {
+        // Reading ICR clears it and deasserts the line
+        let icr = unsafe { read_volatile((source.mmio_base + REG_ICR as u64) as *const u32) };
+        if icr == 0 {
+            continue; // Not our interrupt
+        }
+
+        // The interrupted code holds the driver: leave the causes for later
+        let Some(driver) = source.driver.try_lock() else {
+            source.deferred.fetch_or(icr, Ordering::AcqRel);
+            continue;
+        };
+        let icr = icr | source.deferred.swap(0, Ordering::AcqRel);
+        if driver
+            .handle_interrupt(InterruptCauses::from_icr(icr))
+            .is_some()
+        {
+            source.link_changed.store(true, Ordering::Release);
+        }
+    }
+}
+
+/// Finish interrupt work that cannot run in the handler
+///
+/// Runs from a kernel timer: handles causes the handler found the driver
+/// busy for and reports link changes.
+fn service_deferred() {
+    let enabled = rinux_arch_x86::interrupts_enabled();
+    rinux_arch_x86::disable_interrupts();
+    let work: Vec<_> = INTERRUPT_SOURCES
+        .lock()
+        .iter()
+        .map(|source| {
+            (
+                source.driver.clone(),
+                source.deferred.swap(0, Ordering::AcqRel),
+                source.link_changed.swap(false, Ordering::AcqRel),
+            )
+        })
+        .collect();
+    if enabled {
+        rinux_arch_x86::enable_interrupts();
+    }
+
+    for (driver, icr, mut link_changed) in work {
+        let driver = driver.lock();
+        if icr != 0 {
+            link_changed |= driver
+                .handle_interrupt(InterruptCauses::from_icr(icr))
+                .is_some();
+        }
+        if !link_changed {
+            continue;
+        }
+
+        let name = driver.name.clone();
+        let state = driver.link_state();
+        drop(driver);
+        rinux_kernel::net::netdev::notify_link_change(&name, state);
+    }
+}
+
+/// Service a device from its interrupt line
+///
+/// Returns false if the device has no usable line and stays polled.
+fn attach_interrupt(driver: &Arc<Mutex<E1000Driver>>, irq: u8) -> bool {
+    // Line 0xFF means not connected
+    if irq as usize >= interrupts::IRQ_LINES {
+        return false;
+    }
+
+    let mmio_base = driver.lock().state.lock().mmio_base;
+    let enabled = rinux_arch_x86::interrupts_enabled();
+    rinux_arch_x86::disable_interrupts();
+    INTERRUPT_SOURCES.lock().push(InterruptSource {
+        irq,
+        mmio_base,
+        deferred: AtomicU32::new(0),
+        link_changed: AtomicBool::new(false),
+        driver: driver.clone(),
+    });
+    if enabled {
+        rinux_arch_x86::enable_interrupts();
+    }
+
+    if !DEFERRED_TIMER_STARTED.swap(true, Ordering::AcqRel)
+        && rinux_kernel::time::timer::create_periodic_timer(
+            rinux_kernel::net::rx::RX_POLL_INTERVAL_MS,
+            service_deferred,
+        )
+        .is_err()
+    {
+        rinux_kernel::printk::printk("  Failed to start deferred interrupt work\n");
+    }
+
+    if interrupts::register_handler(irq, interrupt_handler).is_err() {
+        return false;
+    }
+    driver.lock().enable_interrupts();
+    true
+}
+
 /// Check if a PCI device is a supported e1000 device
 pub fn is_e1000_device(device: &PciDevice) -> bool {
     if device.vendor_id != VENDOR_INTEL {
@@ -784,17 +1059,30 @@ pub fn init() {
 
                 // Try to initialize the driver
                 match E1000Driver::new(*device) {
-                    Ok(driver) => {
+                    Ok(mut driver) => {
                         rinux_kernel::printk::printk("  Initialized with MAC: ");
                         print_mac_address(driver.mac_address());
                         rinux_kernel::printk::printk("\n");
 
+                        // Ready to pass frames as soon as the rings are set up
+                        let _ = driver.up();
+
                         // Register with network stack
-                        let driver = alloc::sync::Arc::new(Mutex::new(driver));
-                        if let Err(_e) = rinux_kernel::net::netdev::register_device(driver) {
+                        let driver = Arc::new(Mutex::new(driver));
+                        if let Err(_e) = rinux_kernel::net::netdev::register_device(driver.clone())
+                        {
                             rinux_kernel::printk::printk("  Failed to register device\n");
+                            continue;
+                        }
+                        device_count += 1;
+
+                        if attach_interrupt(&driver, device.interrupt_line()) {
+                            rinux_kernel::printk::printk(&alloc::format!(
+                                "  Interrupts on IRQ {}\n",
+                                device.interrupt_line()
+                            ));
                         } else {
-                            device_count += 1;
+                            rinux_kernel::printk::printk("  No interrupt line, polling\n");
                         }
                     }
                     Err(_) => {
@@ -3,8 +3,10 @@
 //! Network interface drivers for various hardware.
 
 pub mod e1000;
+pub mod virtio_net;
 
 /// Initialize all network drivers
 pub fn init() {
     e1000::init();
+    virtio_net::init();
 }
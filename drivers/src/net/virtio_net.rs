@@ -0,0 +1,576 @@
+//! Virtio Network Driver
+//!
+//! Driver for virtio-net devices, the paravirtualized NIC of QEMU/KVM.
+//! Works with both legacy and modern (virtio 1.0) PCI devices through the
+//! [`virtio`](crate::virtio) transport.
+//!
+//! Queue 0 receives and queue 1 transmits. Every frame is preceded by a
+//! `virtio_net_hdr`; with `MRG_RXBUF` a received frame may span several
+//! buffers. Checksums the host left for the guest to complete
+//! (`GUEST_CSUM`) are filled in before frames reach the stack.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use rinux_kernel::net::ethernet::MacAddress;
+use rinux_kernel::net::netdev::{
+    DeviceCapabilities, DeviceStats, LinkState, NetDevError, NetDevice,
+};
+use spin::Mutex;
+
+use crate::pci::PciDevice;
+use crate::virtio::queue::{DmaRegion, VirtQueue, DESC_F_WRITE};
+use crate::virtio::{Transport, VirtioError, F_ANY_LAYOUT, F_VERSION_1, VENDOR_VIRTIO};
+
+// ============================================================================
+// Device IDs
+// ============================================================================
+
+const DEVICE_TRANSITIONAL_NET: u16 = 0x1000; // Transitional virtio-net
+const DEVICE_MODERN_NET: u16 = 0x1041; // Virtio 1.0 virtio-net
+
+// ============================================================================
+// Feature Bits
+// ============================================================================
+
+const F_CSUM: u64 = 1 << 0; // Device checksums partial TX packets
+const F_GUEST_CSUM: u64 = 1 << 1; // Driver handles partial RX checksums
+const F_MAC: u64 = 1 << 5; // Device has a MAC address
+const F_MRG_RXBUF: u64 = 1 << 15; // Frames may span RX buffers
+const F_STATUS: u64 = 1 << 16; // Link status in config space
+
+/// Features the driver can use
+const SUPPORTED_FEATURES: u64 =
+    F_CSUM | F_GUEST_CSUM | F_MAC | F_MRG_RXBUF | F_STATUS | F_ANY_LAYOUT | F_VERSION_1;
+
+// Device configuration offsets
+const CONFIG_MAC: u16 = 0x00;
+const CONFIG_STATUS: u16 = 0x06;
+
+// Link status bits
+const S_LINK_UP: u16 = 1;
+
+// virtio_net_hdr flags
+const HDR_F_NEEDS_CSUM: u8 = 1;
+
+// ============================================================================
+// Queue Configuration
+// ============================================================================
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+const BUFFER_SIZE: usize = 2048;
+
+/// Header size without `num_buffers` (legacy, no MRG_RXBUF)
+const HDR_SIZE_LEGACY: usize = 10;
+/// Header size with `num_buffers`
+const HDR_SIZE: usize = 12;
+
+/// Header preceding every frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct NetHeader {
+    flags: u8,
+    csum_start: u16,
+    csum_offset: u16,
+    num_buffers: u16,
+}
+
+impl NetHeader {
+    /// Parse a header of `size` bytes (fields are little-endian)
+    fn parse(bytes: &[u8], size: usize) -> Self {
+        let field = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        Self {
+            flags: bytes[0],
+            csum_start: field(6),
+            csum_offset: field(8),
+            num_buffers: if size >= HDR_SIZE { field(10) } else { 1 },
+        }
+    }
+}
+
+/// Fill in a checksum the host left partial
+///
+/// The checksum field holds the pseudo-header sum; summing from
+/// `csum_start` to the end of the frame completes it.
+fn complete_checksum(frame: &mut [u8], header: &NetHeader) -> Result<(), NetDevError> {
+    let start = header.csum_start as usize;
+    let field = start + header.csum_offset as usize;
+    if field + 2 > frame.len() {
+        return Err(NetDevError::InvalidParam);
+    }
+
+    let checksum = match rinux_kernel::net::ipv4::calculate_checksum(&frame[start..]) {
+        0 => 0xFFFF,
+        checksum => checksum,
+    };
+    frame[field..field + 2].copy_from_slice(&checksum.to_be_bytes());
+    Ok(())
+}
+
+impl From<VirtioError> for NetDevError {
+    fn from(err: VirtioError) -> Self {
+        match err {
+            VirtioError::NoMemory => NetDevError::NoMemory,
+            VirtioError::NotSupported => NetDevError::NotSupported,
+            _ => NetDevError::InvalidParam,
+        }
+    }
+}
+
+// ============================================================================
+// Driver State
+// ============================================================================
+
+/// Virtio-net device state
+struct VirtioNetState {
+    transport: Transport,
+    features: u64,
+    header_size: usize,
+    mac_address: MacAddress,
+    device_up: bool,
+
+    rx_queue: VirtQueue,
+    rx_buffers: DmaRegion,
+
+    tx_queue: VirtQueue,
+    tx_buffers: DmaRegion,
+    /// Transmit descriptors not in flight
+    tx_free: Vec<u16>,
+}
+
+impl VirtioNetState {
+    fn new(pci_device: &PciDevice) -> Result<Self, NetDevError> {
+        let transport = Transport::probe(pci_device)?;
+        let features = transport.negotiate(SUPPORTED_FEATURES)?;
+
+        // Headers share a buffer with frame data
+        if !transport.is_modern() && features & F_ANY_LAYOUT == 0 {
+            transport.fail();
+            return Err(NetDevError::NotSupported);
+        }
+
+        let queues = Self::setup_queue(&transport, RX_QUEUE, DESC_F_WRITE)
+            .and_then(|rx| Ok((rx, Self::setup_queue(&transport, TX_QUEUE, 0)?)));
+        let ((mut rx_queue, rx_buffers), (tx_queue, tx_buffers)) = match queues {
+            Ok(queues) => queues,
+            Err(err) => {
+                transport.fail();
+                return Err(err.into());
+            }
+        };
+
+        for id in 0..rx_queue.size() {
+            rx_queue.submit(id, BUFFER_SIZE as u32);
+        }
+        let tx_free = (0..tx_queue.size()).rev().collect();
+
+        let header_size = if features & (F_MRG_RXBUF | F_VERSION_1) != 0 {
+            HDR_SIZE
+        } else {
+            HDR_SIZE_LEGACY
+        };
+
+        let mut state = Self {
+            transport,
+            features,
+            header_size,
+            mac_address: MacAddress::ZERO,
+            device_up: false,
+            rx_queue,
+            rx_buffers,
+            tx_queue,
+            tx_buffers,
+            tx_free,
+        };
+        state.read_mac_address(pci_device);
+
+        state.transport.driver_ok();
+        state.transport.notify(&state.rx_queue);
+
+        Ok(state)
+    }
+
+    // Set up a queue with a fixed buffer behind each descriptor
+    fn setup_queue(
+        transport: &Transport,
+        index: u16,
+        flags: u16,
+    ) -> Result<(VirtQueue, DmaRegion), VirtioError> {
+        let mut queue = transport.setup_queue(index)?;
+        let buffers = DmaRegion::new(queue.size() as usize * BUFFER_SIZE)?;
+        for id in 0..queue.size() {
+            let addr = buffers.phys_addr(id as usize * BUFFER_SIZE);
+            queue.set_descriptor(id, addr, BUFFER_SIZE as u32, flags);
+        }
+        Ok((queue, buffers))
+    }
+
+    fn has_feature(&self, feature: u64) -> bool {
+        self.features & feature != 0
+    }
+
+    // Read MAC address from config space
+    fn read_mac_address(&mut self, pci_device: &PciDevice) {
+        let mut mac = [0u8; 6];
+        if self.has_feature(F_MAC) {
+            for (i, octet) in mac.iter_mut().enumerate() {
+                *octet = self.transport.read_config_u8(CONFIG_MAC + i as u16);
+            }
+        }
+
+        // Without one, derive a locally administered address from the slot
+        if mac.iter().all(|&b| b == 0) {
+            let addr = pci_device.address;
+            mac = [0x02, 0x00, 0x00, addr.bus, addr.device, addr.function];
+        }
+
+        self.mac_address = MacAddress::new(mac);
+    }
+
+    fn link_up(&self) -> bool {
+        !self.has_feature(F_STATUS)
+            || self.transport.read_config_u16(CONFIG_STATUS) & S_LINK_UP != 0
+    }
+
+    // IDs come from `rx_entry`, so they name one of the buffers
+    fn rx_buffer(&self, id: u16) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self.rx_buffers.as_ptr(id as usize * BUFFER_SIZE),
+                BUFFER_SIZE,
+            )
+        }
+    }
+
+    // Return receive buffers to the device
+    fn repost(&mut self, ids: &[u16]) {
+        for &id in ids {
+            self.rx_queue.submit(id, BUFFER_SIZE as u32);
+        }
+        if self.rx_queue.needs_notify() {
+            self.transport.notify(&self.rx_queue);
+        }
+    }
+
+    // Check a used receive entry against the buffers posted to the device
+    fn rx_entry(&self, id: u32, len: u32) -> Option<(u16, usize)> {
+        let len = len as usize;
+        (id < u32::from(self.rx_queue.size()) && len <= BUFFER_SIZE).then_some((id as u16, len))
+    }
+
+    // Drop a frame after the device returned a bad used entry
+    fn drop_bad_rx(&mut self, ids: &[u16], bad: u32, stats: &DeviceStats) -> NetDevError {
+        stats.inc_rx_dropped();
+        self.repost(ids);
+        if bad < u32::from(self.rx_queue.size()) {
+            self.repost(&[bad as u16]);
+        }
+        NetDevError::InvalidParam
+    }
+
+    // Reclaim transmit descriptors the device has sent
+    fn reclaim_tx(&mut self, stats: &DeviceStats) {
+        while let Some((id, _)) = self.tx_queue.pop_used() {
+            // Unknown or already free IDs are device errors
+            let id = match u16::try_from(id) {
+                Ok(id) if id < self.tx_queue.size() && !self.tx_free.contains(&id) => id,
+                _ => {
+                    stats.inc_tx_dropped();
+                    continue;
+                }
+            };
+            self.tx_free.push(id);
+        }
+    }
+
+    // Transmit packet
+    fn transmit(&mut self, packet: &[u8], stats: &DeviceStats) -> Result<(), NetDevError> {
+        let length = self.header_size + packet.len();
+        if length > BUFFER_SIZE {
+            stats.inc_tx_errors();
+            return Err(NetDevError::BufferTooSmall);
+        }
+
+        self.reclaim_tx(stats);
+        let Some(id) = self.tx_free.pop() else {
+            stats.inc_tx_dropped();
+            return Err(NetDevError::Busy);
+        };
+
+        // Checksums are computed by the stack, so the header stays empty
+        let buffer = self.tx_buffers.as_ptr(id as usize * BUFFER_SIZE);
+        unsafe {
+            core::ptr::write_bytes(buffer, 0, self.header_size);
+            core::ptr::copy_nonoverlapping(
+                packet.as_ptr(),
+                buffer.add(self.header_size),
+                packet.len(),
+            );
+        }
+
+        self.tx_queue.submit(id, length as u32);
+        if self.tx_queue.needs_notify() {
+            self.transport.notify(&self.tx_queue);
+        }
+
+        stats.inc_tx_packets(1);
+        stats.inc_tx_bytes(packet.len() as u32);
+
+        Ok(())
+    }
+
+    // Receive packet
+    fn receive(&mut self, buffer: &mut [u8], stats: &DeviceStats) -> Result<usize, NetDevError> {
+        let Some((first, len)) = self.rx_queue.pop_used() else {
+            return Err(NetDevError::WouldBlock);
+        };
+        let Some((first, len)) = self.rx_entry(first, len) else {
+            return Err(self.drop_bad_rx(&[], first, stats));
+        };
+
+        if len < self.header_size {
+            stats.inc_rx_errors();
+            self.repost(&[first]);
+            return Err(NetDevError::InvalidParam);
+        }
+
+        let header = NetHeader::parse(self.rx_buffer(first), self.header_size);
+        let mut used = Vec::with_capacity(header.num_buffers as usize);
+        used.push((first, len));
+
+        // With MRG_RXBUF the rest of the frame follows in further buffers
+        if self.has_feature(F_MRG_RXBUF) {
+            while used.len() < header.num_buffers as usize {
+                let Some((id, len)) = self.rx_queue.pop_used() else {
+                    break;
+                };
+                match self.rx_entry(id, len) {
+                    Some(entry) => used.push(entry),
+                    None => {
+                        let ids: Vec<u16> = used.iter().map(|&(id, _)| id).collect();
+                        return Err(self.drop_bad_rx(&ids, id, stats));
+                    }
+                }
+            }
+        }
+
+        let ids: Vec<u16> = used.iter().map(|&(id, _)| id).collect();
+        let length = len - self.header_size + used[1..].iter().map(|&(_, len)| len).sum::<usize>();
+        if used.len() < header.num_buffers as usize {
+            stats.inc_rx_errors();
+            self.repost(&ids);
+            return Err(NetDevError::InvalidParam);
+        }
+        if length > buffer.len() {
+            stats.inc_rx_dropped();
+            self.repost(&ids);
+            return Err(NetDevError::BufferTooSmall);
+        }
+
+        let mut copied = 0;
+        for (i, &(id, len)) in used.iter().enumerate() {
+            let start = if i == 0 { self.header_size } else { 0 };
+            let data = &self.rx_buffer(id)[start..len];
+            buffer[copied..copied + data.len()].copy_from_slice(data);
+            copied += data.len();
+        }
+        self.repost(&ids);
+
+        if header.flags & HDR_F_NEEDS_CSUM != 0 {
+            if let Err(err) = complete_checksum(&mut buffer[..length], &header) {
+                stats.inc_rx_errors();
+                return Err(err);
+            }
+        }
+
+        stats.inc_rx_packets(1);
+        stats.inc_rx_bytes(length as u32);
+
+        Ok(length)
+    }
+}
+
+// ============================================================================
+// Virtio-net Device Driver
+// ============================================================================
+
+/// Virtio Network Driver
+pub struct VirtioNetDriver {
+    name: String,
+    state: Mutex<VirtioNetState>,
+    stats: DeviceStats,
+}
+
+impl VirtioNetDriver {
+    /// Create a new virtio-net driver instance
+    pub fn new(pci_device: PciDevice) -> Result<Self, NetDevError> {
+        let state = VirtioNetState::new(&pci_device)?;
+        let name = rinux_kernel::net::netdev::alloc_name("eth");
+
+        Ok(Self {
+            name,
+            state: Mutex::new(state),
+            stats: DeviceStats::new(),
+        })
+    }
+
+    /// Check if the device uses the virtio 1.0 interface
+    pub fn is_modern(&self) -> bool {
+        self.state.lock().transport.is_modern()
+    }
+}
+
+impl NetDevice for VirtioNetDriver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        self.state.lock().mac_address
+    }
+
+    fn set_mac_address(&mut self, mac: MacAddress) -> Result<(), NetDevError> {
+        let mut state = self.state.lock();
+
+        // Modern devices only take a new address through the control queue
+        if state.transport.is_modern() {
+            return Err(NetDevError::NotSupported);
+        }
+
+        for (i, &octet) in mac.octets().iter().enumerate() {
+            state
+                .transport
+                .write_config_u8(CONFIG_MAC + i as u16, octet);
+        }
+        state.mac_address = mac;
+
+        Ok(())
+    }
+
+    fn link_state(&self) -> LinkState {
+        if self.state.lock().link_up() {
+            LinkState::Up
+        } else {
+            LinkState::Down
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        1500
+    }
+
+    fn set_mtu(&mut self, _mtu: usize) -> Result<(), NetDevError> {
+        Err(NetDevError::NotSupported)
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            mtu: 1500,
+            checksum_offload: self.state.lock().has_feature(F_CSUM),
+            scatter_gather: false,
+            vlan_support: false,
+        }
+    }
+
+    fn up(&mut self) -> Result<(), NetDevError> {
+        self.state.lock().device_up = true;
+        Ok(())
+    }
+
+    fn down(&mut self) -> Result<(), NetDevError> {
+        self.state.lock().device_up = false;
+        Ok(())
+    }
+
+    fn send(&mut self, packet: &[u8]) -> Result<(), NetDevError> {
+        let mut state = self.state.lock();
+
+        if !state.device_up {
+            return Err(NetDevError::DeviceDown);
+        }
+
+        state.transmit(packet, &self.stats)
+    }
+
+    fn recv(&mut self, buffer: &mut [u8]) -> Result<usize, NetDevError> {
+        let mut state = self.state.lock();
+
+        if !state.device_up {
+            return Err(NetDevError::DeviceDown);
+        }
+
+        state.receive(buffer, &self.stats)
+    }
+
+    fn stats(&self) -> &DeviceStats {
+        &self.stats
+    }
+}
+
+// ============================================================================
+// Device Detection and Initialization
+// ============================================================================
+
+/// Check if a PCI device is a virtio-net device
+pub fn is_virtio_net_device(device: &PciDevice) -> bool {
+    device.vendor_id == VENDOR_VIRTIO
+        && matches!(
+            device.device_id,
+            DEVICE_TRANSITIONAL_NET | DEVICE_MODERN_NET
+        )
+}
+
+/// Initialize virtio-net driver and detect devices
+pub fn init() {
+    rinux_kernel::printk::printk("Initializing virtio-net network driver...\n");
+
+    let scanner = crate::pci::scanner();
+    let mut device_count = 0;
+
+    for i in 0..scanner.device_count() {
+        let Some(device) = scanner.get_device(i) else {
+            continue;
+        };
+        if !is_virtio_net_device(device) {
+            continue;
+        }
+
+        rinux_kernel::printk::printk(&alloc::format!(
+            "Found virtio-net device at {}\n",
+            device.address
+        ));
+
+        let mut driver = match VirtioNetDriver::new(*device) {
+            Ok(driver) => driver,
+            Err(_) => {
+                rinux_kernel::printk::printk("  Failed to initialize driver\n");
+                continue;
+            }
+        };
+
+        rinux_kernel::printk::printk(&alloc::format!(
+            "  Initialized {} ({}) with MAC: {}\n",
+            driver.name(),
+            if driver.is_modern() {
+                "modern"
+            } else {
+                "legacy"
+            },
+            driver.mac_address()
+        ));
+
+        // Paravirtual links are up as soon as the queues are
+        let _ = driver.up();
+
+        // Register with network stack
+        let driver = alloc::sync::Arc::new(Mutex::new(driver));
+        if rinux_kernel::net::netdev::register_device(driver).is_err() {
+            rinux_kernel::printk::printk("  Failed to register device\n");
+        } else {
+            device_count += 1;
+        }
+    }
+
+    if device_count == 0 {
+        rinux_kernel::printk::printk("No virtio-net devices found\n");
+    }
+}
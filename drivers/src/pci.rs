@@ -165,6 +165,50 @@ impl PciDevice {
         self.read_config_u8(0x3D)
     }
 
+    /// Get the address of a memory BAR, combining both halves of a 64-bit BAR
+    pub fn memory_bar(&self, index: usize) -> Option<u64> {
+        let bar = *self.bars.get(index)?;
+        if bar & 0x1 != 0 {
+            return None;
+        }
+
+        let low = u64::from(bar & !0xF);
+        let address = if (bar >> 1) & 0x3 == 0x2 {
+            low | (u64::from(*self.bars.get(index + 1)?) << 32)
+        } else {
+            low
+        };
+        (address != 0).then_some(address)
+    }
+
+    /// Get the port of an I/O BAR
+    pub fn io_bar(&self, index: usize) -> Option<u16> {
+        let bar = *self.bars.get(index)?;
+        if bar & 0x1 == 0 {
+            return None;
+        }
+
+        let port = (bar & !0x3) as u16;
+        (port != 0).then_some(port)
+    }
+
+    /// Iterate over the device's capability list
+    ///
+    /// Yields the ID and configuration space offset of each capability.
+    pub fn capabilities(&self) -> Capabilities<'_> {
+        // Status register bit 4: capability list present
+        let next = if self.read_config_u16(0x06) & 0x10 != 0 {
+            self.read_config_u8(0x34) & 0xFC
+        } else {
+            0
+        };
+        Capabilities {
+            device: self,
+            next,
+            remaining: MAX_CAPABILITIES,
+        }
+    }
+
     /// Check if this is a USB controller
     pub fn is_usb_controller(&self) -> bool {
         self.class == PciClass::SerialBusController && self.subclass == 0x03
@@ -186,6 +230,32 @@ impl PciDevice {
     }
 }
 
+/// Most capabilities followed, guarding against a looping list
+const MAX_CAPABILITIES: usize = 48;
+
+/// Iterator over a device's capability list
+pub struct Capabilities<'a> {
+    device: &'a PciDevice,
+    next: u8,
+    remaining: usize,
+}
+
+impl Iterator for Capabilities<'_> {
+    type Item = (u8, u8);
+
+    fn next(&mut self) -> Option<(u8, u8)> {
+        if self.next < 0x40 || self.remaining == 0 {
+            return None;
+        }
+
+        let offset = self.next;
+        let id = self.device.read_config_u8(offset);
+        self.next = self.device.read_config_u8(offset + 1) & 0xFC;
+        self.remaining -= 1;
+        Some((id, offset))
+    }
+}
+
 impl fmt::Display for PciDevice {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
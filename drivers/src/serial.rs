@@ -325,6 +325,16 @@ pub fn write_str_to(port: ComPort, s: &str) {
     }
 }
 
+/// Write the kernel packet capture to a COM port as a libpcap file
+///
+/// Meant for a port the host logs to a file (e.g. QEMU
+/// `-serial file:capture.pcap`), which then opens in Wireshark.
+pub fn dump_pcap(port: ComPort) {
+    for byte in rinux_kernel::net::pcap::dump() {
+        write_byte_to(port, byte);
+    }
+}
+
 /// Read a byte from serial port
 pub fn read_byte() -> Option<u8> {
     read_byte_from(ComPort::COM1)
@@ -2,8 +2,8 @@
 //!
 //! Programmable Interval Timer (PIT) driver for x86.
 
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use rinux_arch_x86::io::outb;
-use spin::Mutex;
 
 /// PIT frequency (1.193182 MHz)
 const PIT_FREQUENCY: u32 = 1193182;
@@ -13,16 +13,21 @@ const PIT_COMMAND: u16 = 0x43;
 /// PIT channel 0 data port
 const PIT_CHANNEL_0: u16 = 0x40;
 
+/// PIT interrupt line
+const PIT_IRQ: u8 = 0;
+
 /// Global timer state
-static TIMER: Mutex<Timer> = Mutex::new(Timer {
-    ticks: 0,
-    frequency: 0,
-});
+static TIMER: Timer = Timer {
+    ticks: AtomicU64::new(0),
+    frequency: AtomicU32::new(0),
+};
 
 /// Timer structure
+///
+/// Atomic so the interrupt handler never contends with readers.
 pub struct Timer {
-    ticks: u64,
-    frequency: u32,
+    ticks: AtomicU64,
+    frequency: AtomicU32,
 }
 
 impl Timer {
@@ -31,14 +36,14 @@ impl Timer {
     /// # Safety
     ///
     /// Performs I/O port operations.
-    unsafe fn init(&mut self, frequency: u32) {
+    unsafe fn init(&self, frequency: u32) -> bool {
         if frequency == 0 || frequency > PIT_FREQUENCY {
             rinux_kernel::printk::printk("  [ERROR] Invalid PIT frequency: ");
             rinux_kernel::printk::printk("must be between 1 and 1193182 Hz\n");
-            return;
+            return false;
         }
 
-        self.frequency = frequency;
+        self.frequency.store(frequency, Ordering::Relaxed);
         let divisor = PIT_FREQUENCY / frequency;
 
         // Send command: channel 0, access mode lo/hi byte, rate generator
@@ -47,21 +52,22 @@ impl Timer {
         // Send divisor
         outb(PIT_CHANNEL_0, (divisor & 0xFF) as u8);
         outb(PIT_CHANNEL_0, ((divisor >> 8) & 0xFF) as u8);
+        true
     }
 
     /// Handle timer interrupt
-    fn tick(&mut self) {
-        self.ticks = self.ticks.wrapping_add(1);
+    fn tick(&self) {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
     }
 
     /// Get current tick count
     fn get_ticks(&self) -> u64 {
-        self.ticks
+        self.ticks.load(Ordering::Relaxed)
     }
 
     /// Get timer frequency
     fn get_frequency(&self) -> u32 {
-        self.frequency
+        self.frequency.load(Ordering::Relaxed)
     }
 }
 
@@ -69,44 +75,51 @@ impl Timer {
 ///
 /// Common values: 100 Hz (10ms), 1000 Hz (1ms)
 pub fn init(frequency: u32) {
-    let mut timer = TIMER.lock();
-    unsafe {
-        timer.init(frequency);
+    if unsafe { TIMER.init(frequency) }
+        && rinux_arch_x86::interrupts::register_handler(PIT_IRQ, irq_handler).is_err()
+    {
+        rinux_kernel::printk::printk("  [ERROR] Failed to attach PIT interrupt\n");
+    }
+}
+
+/// IRQ0 handler: count the tick and advance kernel uptime
+fn irq_handler(_irq: u8) {
+    tick();
+    let frequency = TIMER.get_frequency();
+    if frequency != 0 {
+        rinux_kernel::time::tick((1000 / frequency as u64).max(1));
     }
 }
 
 /// Handle timer interrupt (call from IRQ handler)
 pub fn tick() {
-    let mut timer = TIMER.lock();
-    timer.tick();
+    TIMER.tick();
 }
 
 /// Get current tick count
 pub fn get_ticks() -> u64 {
-    let timer = TIMER.lock();
-    timer.get_ticks()
+    TIMER.get_ticks()
 }
 
 /// Get timer frequency
 pub fn get_frequency() -> u32 {
-    let timer = TIMER.lock();
-    timer.get_frequency()
+    TIMER.get_frequency()
 }
 
 /// Get uptime in milliseconds (approximate)
 pub fn get_uptime_ms() -> u64 {
-    let timer = TIMER.lock();
-    if timer.frequency == 0 {
+    let frequency = get_frequency();
+    if frequency == 0 {
         return 0;
     }
-    (timer.ticks * 1000) / timer.frequency as u64
+    (get_ticks() * 1000) / frequency as u64
 }
 
 /// Get uptime in seconds (approximate)
 pub fn get_uptime_secs() -> u64 {
-    let timer = TIMER.lock();
-    if timer.frequency == 0 {
+    let frequency = get_frequency();
+    if frequency == 0 {
         return 0;
     }
-    timer.ticks / timer.frequency as u64
+    get_ticks() / frequency as u64
 }
@@ -0,0 +1,382 @@
+//! Virtio PCI Transport
+//!
+//! Common support for virtio devices on the PCI bus. Both the legacy
+//! interface (registers in I/O BAR0) and the modern one (register blocks
+//! located through vendor capabilities) are handled, so the same driver
+//! works with transitional and virtio 1.0 devices.
+
+pub mod queue;
+
+use core::ptr::{read_volatile, write_volatile};
+use rinux_arch_x86::io::{inb, inl, inw, outb, outl, outw};
+use rinux_arch_x86::memory::phys_to_virt;
+
+use crate::pci::PciDevice;
+use queue::VirtQueue;
+
+/// Virtio PCI vendor ID
+pub const VENDOR_VIRTIO: u16 = 0x1AF4;
+
+// Device status bits
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+pub const STATUS_DRIVER: u8 = 2;
+pub const STATUS_DRIVER_OK: u8 = 4;
+pub const STATUS_FEATURES_OK: u8 = 8;
+pub const STATUS_FAILED: u8 = 128;
+
+// Device-independent feature bits
+pub const F_ANY_LAYOUT: u64 = 1 << 27;
+pub const F_VERSION_1: u64 = 1 << 32;
+
+// Legacy I/O register offsets
+const LEGACY_HOST_FEATURES: u16 = 0x00;
+const LEGACY_GUEST_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_PFN: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0C;
+const LEGACY_QUEUE_SELECT: u16 = 0x0E;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_STATUS: u16 = 0x12;
+const LEGACY_ISR: u16 = 0x13;
+const LEGACY_CONFIG: u16 = 0x14;
+
+// Modern common configuration offsets
+const COMMON_DFSELECT: u64 = 0x00;
+const COMMON_DF: u64 = 0x04;
+const COMMON_GFSELECT: u64 = 0x08;
+const COMMON_GF: u64 = 0x0C;
+const COMMON_STATUS: u64 = 0x14;
+const COMMON_Q_SELECT: u64 = 0x16;
+const COMMON_Q_SIZE: u64 = 0x18;
+const COMMON_Q_ENABLE: u64 = 0x1C;
+const COMMON_Q_NOFF: u64 = 0x1E;
+const COMMON_Q_DESC: u64 = 0x20;
+const COMMON_Q_DRIVER: u64 = 0x28;
+const COMMON_Q_DEVICE: u64 = 0x30;
+
+// Vendor capability
+const PCI_CAP_VENDOR: u8 = 0x09;
+const CAP_COMMON_CFG: u8 = 1;
+const CAP_NOTIFY_CFG: u8 = 2;
+const CAP_ISR_CFG: u8 = 3;
+const CAP_DEVICE_CFG: u8 = 4;
+
+/// Largest queue set up on modern devices
+///
+/// Legacy devices fix the queue size themselves.
+const MAX_QUEUE_SIZE: u16 = 256;
+
+/// Virtio errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtioError {
+    /// No usable register interface on the device
+    NoTransport,
+    /// Queue does not exist
+    NoQueue,
+    /// Device did not accept the negotiated features
+    FeaturesRejected,
+    /// Device lacks a required feature
+    NotSupported,
+    /// Out of DMA memory
+    NoMemory,
+}
+
+/// Register interface of a virtio PCI device
+pub enum Transport {
+    /// Legacy interface in an I/O BAR
+    Legacy { io_base: u16 },
+    /// Virtio 1.0 interface in memory BARs (virtual addresses)
+    Modern {
+        common: u64,
+        notify: u64,
+        notify_multiplier: u32,
+        isr: u64,
+        device: u64,
+    },
+}
+
+impl Transport {
+    /// Locate the register interface of a device
+    ///
+    /// The modern interface is preferred when a transitional device offers
+    /// both.
+    pub fn probe(pci_device: &PciDevice) -> Result<Self, VirtioError> {
+        if let Some(transport) = Self::probe_modern(pci_device) {
+            pci_device.enable_memory_space();
+            pci_device.enable_bus_mastering();
+            return Ok(transport);
+        }
+
+        let io_base = pci_device.io_bar(0).ok_or(VirtioError::NoTransport)?;
+        pci_device.enable_io_space();
+        pci_device.enable_bus_mastering();
+        Ok(Transport::Legacy { io_base })
+    }
+
+    fn probe_modern(pci_device: &PciDevice) -> Option<Self> {
+        let mut common = None;
+        let mut notify = None;
+        let mut isr = None;
+        let mut device = None;
+
+        for (id, cap) in pci_device.capabilities() {
+            if id != PCI_CAP_VENDOR {
+                continue;
+            }
+
+            let cfg_type = pci_device.read_config_u8(cap + 3);
+            let bar = pci_device.read_config_u8(cap + 4) as usize;
+            let offset = u64::from(pci_device.read_config(cap + 8));
+            let Some(base) = pci_device.memory_bar(bar) else {
+                continue;
+            };
+            let address = phys_to_virt(base + offset);
+
+            // The first capability of each type is the preferred one
+            match cfg_type {
+                CAP_COMMON_CFG => {
+                    common.get_or_insert(address);
+                }
+                CAP_NOTIFY_CFG => {
+                    let multiplier = pci_device.read_config(cap + 16);
+                    notify.get_or_insert((address, multiplier));
+                }
+                CAP_ISR_CFG => {
+                    isr.get_or_insert(address);
+                }
+                CAP_DEVICE_CFG => {
+                    device.get_or_insert(address);
+                }
+                _ => {}
+            }
+        }
+
+        let (notify, notify_multiplier) = notify?;
+        Some(Transport::Modern {
+            common: common?,
+            notify,
+            notify_multiplier,
+            isr: isr?,
+            device: device?,
+        })
+    }
+
+    /// Check if the device uses the virtio 1.0 interface
+    pub fn is_modern(&self) -> bool {
+        matches!(self, Transport::Modern { .. })
+    }
+
+    /// Reset the device
+    pub fn reset(&self) {
+        self.set_status(0);
+        // Modern devices finish the reset before status reads back as 0
+        while self.status() != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Read the device status
+    pub fn status(&self) -> u8 {
+        match *self {
+            Transport::Legacy { io_base } => unsafe { inb(io_base + LEGACY_STATUS) },
+            Transport::Modern { common, .. } => unsafe {
+                read_volatile((common + COMMON_STATUS) as *const u8)
+            },
+        }
+    }
+
+    /// Write the device status
+    pub fn set_status(&self, status: u8) {
+        match *self {
+            Transport::Legacy { io_base } => unsafe { outb(io_base + LEGACY_STATUS, status) },
+            Transport::Modern { common, .. } => unsafe {
+                write_volatile((common + COMMON_STATUS) as *mut u8, status)
+            },
+        }
+    }
+
+    /// Set status bits, keeping the ones already set
+    pub fn add_status(&self, bits: u8) {
+        self.set_status(self.status() | bits);
+    }
+
+    /// Read the features offered by the device
+    pub fn device_features(&self) -> u64 {
+        match *self {
+            Transport::Legacy { io_base } => {
+                u64::from(unsafe { inl(io_base + LEGACY_HOST_FEATURES) })
+            }
+            Transport::Modern { common, .. } => unsafe {
+                let mut features = 0;
+                for select in 0..2u32 {
+                    write_volatile((common + COMMON_DFSELECT) as *mut u32, select);
+                    let word = read_volatile((common + COMMON_DF) as *const u32);
+                    features |= u64::from(word) << (32 * select);
+                }
+                features
+            },
+        }
+    }
+
+    /// Acknowledge the device and agree on features
+    ///
+    /// Accepts the subset of `wanted` the device offers and returns it.
+    /// The device is left ready for queue setup.
+    pub fn negotiate(&self, wanted: u64) -> Result<u64, VirtioError> {
+        self.reset();
+        self.add_status(STATUS_ACKNOWLEDGE);
+        self.add_status(STATUS_DRIVER);
+
+        let features = self.device_features() & wanted;
+        match *self {
+            Transport::Legacy { io_base } => unsafe {
+                outl(io_base + LEGACY_GUEST_FEATURES, features as u32);
+            },
+            Transport::Modern { common, .. } => {
+                if features & F_VERSION_1 == 0 {
+                    self.add_status(STATUS_FAILED);
+                    return Err(VirtioError::NotSupported);
+                }
+                unsafe {
+                    for select in 0..2u32 {
+                        write_volatile((common + COMMON_GFSELECT) as *mut u32, select);
+                        write_volatile(
+                            (common + COMMON_GF) as *mut u32,
+                            (features >> (32 * select)) as u32,
+                        );
+                    }
+                }
+
+                self.add_status(STATUS_FEATURES_OK);
+                if self.status() & STATUS_FEATURES_OK == 0 {
+                    self.add_status(STATUS_FAILED);
+                    return Err(VirtioError::FeaturesRejected);
+                }
+            }
+        }
+
+        Ok(features)
+    }
+
+    /// Tell the device the driver is ready
+    pub fn driver_ok(&self) {
+        self.add_status(STATUS_DRIVER_OK);
+    }
+
+    /// Mark the device as failed
+    pub fn fail(&self) {
+        self.add_status(STATUS_FAILED);
+    }
+
+    /// Get the size of a queue, or 0 if it does not exist
+    pub fn queue_size(&self, index: u16) -> u16 {
+        match *self {
+            Transport::Legacy { io_base } => unsafe {
+                outw(io_base + LEGACY_QUEUE_SELECT, index);
+                inw(io_base + LEGACY_QUEUE_SIZE)
+            },
+            Transport::Modern { common, .. } => unsafe {
+                write_volatile((common + COMMON_Q_SELECT) as *mut u16, index);
+                let size = read_volatile((common + COMMON_Q_SIZE) as *const u16);
+                size.min(MAX_QUEUE_SIZE)
+            },
+        }
+    }
+
+    /// Allocate a queue and hand it to the device
+    pub fn setup_queue(&self, index: u16) -> Result<VirtQueue, VirtioError> {
+        let size = self.queue_size(index);
+        if size == 0 {
+            return Err(VirtioError::NoQueue);
+        }
+
+        let mut queue = VirtQueue::new(index, size)?;
+        match *self {
+            Transport::Legacy { io_base } => unsafe {
+                outw(io_base + LEGACY_QUEUE_SELECT, index);
+                outl(
+                    io_base + LEGACY_QUEUE_PFN,
+                    (queue.desc_addr() >> queue::PAGE_SHIFT) as u32,
+                );
+            },
+            Transport::Modern { common, .. } => unsafe {
+                write_volatile((common + COMMON_Q_SELECT) as *mut u16, index);
+                write_volatile((common + COMMON_Q_SIZE) as *mut u16, size);
+                write_u64(common + COMMON_Q_DESC, queue.desc_addr());
+                write_u64(common + COMMON_Q_DRIVER, queue.avail_addr());
+                write_u64(common + COMMON_Q_DEVICE, queue.used_addr());
+                let notify_off = read_volatile((common + COMMON_Q_NOFF) as *const u16);
+                queue.set_notify_offset(notify_off);
+                write_volatile((common + COMMON_Q_ENABLE) as *mut u16, 1);
+            },
+        }
+
+        Ok(queue)
+    }
+
+    /// Tell the device a queue has new buffers
+    pub fn notify(&self, queue: &VirtQueue) {
+        match *self {
+            Transport::Legacy { io_base } => unsafe {
+                outw(io_base + LEGACY_QUEUE_NOTIFY, queue.index());
+            },
+            Transport::Modern {
+                notify,
+                notify_multiplier,
+                ..
+            } => unsafe {
+                let offset = u64::from(queue.notify_offset()) * u64::from(notify_multiplier);
+                write_volatile((notify + offset) as *mut u16, queue.index());
+            },
+        }
+    }
+
+    /// Read and acknowledge the interrupt status
+    pub fn isr_status(&self) -> u8 {
+        match *self {
+            Transport::Legacy { io_base } => unsafe { inb(io_base + LEGACY_ISR) },
+            Transport::Modern { isr, .. } => unsafe { read_volatile(isr as *const u8) },
+        }
+    }
+
+    /// Read a byte of device-specific configuration
+    pub fn read_config_u8(&self, offset: u16) -> u8 {
+        match *self {
+            Transport::Legacy { io_base } => unsafe { inb(io_base + LEGACY_CONFIG + offset) },
+            Transport::Modern { device, .. } => unsafe {
+                read_volatile((device + u64::from(offset)) as *const u8)
+            },
+        }
+    }
+
+    /// Read a 16-bit field of device-specific configuration
+    pub fn read_config_u16(&self, offset: u16) -> u16 {
+        match *self {
+            Transport::Legacy { io_base } => unsafe { inw(io_base + LEGACY_CONFIG + offset) },
+            Transport::Modern { device, .. } => unsafe {
+                read_volatile((device + u64::from(offset)) as *const u16)
+            },
+        }
+    }
+
+    /// Write a byte of device-specific configuration
+    pub fn write_config_u8(&self, offset: u16, value: u8) {
+        match *self {
+            Transport::Legacy { io_base } => unsafe {
+                outb(io_base + LEGACY_CONFIG + offset, value)
+            },
+            Transport::Modern { device, .. } => unsafe {
+                write_volatile((device + u64::from(offset)) as *mut u8, value)
+            },
+        }
+    }
+}
+
+/// Write a 64-bit register as two 32-bit halves
+///
+/// # Safety
+///
+/// `address` must be a mapped device register.
+unsafe fn write_u64(address: u64, value: u64) {
+    write_volatile(address as *mut u32, value as u32);
+    write_volatile((address + 4) as *mut u32, (value >> 32) as u32);
+}
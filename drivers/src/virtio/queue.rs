@@ -0,0 +1,229 @@
+//! Split Virtqueues
+//!
+//! A virtqueue is a descriptor table, an available ring the driver fills
+//! and a used ring the device returns buffers through. All three live in
+//! one physically contiguous region laid out as legacy devices expect,
+//! with the used ring on its own page; modern devices accept the same
+//! layout.
+//!
+//! Drivers here point each descriptor at a fixed buffer once, then hand
+//! descriptors back and forth by ID.
+
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+use rinux_arch_x86::memory::phys_to_virt;
+use rinux_mm::frame::{self, Frame};
+
+use super::VirtioError;
+
+/// Page size used for queue alignment
+pub const PAGE_SIZE: usize = 4096;
+
+/// Log2 of the page size (legacy queue addresses are page numbers)
+pub const PAGE_SHIFT: u32 = 12;
+
+/// Descriptor continues in the `next` field
+pub const DESC_F_NEXT: u16 = 1;
+/// Buffer is written by the device
+pub const DESC_F_WRITE: u16 = 2;
+
+/// Driver does not need interrupts (available ring flag)
+const AVAIL_F_NO_INTERRUPT: u16 = 1;
+/// Device does not need notifications (used ring flag)
+const USED_F_NO_NOTIFY: u16 = 1;
+
+/// Size of a descriptor
+const DESC_SIZE: usize = 16;
+
+/// Physically contiguous, zeroed memory shared with a device
+pub struct DmaRegion {
+    first: Frame,
+    frames: usize,
+    virt_addr: u64,
+}
+
+impl DmaRegion {
+    /// Allocate a region of at least `size` bytes
+    pub fn new(size: usize) -> Result<Self, VirtioError> {
+        let frames = size.div_ceil(PAGE_SIZE).max(1);
+        let first = frame::allocate_contiguous(frames).ok_or(VirtioError::NoMemory)?;
+        let virt_addr = phys_to_virt(first.start_address());
+
+        unsafe {
+            core::ptr::write_bytes(virt_addr as *mut u8, 0, frames * PAGE_SIZE);
+        }
+
+        Ok(Self {
+            first,
+            frames,
+            virt_addr,
+        })
+    }
+
+    /// Get the physical address of a byte in the region
+    pub fn phys_addr(&self, offset: usize) -> u64 {
+        self.first.start_address() + offset as u64
+    }
+
+    /// Get a pointer to a byte in the region
+    pub fn as_ptr(&self, offset: usize) -> *mut u8 {
+        (self.virt_addr + offset as u64) as *mut u8
+    }
+
+    /// Get the size of the region in bytes
+    pub fn len(&self) -> usize {
+        self.frames * PAGE_SIZE
+    }
+
+    /// Check if the region is empty (never true once allocated)
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+}
+
+impl Drop for DmaRegion {
+    fn drop(&mut self) {
+        frame::deallocate_contiguous(self.first, self.frames);
+    }
+}
+
+/// Split virtqueue
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    region: DmaRegion,
+    avail_offset: usize,
+    used_offset: usize,
+    /// Next free slot of the available ring
+    avail_idx: u16,
+    /// Next used ring entry to consume
+    last_used: u16,
+    /// Notification offset (modern devices)
+    notify_offset: u16,
+}
+
+impl VirtQueue {
+    /// Allocate a queue of `size` descriptors
+    pub fn new(index: u16, size: u16) -> Result<Self, VirtioError> {
+        let entries = size as usize;
+        let avail_offset = DESC_SIZE * entries;
+        let used_offset = (avail_offset + 6 + 2 * entries).next_multiple_of(PAGE_SIZE);
+        let total = used_offset + 6 + 8 * entries;
+
+        let queue = Self {
+            index,
+            size,
+            region: DmaRegion::new(total)?,
+            avail_offset,
+            used_offset,
+            avail_idx: 0,
+            last_used: 0,
+            notify_offset: 0,
+        };
+
+        // Buffers are reaped by polling
+        queue.write_u16(avail_offset, AVAIL_F_NO_INTERRUPT);
+        Ok(queue)
+    }
+
+    /// Get the queue index
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Get the number of descriptors
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Physical address of the descriptor table
+    pub fn desc_addr(&self) -> u64 {
+        self.region.phys_addr(0)
+    }
+
+    /// Physical address of the available ring
+    pub fn avail_addr(&self) -> u64 {
+        self.region.phys_addr(self.avail_offset)
+    }
+
+    /// Physical address of the used ring
+    pub fn used_addr(&self) -> u64 {
+        self.region.phys_addr(self.used_offset)
+    }
+
+    /// Get the notification offset
+    pub fn notify_offset(&self) -> u16 {
+        self.notify_offset
+    }
+
+    /// Set the notification offset read from the device
+    pub fn set_notify_offset(&mut self, offset: u16) {
+        self.notify_offset = offset;
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        unsafe { read_volatile(self.region.as_ptr(offset) as *const u16) }
+    }
+
+    fn write_u16(&self, offset: usize, value: u16) {
+        unsafe { write_volatile(self.region.as_ptr(offset) as *mut u16, value) }
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile(self.region.as_ptr(offset) as *const u32) }
+    }
+
+    fn write_u32(&self, offset: usize, value: u32) {
+        unsafe { write_volatile(self.region.as_ptr(offset) as *mut u32, value) }
+    }
+
+    /// Point a descriptor at a buffer
+    pub fn set_descriptor(&mut self, id: u16, addr: u64, len: u32, flags: u16) {
+        let offset = DESC_SIZE * id as usize;
+        unsafe {
+            write_volatile(self.region.as_ptr(offset) as *mut u64, addr);
+        }
+        self.write_u32(offset + 8, len);
+        self.write_u16(offset + 12, flags);
+        self.write_u16(offset + 14, 0);
+    }
+
+    /// Offer a descriptor's buffer to the device
+    ///
+    /// `len` replaces the descriptor length, so transmit buffers can
+    /// carry frames of any size.
+    pub fn submit(&mut self, id: u16, len: u32) {
+        self.write_u32(DESC_SIZE * id as usize + 8, len);
+
+        let slot = self.avail_idx % self.size;
+        self.write_u16(self.avail_offset + 4 + 2 * slot as usize, id);
+        // The ring entry must be visible before the index moves past it
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        self.write_u16(self.avail_offset + 2, self.avail_idx);
+        fence(Ordering::SeqCst);
+    }
+
+    /// Take the next buffer the device has finished with
+    ///
+    /// Returns the descriptor ID and the number of bytes written, both as
+    /// reported by the device and not yet checked.
+    pub fn pop_used(&mut self) -> Option<(u32, u32)> {
+        if self.read_u16(self.used_offset + 2) == self.last_used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+
+        let slot = self.last_used % self.size;
+        let entry = self.used_offset + 4 + 8 * slot as usize;
+        let id = self.read_u32(entry);
+        let len = self.read_u32(entry + 4);
+        self.last_used = self.last_used.wrapping_add(1);
+        Some((id, len))
+    }
+
+    /// Check if the device wants to be notified of new buffers
+    pub fn needs_notify(&self) -> bool {
+        self.read_u16(self.used_offset) & USED_F_NO_NOTIFY == 0
+    }
+}
@@ -5,8 +5,11 @@
 
 use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 use spin::Mutex;
 
+use crate::net::ipv4::Ipv4Addr;
+
 /// Maximum command line length
 const MAX_CMDLINE_LEN: usize = 4096;
 
@@ -155,6 +158,107 @@ pub fn mem_limit() -> Option<u64> {
     get("mem").and_then(|s| parse_size(&s))
 }
 
+/// How the network interface is configured at boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpAutoconf {
+    /// Use the addresses given on the command line
+    Off,
+    /// Obtain a lease with DHCP
+    Dhcp,
+}
+
+/// Network configuration from the `ip=` parameter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpConfig {
+    /// Static address
+    pub address: Option<Ipv4Addr>,
+    /// Default gateway
+    pub gateway: Option<Ipv4Addr>,
+    /// Subnet mask
+    pub netmask: Option<Ipv4Addr>,
+    /// Device to configure, or the default device
+    pub device: Option<String>,
+    /// Configuration method
+    pub autoconf: IpAutoconf,
+    /// DNS servers
+    pub nameservers: Vec<Ipv4Addr>,
+}
+
+/// Get the network configuration
+///
+/// Accepts the Linux forms `ip=dhcp`, `ip=off` and
+/// `ip=<client>:<server>:<gateway>:<netmask>:<hostname>:<device>:<autoconf>:<dns0>:<dns1>`,
+/// where trailing fields may be omitted. Returns `None` if the parameter is
+/// absent, disables configuration or is malformed.
+pub fn ip_config() -> Option<IpConfig> {
+    get("ip").and_then(|value| parse_ip_config(&value))
+}
+
+/// Get the packet capture buffer size in bytes
+///
+/// `pcap` alone enables capture with the default buffer size (`Some(0)`);
+/// `pcap=<size>` takes a size like `mem=`.
+pub fn pcap_buffer_size() -> Option<u64> {
+    match get("pcap")? {
+        value if value.is_empty() => Some(0),
+        value => parse_size(&value),
+    }
+}
+
+/// Check if IPv4 forwarding between interfaces is enabled
+///
+/// Given as `ip_forward` or `ip_forward=1`.
+pub fn ip_forward() -> bool {
+    get("ip_forward").is_some_and(|value| value.is_empty() || value == "1")
+}
+
+/// Parse the value of the `ip=` parameter
+fn parse_ip_config(value: &str) -> Option<IpConfig> {
+    let fields: Vec<&str> = value.split(':').collect();
+    if fields.len() > 9 {
+        return None;
+    }
+
+    let field = |index: usize| fields.get(index).copied().filter(|f| !f.is_empty());
+    // Empty fields are `Some(None)`, malformed ones `None`
+    let addr = |index: usize| match field(index) {
+        Some(f) => f.parse::<Ipv4Addr>().ok().map(Some),
+        None => Some(None),
+    };
+
+    // A lone autoconf method, as in `ip=dhcp`
+    let method = if fields.len() == 1 {
+        field(0)
+    } else {
+        field(6)
+    };
+    let address = if fields.len() == 1 { None } else { addr(0)? };
+
+    let autoconf = match method {
+        Some("off") | Some("none") if address.is_some() => IpAutoconf::Off,
+        Some("dhcp") | Some("on") | Some("any") => IpAutoconf::Dhcp,
+        None if address.is_some() => IpAutoconf::Off,
+        None => IpAutoconf::Dhcp,
+        Some(_) => return None,
+    };
+
+    let mut nameservers = Vec::new();
+    for index in [7, 8] {
+        if let Some(server) = addr(index)? {
+            nameservers.push(server);
+        }
+    }
+
+    Some(IpConfig {
+        address,
+        gateway: addr(2)?,
+        netmask: addr(3)?,
+        device: field(5).map(|f| f.to_string()),
+        autoconf,
+        nameservers,
+    })
+}
+
 /// Parse size strings like "256M", "1G", "512K"
 fn parse_size(s: &str) -> Option<u64> {
     if s.is_empty() {
@@ -208,6 +312,30 @@ mod tests {
         assert_eq!(parse_size("100"), Some(100));
     }
 
+    #[test]
+    fn test_parse_ip_config() {
+        let dhcp = parse_ip_config("dhcp").unwrap();
+        assert_eq!(dhcp.autoconf, IpAutoconf::Dhcp);
+        assert_eq!(dhcp.address, None);
+        assert_eq!(parse_ip_config("off"), None);
+
+        let fixed =
+            parse_ip_config("10.0.2.15::10.0.2.2:255.255.255.0::eth0:off:10.0.2.3").unwrap();
+        assert_eq!(fixed.autoconf, IpAutoconf::Off);
+        assert_eq!(fixed.address, Some(Ipv4Addr::new(10, 0, 2, 15)));
+        assert_eq!(fixed.gateway, Some(Ipv4Addr::new(10, 0, 2, 2)));
+        assert_eq!(fixed.netmask, Some(Ipv4Addr::new(255, 255, 255, 0)));
+        assert_eq!(fixed.device.as_deref(), Some("eth0"));
+        assert_eq!(fixed.nameservers, [Ipv4Addr::new(10, 0, 2, 3)]);
+
+        let on_device = parse_ip_config(":::::eth1:dhcp").unwrap();
+        assert_eq!(on_device.autoconf, IpAutoconf::Dhcp);
+        assert_eq!(on_device.device.as_deref(), Some("eth1"));
+
+        assert_eq!(parse_ip_config("10.0.2.300::::::off"), None);
+        assert_eq!(parse_ip_config("bootp"), None);
+    }
+
     #[test]
     fn test_init_and_get() {
         init("root=/dev/sda1 ro quiet");
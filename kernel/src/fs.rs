@@ -5,13 +5,18 @@
 pub mod fd;
 pub mod file;
 pub mod filesystems;
+pub mod mount;
+pub mod path;
 pub mod vfs;
 
 pub use fd::{FileDescriptor, FileDescriptorTable};
 pub use file::{File, FileMode, FileType};
-pub use vfs::{VfsNode, VfsNodeType};
+pub use vfs::{FileAttr, FsError, VfsNode, VfsNodeType};
 
+use alloc::sync::Arc;
+use alloc::vec;
 use core::sync::atomic::{AtomicBool, Ordering};
+use vfs::VNode;
 
 static FS_INITIALIZED: AtomicBool = AtomicBool::new(false);
 
@@ -31,10 +36,34 @@ pub mod flags {
     pub const O_TRUNC: i32 = 0x0400;
     /// Append to end of file
     pub const O_APPEND: i32 = 0x0800;
+    /// Do not block on reads and writes
+    pub const O_NONBLOCK: i32 = 0x1000;
+    /// Do not update the access time on reads
+    pub const O_NOATIME: i32 = 0x40000;
+    /// Close the descriptor on exec
+    pub const O_CLOEXEC: i32 = 0x80000;
 }
 
-/// Open a file via the VFS (backed by tmpfs)
-pub fn open_file(pathname: &str, flags: i32, _mode: u32) -> Result<FileDescriptor, isize> {
+/// Seek relative to the start of the file
+pub const SEEK_SET: i32 = 0;
+/// Seek relative to the current position
+pub const SEEK_CUR: i32 = 1;
+/// Seek relative to the end of the file
+pub const SEEK_END: i32 = 2;
+
+/// Size of the kernel bounce buffer used for file I/O
+const IO_CHUNK: usize = 4096;
+
+/// Fail with `EROFS` if a canonical path lies on a read-only mount
+fn check_writable(path: &str) -> Result<(), isize> {
+    match mount::get_mount_flags(path) {
+        Some(flags) if flags.readonly => Err(crate::syscall::errno::EROFS),
+        _ => Ok(()),
+    }
+}
+
+/// Open a file via the VFS
+pub fn open_file(pathname: &str, flags: i32, mode: u32) -> Result<FileDescriptor, isize> {
     use crate::syscall::errno;
 
     if !is_initialized() {
@@ -42,54 +71,83 @@ pub fn open_file(pathname: &str, flags: i32, _mode: u32) -> Result<FileDescripto
     }
 
     // Parse flags to determine access mode
-    let mode = match flags & 0x3 {
+    let access = match flags & 0x3 {
         flags::O_RDONLY => FileMode::read_only(),
         flags::O_WRONLY => FileMode::write_only(),
         flags::O_RDWR => FileMode::read_write(),
         _ => return Err(errno::EINVAL),
     };
 
-    // Attempt to look up the file in tmpfs
-    let inode = match filesystems::tmpfs::global_lookup_path(pathname) {
-        Ok(ino) => ino,
-        Err(_) => {
-            // If O_CREAT is set, create the file
-            if (flags & flags::O_CREAT) != 0 {
-                filesystems::tmpfs::global_create_file(pathname).map_err(|_| errno::EIO)?
-            } else {
-                return Err(errno::ENOENT);
+    let (readonly, vnode) = match path::resolve(pathname, true) {
+        Ok((path, vnode)) => {
+            if flags & flags::O_CREAT != 0 && flags & flags::O_EXCL != 0 {
+                return Err(errno::EEXIST);
             }
+            let readonly = check_writable(&path).is_err();
+            if readonly && access.write {
+                return Err(errno::EROFS);
+            }
+            (readonly, vnode)
+        }
+        Err(FsError::NotFound) if flags & flags::O_CREAT != 0 => {
+            let parent = path::lookup_parent(pathname).map_err(FsError::to_errno)?;
+            check_writable(&parent.path)?;
+            let vnode = parent
+                .dir
+                .create(&parent.name, vfs::FileMode::new(mode & 0o7777))
+                .map_err(FsError::to_errno)?;
+            (false, vnode)
         }
+        Err(e) => return Err(e.to_errno()),
     };
 
-    let file = File::new(inode as crate::types::Inode, FileType::Regular, mode);
+    let mut file = File::from_vnode(vnode, access).map_err(FsError::to_errno)?;
+    if file.file_type == FileType::Directory && access.write {
+        return Err(errno::EISDIR);
+    }
+
+    file.flags = flags & !(flags::O_CREAT | flags::O_EXCL | flags::O_TRUNC | flags::O_CLOEXEC);
+    file.readonly_mount = readonly;
 
-    match fd::allocate_fd(file) {
+    if flags & flags::O_TRUNC != 0 && access.write && file.file_type == FileType::Regular {
+        let vnode = file.vnode.as_ref().unwrap();
+        vnode.truncate(0).map_err(FsError::to_errno)?;
+        touch(vnode.as_ref(), false, true);
+        file.size = 0;
+    }
+
+    match fd::allocate_fd(file, flags & flags::O_CLOEXEC != 0) {
         Ok(fd) => Ok(fd),
         Err(_) => Err(errno::EMFILE),
     }
 }
 
 /// Stat a file by path via the VFS
-pub fn stat_file(pathname: &str) -> Result<filesystems::tmpfs::FileStat, isize> {
+pub fn stat_file(pathname: &str) -> Result<FileAttr, isize> {
     use crate::syscall::errno;
 
     if !is_initialized() {
         return Err(errno::EIO);
     }
 
-    filesystems::tmpfs::global_stat(pathname).map_err(|_| errno::ENOENT)
+    let vnode = path::lookup(pathname, true).map_err(FsError::to_errno)?;
+    vnode.getattr().map_err(FsError::to_errno)
 }
 
 /// Create a directory via the VFS
-pub fn mkdir(pathname: &str, _mode: u32) -> Result<(), isize> {
+pub fn mkdir(pathname: &str, mode: u32) -> Result<(), isize> {
     use crate::syscall::errno;
 
     if !is_initialized() {
         return Err(errno::EIO);
     }
 
-    filesystems::tmpfs::global_mkdir(pathname).map_err(|_| errno::EIO)?;
+    let parent = path::lookup_parent(pathname).map_err(FsError::to_errno)?;
+    check_writable(&parent.path)?;
+    parent
+        .dir
+        .mkdir(&parent.name, vfs::FileMode::new(mode & 0o7777))
+        .map_err(FsError::to_errno)?;
     Ok(())
 }
 
@@ -101,7 +159,12 @@ pub fn rmdir(pathname: &str) -> Result<(), isize> {
         return Err(errno::EIO);
     }
 
-    filesystems::tmpfs::global_rmdir(pathname).map_err(|_| errno::ENOENT)
+    let parent = path::lookup_parent(pathname).map_err(FsError::to_errno)?;
+    if mount::is_mount_point(&parent.child_path()) {
+        return Err(errno::EBUSY);
+    }
+    check_writable(&parent.path)?;
+    parent.dir.rmdir(&parent.name).map_err(FsError::to_errno)
 }
 
 /// Unlink (delete) a file via the VFS
@@ -112,7 +175,12 @@ pub fn unlink(pathname: &str) -> Result<(), isize> {
         return Err(errno::EIO);
     }
 
-    filesystems::tmpfs::global_unlink(pathname).map_err(|_| errno::ENOENT)
+    let parent = path::lookup_parent(pathname).map_err(FsError::to_errno)?;
+    if mount::is_mount_point(&parent.child_path()) {
+        return Err(errno::EBUSY);
+    }
+    check_writable(&parent.path)?;
+    parent.dir.unlink(&parent.name).map_err(FsError::to_errno)
 }
 
 /// Rename a file or directory via the VFS
@@ -123,36 +191,177 @@ pub fn rename(old_path: &str, new_path: &str) -> Result<(), isize> {
         return Err(errno::EIO);
     }
 
-    filesystems::tmpfs::global_rename(old_path, new_path).map_err(|_| errno::ENOENT)
+    let old = path::lookup_parent(old_path).map_err(FsError::to_errno)?;
+    let new = path::lookup_parent(new_path).map_err(FsError::to_errno)?;
+    if mount::is_mount_point(&old.child_path()) || mount::is_mount_point(&new.child_path()) {
+        return Err(errno::EBUSY);
+    }
+    if mount::mount_point_of(&old.path) != mount::mount_point_of(&new.path) {
+        return Err(errno::EXDEV);
+    }
+    check_writable(&old.path)?;
+
+    old.dir
+        .rename(&old.name, new.dir, &new.name)
+        .map_err(FsError::to_errno)
 }
 
-/// Read from a file
-pub fn read_file(file: &mut File, buf: *mut u8, count: usize) -> Result<usize, ()> {
+/// Create a symbolic link at `linkpath` pointing to `target`
+pub fn symlink(target: &str, linkpath: &str) -> Result<(), isize> {
+    use crate::syscall::errno;
+
+    if !is_initialized() {
+        return Err(errno::EIO);
+    }
+
+    let parent = path::lookup_parent(linkpath).map_err(FsError::to_errno)?;
+    check_writable(&parent.path)?;
+    parent
+        .dir
+        .symlink(&parent.name, target)
+        .map_err(FsError::to_errno)?;
+    Ok(())
+}
+
+/// Stamp a node's access and/or modification times with the current time
+fn touch(vnode: &dyn VNode, access: bool, modify: bool) {
+    let Ok(mut attr) = vnode.getattr() else {
+        return;
+    };
+    let now = crate::time::uptime_sec();
+    if access {
+        attr.atime = now;
+    }
+    if modify {
+        attr.mtime = now;
+        attr.ctime = now;
+    }
+    let _ = vnode.setattr(&attr);
+}
+
+/// Get the backing node of an open regular file
+fn file_vnode(file: &File) -> Result<Arc<dyn VNode>, isize> {
+    use crate::syscall::errno;
+
+    let vnode = file.vnode.clone().ok_or(errno::EBADF)?;
+    if file.file_type == FileType::Directory {
+        return Err(errno::EISDIR);
+    }
+    Ok(vnode)
+}
+
+/// Read from a file at its position into the user buffer at `buf`
+///
+/// Data is copied out through a kernel bounce buffer, one chunk at a
+/// time, and the position advances by the number of bytes read.
+pub fn read_file(file: &mut File, buf: usize, count: usize) -> Result<usize, isize> {
+    use crate::security::validation::{copy_to_user, validate_user_buffer_mut};
+    use crate::syscall::errno;
+
     if !file.is_readable() {
-        return Err(());
+        return Err(errno::EBADF);
     }
+    let vnode = file_vnode(file)?;
+    validate_user_buffer_mut(buf as *mut u8, count).map_err(|_| errno::EFAULT)?;
+
+    let mut chunk = vec![0u8; count.min(IO_CHUNK)];
+    let mut done = 0;
+    while done < count {
+        let len = (count - done).min(chunk.len());
+        let n = match vnode.read(file.position, &mut chunk[..len]) {
+            Ok(n) => n,
+            Err(_) if done > 0 => break,
+            Err(e) => return Err(e.to_errno()),
+        };
+        if n == 0 {
+            break;
+        }
 
-    // For now, return 0 (EOF) for all reads
-    let _ = (buf, count);
-    Ok(0)
+        // SAFETY: the whole user range was validated above
+        unsafe { copy_to_user(&chunk[..n], (buf + done) as *mut u8) }.map_err(|_| errno::EFAULT)?;
+        file.position += n as u64;
+        done += n;
+        if n < len {
+            break;
+        }
+    }
+
+    // Access times are never written back to a read-only mount
+    if done > 0 && file.flags & flags::O_NOATIME == 0 && !file.readonly_mount {
+        touch(vnode.as_ref(), true, false);
+    }
+    Ok(done)
 }
 
-/// Write to a file
-pub fn write_file(file: &mut File, buf: *const u8, count: usize) -> Result<usize, ()> {
+/// Write to a file at its position from the user buffer at `buf`
+///
+/// With `O_APPEND` every write first moves the position to the end of
+/// the file. A failure after some data was written reports the short
+/// count instead of the error.
+pub fn write_file(file: &mut File, buf: usize, count: usize) -> Result<usize, isize> {
+    use crate::security::validation::{copy_from_user, validate_user_buffer};
+    use crate::syscall::errno;
+
     if !file.is_writable() {
-        return Err(());
+        return Err(errno::EBADF);
+    }
+    let vnode = file_vnode(file)?;
+    validate_user_buffer(buf as *const u8, count).map_err(|_| errno::EFAULT)?;
+
+    if file.is_append() {
+        file.position = vnode.getattr().map_err(FsError::to_errno)?.size;
+    }
+
+    let mut chunk = vec![0u8; count.min(IO_CHUNK)];
+    let mut done = 0;
+    while done < count {
+        let len = (count - done).min(chunk.len());
+        // SAFETY: the whole user range was validated above
+        unsafe { copy_from_user((buf + done) as *const u8, &mut chunk[..len]) }
+            .map_err(|_| errno::EFAULT)?;
+
+        let n = match vnode.write(file.position, &chunk[..len]) {
+            Ok(n) => n,
+            Err(_) if done > 0 => break,
+            Err(e) => return Err(e.to_errno()),
+        };
+        file.position += n as u64;
+        done += n;
+        if n < len {
+            break;
+        }
     }
 
-    // For now, pretend we wrote all bytes
-    let _ = buf;
-    Ok(count)
+    if done > 0 {
+        touch(vnode.as_ref(), false, true);
+    }
+    file.size = file.size.max(file.position);
+    Ok(done)
 }
 
-/// Get a mutable reference to a file by file descriptor
-pub fn get_file_mut(fd: FileDescriptor) -> Option<&'static mut File> {
-    // This is a placeholder - proper implementation needs per-process FD tables
-    let _ = fd;
-    None
+/// Move the position of a file
+///
+/// `SEEK_END` is relative to the current size of the backing node.
+/// Seeking before the start of the file fails with `EINVAL`.
+pub fn seek_file(file: &mut File, offset: i64, whence: i32) -> Result<u64, isize> {
+    use crate::syscall::errno;
+
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => file.position,
+        SEEK_END => match &file.vnode {
+            Some(vnode) => vnode.getattr().map_err(FsError::to_errno)?.size,
+            None => file.size,
+        },
+        _ => return Err(errno::EINVAL),
+    };
+
+    let position = (base as i64).checked_add(offset).ok_or(errno::EINVAL)?;
+    if position < 0 {
+        return Err(errno::EINVAL);
+    }
+    file.position = position as u64;
+    Ok(file.position)
 }
 
 /// Initialize file system subsystem
@@ -162,8 +371,8 @@ pub fn init() {
     }
 
     fd::init();
-    vfs::init();
     filesystems::init();
+    vfs::init();
 
     FS_INITIALIZED.store(true, Ordering::Release);
     crate::printk::printk("  File system subsystem initialized\n");
@@ -173,3 +382,130 @@ pub fn init() {
 pub fn is_initialized() -> bool {
     FS_INITIALIZED.load(Ordering::Acquire)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syscall::errno;
+    use alloc::vec::Vec;
+
+    /// Bring up the descriptor table and a tmpfs root without logging
+    fn setup() {
+        static FS: spin::Once = spin::Once::new();
+        FS.call_once(|| {
+            fd::init();
+            filesystems::tmpfs::init();
+            vfs::init();
+            FS_INITIALIZED.store(true, Ordering::Release);
+        });
+    }
+
+    fn write_all(fd: FileDescriptor, data: &[u8]) {
+        assert_eq!(fd::write_fd(fd, data.as_ptr(), data.len()), Ok(data.len()));
+    }
+
+    fn read_some(fd: FileDescriptor, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        let n = fd::read_fd(fd, buf.as_mut_ptr(), len).unwrap();
+        buf.truncate(n);
+        buf
+    }
+
+    #[test]
+    fn test_read_write_seek() {
+        setup();
+        let fd = open_file("/fs-test-rw", flags::O_CREAT | flags::O_RDWR, 0o644).unwrap();
+
+        write_all(fd, b"hello world");
+        assert_eq!(fd::seek_fd(fd, 0, SEEK_CUR), Ok(11));
+        assert_eq!(fd::seek_fd(fd, 0, SEEK_SET), Ok(0));
+        assert_eq!(read_some(fd, 5), b"hello");
+        assert_eq!(fd::seek_fd(fd, -5, SEEK_END), Ok(6));
+        assert_eq!(read_some(fd, 64), b"world");
+        assert_eq!(read_some(fd, 64), b"");
+        assert_eq!(fd::seek_fd(fd, -1, SEEK_SET), Err(errno::EINVAL));
+        assert_eq!(fd::seek_fd(fd, 0, 7), Err(errno::EINVAL));
+
+        // Writing past the end leaves a zero-filled hole
+        assert_eq!(fd::seek_fd(fd, 2, SEEK_END), Ok(13));
+        write_all(fd, b"!");
+        assert_eq!(stat_file("/fs-test-rw").unwrap().size, 14);
+        fd::seek_fd(fd, 10, SEEK_SET).unwrap();
+        assert_eq!(read_some(fd, 4), b"d\0\0!");
+
+        fd::free_fd(fd).unwrap();
+        assert_eq!(fd::seek_fd(fd, 0, SEEK_SET), Err(errno::EBADF));
+    }
+
+    #[test]
+    fn test_append_and_trunc() {
+        setup();
+        let fd = open_file("/fs-test-append", flags::O_CREAT | flags::O_WRONLY, 0o644).unwrap();
+        write_all(fd, b"first");
+        fd::free_fd(fd).unwrap();
+
+        let fd = open_file("/fs-test-append", flags::O_WRONLY | flags::O_APPEND, 0).unwrap();
+        fd::seek_fd(fd, 0, SEEK_SET).unwrap();
+        write_all(fd, b"+second");
+        assert_eq!(fd::seek_fd(fd, 0, SEEK_CUR), Ok(12));
+        fd::free_fd(fd).unwrap();
+
+        let fd = open_file("/fs-test-append", flags::O_RDONLY, 0).unwrap();
+        assert_eq!(read_some(fd, 64), b"first+second");
+        fd::free_fd(fd).unwrap();
+
+        let fd = open_file("/fs-test-append", flags::O_RDWR | flags::O_TRUNC, 0).unwrap();
+        assert_eq!(stat_file("/fs-test-append").unwrap().size, 0);
+        assert_eq!(read_some(fd, 64), b"");
+        fd::free_fd(fd).unwrap();
+    }
+
+    #[test]
+    fn test_access_checks() {
+        setup();
+        let fd = open_file("/fs-test-access", flags::O_CREAT | flags::O_WRONLY, 0o644).unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            fd::read_fd(fd, buf.as_mut_ptr(), buf.len()),
+            Err(errno::EBADF)
+        );
+
+        // Kernel addresses are never accepted as user buffers
+        let kernel = 0xffff_8000_0000_1000usize as *const u8;
+        assert_eq!(fd::write_fd(fd, kernel, 4), Err(errno::EFAULT));
+        fd::free_fd(fd).unwrap();
+
+        let fd = open_file("/fs-test-access", flags::O_RDONLY, 0).unwrap();
+        assert_eq!(fd::write_fd(fd, buf.as_ptr(), buf.len()), Err(errno::EBADF));
+        fd::free_fd(fd).unwrap();
+
+        mkdir("/fs-test-access-dir", 0o755).unwrap();
+        let fd = open_file("/fs-test-access-dir", flags::O_RDONLY, 0).unwrap();
+        assert_eq!(
+            fd::read_fd(fd, buf.as_mut_ptr(), buf.len()),
+            Err(errno::EISDIR)
+        );
+        fd::free_fd(fd).unwrap();
+    }
+
+    #[test]
+    fn test_timestamps() {
+        setup();
+        let fd = open_file("/fs-test-times", flags::O_CREAT | flags::O_RDWR, 0o644).unwrap();
+        let vnode = path::lookup("/fs-test-times", true).unwrap();
+        let mut attr = vnode.getattr().unwrap();
+        attr.atime = u64::MAX;
+        attr.mtime = u64::MAX;
+        vnode.setattr(&attr).unwrap();
+
+        write_all(fd, b"data");
+        let attr = vnode.getattr().unwrap();
+        assert_eq!(attr.atime, u64::MAX);
+        assert!(attr.mtime <= crate::time::uptime_sec());
+
+        fd::seek_fd(fd, 0, SEEK_SET).unwrap();
+        read_some(fd, 4);
+        assert!(vnode.getattr().unwrap().atime <= crate::time::uptime_sec());
+        fd::free_fd(fd).unwrap();
+    }
+}
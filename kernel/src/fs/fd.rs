@@ -1,9 +1,21 @@
 //! File Descriptor Management
 //!
-//! File descriptor table and allocation.
+//! Per-process file descriptor tables. A descriptor refers to an open file
+//! description, which is shared by every descriptor duplicated from it by
+//! `dup`, `fork` or `SCM_RIGHTS`, so they move one offset and see the same
+//! status flags. Close-on-exec belongs to the descriptor itself.
+//!
+//! The descriptor calls below work on the current task's table, or on the
+//! kernel's own table while no task is running.
 
 use super::file::File;
+use super::flags;
+use crate::net::socket;
+use crate::syscall::errno;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
 
 /// File descriptor type
@@ -14,17 +26,173 @@ pub const STDIN_FILENO: FileDescriptor = 0;
 pub const STDOUT_FILENO: FileDescriptor = 1;
 pub const STDERR_FILENO: FileDescriptor = 2;
 
+/// Maximum number of descriptors in one table
+pub const MAX_FDS: usize = 1024;
+
+/// Descriptor flag: close the descriptor on exec
+pub const FD_CLOEXEC: i32 = 1;
+
+/// fcntl: duplicate to the lowest free descriptor at or above `arg`
+pub const F_DUPFD: i32 = 0;
+/// fcntl: get descriptor flags
+pub const F_GETFD: i32 = 1;
+/// fcntl: set descriptor flags
+pub const F_SETFD: i32 = 2;
+/// fcntl: get status flags of the open file
+pub const F_GETFL: i32 = 3;
+/// fcntl: set status flags of the open file
+pub const F_SETFL: i32 = 4;
+/// fcntl: like `F_DUPFD`, with close-on-exec set on the new descriptor
+pub const F_DUPFD_CLOEXEC: i32 = 1030;
+
+/// Status flags `F_SETFL` may change
+const SETFL_MASK: i32 = flags::O_APPEND | flags::O_NONBLOCK | flags::O_NOATIME;
+
+/// Open file description shared by duplicated descriptors
+pub type OpenFile = Arc<Mutex<File>>;
+
 /// File descriptor table entry
+#[derive(Clone)]
 pub enum FdEntry {
     /// Empty slot
     Empty,
     /// Open file
-    File(File),
+    File(OpenFile),
+    /// Open socket (handle in the socket table)
+    Socket(i32),
+}
+
+/// Descriptor table slot
+#[derive(Clone)]
+struct FdSlot {
+    entry: FdEntry,
+    cloexec: bool,
+}
+
+impl FdSlot {
+    const EMPTY: FdSlot = FdSlot {
+        entry: FdEntry::Empty,
+        cloexec: false,
+    };
+}
+
+/// Number of references per socket handle, from descriptor tables and
+/// messages in flight
+static SOCKET_REFS: Mutex<BTreeMap<i32, usize>> = Mutex::new(BTreeMap::new());
+
+/// Number of references per socket handle held by messages in flight
+static SOCKET_IN_FLIGHT: Mutex<BTreeMap<i32, usize>> = Mutex::new(BTreeMap::new());
+
+/// Set while unreachable in-flight sockets are being collected
+static GC_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Take a reference to an entry
+fn retain(entry: &FdEntry) {
+    if let FdEntry::Socket(handle) = entry {
+        *SOCKET_REFS.lock().entry(*handle).or_insert(0) += 1;
+    }
+}
+
+/// Drop a reference to an entry
+///
+/// A socket is closed once its last reference is gone. Open files close
+/// when the last descriptor sharing them is dropped.
+fn release(entry: FdEntry) {
+    let FdEntry::Socket(handle) = entry else {
+        return;
+    };
+
+    let remaining = {
+        let mut refs = SOCKET_REFS.lock();
+        match refs.get_mut(&handle) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                *count
+            }
+            _ => {
+                refs.remove(&handle);
+                0
+            }
+        }
+    };
+    if remaining == 0 {
+        let _ = socket::close_socket(handle);
+    } else if SOCKET_IN_FLIGHT.lock().get(&handle) == Some(&remaining) {
+        // Only messages in flight still refer to the socket
+        collect_in_flight();
+    }
+}
+
+/// Close sockets that can only be reached from their own queues
+///
+/// A socket sent over itself, or a ring of sockets sent over each other,
+/// keeps itself open once every descriptor is closed. As in Linux's
+/// unix_gc, sockets referenced only from messages in flight are
+/// candidates; those still referenced from a queue outside the candidates,
+/// and everything queued on them, are live. The rest is garbage, and
+/// dropping the descriptors queued on it breaks the cycles.
+fn collect_in_flight() {
+    if GC_RUNNING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    let candidates: BTreeMap<i32, usize> = {
+        let refs = SOCKET_REFS.lock();
+        let in_flight = SOCKET_IN_FLIGHT.lock();
+        in_flight
+            .iter()
+            .filter(|&(handle, count)| refs.get(handle) == Some(count))
+            .map(|(&handle, &count)| (handle, count))
+            .collect()
+    };
+
+    // Discount the references the candidates hold on each other
+    let mut external = candidates.clone();
+    let mut queued = BTreeMap::new();
+    for &handle in candidates.keys() {
+        let Some(entries) = socket::queued_rights(handle) else {
+            // Busy socket: try again on a later close
+            GC_RUNNING.store(false, Ordering::Release);
+            return;
+        };
+        let children: Vec<i32> = entries
+            .iter()
+            .filter_map(|entry| match entry {
+                FdEntry::Socket(child) => Some(*child),
+                _ => None,
+            })
+            .collect();
+        for child in &children {
+            if let Some(count) = external.get_mut(child) {
+                *count -= 1;
+            }
+        }
+        queued.insert(handle, children);
+    }
+
+    let mut garbage: BTreeSet<i32> = candidates.keys().copied().collect();
+    let mut live: Vec<i32> = external
+        .iter()
+        .filter(|&(_, &count)| count > 0)
+        .map(|(&handle, _)| handle)
+        .collect();
+    while let Some(handle) = live.pop() {
+        if garbage.remove(&handle) {
+            live.extend(queued.get(&handle).into_iter().flatten());
+        }
+    }
+
+    let rights: Vec<InFlightFds> = garbage
+        .iter()
+        .flat_map(|&handle| socket::take_queued_rights(handle))
+        .collect();
+    drop(rights);
+    GC_RUNNING.store(false, Ordering::Release);
 }
 
 /// File descriptor table
 pub struct FileDescriptorTable {
-    entries: Vec<FdEntry>,
+    slots: Vec<FdSlot>,
 }
 
 impl Default for FileDescriptorTable {
@@ -36,213 +204,563 @@ impl Default for FileDescriptorTable {
 impl FileDescriptorTable {
     /// Create a new file descriptor table
     pub fn new() -> Self {
-        let mut entries = Vec::new();
+        let mut slots = Vec::new();
         // Reserve standard file descriptors
         for _ in 0..3 {
-            entries.push(FdEntry::Empty);
+            slots.push(FdSlot::EMPTY);
         }
-        FileDescriptorTable { entries }
+        FileDescriptorTable { slots }
     }
 
     /// Allocate a new file descriptor
-    pub fn allocate_fd(&mut self, file: File) -> Result<FileDescriptor, ()> {
-        // Try to find an empty slot
-        for (i, entry) in self.entries.iter_mut().enumerate() {
-            if matches!(entry, FdEntry::Empty) {
-                *entry = FdEntry::File(file);
-                return Ok(i as FileDescriptor);
-            }
-        }
+    pub fn allocate_fd(&mut self, file: File, cloexec: bool) -> Result<FileDescriptor, ()> {
+        self.allocate_from(0, FdEntry::File(Arc::new(Mutex::new(file))), cloexec)
+    }
 
-        // No empty slot, add a new one
-        let fd = self.entries.len() as FileDescriptor;
-        self.entries.push(FdEntry::File(file));
-        Ok(fd)
+    /// Allocate a new file descriptor for a socket
+    pub fn allocate_socket(&mut self, handle: i32, cloexec: bool) -> Result<FileDescriptor, ()> {
+        self.allocate_from(0, FdEntry::Socket(handle), cloexec)
+    }
+
+    /// Put an entry in the lowest free slot at or above `min`
+    fn allocate_from(
+        &mut self,
+        min: usize,
+        entry: FdEntry,
+        cloexec: bool,
+    ) -> Result<FileDescriptor, ()> {
+        let fd = (min..MAX_FDS)
+            .find(|&fd| {
+                self.slots
+                    .get(fd)
+                    .is_none_or(|slot| matches!(slot.entry, FdEntry::Empty))
+            })
+            .ok_or(())?;
+
+        let replaced = self.install(fd, entry, cloexec);
+        debug_assert!(matches!(replaced, FdEntry::Empty));
+        Ok(fd as FileDescriptor)
+    }
+
+    /// Put an entry in a slot, returning the entry it replaces
+    fn install(&mut self, fd: usize, entry: FdEntry, cloexec: bool) -> FdEntry {
+        retain(&entry);
+        if self.slots.len() <= fd {
+            self.slots.resize(fd + 1, FdSlot::EMPTY);
+        }
+        let old = core::mem::replace(&mut self.slots[fd], FdSlot { entry, cloexec });
+        old.entry
     }
 
     /// Free a file descriptor
     pub fn free_fd(&mut self, fd: FileDescriptor) -> Result<(), ()> {
-        if fd < 0 || fd as usize >= self.entries.len() {
+        release(self.take_entry(fd)?);
+        Ok(())
+    }
+
+    /// Remove the entry of an open file descriptor
+    ///
+    /// The caller becomes responsible for releasing it.
+    fn take_entry(&mut self, fd: FileDescriptor) -> Result<FdEntry, ()> {
+        if fd < 0 || fd as usize >= self.slots.len() {
             return Err(());
         }
 
-        self.entries[fd as usize] = FdEntry::Empty;
-        Ok(())
+        match core::mem::replace(&mut self.slots[fd as usize], FdSlot::EMPTY).entry {
+            FdEntry::Empty => Err(()),
+            entry => Ok(entry),
+        }
     }
 
-    /// Get a file by descriptor
-    pub fn get_file(&self, fd: FileDescriptor) -> Option<&File> {
-        if fd < 0 || fd as usize >= self.entries.len() {
+    /// Get the slot of an open file descriptor
+    fn slot(&self, fd: FileDescriptor) -> Option<&FdSlot> {
+        if fd < 0 {
             return None;
         }
+        self.slots
+            .get(fd as usize)
+            .filter(|slot| !matches!(slot.entry, FdEntry::Empty))
+    }
 
-        match &self.entries[fd as usize] {
-            FdEntry::File(file) => Some(file),
-            FdEntry::Empty => None,
-        }
+    /// Get the entry of a file descriptor
+    fn entry(&self, fd: FileDescriptor) -> Option<&FdEntry> {
+        self.slot(fd).map(|slot| &slot.entry)
     }
 
-    /// Get a mutable file by descriptor
-    pub fn get_file_mut(&mut self, fd: FileDescriptor) -> Option<&mut File> {
-        if fd < 0 || fd as usize >= self.entries.len() {
-            return None;
+    /// Get the socket handle of a descriptor
+    pub fn get_socket(&self, fd: FileDescriptor) -> Option<i32> {
+        match self.entry(fd)? {
+            FdEntry::Socket(handle) => Some(*handle),
+            _ => None,
         }
+    }
 
-        match &mut self.entries[fd as usize] {
+    /// Get the open file behind a descriptor
+    pub fn get_file(&self, fd: FileDescriptor) -> Option<&OpenFile> {
+        match self.entry(fd)? {
             FdEntry::File(file) => Some(file),
-            FdEntry::Empty => None,
+            _ => None,
         }
     }
-}
 
-/// Global file descriptor table (for kernel)
-static GLOBAL_FD_TABLE: Mutex<Option<FileDescriptorTable>> = Mutex::new(None);
+    /// Check if a descriptor is closed on exec
+    pub fn get_cloexec(&self, fd: FileDescriptor) -> Option<bool> {
+        self.slot(fd).map(|slot| slot.cloexec)
+    }
 
-/// Initialize file descriptor subsystem
-pub fn init() {
-    let mut table = GLOBAL_FD_TABLE.lock();
-    *table = Some(FileDescriptorTable::new());
-}
+    /// Set or clear close-on-exec on a descriptor
+    pub fn set_cloexec(&mut self, fd: FileDescriptor, cloexec: bool) -> Result<(), ()> {
+        self.slot(fd).ok_or(())?;
+        self.slots[fd as usize].cloexec = cloexec;
+        Ok(())
+    }
+
+    /// Duplicate the table for a child process
+    ///
+    /// The child gets its own descriptors referring to the same open files,
+    /// with the same close-on-exec flags.
+    pub fn fork(&self) -> Self {
+        for slot in &self.slots {
+            retain(&slot.entry);
+        }
+        FileDescriptorTable {
+            slots: self.slots.clone(),
+        }
+    }
 
-/// Allocate a file descriptor globally
-pub fn allocate_fd(file: File) -> Result<FileDescriptor, ()> {
-    let mut table = GLOBAL_FD_TABLE.lock();
-    if let Some(ref mut t) = *table {
-        t.allocate_fd(file)
-    } else {
-        Err(())
+    /// Close every descriptor marked close-on-exec
+    pub fn close_on_exec(&mut self) {
+        for slot in self.slots.iter_mut().filter(|slot| slot.cloexec) {
+            release(core::mem::replace(slot, FdSlot::EMPTY).entry);
+        }
     }
 }
 
-/// Free a file descriptor globally
-pub fn free_fd(fd: FileDescriptor) -> Result<(), ()> {
-    let mut table = GLOBAL_FD_TABLE.lock();
-    if let Some(ref mut t) = *table {
-        t.free_fd(fd)
-    } else {
-        Err(())
+impl Drop for FileDescriptorTable {
+    fn drop(&mut self) {
+        for slot in self.slots.drain(..) {
+            release(slot.entry);
+        }
     }
 }
 
-/// Get a file by descriptor globally
-pub fn get_file(fd: FileDescriptor) -> Option<File> {
-    let table = GLOBAL_FD_TABLE.lock();
-    if let Some(ref t) = *table {
-        t.get_file(fd).cloned()
-    } else {
-        None
-    }
-}
-
-/// Read from a file descriptor
-pub fn read_fd(fd: FileDescriptor, buf: *mut u8, count: usize) -> Result<usize, ()> {
-    let mut table = GLOBAL_FD_TABLE.lock();
-    if let Some(ref mut t) = *table {
-        if let Some(file) = t.get_file_mut(fd) {
-            // TODO: Call actual filesystem read operation
-            if !file.is_readable() {
-                return Err(());
+/// Kernel file descriptor table, used while no task is current
+static KERNEL_FD_TABLE: Mutex<Option<Arc<Mutex<FileDescriptorTable>>>> = Mutex::new(None);
+
+/// File descriptor subsystem initialization flag
+static FD_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Descriptors in flight in a socket message (SCM_RIGHTS)
+///
+/// Entries stay open while referenced from a queued message; dropping the
+/// last reference closes them like freeing their last descriptor. Sockets
+/// kept open only by cycles of such messages are collected.
+pub struct InFlightFds {
+    entries: Vec<FdEntry>,
+}
+
+impl InFlightFds {
+    /// Take references to descriptor table entries
+    pub fn new(entries: Vec<FdEntry>) -> Self {
+        for entry in &entries {
+            retain(entry);
+            if let FdEntry::Socket(handle) = entry {
+                *SOCKET_IN_FLIGHT.lock().entry(*handle).or_insert(0) += 1;
             }
-            // For now, return 0 (EOF)
-            let _ = (buf, count);
-            Ok(0)
-        } else {
-            Err(())
         }
-    } else {
-        Err(())
+        Self { entries }
+    }
+
+    /// Get the referenced entries
+    pub fn entries(&self) -> &[FdEntry] {
+        &self.entries
+    }
+}
+
+impl Clone for InFlightFds {
+    fn clone(&self) -> Self {
+        Self::new(self.entries.clone())
     }
 }
 
-/// Write to a file descriptor
-pub fn write_fd(fd: FileDescriptor, buf: *const u8, count: usize) -> Result<usize, ()> {
-    let mut table = GLOBAL_FD_TABLE.lock();
-    if let Some(ref mut t) = *table {
-        if let Some(file) = t.get_file_mut(fd) {
-            // TODO: Call actual filesystem write operation
-            if !file.is_writable() {
-                return Err(());
+impl Drop for InFlightFds {
+    fn drop(&mut self) {
+        for entry in self.entries.drain(..) {
+            if let FdEntry::Socket(handle) = entry {
+                let mut in_flight = SOCKET_IN_FLIGHT.lock();
+                if let Some(count) = in_flight.get_mut(&handle) {
+                    *count -= 1;
+                    if *count == 0 {
+                        in_flight.remove(&handle);
+                    }
+                }
             }
-            // For now, pretend we wrote all bytes
-            let _ = buf;
-            Ok(count)
-        } else {
-            Err(())
+            release(entry);
         }
-    } else {
-        Err(())
     }
 }
 
+/// Initialize file descriptor subsystem
+///
+/// An existing table is kept, so calling this again is harmless.
+pub fn init() {
+    KERNEL_FD_TABLE
+        .lock()
+        .get_or_insert_with(|| Arc::new(Mutex::new(FileDescriptorTable::new())));
+    FD_INITIALIZED.store(true, Ordering::Release);
+}
+
+/// Check if the file descriptor subsystem is initialized
+pub fn is_initialized() -> bool {
+    FD_INITIALIZED.load(Ordering::Acquire)
+}
+
+/// Get the descriptor table of the current task
+///
+/// Falls back to the kernel's table while no task is running.
+pub fn current_table() -> Option<Arc<Mutex<FileDescriptorTable>>> {
+    crate::process::sched::current_fd_table().or_else(|| KERNEL_FD_TABLE.lock().clone())
+}
+
+/// Allocate a file descriptor in the current table
+pub fn allocate_fd(file: File, cloexec: bool) -> Result<FileDescriptor, ()> {
+    current_table().ok_or(())?.lock().allocate_fd(file, cloexec)
+}
+
+/// Allocate a file descriptor for a socket in the current table
+pub fn allocate_socket_fd(handle: i32, cloexec: bool) -> Result<FileDescriptor, ()> {
+    current_table()
+        .ok_or(())?
+        .lock()
+        .allocate_socket(handle, cloexec)
+}
+
+/// Free a file descriptor in the current table
+///
+/// A socket is closed once its last descriptor is freed.
+pub fn free_fd(fd: FileDescriptor) -> Result<(), ()> {
+    let entry = current_table().ok_or(())?.lock().take_entry(fd)?;
+    release(entry);
+    Ok(())
+}
+
+/// Install a descriptor table entry in the lowest free slot
+pub fn install_entry(entry: FdEntry) -> Result<FileDescriptor, ()> {
+    current_table()
+        .ok_or(())?
+        .lock()
+        .allocate_from(0, entry, false)
+}
+
+/// Get a copy of the entry of a descriptor
+pub fn get_entry(fd: FileDescriptor) -> Option<FdEntry> {
+    current_table()?.lock().entry(fd).cloned()
+}
+
+/// Get the socket handle of a descriptor
+pub fn get_socket(fd: FileDescriptor) -> Option<i32> {
+    current_table()?.lock().get_socket(fd)
+}
+
+/// Get a snapshot of the open file behind a descriptor
+pub fn get_file(fd: FileDescriptor) -> Option<File> {
+    let file = current_table()?.lock().get_file(fd).cloned()?;
+    let file = file.lock().clone();
+    Some(file)
+}
+
+/// Run an operation on the open file behind a descriptor
+///
+/// The descriptor table is not locked while the operation runs, only the
+/// open file itself.
+fn with_file<T>(
+    fd: FileDescriptor,
+    op: impl FnOnce(&mut File) -> Result<T, isize>,
+) -> Result<T, isize> {
+    let table = current_table().ok_or(errno::EBADF)?;
+    let file = table.lock().get_file(fd).cloned().ok_or(errno::EBADF)?;
+    let mut file = file.lock();
+    op(&mut file)
+}
+
+/// Read from a file descriptor into a user buffer
+pub fn read_fd(fd: FileDescriptor, buf: *mut u8, count: usize) -> Result<usize, isize> {
+    with_file(fd, |file| super::read_file(file, buf as usize, count))
+}
+
+/// Write to a file descriptor from a user buffer
+pub fn write_fd(fd: FileDescriptor, buf: *const u8, count: usize) -> Result<usize, isize> {
+    with_file(fd, |file| super::write_file(file, buf as usize, count))
+}
+
 /// Seek in a file descriptor
-pub fn seek_fd(fd: FileDescriptor, offset: i64, whence: i32) -> Result<i64, ()> {
-    let mut table = GLOBAL_FD_TABLE.lock();
-    if let Some(ref mut t) = *table {
-        if let Some(file) = t.get_file_mut(fd) {
-            // whence: 0 = SEEK_SET, 1 = SEEK_CUR, 2 = SEEK_END
-            let new_pos = match whence {
-                0 => offset.max(0) as u64,
-                1 => (file.position as i64 + offset).max(0) as u64,
-                2 => (file.size as i64 + offset).max(0) as u64,
-                _ => return Err(()),
-            };
-            file.position = new_pos;
-            Ok(new_pos as i64)
-        } else {
-            Err(())
-        }
-    } else {
-        Err(())
-    }
+pub fn seek_fd(fd: FileDescriptor, offset: i64, whence: i32) -> Result<u64, isize> {
+    with_file(fd, |file| super::seek_file(file, offset, whence))
 }
 
 /// Duplicate a file descriptor
+///
+/// The new descriptor shares the open file and does not inherit
+/// close-on-exec.
 pub fn dup_fd(fd: FileDescriptor) -> Result<FileDescriptor, ()> {
-    let table = GLOBAL_FD_TABLE.lock();
-    if let Some(ref t) = *table {
-        if let Some(file) = t.get_file(fd) {
-            let new_file = file.clone();
-            drop(table);
-            allocate_fd(new_file)
-        } else {
-            Err(())
-        }
-    } else {
-        Err(())
-    }
+    let table = current_table().ok_or(())?;
+    let mut t = table.lock();
+    let entry = t.entry(fd).ok_or(())?.clone();
+    t.allocate_from(0, entry, false)
 }
 
 /// Duplicate a file descriptor to a specific fd
+///
+/// An open `newfd` is closed first.
 pub fn dup2_fd(oldfd: FileDescriptor, newfd: FileDescriptor) -> Result<FileDescriptor, ()> {
+    let table = current_table().ok_or(())?;
+    let mut t = table.lock();
+    let entry = t.entry(oldfd).ok_or(())?.clone();
     if oldfd == newfd {
         return Ok(newfd);
     }
+    if newfd < 0 || newfd as usize >= MAX_FDS {
+        return Err(());
+    }
 
-    let table = GLOBAL_FD_TABLE.lock();
-    if let Some(ref t) = *table {
-        if let Some(file) = t.get_file(oldfd) {
-            let new_file = file.clone();
-            drop(table);
+    let replaced = t.install(newfd as usize, entry, false);
+    drop(t);
+    release(replaced);
+    Ok(newfd)
+}
 
-            // Close newfd if it's open
-            let _ = free_fd(newfd);
+/// Manipulate a file descriptor (fcntl)
+pub fn fcntl(fd: FileDescriptor, cmd: i32, arg: usize) -> Result<usize, isize> {
+    let table = current_table().ok_or(errno::EBADF)?;
+    let mut t = table.lock();
+    let entry = t.entry(fd).ok_or(errno::EBADF)?.clone();
 
-            // Allocate at specific fd
-            let mut table = GLOBAL_FD_TABLE.lock();
-            if let Some(ref mut t) = *table {
-                // Ensure table is large enough
-                while t.entries.len() <= newfd as usize {
-                    t.entries.push(FdEntry::Empty);
+    match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            if arg >= MAX_FDS {
+                return Err(errno::EINVAL);
+            }
+            t.allocate_from(arg, entry, cmd == F_DUPFD_CLOEXEC)
+                .map(|fd| fd as usize)
+                .map_err(|()| errno::EMFILE)
+        }
+        F_GETFD => Ok(match t.get_cloexec(fd) {
+            Some(true) => FD_CLOEXEC as usize,
+            _ => 0,
+        }),
+        F_SETFD => {
+            let _ = t.set_cloexec(fd, arg as i32 & FD_CLOEXEC != 0);
+            Ok(0)
+        }
+        F_GETFL => Ok(match entry {
+            FdEntry::File(file) => file.lock().flags as usize,
+            // Sockets keep O_NONBLOCK in their options
+            FdEntry::Socket(handle) => match socket::is_nonblocking_socket(handle) {
+                Ok(true) => (flags::O_RDWR | flags::O_NONBLOCK) as usize,
+                _ => flags::O_RDWR as usize,
+            },
+            FdEntry::Empty => return Err(errno::EBADF),
+        }),
+        F_SETFL => {
+            match entry {
+                FdEntry::File(file) => {
+                    let mut file = file.lock();
+                    file.flags = (file.flags & !SETFL_MASK) | (arg as i32 & SETFL_MASK);
+                }
+                FdEntry::Socket(handle) => {
+                    let _ = socket::set_nonblocking(handle, arg as i32 & flags::O_NONBLOCK != 0);
                 }
-                t.entries[newfd as usize] = FdEntry::File(new_file);
-                Ok(newfd)
-            } else {
-                Err(())
+                FdEntry::Empty => return Err(errno::EBADF),
             }
-        } else {
-            Err(())
+            Ok(0)
+        }
+        _ => Err(errno::EINVAL),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::file::{FileMode, FileType};
+    use crate::fs::filesystems::tmpfs::Tmpfs;
+    use crate::fs::vfs::{self, Filesystem};
+    use crate::net::socket::{SocketDomain, SocketProtocol, SocketType};
+
+    fn setup() {
+        static FD_TABLE: spin::Once = spin::Once::new();
+        FD_TABLE.call_once(init);
+    }
+
+    fn tmpfs_file() -> File {
+        let fs = Tmpfs::new();
+        let node = Filesystem::root(&*fs)
+            .create("file", vfs::FileMode::new(0o644))
+            .unwrap();
+        File::from_vnode(node, FileMode::read_write()).unwrap()
+    }
+
+    #[test]
+    fn test_fork_shares_open_files() {
+        let mut parent = FileDescriptorTable::new();
+        let fd = parent.allocate_fd(tmpfs_file(), false).unwrap();
+        let cloexec = parent
+            .allocate_fd(File::new(1, FileType::Regular, FileMode::read_only()), true)
+            .unwrap();
+
+        let mut child = parent.fork();
+        let shared = child.get_file(fd).unwrap();
+        assert!(Arc::ptr_eq(shared, parent.get_file(fd).unwrap()));
+
+        // One offset moves for both processes
+        shared.lock().seek(42);
+        assert_eq!(parent.get_file(fd).unwrap().lock().tell(), 42);
+
+        // Closing in the child leaves the parent's descriptor open
+        child.free_fd(fd).unwrap();
+        assert!(child.get_file(fd).is_none());
+        assert!(parent.get_file(fd).is_some());
+
+        assert_eq!(child.get_cloexec(cloexec), Some(true));
+        child.close_on_exec();
+        assert!(child.get_file(cloexec).is_none());
+        assert_eq!(parent.get_cloexec(cloexec), Some(true));
+    }
+
+    #[test]
+    fn test_socket_closes_with_last_table() {
+        let (a, b) = socket::socketpair(
+            SocketDomain::Unix,
+            SocketType::Stream,
+            SocketProtocol::Default,
+        )
+        .unwrap();
+
+        let mut parent = FileDescriptorTable::new();
+        let fd = parent.allocate_socket(a, false).unwrap();
+        let child = parent.fork();
+
+        parent.free_fd(fd).unwrap();
+        assert!(socket::set_nonblocking(a, true).is_ok());
+
+        drop(child);
+        assert!(socket::set_nonblocking(a, true).is_err());
+        let _ = socket::close_socket(b);
+    }
+
+    #[test]
+    fn test_in_flight_cycles_collected() {
+        use crate::net::socket::ControlMessage;
+
+        let pair = || {
+            socket::socketpair(
+                SocketDomain::Unix,
+                SocketType::Stream,
+                SocketProtocol::Default,
+            )
+            .unwrap()
+        };
+        let send_rights = |over: i32, handle: i32| {
+            let rights = InFlightFds::new(alloc::vec![FdEntry::Socket(handle)]);
+            let control = [ControlMessage::Rights(rights)];
+            assert_eq!(socket::sendmsg(over, b"x", None, &control, 0), Ok(1));
+        };
+        let mut table = FileDescriptorTable::new();
+
+        // b sent over a sits in b's own queue
+        let (a, b) = pair();
+        let fd_a = table.allocate_socket(a, false).unwrap();
+        let fd_b = table.allocate_socket(b, false).unwrap();
+        send_rights(a, b);
+        table.free_fd(fd_a).unwrap();
+        table.free_fd(fd_b).unwrap();
+        assert!(socket::set_nonblocking(a, true).is_err());
+        assert!(socket::set_nonblocking(b, true).is_err());
+
+        // Two sockets queued on each other
+        let (c, d) = pair();
+        let (e, f) = pair();
+        let fds: Vec<_> = [c, d, e, f]
+            .iter()
+            .map(|&handle| table.allocate_socket(handle, false).unwrap())
+            .collect();
+        send_rights(e, d);
+        send_rights(c, f);
+
+        // Still reachable through a descriptor for d
+        table.free_fd(fds[3]).unwrap();
+        assert!(socket::set_nonblocking(f, true).is_ok());
+
+        for &fd in &fds[..3] {
+            table.free_fd(fd).unwrap();
+        }
+        for handle in [c, d, e, f] {
+            assert!(socket::set_nonblocking(handle, true).is_err());
+        }
+    }
+
+    #[test]
+    fn test_dup_and_fcntl() {
+        setup();
+        let fd = allocate_fd(tmpfs_file(), true).unwrap();
+        assert_eq!(fcntl(fd, F_GETFD, 0), Ok(FD_CLOEXEC as usize));
+
+        // Duplicates share the offset but not close-on-exec
+        let dup = dup_fd(fd).unwrap();
+        assert_eq!(fcntl(dup, F_GETFD, 0), Ok(0));
+        seek_fd(fd, 5, super::super::SEEK_SET).unwrap();
+        assert_eq!(seek_fd(dup, 0, super::super::SEEK_CUR), Ok(5));
+
+        let high = fcntl(fd, F_DUPFD_CLOEXEC, 100).unwrap() as FileDescriptor;
+        assert!(high >= 100);
+        assert_eq!(fcntl(high, F_GETFD, 0), Ok(FD_CLOEXEC as usize));
+        assert_eq!(fcntl(fd, F_DUPFD, MAX_FDS), Err(errno::EINVAL));
+
+        fcntl(dup, F_SETFD, FD_CLOEXEC as usize).unwrap();
+        assert_eq!(fcntl(dup, F_GETFD, 0), Ok(FD_CLOEXEC as usize));
+
+        // Status flags live in the shared open file; the access mode is fixed
+        fcntl(fd, F_SETFL, (flags::O_APPEND | flags::O_WRONLY) as usize).unwrap();
+        assert_eq!(fcntl(dup, F_GETFL, 0), Ok(flags::O_APPEND as usize));
+        assert!(get_file(high).unwrap().is_append());
+
+        assert_eq!(fcntl(fd, 999, 0), Err(errno::EINVAL));
+        for fd in [fd, dup, high] {
+            free_fd(fd).unwrap();
         }
-    } else {
-        Err(())
+        assert_eq!(fcntl(fd, F_GETFD, 0), Err(errno::EBADF));
+    }
+
+    #[test]
+    fn test_fcntl_nonblocking() {
+        setup();
+        let (a, b) = socket::socketpair(
+            SocketDomain::Unix,
+            SocketType::Stream,
+            SocketProtocol::Default,
+        )
+        .unwrap();
+        let sock = allocate_socket_fd(a, false).unwrap();
+        assert_eq!(fcntl(sock, F_GETFL, 0), Ok(flags::O_RDWR as usize));
+
+        // O_NONBLOCK on a socket is its non-blocking mode
+        fcntl(sock, F_SETFL, flags::O_NONBLOCK as usize).unwrap();
+        assert_eq!(socket::is_nonblocking_socket(a), Ok(true));
+        assert_eq!(
+            fcntl(sock, F_GETFL, 0),
+            Ok((flags::O_RDWR | flags::O_NONBLOCK) as usize)
+        );
+        fcntl(sock, F_SETFL, 0).unwrap();
+        assert_eq!(socket::is_nonblocking_socket(a), Ok(false));
+
+        // A read-only mount suppresses access times without showing O_NOATIME
+        let mut file = tmpfs_file();
+        file.readonly_mount = true;
+        let fd = allocate_fd(file, false).unwrap();
+        fcntl(fd, F_SETFL, flags::O_NONBLOCK as usize).unwrap();
+        assert_eq!(fcntl(fd, F_GETFL, 0), Ok(flags::O_NONBLOCK as usize));
+
+        free_fd(sock).unwrap();
+        free_fd(fd).unwrap();
+        let _ = socket::close_socket(b);
     }
 }
@@ -2,7 +2,9 @@
 //!
 //! Represents an open file.
 
+use super::vfs::VNode;
 use crate::types::Inode;
+use alloc::sync::Arc;
 
 /// File type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -76,6 +78,12 @@ pub struct File {
     pub position: u64,
     /// File size
     pub size: u64,
+    /// Backing VFS node
+    pub vnode: Option<Arc<dyn VNode>>,
+    /// Status flags given at open (`fs::flags`)
+    pub flags: i32,
+    /// Opened on a read-only mount
+    pub readonly_mount: bool,
 }
 
 impl File {
@@ -87,9 +95,27 @@ impl File {
             mode,
             position: 0,
             size: 0,
+            vnode: None,
+            flags: 0,
+            readonly_mount: false,
         }
     }
 
+    /// Create a file backed by a VFS node
+    pub fn from_vnode(vnode: Arc<dyn VNode>, mode: FileMode) -> Result<Self, super::vfs::FsError> {
+        let attr = vnode.getattr()?;
+        Ok(File {
+            inode: attr.ino,
+            file_type: attr.file_type,
+            mode,
+            position: 0,
+            size: attr.size,
+            vnode: Some(vnode),
+            flags: 0,
+            readonly_mount: false,
+        })
+    }
+
     /// Check if file is readable
     pub fn is_readable(&self) -> bool {
         self.mode.read
@@ -100,6 +126,11 @@ impl File {
         self.mode.write
     }
 
+    /// Check if writes go to the end of the file
+    pub fn is_append(&self) -> bool {
+        self.flags & super::flags::O_APPEND != 0
+    }
+
     /// Seek to position
     pub fn seek(&mut self, offset: u64) {
         self.position = offset;
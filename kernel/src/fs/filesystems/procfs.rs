@@ -76,15 +76,46 @@ impl Procfs {
         );
     }
 
-    /// Read from a proc entry
-    pub fn read(&self, path: &str) -> Result<Vec<u8>, &'static str> {
+    /// Register a directory whose read callback serves every path below it
+    pub fn register_handler(&mut self, path: &str, read_fn: ProcReadFn) {
+        let mut entries = self.entries.lock();
+        entries.insert(
+            String::from(path),
+            ProcEntry {
+                name: String::from(path),
+                entry_type: ProcEntryType::Directory,
+                read_fn: Some(read_fn),
+            },
+        );
+    }
+
+    /// Find the read callback for a path
+    ///
+    /// Falls back to the closest parent directory with a handler.
+    pub fn handler(&self, path: &str) -> Option<ProcReadFn> {
         let entries = self.entries.lock();
-        if let Some(entry) = entries.get(path) {
-            if let Some(read_fn) = entry.read_fn {
-                return read_fn(path);
+        if let Some(read_fn) = entries.get(path).and_then(|e| e.read_fn) {
+            return Some(read_fn);
+        }
+
+        let mut dir = path;
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            if let Some(entry) = entries.get(parent) {
+                if entry.entry_type == ProcEntryType::Directory && entry.read_fn.is_some() {
+                    return entry.read_fn;
+                }
             }
+            dir = parent;
         }
-        Err("Entry not found or not readable")
+        None
+    }
+
+    /// Read from a proc entry
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, &'static str> {
+        let read_fn = self
+            .handler(path)
+            .ok_or("Entry not found or not readable")?;
+        read_fn(path)
     }
 
     /// List entries in a directory
@@ -97,7 +128,7 @@ impl Procfs {
         };
 
         let mut results = Vec::new();
-        for (path, _) in entries.iter() {
+        for path in entries.keys() {
             if path.starts_with(&prefix) {
                 let rest = &path[prefix.len()..];
                 if !rest.contains('/') {
@@ -161,13 +192,33 @@ pub fn init() {
 }
 
 /// Read from procfs
+///
+/// The callback runs without the procfs lock held, so it may block.
 pub fn read(path: &str) -> Result<Vec<u8>, &'static str> {
-    let fs = PROCFS.lock();
-    if let Some(ref procfs) = *fs {
-        procfs.read(path)
-    } else {
-        Err("Procfs not initialized")
-    }
+    let read_fn = {
+        let fs = PROCFS.lock();
+        let procfs = fs.as_ref().ok_or("Procfs not initialized")?;
+        procfs
+            .handler(path)
+            .ok_or("Entry not found or not readable")?
+    };
+    read_fn(path)
+}
+
+/// Register a proc file
+pub fn register_file(path: &str, read_fn: ProcReadFn) -> Result<(), &'static str> {
+    let mut fs = PROCFS.lock();
+    let procfs = fs.as_mut().ok_or("Procfs not initialized")?;
+    procfs.register_file(path, read_fn);
+    Ok(())
+}
+
+/// Register a directory whose read callback serves every path below it
+pub fn register_handler(path: &str, read_fn: ProcReadFn) -> Result<(), &'static str> {
+    let mut fs = PROCFS.lock();
+    let procfs = fs.as_mut().ok_or("Procfs not initialized")?;
+    procfs.register_handler(path, read_fn);
+    Ok(())
 }
 
 /// List procfs directory
@@ -196,4 +247,17 @@ mod tests {
         let data = procfs.read("/proc/version").unwrap();
         assert!(!data.is_empty());
     }
+
+    #[test]
+    fn test_proc_handler() {
+        fn echo(path: &str) -> Result<Vec<u8>, &'static str> {
+            Ok(path.as_bytes().to_vec())
+        }
+
+        let mut procfs = Procfs::new();
+        procfs.register_handler("/proc/echo", echo);
+        assert_eq!(procfs.read("/proc/echo/a/b").unwrap(), b"/proc/echo/a/b");
+        assert!(procfs.read("/proc/echoes").is_err());
+        assert!(procfs.read("/proc/missing/a").is_err());
+    }
 }
@@ -196,7 +196,7 @@ impl Sysfs {
         };
 
         let mut results = Vec::new();
-        for (path, _) in entries.iter() {
+        for path in entries.keys() {
             if path.starts_with(&prefix) && path != dir {
                 let rest = &path[prefix.len()..];
                 if !rest.contains('/') {
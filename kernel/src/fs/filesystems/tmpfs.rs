@@ -2,9 +2,13 @@
 //!
 //! Simple RAM-based filesystem, data lost on unmount
 
+use crate::fs::vfs::{
+    self, DirEntry, FileAttr, FileMode, Filesystem, FsError, FsType, StatFs, VNode,
+};
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::string::String;
+use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicU64, Ordering};
 use spin::Mutex;
@@ -54,6 +58,21 @@ impl Permissions {
         }
     }
 
+    /// Create permissions from Unix mode bits
+    pub fn from_mode(mode: u16) -> Self {
+        Permissions {
+            owner_read: mode & 0o400 != 0,
+            owner_write: mode & 0o200 != 0,
+            owner_exec: mode & 0o100 != 0,
+            group_read: mode & 0o040 != 0,
+            group_write: mode & 0o020 != 0,
+            group_exec: mode & 0o010 != 0,
+            others_read: mode & 0o004 != 0,
+            others_write: mode & 0o002 != 0,
+            others_exec: mode & 0o001 != 0,
+        }
+    }
+
     /// Create default directory permissions (0755)
     pub fn default_dir() -> Self {
         Permissions {
@@ -167,6 +186,26 @@ impl Inode {
         }
     }
 
+    /// Create a new socket inode
+    pub fn new_socket(number: InodeNumber) -> Self {
+        Inode {
+            file_type: FileType::Socket,
+            data: InodeData::Empty,
+            ..Self::new_file(number)
+        }
+    }
+
+    /// Create a new symbolic link inode
+    pub fn new_symlink(number: InodeNumber, target: String) -> Self {
+        Inode {
+            file_type: FileType::Symlink,
+            permissions: Permissions::from_mode(0o777),
+            size: target.len() as u64,
+            data: InodeData::Symlink(target),
+            ..Self::new_file(number)
+        }
+    }
+
     /// Check if this is a directory
     pub fn is_directory(&self) -> bool {
         self.file_type == FileType::Directory
@@ -211,6 +250,19 @@ impl Inode {
         }
     }
 
+    /// Truncate or extend file data
+    pub fn truncate(&mut self, size: u64) -> Result<(), &'static str> {
+        match &mut self.data {
+            InodeData::Regular(data) => {
+                data.resize(size as usize, 0);
+                self.size = size;
+                Ok(())
+            }
+            InodeData::Directory(_) => Err("Is a directory"),
+            _ => Err("Not a regular file"),
+        }
+    }
+
     /// Add directory entry
     pub fn add_entry(&mut self, name: String, inode: InodeNumber) -> Result<(), &'static str> {
         match &mut self.data {
@@ -255,24 +307,29 @@ pub struct Tmpfs {
     inodes: Mutex<BTreeMap<InodeNumber, Box<Inode>>>,
     next_inode: AtomicU64,
     root_inode: InodeNumber,
+    /// Handle to ourselves for the VNodes we hand out
+    this: Weak<Tmpfs>,
 }
 
 impl Tmpfs {
     const ROOT_INODE: InodeNumber = 1;
 
     /// Create a new tmpfs instance
-    pub fn new() -> Self {
-        let tmpfs = Tmpfs {
-            inodes: Mutex::new(BTreeMap::new()),
-            next_inode: AtomicU64::new(Self::ROOT_INODE + 1),
-            root_inode: Self::ROOT_INODE,
-        };
-
-        // Create root directory
-        let root = Inode::new_directory(Self::ROOT_INODE);
-        tmpfs.inodes.lock().insert(Self::ROOT_INODE, Box::new(root));
-
-        tmpfs
+    pub fn new() -> Arc<Self> {
+        Arc::new_cyclic(|this| {
+            let tmpfs = Tmpfs {
+                inodes: Mutex::new(BTreeMap::new()),
+                next_inode: AtomicU64::new(Self::ROOT_INODE + 1),
+                root_inode: Self::ROOT_INODE,
+                this: this.clone(),
+            };
+
+            // Create root directory
+            let root = Inode::new_directory(Self::ROOT_INODE);
+            tmpfs.inodes.lock().insert(Self::ROOT_INODE, Box::new(root));
+
+            tmpfs
+        })
     }
 
     /// Allocate a new inode number
@@ -332,6 +389,25 @@ impl Tmpfs {
         Ok(inode_num)
     }
 
+    /// Create a socket inode (bound Unix domain socket)
+    pub fn create_socket(
+        &self,
+        parent: InodeNumber,
+        name: String,
+    ) -> Result<InodeNumber, &'static str> {
+        let inode_num = self.alloc_inode_number();
+        let inode = Inode::new_socket(inode_num);
+
+        let mut inodes = self.inodes.lock();
+        match inodes.get_mut(&parent) {
+            Some(parent_inode) => parent_inode.add_entry(name, inode_num)?,
+            None => return Err("Parent directory not found"),
+        }
+
+        inodes.insert(inode_num, Box::new(inode));
+        Ok(inode_num)
+    }
+
     /// Create a new directory
     pub fn create_directory(
         &self,
@@ -360,6 +436,125 @@ impl Tmpfs {
         Ok(inode_num)
     }
 
+    /// Create a symbolic link
+    pub fn create_symlink(
+        &self,
+        parent: InodeNumber,
+        name: String,
+        target: String,
+    ) -> Result<InodeNumber, &'static str> {
+        let inode_num = self.alloc_inode_number();
+        let inode = Inode::new_symlink(inode_num, target);
+
+        let mut inodes = self.inodes.lock();
+        match inodes.get_mut(&parent) {
+            Some(parent_inode) => parent_inode.add_entry(name, inode_num)?,
+            None => return Err("Parent directory not found"),
+        }
+
+        inodes.insert(inode_num, Box::new(inode));
+        Ok(inode_num)
+    }
+
+    /// Look up a name in a directory
+    pub fn lookup(&self, dir: InodeNumber, name: &str) -> Result<InodeNumber, &'static str> {
+        self.inodes
+            .lock()
+            .get(&dir)
+            .ok_or("Inode not found")?
+            .lookup(name)
+    }
+
+    /// List a directory as (name, inode, type) triples
+    pub fn list_dir(
+        &self,
+        dir: InodeNumber,
+    ) -> Result<Vec<(String, InodeNumber, FileType)>, &'static str> {
+        let inodes = self.inodes.lock();
+        match &inodes.get(&dir).ok_or("Inode not found")?.data {
+            InodeData::Directory(entries) => Ok(entries
+                .iter()
+                .filter_map(|(name, ino)| {
+                    inodes
+                        .get(ino)
+                        .map(|inode| (name.clone(), *ino, inode.file_type))
+                })
+                .collect()),
+            _ => Err("Not a directory"),
+        }
+    }
+
+    /// Read file data
+    pub fn read(
+        &self,
+        inode: InodeNumber,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, &'static str> {
+        self.inodes
+            .lock()
+            .get(&inode)
+            .ok_or("Inode not found")?
+            .read(offset, buffer)
+    }
+
+    /// Write file data
+    pub fn write(
+        &self,
+        inode: InodeNumber,
+        offset: u64,
+        buffer: &[u8],
+    ) -> Result<usize, &'static str> {
+        self.inodes
+            .lock()
+            .get_mut(&inode)
+            .ok_or("Inode not found")?
+            .write(offset, buffer)
+    }
+
+    /// Truncate or extend a file
+    pub fn truncate(&self, inode: InodeNumber, size: u64) -> Result<(), &'static str> {
+        self.inodes
+            .lock()
+            .get_mut(&inode)
+            .ok_or("Inode not found")?
+            .truncate(size)
+    }
+
+    /// Read a symbolic link target
+    pub fn readlink(&self, inode: InodeNumber) -> Result<String, &'static str> {
+        match &self
+            .inodes
+            .lock()
+            .get(&inode)
+            .ok_or("Inode not found")?
+            .data
+        {
+            InodeData::Symlink(target) => Ok(target.clone()),
+            _ => Err("Not a symlink"),
+        }
+    }
+
+    /// Change permission bits
+    pub fn chmod(&self, inode: InodeNumber, mode: u16) -> Result<(), &'static str> {
+        let mut inodes = self.inodes.lock();
+        let inode = inodes.get_mut(&inode).ok_or("Inode not found")?;
+        inode.permissions = Permissions::from_mode(mode);
+        Ok(())
+    }
+
+    /// Update mode, owner and timestamps from VFS attributes
+    pub fn set_attr(&self, inode: InodeNumber, attr: &FileAttr) -> Result<(), &'static str> {
+        let mut inodes = self.inodes.lock();
+        let inode = inodes.get_mut(&inode).ok_or("Inode not found")?;
+        inode.permissions = Permissions::from_mode(attr.mode.0 as u16);
+        inode.uid = attr.uid;
+        inode.gid = attr.gid;
+        inode.accessed_time = attr.atime;
+        inode.modified_time = attr.mtime;
+        Ok(())
+    }
+
     /// Resolve a path to an inode number
     ///
     /// Supports absolute paths (starting with `/`).
@@ -554,83 +749,219 @@ pub struct FileStat {
     pub accessed_time: u64,
 }
 
-impl Default for Tmpfs {
-    fn default() -> Self {
-        Self::new()
+/// Map a tmpfs error message to a VFS error
+fn fs_error(msg: &'static str) -> FsError {
+    match msg {
+        "Entry already exists" => FsError::AlreadyExists,
+        "Is a directory" => FsError::IsADirectory,
+        "Not a directory" | "New parent is not a directory" => FsError::NotADirectory,
+        "Directory not empty" => FsError::NotEmpty,
+        "Not a regular file" | "Not a symlink" => FsError::InvalidArgument,
+        "Tmpfs not initialized" => FsError::IoError,
+        _ => FsError::NotFound,
     }
 }
 
-/// Global tmpfs instance
-static TMPFS: Mutex<Option<Tmpfs>> = Mutex::new(None);
+/// Convert a tmpfs file type to the VFS one
+fn vfs_type(file_type: FileType) -> vfs::FileType {
+    match file_type {
+        FileType::Regular => vfs::FileType::Regular,
+        FileType::Directory => vfs::FileType::Directory,
+        FileType::Symlink => vfs::FileType::Symlink,
+        FileType::CharDevice => vfs::FileType::CharDevice,
+        FileType::BlockDevice => vfs::FileType::BlockDevice,
+        FileType::Fifo => vfs::FileType::Fifo,
+        FileType::Socket => vfs::FileType::Socket,
+    }
+}
 
-/// Initialize tmpfs
-pub fn init() {
-    let mut fs = TMPFS.lock();
-    *fs = Some(Tmpfs::new());
+/// VFS node for a tmpfs inode
+pub struct TmpfsNode {
+    fs: Arc<Tmpfs>,
+    ino: InodeNumber,
 }
 
-/// Get the global tmpfs instance
-pub fn get() -> Option<Tmpfs> {
-    // This is a simplified approach - cloning the entire filesystem
-    // Real implementation would use Arc or similar
-    None // Return None for now as cloning is complex
+impl TmpfsNode {
+    fn node(&self, ino: InodeNumber) -> Arc<dyn VNode> {
+        Arc::new(TmpfsNode {
+            fs: self.fs.clone(),
+            ino,
+        })
+    }
 }
 
-/// Resolve a path in the global tmpfs
-pub fn global_lookup_path(path: &str) -> Result<InodeNumber, &'static str> {
-    let fs = TMPFS.lock();
-    fs.as_ref()
-        .ok_or("Tmpfs not initialized")?
-        .lookup_path(path)
+impl VNode for TmpfsNode {
+    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        self.fs.read(self.ino, offset, buffer).map_err(fs_error)
+    }
+
+    fn write(&self, offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
+        self.fs.write(self.ino, offset, buffer).map_err(fs_error)
+    }
+
+    fn getattr(&self) -> Result<FileAttr, FsError> {
+        let stat = self.fs.stat(self.ino).map_err(fs_error)?;
+        Ok(FileAttr {
+            file_type: vfs_type(stat.file_type),
+            mode: FileMode::new(stat.mode as u32),
+            size: stat.size,
+            nlink: stat.link_count,
+            uid: stat.uid,
+            gid: stat.gid,
+            ino: stat.inode,
+            blocks: stat.size.div_ceil(512),
+            atime: stat.accessed_time,
+            mtime: stat.modified_time,
+            ctime: stat.created_time,
+        })
+    }
+
+    fn setattr(&self, attr: &FileAttr) -> Result<(), FsError> {
+        self.fs.set_attr(self.ino, attr).map_err(fs_error)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let entries = self.fs.list_dir(self.ino).map_err(fs_error)?;
+        Ok(entries
+            .into_iter()
+            .map(|(name, ino, file_type)| DirEntry {
+                ino,
+                file_type: vfs_type(file_type),
+                name,
+            })
+            .collect())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn VNode>, FsError> {
+        let ino = self.fs.lookup(self.ino, name).map_err(fs_error)?;
+        Ok(self.node(ino))
+    }
+
+    fn create(&self, name: &str, mode: FileMode) -> Result<Arc<dyn VNode>, FsError> {
+        let ino = self
+            .fs
+            .create_file(self.ino, String::from(name))
+            .map_err(fs_error)?;
+        self.fs.chmod(ino, mode.0 as u16).map_err(fs_error)?;
+        Ok(self.node(ino))
+    }
+
+    fn mkdir(&self, name: &str, mode: FileMode) -> Result<Arc<dyn VNode>, FsError> {
+        let ino = self
+            .fs
+            .create_directory(self.ino, String::from(name))
+            .map_err(fs_error)?;
+        self.fs.chmod(ino, mode.0 as u16).map_err(fs_error)?;
+        Ok(self.node(ino))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        self.fs.unlink(self.ino, name).map_err(fs_error)
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), FsError> {
+        self.fs.rmdir(self.ino, name).map_err(fs_error)
+    }
+
+    fn rename(
+        &self,
+        old_name: &str,
+        new_parent: Arc<dyn VNode>,
+        new_name: &str,
+    ) -> Result<(), FsError> {
+        let new_parent = new_parent.getattr()?.ino;
+        self.fs
+            .rename(self.ino, old_name, new_parent, new_name)
+            .map_err(fs_error)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn VNode>, FsError> {
+        let ino = self
+            .fs
+            .create_symlink(self.ino, String::from(name), String::from(target))
+            .map_err(fs_error)?;
+        Ok(self.node(ino))
+    }
+
+    fn readlink(&self) -> Result<String, FsError> {
+        self.fs.readlink(self.ino).map_err(fs_error)
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        self.fs.truncate(self.ino, size).map_err(fs_error)
+    }
+
+    fn fsync(&self) -> Result<(), FsError> {
+        Ok(())
+    }
 }
 
-/// Create a directory in the global tmpfs
-pub fn global_mkdir(path: &str) -> Result<InodeNumber, &'static str> {
-    let (parent_path, name) = split_path(path);
-    let parent_inode = global_lookup_path(parent_path)?;
-    let fs = TMPFS.lock();
-    fs.as_ref()
-        .ok_or("Tmpfs not initialized")?
-        .create_directory(parent_inode, String::from(name))
+impl Filesystem for Tmpfs {
+    fn fs_type(&self) -> FsType {
+        FsType::TmpFs
+    }
+
+    fn root(&self) -> Arc<dyn VNode> {
+        Arc::new(TmpfsNode {
+            fs: self
+                .this
+                .upgrade()
+                .expect("tmpfs is always reference counted"),
+            ino: self.root_inode,
+        })
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+
+    fn statfs(&self) -> Result<StatFs, FsError> {
+        let inodes = self.inodes.lock();
+        let bytes: u64 = inodes.values().map(|inode| inode.size).sum();
+        Ok(StatFs {
+            fs_type: 0x0102_1994,
+            block_size: 4096,
+            blocks: bytes.div_ceil(4096),
+            blocks_free: 0,
+            blocks_available: 0,
+            files: inodes.len() as u64,
+            files_free: 0,
+            name_max: 255,
+        })
+    }
+
+    fn unmount(&self) -> Result<(), FsError> {
+        Ok(())
+    }
 }
 
-/// Remove a directory in the global tmpfs
-pub fn global_rmdir(path: &str) -> Result<(), &'static str> {
-    let (parent_path, name) = split_path(path);
-    let parent_inode = global_lookup_path(parent_path)?;
-    let fs = TMPFS.lock();
-    fs.as_ref()
-        .ok_or("Tmpfs not initialized")?
-        .rmdir(parent_inode, name)
+/// Global tmpfs instance
+static TMPFS: Mutex<Option<Arc<Tmpfs>>> = Mutex::new(None);
+
+/// Initialize tmpfs
+pub fn init() {
+    let mut fs = TMPFS.lock();
+    if fs.is_none() {
+        *fs = Some(Tmpfs::new());
+    }
 }
 
-/// Unlink a file in the global tmpfs
-pub fn global_unlink(path: &str) -> Result<(), &'static str> {
-    let (parent_path, name) = split_path(path);
-    let parent_inode = global_lookup_path(parent_path)?;
-    let fs = TMPFS.lock();
-    fs.as_ref()
-        .ok_or("Tmpfs not initialized")?
-        .unlink(parent_inode, name)
+/// Check if tmpfs is initialized
+pub fn is_initialized() -> bool {
+    TMPFS.lock().is_some()
 }
 
-/// Rename a path in the global tmpfs
-pub fn global_rename(old_path: &str, new_path: &str) -> Result<(), &'static str> {
-    let (old_parent_path, old_name) = split_path(old_path);
-    let (new_parent_path, new_name) = split_path(new_path);
-    let old_parent = global_lookup_path(old_parent_path)?;
-    let new_parent = global_lookup_path(new_parent_path)?;
-    let fs = TMPFS.lock();
-    fs.as_ref()
-        .ok_or("Tmpfs not initialized")?
-        .rename(old_parent, old_name, new_parent, new_name)
+/// Get the global tmpfs instance
+pub fn get() -> Option<Arc<Tmpfs>> {
+    TMPFS.lock().clone()
 }
 
-/// Get stat information for a path in the global tmpfs
-pub fn global_stat(path: &str) -> Result<FileStat, &'static str> {
-    let inode_num = global_lookup_path(path)?;
+/// Resolve a path in the global tmpfs
+pub fn global_lookup_path(path: &str) -> Result<InodeNumber, &'static str> {
     let fs = TMPFS.lock();
-    fs.as_ref().ok_or("Tmpfs not initialized")?.stat(inode_num)
+    fs.as_ref()
+        .ok_or("Tmpfs not initialized")?
+        .lookup_path(path)
 }
 
 /// Get stat information for an inode number directly in the global tmpfs
@@ -641,14 +972,14 @@ pub fn global_stat_inode(inode_num: InodeNumber) -> Option<FileStat> {
     fs.as_ref()?.stat(inode_num).ok()
 }
 
-/// Create a file in the global tmpfs
-pub fn global_create_file(path: &str) -> Result<InodeNumber, &'static str> {
+/// Create a socket inode in the global tmpfs
+pub fn global_create_socket(path: &str) -> Result<InodeNumber, &'static str> {
     let (parent_path, name) = split_path(path);
     let parent_inode = global_lookup_path(parent_path)?;
     let fs = TMPFS.lock();
     fs.as_ref()
         .ok_or("Tmpfs not initialized")?
-        .create_file(parent_inode, String::from(name))
+        .create_socket(parent_inode, String::from(name))
 }
 
 /// Get the root inode number of the global tmpfs
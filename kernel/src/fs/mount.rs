@@ -0,0 +1,220 @@
+//! VFS Mount Management
+//!
+//! Tracks which filesystem is mounted where. Mount points are stored by
+//! their canonical path (symlinks resolved, no `.`/`..`), which is exactly
+//! what the path walker builds as it descends, so crossing a mount is a
+//! single table lookup per component.
+
+use super::path;
+use super::vfs::{FileType, Filesystem, FsError, VNode};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::RwLock;
+
+/// Mount point information
+pub struct MountPoint {
+    /// Path where filesystem is mounted
+    pub path: String,
+    /// Mounted filesystem
+    pub filesystem: Arc<dyn Filesystem>,
+    /// Mount flags
+    pub flags: MountFlags,
+}
+
+/// Mount flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MountFlags {
+    /// Read-only mount
+    pub readonly: bool,
+    /// No execution of programs
+    pub noexec: bool,
+    /// No device special files
+    pub nodev: bool,
+    /// No setuid/setgid
+    pub nosuid: bool,
+}
+
+impl MountFlags {
+    /// Default read-write flags
+    pub const fn new() -> Self {
+        Self {
+            readonly: false,
+            noexec: false,
+            nodev: false,
+            nosuid: false,
+        }
+    }
+
+    /// Read-only flags
+    pub const fn readonly() -> Self {
+        Self {
+            readonly: true,
+            noexec: false,
+            nodev: false,
+            nosuid: false,
+        }
+    }
+}
+
+impl Default for MountFlags {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Global mount table
+static MOUNT_TABLE: RwLock<Vec<MountPoint>> = RwLock::new(Vec::new());
+
+/// Check if `path` is `mount_path` or lies below it
+fn is_under(path: &str, mount_path: &str) -> bool {
+    mount_path == "/"
+        || path == mount_path
+        || (path.starts_with(mount_path) && path.as_bytes()[mount_path.len()] == b'/')
+}
+
+/// Mount a filesystem at the specified path
+///
+/// The mount point must be an existing directory, except for the first
+/// mount at `/`. Symlinks in `path` are resolved before recording it.
+pub fn mount(
+    path: &str,
+    filesystem: Arc<dyn Filesystem>,
+    flags: MountFlags,
+) -> Result<(), FsError> {
+    let canonical = if path == "/" {
+        String::from("/")
+    } else {
+        let (canonical, vnode) = path::resolve(path, true)?;
+        if vnode.getattr()?.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        canonical
+    };
+
+    let mut table = MOUNT_TABLE.write();
+    if table.iter().any(|mp| mp.path == canonical) {
+        return Err(FsError::Busy);
+    }
+
+    table.push(MountPoint {
+        path: canonical,
+        filesystem,
+        flags,
+    });
+    Ok(())
+}
+
+/// Unmount a filesystem
+///
+/// Fails with [`FsError::Busy`] while other filesystems are mounted
+/// below it.
+pub fn unmount(path: &str) -> Result<(), FsError> {
+    let canonical = if path == "/" {
+        String::from("/")
+    } else {
+        path::resolve(path, true)?.0
+    };
+
+    let mut table = MOUNT_TABLE.write();
+    let index = table
+        .iter()
+        .position(|mp| mp.path == canonical)
+        .ok_or(FsError::InvalidArgument)?;
+
+    if table
+        .iter()
+        .any(|mp| mp.path != canonical && is_under(&mp.path, &canonical))
+    {
+        return Err(FsError::Busy);
+    }
+
+    table[index].filesystem.unmount()?;
+    table.remove(index);
+    Ok(())
+}
+
+/// Get the filesystem mounted exactly at a canonical path
+pub fn mounted_at(path: &str) -> Option<Arc<dyn Filesystem>> {
+    MOUNT_TABLE
+        .read()
+        .iter()
+        .find(|mp| mp.path == path)
+        .map(|mp| mp.filesystem.clone())
+}
+
+/// Check if a canonical path is a mount point
+pub fn is_mount_point(path: &str) -> bool {
+    MOUNT_TABLE.read().iter().any(|mp| mp.path == path)
+}
+
+/// Find the innermost mount containing a canonical path
+fn covering<'a>(table: &'a [MountPoint], path: &str) -> Option<&'a MountPoint> {
+    table
+        .iter()
+        .filter(|mp| is_under(path, &mp.path))
+        .max_by_key(|mp| mp.path.len())
+}
+
+/// Get the mount point path containing a canonical path
+pub fn mount_point_of(path: &str) -> Option<String> {
+    covering(&MOUNT_TABLE.read(), path).map(|mp| mp.path.clone())
+}
+
+/// Get the filesystem containing a canonical path
+pub fn get_mount(path: &str) -> Option<Arc<dyn Filesystem>> {
+    covering(&MOUNT_TABLE.read(), path).map(|mp| mp.filesystem.clone())
+}
+
+/// Get the flags of the mount containing a canonical path
+pub fn get_mount_flags(path: &str) -> Option<MountFlags> {
+    covering(&MOUNT_TABLE.read(), path).map(|mp| mp.flags)
+}
+
+/// Set the root filesystem
+pub fn set_root(filesystem: Arc<dyn Filesystem>) -> Result<(), FsError> {
+    mount("/", filesystem, MountFlags::new())
+}
+
+/// Get the root filesystem
+pub fn get_root() -> Option<Arc<dyn Filesystem>> {
+    mounted_at("/")
+}
+
+/// Get root VNode
+pub fn get_root_vnode() -> Option<Arc<dyn VNode>> {
+    get_root().map(|fs| fs.root())
+}
+
+/// List all mount points as (path, filesystem type)
+pub fn list_mounts() -> Vec<(String, String)> {
+    MOUNT_TABLE
+        .read()
+        .iter()
+        .map(|mp| (mp.path.clone(), format!("{:?}", mp.filesystem.fs_type())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mount_flags() {
+        let flags = MountFlags::new();
+        assert!(!flags.readonly);
+
+        let ro_flags = MountFlags::readonly();
+        assert!(ro_flags.readonly);
+    }
+
+    #[test]
+    fn test_is_under() {
+        assert!(is_under("/mnt/disk/file", "/mnt/disk"));
+        assert!(is_under("/mnt/disk", "/mnt/disk"));
+        assert!(is_under("/etc", "/"));
+        assert!(!is_under("/mnt/diskette", "/mnt/disk"));
+        assert!(!is_under("/mnt", "/mnt/disk"));
+    }
+}
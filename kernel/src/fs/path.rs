@@ -0,0 +1,296 @@
+//! Path Resolution
+//!
+//! Walks a path one component at a time across whatever is mounted. The
+//! walker keeps a stack of the directories it descended through, so `..`
+//! pops back into the parent filesystem when leaving a mount root, and
+//! symbolic links are expanded in place (absolute targets restart at `/`).
+//!
+//! There is no per-process working directory yet, so relative paths are
+//! resolved from the root.
+
+use super::mount;
+use super::vfs::{FileType, FsError, VNode};
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Maximum number of symbolic links followed in one lookup
+pub const MAX_SYMLINKS: usize = 40;
+
+/// Parent directory of a path's final component
+pub struct ParentLookup {
+    /// Canonical path of the parent directory
+    pub path: String,
+    /// Parent directory node
+    pub dir: Arc<dyn VNode>,
+    /// Final component
+    pub name: String,
+}
+
+impl ParentLookup {
+    /// Canonical path of the final component
+    pub fn child_path(&self) -> String {
+        join(&self.path, &self.name)
+    }
+}
+
+/// Append a component to a canonical directory path
+pub fn join(dir: &str, name: &str) -> String {
+    let mut path = String::from(dir);
+    if !path.ends_with('/') {
+        path.push('/');
+    }
+    path.push_str(name);
+    path
+}
+
+/// Split a path into its non-empty components
+fn components(path: &str) -> VecDeque<String> {
+    path.split('/')
+        .filter(|c| !c.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Resolve a path to its canonical form and node
+///
+/// With `follow` unset, a symlink in the final component is returned
+/// itself rather than its target (as `lstat` and `unlink` need). A
+/// trailing slash always follows.
+pub fn resolve(path: &str, follow: bool) -> Result<(String, Arc<dyn VNode>), FsError> {
+    if path.is_empty() {
+        return Err(FsError::NotFound);
+    }
+    let root = mount::get_root_vnode().ok_or(FsError::NotFound)?;
+    let follow = follow || path.ends_with('/');
+
+    let mut stack: Vec<(String, Arc<dyn VNode>, FileType)> =
+        vec![(String::from("/"), root, FileType::Directory)];
+    let mut pending = components(path);
+    let mut links = 0;
+
+    while let Some(name) = pending.pop_front() {
+        match name.as_str() {
+            "." => continue,
+            ".." => {
+                if stack.len() > 1 {
+                    stack.pop();
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let (dir_path, dir, dir_type) = stack.last().unwrap();
+        if *dir_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+
+        let child_path = join(dir_path, &name);
+        let child = match mount::mounted_at(&child_path) {
+            Some(fs) => fs.root(),
+            None => dir.lookup(&name)?,
+        };
+        let child_type = child.getattr()?.file_type;
+
+        if child_type == FileType::Symlink && (follow || !pending.is_empty()) {
+            links += 1;
+            if links > MAX_SYMLINKS {
+                return Err(FsError::TooManyLinks);
+            }
+            let target = child.readlink()?;
+            if target.starts_with('/') {
+                stack.truncate(1);
+            }
+            for component in components(&target).into_iter().rev() {
+                pending.push_front(component);
+            }
+            continue;
+        }
+
+        stack.push((child_path, child, child_type));
+    }
+
+    let (path, node, _) = stack.pop().unwrap();
+    Ok((path, node))
+}
+
+/// Look up a path
+pub fn lookup(path: &str, follow: bool) -> Result<Arc<dyn VNode>, FsError> {
+    resolve(path, follow).map(|(_, node)| node)
+}
+
+/// Look up the directory that holds a path's final component
+///
+/// The final component itself need not exist. Paths ending in `.` or
+/// `..`, and `/` itself, have no usable final component.
+pub fn lookup_parent(path: &str) -> Result<ParentLookup, FsError> {
+    let trimmed = path.trim_end_matches('/');
+    let (parent, name) = match trimmed.rfind('/') {
+        Some(pos) => (&trimmed[..pos], &trimmed[pos + 1..]),
+        None => ("", trimmed),
+    };
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(if path.is_empty() {
+            FsError::NotFound
+        } else {
+            FsError::InvalidArgument
+        });
+    }
+
+    let parent = if parent.is_empty() { "/" } else { parent };
+    let (path, dir) = resolve(parent, true)?;
+    if dir.getattr()?.file_type != FileType::Directory {
+        return Err(FsError::NotADirectory);
+    }
+
+    Ok(ParentLookup {
+        path,
+        dir,
+        name: String::from(name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::filesystems::tmpfs::Tmpfs;
+    use crate::fs::mount::MountFlags;
+    use crate::fs::vfs::{FileMode, Filesystem};
+
+    /// Mount a tmpfs root once for all path tests
+    fn setup() {
+        static ROOT: spin::Once = spin::Once::new();
+        ROOT.call_once(|| {
+            crate::fs::filesystems::tmpfs::init();
+            crate::fs::vfs::init();
+        });
+    }
+
+    fn mkdir_p(path: &str) {
+        let root = mount::get_root_vnode().unwrap();
+        let mut dir = root;
+        for name in components(path) {
+            dir = match dir.lookup(&name) {
+                Ok(node) => node,
+                Err(_) => dir.mkdir(&name, FileMode::new(0o755)).unwrap(),
+            };
+        }
+    }
+
+    fn write_file(dir: &Arc<dyn VNode>, name: &str, data: &[u8]) {
+        let file = dir.create(name, FileMode::new(0o644)).unwrap();
+        file.write(0, data).unwrap();
+    }
+
+    fn read_all(node: &Arc<dyn VNode>) -> Vec<u8> {
+        let mut buf = vec![0u8; node.getattr().unwrap().size as usize];
+        let n = node.read(0, &mut buf).unwrap();
+        buf.truncate(n);
+        buf
+    }
+
+    #[test]
+    fn test_join_and_components() {
+        assert_eq!(join("/", "etc"), "/etc");
+        assert_eq!(join("/mnt", "disk"), "/mnt/disk");
+        assert_eq!(components("//a/./b/"), ["a", ".", "b"]);
+    }
+
+    #[test]
+    fn test_lookup_crosses_mounts() {
+        setup();
+        mkdir_p("/path-test-mnt/disk");
+
+        let disk = Tmpfs::new();
+        write_file(&Filesystem::root(&*disk), "file", b"on disk");
+        mount::mount("/path-test-mnt/disk", disk, MountFlags::new()).unwrap();
+
+        let (path, node) = resolve("/path-test-mnt/disk/file", true).unwrap();
+        assert_eq!(path, "/path-test-mnt/disk/file");
+        assert_eq!(read_all(&node), b"on disk");
+
+        // `..` from the mount root leaves the mounted filesystem
+        let (path, node) = resolve("/path-test-mnt/disk/../disk/./file", true).unwrap();
+        assert_eq!(path, "/path-test-mnt/disk/file");
+        assert_eq!(read_all(&node), b"on disk");
+        let (path, _) = resolve("/path-test-mnt/disk/..", true).unwrap();
+        assert_eq!(path, "/path-test-mnt");
+        assert_eq!(resolve("/..", true).unwrap().0, "/");
+
+        assert_eq!(
+            mount::mount_point_of("/path-test-mnt/disk/file").as_deref(),
+            Some("/path-test-mnt/disk")
+        );
+        assert_eq!(
+            lookup("/path-test-mnt/disk/file/x", true).err(),
+            Some(FsError::NotADirectory)
+        );
+
+        mount::unmount("/path-test-mnt/disk").unwrap();
+        assert_eq!(
+            lookup("/path-test-mnt/disk/file", true).err(),
+            Some(FsError::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_lookup_follows_symlinks() {
+        setup();
+        mkdir_p("/path-test-links/real");
+        let real = lookup("/path-test-links/real", true).unwrap();
+        write_file(&real, "data", b"target");
+
+        let links = lookup("/path-test-links", true).unwrap();
+        links.symlink("abs", "/path-test-links/real").unwrap();
+        links.symlink("rel", "real/data").unwrap();
+        links.symlink("up", "../path-test-links/real").unwrap();
+        links.symlink("loop", "loop").unwrap();
+
+        let (path, node) = resolve("/path-test-links/abs/data", true).unwrap();
+        assert_eq!(path, "/path-test-links/real/data");
+        assert_eq!(read_all(&node), b"target");
+
+        assert_eq!(
+            read_all(&lookup("/path-test-links/rel", true).unwrap()),
+            b"target"
+        );
+        assert_eq!(
+            resolve("/path-test-links/up/data", true).unwrap().0,
+            "/path-test-links/real/data"
+        );
+
+        // Without follow the final link itself is returned
+        let link = lookup("/path-test-links/rel", false).unwrap();
+        assert_eq!(link.getattr().unwrap().file_type, FileType::Symlink);
+        assert_eq!(link.readlink().unwrap(), "real/data");
+
+        assert_eq!(
+            lookup("/path-test-links/loop", true).err(),
+            Some(FsError::TooManyLinks)
+        );
+    }
+
+    #[test]
+    fn test_lookup_parent() {
+        setup();
+        mkdir_p("/path-test-parent/dir");
+
+        let parent = lookup_parent("/path-test-parent/dir/new/").unwrap();
+        assert_eq!(parent.path, "/path-test-parent/dir");
+        assert_eq!(parent.name, "new");
+        assert_eq!(parent.child_path(), "/path-test-parent/dir/new");
+
+        assert_eq!(lookup_parent("/").err(), Some(FsError::InvalidArgument));
+        assert_eq!(
+            lookup_parent("/path-test-parent/..").err(),
+            Some(FsError::InvalidArgument)
+        );
+        assert_eq!(
+            lookup_parent("/path-test-missing/new").err(),
+            Some(FsError::NotFound)
+        );
+    }
+}
@@ -3,12 +3,15 @@
 //! Maps IP addresses to MAC addresses on local networks.
 
 use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
 
-use super::ethernet::{EtherType, EthernetFrameBuilder, MacAddress};
+use super::ethernet::{EtherType, EthernetFrameBuilder, EthernetHeader, MacAddress};
 use super::ipv4::Ipv4Addr;
+use super::netdev::{self, NetDevice};
 
 /// ARP hardware types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -310,6 +313,170 @@ pub fn clear_cache() {
     ARP_CACHE.lock().clear();
 }
 
+/// Maximum number of packets queued per unresolved address
+pub const MAX_PENDING_PER_HOST: usize = 8;
+
+/// Maximum number of addresses waiting for resolution
+pub const MAX_PENDING_HOSTS: usize = 64;
+
+/// Time an address may stay unresolved before its packets are dropped
+/// (milliseconds)
+pub const PENDING_TIMEOUT_MS: u64 = 3000;
+
+/// Interval between ARP aging runs (milliseconds)
+pub const ARP_TIMER_INTERVAL_MS: u64 = 1000;
+
+/// IPv4 packet waiting for address resolution
+struct PendingPacket {
+    /// Device the packet will leave through
+    device: Arc<Mutex<dyn NetDevice>>,
+    /// IPv4 packet (without Ethernet header)
+    packet: Vec<u8>,
+}
+
+/// Packets waiting for one address
+struct PendingHost {
+    /// Uptime when the first packet was queued (milliseconds)
+    queued_at: u64,
+    packets: Vec<PendingPacket>,
+}
+
+/// Packets waiting for an ARP reply, keyed by next-hop address
+struct PendingQueues {
+    hosts: BTreeMap<u32, PendingHost>,
+}
+
+impl PendingQueues {
+    const fn new() -> Self {
+        Self {
+            hosts: BTreeMap::new(),
+        }
+    }
+
+    /// Queue a packet for `next_hop`
+    ///
+    /// When full, the oldest packet of the host or the host waiting
+    /// longest makes room. Returns the dropped packets.
+    fn push(&mut self, next_hop: u32, packet: PendingPacket, now: u64) -> Vec<PendingPacket> {
+        let mut dropped = Vec::new();
+        if !self.hosts.contains_key(&next_hop) && self.hosts.len() >= MAX_PENDING_HOSTS {
+            let oldest = self
+                .hosts
+                .iter()
+                .min_by_key(|(_, host)| host.queued_at)
+                .map(|(&ip, _)| ip);
+            if let Some(host) = oldest.and_then(|ip| self.hosts.remove(&ip)) {
+                dropped.extend(host.packets);
+            }
+        }
+
+        let host = self.hosts.entry(next_hop).or_insert_with(|| PendingHost {
+            queued_at: now,
+            packets: Vec::new(),
+        });
+        if host.packets.len() >= MAX_PENDING_PER_HOST {
+            dropped.push(host.packets.remove(0));
+        }
+        host.packets.push(packet);
+        dropped
+    }
+
+    /// Remove the hosts unresolved for longer than the timeout
+    fn expire(&mut self, now: u64) -> Vec<PendingPacket> {
+        let mut dropped = Vec::new();
+        self.hosts.retain(|_, host| {
+            let alive = now.saturating_sub(host.queued_at) < PENDING_TIMEOUT_MS;
+            if !alive {
+                dropped.append(&mut host.packets);
+            }
+            alive
+        });
+        dropped
+    }
+}
+
+/// Packets waiting for an ARP reply
+static ARP_PENDING: Mutex<PendingQueues> = Mutex::new(PendingQueues::new());
+
+/// Count packets that were given up on
+fn drop_pending(dropped: Vec<PendingPacket>) {
+    for pending in dropped {
+        pending.device.lock().stats().inc_tx_dropped();
+    }
+}
+
+/// Get number of packets waiting for resolution of an address
+pub fn pending_count(ip: Ipv4Addr) -> usize {
+    ARP_PENDING
+        .lock()
+        .hosts
+        .get(&ip.as_u32())
+        .map_or(0, |host| host.packets.len())
+}
+
+/// Frame an IPv4 packet and hand it to a device
+pub fn transmit_ipv4(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    dst_mac: MacAddress,
+    packet: &[u8],
+) -> Result<(), ArpError> {
+    let src_mac = device.lock().mac_address();
+    let mut frame = vec![0u8; EthernetHeader::SIZE + packet.len()];
+    EthernetFrameBuilder::new()
+        .dst(dst_mac)
+        .src(src_mac)
+        .ethertype(EtherType::Ipv4)
+        .build(&mut frame, packet)
+        .map_err(|_| ArpError::BufferTooSmall)?;
+
+    netdev::transmit(device, &frame).map_err(|_| ArpError::TransmitFailed)
+}
+
+/// Send an IPv4 packet to a next hop on the given device
+///
+/// Resolves the next hop's MAC address through the ARP cache. On a cache
+/// miss the packet is queued and an ARP request is broadcast; queued packets
+/// are sent once the reply arrives.
+pub fn send_ipv4_packet(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    src_ip: Ipv4Addr,
+    next_hop: Ipv4Addr,
+    packet: &[u8],
+) -> Result<(), ArpError> {
+    if next_hop.is_broadcast() {
+        return transmit_ipv4(device, MacAddress::BROADCAST, packet);
+    }
+
+    if let Some(mac) = lookup_mac(next_hop) {
+        return transmit_ipv4(device, mac, packet);
+    }
+
+    // Queue the packet until the address is resolved
+    let pending = PendingPacket {
+        device: device.clone(),
+        packet: packet.to_vec(),
+    };
+    let now = crate::time::uptime_ms();
+    let dropped = ARP_PENDING.lock().push(next_hop.as_u32(), pending, now);
+    drop_pending(dropped);
+
+    // Broadcast a request for the next hop
+    let src_mac = device.lock().mac_address();
+    let mut request = [0u8; EthernetHeader::SIZE + ArpHeader::SIZE];
+    let len = build_request(&mut request, src_mac, src_ip, next_hop)?;
+    netdev::transmit(device, &request[..len]).map_err(|_| ArpError::TransmitFailed)
+}
+
+/// Send packets that were waiting for an address to be resolved
+pub fn flush_pending(ip: Ipv4Addr, mac: MacAddress) {
+    let host = ARP_PENDING.lock().hosts.remove(&ip.as_u32());
+
+    for pending in host.into_iter().flat_map(|host| host.packets) {
+        // Transmit failures are recorded in the device statistics
+        let _ = transmit_ipv4(&pending.device, mac, &pending.packet);
+    }
+}
+
 /// Build ARP request packet
 pub fn build_request(
     buffer: &mut [u8],
@@ -379,13 +546,14 @@ pub fn process_packet(
     let arp_data = &data[14..];
     let header = ArpHeader::parse(arp_data)?;
 
-    // Add sender to ARP cache
+    // Add sender to ARP cache and release packets waiting for it
     insert_entry(header.sender_ip(), header.sender_mac());
+    flush_pending(header.sender_ip(), header.sender_mac());
 
     match header.get_operation() {
         ArpOperation::Request => {
             // Is this request for us?
-            if header.target_ip() == our_ip {
+            if !our_ip.is_unspecified() && header.target_ip() == our_ip {
                 // Build and send reply
                 let mut reply_buf = [0u8; 64];
                 let len = build_reply(
@@ -419,17 +587,33 @@ pub enum ArpError {
     InvalidOperation,
     /// Entry not found
     NotFound,
+    /// Device failed to transmit
+    TransmitFailed,
 }
 
 /// ARP subsystem initialized flag
 static ARP_INITIALIZED: AtomicBool = AtomicBool::new(false);
 
+/// Age the ARP cache and give up on addresses that were never resolved
+pub fn timer_tick() {
+    ARP_CACHE
+        .lock()
+        .update_ttl((ARP_TIMER_INTERVAL_MS / 1000) as u32);
+
+    let dropped = ARP_PENDING.lock().expire(crate::time::uptime_ms());
+    drop_pending(dropped);
+}
+
 /// Initialize ARP subsystem
 pub fn init() {
     if ARP_INITIALIZED.load(Ordering::Acquire) {
         return;
     }
 
+    if crate::time::timer::create_periodic_timer(ARP_TIMER_INTERVAL_MS, timer_tick).is_err() {
+        crate::printk::printk("  Failed to start ARP timer\n");
+    }
+
     ARP_INITIALIZED.store(true, Ordering::Release);
 }
 
@@ -441,6 +625,66 @@ pub fn is_initialized() -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::net::netdev::{DeviceCapabilities, DeviceStats, LinkState, NetDevError};
+
+    /// Device that records transmitted frames
+    struct RecordingDevice {
+        mac: MacAddress,
+        sent: Vec<Vec<u8>>,
+        stats: DeviceStats,
+    }
+
+    impl NetDevice for RecordingDevice {
+        fn name(&self) -> &str {
+            "rec0"
+        }
+
+        fn mac_address(&self) -> MacAddress {
+            self.mac
+        }
+
+        fn set_mac_address(&mut self, mac: MacAddress) -> Result<(), NetDevError> {
+            self.mac = mac;
+            Ok(())
+        }
+
+        fn link_state(&self) -> LinkState {
+            LinkState::Up
+        }
+
+        fn mtu(&self) -> usize {
+            1500
+        }
+
+        fn set_mtu(&mut self, _mtu: usize) -> Result<(), NetDevError> {
+            Err(NetDevError::NotSupported)
+        }
+
+        fn capabilities(&self) -> DeviceCapabilities {
+            DeviceCapabilities::default()
+        }
+
+        fn up(&mut self) -> Result<(), NetDevError> {
+            Ok(())
+        }
+
+        fn down(&mut self) -> Result<(), NetDevError> {
+            Ok(())
+        }
+
+        fn send(&mut self, packet: &[u8]) -> Result<(), NetDevError> {
+            self.sent.push(packet.to_vec());
+            Ok(())
+        }
+
+        fn recv(&mut self, _buffer: &mut [u8]) -> Result<usize, NetDevError> {
+            Err(NetDevError::WouldBlock)
+        }
+
+        fn stats(&self) -> &DeviceStats {
+            &self.stats
+        }
+    }
 
     #[test]
     fn test_arp_operation() {
@@ -484,4 +728,76 @@ mod tests {
         assert_eq!(header.sender_ip(), sender_ip);
         assert_eq!(header.target_ip(), target_ip);
     }
+
+    #[test]
+    fn test_send_queues_until_resolved() {
+        let our_mac = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        let our_ip = Ipv4Addr::new(10, 99, 0, 1);
+        let peer_mac = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x02]);
+        let peer_ip = Ipv4Addr::new(10, 99, 0, 2);
+
+        let recorder = Arc::new(Mutex::new(RecordingDevice {
+            mac: our_mac,
+            sent: Vec::new(),
+            stats: DeviceStats::new(),
+        }));
+        let device: Arc<Mutex<dyn NetDevice>> = recorder.clone();
+
+        // Cache miss: packet is queued and an ARP request goes out
+        send_ipv4_packet(&device, our_ip, peer_ip, &[0x45; 20]).unwrap();
+        assert_eq!(pending_count(peer_ip), 1);
+        {
+            let dev = recorder.lock();
+            assert_eq!(dev.sent.len(), 1);
+            let request = ArpHeader::parse(&dev.sent[0][EthernetHeader::SIZE..]).unwrap();
+            assert_eq!(request.get_operation(), ArpOperation::Request);
+            assert_eq!(request.target_ip(), peer_ip);
+        }
+
+        // Reply arrives: queued packet is framed and sent to the peer
+        let mut reply = [0u8; 64];
+        let len = build_reply(&mut reply, peer_mac, peer_ip, our_mac, our_ip).unwrap();
+        assert!(process_packet(&reply[..len], our_mac, our_ip)
+            .unwrap()
+            .is_none());
+        assert_eq!(pending_count(peer_ip), 0);
+
+        let dev = recorder.lock();
+        assert_eq!(dev.sent.len(), 2);
+        let frame = EthernetHeader::parse(&dev.sent[1]).unwrap();
+        assert_eq!(frame.dst, peer_mac);
+        assert_eq!(frame.get_ethertype(), EtherType::Ipv4);
+
+        remove_entry(peer_ip);
+    }
+
+    #[test]
+    fn test_pending_hosts_bounded_and_expire() {
+        let recorder = Arc::new(Mutex::new(RecordingDevice {
+            mac: MacAddress::BROADCAST,
+            sent: Vec::new(),
+            stats: DeviceStats::new(),
+        }));
+        let device: Arc<Mutex<dyn NetDevice>> = recorder.clone();
+        let packet = || PendingPacket {
+            device: device.clone(),
+            packet: vec![0x45; 20],
+        };
+
+        // Every further unresolvable host pushes out the one waiting longest
+        let mut queues = PendingQueues::new();
+        for host in 0..MAX_PENDING_HOSTS as u32 + 10 {
+            let dropped = queues.push(host, packet(), u64::from(host));
+            assert_eq!(dropped.len(), usize::from(host >= MAX_PENDING_HOSTS as u32));
+        }
+        assert_eq!(queues.hosts.len(), MAX_PENDING_HOSTS);
+        assert!(!queues.hosts.contains_key(&0));
+
+        // Hosts still unresolved after the timeout are dropped
+        let last = MAX_PENDING_HOSTS as u64 + 9;
+        assert!(queues.expire(last).is_empty());
+        let dropped = queues.expire(last + PENDING_TIMEOUT_MS);
+        assert_eq!(dropped.len(), MAX_PENDING_HOSTS);
+        assert!(queues.hosts.is_empty());
+    }
 }
@@ -0,0 +1,1089 @@
+//! DHCPv4 Client
+//!
+//! Obtains an address lease (RFC 2131) over a UDP socket on port 68 and
+//! applies it to the interface and routing table. Leases are renewed with
+//! the server at T1 and with any server at T2; expired leases are removed.
+//! The client is driven by a periodic kernel timer.
+//!
+//! Broadcasts leave through the default network device, so that is the
+//! device the client is meant to configure.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use super::ethernet::MacAddress;
+use super::ip::{IpAddr, IpEndpoint};
+use super::ipv4::addr::{self, AddrError};
+use super::ipv4::route::{self, Route, RouteError};
+use super::ipv4::Ipv4Addr;
+use super::netdev;
+use super::socket::SocketError;
+use super::udp::UdpSocket;
+
+/// Server port
+pub const DHCP_SERVER_PORT: u16 = 67;
+
+/// Client port
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+/// Interval between client timer runs (milliseconds)
+pub const DHCP_TIMER_INTERVAL_MS: u64 = 500;
+
+/// First retransmission timeout; doubled on every retry (milliseconds)
+const INITIAL_TIMEOUT_MS: u64 = 4000;
+
+/// Largest retransmission timeout (milliseconds)
+const MAX_TIMEOUT_MS: u64 = 64_000;
+
+/// Shortest wait between renewal attempts (milliseconds)
+const MIN_RENEW_RETRY_MS: u64 = 60_000;
+
+/// REQUEST retransmissions before falling back to DISCOVER
+const MAX_REQUEST_RETRIES: u32 = 4;
+
+/// Lease time meaning the lease never expires
+pub const INFINITE_LEASE: u32 = u32::MAX;
+
+/// BOOTP operation: client to server
+const BOOTREQUEST: u8 = 1;
+
+/// BOOTP operation: server to client
+const BOOTREPLY: u8 = 2;
+
+/// Ethernet hardware type
+const HTYPE_ETHERNET: u8 = 1;
+
+/// Asks the server to broadcast its replies
+const BROADCAST_FLAG: u16 = 0x8000;
+
+/// Marks the start of the options field
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// Fixed BOOTP header size, before the magic cookie
+const HEADER_SIZE: usize = 236;
+
+/// Minimum BOOTP message size (RFC 951)
+const MIN_MESSAGE_SIZE: usize = 300;
+
+/// Largest message the client accepts
+const MAX_MESSAGE_SIZE: usize = 1500;
+
+/// Option codes
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS_SERVER: u8 = 6;
+const OPT_DOMAIN_NAME: u8 = 15;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETER_LIST: u8 = 55;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_REBINDING_TIME: u8 = 59;
+const OPT_END: u8 = 255;
+
+/// Options the client asks servers for
+const REQUESTED_PARAMETERS: [u8; 7] = [
+    OPT_SUBNET_MASK,
+    OPT_ROUTER,
+    OPT_DNS_SERVER,
+    OPT_DOMAIN_NAME,
+    OPT_LEASE_TIME,
+    OPT_RENEWAL_TIME,
+    OPT_REBINDING_TIME,
+];
+
+/// DHCP message types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+    Inform = 8,
+}
+
+impl MessageType {
+    /// Convert from the option value
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Discover),
+            2 => Some(Self::Offer),
+            3 => Some(Self::Request),
+            4 => Some(Self::Decline),
+            5 => Some(Self::Ack),
+            6 => Some(Self::Nak),
+            7 => Some(Self::Release),
+            8 => Some(Self::Inform),
+            _ => None,
+        }
+    }
+}
+
+/// Options the client understands
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DhcpOptions {
+    /// DHCP message type
+    pub message_type: Option<MessageType>,
+    /// Subnet mask
+    pub subnet_mask: Option<Ipv4Addr>,
+    /// First router
+    pub router: Option<Ipv4Addr>,
+    /// DNS servers
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// DNS domain name
+    pub domain_name: Option<String>,
+    /// Address asked for in a REQUEST
+    pub requested_ip: Option<Ipv4Addr>,
+    /// Lease time (seconds)
+    pub lease_time: Option<u32>,
+    /// Server identifier
+    pub server_id: Option<Ipv4Addr>,
+    /// Options the client asks for
+    pub parameter_list: Vec<u8>,
+    /// T1 (seconds)
+    pub renewal_time: Option<u32>,
+    /// T2 (seconds)
+    pub rebinding_time: Option<u32>,
+}
+
+impl DhcpOptions {
+    /// Parse the options field, after the magic cookie
+    fn parse(data: &[u8]) -> Result<Self, DhcpError> {
+        let mut options = Self::default();
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let code = data[offset];
+            match code {
+                OPT_PAD => {
+                    offset += 1;
+                    continue;
+                }
+                OPT_END => break,
+                _ => {}
+            }
+
+            let len = *data.get(offset + 1).ok_or(DhcpError::InvalidOption)? as usize;
+            let value = data
+                .get(offset + 2..offset + 2 + len)
+                .ok_or(DhcpError::InvalidOption)?;
+            offset += 2 + len;
+
+            match code {
+                OPT_SUBNET_MASK => options.subnet_mask = Some(addr_option(value)?),
+                OPT_ROUTER => options.router = addr_list_option(value)?.first().copied(),
+                OPT_DNS_SERVER => options.dns_servers = addr_list_option(value)?,
+                OPT_DOMAIN_NAME => {
+                    let name = core::str::from_utf8(value).map_err(|_| DhcpError::InvalidOption)?;
+                    options.domain_name = Some(name.trim_end_matches('\0').to_string());
+                }
+                OPT_REQUESTED_IP => options.requested_ip = Some(addr_option(value)?),
+                OPT_LEASE_TIME => options.lease_time = Some(u32_option(value)?),
+                OPT_MESSAGE_TYPE => {
+                    let [value] = value else {
+                        return Err(DhcpError::InvalidOption);
+                    };
+                    options.message_type = MessageType::from_u8(*value);
+                }
+                OPT_SERVER_ID => options.server_id = Some(addr_option(value)?),
+                OPT_PARAMETER_LIST => options.parameter_list = value.to_vec(),
+                OPT_RENEWAL_TIME => options.renewal_time = Some(u32_option(value)?),
+                OPT_REBINDING_TIME => options.rebinding_time = Some(u32_option(value)?),
+                // Unknown options are skipped
+                _ => {}
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Append the options and the end option to a buffer
+    fn write_to(&self, buffer: &mut Vec<u8>) {
+        if let Some(message_type) = self.message_type {
+            buffer.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, message_type as u8]);
+        }
+        let addrs = [
+            (OPT_SUBNET_MASK, self.subnet_mask),
+            (OPT_ROUTER, self.router),
+            (OPT_REQUESTED_IP, self.requested_ip),
+            (OPT_SERVER_ID, self.server_id),
+        ];
+        for (code, addr) in addrs {
+            if let Some(addr) = addr {
+                buffer.extend_from_slice(&[code, 4]);
+                buffer.extend_from_slice(&addr.octets());
+            }
+        }
+        if !self.dns_servers.is_empty() {
+            buffer.extend_from_slice(&[OPT_DNS_SERVER, (self.dns_servers.len() * 4) as u8]);
+            for server in &self.dns_servers {
+                buffer.extend_from_slice(&server.octets());
+            }
+        }
+        if let Some(name) = &self.domain_name {
+            buffer.extend_from_slice(&[OPT_DOMAIN_NAME, name.len() as u8]);
+            buffer.extend_from_slice(name.as_bytes());
+        }
+        let times = [
+            (OPT_LEASE_TIME, self.lease_time),
+            (OPT_RENEWAL_TIME, self.renewal_time),
+            (OPT_REBINDING_TIME, self.rebinding_time),
+        ];
+        for (code, time) in times {
+            if let Some(time) = time {
+                buffer.extend_from_slice(&[code, 4]);
+                buffer.extend_from_slice(&time.to_be_bytes());
+            }
+        }
+        if !self.parameter_list.is_empty() {
+            buffer.extend_from_slice(&[OPT_PARAMETER_LIST, self.parameter_list.len() as u8]);
+            buffer.extend_from_slice(&self.parameter_list);
+        }
+        buffer.push(OPT_END);
+    }
+}
+
+/// Parse an option holding one address
+fn addr_option(value: &[u8]) -> Result<Ipv4Addr, DhcpError> {
+    let octets: [u8; 4] = value.try_into().map_err(|_| DhcpError::InvalidOption)?;
+    Ok(Ipv4Addr(octets))
+}
+
+/// Parse an option holding a list of addresses
+fn addr_list_option(value: &[u8]) -> Result<Vec<Ipv4Addr>, DhcpError> {
+    if value.is_empty() || !value.len().is_multiple_of(4) {
+        return Err(DhcpError::InvalidOption);
+    }
+    Ok(value
+        .chunks_exact(4)
+        .map(|c| Ipv4Addr([c[0], c[1], c[2], c[3]]))
+        .collect())
+}
+
+/// Parse an option holding a 32-bit integer
+fn u32_option(value: &[u8]) -> Result<u32, DhcpError> {
+    let bytes: [u8; 4] = value.try_into().map_err(|_| DhcpError::InvalidOption)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+/// DHCP message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpMessage {
+    /// BOOTP operation
+    pub op: u8,
+    /// Transaction ID
+    pub xid: u32,
+    /// Seconds since the client began acquiring or renewing
+    pub secs: u16,
+    /// Flags
+    pub flags: u16,
+    /// Client address, when it has one
+    pub ciaddr: Ipv4Addr,
+    /// Address offered to the client
+    pub yiaddr: Ipv4Addr,
+    /// Next server address
+    pub siaddr: Ipv4Addr,
+    /// Relay agent address
+    pub giaddr: Ipv4Addr,
+    /// Client hardware address
+    pub chaddr: MacAddress,
+    /// Options
+    pub options: DhcpOptions,
+}
+
+impl DhcpMessage {
+    /// Create a client message
+    pub fn request(xid: u32, chaddr: MacAddress, message_type: MessageType) -> Self {
+        Self {
+            op: BOOTREQUEST,
+            xid,
+            secs: 0,
+            flags: 0,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr,
+            options: DhcpOptions {
+                message_type: Some(message_type),
+                ..DhcpOptions::default()
+            },
+        }
+    }
+
+    /// Parse a message
+    pub fn parse(data: &[u8]) -> Result<Self, DhcpError> {
+        if data.len() < HEADER_SIZE + MAGIC_COOKIE.len() {
+            return Err(DhcpError::TooShort);
+        }
+        if data[1] != HTYPE_ETHERNET || data[2] != 6 {
+            return Err(DhcpError::InvalidMessage);
+        }
+        if data[HEADER_SIZE..HEADER_SIZE + 4] != MAGIC_COOKIE {
+            return Err(DhcpError::InvalidMessage);
+        }
+
+        let addr_at = |offset: usize| {
+            Ipv4Addr([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+        let mut chaddr = [0u8; 6];
+        chaddr.copy_from_slice(&data[28..34]);
+
+        Ok(Self {
+            op: data[0],
+            xid: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            secs: u16::from_be_bytes([data[8], data[9]]),
+            flags: u16::from_be_bytes([data[10], data[11]]),
+            ciaddr: addr_at(12),
+            yiaddr: addr_at(16),
+            siaddr: addr_at(20),
+            giaddr: addr_at(24),
+            chaddr: MacAddress(chaddr),
+            options: DhcpOptions::parse(&data[HEADER_SIZE + 4..])?,
+        })
+    }
+
+    /// Serialize the message, padded to the BOOTP minimum size
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = vec![0u8; HEADER_SIZE];
+        buffer[0] = self.op;
+        buffer[1] = HTYPE_ETHERNET;
+        buffer[2] = 6;
+        buffer[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        buffer[8..10].copy_from_slice(&self.secs.to_be_bytes());
+        buffer[10..12].copy_from_slice(&self.flags.to_be_bytes());
+        buffer[12..16].copy_from_slice(&self.ciaddr.octets());
+        buffer[16..20].copy_from_slice(&self.yiaddr.octets());
+        buffer[20..24].copy_from_slice(&self.siaddr.octets());
+        buffer[24..28].copy_from_slice(&self.giaddr.octets());
+        buffer[28..34].copy_from_slice(&self.chaddr.octets());
+
+        buffer.extend_from_slice(&MAGIC_COOKIE);
+        self.options.write_to(&mut buffer);
+        if buffer.len() < MIN_MESSAGE_SIZE {
+            buffer.resize(MIN_MESSAGE_SIZE, OPT_PAD);
+        }
+        buffer
+    }
+
+    /// Get the message type
+    pub fn message_type(&self) -> Option<MessageType> {
+        self.options.message_type
+    }
+}
+
+/// Address lease
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpLease {
+    /// Leased address
+    pub address: Ipv4Addr,
+    /// Subnet prefix length
+    pub prefix_len: u8,
+    /// Default gateway
+    pub router: Option<Ipv4Addr>,
+    /// DNS servers
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// DNS domain name
+    pub domain_name: Option<String>,
+    /// Server that granted the lease
+    pub server: Ipv4Addr,
+    /// Lease time (seconds)
+    pub lease_time: u32,
+    /// T1 (seconds)
+    pub renewal_time: u32,
+    /// T2 (seconds)
+    pub rebinding_time: u32,
+    /// Uptime when the lease was granted (milliseconds)
+    pub acquired_at: u64,
+}
+
+impl DhcpLease {
+    /// Build a lease from an OFFER or ACK
+    ///
+    /// T1 and T2 default to 50% and 87.5% of the lease time. Without a
+    /// subnet mask the classful prefix of the address is used.
+    fn from_message(message: &DhcpMessage, now: u64) -> Option<Self> {
+        let options = &message.options;
+        if message.yiaddr.is_unspecified() {
+            return None;
+        }
+        let server = options.server_id.or_else(|| {
+            // Some servers leave the identifier out of renewal ACKs
+            Some(message.siaddr).filter(|a| !a.is_unspecified())
+        })?;
+
+        let lease_time = options.lease_time.unwrap_or(INFINITE_LEASE);
+        let (renewal_time, rebinding_time) = if lease_time == INFINITE_LEASE {
+            (INFINITE_LEASE, INFINITE_LEASE)
+        } else {
+            (
+                options.renewal_time.unwrap_or(lease_time / 2),
+                options
+                    .rebinding_time
+                    .unwrap_or((lease_time as u64 * 7 / 8) as u32),
+            )
+        };
+
+        Some(Self {
+            address: message.yiaddr,
+            prefix_len: options
+                .subnet_mask
+                .map_or_else(|| classful_prefix(message.yiaddr), mask_prefix),
+            router: options.router,
+            dns_servers: options.dns_servers.clone(),
+            domain_name: options.domain_name.clone(),
+            server,
+            lease_time,
+            renewal_time,
+            rebinding_time,
+            acquired_at: now,
+        })
+    }
+
+    /// Uptime at which renewal starts (T1)
+    pub fn renew_at(&self) -> u64 {
+        deadline(self.acquired_at, self.renewal_time)
+    }
+
+    /// Uptime at which rebinding starts (T2)
+    pub fn rebind_at(&self) -> u64 {
+        deadline(self.acquired_at, self.rebinding_time)
+    }
+
+    /// Uptime at which the lease expires
+    pub fn expires_at(&self) -> u64 {
+        deadline(self.acquired_at, self.lease_time)
+    }
+}
+
+/// Uptime a number of lease seconds after a start time
+fn deadline(start: u64, seconds: u32) -> u64 {
+    if seconds == INFINITE_LEASE {
+        u64::MAX
+    } else {
+        start.saturating_add(seconds as u64 * 1000)
+    }
+}
+
+/// Prefix length of a subnet mask
+pub fn mask_prefix(mask: Ipv4Addr) -> u8 {
+    mask.as_u32().leading_ones() as u8
+}
+
+/// Prefix length of the class an address belongs to
+pub fn classful_prefix(address: Ipv4Addr) -> u8 {
+    match address.octets()[0] {
+        0..=127 => 8,
+        128..=191 => 16,
+        _ => 24,
+    }
+}
+
+/// Client state (RFC 2131 figure 5)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpState {
+    /// Not started, or released
+    Init,
+    /// Broadcasting DISCOVER and waiting for an OFFER
+    Selecting,
+    /// Requesting an offered address
+    Requesting,
+    /// Holding a lease
+    Bound,
+    /// Extending the lease with the server that granted it
+    Renewing,
+    /// Extending the lease with any server
+    Rebinding,
+}
+
+/// Work the client asks its driver to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DhcpAction {
+    /// Send a message to a server, or broadcast it
+    Send(DhcpMessage, Ipv4Addr),
+    /// Apply a lease to the interface
+    Configure(DhcpLease),
+    /// Remove a lease from the interface
+    Deconfigure(DhcpLease),
+}
+
+/// DHCP client state machine for one interface
+///
+/// The client performs no I/O: received messages and timer ticks go in,
+/// and the messages to send and the configuration changes come out as
+/// [`DhcpAction`]s.
+pub struct DhcpClient {
+    /// Hardware address
+    mac: MacAddress,
+    /// Current transaction ID
+    xid: u32,
+    /// Current state
+    state: DhcpState,
+    /// Offer being requested
+    offer: Option<DhcpLease>,
+    /// Lease held
+    lease: Option<DhcpLease>,
+    /// Uptime the current exchange started at
+    started_at: u64,
+    /// Uptime of the next retransmission or state change
+    deadline: u64,
+    /// Current retransmission timeout
+    timeout: u64,
+    /// Retransmissions of the current message
+    retries: u32,
+}
+
+impl DhcpClient {
+    /// Create a client for a hardware address
+    pub fn new(mac: MacAddress, xid: u32) -> Self {
+        Self {
+            mac,
+            xid,
+            state: DhcpState::Init,
+            offer: None,
+            lease: None,
+            started_at: 0,
+            deadline: u64::MAX,
+            timeout: INITIAL_TIMEOUT_MS,
+            retries: 0,
+        }
+    }
+
+    /// Get the current state
+    pub fn state(&self) -> DhcpState {
+        self.state
+    }
+
+    /// Get the lease held
+    pub fn lease(&self) -> Option<&DhcpLease> {
+        self.lease.as_ref()
+    }
+
+    /// Start acquiring a lease
+    pub fn start(&mut self, now: u64) -> Vec<DhcpAction> {
+        self.state = DhcpState::Selecting;
+        self.offer = None;
+        self.started_at = now;
+        self.retries = 0;
+        self.arm_retransmit(now, INITIAL_TIMEOUT_MS);
+        vec![self.discover(now)]
+    }
+
+    /// Handle a message from a server
+    pub fn receive(&mut self, message: &DhcpMessage, now: u64) -> Vec<DhcpAction> {
+        if message.op != BOOTREPLY || message.xid != self.xid || message.chaddr != self.mac {
+            return Vec::new();
+        }
+
+        match (self.state, message.message_type()) {
+            (DhcpState::Selecting, Some(MessageType::Offer)) => {
+                let Some(offer) = DhcpLease::from_message(message, now) else {
+                    return Vec::new();
+                };
+                self.offer = Some(offer);
+                self.state = DhcpState::Requesting;
+                self.retries = 0;
+                self.arm_retransmit(now, INITIAL_TIMEOUT_MS);
+                vec![self.select_request(now)]
+            }
+            (
+                DhcpState::Requesting | DhcpState::Renewing | DhcpState::Rebinding,
+                Some(MessageType::Ack),
+            ) => {
+                let Some(mut lease) = DhcpLease::from_message(message, now) else {
+                    return Vec::new();
+                };
+                // Lease times count from when the request was sent
+                lease.acquired_at = self.started_at;
+
+                let mut actions = Vec::new();
+                if let Some(old) = self.lease.take() {
+                    if old.address != lease.address || old.prefix_len != lease.prefix_len {
+                        actions.push(DhcpAction::Deconfigure(old));
+                    }
+                }
+                self.state = DhcpState::Bound;
+                self.offer = None;
+                self.deadline = lease.renew_at();
+                self.lease = Some(lease.clone());
+                actions.push(DhcpAction::Configure(lease));
+                actions
+            }
+            (
+                DhcpState::Requesting | DhcpState::Renewing | DhcpState::Rebinding,
+                Some(MessageType::Nak),
+            ) => self.restart(now),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Retransmit and move through the lease lifetime
+    pub fn tick(&mut self, now: u64) -> Vec<DhcpAction> {
+        if now < self.deadline {
+            return Vec::new();
+        }
+
+        match self.state {
+            DhcpState::Init => Vec::new(),
+            DhcpState::Selecting => {
+                self.arm_retransmit(now, (self.timeout * 2).min(MAX_TIMEOUT_MS));
+                vec![self.discover(now)]
+            }
+            DhcpState::Requesting => {
+                self.retries += 1;
+                if self.retries > MAX_REQUEST_RETRIES {
+                    return self.restart(now);
+                }
+                self.arm_retransmit(now, (self.timeout * 2).min(MAX_TIMEOUT_MS));
+                vec![self.select_request(now)]
+            }
+            DhcpState::Bound | DhcpState::Renewing | DhcpState::Rebinding => {
+                let Some(lease) = self.lease.clone() else {
+                    return self.restart(now);
+                };
+
+                if now >= lease.expires_at() {
+                    return self.restart(now);
+                }
+
+                let (state, limit) = if now >= lease.rebind_at() {
+                    (DhcpState::Rebinding, lease.expires_at())
+                } else {
+                    (DhcpState::Renewing, lease.rebind_at())
+                };
+                if state != self.state {
+                    self.state = state;
+                    self.started_at = now;
+                }
+
+                // Wait half the time left, but at least a minute (RFC 2131 4.4.5)
+                let wait = ((limit - now) / 2).max(MIN_RENEW_RETRY_MS);
+                self.deadline = now.saturating_add(wait).min(limit);
+
+                let destination = match state {
+                    DhcpState::Renewing => lease.server,
+                    _ => Ipv4Addr::BROADCAST,
+                };
+                vec![DhcpAction::Send(
+                    self.renew_request(&lease, now),
+                    destination,
+                )]
+            }
+        }
+    }
+
+    /// Give up the lease
+    pub fn release(&mut self, now: u64) -> Vec<DhcpAction> {
+        self.state = DhcpState::Init;
+        self.offer = None;
+        self.deadline = u64::MAX;
+
+        let Some(lease) = self.lease.take() else {
+            return Vec::new();
+        };
+        let mut message = self.message(MessageType::Release, now);
+        message.ciaddr = lease.address;
+        message.options.server_id = Some(lease.server);
+        message.options.parameter_list.clear();
+        vec![
+            DhcpAction::Send(message, lease.server),
+            DhcpAction::Deconfigure(lease),
+        ]
+    }
+
+    /// Drop any lease and start over with a new transaction
+    fn restart(&mut self, now: u64) -> Vec<DhcpAction> {
+        let mut actions = Vec::new();
+        if let Some(lease) = self.lease.take() {
+            actions.push(DhcpAction::Deconfigure(lease));
+        }
+        self.xid = self.xid.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        actions.extend(self.start(now));
+        actions
+    }
+
+    /// Schedule the next retransmission
+    fn arm_retransmit(&mut self, now: u64, timeout: u64) {
+        self.timeout = timeout;
+        self.deadline = now + timeout;
+    }
+
+    /// Build a client message for the current transaction
+    fn message(&self, message_type: MessageType, now: u64) -> DhcpMessage {
+        let mut message = DhcpMessage::request(self.xid, self.mac, message_type);
+        message.secs = ((now - self.started_at) / 1000).min(u16::MAX as u64) as u16;
+        message.options.parameter_list = REQUESTED_PARAMETERS.to_vec();
+        message
+    }
+
+    /// Broadcast DISCOVER
+    fn discover(&self, now: u64) -> DhcpAction {
+        let mut message = self.message(MessageType::Discover, now);
+        message.flags = BROADCAST_FLAG;
+        DhcpAction::Send(message, Ipv4Addr::BROADCAST)
+    }
+
+    /// Broadcast REQUEST for the selected offer
+    fn select_request(&self, now: u64) -> DhcpAction {
+        let mut message = self.message(MessageType::Request, now);
+        message.flags = BROADCAST_FLAG;
+        if let Some(offer) = &self.offer {
+            message.options.requested_ip = Some(offer.address);
+            message.options.server_id = Some(offer.server);
+        }
+        DhcpAction::Send(message, Ipv4Addr::BROADCAST)
+    }
+
+    /// REQUEST extending a lease
+    fn renew_request(&self, lease: &DhcpLease, now: u64) -> DhcpMessage {
+        let mut message = self.message(MessageType::Request, now);
+        message.ciaddr = lease.address;
+        message
+    }
+}
+
+/// Clients by device name
+static CLIENTS: Mutex<BTreeMap<String, DhcpClient>> = Mutex::new(BTreeMap::new());
+
+/// Socket bound to the client port, shared by all clients
+static SOCKET: Mutex<Option<UdpSocket>> = Mutex::new(None);
+
+/// Client timer started flag
+static TIMER_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Start acquiring a lease for a device
+pub fn start(device: &str) -> Result<(), DhcpError> {
+    let dev = netdev::get_device(device).ok_or(DhcpError::NoDevice)?;
+    let mac = {
+        let dev = dev.lock();
+        if dev.is_loopback() {
+            return Err(DhcpError::NoDevice);
+        }
+        dev.mac_address()
+    };
+
+    {
+        let mut socket = SOCKET.lock();
+        if socket.is_none() {
+            let mut udp = UdpSocket::new().map_err(DhcpError::Socket)?;
+            udp.bind(IpEndpoint::new(
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                DHCP_CLIENT_PORT,
+            ))
+            .map_err(DhcpError::Socket)?;
+            *socket = Some(udp);
+        }
+    }
+
+    let actions = {
+        let mut clients = CLIENTS.lock();
+        if clients.contains_key(device) {
+            return Err(DhcpError::AlreadyRunning);
+        }
+        let xid = crate::security::aslr::get_random_u64() as u32;
+        let mut client = DhcpClient::new(mac, xid);
+        let actions = client.start(crate::time::uptime_ms());
+        clients.insert(device.to_string(), client);
+        actions
+    };
+
+    if !TIMER_STARTED.swap(true, Ordering::AcqRel)
+        && crate::time::timer::create_periodic_timer(DHCP_TIMER_INTERVAL_MS, timer_tick).is_err()
+    {
+        TIMER_STARTED.store(false, Ordering::Release);
+        crate::printk::printk("  Failed to start DHCP timer\n");
+    }
+
+    perform(device, actions);
+    Ok(())
+}
+
+/// Release the lease of a device and stop its client
+pub fn release(device: &str) -> Result<(), DhcpError> {
+    let mut client = CLIENTS.lock().remove(device).ok_or(DhcpError::NotRunning)?;
+    let actions = client.release(crate::time::uptime_ms());
+    perform(device, actions);
+    Ok(())
+}
+
+/// Get the state of a device's client
+pub fn state(device: &str) -> Option<DhcpState> {
+    CLIENTS.lock().get(device).map(|c| c.state())
+}
+
+/// Get the lease held for a device
+pub fn lease(device: &str) -> Option<DhcpLease> {
+    CLIENTS.lock().get(device).and_then(|c| c.lease().cloned())
+}
+
+/// Get the DNS servers of all leases
+pub fn nameservers() -> Vec<Ipv4Addr> {
+    let mut servers = Vec::new();
+    for client in CLIENTS.lock().values() {
+        for server in client.lease().map_or(&[][..], |l| &l.dns_servers) {
+            if !servers.contains(server) {
+                servers.push(*server);
+            }
+        }
+    }
+    servers
+}
+
+/// Receive server replies and drive client timers
+///
+/// Called from a periodic kernel timer.
+pub fn timer_tick() {
+    let now = crate::time::uptime_ms();
+    let mut pending = Vec::new();
+    let mut buffer = vec![0u8; MAX_MESSAGE_SIZE];
+
+    loop {
+        let received = match SOCKET.lock().as_mut() {
+            Some(socket) => socket.recv_from(&mut buffer),
+            None => return,
+        };
+        let Ok((len, src)) = received else {
+            break;
+        };
+        if src.port != DHCP_SERVER_PORT {
+            continue;
+        }
+        let Ok(message) = DhcpMessage::parse(&buffer[..len]) else {
+            continue;
+        };
+
+        let mut clients = CLIENTS.lock();
+        if let Some((name, client)) = clients.iter_mut().find(|(_, c)| c.mac == message.chaddr) {
+            pending.push((name.clone(), client.receive(&message, now)));
+        }
+    }
+
+    for (name, client) in CLIENTS.lock().iter_mut() {
+        pending.push((name.clone(), client.tick(now)));
+    }
+
+    for (name, actions) in pending {
+        perform(&name, actions);
+    }
+}
+
+/// Carry out client actions for a device
+fn perform(device: &str, actions: Vec<DhcpAction>) {
+    for action in actions {
+        match action {
+            DhcpAction::Send(message, dst) => {
+                if let Some(socket) = SOCKET.lock().as_mut() {
+                    let dst = IpEndpoint::new(IpAddr::V4(dst), DHCP_SERVER_PORT);
+                    let _ = socket.send_to(&message.to_bytes(), dst);
+                }
+            }
+            DhcpAction::Configure(lease) => apply_lease(device, &lease),
+            DhcpAction::Deconfigure(lease) => remove_lease(device, &lease),
+        }
+    }
+}
+
+/// Assign the leased address and default route
+fn apply_lease(device: &str, lease: &DhcpLease) {
+    match addr::add_address(device, lease.address, lease.prefix_len) {
+        Ok(()) => crate::printkln!(
+            "dhcp: {} leased {}/{} from {}",
+            device,
+            lease.address,
+            lease.prefix_len,
+            lease.server
+        ),
+        Err(AddrError::AddressExists) => {}
+        Err(_) => return,
+    }
+
+    let Some(router) = lease.router else {
+        return;
+    };
+    if route::set_default_gateway(router, device) == Err(RouteError::GatewayUnreachable) {
+        // The gateway lies outside the leased subnet; reach it on-link
+        let _ = route::add_route(Route::new(router, 32, device).with_source(lease.address));
+        let _ = route::set_default_gateway(router, device);
+    }
+}
+
+/// Remove the leased address and the routes installed for it
+fn remove_lease(device: &str, lease: &DhcpLease) {
+    if let Some(router) = lease.router {
+        let ours =
+            route::default_route().is_some_and(|r| r.device == device && r.gateway == Some(router));
+        if ours {
+            let _ = route::remove_default_gateway();
+        }
+        let _ = route::remove_route(router, 32, Some(device));
+    }
+    let _ = addr::remove_address(device, lease.address);
+}
+
+/// DHCP errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpError {
+    /// Message too short
+    TooShort,
+    /// Not a DHCP message for Ethernet
+    InvalidMessage,
+    /// Malformed option
+    InvalidOption,
+    /// No such device, or the device cannot be configured
+    NoDevice,
+    /// A client is already running for the device
+    AlreadyRunning,
+    /// No client is running for the device
+    NotRunning,
+    /// Client socket error
+    Socket(SocketError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddress = MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+    const LEASED: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+
+    /// Server reply to the client's transaction
+    fn reply(xid: u32, message_type: MessageType, lease_time: u32) -> DhcpMessage {
+        let mut message = DhcpMessage::request(xid, MAC, message_type);
+        message.op = BOOTREPLY;
+        message.yiaddr = LEASED;
+        message.options.subnet_mask = Some(Ipv4Addr::new(255, 255, 255, 0));
+        message.options.router = Some(SERVER);
+        message.options.dns_servers = vec![Ipv4Addr::new(10, 0, 2, 3)];
+        message.options.server_id = Some(SERVER);
+        message.options.lease_time = Some(lease_time);
+        message
+    }
+
+    fn sent(actions: &[DhcpAction]) -> Option<(&DhcpMessage, Ipv4Addr)> {
+        actions.iter().find_map(|a| match a {
+            DhcpAction::Send(message, dst) => Some((message, *dst)),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        let mut message = reply(0x1234_5678, MessageType::Offer, 3600);
+        message.options.domain_name = Some("example.org".to_string());
+        message.options.renewal_time = Some(1800);
+
+        let bytes = message.to_bytes();
+        assert!(bytes.len() >= MIN_MESSAGE_SIZE);
+        assert_eq!(bytes[HEADER_SIZE..HEADER_SIZE + 4], MAGIC_COOKIE);
+        assert_eq!(DhcpMessage::parse(&bytes), Ok(message));
+
+        assert_eq!(DhcpMessage::parse(&bytes[..200]), Err(DhcpError::TooShort));
+        let mut truncated = bytes.clone();
+        truncated.truncate(HEADER_SIZE + 6);
+        truncated[HEADER_SIZE + 4] = OPT_LEASE_TIME;
+        truncated[HEADER_SIZE + 5] = 4;
+        assert_eq!(
+            DhcpMessage::parse(&truncated),
+            Err(DhcpError::InvalidOption)
+        );
+    }
+
+    #[test]
+    fn test_acquire_renew_rebind() {
+        let mut client = DhcpClient::new(MAC, 42);
+
+        let actions = client.start(0);
+        let (discover, dst) = sent(&actions).unwrap();
+        assert_eq!(discover.message_type(), Some(MessageType::Discover));
+        assert_eq!(dst, Ipv4Addr::BROADCAST);
+
+        // Replies for another transaction are ignored
+        assert!(client
+            .receive(&reply(7, MessageType::Offer, 3600), 100)
+            .is_empty());
+
+        let actions = client.receive(&reply(42, MessageType::Offer, 3600), 100);
+        let (request, _) = sent(&actions).unwrap();
+        assert_eq!(request.message_type(), Some(MessageType::Request));
+        assert_eq!(request.options.requested_ip, Some(LEASED));
+        assert_eq!(request.options.server_id, Some(SERVER));
+        assert_eq!(client.state(), DhcpState::Requesting);
+
+        let actions = client.receive(&reply(42, MessageType::Ack, 3600), 200);
+        let DhcpAction::Configure(lease) = &actions[0] else {
+            panic!("expected the lease to be applied");
+        };
+        assert_eq!(lease.prefix_len, 24);
+        assert_eq!(lease.renew_at(), 1_800_000);
+        assert_eq!(lease.rebind_at(), 3_150_000);
+        assert_eq!(client.state(), DhcpState::Bound);
+
+        // Nothing happens before T1
+        assert!(client.tick(1_799_999).is_empty());
+
+        let actions = client.tick(1_800_000);
+        let (renew, dst) = sent(&actions).unwrap();
+        assert_eq!(dst, SERVER);
+        assert_eq!(renew.ciaddr, LEASED);
+        assert_eq!(client.state(), DhcpState::Renewing);
+
+        let actions = client.tick(3_150_000);
+        assert_eq!(sent(&actions).unwrap().1, Ipv4Addr::BROADCAST);
+        assert_eq!(client.state(), DhcpState::Rebinding);
+
+        // The lease expires unanswered and the client starts over
+        let actions = client.tick(3_600_000);
+        assert!(matches!(actions[0], DhcpAction::Deconfigure(_)));
+        let (discover, _) = sent(&actions).unwrap();
+        assert_eq!(discover.message_type(), Some(MessageType::Discover));
+        assert_eq!(client.state(), DhcpState::Selecting);
+        assert!(client.lease().is_none());
+    }
+
+    #[test]
+    fn test_nak_and_retransmit() {
+        let mut client = DhcpClient::new(MAC, 9);
+        client.start(0);
+
+        // DISCOVER backs off exponentially
+        assert!(client.tick(3_999).is_empty());
+        assert_eq!(sent(&client.tick(4_000)).unwrap().0.secs, 4);
+        assert!(client.tick(11_999).is_empty());
+        assert!(sent(&client.tick(12_000)).is_some());
+
+        client.receive(&reply(9, MessageType::Offer, 600), 12_100);
+        assert_eq!(client.state(), DhcpState::Requesting);
+
+        let actions = client.receive(&reply(9, MessageType::Nak, 600), 12_200);
+        let (discover, _) = sent(&actions).unwrap();
+        assert_eq!(discover.message_type(), Some(MessageType::Discover));
+        assert_ne!(discover.xid, 9);
+        assert_eq!(client.state(), DhcpState::Selecting);
+    }
+
+    #[test]
+    fn test_prefix_helpers() {
+        assert_eq!(mask_prefix(Ipv4Addr::new(255, 255, 255, 0)), 24);
+        assert_eq!(mask_prefix(Ipv4Addr::new(255, 255, 240, 0)), 20);
+        assert_eq!(mask_prefix(Ipv4Addr::UNSPECIFIED), 0);
+        assert_eq!(classful_prefix(Ipv4Addr::new(10, 0, 0, 1)), 8);
+        assert_eq!(classful_prefix(Ipv4Addr::new(172, 16, 0, 1)), 16);
+        assert_eq!(classful_prefix(Ipv4Addr::new(192, 168, 1, 1)), 24);
+    }
+}
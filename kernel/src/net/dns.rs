@@ -0,0 +1,1035 @@
+//! DNS Stub Resolver
+//!
+//! Resolves host names to IPv4 and IPv6 addresses by querying recursive
+//! nameservers over UDP (RFC 1035), following CNAME chains. Answers are
+//! cached for their TTL. Nameservers come from the `ip=` kernel parameter,
+//! `set_nameservers` and DHCP leases.
+//!
+//! Lookups are exposed under `/proc/net/resolve/<name>`; `/proc/net/dns`
+//! lists the nameservers and the cache.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use super::dhcp;
+use super::ip::{IpAddr, IpEndpoint};
+use super::ipv4::Ipv4Addr;
+use super::ipv6::Ipv6Addr;
+use super::socket::SocketError;
+use super::udp::UdpSocket;
+use crate::fs::filesystems::procfs;
+
+/// Nameserver port
+pub const DNS_PORT: u16 = 53;
+
+/// Largest message over UDP without EDNS
+pub const MAX_MESSAGE_SIZE: usize = 512;
+
+/// Time to wait for an answer before asking the next server (milliseconds)
+pub const QUERY_TIMEOUT_MS: u64 = 2000;
+
+/// Times each nameserver is tried
+const QUERY_ROUNDS: usize = 2;
+
+/// Longest CNAME chain followed
+const MAX_CNAME_DEPTH: usize = 8;
+
+/// Compression pointers followed while reading one name
+const MAX_POINTERS: usize = 16;
+
+/// Longest encoded name (RFC 1035 3.1)
+const MAX_NAME_LEN: usize = 255;
+
+/// Longest label
+const MAX_LABEL_LEN: usize = 63;
+
+/// Cache capacity
+pub const MAX_CACHE_ENTRIES: usize = 256;
+
+/// Longest time an answer is cached (seconds)
+pub const MAX_CACHE_TTL: u32 = 86_400;
+
+/// DNS header size
+const HEADER_SIZE: usize = 12;
+
+/// Internet class
+const CLASS_IN: u16 = 1;
+
+/// Header flags
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+
+/// Response codes
+const RCODE_NO_ERROR: u16 = 0;
+const RCODE_NAME_ERROR: u16 = 3;
+
+/// Record types the resolver asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u16)]
+pub enum RecordType {
+    /// IPv4 address
+    A = 1,
+    /// Canonical name
+    Cname = 5,
+    /// IPv6 address
+    Aaaa = 28,
+}
+
+impl RecordType {
+    /// Name used in listings
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::A => "A",
+            Self::Cname => "CNAME",
+            Self::Aaaa => "AAAA",
+        }
+    }
+}
+
+/// Question section entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsQuestion {
+    /// Queried name
+    pub name: String,
+    /// Record type
+    pub rtype: u16,
+    /// Class
+    pub class: u16,
+}
+
+/// Record data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+    /// IPv4 address
+    A(Ipv4Addr),
+    /// IPv6 address
+    Aaaa(Ipv6Addr),
+    /// Alias target
+    Cname(String),
+    /// Any other type, kept raw
+    Other(u16, Vec<u8>),
+}
+
+impl RecordData {
+    /// Record type code
+    pub fn rtype(&self) -> u16 {
+        match self {
+            Self::A(_) => RecordType::A as u16,
+            Self::Aaaa(_) => RecordType::Aaaa as u16,
+            Self::Cname(_) => RecordType::Cname as u16,
+            Self::Other(rtype, _) => *rtype,
+        }
+    }
+}
+
+/// Resource record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsRecord {
+    /// Owner name
+    pub name: String,
+    /// Class
+    pub class: u16,
+    /// Time to live (seconds)
+    pub ttl: u32,
+    /// Data
+    pub data: RecordData,
+}
+
+/// DNS message
+///
+/// Authority and additional sections are not kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsMessage {
+    /// Query ID
+    pub id: u16,
+    /// Header flags, including the response code
+    pub flags: u16,
+    /// Question section
+    pub questions: Vec<DnsQuestion>,
+    /// Answer section
+    pub answers: Vec<DnsRecord>,
+}
+
+impl DnsMessage {
+    /// Create a recursive query for one name
+    pub fn query(id: u16, name: &str, rtype: RecordType) -> Self {
+        Self {
+            id,
+            flags: FLAG_RECURSION_DESIRED,
+            questions: vec![DnsQuestion {
+                name: name.to_string(),
+                rtype: rtype as u16,
+                class: CLASS_IN,
+            }],
+            answers: Vec::new(),
+        }
+    }
+
+    /// Check if this is a response
+    pub fn is_response(&self) -> bool {
+        self.flags & FLAG_RESPONSE != 0
+    }
+
+    /// Check if the response was truncated
+    pub fn is_truncated(&self) -> bool {
+        self.flags & FLAG_TRUNCATED != 0
+    }
+
+    /// Response code
+    pub fn rcode(&self) -> u16 {
+        self.flags & 0x000F
+    }
+
+    /// Parse a message
+    pub fn parse(data: &[u8]) -> Result<Self, DnsError> {
+        if data.len() < HEADER_SIZE {
+            return Err(DnsError::TooShort);
+        }
+        let field = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+        let (qdcount, ancount) = (field(4), field(6));
+
+        let mut offset = HEADER_SIZE;
+        let mut questions = Vec::new();
+        for _ in 0..qdcount {
+            let (name, next) = read_name(data, offset)?;
+            let fixed = data.get(next..next + 4).ok_or(DnsError::TooShort)?;
+            questions.push(DnsQuestion {
+                name,
+                rtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+                class: u16::from_be_bytes([fixed[2], fixed[3]]),
+            });
+            offset = next + 4;
+        }
+
+        let mut answers = Vec::new();
+        for _ in 0..ancount {
+            let (record, next) = read_record(data, offset)?;
+            answers.push(record);
+            offset = next;
+        }
+
+        Ok(Self {
+            id: field(0),
+            flags: field(2),
+            questions,
+            answers,
+        })
+    }
+
+    /// Serialize the message without name compression
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DnsError> {
+        let mut buffer = Vec::with_capacity(MAX_MESSAGE_SIZE);
+        buffer.extend_from_slice(&self.id.to_be_bytes());
+        buffer.extend_from_slice(&self.flags.to_be_bytes());
+        buffer.extend_from_slice(&(self.questions.len() as u16).to_be_bytes());
+        buffer.extend_from_slice(&(self.answers.len() as u16).to_be_bytes());
+        buffer.extend_from_slice(&[0, 0, 0, 0]);
+
+        for question in &self.questions {
+            write_name(&mut buffer, &question.name)?;
+            buffer.extend_from_slice(&question.rtype.to_be_bytes());
+            buffer.extend_from_slice(&question.class.to_be_bytes());
+        }
+
+        for record in &self.answers {
+            write_name(&mut buffer, &record.name)?;
+            buffer.extend_from_slice(&record.data.rtype().to_be_bytes());
+            buffer.extend_from_slice(&record.class.to_be_bytes());
+            buffer.extend_from_slice(&record.ttl.to_be_bytes());
+
+            let mut rdata = Vec::new();
+            match &record.data {
+                RecordData::A(addr) => rdata.extend_from_slice(&addr.octets()),
+                RecordData::Aaaa(addr) => rdata.extend_from_slice(&addr.0),
+                RecordData::Cname(target) => write_name(&mut rdata, target)?,
+                RecordData::Other(_, raw) => rdata.extend_from_slice(raw),
+            }
+            buffer.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            buffer.extend_from_slice(&rdata);
+        }
+
+        Ok(buffer)
+    }
+}
+
+/// Read a possibly compressed name, returning it and the offset after it
+fn read_name(data: &[u8], mut offset: usize) -> Result<(String, usize), DnsError> {
+    let mut name = String::new();
+    let mut end = None;
+    let mut pointers = 0;
+
+    loop {
+        let len = *data.get(offset).ok_or(DnsError::TooShort)? as usize;
+        match len & 0xC0 {
+            0x00 if len == 0 => {
+                break;
+            }
+            0x00 => {
+                let label = data
+                    .get(offset + 1..offset + 1 + len)
+                    .ok_or(DnsError::TooShort)?;
+                let label = core::str::from_utf8(label).map_err(|_| DnsError::InvalidName)?;
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(label);
+                if name.len() > MAX_NAME_LEN {
+                    return Err(DnsError::InvalidName);
+                }
+                offset += 1 + len;
+            }
+            0xC0 => {
+                let low = *data.get(offset + 1).ok_or(DnsError::TooShort)? as usize;
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err(DnsError::InvalidName);
+                }
+                end.get_or_insert(offset + 2);
+                offset = ((len & 0x3F) << 8) | low;
+            }
+            _ => return Err(DnsError::InvalidName),
+        }
+    }
+
+    Ok((name, end.unwrap_or(offset + 1)))
+}
+
+/// Append a name in wire format
+fn write_name(buffer: &mut Vec<u8>, name: &str) -> Result<(), DnsError> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let start = buffer.len();
+
+    if !name.is_empty() {
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > MAX_LABEL_LEN {
+                return Err(DnsError::InvalidName);
+            }
+            buffer.push(label.len() as u8);
+            buffer.extend_from_slice(label.as_bytes());
+        }
+    }
+    buffer.push(0);
+
+    if buffer.len() - start > MAX_NAME_LEN {
+        return Err(DnsError::InvalidName);
+    }
+    Ok(())
+}
+
+/// Read a resource record, returning it and the offset after it
+fn read_record(data: &[u8], offset: usize) -> Result<(DnsRecord, usize), DnsError> {
+    let (name, next) = read_name(data, offset)?;
+    let fixed = data.get(next..next + 10).ok_or(DnsError::TooShort)?;
+    let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+    let class = u16::from_be_bytes([fixed[2], fixed[3]]);
+    let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+    let rdlength = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+
+    let rdata_offset = next + 10;
+    let rdata = data
+        .get(rdata_offset..rdata_offset + rdlength)
+        .ok_or(DnsError::TooShort)?;
+
+    let record_data = if rtype == RecordType::A as u16 {
+        let octets: [u8; 4] = rdata.try_into().map_err(|_| DnsError::InvalidRecord)?;
+        RecordData::A(Ipv4Addr(octets))
+    } else if rtype == RecordType::Aaaa as u16 {
+        let octets: [u8; 16] = rdata.try_into().map_err(|_| DnsError::InvalidRecord)?;
+        RecordData::Aaaa(Ipv6Addr(octets))
+    } else if rtype == RecordType::Cname as u16 {
+        // The target may point back into the rest of the message
+        RecordData::Cname(read_name(data, rdata_offset)?.0)
+    } else {
+        RecordData::Other(rtype, rdata.to_vec())
+    };
+
+    // TTLs with the top bit set are treated as zero (RFC 2181 8)
+    let ttl = if ttl > i32::MAX as u32 { 0 } else { ttl };
+
+    Ok((
+        DnsRecord {
+            name,
+            class,
+            ttl,
+            data: record_data,
+        },
+        rdata_offset + rdlength,
+    ))
+}
+
+/// Canonical form of a name: lower case without the trailing dot
+fn normalize(name: &str) -> String {
+    name.strip_suffix('.').unwrap_or(name).to_ascii_lowercase()
+}
+
+/// Result of following an answer section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    /// Name at the end of the CNAME chain
+    pub canonical: String,
+    /// Addresses of the canonical name
+    pub addresses: Vec<IpAddr>,
+    /// Smallest TTL along the chain (seconds)
+    pub ttl: u32,
+}
+
+/// Collect the addresses of a type for a name, following CNAMEs
+pub fn extract_answer(message: &DnsMessage, name: &str, rtype: RecordType) -> Answer {
+    let mut canonical = normalize(name);
+    let mut ttl = u32::MAX;
+
+    for _ in 0..MAX_CNAME_DEPTH {
+        let alias = message.answers.iter().find_map(|r| match &r.data {
+            RecordData::Cname(target) if normalize(&r.name) == canonical => Some((target, r.ttl)),
+            _ => None,
+        });
+        let Some((target, alias_ttl)) = alias else {
+            break;
+        };
+        canonical = normalize(target);
+        ttl = ttl.min(alias_ttl);
+    }
+
+    let mut addresses = Vec::new();
+    for record in &message.answers {
+        if record.class != CLASS_IN || normalize(&record.name) != canonical {
+            continue;
+        }
+        let addr = match (&record.data, rtype) {
+            (RecordData::A(addr), RecordType::A) => IpAddr::V4(*addr),
+            (RecordData::Aaaa(addr), RecordType::Aaaa) => IpAddr::V6(*addr),
+            _ => continue,
+        };
+        if !addresses.contains(&addr) {
+            addresses.push(addr);
+        }
+        ttl = ttl.min(record.ttl);
+    }
+
+    Answer {
+        canonical,
+        addresses,
+        ttl: if ttl == u32::MAX { 0 } else { ttl },
+    }
+}
+
+/// Cached answer
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    /// Addresses
+    addresses: Vec<IpAddr>,
+    /// Uptime the entry expires at (milliseconds)
+    expires_at: u64,
+}
+
+/// Answer cache keyed by name and type
+pub struct DnsCache {
+    entries: BTreeMap<(String, RecordType), CacheEntry>,
+}
+
+impl DnsCache {
+    /// Create empty cache
+    pub const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Get unexpired addresses for a name
+    pub fn get(&self, name: &str, rtype: RecordType, now: u64) -> Option<Vec<IpAddr>> {
+        self.entries
+            .get(&(normalize(name), rtype))
+            .filter(|e| now < e.expires_at)
+            .map(|e| e.addresses.clone())
+    }
+
+    /// Cache addresses for a TTL, capped at `MAX_CACHE_TTL`
+    ///
+    /// Answers with a zero TTL are not cached. When the cache is full,
+    /// expired entries are dropped first, then the one closest to expiry.
+    pub fn insert(
+        &mut self,
+        name: &str,
+        rtype: RecordType,
+        addresses: Vec<IpAddr>,
+        ttl: u32,
+        now: u64,
+    ) {
+        if ttl == 0 {
+            return;
+        }
+
+        let key = (normalize(name), rtype);
+        if self.entries.len() >= MAX_CACHE_ENTRIES && !self.entries.contains_key(&key) {
+            self.purge(now);
+            if self.entries.len() >= MAX_CACHE_ENTRIES {
+                let soonest = self
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, e)| e.expires_at)
+                    .map(|(k, _)| k.clone());
+                if let Some(soonest) = soonest {
+                    self.entries.remove(&soonest);
+                }
+            }
+        }
+
+        let expires_at = now + ttl.min(MAX_CACHE_TTL) as u64 * 1000;
+        self.entries.insert(
+            key,
+            CacheEntry {
+                addresses,
+                expires_at,
+            },
+        );
+    }
+
+    /// Drop expired entries
+    pub fn purge(&mut self, now: u64) {
+        self.entries.retain(|_, e| now < e.expires_at);
+    }
+
+    /// Remove all entries
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// List unexpired entries with their remaining TTL (seconds)
+    pub fn entries(&self, now: u64) -> Vec<(String, RecordType, u32, Vec<IpAddr>)> {
+        self.entries
+            .iter()
+            .filter(|(_, e)| now < e.expires_at)
+            .map(|((name, rtype), e)| {
+                let remaining = ((e.expires_at - now) / 1000) as u32;
+                (name.clone(), *rtype, remaining, e.addresses.clone())
+            })
+            .collect()
+    }
+
+    /// Get entry count
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Global answer cache
+static CACHE: Mutex<DnsCache> = Mutex::new(DnsCache::new());
+
+/// Configured nameservers
+static NAMESERVERS: Mutex<Vec<IpAddr>> = Mutex::new(Vec::new());
+
+/// Query in progress
+///
+/// Sends to each nameserver in turn, moving on after `QUERY_TIMEOUT_MS`
+/// or a server failure, and re-queries the target when an answer ends in
+/// a CNAME without addresses. Answers are added to the cache.
+pub struct DnsQuery {
+    /// Name asked for
+    name: String,
+    /// Name currently queried, after any CNAMEs
+    target: String,
+    /// Record type
+    rtype: RecordType,
+    /// Query ID
+    id: u16,
+    /// Nameservers to ask
+    servers: Vec<IpEndpoint>,
+    /// Sends so far; selects the current server
+    attempts: usize,
+    /// Uptime at which the next server is asked (milliseconds)
+    deadline: u64,
+    /// CNAMEs followed across queries
+    depth: usize,
+    /// Smallest TTL of CNAMEs followed across queries
+    chain_ttl: u32,
+    /// Socket the query is sent from
+    socket: UdpSocket,
+}
+
+impl DnsQuery {
+    /// Send a query to the first nameserver
+    pub fn start(
+        name: &str,
+        rtype: RecordType,
+        servers: Vec<IpEndpoint>,
+        now: u64,
+    ) -> Result<Self, DnsError> {
+        let first = *servers.first().ok_or(DnsError::NoNameservers)?;
+        let mut socket = UdpSocket::new().map_err(DnsError::Socket)?;
+        socket.connect(first).map_err(DnsError::Socket)?;
+
+        let mut query = Self {
+            name: name.to_string(),
+            target: normalize(name),
+            rtype,
+            id: 0,
+            servers,
+            attempts: 0,
+            deadline: 0,
+            depth: 0,
+            chain_ttl: u32::MAX,
+            socket,
+        };
+        query.id = next_id();
+        query.send(now)?;
+        Ok(query)
+    }
+
+    /// Process replies and timeouts
+    ///
+    /// Returns `Ok(None)` while the query is still waiting.
+    pub fn poll(&mut self, now: u64) -> Result<Option<Vec<IpAddr>>, DnsError> {
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+
+        while let Ok((len, from)) = self.socket.recv_from(&mut buffer) {
+            if !self.servers.contains(&from) {
+                continue;
+            }
+            let Ok(message) = DnsMessage::parse(&buffer[..len]) else {
+                continue;
+            };
+            if !self.answers_query(&message) {
+                continue;
+            }
+
+            match message.rcode() {
+                RCODE_NO_ERROR => {}
+                RCODE_NAME_ERROR => return Err(DnsError::NotFound),
+                // Server failure or refusal: ask the next one
+                _ => {
+                    self.next_server(now)?;
+                    continue;
+                }
+            }
+
+            let answer = extract_answer(&message, &self.target, self.rtype);
+            let ttl = answer.ttl.min(self.chain_ttl);
+            if !answer.addresses.is_empty() {
+                CACHE
+                    .lock()
+                    .insert(&self.name, self.rtype, answer.addresses.clone(), ttl, now);
+                return Ok(Some(answer.addresses));
+            }
+
+            // The chain ended in an alias the server did not resolve
+            if answer.canonical != self.target {
+                self.depth += 1;
+                if self.depth > MAX_CNAME_DEPTH {
+                    return Err(DnsError::CnameLoop);
+                }
+                self.chain_ttl = ttl;
+                self.target = answer.canonical;
+                self.id = next_id();
+                self.attempts = 0;
+                self.send(now)?;
+                continue;
+            }
+
+            // The name exists but has no records of this type
+            return Err(DnsError::NotFound);
+        }
+
+        if now >= self.deadline {
+            self.next_server(now)?;
+        }
+        Ok(None)
+    }
+
+    /// Check if a message is the response to the current query
+    fn answers_query(&self, message: &DnsMessage) -> bool {
+        message.id == self.id
+            && message.is_response()
+            && message
+                .questions
+                .first()
+                .is_some_and(|q| q.rtype == self.rtype as u16 && normalize(&q.name) == self.target)
+    }
+
+    /// Move on to the next nameserver, or give up after every round
+    fn next_server(&mut self, now: u64) -> Result<(), DnsError> {
+        if self.attempts >= self.servers.len() * QUERY_ROUNDS {
+            return Err(DnsError::Timeout);
+        }
+        self.send(now)
+    }
+
+    /// Send the query to the current server
+    fn send(&mut self, now: u64) -> Result<(), DnsError> {
+        let server = self.servers[self.attempts % self.servers.len()];
+        self.attempts += 1;
+        self.deadline = now + QUERY_TIMEOUT_MS;
+
+        let bytes = DnsMessage::query(self.id, &self.target, self.rtype).to_bytes()?;
+        self.socket
+            .send_to(&bytes, server)
+            .map_err(DnsError::Socket)?;
+        Ok(())
+    }
+}
+
+impl Drop for DnsQuery {
+    fn drop(&mut self) {
+        let _ = self.socket.close();
+    }
+}
+
+/// Random query ID, to make spoofed answers harder to match
+fn next_id() -> u16 {
+    crate::security::aslr::get_random_u64() as u16
+}
+
+/// Replace the configured nameservers
+pub fn set_nameservers(servers: &[IpAddr]) {
+    *NAMESERVERS.lock() = servers.to_vec();
+}
+
+/// Get the nameservers to query: configured ones first, then DHCP's
+pub fn nameservers() -> Vec<IpAddr> {
+    let mut servers = NAMESERVERS.lock().clone();
+    for server in dhcp::nameservers() {
+        let server = IpAddr::V4(server);
+        if !servers.contains(&server) {
+            servers.push(server);
+        }
+    }
+    servers
+}
+
+/// Get cached addresses for a name
+pub fn cached(name: &str, rtype: RecordType) -> Option<Vec<IpAddr>> {
+    CACHE.lock().get(name, rtype, crate::time::uptime_ms())
+}
+
+/// Empty the answer cache
+pub fn flush_cache() {
+    CACHE.lock().clear();
+}
+
+/// Look up the addresses of one type for a name
+///
+/// Answers from the cache when possible, otherwise waits for the
+/// nameservers; replies are delivered by the receive timer.
+pub fn lookup(name: &str, rtype: RecordType) -> Result<Vec<IpAddr>, DnsError> {
+    if let Some(addresses) = cached(name, rtype) {
+        return Ok(addresses);
+    }
+
+    let servers = nameservers()
+        .into_iter()
+        .map(|ip| IpEndpoint::new(ip, DNS_PORT))
+        .collect();
+    let mut query = DnsQuery::start(name, rtype, servers, crate::time::uptime_ms())?;
+    loop {
+        if let Some(addresses) = query.poll(crate::time::uptime_ms())? {
+            return Ok(addresses);
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Resolve a name to its IPv4 and IPv6 addresses
+///
+/// Dotted-quad literals are returned as they are.
+pub fn resolve(name: &str) -> Result<Vec<IpAddr>, DnsError> {
+    if let Ok(addr) = name.parse::<Ipv4Addr>() {
+        return Ok(vec![IpAddr::V4(addr)]);
+    }
+
+    let v4 = lookup(name, RecordType::A);
+    let v6 = lookup(name, RecordType::Aaaa);
+    match (v4, v6) {
+        (Err(e), Err(_)) => Err(e),
+        (v4, v6) => Ok(v4
+            .unwrap_or_default()
+            .into_iter()
+            .chain(v6.unwrap_or_default())
+            .collect()),
+    }
+}
+
+/// Read /proc/net/dns
+fn read_dns(_path: &str) -> Result<Vec<u8>, &'static str> {
+    let mut out = String::new();
+    for server in nameservers() {
+        let _ = writeln!(out, "nameserver {}", server);
+    }
+    for (name, rtype, ttl, addresses) in CACHE.lock().entries(crate::time::uptime_ms()) {
+        for addr in addresses {
+            let _ = writeln!(out, "{} {} {} {}", name, ttl, rtype.as_str(), addr);
+        }
+    }
+    Ok(out.into_bytes())
+}
+
+/// Read /proc/net/resolve/<name>
+fn read_resolve(path: &str) -> Result<Vec<u8>, &'static str> {
+    let name = path
+        .strip_prefix(PROC_RESOLVE_DIR)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .filter(|name| !name.is_empty())
+        .ok_or("No name given")?;
+
+    let addresses = resolve(name).map_err(|e| match e {
+        DnsError::NotFound => "Name not found",
+        DnsError::NoNameservers => "No nameservers configured",
+        DnsError::Timeout => "Nameservers did not answer",
+        DnsError::InvalidName => "Invalid name",
+        _ => "Resolution failed",
+    })?;
+
+    let mut out = String::new();
+    for addr in addresses {
+        let _ = writeln!(out, "{}", addr);
+    }
+    Ok(out.into_bytes())
+}
+
+/// Directory lookups are made under
+const PROC_RESOLVE_DIR: &str = "/proc/net/resolve";
+
+/// DNS errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsError {
+    /// Message too short
+    TooShort,
+    /// Malformed or oversized name
+    InvalidName,
+    /// Record data does not match its type
+    InvalidRecord,
+    /// Name has no addresses of the requested type
+    NotFound,
+    /// No nameserver answered
+    Timeout,
+    /// No nameservers configured
+    NoNameservers,
+    /// CNAME chain too long
+    CnameLoop,
+    /// Query socket error
+    Socket(SocketError),
+}
+
+/// DNS resolver initialized flag
+static DNS_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Initialize DNS resolver
+///
+/// Takes nameservers from the `ip=` kernel parameter and registers the
+/// /proc interface.
+pub fn init() {
+    if DNS_INITIALIZED.load(Ordering::Acquire) {
+        return;
+    }
+
+    if let Some(config) = crate::cmdline::ip_config() {
+        let servers: Vec<IpAddr> = config.nameservers.into_iter().map(IpAddr::V4).collect();
+        set_nameservers(&servers);
+    }
+
+    let registered = procfs::register_file("/proc/net/dns", read_dns)
+        .and_then(|_| procfs::register_handler(PROC_RESOLVE_DIR, read_resolve));
+    if registered.is_err() {
+        crate::printk::printk("  DNS: /proc interface unavailable\n");
+    }
+
+    DNS_INITIALIZED.store(true, Ordering::Release);
+}
+
+/// Check if DNS resolver is initialized
+pub fn is_initialized() -> bool {
+    DNS_INITIALIZED.load(Ordering::Acquire)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::loopback;
+    use crate::net::rx;
+
+    /// Deliver everything queued on the loopback device
+    fn drain() {
+        let lo = loopback::device().unwrap();
+        while rx::poll_device(&lo) > 0 {}
+    }
+
+    #[test]
+    fn test_compressed_names() {
+        // www.example.test, then a CNAME pointing at offset 16 ("example.test")
+        let mut data = vec![0u8; HEADER_SIZE];
+        write_name(&mut data, "www.example.test").unwrap();
+        let second = data.len();
+        data.extend_from_slice(&[4, b'm', b'a', b'i', b'l', 0xC0, 16]);
+
+        assert_eq!(
+            read_name(&data, HEADER_SIZE).unwrap(),
+            ("www.example.test".to_string(), second)
+        );
+        assert_eq!(
+            read_name(&data, second).unwrap(),
+            ("mail.example.test".to_string(), data.len())
+        );
+
+        // A pointer to itself must not loop forever
+        let looped = [0xC0, 0x00];
+        assert_eq!(read_name(&looped, 0), Err(DnsError::InvalidName));
+
+        let long_label = "a".repeat(64);
+        assert_eq!(
+            write_name(&mut Vec::new(), &long_label),
+            Err(DnsError::InvalidName)
+        );
+    }
+
+    #[test]
+    fn test_cname_chain_answer() {
+        let record = |name: &str, ttl: u32, data: RecordData| DnsRecord {
+            name: name.to_string(),
+            class: CLASS_IN,
+            ttl,
+            data,
+        };
+        let mut message = DnsMessage::query(1, "www.example.test", RecordType::A);
+        message.flags |= FLAG_RESPONSE;
+        message.answers = vec![
+            record(
+                "WWW.example.test",
+                600,
+                RecordData::Cname("edge.example.test".to_string()),
+            ),
+            record(
+                "edge.example.test",
+                120,
+                RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            ),
+            record(
+                "edge.example.test",
+                300,
+                RecordData::A(Ipv4Addr::new(192, 0, 2, 2)),
+            ),
+            record(
+                "other.example.test",
+                10,
+                RecordData::A(Ipv4Addr::new(192, 0, 2, 3)),
+            ),
+        ];
+
+        let parsed = DnsMessage::parse(&message.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed, message);
+
+        let answer = extract_answer(&parsed, "www.example.test.", RecordType::A);
+        assert_eq!(answer.canonical, "edge.example.test");
+        assert_eq!(answer.ttl, 120);
+        assert_eq!(
+            answer.addresses,
+            [
+                IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2))
+            ]
+        );
+        assert!(
+            extract_answer(&parsed, "www.example.test", RecordType::Aaaa)
+                .addresses
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_cache_ttl() {
+        let mut cache = DnsCache::new();
+        let addr = vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))];
+
+        cache.insert("Host.Example.test", RecordType::A, addr.clone(), 30, 1000);
+        cache.insert("uncached.test", RecordType::A, addr.clone(), 0, 1000);
+        assert_eq!(cache.len(), 1);
+
+        assert_eq!(
+            cache.get("host.example.test.", RecordType::A, 30_999),
+            Some(addr)
+        );
+        assert_eq!(cache.get("host.example.test", RecordType::Aaaa, 2000), None);
+        assert_eq!(cache.get("host.example.test", RecordType::A, 31_000), None);
+
+        cache.purge(31_000);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_query_against_local_server() {
+        loopback::init();
+
+        let server_addr = IpEndpoint::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9500);
+        let mut server = UdpSocket::new().unwrap();
+        server.bind(server_addr).unwrap();
+
+        let mut query =
+            DnsQuery::start("www.resolver.test", RecordType::A, vec![server_addr], 0).unwrap();
+        drain();
+
+        // The stand-in answers with an alias and an address
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        let (len, client) = server.recv_from(&mut buffer).unwrap();
+        let request = DnsMessage::parse(&buffer[..len]).unwrap();
+        assert_eq!(request.questions[0].name, "www.resolver.test");
+        assert_eq!(request.questions[0].rtype, RecordType::A as u16);
+
+        let mut response = request.clone();
+        response.flags |= FLAG_RESPONSE;
+        response.answers = vec![
+            DnsRecord {
+                name: "www.resolver.test".to_string(),
+                class: CLASS_IN,
+                ttl: 3600,
+                data: RecordData::Cname("host.resolver.test".to_string()),
+            },
+            DnsRecord {
+                name: "host.resolver.test".to_string(),
+                class: CLASS_IN,
+                ttl: 60,
+                data: RecordData::A(Ipv4Addr::new(198, 51, 100, 7)),
+            },
+        ];
+
+        // A reply with the wrong ID is ignored
+        let mut forged = response.clone();
+        forged.id = forged.id.wrapping_add(1);
+        server.send_to(&forged.to_bytes().unwrap(), client).unwrap();
+        drain();
+        assert_eq!(query.poll(10), Ok(None));
+
+        server
+            .send_to(&response.to_bytes().unwrap(), client)
+            .unwrap();
+        drain();
+
+        let expected = vec![IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7))];
+        assert_eq!(query.poll(20), Ok(Some(expected.clone())));
+        assert_eq!(
+            CACHE.lock().get("www.resolver.test", RecordType::A, 20),
+            Some(expected)
+        );
+
+        // With no answer the query gives up after every round
+        let mut silent =
+            DnsQuery::start("silent.resolver.test", RecordType::A, vec![server_addr], 0).unwrap();
+        assert_eq!(silent.poll(QUERY_TIMEOUT_MS), Ok(None));
+        assert_eq!(silent.poll(2 * QUERY_TIMEOUT_MS), Err(DnsError::Timeout));
+
+        server.close().unwrap();
+    }
+}
@@ -0,0 +1,580 @@
+//! Internet Control Message Protocol (ICMP)
+//!
+//! Echo request/reply handling, error reporting and ping sockets.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use spin::Mutex;
+
+use super::ipv4::{self, calculate_checksum, IpProtocol, Ipv4Addr, Ipv4Header};
+use super::socket::SocketError;
+use crate::process::wait_queue::WaitQueue;
+
+/// ICMP message types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IcmpType {
+    /// Echo reply
+    EchoReply = 0,
+    /// Destination unreachable
+    DestUnreachable = 3,
+    /// Echo request
+    EchoRequest = 8,
+    /// Time exceeded
+    TimeExceeded = 11,
+    /// Unknown
+    Unknown = 0xFF,
+}
+
+impl IcmpType {
+    /// Create from u8
+    pub const fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::EchoReply,
+            3 => Self::DestUnreachable,
+            8 => Self::EchoRequest,
+            11 => Self::TimeExceeded,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Convert to u8
+    pub const fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    /// Check if this is an error message
+    pub const fn is_error(self) -> bool {
+        matches!(self, Self::DestUnreachable | Self::TimeExceeded)
+    }
+}
+
+/// Destination unreachable codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum UnreachableCode {
+    /// Network unreachable
+    Net = 0,
+    /// Host unreachable
+    Host = 1,
+    /// Protocol unreachable
+    Protocol = 2,
+    /// Port unreachable
+    Port = 3,
+    /// Fragmentation needed and DF set
+    FragmentationNeeded = 4,
+}
+
+/// Time exceeded codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TimeExceededCode {
+    /// TTL exceeded in transit
+    TtlExceeded = 0,
+    /// Fragment reassembly time exceeded
+    FragmentReassembly = 1,
+}
+
+/// ICMP header (8 bytes)
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct IcmpHeader {
+    /// Message type
+    pub icmp_type: u8,
+    /// Message code
+    pub code: u8,
+    /// Checksum
+    pub checksum: u16,
+    /// Rest of header (identifier/sequence, next-hop MTU or unused)
+    pub rest: u32,
+}
+
+impl IcmpHeader {
+    /// ICMP header size
+    pub const SIZE: usize = 8;
+
+    /// Create new ICMP header
+    pub const fn new(icmp_type: IcmpType, code: u8, rest: u32) -> Self {
+        Self {
+            icmp_type: icmp_type.as_u8(),
+            code,
+            checksum: 0,
+            rest: rest.to_be(),
+        }
+    }
+
+    /// Create echo request/reply header
+    pub const fn echo(icmp_type: IcmpType, identifier: u16, sequence: u16) -> Self {
+        Self::new(icmp_type, 0, ((identifier as u32) << 16) | sequence as u32)
+    }
+
+    /// Parse header from bytes
+    pub fn parse(data: &[u8]) -> Result<Self, IcmpError> {
+        if data.len() < Self::SIZE {
+            return Err(IcmpError::TooShort);
+        }
+
+        Ok(Self {
+            icmp_type: data[0],
+            code: data[1],
+            checksum: u16::from_be_bytes([data[2], data[3]]).to_be(),
+            rest: u32::from_be_bytes([data[4], data[5], data[6], data[7]]).to_be(),
+        })
+    }
+
+    /// Write header to buffer
+    pub fn write_to(&self, buffer: &mut [u8]) -> Result<(), IcmpError> {
+        if buffer.len() < Self::SIZE {
+            return Err(IcmpError::BufferTooSmall);
+        }
+
+        buffer[0] = self.icmp_type;
+        buffer[1] = self.code;
+        buffer[2..4].copy_from_slice(&u16::from_be(self.checksum).to_be_bytes());
+        buffer[4..8].copy_from_slice(&u32::from_be(self.rest).to_be_bytes());
+
+        Ok(())
+    }
+
+    /// Get message type
+    pub fn get_type(&self) -> IcmpType {
+        IcmpType::from_u8(self.icmp_type)
+    }
+
+    /// Get checksum
+    pub fn checksum(&self) -> u16 {
+        u16::from_be(self.checksum)
+    }
+
+    /// Get rest of header
+    pub fn rest(&self) -> u32 {
+        u32::from_be(self.rest)
+    }
+
+    /// Get echo identifier
+    pub fn identifier(&self) -> u16 {
+        (self.rest() >> 16) as u16
+    }
+
+    /// Get echo sequence number
+    pub fn sequence(&self) -> u16 {
+        self.rest() as u16
+    }
+}
+
+/// Build an ICMP message with a valid checksum
+pub fn build_message(header: IcmpHeader, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(IcmpHeader::SIZE + payload.len());
+    message.resize(IcmpHeader::SIZE, 0);
+    let _ = header.write_to(&mut message);
+    message.extend_from_slice(payload);
+
+    fill_checksum(&mut message);
+    message
+}
+
+/// Recalculate the checksum of an ICMP message in place
+fn fill_checksum(message: &mut [u8]) {
+    message[2] = 0;
+    message[3] = 0;
+    let checksum = calculate_checksum(message);
+    message[2..4].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// Verify the checksum of an ICMP message
+pub fn verify_checksum(message: &[u8]) -> bool {
+    calculate_checksum(message) == 0
+}
+
+/// Maximum number of messages queued on a ping socket
+pub const ICMP_RECV_QUEUE_LEN: usize = 64;
+
+/// Messages waiting to be read, with their source addresses
+type MessageQueue = Arc<Mutex<VecDeque<(Vec<u8>, Ipv4Addr)>>>;
+
+/// Receive endpoint of a ping socket
+struct PingBinding {
+    /// Socket receive queue
+    queue: MessageQueue,
+    /// Tasks waiting for a message
+    wait: Arc<WaitQueue>,
+}
+
+/// Ping sockets by echo identifier
+static PING_SOCKETS: Mutex<BTreeMap<u16, PingBinding>> = Mutex::new(BTreeMap::new());
+
+/// Next echo identifier to try
+static NEXT_IDENTIFIER: AtomicU16 = AtomicU16::new(1);
+
+/// Queue a message on the ping socket owning an identifier
+fn deliver(identifier: u16, src: Ipv4Addr, message: &[u8]) -> Result<(), IcmpError> {
+    let sockets = PING_SOCKETS.lock();
+    let binding = sockets.get(&identifier).ok_or(IcmpError::NoSocket)?;
+
+    let mut queue = binding.queue.lock();
+    if queue.len() >= ICMP_RECV_QUEUE_LEN {
+        return Err(IcmpError::QueueFull);
+    }
+
+    queue.push_back((message.to_vec(), src));
+    drop(queue);
+    binding.wait.wake_all();
+    Ok(())
+}
+
+/// Deliver an error message to the ping socket whose echo request caused it
+fn deliver_error(src: Ipv4Addr, message: &[u8]) -> Result<(), IcmpError> {
+    let quoted = &message[IcmpHeader::SIZE..];
+    let quoted_ip = Ipv4Header::parse(quoted).map_err(|_| IcmpError::TooShort)?;
+    let quoted_icmp = quoted
+        .get(quoted_ip.header_length()..)
+        .ok_or(IcmpError::TooShort)?;
+
+    if quoted_ip.protocol() != IpProtocol::Icmp {
+        return Err(IcmpError::NoSocket);
+    }
+
+    let echo = IcmpHeader::parse(quoted_icmp)?;
+    if echo.get_type() != IcmpType::EchoRequest {
+        return Err(IcmpError::NoSocket);
+    }
+
+    deliver(echo.identifier(), src, message)
+}
+
+/// Process incoming ICMP message
+pub fn process_packet(
+    ip_payload: &[u8],
+    src_ip: Ipv4Addr,
+    dst_ip: Ipv4Addr,
+) -> Result<(), IcmpError> {
+    let header = IcmpHeader::parse(ip_payload)?;
+
+    if !verify_checksum(ip_payload) {
+        return Err(IcmpError::InvalidChecksum);
+    }
+
+    match header.get_type() {
+        IcmpType::EchoRequest => {
+            // Broadcast and multicast pings are not answered
+            if dst_ip.is_broadcast() || dst_ip.is_multicast() {
+                return Ok(());
+            }
+
+            let reply = build_message(
+                IcmpHeader::new(IcmpType::EchoReply, 0, header.rest()),
+                &ip_payload[IcmpHeader::SIZE..],
+            );
+            ipv4::send_packet(dst_ip, src_ip, IpProtocol::Icmp, &reply)
+                .map_err(|_| IcmpError::TransmitFailed)
+        }
+        IcmpType::EchoReply => deliver(header.identifier(), src_ip, ip_payload),
+        IcmpType::DestUnreachable | IcmpType::TimeExceeded => deliver_error(src_ip, ip_payload),
+        IcmpType::Unknown => Err(IcmpError::UnsupportedType),
+    }
+}
+
+/// Send an ICMP error about a received packet
+///
+/// `original` is the complete offending IPv4 packet. Following RFC 1122,
+/// no error is generated for broadcast or multicast traffic, for non-initial
+/// fragments or in response to another ICMP error.
+pub fn send_error(
+    icmp_type: IcmpType,
+    code: u8,
+    rest: u32,
+    original: &[u8],
+) -> Result<(), IcmpError> {
+    let header = Ipv4Header::parse(original).map_err(|_| IcmpError::TooShort)?;
+    send_error_from(header.dst_addr(), icmp_type, code, rest, original)
+}
+
+/// Send an ICMP error from a given source address
+///
+/// Errors about packets addressed to this host come from the packet's
+/// destination; a router reports errors about packets in transit from an
+/// address of the interface they arrived on instead.
+pub fn send_error_from(
+    source: Ipv4Addr,
+    icmp_type: IcmpType,
+    code: u8,
+    rest: u32,
+    original: &[u8],
+) -> Result<(), IcmpError> {
+    let header = Ipv4Header::parse(original).map_err(|_| IcmpError::TooShort)?;
+    let src = header.src_addr();
+    let dst = header.dst_addr();
+
+    if dst.is_broadcast() || dst.is_multicast() {
+        return Ok(());
+    }
+    if src.is_unspecified() || src.is_broadcast() || src.is_multicast() {
+        return Ok(());
+    }
+    if header.fragment_offset() != 0 {
+        return Ok(());
+    }
+
+    let header_len = header.header_length();
+    if header.protocol() == IpProtocol::Icmp {
+        let is_error = original
+            .get(header_len)
+            .is_some_and(|&t| IcmpType::from_u8(t).is_error());
+        if is_error {
+            return Ok(());
+        }
+    }
+
+    // Quote the IP header and the first 8 bytes of its payload
+    let quoted_len = original.len().min(header_len + 8);
+    let message = build_message(
+        IcmpHeader::new(icmp_type, code, rest),
+        &original[..quoted_len],
+    );
+
+    ipv4::send_packet(source, src, IpProtocol::Icmp, &message)
+        .map_err(|_| IcmpError::TransmitFailed)
+}
+
+/// Send a destination unreachable error about a received packet
+pub fn send_dest_unreachable(code: UnreachableCode, original: &[u8]) -> Result<(), IcmpError> {
+    send_error(IcmpType::DestUnreachable, code as u8, 0, original)
+}
+
+/// Send a time exceeded error about a received packet
+pub fn send_time_exceeded(code: TimeExceededCode, original: &[u8]) -> Result<(), IcmpError> {
+    send_error(IcmpType::TimeExceeded, code as u8, 0, original)
+}
+
+/// Ping socket
+///
+/// Sends echo requests and receives the matching replies and errors. Each
+/// socket owns a unique echo identifier which is stamped on every request.
+pub struct IcmpSocket {
+    /// Echo identifier
+    identifier: u16,
+    /// Default destination
+    remote_addr: Option<Ipv4Addr>,
+    /// Receive queue (shared with the receive path)
+    recv_queue: MessageQueue,
+    /// Tasks waiting for a message (woken by the receive path)
+    wait: Arc<WaitQueue>,
+}
+
+impl IcmpSocket {
+    /// Create new ping socket with a free identifier
+    pub fn new() -> Result<Self, SocketError> {
+        let recv_queue: MessageQueue = Arc::new(Mutex::new(VecDeque::new()));
+        let wait = Arc::new(WaitQueue::new());
+        let mut sockets = PING_SOCKETS.lock();
+
+        for _ in 0..=u16::MAX {
+            let identifier = NEXT_IDENTIFIER.fetch_add(1, Ordering::Relaxed);
+            if identifier == 0 || sockets.contains_key(&identifier) {
+                continue;
+            }
+
+            sockets.insert(
+                identifier,
+                PingBinding {
+                    queue: recv_queue.clone(),
+                    wait: wait.clone(),
+                },
+            );
+            return Ok(Self {
+                identifier,
+                remote_addr: None,
+                recv_queue,
+                wait,
+            });
+        }
+
+        Err(SocketError::AddrNotAvail)
+    }
+
+    /// Get echo identifier
+    pub fn identifier(&self) -> u16 {
+        self.identifier
+    }
+
+    /// Set default destination
+    pub fn connect(&mut self, addr: Ipv4Addr) {
+        self.remote_addr = Some(addr);
+    }
+
+    /// Get default destination
+    pub fn remote_addr(&self) -> Option<Ipv4Addr> {
+        self.remote_addr
+    }
+
+    /// Check if a message is waiting to be read
+    pub fn has_pending(&self) -> bool {
+        !self.recv_queue.lock().is_empty()
+    }
+
+    /// Get the wait queue woken when a message arrives
+    pub fn wait_queue(&self) -> Arc<WaitQueue> {
+        self.wait.clone()
+    }
+
+    /// Send an echo request message
+    ///
+    /// `message` is a complete ICMP echo request. Its identifier and
+    /// checksum are filled in by the kernel.
+    pub fn send_to(&mut self, message: &[u8], dst: Ipv4Addr) -> Result<usize, SocketError> {
+        let header = IcmpHeader::parse(message).map_err(|_| SocketError::InvalidArg)?;
+        if header.get_type() != IcmpType::EchoRequest || header.code != 0 {
+            return Err(SocketError::InvalidArg);
+        }
+
+        let mut request = message.to_vec();
+        request[4..6].copy_from_slice(&self.identifier.to_be_bytes());
+        fill_checksum(&mut request);
+
+        ipv4::send_packet(ipv4::select_source(dst), dst, IpProtocol::Icmp, &request)
+            .map_err(|_| SocketError::NetUnreachable)?;
+        Ok(message.len())
+    }
+
+    /// Send an echo request with the given sequence number and payload
+    pub fn ping(
+        &mut self,
+        dst: Ipv4Addr,
+        sequence: u16,
+        payload: &[u8],
+    ) -> Result<(), SocketError> {
+        let request = build_message(
+            IcmpHeader::echo(IcmpType::EchoRequest, self.identifier, sequence),
+            payload,
+        );
+        self.send_to(&request, dst).map(|_| ())
+    }
+
+    /// Send an echo request message to the default destination
+    pub fn send(&mut self, message: &[u8]) -> Result<usize, SocketError> {
+        let remote = self.remote_addr.ok_or(SocketError::NotConnected)?;
+        self.send_to(message, remote)
+    }
+
+    /// Receive an echo reply or error message
+    pub fn recv_from(&mut self, buffer: &mut [u8]) -> Result<(usize, Ipv4Addr), SocketError> {
+        let (message, src) = self
+            .recv_queue
+            .lock()
+            .pop_front()
+            .ok_or(SocketError::WouldBlock)?;
+
+        let len = message.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&message[..len]);
+        Ok((len, src))
+    }
+
+    /// Close socket
+    pub fn close(&mut self) {
+        PING_SOCKETS.lock().remove(&self.identifier);
+        self.recv_queue.lock().clear();
+    }
+}
+
+impl Drop for IcmpSocket {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// ICMP errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IcmpError {
+    /// Packet too short
+    TooShort,
+    /// Buffer too small
+    BufferTooSmall,
+    /// Invalid checksum
+    InvalidChecksum,
+    /// Unsupported message type
+    UnsupportedType,
+    /// No socket for the message
+    NoSocket,
+    /// Socket receive queue is full
+    QueueFull,
+    /// Failed to send a message
+    TransmitFailed,
+}
+
+/// ICMP subsystem initialized flag
+static ICMP_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Initialize ICMP subsystem
+pub fn init() {
+    if ICMP_INITIALIZED.load(Ordering::Acquire) {
+        return;
+    }
+
+    ICMP_INITIALIZED.store(true, Ordering::Release);
+}
+
+/// Check if ICMP subsystem is initialized
+pub fn is_initialized() -> bool {
+    ICMP_INITIALIZED.load(Ordering::Acquire)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{loopback, rx};
+
+    #[test]
+    fn test_icmp_type() {
+        assert_eq!(IcmpType::from_u8(8), IcmpType::EchoRequest);
+        assert_eq!(IcmpType::EchoReply.as_u8(), 0);
+        assert!(IcmpType::DestUnreachable.is_error());
+        assert!(!IcmpType::EchoRequest.is_error());
+    }
+
+    #[test]
+    fn test_echo_message() {
+        let message = build_message(IcmpHeader::echo(IcmpType::EchoRequest, 0x1234, 7), b"abc");
+        assert!(verify_checksum(&message));
+
+        let header = IcmpHeader::parse(&message).unwrap();
+        assert_eq!(header.get_type(), IcmpType::EchoRequest);
+        assert_eq!(header.identifier(), 0x1234);
+        assert_eq!(header.sequence(), 7);
+    }
+
+    #[test]
+    fn test_drop_releases_identifier() {
+        let socket = IcmpSocket::new().unwrap();
+        let identifier = socket.identifier();
+        assert!(PING_SOCKETS.lock().contains_key(&identifier));
+
+        drop(socket);
+        assert!(!PING_SOCKETS.lock().contains_key(&identifier));
+    }
+
+    #[test]
+    fn test_ping_loopback() {
+        loopback::init();
+        let lo = loopback::device().unwrap();
+
+        let mut socket = IcmpSocket::new().unwrap();
+        socket.ping(Ipv4Addr::LOCALHOST, 1, b"rinux").unwrap();
+
+        // Request is answered by the stack, reply is delivered to the socket
+        while rx::poll_device(&lo) > 0 {}
+
+        let mut buffer = [0u8; 32];
+        let (len, from) = socket.recv_from(&mut buffer).unwrap();
+        assert_eq!(from, Ipv4Addr::LOCALHOST);
+
+        let reply = IcmpHeader::parse(&buffer[..len]).unwrap();
+        assert_eq!(reply.get_type(), IcmpType::EchoReply);
+        assert_eq!(reply.identifier(), socket.identifier());
+        assert_eq!(reply.sequence(), 1);
+        assert_eq!(&buffer[IcmpHeader::SIZE..len], b"rinux");
+
+        socket.close();
+    }
+}
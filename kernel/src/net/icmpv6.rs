@@ -0,0 +1,411 @@
+//! Internet Control Message Protocol for IPv6 (ICMPv6)
+//!
+//! Echo request/reply handling, error reporting (RFC 4443) and dispatch of
+//! Neighbor Discovery messages.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use super::ipv4::IpProtocol;
+use super::ipv6::{self, calculate_pseudo_header_checksum, Ipv6Addr, Ipv6Header};
+use super::ndisc::{self, NdiscError};
+use super::netdev::NetDevice;
+
+/// ICMPv6 message types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Icmpv6Type {
+    /// Destination unreachable
+    DestUnreachable = 1,
+    /// Packet too big
+    PacketTooBig = 2,
+    /// Time exceeded
+    TimeExceeded = 3,
+    /// Parameter problem
+    ParameterProblem = 4,
+    /// Echo request
+    EchoRequest = 128,
+    /// Echo reply
+    EchoReply = 129,
+    /// Router solicitation
+    RouterSolicitation = 133,
+    /// Router advertisement
+    RouterAdvertisement = 134,
+    /// Neighbor solicitation
+    NeighborSolicitation = 135,
+    /// Neighbor advertisement
+    NeighborAdvertisement = 136,
+    /// Redirect
+    Redirect = 137,
+    /// Unknown
+    Unknown = 0xFF,
+}
+
+impl Icmpv6Type {
+    /// Create from u8
+    pub const fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::DestUnreachable,
+            2 => Self::PacketTooBig,
+            3 => Self::TimeExceeded,
+            4 => Self::ParameterProblem,
+            128 => Self::EchoRequest,
+            129 => Self::EchoReply,
+            133 => Self::RouterSolicitation,
+            134 => Self::RouterAdvertisement,
+            135 => Self::NeighborSolicitation,
+            136 => Self::NeighborAdvertisement,
+            137 => Self::Redirect,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Convert to u8
+    pub const fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    /// Check if a type value is an error message (RFC 4443: below 128)
+    pub const fn is_error_type(value: u8) -> bool {
+        value < 128
+    }
+}
+
+/// Destination unreachable codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum UnreachableCode {
+    /// No route to destination
+    NoRoute = 0,
+    /// Communication administratively prohibited
+    AdminProhibited = 1,
+    /// Beyond scope of source address
+    BeyondScope = 2,
+    /// Address unreachable
+    Address = 3,
+    /// Port unreachable
+    Port = 4,
+}
+
+/// Parameter problem codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ParameterProblemCode {
+    /// Erroneous header field
+    ErroneousField = 0,
+    /// Unrecognized next header type
+    UnrecognizedNextHeader = 1,
+    /// Unrecognized IPv6 option
+    UnrecognizedOption = 2,
+}
+
+/// ICMPv6 header (8 bytes, including the first four bytes of the body)
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct Icmpv6Header {
+    /// Message type
+    pub icmp_type: u8,
+    /// Message code
+    pub code: u8,
+    /// Checksum
+    pub checksum: u16,
+    /// Message-specific word (identifier/sequence, MTU, pointer, flags)
+    pub rest: u32,
+}
+
+impl Icmpv6Header {
+    /// ICMPv6 header size
+    pub const SIZE: usize = 8;
+
+    /// Create new ICMPv6 header
+    pub const fn new(icmp_type: Icmpv6Type, code: u8, rest: u32) -> Self {
+        Self {
+            icmp_type: icmp_type.as_u8(),
+            code,
+            checksum: 0,
+            rest: rest.to_be(),
+        }
+    }
+
+    /// Parse header from bytes
+    pub fn parse(data: &[u8]) -> Result<Self, Icmpv6Error> {
+        if data.len() < Self::SIZE {
+            return Err(Icmpv6Error::TooShort);
+        }
+
+        Ok(Self {
+            icmp_type: data[0],
+            code: data[1],
+            checksum: u16::from_be_bytes([data[2], data[3]]).to_be(),
+            rest: u32::from_be_bytes([data[4], data[5], data[6], data[7]]).to_be(),
+        })
+    }
+
+    /// Write header to buffer
+    pub fn write_to(&self, buffer: &mut [u8]) -> Result<(), Icmpv6Error> {
+        if buffer.len() < Self::SIZE {
+            return Err(Icmpv6Error::BufferTooSmall);
+        }
+
+        buffer[0] = self.icmp_type;
+        buffer[1] = self.code;
+        buffer[2..4].copy_from_slice(&u16::from_be(self.checksum).to_be_bytes());
+        buffer[4..8].copy_from_slice(&u32::from_be(self.rest).to_be_bytes());
+
+        Ok(())
+    }
+
+    /// Get message type
+    pub fn get_type(&self) -> Icmpv6Type {
+        Icmpv6Type::from_u8(self.icmp_type)
+    }
+
+    /// Get checksum
+    pub fn checksum(&self) -> u16 {
+        u16::from_be(self.checksum)
+    }
+
+    /// Get message-specific word
+    pub fn rest(&self) -> u32 {
+        u32::from_be(self.rest)
+    }
+}
+
+/// Build an ICMPv6 message with a valid checksum
+///
+/// Unlike ICMPv4, the checksum covers an IPv6 pseudo-header, so the
+/// addresses the message will be sent between are needed.
+pub fn build_message(src: Ipv6Addr, dst: Ipv6Addr, header: Icmpv6Header, body: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(Icmpv6Header::SIZE + body.len());
+    message.resize(Icmpv6Header::SIZE, 0);
+    let _ = header.write_to(&mut message);
+    message.extend_from_slice(body);
+
+    let checksum = checksum(src, dst, &message);
+    message[2..4].copy_from_slice(&checksum.to_be_bytes());
+    message
+}
+
+/// Checksum of a message with its checksum field included as-is
+fn checksum(src: Ipv6Addr, dst: Ipv6Addr, message: &[u8]) -> u16 {
+    let mut sum = calculate_pseudo_header_checksum(
+        src,
+        dst,
+        IpProtocol::Icmpv6.as_u8(),
+        message.len() as u32,
+    );
+
+    for chunk in message.chunks(2) {
+        let word = if chunk.len() == 2 {
+            u16::from_be_bytes([chunk[0], chunk[1]])
+        } else {
+            u16::from_be_bytes([chunk[0], 0])
+        };
+        sum += word as u32;
+    }
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    !sum as u16
+}
+
+/// Verify the checksum of an ICMPv6 message
+pub fn verify_checksum(src: Ipv6Addr, dst: Ipv6Addr, message: &[u8]) -> bool {
+    checksum(src, dst, message) == 0
+}
+
+/// Process incoming ICMPv6 message
+///
+/// `device` is the interface the packet arrived on, which Neighbor
+/// Discovery needs; `hop_limit` is taken from the IPv6 header.
+pub fn process_packet(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    ip_payload: &[u8],
+    src_ip: Ipv6Addr,
+    dst_ip: Ipv6Addr,
+    hop_limit: u8,
+) -> Result<(), Icmpv6Error> {
+    let header = Icmpv6Header::parse(ip_payload)?;
+
+    if !verify_checksum(src_ip, dst_ip, ip_payload) {
+        return Err(Icmpv6Error::InvalidChecksum);
+    }
+
+    match header.get_type() {
+        Icmpv6Type::EchoRequest => {
+            // Multicast requests are answered from a unicast address
+            let src = if dst_ip.is_multicast() {
+                ipv6::select_source(src_ip)
+            } else {
+                dst_ip
+            };
+
+            let reply = build_message(
+                src,
+                src_ip,
+                Icmpv6Header::new(Icmpv6Type::EchoReply, 0, header.rest()),
+                &ip_payload[Icmpv6Header::SIZE..],
+            );
+            ipv6::send_packet(src, src_ip, IpProtocol::Icmpv6, &reply)
+                .map_err(|_| Icmpv6Error::TransmitFailed)
+        }
+        Icmpv6Type::RouterSolicitation
+        | Icmpv6Type::RouterAdvertisement
+        | Icmpv6Type::NeighborSolicitation
+        | Icmpv6Type::NeighborAdvertisement
+        | Icmpv6Type::Redirect => {
+            ndisc::process_message(device, ip_payload, src_ip, dst_ip, hop_limit)
+                .map_err(Icmpv6Error::Ndisc)
+        }
+        // Errors are not reported to sockets yet
+        Icmpv6Type::DestUnreachable
+        | Icmpv6Type::PacketTooBig
+        | Icmpv6Type::TimeExceeded
+        | Icmpv6Type::ParameterProblem => Ok(()),
+        Icmpv6Type::EchoReply => Err(Icmpv6Error::NoSocket),
+        Icmpv6Type::Unknown => Err(Icmpv6Error::UnsupportedType),
+    }
+}
+
+/// Minimum IPv6 link MTU, which error messages must fit in
+const MIN_MTU: usize = 1280;
+
+/// Send an ICMPv6 error about a received packet
+///
+/// `original` is the complete offending IPv6 packet, quoted as far as the
+/// error fits in the minimum MTU. Following RFC 4443 section 2.4, no error
+/// is generated in response to another error, for packets from unspecified
+/// or multicast sources, or for multicast destinations unless the error is
+/// Packet Too Big or an unrecognized option that asks to be reported.
+pub fn send_error(
+    icmp_type: Icmpv6Type,
+    code: u8,
+    rest: u32,
+    original: &[u8],
+) -> Result<(), Icmpv6Error> {
+    let header = Ipv6Header::parse(original).map_err(|_| Icmpv6Error::TooShort)?;
+    let src = header.src_addr();
+    let dst = header.dst_addr();
+
+    if src.is_unspecified() || src.is_multicast() {
+        return Ok(());
+    }
+
+    let multicast_allowed = icmp_type == Icmpv6Type::PacketTooBig
+        || (icmp_type == Icmpv6Type::ParameterProblem
+            && code == ParameterProblemCode::UnrecognizedOption as u8);
+    if dst.is_multicast() && !multicast_allowed {
+        return Ok(());
+    }
+
+    if header.next_header == IpProtocol::Icmpv6.as_u8() {
+        let is_error = original
+            .get(Ipv6Header::SIZE)
+            .is_some_and(|&t| Icmpv6Type::is_error_type(t));
+        if is_error {
+            return Ok(());
+        }
+    }
+
+    let reply_src = if dst.is_multicast() {
+        ipv6::select_source(src)
+    } else {
+        dst
+    };
+
+    let quoted_len = original
+        .len()
+        .min(MIN_MTU - Ipv6Header::SIZE - Icmpv6Header::SIZE);
+    let message = build_message(
+        reply_src,
+        src,
+        Icmpv6Header::new(icmp_type, code, rest),
+        &original[..quoted_len],
+    );
+
+    ipv6::send_packet(reply_src, src, IpProtocol::Icmpv6, &message)
+        .map_err(|_| Icmpv6Error::TransmitFailed)
+}
+
+/// Send a destination unreachable error about a received packet
+pub fn send_dest_unreachable(code: UnreachableCode, original: &[u8]) -> Result<(), Icmpv6Error> {
+    send_error(Icmpv6Type::DestUnreachable, code as u8, 0, original)
+}
+
+/// Send a parameter problem error pointing at an offset in the packet
+pub fn send_parameter_problem(
+    code: ParameterProblemCode,
+    pointer: u32,
+    original: &[u8],
+) -> Result<(), Icmpv6Error> {
+    send_error(Icmpv6Type::ParameterProblem, code as u8, pointer, original)
+}
+
+/// ICMPv6 errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Icmpv6Error {
+    /// Message too short
+    TooShort,
+    /// Buffer too small
+    BufferTooSmall,
+    /// Invalid checksum
+    InvalidChecksum,
+    /// Unsupported message type
+    UnsupportedType,
+    /// No socket for the message
+    NoSocket,
+    /// Neighbor Discovery message rejected
+    Ndisc(NdiscError),
+    /// Failed to send a reply
+    TransmitFailed,
+}
+
+/// ICMPv6 subsystem initialized flag
+static ICMPV6_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Initialize ICMPv6 subsystem
+pub fn init() {
+    if ICMPV6_INITIALIZED.load(Ordering::Acquire) {
+        return;
+    }
+
+    ICMPV6_INITIALIZED.store(true, Ordering::Release);
+}
+
+/// Check if ICMPv6 subsystem is initialized
+pub fn is_initialized() -> bool {
+    ICMPV6_INITIALIZED.load(Ordering::Acquire)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_checksum() {
+        let src = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let dst = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+        let header = Icmpv6Header::new(Icmpv6Type::EchoRequest, 0, 0x1234_0001);
+        let mut message = build_message(src, dst, header, b"ping6");
+
+        assert_eq!(message[0], 128);
+        assert!(verify_checksum(src, dst, &message));
+        // The pseudo-header binds the checksum to the addresses
+        assert!(!verify_checksum(src, Ipv6Addr::LOCALHOST, &message));
+
+        message[9] ^= 0xFF;
+        assert!(!verify_checksum(src, dst, &message));
+    }
+
+    #[test]
+    fn test_error_types() {
+        assert!(Icmpv6Type::is_error_type(1));
+        assert!(Icmpv6Type::is_error_type(4));
+        assert!(!Icmpv6Type::is_error_type(128));
+        assert_eq!(Icmpv6Type::from_u8(135), Icmpv6Type::NeighborSolicitation);
+    }
+}
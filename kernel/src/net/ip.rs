@@ -0,0 +1,305 @@
+//! Dual-stack IP addressing
+//!
+//! Version-independent addresses and endpoints used by TCP and UDP, and
+//! dispatch of outgoing traffic to IPv4 or IPv6.
+
+use core::fmt;
+
+use super::ipv4::{self, IpProtocol, Ipv4Addr, Ipv4Error, Ipv4Header};
+use super::ipv6::{self, Ipv6Addr, Ipv6Error, Ipv6Header};
+use super::socket::{SocketAddr, SocketAddrV4, SocketAddrV6, SocketError};
+
+/// IPv4 or IPv6 address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IpAddr {
+    /// IPv4 address
+    V4(Ipv4Addr),
+    /// IPv6 address
+    V6(Ipv6Addr),
+}
+
+impl IpAddr {
+    /// Check if this is the unspecified address
+    pub const fn is_unspecified(&self) -> bool {
+        match self {
+            Self::V4(addr) => addr.is_unspecified(),
+            Self::V6(addr) => addr.is_unspecified(),
+        }
+    }
+
+    /// Check if this is a loopback address
+    pub const fn is_loopback(&self) -> bool {
+        match self {
+            Self::V4(addr) => addr.is_loopback(),
+            Self::V6(addr) => addr.is_loopback(),
+        }
+    }
+
+    /// Check if this is a multicast address
+    pub const fn is_multicast(&self) -> bool {
+        match self {
+            Self::V4(addr) => addr.is_multicast(),
+            Self::V6(addr) => addr.is_multicast(),
+        }
+    }
+
+    /// Check if this is the IPv4 broadcast address
+    pub const fn is_broadcast(&self) -> bool {
+        match self {
+            Self::V4(addr) => addr.is_broadcast(),
+            Self::V6(_) => false,
+        }
+    }
+
+    /// Check if this is an IPv6 address
+    pub const fn is_ipv6(&self) -> bool {
+        matches!(self, Self::V6(_))
+    }
+
+    /// Unspecified address of the same family
+    pub const fn unspecified(&self) -> Self {
+        match self {
+            Self::V4(_) => Self::V4(Ipv4Addr::UNSPECIFIED),
+            Self::V6(_) => Self::V6(Ipv6Addr::UNSPECIFIED),
+        }
+    }
+
+    /// Convert IPv4-mapped IPv6 addresses to plain IPv4
+    pub const fn to_canonical(&self) -> Self {
+        match self {
+            Self::V6(addr) => match addr.to_ipv4_mapped() {
+                Some(v4) => Self::V4(v4),
+                None => *self,
+            },
+            Self::V4(_) => *self,
+        }
+    }
+
+    /// Size of the IP header in front of transport data
+    pub const fn header_size(&self) -> usize {
+        match self {
+            Self::V4(_) => Ipv4Header::MIN_SIZE,
+            Self::V6(_) => Ipv6Header::SIZE,
+        }
+    }
+}
+
+impl From<Ipv4Addr> for IpAddr {
+    fn from(addr: Ipv4Addr) -> Self {
+        Self::V4(addr)
+    }
+}
+
+impl From<Ipv6Addr> for IpAddr {
+    fn from(addr: Ipv6Addr) -> Self {
+        Self::V6(addr)
+    }
+}
+
+impl fmt::Display for IpAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V4(addr) => addr.fmt(f),
+            Self::V6(addr) => addr.fmt(f),
+        }
+    }
+}
+
+/// Transport endpoint: an address and a port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpEndpoint {
+    /// Address
+    pub ip: IpAddr,
+    /// Port
+    pub port: u16,
+}
+
+impl IpEndpoint {
+    /// Create new endpoint
+    pub const fn new(ip: IpAddr, port: u16) -> Self {
+        Self { ip, port }
+    }
+
+    /// Check if a local endpoint bound to this address takes traffic for `dst`
+    ///
+    /// An unspecified IPv4 address accepts any IPv4 destination; an
+    /// unspecified IPv6 address accepts both families.
+    pub fn accepts(&self, dst: IpAddr) -> bool {
+        match (self.ip, dst) {
+            (IpAddr::V4(local), IpAddr::V4(_)) if local.is_unspecified() => true,
+            (IpAddr::V6(local), _) if local.is_unspecified() => true,
+            (local, dst) => local == dst,
+        }
+    }
+
+    /// Convert to a socket address of the matching family
+    pub fn to_socket_addr(&self) -> SocketAddr {
+        match self.ip {
+            IpAddr::V4(ip) => SocketAddr::V4(SocketAddrV4 {
+                ip: ip.0,
+                port: self.port,
+            }),
+            IpAddr::V6(ip) => SocketAddr::V6(SocketAddrV6 {
+                ip: ip.0,
+                port: self.port,
+                flowinfo: 0,
+                scope_id: 0,
+            }),
+        }
+    }
+
+    /// Convert to an IPv6 socket address, mapping IPv4 addresses
+    pub fn to_socket_addr_v6(&self) -> SocketAddrV6 {
+        let ip = match self.ip {
+            IpAddr::V4(ip) => Ipv6Addr::from_ipv4_mapped(ip),
+            IpAddr::V6(ip) => ip,
+        };
+        SocketAddrV6 {
+            ip: ip.0,
+            port: self.port,
+            flowinfo: 0,
+            scope_id: 0,
+        }
+    }
+}
+
+impl From<SocketAddrV4> for IpEndpoint {
+    fn from(addr: SocketAddrV4) -> Self {
+        Self::new(IpAddr::V4(Ipv4Addr(addr.ip)), addr.port)
+    }
+}
+
+impl From<SocketAddrV6> for IpEndpoint {
+    /// IPv4-mapped addresses become IPv4 endpoints
+    fn from(addr: SocketAddrV6) -> Self {
+        Self::new(IpAddr::V6(Ipv6Addr(addr.ip)).to_canonical(), addr.port)
+    }
+}
+
+impl TryFrom<SocketAddr> for IpEndpoint {
+    type Error = SocketError;
+
+    fn try_from(addr: SocketAddr) -> Result<Self, Self::Error> {
+        match addr {
+            SocketAddr::V4(addr) => Ok(addr.into()),
+            SocketAddr::V6(addr) => Ok(addr.into()),
+            SocketAddr::Unix(_) | SocketAddr::Packet(_) => Err(SocketError::NotSupported),
+        }
+    }
+}
+
+impl fmt::Display for IpEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ip {
+            IpAddr::V4(ip) => write!(f, "{}:{}", ip, self.port),
+            IpAddr::V6(ip) => write!(f, "[{}]:{}", ip, self.port),
+        }
+    }
+}
+
+/// Calculate the pseudo-header checksum for TCP or UDP
+///
+/// Addresses of different families are compared as IPv6, mapping the IPv4
+/// one; this cannot happen for traffic that came off the wire.
+pub fn pseudo_header_checksum(
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: IpProtocol,
+    length: usize,
+) -> u32 {
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            ipv4::calculate_pseudo_header_checksum(src, dst, protocol, length as u16)
+        }
+        (src, dst) => ipv6::calculate_pseudo_header_checksum(
+            to_ipv6(src),
+            to_ipv6(dst),
+            protocol.as_u8(),
+            length as u32,
+        ),
+    }
+}
+
+/// IPv6 form of an address, mapping IPv4
+fn to_ipv6(addr: IpAddr) -> Ipv6Addr {
+    match addr {
+        IpAddr::V4(addr) => Ipv6Addr::from_ipv4_mapped(addr),
+        IpAddr::V6(addr) => addr,
+    }
+}
+
+/// Select the source address for traffic to a destination
+pub fn select_source(dst: IpAddr) -> IpAddr {
+    match dst {
+        IpAddr::V4(dst) => IpAddr::V4(ipv4::select_source(dst)),
+        IpAddr::V6(dst) => IpAddr::V6(ipv6::select_source(dst)),
+    }
+}
+
+/// MTU of the device that traffic to a destination leaves through
+pub fn route_mtu(dst: IpAddr) -> Option<usize> {
+    match dst {
+        IpAddr::V4(dst) => ipv4::route_mtu(dst),
+        IpAddr::V6(dst) => ipv6::route_mtu(dst),
+    }
+}
+
+/// Send a transport payload over IPv4 or IPv6
+pub fn send_packet(
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: IpProtocol,
+    payload: &[u8],
+) -> Result<(), SocketError> {
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => ipv4::send_packet(src, dst, protocol, payload)
+            .map_err(|e| match e {
+                Ipv4Error::NoRoute => SocketError::NetUnreachable,
+                Ipv4Error::Filtered => SocketError::PermissionDenied,
+                Ipv4Error::PacketTooLarge | Ipv4Error::FragmentationNeeded => {
+                    SocketError::InvalidArg
+                }
+                _ => SocketError::Other,
+            }),
+        (IpAddr::V6(src), IpAddr::V6(dst)) => ipv6::send_packet(src, dst, protocol, payload)
+            .map_err(|e| match e {
+                Ipv6Error::NoRoute => SocketError::NetUnreachable,
+                Ipv6Error::PacketTooLarge => SocketError::InvalidArg,
+                _ => SocketError::Other,
+            }),
+        _ => Err(SocketError::NotSupported),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_accepts() {
+        let any_v4 = IpEndpoint::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 80);
+        let any_v6 = IpEndpoint::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 80);
+        let v4 = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+
+        assert!(any_v4.accepts(v4));
+        assert!(!any_v4.accepts(v6));
+        assert!(any_v6.accepts(v4));
+        assert!(any_v6.accepts(v6));
+        assert!(IpEndpoint::new(v6, 80).accepts(v6));
+        assert!(!IpEndpoint::new(v6, 80).accepts(v4));
+    }
+
+    #[test]
+    fn test_mapped_socket_addr() {
+        let mapped = SocketAddrV6 {
+            ip: Ipv6Addr::from_ipv4_mapped(Ipv4Addr::new(192, 168, 1, 1)).0,
+            port: 53,
+            flowinfo: 0,
+            scope_id: 0,
+        };
+        let endpoint = IpEndpoint::from(mapped);
+        assert_eq!(endpoint.ip, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(endpoint.to_socket_addr_v6().ip, mapped.ip);
+    }
+}
@@ -2,8 +2,24 @@
 //!
 //! IPv4 packet handling, routing, and checksum calculation.
 
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::fmt;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+use super::ethernet::MacAddress;
+use super::netdev::NetDevice;
+use super::netfilter::{self, Hook, Verdict};
+use super::{arp, loopback, netdev};
+use spin::Mutex;
+
+pub mod addr;
+pub mod forward;
+pub mod fragment;
+pub mod route;
 
 /// IPv4 address (4 bytes)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -82,6 +98,27 @@ impl fmt::Display for Ipv4Addr {
     }
 }
 
+impl FromStr for Ipv4Addr {
+    type Err = Ipv4Error;
+
+    /// Parse dotted-quad notation
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut octets = [0u8; 4];
+        let mut parts = s.split('.');
+        for octet in octets.iter_mut() {
+            let part = parts.next().ok_or(Ipv4Error::InvalidAddress)?;
+            if part.is_empty() || part.len() > 3 || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(Ipv4Error::InvalidAddress);
+            }
+            *octet = part.parse().map_err(|_| Ipv4Error::InvalidAddress)?;
+        }
+        if parts.next().is_some() {
+            return Err(Ipv4Error::InvalidAddress);
+        }
+        Ok(Self(octets))
+    }
+}
+
 /// IP protocol numbers
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -92,6 +129,8 @@ pub enum IpProtocol {
     Tcp = 6,
     /// UDP
     Udp = 17,
+    /// ICMPv6
+    Icmpv6 = 58,
     /// Unknown
     Unknown = 0xFF,
 }
@@ -103,6 +142,7 @@ impl IpProtocol {
             1 => Self::Icmp,
             6 => Self::Tcp,
             17 => Self::Udp,
+            58 => Self::Icmpv6,
             _ => Self::Unknown,
         }
     }
@@ -260,6 +300,38 @@ impl Ipv4Header {
         u16::from_be(self.checksum)
     }
 
+    /// Get identification
+    pub fn identification(&self) -> u16 {
+        u16::from_be(self.identification)
+    }
+
+    /// Check if the Don't Fragment flag is set
+    pub fn dont_fragment(&self) -> bool {
+        u16::from_be(self.flags_fragment) & 0x4000 != 0
+    }
+
+    /// Set or clear the Don't Fragment flag
+    pub fn set_dont_fragment(&mut self, dont_fragment: bool) {
+        let flags = u16::from_be(self.flags_fragment) & !0x4000;
+        let flags = if dont_fragment { flags | 0x4000 } else { flags };
+        self.flags_fragment = flags.to_be();
+    }
+
+    /// Check if the More Fragments flag is set
+    pub fn more_fragments(&self) -> bool {
+        u16::from_be(self.flags_fragment) & 0x2000 != 0
+    }
+
+    /// Get fragment offset in bytes
+    pub fn fragment_offset(&self) -> usize {
+        ((u16::from_be(self.flags_fragment) & 0x1FFF) as usize) * 8
+    }
+
+    /// Check if the packet is a fragment of a larger datagram
+    pub fn is_fragment(&self) -> bool {
+        self.more_fragments() || self.fragment_offset() != 0
+    }
+
     /// Calculate checksum
     pub fn calculate_checksum(&mut self) {
         // Zero out checksum field
@@ -269,8 +341,8 @@ impl Ipv4Header {
         let mut buffer = [0u8; Self::MIN_SIZE];
         let _ = self.write_to(&mut buffer);
 
-        // Calculate checksum
-        let checksum = calculate_checksum(&buffer[..self.header_length()]);
+        // Calculate checksum (options are not stored in the header struct)
+        let checksum = calculate_checksum(&buffer[..self.header_length().min(Self::MIN_SIZE)]);
         self.checksum = checksum.to_be();
     }
 
@@ -279,7 +351,7 @@ impl Ipv4Header {
         let mut buffer = [0u8; Self::MIN_SIZE];
         let _ = self.write_to(&mut buffer);
 
-        let checksum = calculate_checksum(&buffer[..self.header_length()]);
+        let checksum = calculate_checksum(&buffer[..self.header_length().min(Self::MIN_SIZE)]);
         checksum == 0
     }
 }
@@ -311,6 +383,10 @@ impl<'a> Ipv4Packet<'a> {
         let header_len = header.header_length();
         let total_len = header.total_length() as usize;
 
+        if total_len < header_len {
+            return Err(Ipv4Error::InvalidHeaderLength);
+        }
+
         if self.data.len() < total_len {
             return Err(Ipv4Error::TooShort);
         }
@@ -332,6 +408,143 @@ impl<'a> Ipv4Packet<'a> {
     pub fn protocol(&self) -> Result<IpProtocol, Ipv4Error> {
         Ok(self.header()?.protocol())
     }
+
+    /// Verify the header checksum, including any options
+    pub fn verify_checksum(&self) -> bool {
+        match self.header() {
+            Ok(header) => calculate_checksum(&self.data[..header.header_length()]) == 0,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Identification counter for outgoing datagrams
+static NEXT_IDENTIFICATION: AtomicU16 = AtomicU16::new(1);
+
+/// Build a complete IPv4 packet (header + payload)
+pub fn build_packet(
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    protocol: IpProtocol,
+    payload: &[u8],
+) -> Result<Vec<u8>, Ipv4Error> {
+    let total_len = Ipv4Header::MIN_SIZE + payload.len();
+    if total_len > u16::MAX as usize {
+        return Err(Ipv4Error::PacketTooLarge);
+    }
+
+    let mut header = Ipv4Header::new(src, dst, protocol, payload.len() as u16);
+    header.identification = NEXT_IDENTIFICATION.fetch_add(1, Ordering::Relaxed).to_be();
+    header.calculate_checksum();
+
+    let mut packet = vec![0u8; total_len];
+    header.write_to(&mut packet)?;
+    packet[Ipv4Header::MIN_SIZE..].copy_from_slice(payload);
+
+    Ok(packet)
+}
+
+/// Resolve where traffic to a destination leaves
+///
+/// Loopback destinations and this host's own addresses are delivered
+/// through the loopback device, and the limited broadcast address is never
+/// routed: it leaves the default device, even before that device has an
+/// address. Everything else follows the routing table.
+fn next_hop(dst: Ipv4Addr) -> Option<route::NextHop> {
+    if dst.is_loopback() || addr::is_local_address(dst) {
+        return Some(route::NextHop {
+            device: loopback::device()?,
+            address: dst,
+            source: dst,
+        });
+    }
+
+    if dst.is_broadcast() {
+        let device = netdev::default_device()?;
+        let name = device.lock().name().to_string();
+        return Some(route::NextHop {
+            device,
+            address: dst,
+            source: addr::primary_address(&name).unwrap_or(Ipv4Addr::UNSPECIFIED),
+        });
+    }
+
+    route::resolve(dst)
+}
+
+/// Select the source address for traffic to a destination
+///
+/// Local destinations are answered from the destination itself; anything
+/// else uses the preferred source of its route, or the unspecified address
+/// if there is none.
+pub fn select_source(dst: Ipv4Addr) -> Ipv4Addr {
+    next_hop(dst).map_or(Ipv4Addr::UNSPECIFIED, |hop| hop.source)
+}
+
+/// MTU of the device that traffic to a destination leaves through
+pub fn route_mtu(dst: Ipv4Addr) -> Option<usize> {
+    let mtu = next_hop(dst)?.device.lock().mtu();
+    Some(mtu)
+}
+
+/// Send an IPv4 datagram
+///
+/// Builds the IPv4 header and hands the packet to `send_datagram`.
+pub fn send_packet(
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    protocol: IpProtocol,
+    payload: &[u8],
+) -> Result<(), Ipv4Error> {
+    let packet = build_packet(src, dst, protocol, payload)?;
+    send_datagram(&packet)
+}
+
+/// Transmit a complete IPv4 datagram
+///
+/// Looks up the route, resolves the next hop and fragments the datagram
+/// to the device MTU unless it has DF set.
+pub fn send_datagram(packet: &[u8]) -> Result<(), Ipv4Error> {
+    let header = Ipv4Header::parse(packet)?;
+    let hop = next_hop(header.dst_addr()).ok_or(Ipv4Error::NoRoute)?;
+
+    let device = hop.device.lock().name().to_string();
+    for hook in [Hook::Output, Hook::Postrouting] {
+        if netfilter::filter(hook, packet, None, Some(&device)) == Verdict::Drop {
+            return Err(Ipv4Error::Filtered);
+        }
+    }
+
+    // ARP requests carry our address on the link when one is known
+    let src = if header.src_addr().is_unspecified() {
+        hop.source
+    } else {
+        header.src_addr()
+    };
+
+    let mtu = hop.device.lock().mtu();
+    for fragment in fragment::fragment(packet, mtu)? {
+        transmit(&hop.device, src, hop.address, &fragment)?;
+    }
+
+    Ok(())
+}
+
+/// Hand a packet that fits the MTU to a device
+fn transmit(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    src: Ipv4Addr,
+    next_hop: Ipv4Addr,
+    packet: &[u8],
+) -> Result<(), Ipv4Error> {
+    let loopback = device.lock().is_loopback();
+    let result = if loopback {
+        arp::transmit_ipv4(device, MacAddress::ZERO, packet)
+    } else {
+        arp::send_ipv4_packet(device, src, next_hop, packet)
+    };
+
+    result.map_err(|_| Ipv4Error::TransmitFailed)
 }
 
 /// Calculate IP checksum
@@ -359,6 +572,14 @@ pub fn calculate_checksum(data: &[u8]) -> u16 {
     !sum as u16
 }
 
+/// Recompute the checksum of a serialized header (options included)
+pub fn fill_header_checksum(header: &mut [u8]) {
+    header[10] = 0;
+    header[11] = 0;
+    let checksum = calculate_checksum(header);
+    header[10..12].copy_from_slice(&checksum.to_be_bytes());
+}
+
 /// Calculate pseudo-header checksum for TCP/UDP
 pub fn calculate_pseudo_header_checksum(
     src: Ipv4Addr,
@@ -400,6 +621,22 @@ pub enum Ipv4Error {
     InvalidChecksum,
     /// Invalid address
     InvalidAddress,
+    /// Packet exceeds the maximum datagram size
+    PacketTooLarge,
+    /// Packet exceeds the MTU but has DF set
+    FragmentationNeeded,
+    /// Fragment inconsistent with the rest of its datagram
+    InvalidFragment,
+    /// No route to destination
+    NoRoute,
+    /// Device failed to transmit
+    TransmitFailed,
+    /// Dropped by a firewall rule
+    Filtered,
+    /// TTL ran out while forwarding
+    TtlExceeded,
+    /// No NAT binding could be made for a forwarded datagram
+    NoTranslation,
 }
 
 /// IPv4 subsystem initialized flag
@@ -411,6 +648,24 @@ pub fn init() {
         return;
     }
 
+    if crate::time::timer::create_periodic_timer(
+        fragment::REASSEMBLY_TIMER_INTERVAL_MS,
+        fragment::timer_tick,
+    )
+    .is_err()
+    {
+        crate::printk::printk("  Failed to start IPv4 reassembly timer\n");
+    }
+
+    if let Some(lo) = loopback::device() {
+        let name = lo.lock().name().to_string();
+        let _ = addr::add_address(&name, Ipv4Addr::LOCALHOST, 8);
+    }
+
+    if crate::cmdline::ip_forward() {
+        forward::set_enabled(true);
+    }
+
     IPV4_INITIALIZED.store(true, Ordering::Release);
 }
 
@@ -438,6 +693,22 @@ mod tests {
         assert!(Ipv4Addr::UNSPECIFIED.is_unspecified());
     }
 
+    #[test]
+    fn test_ipv4_addr_parse() {
+        assert_eq!("10.0.2.15".parse(), Ok(Ipv4Addr::new(10, 0, 2, 15)));
+        assert_eq!("255.255.255.0".parse(), Ok(Ipv4Addr::new(255, 255, 255, 0)));
+        for bad in [
+            "",
+            "10.0.2",
+            "10.0.2.15.1",
+            "10.0.2.256",
+            "10..2.15",
+            "+1.0.0.1",
+        ] {
+            assert_eq!(bad.parse::<Ipv4Addr>(), Err(Ipv4Error::InvalidAddress));
+        }
+    }
+
     #[test]
     fn test_ip_protocol() {
         assert_eq!(IpProtocol::from_u8(6), IpProtocol::Tcp);
@@ -471,4 +742,22 @@ mod tests {
         // Verify checksum is calculated (specific value depends on data)
         assert_ne!(checksum, 0);
     }
+
+    #[test]
+    fn test_build_packet() {
+        let src = Ipv4Addr::new(10, 0, 0, 1);
+        let dst = Ipv4Addr::new(10, 0, 0, 2);
+        let payload = [0xDE, 0xAD, 0xBE, 0xEF];
+
+        let packet = build_packet(src, dst, IpProtocol::Udp, &payload).unwrap();
+        assert_eq!(packet.len(), Ipv4Header::MIN_SIZE + payload.len());
+
+        let parsed = Ipv4Packet::new(&packet).unwrap();
+        let header = parsed.header().unwrap();
+        assert!(header.verify_checksum());
+        assert_eq!(header.src_addr(), src);
+        assert_eq!(header.dst_addr(), dst);
+        assert_eq!(header.protocol(), IpProtocol::Udp);
+        assert_eq!(parsed.payload().unwrap(), &payload);
+    }
 }
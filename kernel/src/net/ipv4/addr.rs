@@ -0,0 +1,279 @@
+//! IPv4 Interface Addresses
+//!
+//! Addresses assigned to network devices. Assigning an address installs
+//! the connected route for its subnet.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+
+use super::route::{self, Route, RouteProtocol};
+use super::Ipv4Addr;
+use crate::net::loopback::LOOPBACK_NAME;
+use crate::net::netdev;
+
+/// Address assigned to an interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceAddress {
+    /// Address
+    pub address: Ipv4Addr,
+    /// Subnet prefix length
+    pub prefix_len: u8,
+}
+
+impl InterfaceAddress {
+    /// Create new interface address
+    pub const fn new(address: Ipv4Addr, prefix_len: u8) -> Self {
+        Self {
+            address,
+            prefix_len,
+        }
+    }
+
+    /// Subnet mask
+    pub const fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from_u32(prefix_mask(self.prefix_len))
+    }
+
+    /// Subnet address
+    pub const fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from_u32(self.address.as_u32() & prefix_mask(self.prefix_len))
+    }
+
+    /// Directed broadcast address of the subnet
+    pub const fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from_u32(self.address.as_u32() | !prefix_mask(self.prefix_len))
+    }
+
+    /// Check if an address is in the subnet
+    pub const fn contains(&self, addr: Ipv4Addr) -> bool {
+        let mask = prefix_mask(self.prefix_len);
+        addr.as_u32() & mask == self.address.as_u32() & mask
+    }
+}
+
+impl fmt::Display for InterfaceAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)?;
+        if self.prefix_len < 31 {
+            write!(f, " brd {}", self.broadcast())?;
+        }
+        Ok(())
+    }
+}
+
+/// Subnet mask of a prefix length as a host-order integer
+pub const fn prefix_mask(prefix_len: u8) -> u32 {
+    if prefix_len == 0 {
+        0
+    } else if prefix_len >= 32 {
+        u32::MAX
+    } else {
+        u32::MAX << (32 - prefix_len as u32)
+    }
+}
+
+/// Addresses by device name
+static INTERFACE_ADDRESSES: Mutex<BTreeMap<String, Vec<InterfaceAddress>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Assign an address to a device
+///
+/// Also installs the connected route for the subnet, using the address as
+/// the preferred source.
+pub fn add_address(device: &str, address: Ipv4Addr, prefix_len: u8) -> Result<(), AddrError> {
+    if prefix_len > 32 {
+        return Err(AddrError::InvalidPrefix);
+    }
+    if address.is_unspecified() || address.is_multicast() || address.is_broadcast() {
+        return Err(AddrError::InvalidAddress);
+    }
+    if netdev::get_device(device).is_none() {
+        return Err(AddrError::NoDevice);
+    }
+
+    let entry = InterfaceAddress::new(address, prefix_len);
+    {
+        let mut addresses = INTERFACE_ADDRESSES.lock();
+        let list = addresses.entry(device.to_string()).or_default();
+        if list.iter().any(|a| a.address == address) {
+            return Err(AddrError::AddressExists);
+        }
+        list.push(entry);
+    }
+
+    // Another address in the same subnet may have installed it already
+    let _ = route::add_route(
+        Route::new(entry.network(), prefix_len, device)
+            .with_source(address)
+            .with_protocol(RouteProtocol::Kernel),
+    );
+    Ok(())
+}
+
+/// Remove an address from a device, along with its connected route
+pub fn remove_address(device: &str, address: Ipv4Addr) -> Result<(), AddrError> {
+    let (removed, subnet_in_use) = {
+        let mut addresses = INTERFACE_ADDRESSES.lock();
+        let list = addresses.get_mut(device).ok_or(AddrError::NotFound)?;
+        let pos = list
+            .iter()
+            .position(|a| a.address == address)
+            .ok_or(AddrError::NotFound)?;
+        let removed = list.remove(pos);
+        let in_use = list
+            .iter()
+            .any(|a| a.prefix_len == removed.prefix_len && a.network() == removed.network());
+        (removed, in_use)
+    };
+
+    if !subnet_in_use {
+        let _ = route::remove_route(removed.network(), removed.prefix_len, Some(device));
+    }
+    Ok(())
+}
+
+/// Remove all addresses and routes of a device
+pub fn flush(device: &str) {
+    INTERFACE_ADDRESSES.lock().remove(device);
+    route::flush_device(device);
+}
+
+/// Get the addresses assigned to a device
+pub fn addresses(device: &str) -> Vec<InterfaceAddress> {
+    INTERFACE_ADDRESSES
+        .lock()
+        .get(device)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Get all assigned addresses with their device names
+pub fn all_addresses() -> Vec<(String, InterfaceAddress)> {
+    INTERFACE_ADDRESSES
+        .lock()
+        .iter()
+        .flat_map(|(name, list)| list.iter().map(move |a| (name.clone(), *a)))
+        .collect()
+}
+
+/// Get the first address assigned to a device
+pub fn primary_address(device: &str) -> Option<Ipv4Addr> {
+    INTERFACE_ADDRESSES
+        .lock()
+        .get(device)
+        .and_then(|list| list.first())
+        .map(|a| a.address)
+}
+
+/// Check if an address is assigned to any device
+pub fn is_local_address(address: Ipv4Addr) -> bool {
+    INTERFACE_ADDRESSES
+        .lock()
+        .values()
+        .any(|list| list.iter().any(|a| a.address == address))
+}
+
+/// Check if a destination is a directed broadcast of a device's subnets
+pub fn is_subnet_broadcast(device: &str, address: Ipv4Addr) -> bool {
+    INTERFACE_ADDRESSES.lock().get(device).is_some_and(|list| {
+        list.iter()
+            .any(|a| a.prefix_len < 31 && a.broadcast() == address)
+    })
+}
+
+/// Check if a datagram received on a device is addressed to this host
+///
+/// Accepts assigned addresses, broadcasts and multicast groups. The
+/// loopback device only carries traffic this host sent to itself, and a
+/// device without an address accepts everything so that DHCP can
+/// configure it.
+pub fn accepts_destination(device: &str, dst: Ipv4Addr) -> bool {
+    if device == LOOPBACK_NAME || dst.is_broadcast() || dst.is_multicast() {
+        return true;
+    }
+
+    let addresses = INTERFACE_ADDRESSES.lock();
+    if addresses.get(device).is_none_or(|list| list.is_empty()) {
+        return true;
+    }
+    addresses.values().flatten().any(|a| a.address == dst)
+        || addresses.get(device).is_some_and(|list| {
+            list.iter()
+                .any(|a| a.prefix_len < 31 && a.broadcast() == dst)
+        })
+}
+
+/// Interface address errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddrError {
+    /// Prefix length above 32
+    InvalidPrefix,
+    /// Address cannot be assigned to an interface
+    InvalidAddress,
+    /// No such device
+    NoDevice,
+    /// Address already assigned to the device
+    AddressExists,
+    /// Address not assigned to the device
+    NotFound,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subnet_arithmetic() {
+        let addr = InterfaceAddress::new(Ipv4Addr::new(192, 168, 10, 42), 24);
+        assert_eq!(addr.netmask(), Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(addr.network(), Ipv4Addr::new(192, 168, 10, 0));
+        assert_eq!(addr.broadcast(), Ipv4Addr::new(192, 168, 10, 255));
+        assert!(addr.contains(Ipv4Addr::new(192, 168, 10, 1)));
+        assert!(!addr.contains(Ipv4Addr::new(192, 168, 11, 1)));
+
+        assert_eq!(prefix_mask(0), 0);
+        assert_eq!(prefix_mask(32), u32::MAX);
+        assert_eq!(
+            InterfaceAddress::new(Ipv4Addr::new(10, 1, 2, 3), 32).network(),
+            Ipv4Addr::new(10, 1, 2, 3)
+        );
+    }
+
+    #[test]
+    fn test_address_installs_connected_route() {
+        crate::net::loopback::init();
+        let address = Ipv4Addr::new(10, 210, 7, 1);
+        let network = Ipv4Addr::new(10, 210, 7, 0);
+
+        assert_eq!(
+            add_address("nonexistent0", address, 24),
+            Err(AddrError::NoDevice)
+        );
+        assert_eq!(
+            add_address("lo", address, 33),
+            Err(AddrError::InvalidPrefix)
+        );
+
+        add_address("lo", address, 24).unwrap();
+        assert_eq!(
+            add_address("lo", address, 24),
+            Err(AddrError::AddressExists)
+        );
+        assert!(is_local_address(address));
+        assert!(is_subnet_broadcast("lo", Ipv4Addr::new(10, 210, 7, 255)));
+
+        let route = route::lookup(Ipv4Addr::new(10, 210, 7, 20)).unwrap();
+        assert_eq!(route.destination, network);
+        assert_eq!(route.source, Some(address));
+        assert_eq!(route.protocol, RouteProtocol::Kernel);
+
+        remove_address("lo", address).unwrap();
+        assert!(!is_local_address(address));
+        assert!(
+            route::lookup(Ipv4Addr::new(10, 210, 7, 20)).is_none_or(|r| r.destination != network)
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! IPv4 Forwarding
+//!
+//! Routes datagrams that are not addressed to this host out of the
+//! interface their destination is reached through. Each hop decrements the
+//! TTL; datagrams that run out of it or have no route are answered with
+//! ICMP errors from the address of the interface they arrived on.
+//!
+//! Forwarded datagrams pass the forward and postrouting firewall hooks,
+//! then have their source rewritten if a NAT rule covers the outgoing
+//! interface (see [`nat`]).
+//!
+//! Forwarding is off until enabled with the `ip_forward` boot parameter or
+//! [`set_enabled`].
+
+use alloc::string::ToString;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::{addr, fill_header_checksum, fragment, route, Ipv4Addr, Ipv4Error, Ipv4Header};
+use crate::net::icmp::{self, IcmpType, TimeExceededCode, UnreachableCode};
+use crate::net::netfilter::{self, nat, Hook, Verdict};
+
+/// Forwarding enabled flag
+static FORWARDING: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable forwarding between interfaces
+pub fn set_enabled(enabled: bool) {
+    FORWARDING.store(enabled, Ordering::Release);
+}
+
+/// Check if forwarding is enabled
+pub fn is_enabled() -> bool {
+    FORWARDING.load(Ordering::Acquire)
+}
+
+/// Check if an address may appear on a routed datagram
+///
+/// Broadcast, multicast, loopback and link-local traffic never leaves its
+/// link.
+fn is_routable(address: Ipv4Addr) -> bool {
+    !(address.is_unspecified()
+        || address.is_broadcast()
+        || address.is_multicast()
+        || address.is_loopback()
+        || address.is_link_local())
+}
+
+/// Report a problem with a datagram in transit to its sender
+fn send_error(in_device: &str, icmp_type: IcmpType, code: u8, rest: u32, datagram: &[u8]) {
+    let Ok(header) = Ipv4Header::parse(datagram) else {
+        return;
+    };
+    let source =
+        addr::primary_address(in_device).unwrap_or_else(|| super::select_source(header.src_addr()));
+    if !source.is_unspecified() {
+        let _ = icmp::send_error_from(source, icmp_type, code, rest, datagram);
+    }
+}
+
+/// Forward a complete datagram received on `in_device`
+///
+/// Fragments are reassembled before they get here, so the datagram is
+/// refragmented to the MTU of the outgoing device.
+pub fn forward(in_device: &str, datagram: &[u8]) -> Result<(), Ipv4Error> {
+    let header = Ipv4Header::parse(datagram)?;
+    if !is_routable(header.src_addr()) || !is_routable(header.dst_addr()) {
+        return Err(Ipv4Error::InvalidAddress);
+    }
+
+    if header.ttl <= 1 {
+        send_error(
+            in_device,
+            IcmpType::TimeExceeded,
+            TimeExceededCode::TtlExceeded as u8,
+            0,
+            datagram,
+        );
+        return Err(Ipv4Error::TtlExceeded);
+    }
+
+    let Some(hop) = route::resolve(header.dst_addr()) else {
+        send_error(
+            in_device,
+            IcmpType::DestUnreachable,
+            UnreachableCode::Net as u8,
+            0,
+            datagram,
+        );
+        return Err(Ipv4Error::NoRoute);
+    };
+
+    let (out_device, mtu) = {
+        let device = hop.device.lock();
+        (device.name().to_string(), device.mtu())
+    };
+    for hook in [Hook::Forward, Hook::Postrouting] {
+        if netfilter::filter(hook, datagram, Some(in_device), Some(&out_device)) == Verdict::Drop {
+            return Err(Ipv4Error::Filtered);
+        }
+    }
+
+    let mut packet = datagram.to_vec();
+    packet[8] -= 1;
+    fill_header_checksum(&mut packet[..header.header_length()]);
+
+    nat::translate_outbound(&mut packet, &out_device).map_err(|_| Ipv4Error::NoTranslation)?;
+
+    let fragments = match fragment::fragment(&packet, mtu) {
+        Ok(fragments) => fragments,
+        Err(Ipv4Error::FragmentationNeeded) => {
+            // The next-hop MTU lets the sender lower its path MTU (RFC 1191)
+            send_error(
+                in_device,
+                IcmpType::DestUnreachable,
+                UnreachableCode::FragmentationNeeded as u8,
+                mtu as u32,
+                datagram,
+            );
+            return Err(Ipv4Error::FragmentationNeeded);
+        }
+        Err(err) => return Err(err),
+    };
+
+    for fragment in fragments {
+        super::transmit(&hop.device, hop.source, hop.address, &fragment)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ethernet::{
+        EtherType, EthernetFrame, EthernetFrameBuilder, EthernetHeader, MacAddress,
+    };
+    use crate::net::ipv4::{build_packet, IpProtocol};
+    use crate::net::netdev::{
+        self, DeviceCapabilities, DeviceStats, LinkState, NetDevError, NetDevice,
+    };
+    use crate::net::netfilter::nat::NatRule;
+    use crate::net::rx;
+    use crate::net::udp::{build_udp_packet, UdpHeader};
+    use alloc::collections::VecDeque;
+    use alloc::string::String;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use spin::Mutex;
+
+    /// Point-to-point virtual device that records transmitted frames
+    struct VirtualDevice {
+        name: String,
+        sent: VecDeque<Vec<u8>>,
+        stats: DeviceStats,
+    }
+
+    impl NetDevice for VirtualDevice {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn mac_address(&self) -> MacAddress {
+            MacAddress::ZERO
+        }
+
+        fn set_mac_address(&mut self, _mac: MacAddress) -> Result<(), NetDevError> {
+            Err(NetDevError::NotSupported)
+        }
+
+        fn link_state(&self) -> LinkState {
+            LinkState::Up
+        }
+
+        fn mtu(&self) -> usize {
+            1500
+        }
+
+        fn set_mtu(&mut self, _mtu: usize) -> Result<(), NetDevError> {
+            Err(NetDevError::NotSupported)
+        }
+
+        fn capabilities(&self) -> DeviceCapabilities {
+            DeviceCapabilities::default()
+        }
+
+        fn up(&mut self) -> Result<(), NetDevError> {
+            Ok(())
+        }
+
+        fn down(&mut self) -> Result<(), NetDevError> {
+            Ok(())
+        }
+
+        fn send(&mut self, packet: &[u8]) -> Result<(), NetDevError> {
+            self.sent.push_back(packet.to_vec());
+            Ok(())
+        }
+
+        fn recv(&mut self, _buffer: &mut [u8]) -> Result<usize, NetDevError> {
+            Err(NetDevError::WouldBlock)
+        }
+
+        fn stats(&self) -> &DeviceStats {
+            &self.stats
+        }
+
+        // Frames need no address resolution, as on loopback
+        fn is_loopback(&self) -> bool {
+            true
+        }
+    }
+
+    fn add_device(name: &str, address: Ipv4Addr) -> Arc<Mutex<VirtualDevice>> {
+        let device = Arc::new(Mutex::new(VirtualDevice {
+            name: name.to_string(),
+            sent: VecDeque::new(),
+            stats: DeviceStats::new(),
+        }));
+        netdev::register_device(device.clone()).unwrap();
+        addr::add_address(name, address, 24).unwrap();
+        device
+    }
+
+    fn frame(packet: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; EthernetHeader::SIZE + packet.len()];
+        EthernetFrameBuilder::new()
+            .dst(MacAddress::ZERO)
+            .src(MacAddress::ZERO)
+            .ethertype(EtherType::Ipv4)
+            .build(&mut frame, packet)
+            .unwrap();
+        frame
+    }
+
+    fn sent_packet(device: &Arc<Mutex<VirtualDevice>>) -> Vec<u8> {
+        let frame = device.lock().sent.pop_front().unwrap();
+        EthernetFrame::new(&frame).unwrap().payload().to_vec()
+    }
+
+    fn udp(src: Ipv4Addr, sport: u16, dst: Ipv4Addr, dport: u16, ttl: u8) -> Vec<u8> {
+        let mut datagram = [0u8; 16];
+        let len = build_udp_packet(&mut datagram, src, sport, dst, dport, b"data").unwrap();
+        let mut packet = build_packet(src, dst, IpProtocol::Udp, &datagram[..len]).unwrap();
+        packet[8] = ttl;
+        fill_header_checksum(&mut packet[..Ipv4Header::MIN_SIZE]);
+        packet
+    }
+
+    fn udp_checksum_ok(packet: &[u8]) -> bool {
+        let header = Ipv4Header::parse(packet).unwrap();
+        let payload = &packet[Ipv4Header::MIN_SIZE..];
+        UdpHeader::parse(payload).unwrap().verify_checksum(
+            header.src_addr(),
+            header.dst_addr(),
+            &payload[UdpHeader::SIZE..],
+        )
+    }
+
+    #[test]
+    fn test_forward_with_masquerade() {
+        crate::net::loopback::init();
+        let lan = add_device("fwd0", Ipv4Addr::new(10, 230, 1, 1));
+        let wan = add_device("fwd1", Ipv4Addr::new(10, 230, 2, 1));
+        let lan_dev: Arc<Mutex<dyn NetDevice>> = lan.clone();
+        let wan_dev: Arc<Mutex<dyn NetDevice>> = wan.clone();
+        set_enabled(true);
+
+        let host = Ipv4Addr::new(10, 230, 1, 2);
+        let server = Ipv4Addr::new(10, 230, 2, 2);
+
+        // Routed with the TTL decremented
+        rx::process_frame(&lan_dev, &frame(&udp(host, 7000, server, 53, 64))).unwrap();
+        let packet = sent_packet(&wan);
+        let header = Ipv4Header::parse(&packet).unwrap();
+        assert_eq!(header.ttl, 63);
+        assert!(header.verify_checksum());
+        assert_eq!(header.src_addr(), host);
+
+        // Out of TTL: the sender hears back from the router
+        assert_eq!(
+            rx::process_frame(&lan_dev, &frame(&udp(host, 7000, server, 53, 1))),
+            Err(rx::RxError::Ipv4(Ipv4Error::TtlExceeded))
+        );
+        assert!(wan.lock().sent.is_empty());
+        let error = sent_packet(&lan);
+        let header = Ipv4Header::parse(&error).unwrap();
+        assert_eq!(header.src_addr(), Ipv4Addr::new(10, 230, 1, 1));
+        assert_eq!(header.dst_addr(), host);
+        assert_eq!(error[Ipv4Header::MIN_SIZE], IcmpType::TimeExceeded.as_u8());
+
+        // Masqueraded behind the address of the outgoing device
+        nat::append_rule(NatRule::masquerade("fwd1").with_source(Ipv4Addr::new(10, 230, 1, 0), 24));
+        rx::process_frame(&lan_dev, &frame(&udp(host, 7001, server, 53, 64))).unwrap();
+        let packet = sent_packet(&wan);
+        let header = Ipv4Header::parse(&packet).unwrap();
+        assert_eq!(header.src_addr(), Ipv4Addr::new(10, 230, 2, 1));
+        assert!(header.verify_checksum());
+        assert!(udp_checksum_ok(&packet));
+        let port = u16::from_be_bytes([packet[20], packet[21]]);
+        assert!((nat::NAT_PORT_MIN..=nat::NAT_PORT_MAX).contains(&port));
+
+        // The reply is translated back and routed to the host
+        let reply = udp(server, 53, Ipv4Addr::new(10, 230, 2, 1), port, 64);
+        rx::process_frame(&wan_dev, &frame(&reply)).unwrap();
+        let packet = sent_packet(&lan);
+        let header = Ipv4Header::parse(&packet).unwrap();
+        assert_eq!(header.dst_addr(), host);
+        assert_eq!(u16::from_be_bytes([packet[22], packet[23]]), 7001);
+        assert!(header.verify_checksum());
+        assert!(udp_checksum_ok(&packet));
+    }
+}
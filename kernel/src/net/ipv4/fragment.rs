@@ -0,0 +1,541 @@
+//! IPv4 Fragmentation and Reassembly
+//!
+//! Splits outgoing datagrams that exceed the device MTU (RFC 791) and
+//! rebuilds fragmented datagrams on receive. Partially reassembled
+//! datagrams are dropped after `REASSEMBLY_TIMEOUT_MS` or when the cache
+//! grows past `REASSEMBLY_HIGH_THRESH` bytes. Each datagram and fragment
+//! is charged a fixed overhead on top of the bytes it stores, so floods of
+//! tiny fragments are bounded too.
+
+use alloc::collections::btree_map::Entry;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::{fill_header_checksum, Ipv4Addr, Ipv4Error, Ipv4Header};
+use crate::net::icmp::{self, TimeExceededCode};
+
+/// Time allowed to collect all fragments of a datagram (milliseconds)
+pub const REASSEMBLY_TIMEOUT_MS: u64 = 30_000;
+
+/// Interval between reassembly timeout checks (milliseconds)
+pub const REASSEMBLY_TIMER_INTERVAL_MS: u64 = 1000;
+
+/// Cache size above which the oldest datagrams are evicted (bytes)
+pub const REASSEMBLY_HIGH_THRESH: usize = 256 * 1024;
+
+/// Cache size eviction brings the cache back down to (bytes)
+pub const REASSEMBLY_LOW_THRESH: usize = 192 * 1024;
+
+/// Maximum fragments kept for a single datagram
+pub const MAX_FRAGMENTS: usize = 64;
+
+/// Bookkeeping charged for each datagram being reassembled (bytes)
+const ENTRY_OVERHEAD: usize = 256;
+
+/// Bookkeeping charged for each stored fragment (bytes)
+const FRAGMENT_OVERHEAD: usize = 64;
+
+/// Largest IPv4 datagram (header and payload)
+const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+
+/// Datagram identity (RFC 791: source, destination, protocol and ID)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct ReassemblyKey {
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    protocol: u8,
+    identification: u16,
+}
+
+/// Fragments collected for one datagram
+struct Reassembly {
+    /// Header of the first fragment, including options
+    header: Vec<u8>,
+    /// Leading bytes of the first fragment, quoted if reassembly times out
+    first_fragment: Option<Vec<u8>>,
+    /// Non-overlapping payload pieces keyed by offset
+    fragments: BTreeMap<usize, Vec<u8>>,
+    /// Payload length, known once the last fragment arrives
+    total_len: Option<usize>,
+    /// Payload bytes received
+    received: usize,
+    /// Expiry time (uptime in milliseconds)
+    deadline: u64,
+}
+
+impl Reassembly {
+    fn new(deadline: u64) -> Self {
+        Self {
+            header: Vec::new(),
+            first_fragment: None,
+            fragments: BTreeMap::new(),
+            total_len: None,
+            received: 0,
+            deadline,
+        }
+    }
+
+    /// Store the parts of `data` at `offset` not already received
+    ///
+    /// Overlapping bytes keep the value that arrived first.
+    fn insert(&mut self, offset: usize, data: &[u8]) {
+        let end = offset + data.len();
+        let mut gaps = Vec::new();
+        let mut pos = offset;
+
+        for (&start, piece) in self.fragments.range(..end) {
+            let piece_end = start + piece.len();
+            if piece_end <= pos {
+                continue;
+            }
+            if start > pos {
+                gaps.push((pos, start));
+            }
+            pos = piece_end;
+        }
+        if pos < end {
+            gaps.push((pos, end));
+        }
+
+        for (start, stop) in gaps {
+            self.fragments
+                .insert(start, data[start - offset..stop - offset].to_vec());
+            self.received += stop - start;
+        }
+    }
+
+    /// Bytes charged to the cache for this datagram
+    fn truesize(&self) -> usize {
+        ENTRY_OVERHEAD
+            + self.header.len()
+            + self.first_fragment.as_ref().map_or(0, Vec::len)
+            + self.fragments.len() * FRAGMENT_OVERHEAD
+            + self.received
+    }
+
+    /// Check if every payload byte has arrived
+    fn is_complete(&self) -> bool {
+        !self.header.is_empty() && self.total_len == Some(self.received)
+    }
+
+    /// Build the reassembled datagram
+    fn assemble(self) -> Vec<u8> {
+        let header_len = self.header.len();
+        let total_len = header_len + self.received;
+
+        let mut datagram = Vec::with_capacity(total_len);
+        datagram.extend_from_slice(&self.header);
+        for piece in self.fragments.into_values() {
+            datagram.extend_from_slice(&piece);
+        }
+
+        // The result is a whole datagram: clear MF and the offset
+        datagram[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+        datagram[6] &= 0x40;
+        datagram[7] = 0;
+        fill_header_checksum(&mut datagram[..header_len]);
+
+        datagram
+    }
+}
+
+/// Datagrams being reassembled
+struct ReassemblyCache {
+    entries: BTreeMap<ReassemblyKey, Reassembly>,
+    /// Bytes charged across all entries
+    memory: usize,
+}
+
+impl ReassemblyCache {
+    const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            memory: 0,
+        }
+    }
+
+    /// Remove an entry, returning it
+    fn remove(&mut self, key: &ReassemblyKey) -> Option<Reassembly> {
+        let entry = self.entries.remove(key)?;
+        self.memory -= entry.truesize();
+        Some(entry)
+    }
+
+    /// Evict the datagrams closest to expiry until under the low threshold
+    fn evict(&mut self) {
+        while self.memory > REASSEMBLY_LOW_THRESH {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.deadline)
+                .map(|(key, _)| *key);
+            match oldest {
+                Some(key) => {
+                    self.remove(&key);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Add a fragment, returning the whole datagram once it is complete
+    fn add(&mut self, packet: &[u8], now: u64) -> Result<Option<Vec<u8>>, Ipv4Error> {
+        let header = Ipv4Header::parse(packet)?;
+        let header_len = header.header_length();
+        let total_len = header.total_length() as usize;
+        if total_len < header_len || total_len > packet.len() {
+            return Err(Ipv4Error::TooShort);
+        }
+
+        let data = &packet[header_len..total_len];
+        let offset = header.fragment_offset();
+        let end = offset + data.len();
+        let last = !header.more_fragments();
+
+        // Every fragment but the last carries a non-empty multiple of 8 bytes
+        if (!last && (data.is_empty() || !data.len().is_multiple_of(8)))
+            || header_len + end > MAX_DATAGRAM_SIZE
+        {
+            return Err(Ipv4Error::InvalidFragment);
+        }
+
+        let key = ReassemblyKey {
+            src: header.src_addr(),
+            dst: header.dst_addr(),
+            protocol: header.protocol,
+            identification: header.identification(),
+        };
+        let entry = match self.entries.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let entry = entry.insert(Reassembly::new(now + REASSEMBLY_TIMEOUT_MS));
+                self.memory += entry.truesize();
+                entry
+            }
+        };
+
+        // Nothing may lie beyond the last fragment, whose end is fixed
+        let received_end = entry
+            .fragments
+            .iter()
+            .next_back()
+            .map_or(0, |(&start, piece)| start + piece.len());
+        let conflict = match entry.total_len {
+            Some(len) => end > len || (last && end != len),
+            None => last && received_end > end,
+        };
+        if conflict || entry.fragments.len() >= MAX_FRAGMENTS {
+            self.remove(&key);
+            return Err(Ipv4Error::InvalidFragment);
+        }
+
+        let before = entry.truesize();
+        if last {
+            entry.total_len = Some(end);
+        }
+        if offset == 0 {
+            entry.header = packet[..header_len].to_vec();
+            let quoted = (header_len + 8).min(total_len);
+            entry.first_fragment = Some(packet[..quoted].to_vec());
+        }
+
+        entry.insert(offset, data);
+        let complete = entry.is_complete();
+        self.memory = self.memory + entry.truesize() - before;
+
+        if complete {
+            return Ok(self.remove(&key).map(Reassembly::assemble));
+        }
+
+        if self.memory > REASSEMBLY_HIGH_THRESH {
+            self.evict();
+        }
+
+        Ok(None)
+    }
+
+    /// Remove and return the datagrams whose timer has expired
+    fn expire(&mut self, now: u64) -> Vec<Reassembly> {
+        let keys: Vec<ReassemblyKey> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.deadline <= now)
+            .map(|(key, _)| *key)
+            .collect();
+        keys.iter().filter_map(|key| self.remove(key)).collect()
+    }
+}
+
+/// Global reassembly cache
+static REASSEMBLY_CACHE: Mutex<ReassemblyCache> = Mutex::new(ReassemblyCache::new());
+
+/// Add a received fragment to the reassembly cache
+///
+/// `packet` is the fragment without link-layer padding. Returns the whole
+/// datagram once its last missing fragment arrives.
+pub fn reassemble(packet: &[u8]) -> Result<Option<Vec<u8>>, Ipv4Error> {
+    REASSEMBLY_CACHE
+        .lock()
+        .add(packet, crate::time::uptime_ms())
+}
+
+/// Drop datagrams whose reassembly timer has expired
+///
+/// The sender is told with a Time Exceeded message if the first fragment
+/// was received (RFC 792).
+pub fn expire(now: u64) {
+    let expired = REASSEMBLY_CACHE.lock().expire(now);
+
+    for entry in expired {
+        if let Some(first) = entry.first_fragment {
+            let _ = icmp::send_time_exceeded(TimeExceededCode::FragmentReassembly, &first);
+        }
+    }
+}
+
+/// Periodic reassembly timer callback
+pub fn timer_tick() {
+    expire(crate::time::uptime_ms());
+}
+
+/// Number of datagrams being reassembled
+pub fn pending_count() -> usize {
+    REASSEMBLY_CACHE.lock().entries.len()
+}
+
+/// Bytes charged to the reassembly cache
+pub fn memory_usage() -> usize {
+    REASSEMBLY_CACHE.lock().memory
+}
+
+/// Split a datagram into fragments that fit in `mtu` bytes
+///
+/// Datagrams that already fit are returned unchanged. Fails with
+/// `FragmentationNeeded` if the datagram is too large and has DF set.
+pub fn fragment(packet: &[u8], mtu: usize) -> Result<Vec<Vec<u8>>, Ipv4Error> {
+    let header = Ipv4Header::parse(packet)?;
+    let header_len = header.header_length();
+    let total_len = header.total_length() as usize;
+    if total_len < header_len || total_len > packet.len() {
+        return Err(Ipv4Error::TooShort);
+    }
+
+    if total_len <= mtu {
+        return Ok(vec![packet[..total_len].to_vec()]);
+    }
+    if header.dont_fragment() {
+        return Err(Ipv4Error::FragmentationNeeded);
+    }
+
+    // Later fragments only repeat the options marked for copying
+    let first_header = &packet[..header_len];
+    let mut later_header = packet[..Ipv4Header::MIN_SIZE].to_vec();
+    later_header.extend(copied_options(&packet[Ipv4Header::MIN_SIZE..header_len]));
+    later_header[0] = 0x40 | (later_header.len() / 4) as u8;
+
+    let payload = &packet[header_len..total_len];
+    let base_offset = header.fragment_offset();
+    let last_mf = header.more_fragments();
+
+    let mut fragments = Vec::new();
+    let mut offset = 0;
+    while offset < payload.len() {
+        let prefix = if offset == 0 {
+            first_header
+        } else {
+            &later_header[..]
+        };
+
+        // Fragment data must be a multiple of 8 bytes except at the end
+        let room = mtu.saturating_sub(prefix.len()) & !7;
+        if room == 0 {
+            return Err(Ipv4Error::FragmentationNeeded);
+        }
+
+        let len = room.min(payload.len() - offset);
+        let more = offset + len < payload.len() || last_mf;
+
+        let mut piece = Vec::with_capacity(prefix.len() + len);
+        piece.extend_from_slice(prefix);
+        piece.extend_from_slice(&payload[offset..offset + len]);
+
+        let flags_fragment = ((more as u16) << 13) | (((base_offset + offset) / 8) as u16 & 0x1FFF);
+        let size = piece.len() as u16;
+        piece[2..4].copy_from_slice(&size.to_be_bytes());
+        piece[6..8].copy_from_slice(&flags_fragment.to_be_bytes());
+        fill_header_checksum(&mut piece[..prefix.len()]);
+
+        fragments.push(piece);
+        offset += len;
+    }
+
+    Ok(fragments)
+}
+
+/// Options with the copied flag set, padded to a 32-bit boundary
+fn copied_options(options: &[u8]) -> Vec<u8> {
+    let mut copied = Vec::new();
+    let mut i = 0;
+
+    while i < options.len() {
+        let kind = options[i];
+        match kind {
+            // End of option list
+            0 => break,
+            // No operation
+            1 => {
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let len = match options.get(i + 1) {
+            Some(&len) if len >= 2 && i + len as usize <= options.len() => len as usize,
+            _ => break,
+        };
+        if kind & 0x80 != 0 {
+            copied.extend_from_slice(&options[i..i + len]);
+        }
+        i += len;
+    }
+
+    while copied.len() % 4 != 0 {
+        copied.push(0);
+    }
+    copied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipv4::{build_packet, IpProtocol};
+
+    fn datagram(len: usize) -> Vec<u8> {
+        let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        build_packet(
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(10, 0, 0, 2),
+            IpProtocol::Udp,
+            &payload,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_fragment_and_reassemble() {
+        let packet = datagram(3000);
+        let fragments = fragment(&packet, 1500).unwrap();
+        assert_eq!(fragments.len(), 3);
+
+        let offsets: Vec<usize> = fragments
+            .iter()
+            .map(|f| Ipv4Header::parse(f).unwrap().fragment_offset())
+            .collect();
+        assert_eq!(offsets, vec![0, 1480, 2960]);
+        assert!(fragments.iter().all(|f| f.len() <= 1500));
+        assert!(Ipv4Header::parse(&fragments[0]).unwrap().verify_checksum());
+        assert!(!Ipv4Header::parse(&fragments[2]).unwrap().more_fragments());
+
+        // Arrival order does not matter
+        let mut cache = ReassemblyCache::new();
+        assert_eq!(cache.add(&fragments[2], 0), Ok(None));
+        assert_eq!(cache.add(&fragments[0], 0), Ok(None));
+        assert_eq!(cache.add(&fragments[1], 0), Ok(Some(packet)));
+        assert_eq!(cache.memory, 0);
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn test_dont_fragment() {
+        let mut packet = datagram(2000);
+        let mut header = Ipv4Header::parse(&packet).unwrap();
+        header.set_dont_fragment(true);
+        header.calculate_checksum();
+        header.write_to(&mut packet).unwrap();
+
+        assert_eq!(fragment(&packet, 1500), Err(Ipv4Error::FragmentationNeeded));
+        assert_eq!(fragment(&packet, 2100).unwrap(), vec![packet]);
+    }
+
+    #[test]
+    fn test_overlapping_fragments() {
+        let packet = datagram(64);
+        let small = fragment(&packet, 20 + 16).unwrap();
+        let large = fragment(&packet, 20 + 32).unwrap();
+
+        // Overlaps and duplicates fill only the missing bytes
+        let mut cache = ReassemblyCache::new();
+        assert_eq!(cache.add(&small[1], 0), Ok(None));
+        assert_eq!(cache.add(&small[1], 0), Ok(None));
+        assert_eq!(cache.add(&large[0], 0), Ok(None));
+        // Header plus the quoted start of the first fragment
+        let header_bytes = Ipv4Header::MIN_SIZE + Ipv4Header::MIN_SIZE + 8;
+        assert_eq!(
+            cache.memory,
+            ENTRY_OVERHEAD + header_bytes + 2 * FRAGMENT_OVERHEAD + 32
+        );
+        assert_eq!(cache.add(&large[1], 0), Ok(Some(packet)));
+
+        // A last fragment ending before received data is rejected
+        let packet = datagram(64);
+        let pieces = fragment(&packet, 20 + 16).unwrap();
+        let mut truncated = pieces[1].clone();
+        truncated[6] &= !0x20;
+        assert_eq!(cache.add(&pieces[3], 0), Ok(None));
+        assert_eq!(cache.add(&truncated, 0), Err(Ipv4Error::InvalidFragment));
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn test_reassembly_limits() {
+        let mut cache = ReassemblyCache::new();
+
+        // Incomplete datagrams expire with their first fragment kept
+        let packet = datagram(3000);
+        let fragments = fragment(&packet, 1500).unwrap();
+        cache.add(&fragments[0], 100).unwrap();
+        assert!(cache.expire(100 + REASSEMBLY_TIMEOUT_MS - 1).is_empty());
+
+        let expired = cache.expire(100 + REASSEMBLY_TIMEOUT_MS);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(
+            expired[0].first_fragment.as_deref(),
+            Some(&fragments[0][..28])
+        );
+        assert_eq!(cache.memory, 0);
+
+        // Memory pressure evicts the oldest datagrams
+        for now in 0..400 {
+            let fragments = fragment(&datagram(3000), 1500).unwrap();
+            cache.add(&fragments[0], now).unwrap();
+            assert!(cache.memory <= REASSEMBLY_HIGH_THRESH);
+        }
+        assert!(cache.entries.len() < 400);
+        assert!(cache.entries.values().all(|entry| entry.deadline > 100));
+    }
+
+    #[test]
+    fn test_empty_fragment_flood() {
+        let packet = datagram(64);
+        let mut empty = fragment(&packet, 20 + 16).unwrap()[1].clone();
+        empty.truncate(Ipv4Header::MIN_SIZE);
+        empty[2..4].copy_from_slice(&(Ipv4Header::MIN_SIZE as u16).to_be_bytes());
+
+        // Empty fragments that are not last are dropped outright
+        let mut cache = ReassemblyCache::new();
+        assert_eq!(cache.add(&empty, 0), Err(Ipv4Error::InvalidFragment));
+        assert!(cache.entries.is_empty());
+
+        // Tiny fragments for distinct datagrams are bounded by their overhead
+        let mut piece = fragment(&packet, 20 + 8).unwrap()[1].clone();
+        for id in 0..4096u16 {
+            piece[4..6].copy_from_slice(&id.to_be_bytes());
+            cache.add(&piece, 0).unwrap();
+            assert!(cache.memory <= REASSEMBLY_HIGH_THRESH);
+        }
+        assert!(cache.entries.len() < REASSEMBLY_HIGH_THRESH / ENTRY_OVERHEAD);
+    }
+}
@@ -0,0 +1,437 @@
+//! IPv4 Routing Table
+//!
+//! Selects the device, next hop and source address for outgoing
+//! datagrams by longest-prefix match, preferring the lowest metric among
+//! routes of equal length.
+
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+
+use super::addr::{self, prefix_mask};
+use super::Ipv4Addr;
+use crate::net::netdev::{self, NetDevice};
+
+/// Where a route came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteProtocol {
+    /// Installed for a subnet when an address was assigned
+    Kernel,
+    /// Added by an administrator
+    Static,
+}
+
+/// Routing table entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    /// Destination network
+    pub destination: Ipv4Addr,
+    /// Destination prefix length
+    pub prefix_len: u8,
+    /// Gateway, or `None` if the destination is on-link
+    pub gateway: Option<Ipv4Addr>,
+    /// Outgoing device name
+    pub device: String,
+    /// Route preference; lower wins among equal prefixes
+    pub metric: u32,
+    /// Preferred source address
+    pub source: Option<Ipv4Addr>,
+    /// Origin of the route
+    pub protocol: RouteProtocol,
+}
+
+impl Route {
+    /// Create an on-link route through a device
+    pub fn new(destination: Ipv4Addr, prefix_len: u8, device: &str) -> Self {
+        Self {
+            destination,
+            prefix_len,
+            gateway: None,
+            device: device.to_string(),
+            metric: 0,
+            source: None,
+            protocol: RouteProtocol::Static,
+        }
+    }
+
+    /// Create a default route via a gateway
+    pub fn default_via(gateway: Ipv4Addr, device: &str) -> Self {
+        Self::new(Ipv4Addr::UNSPECIFIED, 0, device).with_gateway(gateway)
+    }
+
+    /// Send traffic through a gateway
+    pub fn with_gateway(mut self, gateway: Ipv4Addr) -> Self {
+        self.gateway = Some(gateway);
+        self
+    }
+
+    /// Set the metric
+    pub fn with_metric(mut self, metric: u32) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Set the preferred source address
+    pub fn with_source(mut self, source: Ipv4Addr) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Set the origin of the route
+    pub fn with_protocol(mut self, protocol: RouteProtocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Check if this is a default route
+    pub fn is_default(&self) -> bool {
+        self.prefix_len == 0
+    }
+
+    /// Check if a destination matches the route
+    pub fn matches(&self, dst: Ipv4Addr) -> bool {
+        let mask = prefix_mask(self.prefix_len);
+        dst.as_u32() & mask == self.destination.as_u32()
+    }
+}
+
+impl fmt::Display for Route {
+    /// Format like `ip route` output
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_default() {
+            f.write_str("default")?;
+        } else {
+            write!(f, "{}/{}", self.destination, self.prefix_len)?;
+        }
+        if let Some(gateway) = self.gateway {
+            write!(f, " via {}", gateway)?;
+        }
+        write!(f, " dev {}", self.device)?;
+        if self.protocol == RouteProtocol::Kernel {
+            f.write_str(" proto kernel scope link")?;
+        }
+        if let Some(source) = self.source {
+            write!(f, " src {}", source)?;
+        }
+        if self.metric != 0 {
+            write!(f, " metric {}", self.metric)?;
+        }
+        Ok(())
+    }
+}
+
+/// Routing table
+pub struct RoutingTable {
+    routes: Vec<Route>,
+}
+
+impl RoutingTable {
+    /// Create empty routing table
+    pub const fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    /// Add a route
+    ///
+    /// Gateways must lie in a subnet that is on-link through the same
+    /// device.
+    pub fn add(&mut self, route: Route) -> Result<(), RouteError> {
+        if route.prefix_len > 32 {
+            return Err(RouteError::InvalidPrefix);
+        }
+        if route.destination.as_u32() & !prefix_mask(route.prefix_len) != 0 {
+            return Err(RouteError::InvalidPrefix);
+        }
+
+        if let Some(gateway) = route.gateway {
+            let reachable = self
+                .routes
+                .iter()
+                .any(|r| r.gateway.is_none() && r.device == route.device && r.matches(gateway));
+            if !reachable {
+                return Err(RouteError::GatewayUnreachable);
+            }
+        }
+
+        let exists = self.routes.iter().any(|r| {
+            r.destination == route.destination
+                && r.prefix_len == route.prefix_len
+                && r.metric == route.metric
+        });
+        if exists {
+            return Err(RouteError::RouteExists);
+        }
+
+        self.routes.push(route);
+        Ok(())
+    }
+
+    /// Remove the first route to a destination, optionally only through
+    /// a given device
+    pub fn remove(
+        &mut self,
+        destination: Ipv4Addr,
+        prefix_len: u8,
+        device: Option<&str>,
+    ) -> Result<Route, RouteError> {
+        let pos = self
+            .routes
+            .iter()
+            .position(|r| {
+                r.destination == destination
+                    && r.prefix_len == prefix_len
+                    && device.is_none_or(|name| r.device == name)
+            })
+            .ok_or(RouteError::NotFound)?;
+
+        Ok(self.routes.remove(pos))
+    }
+
+    /// Find the route for a destination
+    pub fn lookup(&self, dst: Ipv4Addr) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|r| r.matches(dst))
+            .min_by_key(|r| (u8::MAX - r.prefix_len, r.metric))
+    }
+
+    /// Remove all routes through a device
+    pub fn flush_device(&mut self, device: &str) {
+        self.routes.retain(|r| r.device != device);
+    }
+
+    /// Get all routes, most specific first
+    pub fn routes(&self) -> Vec<Route> {
+        let mut routes = self.routes.clone();
+        routes.sort_by_key(|r| (u8::MAX - r.prefix_len, r.metric));
+        routes
+    }
+
+    /// Get route count
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    /// Check if table is empty
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+impl Default for RoutingTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Global routing table
+static ROUTING_TABLE: Mutex<RoutingTable> = Mutex::new(RoutingTable::new());
+
+/// Add a route to the global table
+pub fn add_route(route: Route) -> Result<(), RouteError> {
+    if netdev::get_device(&route.device).is_none() {
+        return Err(RouteError::NoDevice);
+    }
+
+    ROUTING_TABLE.lock().add(route)
+}
+
+/// Remove a route from the global table
+pub fn remove_route(
+    destination: Ipv4Addr,
+    prefix_len: u8,
+    device: Option<&str>,
+) -> Result<Route, RouteError> {
+    ROUTING_TABLE.lock().remove(destination, prefix_len, device)
+}
+
+/// Replace the default route
+pub fn set_default_gateway(gateway: Ipv4Addr, device: &str) -> Result<(), RouteError> {
+    let _ = remove_default_gateway();
+    add_route(Route::default_via(gateway, device))
+}
+
+/// Remove the default route
+pub fn remove_default_gateway() -> Result<Route, RouteError> {
+    remove_route(Ipv4Addr::UNSPECIFIED, 0, None)
+}
+
+/// Get the default route
+pub fn default_route() -> Option<Route> {
+    ROUTING_TABLE
+        .lock()
+        .routes
+        .iter()
+        .filter(|r| r.is_default())
+        .min_by_key(|r| r.metric)
+        .cloned()
+}
+
+/// Find the route for a destination
+pub fn lookup(dst: Ipv4Addr) -> Option<Route> {
+    ROUTING_TABLE.lock().lookup(dst).cloned()
+}
+
+/// Remove all routes through a device
+pub fn flush_device(device: &str) {
+    ROUTING_TABLE.lock().flush_device(device);
+}
+
+/// Get all routes, most specific first
+pub fn routes() -> Vec<Route> {
+    ROUTING_TABLE.lock().routes()
+}
+
+/// Where to send a datagram
+pub struct NextHop {
+    /// Outgoing device
+    pub device: Arc<Mutex<dyn NetDevice>>,
+    /// Address to resolve on the link: the gateway or the destination
+    pub address: Ipv4Addr,
+    /// Source address for locally generated traffic
+    pub source: Ipv4Addr,
+}
+
+/// Resolve the device, next hop and source address for a destination
+pub fn resolve(dst: Ipv4Addr) -> Option<NextHop> {
+    let route = lookup(dst)?;
+    let device = netdev::get_device(&route.device)?;
+    let source = route
+        .source
+        .or_else(|| addr::primary_address(&route.device))
+        .unwrap_or(Ipv4Addr::UNSPECIFIED);
+
+    Some(NextHop {
+        device,
+        address: route.gateway.unwrap_or(dst),
+        source,
+    })
+}
+
+/// Routing errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteError {
+    /// Prefix length above 32 or host bits set in the destination
+    InvalidPrefix,
+    /// No such device
+    NoDevice,
+    /// Gateway is not on-link through the device
+    GatewayUnreachable,
+    /// A route with the same destination and metric exists
+    RouteExists,
+    /// No matching route
+    NotFound,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    fn table() -> RoutingTable {
+        let mut table = RoutingTable::new();
+        table
+            .add(
+                Route::new(Ipv4Addr::new(10, 0, 0, 0), 24, "eth0")
+                    .with_source(Ipv4Addr::new(10, 0, 0, 2))
+                    .with_protocol(RouteProtocol::Kernel),
+            )
+            .unwrap();
+        table
+            .add(Route::new(Ipv4Addr::new(192, 168, 1, 0), 24, "eth1"))
+            .unwrap();
+        table
+    }
+
+    #[test]
+    fn test_longest_prefix_match() {
+        let mut table = table();
+        table
+            .add(Route::default_via(Ipv4Addr::new(10, 0, 0, 1), "eth0"))
+            .unwrap();
+        table
+            .add(
+                Route::new(Ipv4Addr::new(172, 16, 0, 0), 12, "eth1")
+                    .with_gateway(Ipv4Addr::new(192, 168, 1, 1)),
+            )
+            .unwrap();
+
+        let route = table.lookup(Ipv4Addr::new(10, 0, 0, 7)).unwrap();
+        assert_eq!(route.device, "eth0");
+        assert_eq!(route.gateway, None);
+
+        let route = table.lookup(Ipv4Addr::new(172, 20, 1, 1)).unwrap();
+        assert_eq!(route.gateway, Some(Ipv4Addr::new(192, 168, 1, 1)));
+
+        let route = table.lookup(Ipv4Addr::new(8, 8, 8, 8)).unwrap();
+        assert!(route.is_default());
+        assert_eq!(route.gateway, Some(Ipv4Addr::new(10, 0, 0, 1)));
+    }
+
+    #[test]
+    fn test_metric_preference() {
+        let mut table = table();
+        table
+            .add(Route::default_via(Ipv4Addr::new(10, 0, 0, 1), "eth0").with_metric(100))
+            .unwrap();
+        table
+            .add(Route::default_via(Ipv4Addr::new(192, 168, 1, 1), "eth1").with_metric(50))
+            .unwrap();
+        assert_eq!(
+            table.lookup(Ipv4Addr::new(1, 1, 1, 1)).unwrap().device,
+            "eth1"
+        );
+
+        table
+            .remove(Ipv4Addr::UNSPECIFIED, 0, Some("eth1"))
+            .unwrap();
+        assert_eq!(
+            table.lookup(Ipv4Addr::new(1, 1, 1, 1)).unwrap().device,
+            "eth0"
+        );
+
+        table.flush_device("eth0");
+        assert!(table.lookup(Ipv4Addr::new(1, 1, 1, 1)).is_none());
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_routes() {
+        let mut table = table();
+        assert_eq!(
+            table.add(Route::new(Ipv4Addr::new(10, 0, 0, 1), 24, "eth0")),
+            Err(RouteError::InvalidPrefix)
+        );
+        assert_eq!(
+            table.add(Route::default_via(Ipv4Addr::new(172, 16, 0, 1), "eth0")),
+            Err(RouteError::GatewayUnreachable)
+        );
+        assert_eq!(
+            table.add(Route::new(Ipv4Addr::new(10, 0, 0, 0), 24, "eth1")),
+            Err(RouteError::RouteExists)
+        );
+        assert_eq!(
+            table.remove(Ipv4Addr::new(10, 1, 0, 0), 16, None),
+            Err(RouteError::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_route_display() {
+        let table = table();
+        let routes: Vec<String> = table.routes().iter().map(|r| format!("{}", r)).collect();
+        assert_eq!(
+            routes[0],
+            "10.0.0.0/24 dev eth0 proto kernel scope link src 10.0.0.2"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Route::default_via(Ipv4Addr::new(10, 0, 0, 1), "eth0").with_metric(100)
+            ),
+            "default via 10.0.0.1 dev eth0 metric 100"
+        );
+    }
+}
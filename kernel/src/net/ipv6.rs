@@ -0,0 +1,1014 @@
+//! Internet Protocol version 6 (IPv6)
+//!
+//! IPv6 packet handling, extension headers, interface addresses and
+//! stateless address autoconfiguration (RFC 8200, RFC 4862).
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use super::ethernet::MacAddress;
+use super::ipv4::{IpProtocol, Ipv4Addr};
+use super::netdev::NetDevice;
+use super::{loopback, ndisc, netdev};
+
+/// IPv6 address (16 bytes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct Ipv6Addr(pub [u8; 16]);
+
+impl Ipv6Addr {
+    /// Unspecified address (::)
+    pub const UNSPECIFIED: Self = Self([0; 16]);
+
+    /// Loopback address (::1)
+    pub const LOCALHOST: Self = Self::new(0, 0, 0, 0, 0, 0, 0, 1);
+
+    /// All-nodes link-local multicast address (ff02::1)
+    pub const ALL_NODES: Self = Self::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+    /// All-routers link-local multicast address (ff02::2)
+    pub const ALL_ROUTERS: Self = Self::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+    /// Create from eight 16-bit segments
+    #[allow(clippy::too_many_arguments)]
+    pub const fn new(a: u16, b: u16, c: u16, d: u16, e: u16, f: u16, g: u16, h: u16) -> Self {
+        let segments = [a, b, c, d, e, f, g, h];
+        let mut octets = [0u8; 16];
+        let mut i = 0;
+        while i < 8 {
+            octets[2 * i] = (segments[i] >> 8) as u8;
+            octets[2 * i + 1] = segments[i] as u8;
+            i += 1;
+        }
+        Self(octets)
+    }
+
+    /// Get the eight 16-bit segments
+    pub const fn segments(&self) -> [u16; 8] {
+        let mut segments = [0u16; 8];
+        let mut i = 0;
+        while i < 8 {
+            segments[i] = ((self.0[2 * i] as u16) << 8) | self.0[2 * i + 1] as u16;
+            i += 1;
+        }
+        segments
+    }
+
+    /// Get octets
+    pub const fn octets(&self) -> [u8; 16] {
+        self.0
+    }
+
+    /// Check if address is unspecified (::)
+    pub const fn is_unspecified(&self) -> bool {
+        u128::from_be_bytes(self.0) == 0
+    }
+
+    /// Check if address is the loopback address (::1)
+    pub const fn is_loopback(&self) -> bool {
+        u128::from_be_bytes(self.0) == 1
+    }
+
+    /// Check if address is multicast (ff00::/8)
+    pub const fn is_multicast(&self) -> bool {
+        self.0[0] == 0xff
+    }
+
+    /// Check if address is link-local unicast (fe80::/10)
+    pub const fn is_link_local(&self) -> bool {
+        self.0[0] == 0xfe && self.0[1] & 0xc0 == 0x80
+    }
+
+    /// Check if address is an IPv4-mapped address (::ffff:0:0/96)
+    pub const fn is_ipv4_mapped(&self) -> bool {
+        u128::from_be_bytes(self.0) >> 32 == 0xffff
+    }
+
+    /// Get the IPv4 address embedded in an IPv4-mapped address
+    pub const fn to_ipv4_mapped(&self) -> Option<Ipv4Addr> {
+        if self.is_ipv4_mapped() {
+            Some(Ipv4Addr([self.0[12], self.0[13], self.0[14], self.0[15]]))
+        } else {
+            None
+        }
+    }
+
+    /// Create the IPv4-mapped address of an IPv4 address
+    pub const fn from_ipv4_mapped(addr: Ipv4Addr) -> Self {
+        let mut octets = [0u8; 16];
+        octets[10] = 0xff;
+        octets[11] = 0xff;
+        octets[12] = addr.0[0];
+        octets[13] = addr.0[1];
+        octets[14] = addr.0[2];
+        octets[15] = addr.0[3];
+        Self(octets)
+    }
+
+    /// Solicited-node multicast address (ff02::1:ffXX:XXXX, RFC 4291)
+    pub const fn solicited_node(&self) -> Self {
+        let mut octets = Self::new(0xff02, 0, 0, 0, 0, 1, 0xff00, 0).0;
+        octets[13] = self.0[13];
+        octets[14] = self.0[14];
+        octets[15] = self.0[15];
+        Self(octets)
+    }
+
+    /// Ethernet address a multicast address maps to (33:33:xx:xx:xx:xx)
+    pub const fn multicast_mac(&self) -> MacAddress {
+        MacAddress([0x33, 0x33, self.0[12], self.0[13], self.0[14], self.0[15]])
+    }
+
+    /// Combine a /64 prefix with the modified EUI-64 interface identifier
+    /// of a MAC address (RFC 4291 appendix A)
+    pub const fn from_prefix_and_mac(prefix: &Ipv6Addr, mac: MacAddress) -> Self {
+        let mut octets = prefix.0;
+        octets[8] = mac.0[0] ^ 0x02;
+        octets[9] = mac.0[1];
+        octets[10] = mac.0[2];
+        octets[11] = 0xff;
+        octets[12] = 0xfe;
+        octets[13] = mac.0[3];
+        octets[14] = mac.0[4];
+        octets[15] = mac.0[5];
+        Self(octets)
+    }
+
+    /// Link-local address derived from a MAC address
+    pub const fn link_local_from_mac(mac: MacAddress) -> Self {
+        Self::from_prefix_and_mac(&Self::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), mac)
+    }
+
+    /// Check if two addresses share the first `prefix_len` bits
+    pub const fn matches_prefix(&self, other: &Ipv6Addr, prefix_len: u8) -> bool {
+        if prefix_len == 0 {
+            return true;
+        }
+        let len = if prefix_len > 128 { 128 } else { prefix_len };
+        let mask = u128::MAX << (128 - len as u32);
+        u128::from_be_bytes(self.0) & mask == u128::from_be_bytes(other.0) & mask
+    }
+}
+
+impl fmt::Display for Ipv6Addr {
+    /// Format in the RFC 5952 canonical text form
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(v4) = self.to_ipv4_mapped() {
+            return write!(f, "::ffff:{}", v4);
+        }
+
+        let segments = self.segments();
+
+        // Longest run of two or more zero segments, first one on ties
+        let mut best = (0, 0);
+        let mut run = (0, 0);
+        for (i, &segment) in segments.iter().enumerate() {
+            if segment == 0 {
+                if run.1 == 0 {
+                    run.0 = i;
+                }
+                run.1 += 1;
+                if run.1 > best.1 {
+                    best = run;
+                }
+            } else {
+                run.1 = 0;
+            }
+        }
+
+        if best.1 < 2 {
+            for (i, segment) in segments.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(":")?;
+                }
+                write!(f, "{:x}", segment)?;
+            }
+            return Ok(());
+        }
+
+        let (start, len) = best;
+        for (i, segment) in segments[..start].iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:x}", segment)?;
+        }
+        f.write_str("::")?;
+        for (i, segment) in segments[start + len..].iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:x}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+/// IPv6 header (40 bytes)
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct Ipv6Header {
+    /// Version (4 bits), traffic class (8 bits) and flow label (20 bits)
+    pub version_class_flow: u32,
+    /// Payload length (extension headers + upper-layer data)
+    pub payload_length: u16,
+    /// Next header
+    pub next_header: u8,
+    /// Hop limit
+    pub hop_limit: u8,
+    /// Source address
+    pub src_addr: [u8; 16],
+    /// Destination address
+    pub dst_addr: [u8; 16],
+}
+
+impl Ipv6Header {
+    /// Header size (40 bytes)
+    pub const SIZE: usize = 40;
+
+    /// Default hop limit
+    pub const DEFAULT_HOP_LIMIT: u8 = 64;
+
+    /// Create new IPv6 header
+    pub const fn new(src: Ipv6Addr, dst: Ipv6Addr, next_header: u8, payload_len: u16) -> Self {
+        Self {
+            version_class_flow: (6u32 << 28).to_be(),
+            payload_length: payload_len.to_be(),
+            next_header,
+            hop_limit: Self::DEFAULT_HOP_LIMIT,
+            src_addr: src.0,
+            dst_addr: dst.0,
+        }
+    }
+
+    /// Parse header from bytes
+    pub fn parse(data: &[u8]) -> Result<Self, Ipv6Error> {
+        if data.len() < Self::SIZE {
+            return Err(Ipv6Error::TooShort);
+        }
+
+        let version_class_flow = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        if version_class_flow >> 28 != 6 {
+            return Err(Ipv6Error::InvalidVersion);
+        }
+
+        let mut src_addr = [0u8; 16];
+        let mut dst_addr = [0u8; 16];
+        src_addr.copy_from_slice(&data[8..24]);
+        dst_addr.copy_from_slice(&data[24..40]);
+
+        Ok(Self {
+            version_class_flow: version_class_flow.to_be(),
+            payload_length: u16::from_be_bytes([data[4], data[5]]).to_be(),
+            next_header: data[6],
+            hop_limit: data[7],
+            src_addr,
+            dst_addr,
+        })
+    }
+
+    /// Write header to buffer
+    pub fn write_to(&self, buffer: &mut [u8]) -> Result<(), Ipv6Error> {
+        if buffer.len() < Self::SIZE {
+            return Err(Ipv6Error::BufferTooSmall);
+        }
+
+        buffer[0..4].copy_from_slice(&u32::from_be(self.version_class_flow).to_be_bytes());
+        buffer[4..6].copy_from_slice(&u16::from_be(self.payload_length).to_be_bytes());
+        buffer[6] = self.next_header;
+        buffer[7] = self.hop_limit;
+        buffer[8..24].copy_from_slice(&self.src_addr);
+        buffer[24..40].copy_from_slice(&self.dst_addr);
+
+        Ok(())
+    }
+
+    /// Get traffic class
+    pub fn traffic_class(&self) -> u8 {
+        (u32::from_be(self.version_class_flow) >> 20) as u8
+    }
+
+    /// Get flow label
+    pub fn flow_label(&self) -> u32 {
+        u32::from_be(self.version_class_flow) & 0xF_FFFF
+    }
+
+    /// Get payload length
+    pub fn payload_length(&self) -> u16 {
+        u16::from_be(self.payload_length)
+    }
+
+    /// Get hop limit
+    pub fn hop_limit(&self) -> u8 {
+        self.hop_limit
+    }
+
+    /// Get source address
+    pub fn src_addr(&self) -> Ipv6Addr {
+        Ipv6Addr(self.src_addr)
+    }
+
+    /// Get destination address
+    pub fn dst_addr(&self) -> Ipv6Addr {
+        Ipv6Addr(self.dst_addr)
+    }
+}
+
+/// Offset of the next header field in the IPv6 header
+const NEXT_HEADER_FIELD: usize = 6;
+
+/// Hop-by-Hop Options header
+pub const NEXT_HOP_BY_HOP: u8 = 0;
+
+/// Routing header
+pub const NEXT_ROUTING: u8 = 43;
+
+/// Fragment header
+pub const NEXT_FRAGMENT: u8 = 44;
+
+/// No next header
+pub const NEXT_NONE: u8 = 59;
+
+/// Destination Options header
+pub const NEXT_DEST_OPTIONS: u8 = 60;
+
+/// Result of walking a packet's extension header chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtensionHeaders {
+    /// Upper-layer protocol
+    pub protocol: u8,
+    /// Offset of the upper-layer header from the start of the payload
+    pub offset: usize,
+    /// Offset within the packet of the field naming `protocol`
+    pub next_header_field: usize,
+    /// Whether a Hop-by-Hop Options header was present
+    pub hop_by_hop: bool,
+}
+
+/// Walk the extension headers at the start of an IPv6 payload
+///
+/// Hop-by-Hop and Destination Options are checked for options that must
+/// not be skipped. Routing headers with segments left and non-atomic
+/// fragments are rejected, since packets are neither forwarded nor
+/// reassembled. Error pointers are offsets within the whole packet.
+pub fn parse_extension_headers(
+    next_header: u8,
+    payload: &[u8],
+    multicast: bool,
+) -> Result<ExtensionHeaders, Ipv6Error> {
+    let mut next = next_header;
+    let mut offset = 0;
+    let mut next_header_field = NEXT_HEADER_FIELD;
+    let mut hop_by_hop = false;
+
+    loop {
+        match next {
+            NEXT_HOP_BY_HOP | NEXT_DEST_OPTIONS | NEXT_ROUTING => {
+                // Hop-by-Hop Options may only appear first
+                if next == NEXT_HOP_BY_HOP && offset != 0 {
+                    return Err(Ipv6Error::UnknownNextHeader(next_header_field as u32));
+                }
+
+                let header = payload.get(offset..offset + 2).ok_or(Ipv6Error::TooShort)?;
+                let len = (header[1] as usize + 1) * 8;
+                let ext = payload
+                    .get(offset..offset + len)
+                    .ok_or(Ipv6Error::TooShort)?;
+
+                if next == NEXT_ROUTING {
+                    // Segments left: this host would have to forward it
+                    if ext[3] != 0 {
+                        let pointer = Ipv6Header::SIZE + offset + 3;
+                        return Err(Ipv6Error::ParameterProblem(pointer as u32));
+                    }
+                } else {
+                    check_options(&ext[2..], Ipv6Header::SIZE + offset + 2, multicast)?;
+                }
+
+                hop_by_hop |= next == NEXT_HOP_BY_HOP;
+                next = ext[0];
+                next_header_field = Ipv6Header::SIZE + offset;
+                offset += len;
+            }
+            NEXT_FRAGMENT => {
+                let ext = payload.get(offset..offset + 8).ok_or(Ipv6Error::TooShort)?;
+                let offset_flags = u16::from_be_bytes([ext[2], ext[3]]);
+
+                // Only atomic fragments (offset 0, no more fragments)
+                if offset_flags & 0xFFF9 != 0 {
+                    return Err(Ipv6Error::Fragmented);
+                }
+
+                next = ext[0];
+                next_header_field = Ipv6Header::SIZE + offset;
+                offset += 8;
+            }
+            _ => {
+                return Ok(ExtensionHeaders {
+                    protocol: next,
+                    offset,
+                    next_header_field,
+                    hop_by_hop,
+                })
+            }
+        }
+    }
+}
+
+/// Check the options of a Hop-by-Hop or Destination Options header
+///
+/// `base` is the offset of `options` within the packet. Unknown
+/// options are handled as their two high-order type bits ask (RFC 8200
+/// section 4.2).
+fn check_options(options: &[u8], base: usize, multicast: bool) -> Result<(), Ipv6Error> {
+    let mut i = 0;
+
+    while i < options.len() {
+        let kind = options[i];
+
+        // Pad1
+        if kind == 0 {
+            i += 1;
+            continue;
+        }
+
+        let len = *options.get(i + 1).ok_or(Ipv6Error::TooShort)? as usize;
+        if i + 2 + len > options.len() {
+            return Err(Ipv6Error::TooShort);
+        }
+
+        // PadN is the only option this host understands
+        if kind != 1 {
+            let pointer = (base + i) as u32;
+            match kind >> 6 {
+                0 => {}
+                1 => return Err(Ipv6Error::Discarded),
+                2 => return Err(Ipv6Error::UnrecognizedOption(pointer)),
+                _ if multicast => return Err(Ipv6Error::Discarded),
+                _ => return Err(Ipv6Error::UnrecognizedOption(pointer)),
+            }
+        }
+
+        i += 2 + len;
+    }
+
+    Ok(())
+}
+
+/// Build a complete IPv6 packet (header + payload)
+pub fn build_packet(
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    next_header: u8,
+    hop_limit: u8,
+    payload: &[u8],
+) -> Result<Vec<u8>, Ipv6Error> {
+    if payload.len() > u16::MAX as usize {
+        return Err(Ipv6Error::PacketTooLarge);
+    }
+
+    let mut header = Ipv6Header::new(src, dst, next_header, payload.len() as u16);
+    header.hop_limit = hop_limit;
+
+    let mut packet = vec![0u8; Ipv6Header::SIZE + payload.len()];
+    header.write_to(&mut packet)?;
+    packet[Ipv6Header::SIZE..].copy_from_slice(payload);
+
+    Ok(packet)
+}
+
+/// Calculate pseudo-header checksum for TCP, UDP and ICMPv6 (RFC 8200)
+pub fn calculate_pseudo_header_checksum(
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    next_header: u8,
+    length: u32,
+) -> u32 {
+    let mut sum: u32 = 0;
+
+    for chunk in src.0.chunks(2).chain(dst.0.chunks(2)) {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+
+    sum += length >> 16;
+    sum += length & 0xFFFF;
+    sum += next_header as u32;
+
+    sum
+}
+
+/// State of an interface address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressState {
+    /// Duplicate address detection still running; not used yet
+    Tentative,
+    /// Usable address
+    Preferred,
+}
+
+/// Address assigned to an interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceAddress {
+    /// Address
+    pub addr: Ipv6Addr,
+    /// On-link prefix length
+    pub prefix_len: u8,
+    /// Address state
+    pub state: AddressState,
+    /// Expiry (uptime in milliseconds), `None` for permanent addresses
+    pub valid_until: Option<u64>,
+    /// End of duplicate address detection (uptime in milliseconds)
+    dad_deadline: Option<u64>,
+}
+
+/// Per-interface IPv6 configuration
+#[derive(Default)]
+struct Interface {
+    /// Assigned addresses
+    addresses: Vec<InterfaceAddress>,
+    /// Router solicitations sent
+    solicitations: u32,
+    /// Time of the next router solicitation (uptime in milliseconds)
+    next_solicitation: Option<u64>,
+}
+
+/// IPv6 configuration by interface name
+static INTERFACES: Mutex<BTreeMap<String, Interface>> = Mutex::new(BTreeMap::new());
+
+/// Assign an address to an interface
+///
+/// A tentative address goes through duplicate address detection before it
+/// is used.
+pub fn add_address(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    addr: Ipv6Addr,
+    prefix_len: u8,
+    tentative: bool,
+    valid_until: Option<u64>,
+) -> Result<(), Ipv6Error> {
+    if addr.is_unspecified() || addr.is_multicast() || prefix_len > 128 {
+        return Err(Ipv6Error::InvalidAddress);
+    }
+
+    let name = device.lock().name().to_string();
+    {
+        let mut interfaces = INTERFACES.lock();
+        let interface = interfaces.entry(name).or_default();
+
+        if let Some(existing) = interface.addresses.iter_mut().find(|a| a.addr == addr) {
+            // Renewed by a router advertisement
+            existing.valid_until = valid_until;
+            return Ok(());
+        }
+
+        let now = crate::time::uptime_ms();
+        interface.addresses.push(InterfaceAddress {
+            addr,
+            prefix_len,
+            state: if tentative {
+                AddressState::Tentative
+            } else {
+                AddressState::Preferred
+            },
+            valid_until,
+            dad_deadline: tentative.then_some(now + ndisc::RETRANS_TIMER_MS),
+        });
+    }
+
+    if tentative {
+        let _ = ndisc::send_dad_probe(device, addr);
+    }
+    Ok(())
+}
+
+/// Remove an address from an interface
+pub fn remove_address(name: &str, addr: Ipv6Addr) {
+    if let Some(interface) = INTERFACES.lock().get_mut(name) {
+        interface.addresses.retain(|a| a.addr != addr);
+    }
+}
+
+/// Get the addresses assigned to an interface
+pub fn addresses(name: &str) -> Vec<InterfaceAddress> {
+    INTERFACES
+        .lock()
+        .get(name)
+        .map(|interface| interface.addresses.clone())
+        .unwrap_or_default()
+}
+
+/// Get the state of an address assigned to an interface
+pub fn address_state(name: &str, addr: Ipv6Addr) -> Option<AddressState> {
+    INTERFACES
+        .lock()
+        .get(name)?
+        .addresses
+        .iter()
+        .find(|a| a.addr == addr)
+        .map(|a| a.state)
+}
+
+/// Check if an address is assigned (and usable) on any interface
+pub fn is_local_address(addr: Ipv6Addr) -> bool {
+    INTERFACES.lock().values().any(|interface| {
+        interface
+            .addresses
+            .iter()
+            .any(|a| a.addr == addr && a.state == AddressState::Preferred)
+    })
+}
+
+/// Check if this host receives traffic for a destination address
+///
+/// Accepts assigned addresses, all-nodes and the solicited-node groups of
+/// assigned (including tentative) addresses.
+pub fn accepts_destination(name: &str, dst: Ipv6Addr) -> bool {
+    if dst == Ipv6Addr::ALL_NODES || dst.is_loopback() {
+        return true;
+    }
+
+    let interfaces = INTERFACES.lock();
+    let Some(interface) = interfaces.get(name) else {
+        return false;
+    };
+
+    interface.addresses.iter().any(|a| {
+        (a.addr == dst && a.state == AddressState::Preferred) || a.addr.solicited_node() == dst
+    })
+}
+
+/// Check if a destination is on-link for an interface
+fn is_on_link(name: &str, dst: Ipv6Addr) -> bool {
+    if dst.is_link_local() || dst.is_multicast() {
+        return true;
+    }
+
+    INTERFACES.lock().get(name).is_some_and(|interface| {
+        interface
+            .addresses
+            .iter()
+            .any(|a| dst.matches_prefix(&a.addr, a.prefix_len))
+    }) || ndisc::is_on_link_prefix(dst)
+}
+
+/// Select the source address for traffic to a destination
+///
+/// Loopback traffic uses ::1 and link-local destinations a link-local
+/// address; anything else prefers a global address (RFC 6724, simplified).
+pub fn select_source(dst: Ipv6Addr) -> Ipv6Addr {
+    if dst.is_loopback() {
+        return Ipv6Addr::LOCALHOST;
+    }
+
+    let Some(device) = netdev::default_device() else {
+        return Ipv6Addr::UNSPECIFIED;
+    };
+    let name = device.lock().name().to_string();
+    let usable: Vec<Ipv6Addr> = addresses(&name)
+        .into_iter()
+        .filter(|a| a.state == AddressState::Preferred)
+        .map(|a| a.addr)
+        .collect();
+
+    let link_scope = dst.is_link_local() || (dst.is_multicast() && dst.0[1] & 0x0F <= 2);
+    usable
+        .iter()
+        .find(|a| a.is_link_local() == link_scope)
+        .or_else(|| usable.first())
+        .copied()
+        .unwrap_or(Ipv6Addr::UNSPECIFIED)
+}
+
+/// Device that traffic to a destination leaves through
+fn route_device(dst: Ipv6Addr) -> Option<Arc<Mutex<dyn NetDevice>>> {
+    if dst.is_loopback() {
+        loopback::device()
+    } else {
+        netdev::default_device()
+    }
+}
+
+/// MTU of the device that traffic to a destination leaves through
+pub fn route_mtu(dst: Ipv6Addr) -> Option<usize> {
+    let mtu = route_device(dst)?.lock().mtu();
+    Some(mtu)
+}
+
+/// Send an IPv6 packet
+///
+/// Loopback traffic goes through the loopback device and everything else
+/// through the default device, either directly when the destination is
+/// on-link or via the default router. Packets are not fragmented.
+pub fn send_packet(
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    protocol: IpProtocol,
+    payload: &[u8],
+) -> Result<(), Ipv6Error> {
+    let device = route_device(dst).ok_or(Ipv6Error::NoRoute)?;
+    let packet = build_packet(
+        src,
+        dst,
+        protocol.as_u8(),
+        Ipv6Header::DEFAULT_HOP_LIMIT,
+        payload,
+    )?;
+
+    if dst.is_loopback() {
+        return ndisc::transmit_ipv6(&device, MacAddress::ZERO, &packet)
+            .map_err(|_| Ipv6Error::TransmitFailed);
+    }
+
+    let (name, mtu) = {
+        let dev = device.lock();
+        (dev.name().to_string(), dev.mtu())
+    };
+    if packet.len() > mtu {
+        return Err(Ipv6Error::PacketTooLarge);
+    }
+
+    let next_hop = if is_on_link(&name, dst) {
+        dst
+    } else {
+        ndisc::default_router().ok_or(Ipv6Error::NoRoute)?
+    };
+
+    ndisc::send_ipv6_packet(&device, next_hop, &packet).map_err(|_| Ipv6Error::TransmitFailed)
+}
+
+/// Start autoconfiguration on an interface
+///
+/// Assigns the EUI-64 link-local address, which becomes usable once
+/// duplicate address detection has passed (RFC 4862).
+pub fn configure_interface(device: &Arc<Mutex<dyn NetDevice>>) -> Result<(), Ipv6Error> {
+    let (name, mac, loopback) = {
+        let dev = device.lock();
+        (dev.name().to_string(), dev.mac_address(), dev.is_loopback())
+    };
+
+    if loopback {
+        return add_address(device, Ipv6Addr::LOCALHOST, 128, false, None);
+    }
+
+    let configured = INTERFACES
+        .lock()
+        .get(&name)
+        .is_some_and(|interface| interface.addresses.iter().any(|a| a.addr.is_link_local()));
+    if configured {
+        return Ok(());
+    }
+
+    add_address(device, Ipv6Addr::link_local_from_mac(mac), 64, true, None)
+}
+
+/// Interval between IPv6 configuration timer runs (milliseconds)
+pub const IPV6_TIMER_INTERVAL_MS: u64 = 100;
+
+/// Run autoconfiguration timers
+///
+/// Configures newly registered devices, completes duplicate address
+/// detection, solicits routers once a link-local address is usable and
+/// drops expired addresses.
+pub fn timer_tick() {
+    let now = crate::time::uptime_ms();
+
+    for device in netdev::devices() {
+        let _ = configure_interface(&device);
+    }
+
+    let mut solicit = Vec::new();
+    {
+        let mut interfaces = INTERFACES.lock();
+        for (name, interface) in interfaces.iter_mut() {
+            interface
+                .addresses
+                .retain(|a| a.valid_until.is_none_or(|until| until > now));
+
+            for addr in interface.addresses.iter_mut() {
+                if addr.dad_deadline.is_some_and(|deadline| deadline <= now) {
+                    addr.dad_deadline = None;
+                    addr.state = AddressState::Preferred;
+                    if addr.addr.is_link_local() {
+                        interface.next_solicitation = Some(now);
+                    }
+                }
+            }
+
+            if interface.next_solicitation.is_some_and(|at| at <= now) {
+                interface.solicitations += 1;
+                interface.next_solicitation = (interface.solicitations
+                    < ndisc::MAX_RTR_SOLICITATIONS)
+                    .then_some(now + ndisc::RTR_SOLICITATION_INTERVAL_MS);
+                solicit.push(name.clone());
+            }
+        }
+    }
+
+    for name in solicit {
+        if let Some(device) = netdev::get_device(&name) {
+            let _ = ndisc::send_router_solicitation(&device);
+        }
+    }
+}
+
+/// Stop soliciting routers on an interface (a router has answered)
+pub fn router_found(name: &str) {
+    if let Some(interface) = INTERFACES.lock().get_mut(name) {
+        interface.next_solicitation = None;
+    }
+}
+
+/// Handle a duplicate of a tentative address
+///
+/// The address is removed and never used (RFC 4862 section 5.4.5).
+pub fn duplicate_detected(name: &str, addr: Ipv6Addr) -> bool {
+    let mut interfaces = INTERFACES.lock();
+    let Some(interface) = interfaces.get_mut(name) else {
+        return false;
+    };
+
+    let before = interface.addresses.len();
+    interface
+        .addresses
+        .retain(|a| !(a.addr == addr && a.state == AddressState::Tentative));
+    let removed = interface.addresses.len() != before;
+    drop(interfaces);
+
+    if removed {
+        crate::printk::printk("  IPv6: duplicate address detected\n");
+    }
+    removed
+}
+
+/// IPv6 errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ipv6Error {
+    /// Packet too short
+    TooShort,
+    /// Buffer too small
+    BufferTooSmall,
+    /// Invalid version
+    InvalidVersion,
+    /// Invalid address
+    InvalidAddress,
+    /// Packet exceeds the maximum payload size or the link MTU
+    PacketTooLarge,
+    /// Unknown next header (offset of the field within the packet)
+    UnknownNextHeader(u32),
+    /// Unrecognized option that must be reported (offset within the packet)
+    UnrecognizedOption(u32),
+    /// Erroneous header field (offset within the packet)
+    ParameterProblem(u32),
+    /// Packet silently discarded
+    Discarded,
+    /// Non-atomic fragment
+    Fragmented,
+    /// Packet addressed to another host
+    NotForUs,
+    /// No route to destination
+    NoRoute,
+    /// Device failed to transmit
+    TransmitFailed,
+}
+
+/// IPv6 subsystem initialized flag
+static IPV6_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Initialize IPv6 subsystem
+///
+/// Assigns ::1 to the loopback device; other devices are configured from
+/// the periodic timer as they appear.
+pub fn init() {
+    if IPV6_INITIALIZED.load(Ordering::Acquire) {
+        return;
+    }
+
+    if let Some(lo) = loopback::device() {
+        let _ = configure_interface(&lo);
+    }
+
+    if crate::time::timer::create_periodic_timer(IPV6_TIMER_INTERVAL_MS, timer_tick).is_err() {
+        crate::printk::printk("  Failed to start IPv6 timer\n");
+    }
+
+    IPV6_INITIALIZED.store(true, Ordering::Release);
+}
+
+/// Check if IPv6 subsystem is initialized
+pub fn is_initialized() -> bool {
+    IPV6_INITIALIZED.load(Ordering::Acquire)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[test]
+    fn test_address_formatting() {
+        assert_eq!(format!("{}", Ipv6Addr::UNSPECIFIED), "::");
+        assert_eq!(format!("{}", Ipv6Addr::LOCALHOST), "::1");
+        assert_eq!(
+            format!("{}", Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 1, 0, 0, 1)),
+            "2001:db8::1:0:0:1"
+        );
+        assert_eq!(
+            format!("{}", Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 1, 1, 1, 1)),
+            "2001:db8:0:1:1:1:1:1"
+        );
+        assert_eq!(
+            format!("{}", Ipv6Addr::from_ipv4_mapped(Ipv4Addr::new(10, 0, 0, 1))),
+            "::ffff:10.0.0.1"
+        );
+    }
+
+    #[test]
+    fn test_autoconfigured_addresses() {
+        let mac = MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+        let link_local = Ipv6Addr::link_local_from_mac(mac);
+        assert_eq!(
+            link_local,
+            Ipv6Addr::new(0xfe80, 0, 0, 0, 0x5054, 0x00ff, 0xfe12, 0x3456)
+        );
+        assert!(link_local.is_link_local());
+
+        let group = link_local.solicited_node();
+        assert_eq!(group, Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff12, 0x3456));
+        assert_eq!(
+            group.multicast_mac(),
+            MacAddress([0x33, 0x33, 0xff, 0x12, 0x34, 0x56])
+        );
+
+        let prefix = Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 0);
+        let global = Ipv6Addr::from_prefix_and_mac(&prefix, mac);
+        assert!(global.matches_prefix(&prefix, 64));
+        assert!(!global.matches_prefix(&link_local, 64));
+    }
+
+    #[test]
+    fn test_extension_headers() {
+        // Hop-by-Hop (PadN) followed by Destination Options, then UDP
+        let mut payload = vec![NEXT_DEST_OPTIONS, 0, 1, 4, 0, 0, 0, 0];
+        payload.extend_from_slice(&[17, 0, 1, 4, 0, 0, 0, 0]);
+        payload.extend_from_slice(&[0u8; 8]);
+
+        let ext = parse_extension_headers(NEXT_HOP_BY_HOP, &payload, false).unwrap();
+        assert_eq!(ext.protocol, 17);
+        assert_eq!(ext.offset, 16);
+        assert_eq!(ext.next_header_field, Ipv6Header::SIZE + 8);
+        assert!(ext.hop_by_hop);
+
+        // Unknown option whose type asks for an error report
+        payload[10] = 0x80;
+        assert_eq!(
+            parse_extension_headers(NEXT_HOP_BY_HOP, &payload, false),
+            Err(Ipv6Error::UnrecognizedOption(
+                (Ipv6Header::SIZE + 10) as u32
+            ))
+        );
+        assert_eq!(
+            parse_extension_headers(NEXT_HOP_BY_HOP, &payload, true),
+            Err(Ipv6Error::UnrecognizedOption(
+                (Ipv6Header::SIZE + 10) as u32
+            ))
+        );
+
+        // Unknown option to be skipped
+        payload[10] = 0x3E;
+        assert!(parse_extension_headers(NEXT_HOP_BY_HOP, &payload, false).is_ok());
+
+        // Routing header with segments left
+        let routing = [17, 0, 0, 1, 0, 0, 0, 0];
+        assert_eq!(
+            parse_extension_headers(NEXT_ROUTING, &routing, false),
+            Err(Ipv6Error::ParameterProblem((Ipv6Header::SIZE + 3) as u32))
+        );
+
+        // Non-atomic fragment
+        let fragment = [17, 0, 0, 1, 0, 0, 0, 1];
+        assert_eq!(
+            parse_extension_headers(NEXT_FRAGMENT, &fragment, false),
+            Err(Ipv6Error::Fragmented)
+        );
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let src = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let dst = Ipv6Addr::ALL_NODES;
+        let packet = build_packet(src, dst, 58, 255, &[1, 2, 3, 4]).unwrap();
+
+        let header = Ipv6Header::parse(&packet).unwrap();
+        assert_eq!(header.src_addr(), src);
+        assert_eq!(header.dst_addr(), dst);
+        assert_eq!(header.payload_length(), 4);
+        assert_eq!(header.hop_limit(), 255);
+        assert_eq!({ header.next_header }, 58);
+    }
+}
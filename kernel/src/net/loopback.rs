@@ -0,0 +1,375 @@
+//! Loopback Network Device
+//!
+//! Software device that hands every transmitted frame back to the local
+//! receive path. Traffic to 127.0.0.0/8 is routed through it.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::ethernet::MacAddress;
+use super::netdev::{self, DeviceCapabilities, DeviceStats, LinkState, NetDevError, NetDevice};
+
+/// Loopback device name
+pub const LOOPBACK_NAME: &str = "lo";
+
+/// Loopback MTU
+pub const LOOPBACK_MTU: usize = 65536;
+
+/// Maximum number of frames waiting to be received
+pub const LOOPBACK_QUEUE_LEN: usize = 1000;
+
+/// Loopback network device
+pub struct LoopbackDevice {
+    /// Maximum transmission unit
+    mtu: usize,
+    /// Interface is up
+    up: bool,
+    /// Frames waiting to be received
+    queue: VecDeque<Vec<u8>>,
+    /// Device statistics
+    stats: DeviceStats,
+}
+
+impl LoopbackDevice {
+    /// Create new loopback device
+    pub fn new() -> Self {
+        Self {
+            mtu: LOOPBACK_MTU,
+            up: false,
+            queue: VecDeque::new(),
+            stats: DeviceStats::new(),
+        }
+    }
+
+    /// Get number of frames waiting to be received
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+impl Default for LoopbackDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetDevice for LoopbackDevice {
+    fn name(&self) -> &str {
+        LOOPBACK_NAME
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        MacAddress::ZERO
+    }
+
+    fn set_mac_address(&mut self, _mac: MacAddress) -> Result<(), NetDevError> {
+        Err(NetDevError::NotSupported)
+    }
+
+    fn link_state(&self) -> LinkState {
+        if self.up {
+            LinkState::Up
+        } else {
+            LinkState::Down
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn set_mtu(&mut self, mtu: usize) -> Result<(), NetDevError> {
+        if mtu == 0 || mtu > LOOPBACK_MTU {
+            return Err(NetDevError::InvalidParam);
+        }
+
+        self.mtu = mtu;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            mtu: self.mtu,
+            ..DeviceCapabilities::default()
+        }
+    }
+
+    fn up(&mut self) -> Result<(), NetDevError> {
+        self.up = true;
+        Ok(())
+    }
+
+    fn down(&mut self) -> Result<(), NetDevError> {
+        self.up = false;
+        self.queue.clear();
+        Ok(())
+    }
+
+    fn send(&mut self, packet: &[u8]) -> Result<(), NetDevError> {
+        if !self.up {
+            return Err(NetDevError::DeviceDown);
+        }
+
+        if self.queue.len() >= LOOPBACK_QUEUE_LEN {
+            self.stats.inc_tx_dropped();
+            return Err(NetDevError::Busy);
+        }
+
+        self.queue.push_back(packet.to_vec());
+        self.stats.inc_tx_packets(1);
+        self.stats.inc_tx_bytes(packet.len() as u32);
+        Ok(())
+    }
+
+    fn recv(&mut self, buffer: &mut [u8]) -> Result<usize, NetDevError> {
+        if !self.up {
+            return Err(NetDevError::DeviceDown);
+        }
+
+        let frame = self.queue.pop_front().ok_or(NetDevError::WouldBlock)?;
+        if frame.len() > buffer.len() {
+            self.stats.inc_rx_dropped();
+            return Err(NetDevError::BufferTooSmall);
+        }
+
+        buffer[..frame.len()].copy_from_slice(&frame);
+        self.stats.inc_rx_packets(1);
+        self.stats.inc_rx_bytes(frame.len() as u32);
+        Ok(frame.len())
+    }
+
+    fn stats(&self) -> &DeviceStats {
+        &self.stats
+    }
+
+    fn is_loopback(&self) -> bool {
+        true
+    }
+}
+
+/// Get the registered loopback device
+pub fn device() -> Option<Arc<Mutex<dyn NetDevice>>> {
+    netdev::get_device(LOOPBACK_NAME)
+}
+
+/// Create, bring up and register the loopback device
+pub fn init() {
+    if device().is_some() {
+        return;
+    }
+
+    let mut lo = LoopbackDevice::new();
+    let _ = lo.up();
+
+    // Registration only fails if another caller registered "lo" first
+    let _ = netdev::register_device(Arc::new(Mutex::new(lo)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ip::IpAddr;
+    use crate::net::ipv4::Ipv4Addr;
+    use crate::net::ipv6::Ipv6Addr;
+    use crate::net::rx;
+    use crate::net::socket::{Socket, SocketAddr, SocketAddrV4, SocketAddrV6};
+    use crate::net::tcp::{self, TcpSocket, TcpState};
+    use crate::net::udp::UdpSocket;
+
+    /// Deliver everything queued on the loopback device
+    fn drain() {
+        let lo = device().unwrap();
+        while rx::poll_device(&lo) > 0 {}
+    }
+
+    #[test]
+    fn test_loopback_queue() {
+        let mut lo = LoopbackDevice::new();
+        assert_eq!(lo.send(&[1, 2, 3]), Err(NetDevError::DeviceDown));
+
+        lo.up().unwrap();
+        lo.send(&[1, 2, 3]).unwrap();
+        assert_eq!(lo.pending(), 1);
+
+        let mut buffer = [0u8; 8];
+        assert_eq!(lo.recv(&mut buffer), Ok(3));
+        assert_eq!(&buffer[..3], &[1, 2, 3]);
+        assert_eq!(lo.recv(&mut buffer), Err(NetDevError::WouldBlock));
+    }
+
+    #[test]
+    fn test_udp_over_loopback() {
+        init();
+
+        let mut server = UdpSocket::new().unwrap();
+        server
+            .bind(SocketAddrV4 {
+                ip: Ipv4Addr::LOCALHOST.0,
+                port: 9100,
+            })
+            .unwrap();
+
+        let mut client = UdpSocket::new().unwrap();
+        client
+            .connect(SocketAddrV4 {
+                ip: Ipv4Addr::LOCALHOST.0,
+                port: 9100,
+            })
+            .unwrap();
+        client.send(b"over lo").unwrap();
+        drain();
+
+        let mut buffer = [0u8; 16];
+        let (len, from) = server.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"over lo");
+        assert_eq!(from.ip, IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        server.close().unwrap();
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn test_tcp_handshake_over_loopback() {
+        init();
+
+        let listener = Arc::new(Mutex::new(TcpSocket::new().unwrap()));
+        let local = SocketAddrV4 {
+            ip: Ipv4Addr::LOCALHOST.0,
+            port: 9200,
+        };
+        {
+            let mut sock = listener.lock();
+            sock.bind(SocketAddr::V4(local)).unwrap();
+            sock.listen(1).unwrap();
+        }
+        tcp::register_listener(local.port, listener.clone());
+
+        let mut client = TcpSocket::new().unwrap();
+        client.connect(SocketAddr::V4(local)).unwrap();
+        drain();
+
+        let server = listener.lock().accept().unwrap();
+        client.send(b"hello, lo", 0).unwrap();
+        drain();
+
+        let mut buffer = [0u8; 16];
+        let len = server.lock().recv(&mut buffer, 0).unwrap();
+        assert_eq!(&buffer[..len], b"hello, lo");
+
+        // Active close from the client side
+        client.close().unwrap();
+        drain();
+        server.lock().close().unwrap();
+        drain();
+        assert_eq!(client.tcb_state(), TcpState::TimeWait);
+
+        listener.lock().close().unwrap();
+    }
+
+    #[test]
+    fn test_tcp_closed_port_reset() {
+        init();
+
+        let mut client = TcpSocket::new().unwrap();
+        client
+            .connect(SocketAddr::V4(SocketAddrV4 {
+                ip: Ipv4Addr::LOCALHOST.0,
+                port: 9201,
+            }))
+            .unwrap();
+        drain();
+
+        // Nothing listens on the port, so the SYN is refused with a reset
+        assert_eq!(client.tcb_state(), TcpState::Closed);
+        client.close().unwrap();
+    }
+
+    /// IPv6 socket address on a port
+    fn addr_v6(ip: Ipv6Addr, port: u16) -> SocketAddrV6 {
+        SocketAddrV6 {
+            ip: ip.0,
+            port,
+            flowinfo: 0,
+            scope_id: 0,
+        }
+    }
+
+    #[test]
+    fn test_udp_over_loopback_v6() {
+        init();
+
+        let mut server = UdpSocket::new().unwrap();
+        server.bind(addr_v6(Ipv6Addr::LOCALHOST, 9400)).unwrap();
+
+        let mut client = UdpSocket::new().unwrap();
+        client.connect(addr_v6(Ipv6Addr::LOCALHOST, 9400)).unwrap();
+        client.send(b"over ::1").unwrap();
+        drain();
+
+        let mut buffer = [0u8; 16];
+        let (len, from) = server.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"over ::1");
+        assert_eq!(from.ip, IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!(from.port, client.local_addr().unwrap().port);
+
+        server.close().unwrap();
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn test_tcp_dual_stack_listener() {
+        init();
+
+        // A listener on :: takes connections over both IPv6 and IPv4
+        let listener = Arc::new(Mutex::new(TcpSocket::new().unwrap()));
+        {
+            let mut sock = listener.lock();
+            sock.bind(SocketAddr::V6(addr_v6(Ipv6Addr::UNSPECIFIED, 9401)))
+                .unwrap();
+            sock.listen(2).unwrap();
+        }
+        tcp::register_listener(9401, listener.clone());
+
+        let mut client6 = TcpSocket::new().unwrap();
+        client6
+            .connect(SocketAddr::V6(addr_v6(Ipv6Addr::LOCALHOST, 9401)))
+            .unwrap();
+        let mut client4 = TcpSocket::new().unwrap();
+        client4
+            .connect(SocketAddr::V4(SocketAddrV4 {
+                ip: Ipv4Addr::LOCALHOST.0,
+                port: 9401,
+            }))
+            .unwrap();
+        drain();
+
+        for client in [&mut client6, &mut client4] {
+            let server = listener.lock().accept().unwrap();
+            client.send(b"dual stack", 0).unwrap();
+            drain();
+
+            let mut buffer = [0u8; 16];
+            let len = server.lock().recv(&mut buffer, 0).unwrap();
+            assert_eq!(&buffer[..len], b"dual stack");
+            assert_eq!(
+                server
+                    .lock()
+                    .peer_addr()
+                    .map(|peer| matches!(peer, SocketAddr::V6(_))),
+                client
+                    .local_addr()
+                    .map(|local| matches!(local, SocketAddr::V6(_)))
+            );
+
+            client.close().unwrap();
+            drain();
+            server.lock().close().unwrap();
+            drain();
+            assert_eq!(client.tcb_state(), TcpState::TimeWait);
+        }
+
+        listener.lock().close().unwrap();
+    }
+}
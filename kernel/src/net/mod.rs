@@ -2,18 +2,31 @@
 //!
 //! TCP/IP Network stack implementation
 
+use alloc::string::ToString;
 use core::sync::atomic::{AtomicBool, Ordering};
 
+use crate::cmdline::IpAutoconf;
+
 pub mod arp;
+pub mod dhcp;
+pub mod dns;
 pub mod ethernet;
+pub mod icmp;
+pub mod icmpv6;
+pub mod ip;
 pub mod ipv4;
+pub mod ipv6;
+pub mod loopback;
+pub mod ndisc;
 pub mod netdev;
+pub mod netfilter;
+pub mod packet;
+pub mod pcap;
+pub mod rx;
 pub mod socket;
 pub mod tcp;
 pub mod udp;
-
-// TODO: Add these modules as they're implemented
-// pub mod icmp;
+pub mod unix;
 
 /// Network subsystem initialized flag
 static NET_INITIALIZED: AtomicBool = AtomicBool::new(false);
@@ -26,16 +39,69 @@ pub fn init() {
 
     // Initialize subsystems in order
     netdev::init();
+    loopback::init();
     ethernet::init();
     arp::init();
     ipv4::init();
+    netfilter::init();
+    icmp::init();
+    ndisc::init();
+    ipv6::init();
+    icmpv6::init();
     udp::init();
+    dns::init();
     tcp::init();
     socket::init();
+    pcap::init();
+    rx::init();
 
     NET_INITIALIZED.store(true, Ordering::Release);
 }
 
+/// Configure the network interface from the `ip=` kernel parameter
+///
+/// Called once device drivers have registered their devices. Static
+/// addresses are assigned directly; `ip=dhcp` starts the DHCP client.
+pub fn autoconfigure() {
+    let Some(config) = crate::cmdline::ip_config() else {
+        return;
+    };
+
+    let device = config
+        .device
+        .clone()
+        .or_else(|| netdev::default_device().map(|d| d.lock().name().to_string()));
+    let Some(device) = device else {
+        crate::printk::printk("ip=: no network device to configure\n");
+        return;
+    };
+
+    match config.autoconf {
+        IpAutoconf::Dhcp => {
+            if dhcp::start(&device).is_err() {
+                crate::printk::printk("ip=: failed to start DHCP\n");
+            }
+        }
+        IpAutoconf::Off => {
+            let Some(address) = config.address else {
+                return;
+            };
+            let prefix_len = config
+                .netmask
+                .map_or_else(|| dhcp::classful_prefix(address), dhcp::mask_prefix);
+            if ipv4::addr::add_address(&device, address, prefix_len).is_err() {
+                crate::printk::printk("ip=: failed to assign address\n");
+                return;
+            }
+            if let Some(gateway) = config.gateway {
+                if ipv4::route::set_default_gateway(gateway, &device).is_err() {
+                    crate::printk::printk("ip=: failed to set default gateway\n");
+                }
+            }
+        }
+    }
+}
+
 /// Check if network subsystem is initialized
 pub fn is_initialized() -> bool {
     NET_INITIALIZED.load(Ordering::Acquire)
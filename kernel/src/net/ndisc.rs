@@ -0,0 +1,757 @@
+//! Neighbor Discovery (NDP)
+//!
+//! Maps IPv6 addresses to MAC addresses, detects duplicate addresses and
+//! learns routers and prefixes from router advertisements (RFC 4861).
+
+use alloc::collections::BTreeMap;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use super::ethernet::{EtherType, EthernetFrameBuilder, EthernetHeader, MacAddress};
+use super::icmpv6::{self, Icmpv6Header, Icmpv6Type};
+use super::ipv4::IpProtocol;
+use super::ipv6::{self, AddressState, Ipv6Addr};
+use super::netdev::{self, NetDevice};
+
+/// Hop limit of every Neighbor Discovery message
+pub const ND_HOP_LIMIT: u8 = 255;
+
+/// Time between solicitations, and the duplicate address detection
+/// period (milliseconds)
+pub const RETRANS_TIMER_MS: u64 = 1000;
+
+/// Router solicitations sent before giving up
+pub const MAX_RTR_SOLICITATIONS: u32 = 3;
+
+/// Interval between router solicitations (milliseconds)
+pub const RTR_SOLICITATION_INTERVAL_MS: u64 = 4000;
+
+/// Maximum number of packets queued per unresolved address
+pub const MAX_PENDING_PER_HOST: usize = 8;
+
+/// Source link-layer address option
+const OPT_SOURCE_LL_ADDR: u8 = 1;
+
+/// Target link-layer address option
+const OPT_TARGET_LL_ADDR: u8 = 2;
+
+/// Prefix information option
+const OPT_PREFIX_INFO: u8 = 3;
+
+/// Neighbor advertisement flags
+const NA_FLAG_SOLICITED: u32 = 0x4000_0000;
+const NA_FLAG_OVERRIDE: u32 = 0x2000_0000;
+
+/// Prefix information flags
+const PREFIX_FLAG_ON_LINK: u8 = 0x80;
+const PREFIX_FLAG_AUTONOMOUS: u8 = 0x40;
+
+/// Lifetime meaning "forever" in prefix information
+const INFINITE_LIFETIME: u32 = 0xFFFF_FFFF;
+
+/// Options carried by a Neighbor Discovery message
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NdOptions {
+    /// Source link-layer address
+    pub source_mac: Option<MacAddress>,
+    /// Target link-layer address
+    pub target_mac: Option<MacAddress>,
+    /// Prefix information
+    pub prefixes: Vec<PrefixInfo>,
+}
+
+/// Prefix information option contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefixInfo {
+    /// Prefix
+    pub prefix: Ipv6Addr,
+    /// Prefix length in bits
+    pub prefix_len: u8,
+    /// Prefix is on-link
+    pub on_link: bool,
+    /// Prefix may be used for address autoconfiguration
+    pub autonomous: bool,
+    /// Valid lifetime (seconds)
+    pub valid_lifetime: u32,
+}
+
+impl NdOptions {
+    /// Parse the options that follow a message's fixed part
+    pub fn parse(data: &[u8]) -> Result<Self, NdiscError> {
+        let mut options = Self::default();
+        let mut i = 0;
+
+        while i < data.len() {
+            let kind = data[i];
+            let len = *data.get(i + 1).ok_or(NdiscError::TooShort)? as usize * 8;
+            if len == 0 {
+                return Err(NdiscError::InvalidMessage);
+            }
+            let option = data.get(i..i + len).ok_or(NdiscError::TooShort)?;
+
+            match kind {
+                OPT_SOURCE_LL_ADDR | OPT_TARGET_LL_ADDR if len >= 8 => {
+                    let mut mac = [0u8; 6];
+                    mac.copy_from_slice(&option[2..8]);
+                    if kind == OPT_SOURCE_LL_ADDR {
+                        options.source_mac = Some(MacAddress(mac));
+                    } else {
+                        options.target_mac = Some(MacAddress(mac));
+                    }
+                }
+                OPT_PREFIX_INFO if len == 32 => {
+                    let mut prefix = [0u8; 16];
+                    prefix.copy_from_slice(&option[16..32]);
+                    options.prefixes.push(PrefixInfo {
+                        prefix: Ipv6Addr(prefix),
+                        prefix_len: option[2],
+                        on_link: option[3] & PREFIX_FLAG_ON_LINK != 0,
+                        autonomous: option[3] & PREFIX_FLAG_AUTONOMOUS != 0,
+                        valid_lifetime: u32::from_be_bytes([
+                            option[4], option[5], option[6], option[7],
+                        ]),
+                    });
+                }
+                // Unknown options are skipped (RFC 4861 section 4.6)
+                _ => {}
+            }
+
+            i += len;
+        }
+
+        Ok(options)
+    }
+}
+
+/// Append a link-layer address option
+fn push_mac_option(buffer: &mut Vec<u8>, kind: u8, mac: MacAddress) {
+    buffer.push(kind);
+    buffer.push(1);
+    buffer.extend_from_slice(&mac.0);
+}
+
+/// Neighbor cache entry
+#[derive(Debug, Clone, Copy)]
+pub struct NeighborEntry {
+    /// MAC address
+    pub mac: MacAddress,
+    /// IPv6 address
+    pub ip: Ipv6Addr,
+    /// Time-to-live (in seconds)
+    pub ttl: u32,
+}
+
+impl NeighborEntry {
+    /// Default TTL (5 minutes)
+    pub const DEFAULT_TTL: u32 = 300;
+
+    /// Create new neighbor entry
+    pub const fn new(mac: MacAddress, ip: Ipv6Addr) -> Self {
+        Self {
+            mac,
+            ip,
+            ttl: Self::DEFAULT_TTL,
+        }
+    }
+}
+
+/// Neighbor cache
+pub struct NeighborCache {
+    /// IPv6 to MAC mappings
+    entries: BTreeMap<Ipv6Addr, NeighborEntry>,
+}
+
+impl NeighborCache {
+    /// Create new neighbor cache
+    pub const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Insert entry
+    pub fn insert(&mut self, ip: Ipv6Addr, mac: MacAddress) {
+        self.entries.insert(ip, NeighborEntry::new(mac, ip));
+    }
+
+    /// Lookup MAC address by IPv6 address
+    pub fn lookup(&self, ip: Ipv6Addr) -> Option<MacAddress> {
+        self.entries.get(&ip).map(|entry| entry.mac)
+    }
+
+    /// Remove entry
+    pub fn remove(&mut self, ip: Ipv6Addr) -> Option<NeighborEntry> {
+        self.entries.remove(&ip)
+    }
+
+    /// Clear cache
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Get entry count
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Update TTL for all entries (call periodically)
+    pub fn update_ttl(&mut self, elapsed_secs: u32) {
+        self.entries.retain(|_, entry| {
+            if entry.ttl > elapsed_secs {
+                entry.ttl -= elapsed_secs;
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
+
+impl Default for NeighborCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Global neighbor cache
+static NEIGHBOR_CACHE: Mutex<NeighborCache> = Mutex::new(NeighborCache::new());
+
+/// Insert entry into global neighbor cache
+pub fn insert_entry(ip: Ipv6Addr, mac: MacAddress) {
+    NEIGHBOR_CACHE.lock().insert(ip, mac);
+}
+
+/// Lookup MAC address for IPv6 address in global cache
+pub fn lookup_mac(ip: Ipv6Addr) -> Option<MacAddress> {
+    NEIGHBOR_CACHE.lock().lookup(ip)
+}
+
+/// Remove entry from global cache
+pub fn remove_entry(ip: Ipv6Addr) -> Option<NeighborEntry> {
+    NEIGHBOR_CACHE.lock().remove(ip)
+}
+
+/// Clear global neighbor cache
+pub fn clear_cache() {
+    NEIGHBOR_CACHE.lock().clear();
+}
+
+/// Router learned from a router advertisement
+#[derive(Debug, Clone)]
+struct Router {
+    /// Router link-local address
+    addr: Ipv6Addr,
+    /// Expiry (uptime in milliseconds)
+    expires: u64,
+}
+
+/// On-link prefix learned from a router advertisement
+#[derive(Debug, Clone, Copy)]
+struct OnLinkPrefix {
+    prefix: Ipv6Addr,
+    prefix_len: u8,
+    /// Expiry (uptime in milliseconds), `None` if infinite
+    expires: Option<u64>,
+}
+
+/// Default router list
+static ROUTERS: Mutex<Vec<Router>> = Mutex::new(Vec::new());
+
+/// Prefix list
+static PREFIXES: Mutex<Vec<OnLinkPrefix>> = Mutex::new(Vec::new());
+
+/// Get the default router, if one is known
+pub fn default_router() -> Option<Ipv6Addr> {
+    let now = crate::time::uptime_ms();
+    ROUTERS
+        .lock()
+        .iter()
+        .find(|router| router.expires > now)
+        .map(|router| router.addr)
+}
+
+/// Check if an address falls in a prefix advertised as on-link
+pub fn is_on_link_prefix(addr: Ipv6Addr) -> bool {
+    let now = crate::time::uptime_ms();
+    PREFIXES.lock().iter().any(|p| {
+        p.expires.is_none_or(|expires| expires > now)
+            && addr.matches_prefix(&p.prefix, p.prefix_len)
+    })
+}
+
+/// IPv6 packet waiting for address resolution
+struct PendingPacket {
+    /// Device the packet will leave through
+    device: Arc<Mutex<dyn NetDevice>>,
+    /// IPv6 packet (without Ethernet header)
+    packet: Vec<u8>,
+}
+
+/// Packets waiting for a neighbor advertisement, keyed by next hop
+static ND_PENDING: Mutex<BTreeMap<Ipv6Addr, Vec<PendingPacket>>> = Mutex::new(BTreeMap::new());
+
+/// Get number of packets waiting for resolution of an address
+pub fn pending_count(ip: Ipv6Addr) -> usize {
+    ND_PENDING.lock().get(&ip).map_or(0, |queue| queue.len())
+}
+
+/// Frame an IPv6 packet and hand it to a device
+pub fn transmit_ipv6(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    dst_mac: MacAddress,
+    packet: &[u8],
+) -> Result<(), NdiscError> {
+    let src_mac = device.lock().mac_address();
+    let mut frame = vec![0u8; EthernetHeader::SIZE + packet.len()];
+    EthernetFrameBuilder::new()
+        .dst(dst_mac)
+        .src(src_mac)
+        .ethertype(EtherType::Ipv6)
+        .build(&mut frame, packet)
+        .map_err(|_| NdiscError::BufferTooSmall)?;
+
+    netdev::transmit(device, &frame).map_err(|_| NdiscError::TransmitFailed)
+}
+
+/// Send an IPv6 packet to a next hop on the given device
+///
+/// Multicast destinations map directly to a MAC address. Unicast next hops
+/// are resolved through the neighbor cache; on a miss the packet is queued
+/// and a neighbor solicitation is multicast.
+pub fn send_ipv6_packet(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    next_hop: Ipv6Addr,
+    packet: &[u8],
+) -> Result<(), NdiscError> {
+    if next_hop.is_multicast() {
+        return transmit_ipv6(device, next_hop.multicast_mac(), packet);
+    }
+
+    if let Some(mac) = lookup_mac(next_hop) {
+        return transmit_ipv6(device, mac, packet);
+    }
+
+    // Queue the packet until the address is resolved
+    {
+        let mut pending = ND_PENDING.lock();
+        let queue = pending.entry(next_hop).or_default();
+        if queue.len() >= MAX_PENDING_PER_HOST {
+            // Drop the oldest packet to make room
+            let dropped = queue.remove(0);
+            dropped.device.lock().stats().inc_tx_dropped();
+        }
+        queue.push(PendingPacket {
+            device: device.clone(),
+            packet: packet.to_vec(),
+        });
+    }
+
+    send_neighbor_solicitation(device, next_hop)
+}
+
+/// Send packets that were waiting for an address to be resolved
+pub fn flush_pending(ip: Ipv6Addr, mac: MacAddress) {
+    let queue = ND_PENDING.lock().remove(&ip);
+
+    for pending in queue.into_iter().flatten() {
+        // Transmit failures are recorded in the device statistics
+        let _ = transmit_ipv6(&pending.device, mac, &pending.packet);
+    }
+}
+
+/// Build and send a Neighbor Discovery message
+fn send_message(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    dst_mac: MacAddress,
+    header: Icmpv6Header,
+    body: &[u8],
+) -> Result<(), NdiscError> {
+    let message = icmpv6::build_message(src, dst, header, body);
+    let packet = ipv6::build_packet(src, dst, IpProtocol::Icmpv6.as_u8(), ND_HOP_LIMIT, &message)
+        .map_err(|_| NdiscError::BufferTooSmall)?;
+
+    transmit_ipv6(device, dst_mac, &packet)
+}
+
+/// Usable link-local address of a device
+fn link_local_address(name: &str) -> Option<Ipv6Addr> {
+    ipv6::addresses(name)
+        .into_iter()
+        .find(|a| a.addr.is_link_local() && a.state == AddressState::Preferred)
+        .map(|a| a.addr)
+}
+
+/// Multicast a neighbor solicitation for a target address
+pub fn send_neighbor_solicitation(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    target: Ipv6Addr,
+) -> Result<(), NdiscError> {
+    let (name, mac) = {
+        let dev = device.lock();
+        (dev.name().to_string(), dev.mac_address())
+    };
+    let src = link_local_address(&name).ok_or(NdiscError::NoAddress)?;
+
+    let mut body = target.0.to_vec();
+    push_mac_option(&mut body, OPT_SOURCE_LL_ADDR, mac);
+
+    let dst = target.solicited_node();
+    let header = Icmpv6Header::new(Icmpv6Type::NeighborSolicitation, 0, 0);
+    send_message(device, src, dst, dst.multicast_mac(), header, &body)
+}
+
+/// Probe for other users of a tentative address (RFC 4862 section 5.4.2)
+///
+/// Sent from the unspecified address and without a link-layer address.
+pub fn send_dad_probe(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    target: Ipv6Addr,
+) -> Result<(), NdiscError> {
+    if device.lock().is_loopback() {
+        return Ok(());
+    }
+
+    let dst = target.solicited_node();
+    let header = Icmpv6Header::new(Icmpv6Type::NeighborSolicitation, 0, 0);
+    send_message(
+        device,
+        Ipv6Addr::UNSPECIFIED,
+        dst,
+        dst.multicast_mac(),
+        header,
+        &target.0,
+    )
+}
+
+/// Ask routers on the link to advertise themselves
+pub fn send_router_solicitation(device: &Arc<Mutex<dyn NetDevice>>) -> Result<(), NdiscError> {
+    let (name, mac) = {
+        let dev = device.lock();
+        (dev.name().to_string(), dev.mac_address())
+    };
+
+    // The link-layer address may only be given with a real source address
+    let mut body = Vec::new();
+    let src = match link_local_address(&name) {
+        Some(src) => {
+            push_mac_option(&mut body, OPT_SOURCE_LL_ADDR, mac);
+            src
+        }
+        None => Ipv6Addr::UNSPECIFIED,
+    };
+
+    let dst = Ipv6Addr::ALL_ROUTERS;
+    let header = Icmpv6Header::new(Icmpv6Type::RouterSolicitation, 0, 0);
+    send_message(device, src, dst, dst.multicast_mac(), header, &body)
+}
+
+/// Advertise one of our addresses
+fn send_neighbor_advertisement(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    target: Ipv6Addr,
+    dst: Ipv6Addr,
+    dst_mac: MacAddress,
+    solicited: bool,
+) -> Result<(), NdiscError> {
+    let mac = device.lock().mac_address();
+
+    let mut body = target.0.to_vec();
+    push_mac_option(&mut body, OPT_TARGET_LL_ADDR, mac);
+
+    let mut flags = NA_FLAG_OVERRIDE;
+    if solicited {
+        flags |= NA_FLAG_SOLICITED;
+    }
+    let header = Icmpv6Header::new(Icmpv6Type::NeighborAdvertisement, 0, flags);
+    send_message(device, target, dst, dst_mac, header, &body)
+}
+
+/// Process a Neighbor Discovery message
+///
+/// `message` is the complete ICMPv6 message, already checksummed.
+pub fn process_message(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    message: &[u8],
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    hop_limit: u8,
+) -> Result<(), NdiscError> {
+    // Anything that crossed a router is forged (RFC 4861 section 6.1)
+    if hop_limit != ND_HOP_LIMIT {
+        return Err(NdiscError::InvalidHopLimit);
+    }
+
+    let header = Icmpv6Header::parse(message).map_err(|_| NdiscError::TooShort)?;
+    if header.code != 0 {
+        return Err(NdiscError::InvalidMessage);
+    }
+
+    match header.get_type() {
+        Icmpv6Type::NeighborSolicitation => process_solicitation(device, message, src, dst),
+        Icmpv6Type::NeighborAdvertisement => process_advertisement(device, message),
+        Icmpv6Type::RouterAdvertisement => process_router_advertisement(device, message, src),
+        // Hosts ignore router solicitations and redirects are not supported
+        _ => Ok(()),
+    }
+}
+
+/// Target address of a solicitation or advertisement
+fn target_address(message: &[u8]) -> Result<Ipv6Addr, NdiscError> {
+    let mut target = [0u8; 16];
+    target.copy_from_slice(message.get(8..24).ok_or(NdiscError::TooShort)?);
+    let target = Ipv6Addr(target);
+
+    if target.is_multicast() {
+        return Err(NdiscError::InvalidMessage);
+    }
+    Ok(target)
+}
+
+/// Answer a neighbor solicitation for one of our addresses
+fn process_solicitation(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    message: &[u8],
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+) -> Result<(), NdiscError> {
+    let target = target_address(message)?;
+    let options = NdOptions::parse(&message[24..])?;
+    let name = device.lock().name().to_string();
+
+    match ipv6::address_state(&name, target) {
+        // Someone else is probing for or using our tentative address
+        Some(AddressState::Tentative) => {
+            if src.is_unspecified() {
+                ipv6::duplicate_detected(&name, target);
+            }
+            Ok(())
+        }
+        Some(AddressState::Preferred) => {
+            if src.is_unspecified() {
+                // Defend the address against a duplicate address probe
+                if dst.is_multicast() && options.source_mac.is_none() {
+                    let all_nodes = Ipv6Addr::ALL_NODES;
+                    return send_neighbor_advertisement(
+                        device,
+                        target,
+                        all_nodes,
+                        all_nodes.multicast_mac(),
+                        false,
+                    );
+                }
+                return Err(NdiscError::InvalidMessage);
+            }
+
+            // Learn the sender so the reply can be sent directly
+            let mac = match options.source_mac.or_else(|| lookup_mac(src)) {
+                Some(mac) => mac,
+                None => return Err(NdiscError::InvalidMessage),
+            };
+            insert_entry(src, mac);
+            flush_pending(src, mac);
+
+            send_neighbor_advertisement(device, target, src, mac, true)
+        }
+        None => Err(NdiscError::NotForUs),
+    }
+}
+
+/// Learn a neighbor's address from an advertisement
+fn process_advertisement(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    message: &[u8],
+) -> Result<(), NdiscError> {
+    let target = target_address(message)?;
+    let options = NdOptions::parse(&message[24..])?;
+    let name = device.lock().name().to_string();
+
+    if ipv6::address_state(&name, target) == Some(AddressState::Tentative) {
+        ipv6::duplicate_detected(&name, target);
+        return Ok(());
+    }
+
+    if let Some(mac) = options.target_mac {
+        insert_entry(target, mac);
+        flush_pending(target, mac);
+    }
+    Ok(())
+}
+
+/// Learn the default router and prefixes, and autoconfigure addresses
+fn process_router_advertisement(
+    device: &Arc<Mutex<dyn NetDevice>>,
+    message: &[u8],
+    src: Ipv6Addr,
+) -> Result<(), NdiscError> {
+    if !src.is_link_local() || message.len() < 16 {
+        return Err(NdiscError::InvalidMessage);
+    }
+
+    let router_lifetime = u16::from_be_bytes([message[6], message[7]]) as u64;
+    let options = NdOptions::parse(&message[16..])?;
+    let now = crate::time::uptime_ms();
+    let (name, mac) = {
+        let dev = device.lock();
+        (dev.name().to_string(), dev.mac_address())
+    };
+
+    {
+        let mut routers = ROUTERS.lock();
+        routers.retain(|router| router.addr != src);
+        if router_lifetime > 0 {
+            routers.push(Router {
+                addr: src,
+                expires: now + router_lifetime * 1000,
+            });
+        }
+    }
+
+    if let Some(router_mac) = options.source_mac {
+        insert_entry(src, router_mac);
+        flush_pending(src, router_mac);
+    }
+
+    for info in &options.prefixes {
+        // Link-local prefixes are never taken from advertisements
+        if info.prefix.is_link_local() {
+            continue;
+        }
+
+        let expires = lifetime_deadline(now, info.valid_lifetime);
+
+        if info.on_link {
+            let mut prefixes = PREFIXES.lock();
+            prefixes.retain(|p| !(p.prefix == info.prefix && p.prefix_len == info.prefix_len));
+            if info.valid_lifetime > 0 {
+                prefixes.push(OnLinkPrefix {
+                    prefix: info.prefix,
+                    prefix_len: info.prefix_len,
+                    expires,
+                });
+            }
+        }
+
+        // SLAAC needs a 64-bit interface identifier
+        if info.autonomous && info.prefix_len == 64 && info.valid_lifetime > 0 {
+            let addr = Ipv6Addr::from_prefix_and_mac(&info.prefix, mac);
+            let _ = ipv6::add_address(device, addr, 64, true, expires);
+        }
+    }
+
+    ipv6::router_found(&name);
+    Ok(())
+}
+
+/// Absolute expiry of a lifetime in seconds
+fn lifetime_deadline(now: u64, lifetime: u32) -> Option<u64> {
+    (lifetime != INFINITE_LIFETIME).then_some(now + lifetime as u64 * 1000)
+}
+
+/// Neighbor Discovery errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NdiscError {
+    /// Message too short
+    TooShort,
+    /// Buffer too small
+    BufferTooSmall,
+    /// Malformed or unexpected message
+    InvalidMessage,
+    /// Hop limit other than 255
+    InvalidHopLimit,
+    /// Target is not one of our addresses
+    NotForUs,
+    /// Interface has no usable link-local address yet
+    NoAddress,
+    /// Device failed to transmit
+    TransmitFailed,
+}
+
+/// Neighbor Discovery initialized flag
+static NDISC_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Initialize Neighbor Discovery
+pub fn init() {
+    if NDISC_INITIALIZED.load(Ordering::Acquire) {
+        return;
+    }
+
+    // Clear cache
+    clear_cache();
+
+    NDISC_INITIALIZED.store(true, Ordering::Release);
+}
+
+/// Check if Neighbor Discovery is initialized
+pub fn is_initialized() -> bool {
+    NDISC_INITIALIZED.load(Ordering::Acquire)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::loopback;
+
+    #[test]
+    fn test_options_parse() {
+        let mac = MacAddress([0x02, 0, 0, 0, 0, 0x01]);
+        let mut data = Vec::new();
+        push_mac_option(&mut data, OPT_SOURCE_LL_ADDR, mac);
+
+        // Prefix information for 2001:db8::/64, on-link and autonomous
+        let mut prefix = vec![OPT_PREFIX_INFO, 4, 64, 0xC0];
+        prefix.extend_from_slice(&3600u32.to_be_bytes());
+        prefix.extend_from_slice(&1800u32.to_be_bytes());
+        prefix.extend_from_slice(&[0; 4]);
+        prefix.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0).0);
+        data.extend_from_slice(&prefix);
+
+        let options = NdOptions::parse(&data).unwrap();
+        assert_eq!(options.source_mac, Some(mac));
+        assert_eq!(options.target_mac, None);
+        assert_eq!(options.prefixes.len(), 1);
+        assert!(options.prefixes[0].on_link && options.prefixes[0].autonomous);
+        assert_eq!(options.prefixes[0].prefix_len, 64);
+        assert_eq!(options.prefixes[0].valid_lifetime, 3600);
+
+        // Zero-length options are malformed
+        assert_eq!(
+            NdOptions::parse(&[OPT_SOURCE_LL_ADDR, 0]),
+            Err(NdiscError::InvalidMessage)
+        );
+    }
+
+    #[test]
+    fn test_advertisement_learns_neighbor() {
+        loopback::init();
+        let lo = loopback::device().unwrap();
+
+        let target = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0x42);
+        let mac = MacAddress([0x02, 0, 0, 0, 0, 0x42]);
+        let mut body = target.0.to_vec();
+        push_mac_option(&mut body, OPT_TARGET_LL_ADDR, mac);
+        let header = Icmpv6Header::new(Icmpv6Type::NeighborAdvertisement, 0, NA_FLAG_OVERRIDE);
+        let message = icmpv6::build_message(target, Ipv6Addr::ALL_NODES, header, &body);
+
+        // Messages that crossed a router are ignored
+        assert_eq!(
+            process_message(&lo, &message, target, Ipv6Addr::ALL_NODES, 64),
+            Err(NdiscError::InvalidHopLimit)
+        );
+        assert_eq!(lookup_mac(target), None);
+
+        process_message(&lo, &message, target, Ipv6Addr::ALL_NODES, ND_HOP_LIMIT).unwrap();
+        assert_eq!(lookup_mac(target), Some(mac));
+        remove_entry(target);
+    }
+}
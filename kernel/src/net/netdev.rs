@@ -2,6 +2,8 @@
 //!
 //! Network device abstraction layer for device drivers.
 
+use alloc::collections::BTreeMap;
+use alloc::format;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -9,6 +11,7 @@ use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use spin::Mutex;
 
 use super::ethernet::MacAddress;
+use super::packet::{self, Direction};
 
 /// Maximum Transmission Unit (default)
 pub const DEFAULT_MTU: usize = 1500;
@@ -48,6 +51,20 @@ pub enum LinkState {
     Unknown,
 }
 
+impl LinkState {
+    /// Get the state name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkState::Down => "down",
+            LinkState::Up => "up",
+            LinkState::Unknown => "unknown",
+        }
+    }
+}
+
+/// Callback run when a device's link goes up or down
+pub type LinkCallback = fn(&str, LinkState);
+
 /// Network device statistics
 #[derive(Debug, Default)]
 pub struct DeviceStats {
@@ -180,11 +197,20 @@ pub trait NetDevice: Send + Sync {
 
     /// Get device statistics
     fn stats(&self) -> &DeviceStats;
+
+    /// Check if this is a loopback device
+    fn is_loopback(&self) -> bool {
+        false
+    }
 }
 
 /// Network device registry
 pub struct DeviceRegistry {
     devices: Vec<Arc<Mutex<dyn NetDevice>>>,
+    /// Interface indices by device name
+    indices: BTreeMap<String, u32>,
+    /// Index given to the next registered device
+    next_index: u32,
 }
 
 impl DeviceRegistry {
@@ -192,6 +218,8 @@ impl DeviceRegistry {
     const fn new() -> Self {
         Self {
             devices: Vec::new(),
+            indices: BTreeMap::new(),
+            next_index: 1,
         }
     }
 
@@ -208,6 +236,8 @@ impl DeviceRegistry {
         }
 
         self.devices.push(device);
+        self.indices.insert(name, self.next_index);
+        self.next_index += 1;
         Ok(())
     }
 
@@ -220,9 +250,18 @@ impl DeviceRegistry {
             .ok_or(NetDevError::NotFound)?;
 
         self.devices.remove(pos);
+        self.indices.remove(name);
         Ok(())
     }
 
+    /// Pick the first unused name made of a prefix and a number
+    pub fn alloc_name(&self, prefix: &str) -> String {
+        (0..)
+            .map(|n| format!("{}{}", prefix, n))
+            .find(|name| !self.indices.contains_key(name))
+            .unwrap_or_default()
+    }
+
     /// Get device by name
     pub fn get_device(&self, name: &str) -> Option<Arc<Mutex<dyn NetDevice>>> {
         self.devices
@@ -231,6 +270,23 @@ impl DeviceRegistry {
             .cloned()
     }
 
+    /// Get the interface index of a device
+    ///
+    /// Indices start at 1 and are not reused after a device is unregistered.
+    pub fn index_of(&self, name: &str) -> Option<u32> {
+        self.indices.get(name).copied()
+    }
+
+    /// Get device by interface index
+    pub fn get_device_by_index(&self, index: u32) -> Option<Arc<Mutex<dyn NetDevice>>> {
+        let name = self
+            .indices
+            .iter()
+            .find(|(_, &i)| i == index)
+            .map(|(name, _)| name)?;
+        self.get_device(name)
+    }
+
     /// List all devices
     pub fn list_devices(&self) -> Vec<String> {
         self.devices
@@ -243,6 +299,21 @@ impl DeviceRegistry {
     pub fn device_count(&self) -> usize {
         self.devices.len()
     }
+
+    /// Get all registered devices
+    pub fn devices(&self) -> Vec<Arc<Mutex<dyn NetDevice>>> {
+        self.devices.clone()
+    }
+
+    /// Get the default device for outgoing traffic
+    ///
+    /// Returns the first registered device that is not a loopback device.
+    pub fn default_device(&self) -> Option<Arc<Mutex<dyn NetDevice>>> {
+        self.devices
+            .iter()
+            .find(|d| !d.lock().is_loopback())
+            .cloned()
+    }
 }
 
 /// Global device registry
@@ -258,11 +329,29 @@ pub fn unregister_device(name: &str) -> Result<(), NetDevError> {
     DEVICE_REGISTRY.lock().unregister(name)
 }
 
+/// Pick a name for a new device, such as `eth0` for prefix `eth`
+///
+/// Drivers call this before registering, so several devices of the same
+/// kind get distinct names.
+pub fn alloc_name(prefix: &str) -> String {
+    DEVICE_REGISTRY.lock().alloc_name(prefix)
+}
+
 /// Get device by name
 pub fn get_device(name: &str) -> Option<Arc<Mutex<dyn NetDevice>>> {
     DEVICE_REGISTRY.lock().get_device(name)
 }
 
+/// Get the interface index of a device
+pub fn index_of(name: &str) -> Option<u32> {
+    DEVICE_REGISTRY.lock().index_of(name)
+}
+
+/// Get device by interface index
+pub fn get_device_by_index(index: u32) -> Option<Arc<Mutex<dyn NetDevice>>> {
+    DEVICE_REGISTRY.lock().get_device_by_index(index)
+}
+
 /// List all registered devices
 pub fn list_devices() -> Vec<String> {
     DEVICE_REGISTRY.lock().list_devices()
@@ -273,6 +362,47 @@ pub fn device_count() -> usize {
     DEVICE_REGISTRY.lock().device_count()
 }
 
+/// Get all registered devices
+pub fn devices() -> Vec<Arc<Mutex<dyn NetDevice>>> {
+    DEVICE_REGISTRY.lock().devices()
+}
+
+/// Get the default device for outgoing traffic
+pub fn default_device() -> Option<Arc<Mutex<dyn NetDevice>>> {
+    DEVICE_REGISTRY.lock().default_device()
+}
+
+/// Send a frame on a device
+///
+/// Packet sockets and the capture buffer see a copy of every frame sent.
+/// The device is unlocked before the copy is made.
+pub fn transmit(device: &Arc<Mutex<dyn NetDevice>>, frame: &[u8]) -> Result<(), NetDevError> {
+    device.lock().send(frame)?;
+    packet::capture(device, frame, Direction::Outgoing);
+    Ok(())
+}
+
+/// Link change callbacks
+static LINK_CALLBACKS: Mutex<Vec<LinkCallback>> = Mutex::new(Vec::new());
+
+/// Register a callback for link changes on any device
+pub fn register_link_callback(callback: LinkCallback) {
+    LINK_CALLBACKS.lock().push(callback);
+}
+
+/// Report a link change detected by a driver
+///
+/// Callbacks run without the callback list locked, so they may register
+/// further callbacks or look up devices.
+pub fn notify_link_change(name: &str, state: LinkState) {
+    crate::printk::printk(&format!("{}: link {}\n", name, state.as_str()));
+
+    let callbacks = LINK_CALLBACKS.lock().clone();
+    for callback in callbacks {
+        callback(name, state);
+    }
+}
+
 /// Network device subsystem initialized flag
 static NETDEV_INITIALIZED: AtomicBool = AtomicBool::new(false);
 
@@ -316,4 +446,25 @@ mod tests {
         assert_eq!(LinkState::Down, LinkState::Down);
         assert_ne!(LinkState::Up, LinkState::Down);
     }
+
+    #[test]
+    fn test_link_callbacks() {
+        static CHANGES: Mutex<Vec<(String, LinkState)>> = Mutex::new(Vec::new());
+
+        register_link_callback(|name, state| {
+            if name.starts_with("linktest") {
+                CHANGES.lock().push((String::from(name), state));
+            }
+        });
+        notify_link_change("linktest0", LinkState::Down);
+        notify_link_change("linktest0", LinkState::Up);
+
+        assert_eq!(
+            *CHANGES.lock(),
+            [
+                (String::from("linktest0"), LinkState::Down),
+                (String::from("linktest0"), LinkState::Up),
+            ]
+        );
+    }
 }
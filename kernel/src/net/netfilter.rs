@@ -0,0 +1,915 @@
+//! Packet Filter
+//!
+//! Netfilter-style hook points in the IPv4 path. Each hook has a chain of
+//! rules matching on addresses, protocol, TCP/UDP ports, interfaces and
+//! connection state. The first rule that accepts or drops a datagram
+//! decides its fate; the chain policy applies when none does.
+//!
+//! Inbound traffic is denied by default: once initialized, the input
+//! chain accepts loopback traffic, replies to flows started by this host
+//! and DHCP server messages, and drops everything else.
+//!
+//! Forwarded traffic can also have its source translated (see [`nat`]).
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::ops::{BitOr, BitOrAssign};
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use super::ipv4::addr::prefix_mask;
+use super::ipv4::{IpProtocol, Ipv4Addr, Ipv4Header};
+use super::loopback::LOOPBACK_NAME;
+use super::tcp::TcpFlags;
+use crate::fs::filesystems::procfs;
+
+pub mod conntrack;
+pub mod nat;
+
+use conntrack::{ConnState, Tuple};
+
+/// DHCP server port
+const DHCP_SERVER_PORT: u16 = 67;
+/// DHCP client port
+const DHCP_CLIENT_PORT: u16 = 68;
+
+/// Hook points in the IPv4 path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    /// Every received datagram, before the routing decision
+    Prerouting,
+    /// Datagrams addressed to this host
+    Input,
+    /// Datagrams routed through this host
+    Forward,
+    /// Datagrams sent by this host
+    Output,
+    /// Every transmitted datagram, after the routing decision
+    Postrouting,
+}
+
+impl Hook {
+    /// All hooks, in chain order
+    pub const ALL: [Hook; 5] = [
+        Hook::Prerouting,
+        Hook::Input,
+        Hook::Forward,
+        Hook::Output,
+        Hook::Postrouting,
+    ];
+
+    /// Chain name, as shown by iptables
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::Prerouting => "PREROUTING",
+            Hook::Input => "INPUT",
+            Hook::Forward => "FORWARD",
+            Hook::Output => "OUTPUT",
+            Hook::Postrouting => "POSTROUTING",
+        }
+    }
+}
+
+/// Fate of a datagram
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Let the datagram through
+    Accept,
+    /// Discard the datagram silently
+    Drop,
+}
+
+impl Verdict {
+    /// Target name, as shown by iptables
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Accept => "ACCEPT",
+            Verdict::Drop => "DROP",
+        }
+    }
+}
+
+/// Action taken by a matching rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Accept the datagram
+    Accept,
+    /// Drop the datagram
+    Drop,
+    /// Log the datagram and continue with the next rule
+    Log,
+}
+
+impl Action {
+    /// Target name, as shown by iptables
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Accept => "ACCEPT",
+            Action::Drop => "DROP",
+            Action::Log => "LOG",
+        }
+    }
+}
+
+/// Set of connection states matched by a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StateMask(u8);
+
+impl StateMask {
+    /// First packet of a flow
+    pub const NEW: Self = Self(0x01);
+    /// Packet of a flow that has seen replies
+    pub const ESTABLISHED: Self = Self(0x02);
+    /// ICMP error about a tracked flow
+    pub const RELATED: Self = Self(0x04);
+    /// Packet belonging to no flow
+    pub const INVALID: Self = Self(0x08);
+
+    /// No state
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Mask of a single state
+    pub const fn of(state: ConnState) -> Self {
+        match state {
+            ConnState::New => Self::NEW,
+            ConnState::Established => Self::ESTABLISHED,
+            ConnState::Related => Self::RELATED,
+            ConnState::Invalid => Self::INVALID,
+        }
+    }
+
+    /// Check if a state is in the set
+    pub const fn matches(self, state: ConnState) -> bool {
+        self.0 & Self::of(state).0 != 0
+    }
+}
+
+impl BitOr for StateMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for StateMask {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl fmt::Display for StateMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let states = [
+            ConnState::New,
+            ConnState::Related,
+            ConnState::Established,
+            ConnState::Invalid,
+        ];
+        let mut first = true;
+        for state in states.into_iter().filter(|&state| self.matches(state)) {
+            if !first {
+                f.write_str(",")?;
+            }
+            f.write_str(state.as_str())?;
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+/// Fields of a datagram that rules and connection tracking look at
+#[derive(Debug, Clone, Copy)]
+pub struct PacketInfo {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    /// IP protocol number
+    pub protocol: u8,
+    /// TCP or UDP source port
+    pub src_port: Option<u16>,
+    /// TCP or UDP destination port
+    pub dst_port: Option<u16>,
+    /// TCP flags, empty for other protocols
+    pub tcp_flags: TcpFlags,
+    /// ICMP message type
+    pub icmp_type: Option<u8>,
+    /// Flow the datagram belongs to, if it can be tracked
+    pub tuple: Option<Tuple>,
+    /// Flow quoted by an ICMP error
+    pub quoted: Option<Tuple>,
+    /// Datagram length
+    pub len: usize,
+}
+
+impl PacketInfo {
+    /// Extract the fields of an IPv4 datagram
+    ///
+    /// Tolerates a truncated payload, as quoted in ICMP errors. Only the
+    /// first fragment of a datagram carries ports.
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        let header = Ipv4Header::parse(packet).ok()?;
+        let end = (header.total_length() as usize).min(packet.len());
+        let payload = packet.get(header.header_length()..end).unwrap_or(&[]);
+
+        let mut info = Self {
+            src: header.src_addr(),
+            dst: header.dst_addr(),
+            protocol: header.protocol,
+            src_port: None,
+            dst_port: None,
+            tcp_flags: TcpFlags::empty(),
+            icmp_type: None,
+            tuple: None,
+            quoted: None,
+            len: packet.len(),
+        };
+        if header.fragment_offset() != 0 {
+            return Some(info);
+        }
+
+        let tuple = |src_port: u16, dst_port: u16| Tuple {
+            protocol: header.protocol,
+            src: header.src_addr(),
+            dst: header.dst_addr(),
+            src_port,
+            dst_port,
+        };
+        match IpProtocol::from_u8(header.protocol) {
+            IpProtocol::Tcp | IpProtocol::Udp if payload.len() >= 4 => {
+                let src_port = u16::from_be_bytes([payload[0], payload[1]]);
+                let dst_port = u16::from_be_bytes([payload[2], payload[3]]);
+                info.src_port = Some(src_port);
+                info.dst_port = Some(dst_port);
+                info.tuple = Some(tuple(src_port, dst_port));
+                if header.protocol == IpProtocol::Tcp.as_u8() && payload.len() >= 14 {
+                    let bits = u16::from_be_bytes([payload[12], payload[13]]);
+                    info.tcp_flags = TcpFlags::from_bits_truncate(bits);
+                }
+            }
+            IpProtocol::Icmp if !payload.is_empty() => {
+                let icmp_type = payload[0];
+                info.icmp_type = Some(icmp_type);
+                match icmp_type {
+                    // Echo reply and request
+                    0 | 8 if payload.len() >= 6 => {
+                        let id = u16::from_be_bytes([payload[4], payload[5]]);
+                        info.tuple = Some(tuple(id, id));
+                    }
+                    // Destination unreachable, source quench, redirect,
+                    // time exceeded and parameter problem
+                    3 | 4 | 5 | 11 | 12 => {
+                        info.quoted = payload
+                            .get(8..)
+                            .and_then(Self::parse)
+                            .and_then(|inner| inner.tuple);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        Some(info)
+    }
+}
+
+/// Name of an IP protocol, as used by iptables
+fn protocol_name(protocol: u8) -> Option<&'static str> {
+    match IpProtocol::from_u8(protocol) {
+        IpProtocol::Icmp => Some("icmp"),
+        IpProtocol::Tcp => Some("tcp"),
+        IpProtocol::Udp => Some("udp"),
+        IpProtocol::Icmpv6 => Some("ipv6-icmp"),
+        IpProtocol::Unknown => None,
+    }
+}
+
+/// Filter rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    /// Source network
+    pub src: Option<(Ipv4Addr, u8)>,
+    /// Destination network
+    pub dst: Option<(Ipv4Addr, u8)>,
+    /// IP protocol
+    pub protocol: Option<IpProtocol>,
+    /// TCP/UDP source port range
+    pub src_ports: Option<(u16, u16)>,
+    /// TCP/UDP destination port range
+    pub dst_ports: Option<(u16, u16)>,
+    /// Receiving device
+    pub in_device: Option<String>,
+    /// Transmitting device
+    pub out_device: Option<String>,
+    /// Connection states
+    pub states: Option<StateMask>,
+    /// Action taken on a match
+    pub action: Action,
+    /// Datagrams matched
+    pub packets: u64,
+    /// Bytes matched
+    pub bytes: u64,
+}
+
+impl Rule {
+    /// Create a rule matching every datagram
+    pub fn new(action: Action) -> Self {
+        Self {
+            src: None,
+            dst: None,
+            protocol: None,
+            src_ports: None,
+            dst_ports: None,
+            in_device: None,
+            out_device: None,
+            states: None,
+            action,
+            packets: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Match a source network
+    pub fn with_source(mut self, network: Ipv4Addr, prefix_len: u8) -> Self {
+        self.src = Some((network, prefix_len));
+        self
+    }
+
+    /// Match a destination network
+    pub fn with_destination(mut self, network: Ipv4Addr, prefix_len: u8) -> Self {
+        self.dst = Some((network, prefix_len));
+        self
+    }
+
+    /// Match an IP protocol
+    pub fn with_protocol(mut self, protocol: IpProtocol) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Match a TCP/UDP source port
+    pub fn with_src_port(self, port: u16) -> Self {
+        self.with_src_ports(port, port)
+    }
+
+    /// Match a range of TCP/UDP source ports
+    pub fn with_src_ports(mut self, first: u16, last: u16) -> Self {
+        self.src_ports = Some((first, last));
+        self
+    }
+
+    /// Match a TCP/UDP destination port
+    pub fn with_dst_port(self, port: u16) -> Self {
+        self.with_dst_ports(port, port)
+    }
+
+    /// Match a range of TCP/UDP destination ports
+    pub fn with_dst_ports(mut self, first: u16, last: u16) -> Self {
+        self.dst_ports = Some((first, last));
+        self
+    }
+
+    /// Match the receiving device
+    pub fn with_in_device(mut self, device: &str) -> Self {
+        self.in_device = Some(device.to_string());
+        self
+    }
+
+    /// Match the transmitting device
+    pub fn with_out_device(mut self, device: &str) -> Self {
+        self.out_device = Some(device.to_string());
+        self
+    }
+
+    /// Match connection states
+    pub fn with_states(mut self, states: StateMask) -> Self {
+        self.states = Some(states);
+        self
+    }
+
+    /// Check if a datagram matches the rule
+    ///
+    /// Port matches only apply to TCP and UDP datagrams.
+    pub fn matches(
+        &self,
+        packet: &PacketInfo,
+        state: ConnState,
+        in_device: Option<&str>,
+        out_device: Option<&str>,
+    ) -> bool {
+        let in_network = |network: Option<(Ipv4Addr, u8)>, addr: Ipv4Addr| {
+            network.is_none_or(|(network, prefix_len)| {
+                let mask = prefix_mask(prefix_len);
+                addr.as_u32() & mask == network.as_u32() & mask
+            })
+        };
+        let in_range = |range: Option<(u16, u16)>, port: Option<u16>| match range {
+            None => true,
+            Some((first, last)) => port.is_some_and(|port| (first..=last).contains(&port)),
+        };
+        let on_device = |wanted: &Option<String>, device: Option<&str>| {
+            wanted
+                .as_deref()
+                .is_none_or(|wanted| device == Some(wanted))
+        };
+
+        in_network(self.src, packet.src)
+            && in_network(self.dst, packet.dst)
+            && self
+                .protocol
+                .is_none_or(|protocol| protocol.as_u8() == packet.protocol)
+            && in_range(self.src_ports, packet.src_port)
+            && in_range(self.dst_ports, packet.dst_port)
+            && on_device(&self.in_device, in_device)
+            && on_device(&self.out_device, out_device)
+            && self.states.is_none_or(|states| states.matches(state))
+    }
+}
+
+impl fmt::Display for Rule {
+    /// Format like `iptables -S` output, without the chain
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(device) = &self.in_device {
+            write!(f, "-i {} ", device)?;
+        }
+        if let Some(device) = &self.out_device {
+            write!(f, "-o {} ", device)?;
+        }
+        if let Some((network, prefix_len)) = self.src {
+            write!(f, "-s {}/{} ", network, prefix_len)?;
+        }
+        if let Some((network, prefix_len)) = self.dst {
+            write!(f, "-d {}/{} ", network, prefix_len)?;
+        }
+        if let Some(protocol) = self.protocol {
+            match protocol_name(protocol.as_u8()) {
+                Some(name) => write!(f, "-p {} ", name)?,
+                None => write!(f, "-p {} ", protocol.as_u8())?,
+            }
+        }
+        for (option, range) in [("--sport", self.src_ports), ("--dport", self.dst_ports)] {
+            match range {
+                Some((first, last)) if first == last => write!(f, "{} {} ", option, first)?,
+                Some((first, last)) => write!(f, "{} {}:{} ", option, first, last)?,
+                None => {}
+            }
+        }
+        if let Some(states) = self.states {
+            write!(f, "-m state --state {} ", states)?;
+        }
+        write!(f, "-j {}", self.action.as_str())
+    }
+}
+
+/// Netfilter errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetfilterError {
+    /// No rule at the given position
+    NoSuchRule,
+}
+
+/// Rules and policy of one hook
+struct Chain {
+    rules: Vec<Rule>,
+    policy: Verdict,
+}
+
+impl Chain {
+    const fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            policy: Verdict::Accept,
+        }
+    }
+}
+
+/// Rule chains of all hooks
+pub struct RuleTable {
+    chains: [Chain; 5],
+}
+
+impl Default for RuleTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RuleTable {
+    /// Create a table that accepts everything
+    pub const fn new() -> Self {
+        Self {
+            chains: [
+                Chain::new(),
+                Chain::new(),
+                Chain::new(),
+                Chain::new(),
+                Chain::new(),
+            ],
+        }
+    }
+
+    /// Install the default-deny inbound policy
+    ///
+    /// Replaces the input chain with rules accepting loopback traffic,
+    /// replies to tracked flows and DHCP server messages, and a policy
+    /// dropping everything else.
+    pub fn install_defaults(&mut self) {
+        let input = &mut self.chains[Hook::Input as usize];
+        input.rules = alloc::vec![
+            Rule::new(Action::Accept).with_in_device(LOOPBACK_NAME),
+            Rule::new(Action::Accept).with_states(StateMask::ESTABLISHED | StateMask::RELATED),
+            Rule::new(Action::Accept)
+                .with_protocol(IpProtocol::Udp)
+                .with_src_port(DHCP_SERVER_PORT)
+                .with_dst_port(DHCP_CLIENT_PORT),
+        ];
+        input.policy = Verdict::Drop;
+    }
+
+    /// Append a rule to a chain
+    pub fn append(&mut self, hook: Hook, rule: Rule) {
+        self.chains[hook as usize].rules.push(rule);
+    }
+
+    /// Insert a rule at a position in a chain
+    pub fn insert(&mut self, hook: Hook, index: usize, rule: Rule) -> Result<(), NetfilterError> {
+        let rules = &mut self.chains[hook as usize].rules;
+        if index > rules.len() {
+            return Err(NetfilterError::NoSuchRule);
+        }
+        rules.insert(index, rule);
+        Ok(())
+    }
+
+    /// Remove the rule at a position in a chain
+    pub fn delete(&mut self, hook: Hook, index: usize) -> Result<Rule, NetfilterError> {
+        let rules = &mut self.chains[hook as usize].rules;
+        if index >= rules.len() {
+            return Err(NetfilterError::NoSuchRule);
+        }
+        Ok(rules.remove(index))
+    }
+
+    /// Remove all rules of a chain
+    pub fn flush(&mut self, hook: Hook) {
+        self.chains[hook as usize].rules.clear();
+    }
+
+    /// Set the verdict for datagrams no rule decides
+    pub fn set_policy(&mut self, hook: Hook, policy: Verdict) {
+        self.chains[hook as usize].policy = policy;
+    }
+
+    /// Get the policy of a chain
+    pub fn policy(&self, hook: Hook) -> Verdict {
+        self.chains[hook as usize].policy
+    }
+
+    /// Get the rules of a chain
+    pub fn rules(&self, hook: Hook) -> Vec<Rule> {
+        self.chains[hook as usize].rules.clone()
+    }
+
+    /// Run a datagram through a chain
+    ///
+    /// Updates the counters of every matching rule up to the one that
+    /// decides the verdict.
+    pub fn evaluate(
+        &mut self,
+        hook: Hook,
+        packet: &PacketInfo,
+        state: ConnState,
+        in_device: Option<&str>,
+        out_device: Option<&str>,
+    ) -> Verdict {
+        let chain = &mut self.chains[hook as usize];
+        for rule in chain.rules.iter_mut() {
+            if !rule.matches(packet, state, in_device, out_device) {
+                continue;
+            }
+            rule.packets += 1;
+            rule.bytes += packet.len as u64;
+            match rule.action {
+                Action::Accept => return Verdict::Accept,
+                Action::Drop => return Verdict::Drop,
+                Action::Log => log_packet(hook, packet, in_device, out_device),
+            }
+        }
+        chain.policy
+    }
+}
+
+/// Print a datagram to the kernel log
+fn log_packet(hook: Hook, packet: &PacketInfo, in_device: Option<&str>, out_device: Option<&str>) {
+    let mut line = String::new();
+    let _ = write!(
+        line,
+        "netfilter: {} IN={} OUT={} SRC={} DST={} LEN={} PROTO=",
+        hook.name(),
+        in_device.unwrap_or(""),
+        out_device.unwrap_or(""),
+        packet.src,
+        packet.dst,
+        packet.len
+    );
+    let _ = match protocol_name(packet.protocol) {
+        Some(name) => write!(line, "{}", name.to_ascii_uppercase()),
+        None => write!(line, "{}", packet.protocol),
+    };
+    if let (Some(src_port), Some(dst_port)) = (packet.src_port, packet.dst_port) {
+        let _ = write!(line, " SPT={} DPT={}", src_port, dst_port);
+    }
+    if let Some(icmp_type) = packet.icmp_type {
+        let _ = write!(line, " TYPE={}", icmp_type);
+    }
+    line.push('\n');
+    crate::printk::printk(&line);
+}
+
+/// Global rule table
+static RULE_TABLE: Mutex<RuleTable> = Mutex::new(RuleTable::new());
+
+/// Append a rule to a chain
+pub fn append_rule(hook: Hook, rule: Rule) {
+    RULE_TABLE.lock().append(hook, rule);
+}
+
+/// Insert a rule at a position in a chain
+pub fn insert_rule(hook: Hook, index: usize, rule: Rule) -> Result<(), NetfilterError> {
+    RULE_TABLE.lock().insert(hook, index, rule)
+}
+
+/// Remove the rule at a position in a chain
+pub fn delete_rule(hook: Hook, index: usize) -> Result<Rule, NetfilterError> {
+    RULE_TABLE.lock().delete(hook, index)
+}
+
+/// Remove all rules of a chain
+pub fn flush_chain(hook: Hook) {
+    RULE_TABLE.lock().flush(hook);
+}
+
+/// Set the policy of a chain
+pub fn set_policy(hook: Hook, policy: Verdict) {
+    RULE_TABLE.lock().set_policy(hook, policy);
+}
+
+/// Get the policy of a chain
+pub fn policy(hook: Hook) -> Verdict {
+    RULE_TABLE.lock().policy(hook)
+}
+
+/// Get the rules of a chain
+pub fn rules(hook: Hook) -> Vec<Rule> {
+    RULE_TABLE.lock().rules(hook)
+}
+
+/// Run an IPv4 datagram through a hook
+///
+/// Connection tracking follows datagrams at the first hook they reach:
+/// prerouting for received ones and output for ones sent by this host.
+/// Datagrams received on the loopback device were already tracked when
+/// they were sent.
+pub fn filter(
+    hook: Hook,
+    packet: &[u8],
+    in_device: Option<&str>,
+    out_device: Option<&str>,
+) -> Verdict {
+    let Some(info) = PacketInfo::parse(packet) else {
+        return Verdict::Drop;
+    };
+
+    let tracked = match hook {
+        Hook::Prerouting => in_device != Some(LOOPBACK_NAME),
+        Hook::Output => true,
+        _ => false,
+    };
+    let state = if tracked {
+        conntrack::track(&info)
+    } else {
+        conntrack::state(&info)
+    };
+
+    RULE_TABLE
+        .lock()
+        .evaluate(hook, &info, state, in_device, out_device)
+}
+
+/// Read /proc/net/ip_tables
+///
+/// Lists policies and rules like `iptables -S`, with rule counters.
+fn read_rules(_path: &str) -> Result<Vec<u8>, &'static str> {
+    let table = RULE_TABLE.lock();
+    let mut out = String::new();
+    for hook in Hook::ALL {
+        let _ = writeln!(out, "-P {} {}", hook.name(), table.policy(hook).as_str());
+    }
+    for hook in Hook::ALL {
+        for rule in &table.chains[hook as usize].rules {
+            let _ = writeln!(
+                out,
+                "-A {} {} [{}:{}]",
+                hook.name(),
+                rule,
+                rule.packets,
+                rule.bytes
+            );
+        }
+    }
+    Ok(out.into_bytes())
+}
+
+/// Read /proc/net/nf_conntrack
+fn read_conntrack(_path: &str) -> Result<Vec<u8>, &'static str> {
+    let now = crate::time::uptime_ms();
+    let mut out = String::new();
+    for conn in conntrack::connections() {
+        let protocol = conn.original.protocol;
+        let _ = write!(
+            out,
+            "{} {} {} ",
+            protocol_name(protocol).unwrap_or("unknown"),
+            protocol,
+            conn.expires.saturating_sub(now) / 1000
+        );
+        if let Some(state) = conn.tcp_state {
+            let _ = write!(out, "{:?} ", state);
+        }
+        let _ = write!(out, "{} ", conn.original);
+        if !conn.replied {
+            out.push_str("[UNREPLIED] ");
+        }
+        let _ = writeln!(out, "{}", conn.original.reply());
+    }
+    Ok(out.into_bytes())
+}
+
+/// Read /proc/net/nf_nat
+///
+/// Lists the NAT rules like `iptables -t nat -S`, followed by the
+/// translated flows.
+fn read_nat(_path: &str) -> Result<Vec<u8>, &'static str> {
+    let mut out = String::new();
+    for rule in nat::rules() {
+        let _ = writeln!(out, "-A POSTROUTING {} [{}]", rule, rule.packets);
+    }
+    for binding in nat::bindings() {
+        let protocol = binding.original.protocol;
+        let _ = writeln!(
+            out,
+            "{} {} {} -> src={} sport={}",
+            protocol_name(protocol).unwrap_or("unknown"),
+            protocol,
+            binding.original,
+            binding.translated.src,
+            binding.translated.src_port
+        );
+    }
+    Ok(out.into_bytes())
+}
+
+/// Sweep expired flows and the NAT bindings that went with them
+fn gc() {
+    conntrack::gc();
+    nat::gc();
+}
+
+/// Packet filter initialized flag
+static NETFILTER_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Initialize the packet filter
+///
+/// Installs the default-deny inbound policy and starts expiring tracked
+/// flows.
+pub fn init() {
+    if NETFILTER_INITIALIZED.load(Ordering::Acquire) {
+        return;
+    }
+
+    RULE_TABLE.lock().install_defaults();
+
+    if crate::time::timer::create_periodic_timer(conntrack::GC_INTERVAL_MS, gc).is_err() {
+        crate::printk::printk("  Failed to start connection tracking timer\n");
+    }
+
+    let registered = procfs::register_file("/proc/net/ip_tables", read_rules)
+        .and_then(|_| procfs::register_file("/proc/net/nf_conntrack", read_conntrack))
+        .and_then(|_| procfs::register_file("/proc/net/nf_nat", read_nat));
+    if registered.is_err() {
+        crate::printk::printk("  netfilter: /proc interface unavailable\n");
+    }
+
+    NETFILTER_INITIALIZED.store(true, Ordering::Release);
+}
+
+/// Check if the packet filter is initialized
+pub fn is_initialized() -> bool {
+    NETFILTER_INITIALIZED.load(Ordering::Acquire)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipv4::build_packet;
+    use crate::net::udp::build_udp_packet;
+
+    fn udp(src: Ipv4Addr, sport: u16, dst: Ipv4Addr, dport: u16) -> PacketInfo {
+        let mut datagram = [0u8; 16];
+        let len = build_udp_packet(&mut datagram, src, sport, dst, dport, b"data").unwrap();
+        let packet = build_packet(src, dst, IpProtocol::Udp, &datagram[..len]).unwrap();
+        PacketInfo::parse(&packet).unwrap()
+    }
+
+    #[test]
+    fn test_rule_matching() {
+        let rule = Rule::new(Action::Accept)
+            .with_source(Ipv4Addr::new(10, 0, 0, 0), 8)
+            .with_protocol(IpProtocol::Udp)
+            .with_dst_ports(5000, 5010)
+            .with_in_device("eth0");
+        let packet = udp(
+            Ipv4Addr::new(10, 1, 2, 3),
+            1234,
+            Ipv4Addr::new(10, 0, 0, 1),
+            5005,
+        );
+        let new = ConnState::New;
+
+        assert!(rule.matches(&packet, new, Some("eth0"), None));
+        assert!(!rule.matches(&packet, new, Some("eth1"), None));
+        let outside = udp(
+            Ipv4Addr::new(11, 1, 2, 3),
+            1234,
+            Ipv4Addr::new(10, 0, 0, 1),
+            5005,
+        );
+        assert!(!rule.matches(&outside, new, Some("eth0"), None));
+        let other_port = udp(
+            Ipv4Addr::new(10, 1, 2, 3),
+            1234,
+            Ipv4Addr::new(10, 0, 0, 1),
+            5011,
+        );
+        assert!(!rule.matches(&other_port, new, Some("eth0"), None));
+
+        let established = Rule::new(Action::Accept).with_states(StateMask::ESTABLISHED);
+        assert!(!established.matches(&packet, new, None, None));
+        assert!(established.matches(&packet, ConnState::Established, None, None));
+
+        assert_eq!(
+            rule.to_string(),
+            "-i eth0 -s 10.0.0.0/8 -p udp --dport 5000:5010 -j ACCEPT"
+        );
+    }
+
+    #[test]
+    fn test_default_deny_inbound() {
+        let mut table = RuleTable::new();
+        table.install_defaults();
+        let local = Ipv4Addr::new(10, 0, 0, 1);
+        let remote = Ipv4Addr::new(10, 0, 0, 2);
+        let packet = udp(remote, 4000, local, 22);
+
+        let verdict = |table: &mut RuleTable, state, device| {
+            table.evaluate(Hook::Input, &packet, state, Some(device), None)
+        };
+        assert_eq!(verdict(&mut table, ConnState::New, "eth0"), Verdict::Drop);
+        assert_eq!(
+            verdict(&mut table, ConnState::Established, "eth0"),
+            Verdict::Accept
+        );
+        assert_eq!(
+            verdict(&mut table, ConnState::New, LOOPBACK_NAME),
+            Verdict::Accept
+        );
+
+        // Opening a port for new flows
+        table
+            .insert(
+                Hook::Input,
+                0,
+                Rule::new(Action::Accept)
+                    .with_protocol(IpProtocol::Udp)
+                    .with_dst_port(22),
+            )
+            .unwrap();
+        assert_eq!(verdict(&mut table, ConnState::New, "eth0"), Verdict::Accept);
+        assert_eq!(table.rules(Hook::Input)[0].packets, 1);
+
+        assert_eq!(
+            table.delete(Hook::Input, 9),
+            Err(NetfilterError::NoSuchRule)
+        );
+        table.delete(Hook::Input, 0).unwrap();
+        assert_eq!(verdict(&mut table, ConnState::New, "eth0"), Verdict::Drop);
+
+        // Outbound traffic is not restricted
+        assert_eq!(
+            table.evaluate(Hook::Output, &packet, ConnState::New, None, Some("eth0")),
+            Verdict::Accept
+        );
+    }
+}
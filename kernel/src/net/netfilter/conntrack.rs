@@ -0,0 +1,439 @@
+//! Connection Tracking
+//!
+//! Follows flows through the firewall hooks so that rules can match on
+//! connection state. TCP flows step through `TcpState` as handshake, FIN
+//! and RST segments are seen in either direction; UDP and ICMP echo flows
+//! are established once a reply is seen. ICMP errors quoting a tracked
+//! flow are related to it.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+
+use super::PacketInfo;
+use crate::net::ipv4::{IpProtocol, Ipv4Addr};
+use crate::net::tcp::{TcpFlags, TcpState};
+
+/// Most flows tracked at once
+pub const MAX_CONNECTIONS: usize = 4096;
+
+/// Interval between sweeps for expired flows (milliseconds)
+pub const GC_INTERVAL_MS: u64 = 1000;
+
+/// Timeout of a UDP flow without replies (milliseconds)
+pub const UDP_TIMEOUT_MS: u64 = 30_000;
+/// Timeout of a UDP flow that has seen replies (milliseconds)
+pub const UDP_STREAM_TIMEOUT_MS: u64 = 180_000;
+/// Timeout of an ICMP echo flow (milliseconds)
+pub const ICMP_TIMEOUT_MS: u64 = 30_000;
+
+/// Connection state of a packet, as matched by firewall rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnState {
+    /// Starts a flow, or belongs to one without replies yet
+    New,
+    /// Belongs to a flow that has seen traffic in both directions
+    Established,
+    /// ICMP error about a tracked flow
+    Related,
+    /// Matches no flow and cannot start one
+    Invalid,
+}
+
+impl ConnState {
+    /// Name as shown by iptables
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnState::New => "NEW",
+            ConnState::Established => "ESTABLISHED",
+            ConnState::Related => "RELATED",
+            ConnState::Invalid => "INVALID",
+        }
+    }
+}
+
+/// Addresses, ports and protocol identifying one direction of a flow
+///
+/// ICMP echo flows use the echo identifier as both ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Tuple {
+    pub protocol: u8,
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub src_port: u16,
+    pub dst_port: u16,
+}
+
+impl Tuple {
+    /// Tuple of the opposite direction
+    pub fn reply(&self) -> Self {
+        Self {
+            protocol: self.protocol,
+            src: self.dst,
+            dst: self.src,
+            src_port: self.dst_port,
+            dst_port: self.src_port,
+        }
+    }
+}
+
+impl fmt::Display for Tuple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "src={} dst={} sport={} dport={}",
+            self.src, self.dst, self.src_port, self.dst_port
+        )
+    }
+}
+
+/// Tracked flow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connection {
+    /// Direction of the packet that started the flow
+    pub original: Tuple,
+    /// TCP state, seen from the side that started the flow
+    pub tcp_state: Option<TcpState>,
+    /// A packet has been seen in the reply direction
+    pub replied: bool,
+    /// Uptime at which the flow is forgotten (milliseconds)
+    pub expires: u64,
+}
+
+impl Connection {
+    /// Time the flow may stay idle (milliseconds)
+    fn timeout(&self) -> u64 {
+        match self.tcp_state {
+            Some(state) => tcp_timeout(state),
+            None if self.original.protocol == IpProtocol::Icmp.as_u8() => ICMP_TIMEOUT_MS,
+            None if self.replied => UDP_STREAM_TIMEOUT_MS,
+            None => UDP_TIMEOUT_MS,
+        }
+    }
+}
+
+/// Idle timeout of a TCP flow in a state (milliseconds)
+fn tcp_timeout(state: TcpState) -> u64 {
+    match state {
+        TcpState::SynSent | TcpState::SynReceived | TcpState::Listen => 120_000,
+        TcpState::Established => 5 * 24 * 3600 * 1000,
+        TcpState::FinWait1 | TcpState::FinWait2 | TcpState::Closing => 120_000,
+        TcpState::CloseWait => 60_000,
+        TcpState::LastAck => 30_000,
+        TcpState::TimeWait => 120_000,
+        TcpState::Closed => 10_000,
+    }
+}
+
+/// Advance a TCP flow on a segment
+///
+/// `reply` is set for segments travelling opposite to the first SYN.
+fn tcp_transition(state: TcpState, flags: TcpFlags, reply: bool) -> TcpState {
+    if flags.contains(TcpFlags::RST) {
+        return TcpState::Closed;
+    }
+    let syn = flags.contains(TcpFlags::SYN);
+    let ack = flags.contains(TcpFlags::ACK);
+    let fin = flags.contains(TcpFlags::FIN);
+
+    match state {
+        TcpState::SynSent if reply && syn && ack => TcpState::SynReceived,
+        TcpState::SynReceived if !reply && ack && !syn => TcpState::Established,
+        TcpState::Established if fin && reply => TcpState::CloseWait,
+        TcpState::Established if fin => TcpState::FinWait1,
+        TcpState::FinWait1 | TcpState::FinWait2 if fin && reply => TcpState::Closing,
+        TcpState::CloseWait if fin && !reply => TcpState::LastAck,
+        TcpState::Closing | TcpState::LastAck if ack && !fin => TcpState::TimeWait,
+        // A fresh SYN reuses the ports of a finished flow
+        TcpState::Closed | TcpState::TimeWait if syn && !ack && !reply => TcpState::SynSent,
+        state => state,
+    }
+}
+
+/// Table of tracked flows
+pub struct ConnTracker {
+    /// Flows by their original tuple
+    connections: BTreeMap<Tuple, Connection>,
+}
+
+impl Default for ConnTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConnTracker {
+    /// Create an empty table
+    pub const fn new() -> Self {
+        Self {
+            connections: BTreeMap::new(),
+        }
+    }
+
+    /// Find the flow a tuple belongs to
+    ///
+    /// Returns the flow's original tuple and whether `tuple` travels in the
+    /// reply direction.
+    fn find(&self, tuple: &Tuple, now: u64) -> Option<(Tuple, bool)> {
+        let live = |key: &Tuple| {
+            self.connections
+                .get(key)
+                .is_some_and(|conn| conn.expires > now)
+        };
+        if live(tuple) {
+            Some((*tuple, false))
+        } else if live(&tuple.reply()) {
+            Some((tuple.reply(), true))
+        } else {
+            None
+        }
+    }
+
+    /// Classify a packet without changing any flow
+    pub fn state(&self, packet: &PacketInfo, now: u64) -> ConnState {
+        if let Some(quoted) = packet.quoted {
+            return match self.find(&quoted, now) {
+                Some(_) => ConnState::Related,
+                None => ConnState::Invalid,
+            };
+        }
+        let Some(tuple) = packet.tuple else {
+            return ConnState::New;
+        };
+
+        match self.find(&tuple, now) {
+            Some((key, reply)) => {
+                if reply || self.connections[&key].replied {
+                    ConnState::Established
+                } else {
+                    ConnState::New
+                }
+            }
+            None if tuple.protocol == IpProtocol::Tcp.as_u8() && !starts_tcp_flow(packet) => {
+                ConnState::Invalid
+            }
+            None => ConnState::New,
+        }
+    }
+
+    /// Classify a packet and update the flow it belongs to
+    ///
+    /// Starts a flow for the first packet of a TCP handshake, a UDP
+    /// datagram or an ICMP echo request.
+    pub fn track(&mut self, packet: &PacketInfo, now: u64) -> ConnState {
+        let state = self.state(packet, now);
+        let Some(tuple) = packet.tuple.filter(|_| packet.quoted.is_none()) else {
+            return state;
+        };
+        let tcp = tuple.protocol == IpProtocol::Tcp.as_u8();
+
+        match self.find(&tuple, now) {
+            Some((key, reply)) => {
+                let Some(conn) = self.connections.get_mut(&key) else {
+                    return state;
+                };
+                conn.replied |= reply;
+                if let Some(tcp_state) = conn.tcp_state {
+                    conn.tcp_state = Some(tcp_transition(tcp_state, packet.tcp_flags, reply));
+                }
+                conn.expires = now + conn.timeout();
+            }
+            None if state == ConnState::Invalid => {}
+            None => {
+                // Only echo requests start ICMP flows
+                if tuple.protocol == IpProtocol::Icmp.as_u8()
+                    && packet.icmp_type != Some(ICMP_ECHO_REQUEST)
+                {
+                    return state;
+                }
+                if self.connections.len() >= MAX_CONNECTIONS {
+                    self.expire(now);
+                    if self.connections.len() >= MAX_CONNECTIONS {
+                        return ConnState::Invalid;
+                    }
+                }
+                let mut conn = Connection {
+                    original: tuple,
+                    tcp_state: tcp.then_some(TcpState::SynSent),
+                    replied: false,
+                    expires: 0,
+                };
+                conn.expires = now + conn.timeout();
+                self.connections.insert(tuple, conn);
+            }
+        }
+        state
+    }
+
+    /// Check if a flow is tracked, by its original tuple
+    pub fn contains(&self, original: &Tuple) -> bool {
+        self.connections.contains_key(original)
+    }
+
+    /// Forget flows whose timeout has passed
+    pub fn expire(&mut self, now: u64) {
+        self.connections.retain(|_, conn| conn.expires > now);
+    }
+
+    /// Forget all flows
+    pub fn flush(&mut self) {
+        self.connections.clear();
+    }
+
+    /// Get all tracked flows
+    pub fn connections(&self) -> Vec<Connection> {
+        self.connections.values().cloned().collect()
+    }
+
+    /// Get the number of tracked flows
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Check if no flow is tracked
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+}
+
+/// ICMP echo request type
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+/// Check if a TCP segment may open a flow (SYN without ACK)
+fn starts_tcp_flow(packet: &PacketInfo) -> bool {
+    packet.tcp_flags.contains(TcpFlags::SYN) && !packet.tcp_flags.contains(TcpFlags::ACK)
+}
+
+/// Global connection tracking table
+static CONNTRACK: Mutex<ConnTracker> = Mutex::new(ConnTracker::new());
+
+/// Classify a packet and update the flow it belongs to
+pub fn track(packet: &PacketInfo) -> ConnState {
+    CONNTRACK.lock().track(packet, crate::time::uptime_ms())
+}
+
+/// Classify a packet without changing any flow
+pub fn state(packet: &PacketInfo) -> ConnState {
+    CONNTRACK.lock().state(packet, crate::time::uptime_ms())
+}
+
+/// Check if a flow is tracked, by its original tuple
+pub fn is_tracked(original: &Tuple) -> bool {
+    CONNTRACK.lock().contains(original)
+}
+
+/// Forget expired flows
+///
+/// Called periodically from a kernel timer.
+pub fn gc() {
+    CONNTRACK.lock().expire(crate::time::uptime_ms());
+}
+
+/// Forget all flows
+pub fn flush() {
+    CONNTRACK.lock().flush();
+}
+
+/// Get all tracked flows
+pub fn connections() -> Vec<Connection> {
+    CONNTRACK.lock().connections()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipv4::build_packet;
+    use crate::net::udp::build_udp_packet;
+    use alloc::vec;
+
+    fn tcp_segment(src: Ipv4Addr, sport: u16, dst: Ipv4Addr, dport: u16, flags: u16) -> PacketInfo {
+        let mut segment = [0u8; 20];
+        segment[0..2].copy_from_slice(&sport.to_be_bytes());
+        segment[2..4].copy_from_slice(&dport.to_be_bytes());
+        segment[12] = 5 << 4;
+        segment[13] = flags as u8;
+        let packet = build_packet(src, dst, IpProtocol::Tcp, &segment).unwrap();
+        PacketInfo::parse(&packet).unwrap()
+    }
+
+    #[test]
+    fn test_tcp_flow_states() {
+        let client = Ipv4Addr::new(10, 0, 0, 1);
+        let server = Ipv4Addr::new(10, 0, 0, 2);
+        let mut tracker = ConnTracker::new();
+        let mut segment = |from_client: bool, flags: u16| {
+            let packet = if from_client {
+                tcp_segment(client, 40000, server, 80, flags)
+            } else {
+                tcp_segment(server, 80, client, 40000, flags)
+            };
+            tracker.track(&packet, 0)
+        };
+
+        // Mid-stream segments cannot open a flow
+        assert_eq!(segment(true, TcpFlags::ACK), ConnState::Invalid);
+        assert_eq!(segment(true, TcpFlags::SYN), ConnState::New);
+        assert_eq!(
+            segment(false, TcpFlags::SYN | TcpFlags::ACK),
+            ConnState::Established
+        );
+        assert_eq!(segment(true, TcpFlags::ACK), ConnState::Established);
+
+        let key = tcp_segment(client, 40000, server, 80, 0).tuple.unwrap();
+        let state = |tracker: &ConnTracker| tracker.connections[&key].tcp_state;
+        assert_eq!(state(&tracker), Some(TcpState::Established));
+
+        tracker.track(
+            &tcp_segment(server, 80, client, 40000, TcpFlags::FIN | TcpFlags::ACK),
+            0,
+        );
+        assert_eq!(state(&tracker), Some(TcpState::CloseWait));
+        tracker.track(
+            &tcp_segment(client, 40000, server, 80, TcpFlags::FIN | TcpFlags::ACK),
+            0,
+        );
+        assert_eq!(state(&tracker), Some(TcpState::LastAck));
+        tracker.track(&tcp_segment(server, 80, client, 40000, TcpFlags::ACK), 0);
+        assert_eq!(state(&tracker), Some(TcpState::TimeWait));
+
+        // The flow is forgotten once its timeout passes
+        tracker.expire(tcp_timeout(TcpState::TimeWait));
+        assert!(tracker.is_empty());
+    }
+
+    #[test]
+    fn test_udp_and_icmp_error_states() {
+        let local = Ipv4Addr::new(10, 0, 0, 1);
+        let remote = Ipv4Addr::new(10, 0, 0, 53);
+        let mut tracker = ConnTracker::new();
+
+        let mut datagram = [0u8; 16];
+        let len = build_udp_packet(&mut datagram, local, 5353, remote, 53, b"query").unwrap();
+        let query = build_packet(local, remote, IpProtocol::Udp, &datagram[..len]).unwrap();
+        let query = PacketInfo::parse(&query).unwrap();
+        assert_eq!(tracker.track(&query, 0), ConnState::New);
+        assert_eq!(tracker.track(&query, 1), ConnState::New);
+
+        let len = build_udp_packet(&mut datagram, remote, 53, local, 5353, b"reply").unwrap();
+        let reply = build_packet(remote, local, IpProtocol::Udp, &datagram[..len]).unwrap();
+        let reply = PacketInfo::parse(&reply).unwrap();
+        assert_eq!(tracker.track(&reply, 2), ConnState::Established);
+        assert_eq!(tracker.state(&query, 3), ConnState::Established);
+
+        // A port unreachable quoting the query is related to the flow
+        let mut error = |quoted_port: u16| {
+            let len = build_udp_packet(&mut datagram, local, quoted_port, remote, 53, b"").unwrap();
+            let original = build_packet(local, remote, IpProtocol::Udp, &datagram[..len]).unwrap();
+            let mut icmp = vec![3, 3, 0, 0, 0, 0, 0, 0];
+            icmp.extend_from_slice(&original);
+            let packet = build_packet(remote, local, IpProtocol::Icmp, &icmp).unwrap();
+            PacketInfo::parse(&packet).unwrap()
+        };
+        assert_eq!(tracker.track(&error(5353), 3), ConnState::Related);
+        assert_eq!(tracker.track(&error(5354), 3), ConnState::Invalid);
+
+        tracker.expire(2 + UDP_STREAM_TIMEOUT_MS);
+        assert!(tracker.is_empty());
+    }
+}
@@ -0,0 +1,555 @@
+//! Source NAT
+//!
+//! Rewrites the source of forwarded traffic leaving an interface so that
+//! the hosts behind it share one external address, either a fixed one or
+//! the interface's own (masquerading). Each TCP, UDP or ICMP echo flow is
+//! bound to a port of the external address taken from
+//! `NAT_PORT_MIN..=NAT_PORT_MAX`, above the ephemeral ports of local TCP
+//! connections. Replies and ICMP errors about a translated flow are
+//! matched against the bindings and rewritten back before they are
+//! routed.
+//!
+//! Bindings last as long as connection tracking follows their flow.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+
+use super::conntrack::{self, Tuple};
+use super::{NetfilterError, PacketInfo};
+use crate::net::ipv4::addr::{self, prefix_mask};
+use crate::net::ipv4::{calculate_checksum, fill_header_checksum, IpProtocol, Ipv4Addr};
+
+/// First external port handed out
+pub const NAT_PORT_MIN: u16 = 61000;
+/// Last external port handed out
+pub const NAT_PORT_MAX: u16 = 65535;
+
+/// Most flows translated at once
+pub const MAX_BINDINGS: usize = conntrack::MAX_CONNECTIONS;
+
+/// Source address a NAT rule translates to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatTarget {
+    /// Primary address of the outgoing interface, looked up per flow
+    Masquerade,
+    /// Fixed address
+    Address(Ipv4Addr),
+}
+
+/// Source NAT rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NatRule {
+    /// Transmitting device
+    pub out_device: String,
+    /// Source network
+    pub src: Option<(Ipv4Addr, u8)>,
+    /// Address translated to
+    pub target: NatTarget,
+    /// Flows translated
+    pub packets: u64,
+}
+
+impl NatRule {
+    /// Masquerade traffic leaving a device behind its address
+    pub fn masquerade(out_device: &str) -> Self {
+        Self {
+            out_device: out_device.to_string(),
+            src: None,
+            target: NatTarget::Masquerade,
+            packets: 0,
+        }
+    }
+
+    /// Translate traffic leaving a device to a fixed address
+    pub fn snat(out_device: &str, address: Ipv4Addr) -> Self {
+        Self {
+            target: NatTarget::Address(address),
+            ..Self::masquerade(out_device)
+        }
+    }
+
+    /// Match a source network
+    pub fn with_source(mut self, network: Ipv4Addr, prefix_len: u8) -> Self {
+        self.src = Some((network, prefix_len));
+        self
+    }
+
+    /// Check if a datagram leaving a device matches the rule
+    pub fn matches(&self, src: Ipv4Addr, out_device: &str) -> bool {
+        self.out_device == out_device
+            && self.src.is_none_or(|(network, prefix_len)| {
+                let mask = prefix_mask(prefix_len);
+                src.as_u32() & mask == network.as_u32() & mask
+            })
+    }
+}
+
+impl fmt::Display for NatRule {
+    /// Format like `iptables -t nat -S` output, without the chain
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "-o {} ", self.out_device)?;
+        if let Some((network, prefix_len)) = self.src {
+            write!(f, "-s {}/{} ", network, prefix_len)?;
+        }
+        match self.target {
+            NatTarget::Masquerade => f.write_str("-j MASQUERADE"),
+            NatTarget::Address(address) => write!(f, "-j SNAT --to-source {}", address),
+        }
+    }
+}
+
+/// Translation of one flow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Binding {
+    /// Flow as sent by the internal host
+    pub original: Tuple,
+    /// Flow as it leaves this host
+    pub translated: Tuple,
+}
+
+/// NAT errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatError {
+    /// Masqueraded device has no address
+    NoAddress,
+    /// Every external port toward the destination is taken
+    NoPorts,
+    /// Too many flows translated
+    TableFull,
+    /// Datagram matches a rule but its flow cannot be translated
+    Unsupported,
+}
+
+/// Endpoint of a datagram
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Source,
+    Destination,
+}
+
+/// Location of the ports and checksum in a transport header
+struct TransportFields {
+    src_port: usize,
+    dst_port: usize,
+    checksum: usize,
+    /// Checksum covers the IP addresses
+    pseudo_header: bool,
+}
+
+impl TransportFields {
+    /// Fields of a translatable protocol; ICMP echo uses its identifier
+    fn of(protocol: u8) -> Option<Self> {
+        let (src_port, dst_port, checksum, pseudo_header) = match IpProtocol::from_u8(protocol) {
+            IpProtocol::Tcp => (0, 2, 16, true),
+            IpProtocol::Udp => (0, 2, 6, true),
+            IpProtocol::Icmp => (4, 4, 2, false),
+            _ => return None,
+        };
+        Some(Self {
+            src_port,
+            dst_port,
+            checksum,
+            pseudo_header,
+        })
+    }
+}
+
+/// Update an Internet checksum for changed data (RFC 1624)
+fn adjust_checksum(checksum: u16, old: &[u8], new: &[u8]) -> u16 {
+    let mut sum = u32::from(!checksum);
+    for (old, new) in old.chunks_exact(2).zip(new.chunks_exact(2)) {
+        sum += u32::from(!u16::from_be_bytes([old[0], old[1]]));
+        sum += u32::from(u16::from_be_bytes([new[0], new[1]]));
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Rewrite the address and port of one endpoint of a datagram
+///
+/// Updates the IP header checksum and, as far as the datagram holds it,
+/// the transport checksum. `packet` may be the truncated copy quoted in
+/// an ICMP error.
+fn rewrite(packet: &mut [u8], side: Side, address: Ipv4Addr, port: u16) {
+    let header_len = usize::from(packet[0] & 0x0F) * 4;
+    let protocol = packet[9];
+    let at = match side {
+        Side::Source => 12,
+        Side::Destination => 16,
+    };
+    let mut old_address = [0u8; 4];
+    old_address.copy_from_slice(&packet[at..at + 4]);
+    packet[at..at + 4].copy_from_slice(&address.octets());
+    fill_header_checksum(&mut packet[..header_len]);
+
+    let Some(fields) = TransportFields::of(protocol) else {
+        return;
+    };
+    let transport = &mut packet[header_len..];
+    let at = match side {
+        Side::Source => fields.src_port,
+        Side::Destination => fields.dst_port,
+    };
+    if transport.len() < at + 2 {
+        return;
+    }
+    let old_port = [transport[at], transport[at + 1]];
+    transport[at..at + 2].copy_from_slice(&port.to_be_bytes());
+
+    let at = fields.checksum;
+    if transport.len() < at + 2 {
+        return;
+    }
+    let udp = protocol == IpProtocol::Udp.as_u8();
+    let mut checksum = u16::from_be_bytes([transport[at], transport[at + 1]]);
+    // A UDP datagram sent without a checksum stays without one
+    if udp && checksum == 0 {
+        return;
+    }
+    checksum = adjust_checksum(checksum, &old_port, &port.to_be_bytes());
+    if fields.pseudo_header {
+        checksum = adjust_checksum(checksum, &old_address, &address.octets());
+    }
+    if udp && checksum == 0 {
+        checksum = 0xFFFF;
+    }
+    transport[at..at + 2].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// NAT rules and the flows translated by them
+pub struct NatTable {
+    rules: Vec<NatRule>,
+    /// Translated tuples by original tuple
+    bindings: BTreeMap<Tuple, Tuple>,
+    /// Original tuples by the tuple replies to the translated flow carry
+    replies: BTreeMap<Tuple, Tuple>,
+    /// Next external port to try
+    next_port: u16,
+}
+
+impl Default for NatTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NatTable {
+    /// Create a table without rules
+    pub const fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            bindings: BTreeMap::new(),
+            replies: BTreeMap::new(),
+            next_port: NAT_PORT_MIN,
+        }
+    }
+
+    /// Append a rule
+    pub fn append(&mut self, rule: NatRule) {
+        self.rules.push(rule);
+    }
+
+    /// Remove the rule at a position
+    ///
+    /// Flows already translated by the rule keep their bindings.
+    pub fn delete(&mut self, index: usize) -> Result<NatRule, NetfilterError> {
+        if index >= self.rules.len() {
+            return Err(NetfilterError::NoSuchRule);
+        }
+        Ok(self.rules.remove(index))
+    }
+
+    /// Remove all rules
+    pub fn flush(&mut self) {
+        self.rules.clear();
+    }
+
+    /// Get the rules
+    pub fn rules(&self) -> Vec<NatRule> {
+        self.rules.clone()
+    }
+
+    /// Get the translated flows
+    pub fn bindings(&self) -> Vec<Binding> {
+        self.bindings
+            .iter()
+            .map(|(&original, &translated)| Binding {
+                original,
+                translated,
+            })
+            .collect()
+    }
+
+    /// Pick an external port for a flow
+    ///
+    /// Ports are shared between flows to different destinations, as long
+    /// as their replies can be told apart.
+    fn allocate(&mut self, original: &Tuple, external: Ipv4Addr) -> Result<Tuple, NatError> {
+        let icmp = original.protocol == IpProtocol::Icmp.as_u8();
+        let range = u32::from(NAT_PORT_MAX - NAT_PORT_MIN) + 1;
+        let start = u32::from(self.next_port - NAT_PORT_MIN);
+
+        for i in 0..range {
+            let port = NAT_PORT_MIN + ((start + i) % range) as u16;
+            let translated = Tuple {
+                src: external,
+                src_port: port,
+                dst_port: if icmp { port } else { original.dst_port },
+                ..*original
+            };
+            if !self.replies.contains_key(&translated.reply()) {
+                self.next_port = if port == NAT_PORT_MAX {
+                    NAT_PORT_MIN
+                } else {
+                    port + 1
+                };
+                return Ok(translated);
+            }
+        }
+        Err(NatError::NoPorts)
+    }
+
+    /// Translate the source of a datagram leaving `out_device`
+    ///
+    /// Returns whether the datagram was rewritten. Datagrams of flows
+    /// without a binding are bound by the first matching rule.
+    pub fn translate_outbound(
+        &mut self,
+        packet: &mut [u8],
+        out_device: &str,
+    ) -> Result<bool, NatError> {
+        let Some(info) = PacketInfo::parse(packet) else {
+            return Ok(false);
+        };
+        let original = info.tuple.filter(|_| info.quoted.is_none());
+
+        let translated = match original.and_then(|tuple| self.bindings.get(&tuple)) {
+            Some(&translated) => translated,
+            None => {
+                let Some(index) = self
+                    .rules
+                    .iter()
+                    .position(|rule| rule.matches(info.src, out_device))
+                else {
+                    return Ok(false);
+                };
+                let original = original.ok_or(NatError::Unsupported)?;
+                let external = match self.rules[index].target {
+                    NatTarget::Masquerade => {
+                        addr::primary_address(out_device).ok_or(NatError::NoAddress)?
+                    }
+                    NatTarget::Address(address) => address,
+                };
+
+                if self.bindings.len() >= MAX_BINDINGS {
+                    return Err(NatError::TableFull);
+                }
+                let translated = self.allocate(&original, external)?;
+                self.bindings.insert(original, translated);
+                self.replies.insert(translated.reply(), original);
+                self.rules[index].packets += 1;
+                translated
+            }
+        };
+
+        rewrite(packet, Side::Source, translated.src, translated.src_port);
+        Ok(true)
+    }
+
+    /// Translate the destination of a reply to a translated flow
+    ///
+    /// ICMP errors quoting a translated datagram are rewritten along with
+    /// the quote. Returns whether the datagram was rewritten.
+    pub fn translate_inbound(&self, packet: &mut [u8]) -> bool {
+        let Some(info) = PacketInfo::parse(packet) else {
+            return false;
+        };
+
+        if let Some(quoted) = info.quoted {
+            let Some(&original) = self.replies.get(&quoted.reply()) else {
+                return false;
+            };
+            let header_len = usize::from(packet[0] & 0x0F) * 4;
+            packet[16..20].copy_from_slice(&original.src.octets());
+            fill_header_checksum(&mut packet[..header_len]);
+
+            // The quote holds the datagram as this host sent it on
+            let message = &mut packet[header_len..];
+            rewrite(
+                &mut message[8..],
+                Side::Source,
+                original.src,
+                original.src_port,
+            );
+            message[2..4].fill(0);
+            let checksum = calculate_checksum(message);
+            message[2..4].copy_from_slice(&checksum.to_be_bytes());
+            return true;
+        }
+
+        let Some(&original) = info.tuple.and_then(|tuple| self.replies.get(&tuple)) else {
+            return false;
+        };
+        rewrite(packet, Side::Destination, original.src, original.src_port);
+        true
+    }
+
+    /// Forget the bindings of flows that are no longer live
+    pub fn expire(&mut self, live: impl Fn(&Tuple) -> bool) {
+        self.bindings.retain(|original, _| live(original));
+        self.replies.retain(|_, original| live(original));
+    }
+
+    /// Check if no flow is translated
+    pub fn is_empty(&self) -> bool {
+        self.bindings.is_empty()
+    }
+}
+
+/// Global NAT table
+static NAT_TABLE: Mutex<NatTable> = Mutex::new(NatTable::new());
+
+/// Append a rule
+pub fn append_rule(rule: NatRule) {
+    NAT_TABLE.lock().append(rule);
+}
+
+/// Remove the rule at a position
+pub fn delete_rule(index: usize) -> Result<NatRule, NetfilterError> {
+    NAT_TABLE.lock().delete(index)
+}
+
+/// Remove all rules
+pub fn flush_rules() {
+    NAT_TABLE.lock().flush();
+}
+
+/// Get the rules
+pub fn rules() -> Vec<NatRule> {
+    NAT_TABLE.lock().rules()
+}
+
+/// Get the translated flows
+pub fn bindings() -> Vec<Binding> {
+    NAT_TABLE.lock().bindings()
+}
+
+/// Translate the source of a datagram being forwarded out of a device
+pub fn translate_outbound(packet: &mut [u8], out_device: &str) -> Result<bool, NatError> {
+    NAT_TABLE.lock().translate_outbound(packet, out_device)
+}
+
+/// Translate a received datagram back if it belongs to a translated flow
+///
+/// Returns the rewritten copy, or `None` if the datagram is left alone.
+pub fn translate_inbound(packet: &[u8]) -> Option<Vec<u8>> {
+    let table = NAT_TABLE.lock();
+    if table.is_empty() {
+        return None;
+    }
+    let mut translated = packet.to_vec();
+    table
+        .translate_inbound(&mut translated)
+        .then_some(translated)
+}
+
+/// Forget the bindings of flows connection tracking has let go of
+///
+/// Called periodically from a kernel timer, after expired flows have
+/// been swept.
+pub fn gc() {
+    NAT_TABLE.lock().expire(conntrack::is_tracked);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipv4::{build_packet, Ipv4Header};
+    use crate::net::udp::{build_udp_packet, UdpHeader};
+
+    fn udp(src: Ipv4Addr, sport: u16, dst: Ipv4Addr, dport: u16) -> Vec<u8> {
+        let mut datagram = [0u8; 16];
+        let len = build_udp_packet(&mut datagram, src, sport, dst, dport, b"data").unwrap();
+        build_packet(src, dst, IpProtocol::Udp, &datagram[..len]).unwrap()
+    }
+
+    fn checksums_ok(packet: &[u8]) -> bool {
+        let header = Ipv4Header::parse(packet).unwrap();
+        let payload = &packet[Ipv4Header::MIN_SIZE..];
+        header.verify_checksum()
+            && UdpHeader::parse(payload).unwrap().verify_checksum(
+                header.src_addr(),
+                header.dst_addr(),
+                &payload[UdpHeader::SIZE..],
+            )
+    }
+
+    #[test]
+    fn test_checksum_adjust() {
+        let mut data = [0x45, 0x00, 0x12, 0x34, 0xab, 0xcd, 0x00, 0x00];
+        let checksum = calculate_checksum(&data);
+        data[2..4].copy_from_slice(&[0xfe, 0xdc]);
+        assert_eq!(
+            adjust_checksum(checksum, &[0x12, 0x34], &[0xfe, 0xdc]),
+            calculate_checksum(&data)
+        );
+    }
+
+    #[test]
+    fn test_snat_round_trip() {
+        let internal = Ipv4Addr::new(192, 168, 50, 2);
+        let external = Ipv4Addr::new(203, 0, 113, 1);
+        let server = Ipv4Addr::new(198, 51, 100, 7);
+
+        let mut table = NatTable::new();
+        table.append(
+            NatRule::snat("wan0", external).with_source(Ipv4Addr::new(192, 168, 50, 0), 24),
+        );
+
+        // Other devices and sources are left alone
+        let mut packet = udp(internal, 5000, server, 53);
+        assert_eq!(table.translate_outbound(&mut packet, "wan1"), Ok(false));
+        let mut other = udp(Ipv4Addr::new(192, 168, 51, 2), 5000, server, 53);
+        assert_eq!(table.translate_outbound(&mut other, "wan0"), Ok(false));
+
+        assert_eq!(table.translate_outbound(&mut packet, "wan0"), Ok(true));
+        assert!(checksums_ok(&packet));
+        let header = Ipv4Header::parse(&packet).unwrap();
+        assert_eq!(header.src_addr(), external);
+        let port = u16::from_be_bytes([packet[20], packet[21]]);
+        assert_eq!(port, NAT_PORT_MIN);
+
+        // Later datagrams of the flow reuse the binding
+        let mut again = udp(internal, 5000, server, 53);
+        table.translate_outbound(&mut again, "wan0").unwrap();
+        assert_eq!(again[12..], packet[12..]);
+
+        // A second flow to the same server gets another port
+        let mut second = udp(Ipv4Addr::new(192, 168, 50, 3), 5000, server, 53);
+        table.translate_outbound(&mut second, "wan0").unwrap();
+        assert_eq!(
+            u16::from_be_bytes([second[20], second[21]]),
+            NAT_PORT_MIN + 1
+        );
+
+        let mut reply = udp(server, 53, external, port);
+        assert!(table.translate_inbound(&mut reply));
+        assert!(checksums_ok(&reply));
+        assert_eq!(Ipv4Header::parse(&reply).unwrap().dst_addr(), internal);
+        assert_eq!(u16::from_be_bytes([reply[22], reply[23]]), 5000);
+
+        // Traffic to unbound ports is not translated
+        let mut stray = udp(server, 53, external, port + 100);
+        assert!(!table.translate_inbound(&mut stray));
+
+        table.expire(|original| original.src != internal);
+        assert_eq!(table.bindings().len(), 1);
+        let mut reply = udp(server, 53, external, port);
+        assert!(!table.translate_inbound(&mut reply));
+    }
+}
@@ -0,0 +1,504 @@
+//! Packet Sockets
+//!
+//! AF_PACKET sockets receive a copy of every frame sent or received by a
+//! network device, ahead of protocol processing. `SOCK_RAW` sockets see
+//! whole Ethernet frames; `SOCK_DGRAM` sockets see the payload, with the
+//! header described by the `sockaddr_ll` source address. The same tap
+//! feeds the kernel capture buffer in [`super::pcap`].
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+use super::ethernet::{EthernetFrame, EthernetHeader, MacAddress};
+use super::netdev::{self, NetDevError, NetDevice};
+use super::pcap;
+use super::socket::{
+    PollEvents, ReceivedMessage, ShutdownHow, Socket, SocketAddr, SocketAddrLl, SocketError,
+    SocketOption, SocketOptionType, SocketOptions, SocketState, SocketType,
+};
+use crate::process::wait_queue::WaitQueue;
+
+/// Protocol matching frames of every EtherType
+pub const ETH_P_ALL: u16 = 0x0003;
+
+/// ARP hardware type of Ethernet devices
+pub const ARPHRD_ETHER: u16 = 1;
+/// ARP hardware type of loopback devices
+pub const ARPHRD_LOOPBACK: u16 = 772;
+
+/// Classification of a captured frame (`sll_pkttype`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PacketType {
+    /// Addressed to this host
+    Host = 0,
+    /// Link-layer broadcast
+    Broadcast = 1,
+    /// Link-layer multicast
+    Multicast = 2,
+    /// Addressed to another host
+    OtherHost = 3,
+    /// Sent by this host
+    Outgoing = 4,
+}
+
+/// Direction of a frame passing a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Received by the device
+    Incoming,
+    /// Sent by the device
+    Outgoing,
+}
+
+/// Frame queued on a packet socket
+struct Frame {
+    data: Vec<u8>,
+    addr: SocketAddrLl,
+}
+
+/// Receive state of a packet socket
+struct TapQueue {
+    /// EtherType to receive (0 receives nothing)
+    protocol: u16,
+    /// Interface to receive from (0 for all)
+    ifindex: u32,
+    frames: VecDeque<Frame>,
+    bytes: usize,
+    /// Receive buffer size (SO_RCVBUF)
+    capacity: usize,
+    /// Frames dropped on a full queue
+    drops: u64,
+}
+
+/// Tap registered by a packet socket
+struct Tap {
+    queue: Mutex<TapQueue>,
+    wait: Arc<WaitQueue>,
+}
+
+impl Tap {
+    /// Queue a copy of a frame if the socket's filter accepts it
+    fn deliver(&self, frame: &[u8], addr: &SocketAddrLl) {
+        {
+            let mut queue = self.queue.lock();
+            let protocol_matches = match queue.protocol {
+                0 => false,
+                ETH_P_ALL => true,
+                protocol => protocol == addr.protocol,
+            };
+            if !protocol_matches || (queue.ifindex != 0 && queue.ifindex != addr.ifindex) {
+                return;
+            }
+            if queue.bytes + frame.len() > queue.capacity {
+                queue.drops += 1;
+                return;
+            }
+            queue.bytes += frame.len();
+            queue.frames.push_back(Frame {
+                data: frame.to_vec(),
+                addr: *addr,
+            });
+        }
+        self.wait.wake_all();
+    }
+}
+
+/// Taps of all open packet sockets
+static TAPS: Mutex<Vec<Arc<Tap>>> = Mutex::new(Vec::new());
+
+/// Number of registered taps, checked without locking on every frame
+static TAP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// ARP hardware type of a device
+fn hardware_type(device: &dyn NetDevice) -> u16 {
+    if device.is_loopback() {
+        ARPHRD_LOOPBACK
+    } else {
+        ARPHRD_ETHER
+    }
+}
+
+/// Hand a copy of a frame to packet sockets and the capture buffer
+///
+/// Called by the receive path and `netdev::transmit` with the device
+/// unlocked. Frames sent on a loopback device are only seen once, when
+/// they are received.
+pub fn capture(device: &Arc<Mutex<dyn NetDevice>>, frame: &[u8], direction: Direction) {
+    let sockets = TAP_COUNT.load(Ordering::Acquire) > 0;
+    if !sockets && !pcap::is_active() {
+        return;
+    }
+    let Ok(ethernet) = EthernetFrame::new(frame) else {
+        return;
+    };
+
+    let (name, mac, hatype, loopback) = {
+        let dev = device.lock();
+        (
+            String::from(dev.name()),
+            dev.mac_address(),
+            hardware_type(&*dev),
+            dev.is_loopback(),
+        )
+    };
+    if loopback && direction == Direction::Outgoing {
+        return;
+    }
+
+    pcap::record(frame);
+    if !sockets {
+        return;
+    }
+
+    let dst = ethernet.dst_mac();
+    let pkttype = match direction {
+        Direction::Outgoing => PacketType::Outgoing,
+        Direction::Incoming if dst.is_broadcast() => PacketType::Broadcast,
+        Direction::Incoming if dst.is_multicast() => PacketType::Multicast,
+        Direction::Incoming if dst == mac || loopback => PacketType::Host,
+        Direction::Incoming => PacketType::OtherHost,
+    };
+    let addr = SocketAddrLl {
+        protocol: u16::from_be_bytes([frame[12], frame[13]]),
+        ifindex: netdev::index_of(&name).unwrap_or(0),
+        hatype,
+        pkttype: pkttype as u8,
+        addr: ethernet.src_mac(),
+    };
+
+    let taps = TAPS.lock().clone();
+    for tap in taps {
+        tap.deliver(frame, &addr);
+    }
+}
+
+/// AF_PACKET socket
+pub struct PacketSocket {
+    socket_type: SocketType,
+    state: SocketState,
+    options: SocketOptions,
+    tap: Arc<Tap>,
+}
+
+impl PacketSocket {
+    /// Create a packet socket receiving frames of `protocol`
+    ///
+    /// A protocol of 0 receives nothing until one is given to `bind`.
+    /// Requires root, as for CAP_NET_RAW on Linux.
+    pub fn new(socket_type: SocketType, protocol: u16) -> Result<Self, SocketError> {
+        if !matches!(socket_type, SocketType::Raw | SocketType::Dgram) {
+            return Err(SocketError::NotSupported);
+        }
+        if crate::process::sched::current_uid() != 0 {
+            return Err(SocketError::PermissionDenied);
+        }
+
+        let options = SocketOptions::default();
+        let tap = Arc::new(Tap {
+            queue: Mutex::new(TapQueue {
+                protocol,
+                ifindex: 0,
+                frames: VecDeque::new(),
+                bytes: 0,
+                capacity: options.rcvbuf,
+                drops: 0,
+            }),
+            wait: Arc::new(WaitQueue::new()),
+        });
+        TAPS.lock().push(tap.clone());
+        TAP_COUNT.fetch_add(1, Ordering::AcqRel);
+
+        Ok(Self {
+            socket_type,
+            state: SocketState::Closed,
+            options,
+            tap,
+        })
+    }
+
+    /// Get the number of frames dropped because the queue was full
+    pub fn drops(&self) -> u64 {
+        self.tap.queue.lock().drops
+    }
+
+    /// Send a frame, or a payload for `SOCK_DGRAM`, on an interface
+    ///
+    /// Without an address the frame goes out on the bound interface.
+    fn transmit(&self, data: &[u8], addr: Option<SocketAddrLl>) -> Result<usize, SocketError> {
+        let (protocol, ifindex) = {
+            let queue = self.tap.queue.lock();
+            (queue.protocol, queue.ifindex)
+        };
+        let ifindex = addr.map_or(ifindex, |addr| addr.ifindex);
+        if ifindex == 0 {
+            return Err(SocketError::AddrNotAvail);
+        }
+        let device = netdev::get_device_by_index(ifindex).ok_or(SocketError::AddrNotAvail)?;
+        let (src_mac, mtu) = {
+            let dev = device.lock();
+            (dev.mac_address(), dev.mtu())
+        };
+
+        let frame = match self.socket_type {
+            SocketType::Dgram => {
+                let addr = addr.ok_or(SocketError::NotConnected)?;
+                let ethertype = match addr.protocol {
+                    0 => protocol,
+                    ethertype => ethertype,
+                };
+                if ethertype == 0 || ethertype == ETH_P_ALL {
+                    return Err(SocketError::InvalidArg);
+                }
+                let mut frame = vec![0u8; EthernetHeader::SIZE + data.len()];
+                frame[0..6].copy_from_slice(&addr.addr.octets());
+                frame[6..12].copy_from_slice(&src_mac.octets());
+                frame[12..14].copy_from_slice(&ethertype.to_be_bytes());
+                frame[EthernetHeader::SIZE..].copy_from_slice(data);
+                frame
+            }
+            _ if data.len() < EthernetHeader::SIZE => return Err(SocketError::InvalidArg),
+            _ => data.to_vec(),
+        };
+        if frame.len() > EthernetHeader::SIZE + mtu {
+            return Err(SocketError::InvalidArg);
+        }
+
+        netdev::transmit(&device, &frame).map_err(|err| match err {
+            NetDevError::DeviceDown => SocketError::NetUnreachable,
+            NetDevError::WouldBlock | NetDevError::Busy => SocketError::WouldBlock,
+            NetDevError::NoMemory => SocketError::OutOfMemory,
+            _ => SocketError::Other,
+        })?;
+        Ok(data.len())
+    }
+
+    /// Remove the socket's tap
+    fn unregister(&mut self) {
+        let mut taps = TAPS.lock();
+        if let Some(pos) = taps.iter().position(|tap| Arc::ptr_eq(tap, &self.tap)) {
+            taps.remove(pos);
+            TAP_COUNT.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+impl Socket for PacketSocket {
+    fn bind(&mut self, addr: SocketAddr) -> Result<(), SocketError> {
+        let SocketAddr::Packet(addr) = addr else {
+            return Err(SocketError::InvalidArg);
+        };
+        if addr.ifindex != 0 && netdev::get_device_by_index(addr.ifindex).is_none() {
+            return Err(SocketError::AddrNotAvail);
+        }
+
+        let mut queue = self.tap.queue.lock();
+        queue.ifindex = addr.ifindex;
+        if addr.protocol != 0 {
+            queue.protocol = addr.protocol;
+        }
+        self.state = SocketState::Bound;
+        Ok(())
+    }
+
+    fn listen(&mut self, _backlog: u32) -> Result<(), SocketError> {
+        Err(SocketError::NotSupported)
+    }
+
+    fn accept(&mut self) -> Result<Arc<Mutex<dyn Socket>>, SocketError> {
+        Err(SocketError::NotSupported)
+    }
+
+    fn connect(&mut self, _addr: SocketAddr) -> Result<(), SocketError> {
+        Err(SocketError::NotSupported)
+    }
+
+    fn send(&mut self, data: &[u8], _flags: u32) -> Result<usize, SocketError> {
+        self.transmit(data, None)
+    }
+
+    fn recv(&mut self, buffer: &mut [u8], flags: u32) -> Result<usize, SocketError> {
+        self.recvmsg(buffer, flags).map(|message| message.len)
+    }
+
+    fn sendto(&mut self, data: &[u8], addr: SocketAddr, _flags: u32) -> Result<usize, SocketError> {
+        let SocketAddr::Packet(addr) = addr else {
+            return Err(SocketError::InvalidArg);
+        };
+        self.transmit(data, Some(addr))
+    }
+
+    fn recvfrom(
+        &mut self,
+        buffer: &mut [u8],
+        flags: u32,
+    ) -> Result<(usize, SocketAddr), SocketError> {
+        let message = self.recvmsg(buffer, flags)?;
+        let addr = message.addr.ok_or(SocketError::Other)?;
+        Ok((message.len, addr))
+    }
+
+    fn shutdown(&mut self, _how: ShutdownHow) -> Result<(), SocketError> {
+        Err(SocketError::NotSupported)
+    }
+
+    fn close(&mut self) -> Result<(), SocketError> {
+        self.unregister();
+        let mut queue = self.tap.queue.lock();
+        queue.frames.clear();
+        queue.bytes = 0;
+        self.state = SocketState::Closed;
+        Ok(())
+    }
+
+    fn state(&self) -> SocketState {
+        self.state
+    }
+
+    fn setsockopt(&mut self, option: SocketOption) -> Result<(), SocketError> {
+        self.options.set(option)?;
+        self.tap.queue.lock().capacity = self.options.rcvbuf;
+        Ok(())
+    }
+
+    fn getsockopt(&self, option: SocketOptionType) -> Result<SocketOption, SocketError> {
+        self.options.get(option)
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        let (protocol, ifindex) = {
+            let queue = self.tap.queue.lock();
+            (queue.protocol, queue.ifindex)
+        };
+        let device = netdev::get_device_by_index(ifindex);
+        let (hatype, addr) = device.map_or((0, MacAddress::ZERO), |device| {
+            let dev = device.lock();
+            (hardware_type(&*dev), dev.mac_address())
+        });
+        Some(SocketAddr::Packet(SocketAddrLl {
+            protocol,
+            ifindex,
+            hatype,
+            pkttype: PacketType::Host as u8,
+            addr,
+        }))
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    fn options(&self) -> SocketOptions {
+        self.options
+    }
+
+    fn poll(&self) -> PollEvents {
+        let mut events = PollEvents::OUT;
+        if !self.tap.queue.lock().frames.is_empty() {
+            events |= PollEvents::IN;
+        }
+        events
+    }
+
+    fn wait_queue(&self) -> Arc<WaitQueue> {
+        self.tap.wait.clone()
+    }
+
+    fn recvmsg(&mut self, buffer: &mut [u8], _flags: u32) -> Result<ReceivedMessage, SocketError> {
+        let frame = {
+            let mut queue = self.tap.queue.lock();
+            let frame = queue.frames.pop_front().ok_or(SocketError::WouldBlock)?;
+            queue.bytes -= frame.data.len();
+            frame
+        };
+
+        let data = match self.socket_type {
+            SocketType::Dgram => &frame.data[EthernetHeader::SIZE..],
+            _ => &frame.data[..],
+        };
+        let len = data.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&data[..len]);
+
+        Ok(ReceivedMessage {
+            len,
+            addr: Some(SocketAddr::Packet(frame.addr)),
+            control: Vec::new(),
+            truncated: data.len() > len,
+        })
+    }
+}
+
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::loopback;
+    use crate::net::rx;
+    use crate::net::socket::{self, SocketAddrV4, SocketDomain, SocketProtocol, MSG_DONTWAIT};
+
+    #[test]
+    fn test_packet_socket_sees_loopback_frames() {
+        loopback::init();
+        let lo = loopback::device().unwrap();
+        let ifindex = netdev::index_of("lo").unwrap();
+        assert!(netdev::get_device_by_index(ifindex).is_some());
+
+        let raw = socket::socket(
+            SocketDomain::Packet,
+            SocketType::Raw,
+            SocketProtocol::Packet(0x0800),
+        )
+        .unwrap();
+        socket::bind(
+            raw,
+            SocketAddr::Packet(SocketAddrLl {
+                protocol: 0,
+                ifindex,
+                hatype: 0,
+                pkttype: 0,
+                addr: MacAddress::ZERO,
+            }),
+        )
+        .unwrap();
+
+        let local = SocketAddrV4 {
+            ip: [127, 0, 0, 1],
+            port: 9605,
+        };
+        let udp =
+            socket::socket(SocketDomain::Inet, SocketType::Dgram, SocketProtocol::Udp).unwrap();
+        socket::bind(udp, SocketAddr::V4(local)).unwrap();
+        socket::sendto(udp, b"captured", SocketAddr::V4(local), 0).unwrap();
+        while rx::poll_device(&lo) > 0 {}
+
+        // Other traffic on lo may be queued too; find our datagram
+        let mut buffer = [0u8; 256];
+        let mut found = false;
+        while let Ok((len, from)) = socket::recvfrom(raw, &mut buffer, MSG_DONTWAIT) {
+            let SocketAddr::Packet(from) = from else {
+                panic!("expected a link-layer address");
+            };
+            assert_eq!(from.protocol, 0x0800);
+            assert_eq!(from.ifindex, ifindex);
+            assert_eq!(from.hatype, ARPHRD_LOOPBACK);
+            assert_eq!(&buffer[12..14], &[0x08, 0x00]);
+            if buffer[..len].ends_with(b"captured") {
+                found = true;
+            }
+        }
+        assert!(found);
+
+        socket::close_socket(udp).unwrap();
+        socket::close_socket(raw).unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+//! Packet Capture Buffer
+//!
+//! Keeps the most recent frames seen by network devices in a kernel ring
+//! buffer and dumps them as a libpcap file, which Wireshark and tcpdump
+//! read directly. The dump is available from `/proc/net/pcap`, and the
+//! serial driver can stream it to a host-side file.
+//!
+//! Capture starts at boot with the `pcap` or `pcap=<size>` parameter, or
+//! at run time with [`start`].
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use crate::fs::filesystems::procfs;
+
+/// Default capture buffer size (bytes of frame data)
+pub const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Default number of bytes kept of each frame
+pub const DEFAULT_SNAPLEN: usize = 65535;
+
+/// libpcap file magic (microsecond timestamps)
+pub const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+
+/// libpcap file format version
+pub const PCAP_VERSION_MAJOR: u16 = 2;
+pub const PCAP_VERSION_MINOR: u16 = 4;
+
+/// Link type of Ethernet captures
+pub const LINKTYPE_ETHERNET: u32 = 1;
+
+/// Size of the file header
+pub const FILE_HEADER_SIZE: usize = 24;
+
+/// Size of each record header
+pub const RECORD_HEADER_SIZE: usize = 16;
+
+/// Captured frame
+struct Record {
+    /// Capture time (uptime in milliseconds)
+    timestamp_ms: u64,
+    /// Frame data, cut to the snapshot length
+    data: Vec<u8>,
+    /// Length of the frame on the wire
+    orig_len: usize,
+}
+
+/// Ring buffer of captured frames
+struct CaptureBuffer {
+    records: VecDeque<Record>,
+    /// Bytes of frame data held
+    bytes: usize,
+    /// Limit on `bytes`; the oldest frames are dropped to stay under it
+    limit: usize,
+    snaplen: usize,
+    /// Frames dropped to make room
+    dropped: u64,
+}
+
+impl CaptureBuffer {
+    const fn new() -> Self {
+        Self {
+            records: VecDeque::new(),
+            bytes: 0,
+            limit: DEFAULT_BUFFER_SIZE,
+            snaplen: DEFAULT_SNAPLEN,
+            dropped: 0,
+        }
+    }
+
+    /// Add a frame, evicting the oldest ones if the buffer is full
+    fn push(&mut self, frame: &[u8], timestamp_ms: u64) {
+        let data = &frame[..frame.len().min(self.snaplen)];
+        if data.len() > self.limit {
+            self.dropped += 1;
+            return;
+        }
+        while self.bytes + data.len() > self.limit {
+            let Some(oldest) = self.records.pop_front() else {
+                break;
+            };
+            self.bytes -= oldest.data.len();
+            self.dropped += 1;
+        }
+
+        self.bytes += data.len();
+        self.records.push_back(Record {
+            timestamp_ms,
+            data: data.to_vec(),
+            orig_len: frame.len(),
+        });
+    }
+
+    fn clear(&mut self) {
+        self.records.clear();
+        self.bytes = 0;
+        self.dropped = 0;
+    }
+
+    /// Encode the buffer as a libpcap file
+    fn to_pcap(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(
+            FILE_HEADER_SIZE + self.bytes + self.records.len() * RECORD_HEADER_SIZE,
+        );
+        out.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        out.extend_from_slice(&PCAP_VERSION_MAJOR.to_le_bytes());
+        out.extend_from_slice(&PCAP_VERSION_MINOR.to_le_bytes());
+        // Timestamps are uptimes: no zone offset or accuracy given
+        out.extend_from_slice(&0i32.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&(self.snaplen as u32).to_le_bytes());
+        out.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+
+        for record in &self.records {
+            let seconds = (record.timestamp_ms / 1000) as u32;
+            let micros = ((record.timestamp_ms % 1000) * 1000) as u32;
+            out.extend_from_slice(&seconds.to_le_bytes());
+            out.extend_from_slice(&micros.to_le_bytes());
+            out.extend_from_slice(&(record.data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(record.orig_len as u32).to_le_bytes());
+            out.extend_from_slice(&record.data);
+        }
+        out
+    }
+}
+
+/// Capture statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureStats {
+    /// Frames held in the buffer
+    pub frames: usize,
+    /// Bytes of frame data held
+    pub bytes: usize,
+    /// Frames dropped to make room
+    pub dropped: u64,
+}
+
+/// Global capture buffer
+static CAPTURE: Mutex<CaptureBuffer> = Mutex::new(CaptureBuffer::new());
+
+/// Capture enabled flag, checked on every frame
+static CAPTURE_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Start capturing frames
+///
+/// A `buffer_size` or `snaplen` of 0 selects the default. Frames already
+/// in the buffer are kept.
+pub fn start(buffer_size: usize, snaplen: usize) {
+    let mut capture = CAPTURE.lock();
+    capture.limit = if buffer_size == 0 {
+        DEFAULT_BUFFER_SIZE
+    } else {
+        buffer_size
+    };
+    capture.snaplen = if snaplen == 0 {
+        DEFAULT_SNAPLEN
+    } else {
+        snaplen
+    };
+    CAPTURE_ACTIVE.store(true, Ordering::Release);
+}
+
+/// Stop capturing frames
+///
+/// The buffer keeps its contents until it is cleared.
+pub fn stop() {
+    CAPTURE_ACTIVE.store(false, Ordering::Release);
+}
+
+/// Check if frames are being captured
+pub fn is_active() -> bool {
+    CAPTURE_ACTIVE.load(Ordering::Acquire)
+}
+
+/// Discard all captured frames
+pub fn clear() {
+    CAPTURE.lock().clear();
+}
+
+/// Add a frame to the capture buffer
+///
+/// Called from the packet tap for every frame sent or received.
+pub fn record(frame: &[u8]) {
+    if !is_active() {
+        return;
+    }
+    let now = crate::time::uptime_ms();
+    CAPTURE.lock().push(frame, now);
+}
+
+/// Get capture statistics
+pub fn stats() -> CaptureStats {
+    let capture = CAPTURE.lock();
+    CaptureStats {
+        frames: capture.records.len(),
+        bytes: capture.bytes,
+        dropped: capture.dropped,
+    }
+}
+
+/// Get the captured frames as a libpcap file
+pub fn dump() -> Vec<u8> {
+    CAPTURE.lock().to_pcap()
+}
+
+/// Read /proc/net/pcap
+fn read_pcap(_path: &str) -> Result<Vec<u8>, &'static str> {
+    Ok(dump())
+}
+
+/// Capture buffer initialized flag
+static PCAP_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Initialize the capture buffer
+///
+/// Starts capturing if requested on the command line.
+pub fn init() {
+    if PCAP_INITIALIZED.load(Ordering::Acquire) {
+        return;
+    }
+
+    if let Some(size) = crate::cmdline::pcap_buffer_size() {
+        start(size as usize, 0);
+    }
+
+    if procfs::register_file("/proc/net/pcap", read_pcap).is_err() {
+        crate::printk::printk("  pcap: /proc interface unavailable\n");
+    }
+
+    PCAP_INITIALIZED.store(true, Ordering::Release);
+}
+
+/// Check if the capture buffer is initialized
+pub fn is_initialized() -> bool {
+    PCAP_INITIALIZED.load(Ordering::Acquire)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pcap_file_format() {
+        let mut capture = CaptureBuffer::new();
+        capture.snaplen = 16;
+        capture.push(&[0xaa; 20], 1_500);
+        capture.push(&[0xbb; 10], 2_001);
+
+        let file = capture.to_pcap();
+        assert_eq!(
+            file.len(),
+            FILE_HEADER_SIZE + 2 * RECORD_HEADER_SIZE + 16 + 10
+        );
+        assert_eq!(&file[0..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+        assert_eq!(u16::from_le_bytes([file[4], file[5]]), 2);
+        assert_eq!(u16::from_le_bytes([file[6], file[7]]), 4);
+        assert_eq!(u32::from_le_bytes(file[16..20].try_into().unwrap()), 16);
+        assert_eq!(u32::from_le_bytes(file[20..24].try_into().unwrap()), 1);
+
+        // First record: 1.5s, cut to the snapshot length
+        let record = &file[FILE_HEADER_SIZE..];
+        let field = |i: usize| u32::from_le_bytes(record[i * 4..i * 4 + 4].try_into().unwrap());
+        assert_eq!((field(0), field(1)), (1, 500_000));
+        assert_eq!((field(2), field(3)), (16, 20));
+        assert_eq!(
+            &record[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + 16],
+            &[0xaa; 16]
+        );
+    }
+
+    #[test]
+    fn test_capture_ring_eviction() {
+        let mut capture = CaptureBuffer::new();
+        capture.limit = 100;
+        for i in 0..5u8 {
+            capture.push(&[i; 40], u64::from(i));
+        }
+
+        // Only the two newest frames fit
+        assert_eq!(capture.records.len(), 2);
+        assert_eq!(capture.bytes, 80);
+        assert_eq!(capture.dropped, 3);
+        assert_eq!(capture.records[0].data[0], 3);
+
+        // Frames larger than the whole buffer are dropped
+        capture.push(&[9; 200], 10);
+        assert_eq!(capture.records.len(), 2);
+        assert_eq!(capture.dropped, 4);
+
+        capture.clear();
+        assert_eq!(capture.to_pcap().len(), FILE_HEADER_SIZE);
+    }
+}
@@ -1,13 +1,31 @@
 //! Socket Layer
 //!
 //! BSD socket API implementation for Rinux
+//!
+//! Operations that cannot complete put the calling task to sleep on the
+//! socket's wait queue until the protocol signals progress, unless the
+//! socket is non-blocking or `MSG_DONTWAIT` is given.
 
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::ops::{BitOr, BitOrAssign};
 use spin::Mutex;
 
-use super::tcp::TcpSocket as NetTcpSocket;
+use super::ethernet::MacAddress;
+use super::icmp::IcmpSocket;
+use super::ip::IpEndpoint;
+use super::ipv4::Ipv4Addr;
+use super::packet::PacketSocket;
+use super::tcp::congestion::CongestionAlgorithm;
+use super::tcp::{self, TcpSocket as NetTcpSocket};
 use super::udp::UdpSocket as NetUdpSocket;
+use super::unix::UnixSocket;
+use crate::fs::fd::{FdEntry, InFlightFds};
+use crate::process::wait_queue::{self, WaitQueue};
+use crate::types::Pid;
+
+/// Don't block this call, as if the socket were non-blocking
+pub const MSG_DONTWAIT: u32 = 0x40;
 
 /// Socket domain (address family)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -20,6 +38,8 @@ pub enum SocketDomain {
     Unix,
     /// Netlink
     Netlink,
+    /// Link-layer packet sockets
+    Packet,
 }
 
 /// Socket type
@@ -48,6 +68,8 @@ pub enum SocketProtocol {
     Icmp,
     /// Raw IP
     Raw,
+    /// EtherType received by a packet socket
+    Packet(u16),
 }
 
 /// Socket address
@@ -59,6 +81,8 @@ pub enum SocketAddr {
     V6(SocketAddrV6),
     /// Unix domain socket address
     Unix(SocketAddrUnix),
+    /// Link-layer socket address
+    Packet(SocketAddrLl),
 }
 
 /// IPv4 socket address
@@ -83,6 +107,21 @@ pub struct SocketAddrUnix {
     pub path: alloc::string::String,
 }
 
+/// Link-layer socket address (`sockaddr_ll`)
+#[derive(Debug, Clone, Copy)]
+pub struct SocketAddrLl {
+    /// EtherType
+    pub protocol: u16,
+    /// Interface index (0 for any interface)
+    pub ifindex: u32,
+    /// ARP hardware type
+    pub hatype: u16,
+    /// Packet type
+    pub pkttype: u8,
+    /// Hardware address
+    pub addr: MacAddress,
+}
+
 /// Socket state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SocketState {
@@ -119,6 +158,10 @@ pub struct SocketOptions {
     pub rcvtimeo: Option<u64>,
     /// Send timeout (milliseconds)
     pub sndtimeo: Option<u64>,
+    /// Non-blocking mode (O_NONBLOCK)
+    pub nonblocking: bool,
+    /// Receive sender credentials (SO_PASSCRED)
+    pub passcred: bool,
 }
 
 impl Default for SocketOptions {
@@ -132,10 +175,162 @@ impl Default for SocketOptions {
             sndbuf: 65536,
             rcvtimeo: None,
             sndtimeo: None,
+            nonblocking: false,
+            passcred: false,
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Set a generic socket option
+    ///
+    /// A zero timeout means waiting forever, as on Linux.
+    pub fn set(&mut self, option: SocketOption) -> Result<(), SocketError> {
+        match option {
+            SocketOption::ReuseAddr(value) => self.reuse_addr = value,
+            SocketOption::ReusePort(value) => self.reuse_port = value,
+            SocketOption::KeepAlive(value) => self.keep_alive = value,
+            SocketOption::Linger(value) => self.linger = value,
+            SocketOption::RcvBuf(size) => self.rcvbuf = size,
+            SocketOption::SndBuf(size) => self.sndbuf = size,
+            SocketOption::RcvTimeo(timeout) => self.rcvtimeo = timeout.filter(|&ms| ms > 0),
+            SocketOption::SndTimeo(timeout) => self.sndtimeo = timeout.filter(|&ms| ms > 0),
+            SocketOption::NonBlocking(value) => self.nonblocking = value,
+            SocketOption::PassCred(value) => self.passcred = value,
+            _ => return Err(SocketError::NotSupported),
+        }
+        Ok(())
+    }
+
+    /// Get a generic socket option
+    pub fn get(&self, option: SocketOptionType) -> Result<SocketOption, SocketError> {
+        Ok(match option {
+            SocketOptionType::ReuseAddr => SocketOption::ReuseAddr(self.reuse_addr),
+            SocketOptionType::ReusePort => SocketOption::ReusePort(self.reuse_port),
+            SocketOptionType::KeepAlive => SocketOption::KeepAlive(self.keep_alive),
+            SocketOptionType::Linger => SocketOption::Linger(self.linger),
+            SocketOptionType::RcvBuf => SocketOption::RcvBuf(self.rcvbuf),
+            SocketOptionType::SndBuf => SocketOption::SndBuf(self.sndbuf),
+            SocketOptionType::RcvTimeo => SocketOption::RcvTimeo(self.rcvtimeo),
+            SocketOptionType::SndTimeo => SocketOption::SndTimeo(self.sndtimeo),
+            SocketOptionType::NonBlocking => SocketOption::NonBlocking(self.nonblocking),
+            SocketOptionType::PassCred => SocketOption::PassCred(self.passcred),
+            _ => return Err(SocketError::NotSupported),
+        })
+    }
+}
+
+/// Socket readiness events (Linux `poll` bits)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PollEvents(u16);
+
+impl PollEvents {
+    /// Data can be read without blocking
+    pub const IN: Self = Self(0x0001);
+    /// Urgent data can be read
+    pub const PRI: Self = Self(0x0002);
+    /// Data can be written without blocking
+    pub const OUT: Self = Self(0x0004);
+    /// Error pending (always reported)
+    pub const ERR: Self = Self(0x0008);
+    /// Connection hung up (always reported)
+    pub const HUP: Self = Self(0x0010);
+    /// Invalid file descriptor (always reported)
+    pub const NVAL: Self = Self(0x0020);
+    /// Peer shut down its sending side
+    pub const RDHUP: Self = Self(0x2000);
+
+    /// No events
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Create from raw bits, dropping unknown ones
+    pub const fn from_bits_truncate(bits: u16) -> Self {
+        Self(bits & 0x203F)
+    }
+
+    /// Get raw bits
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+
+    /// Check if no event is set
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Check if all events of `other` are set
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Check if any event of `other` is set
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Events set in both
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl BitOr for PollEvents {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for PollEvents {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Process credentials (`struct ucred`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Credentials {
+    pub pid: Pid,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Credentials {
+    /// Credentials of the calling task
+    pub fn current() -> Self {
+        use crate::process::sched;
+        Self {
+            pid: sched::current_pid().unwrap_or(0),
+            uid: sched::current_uid(),
+            gid: sched::current_gid(),
         }
     }
 }
 
+/// Ancillary data of a message
+#[derive(Clone)]
+pub enum ControlMessage {
+    /// Sender credentials (SCM_CREDENTIALS)
+    Credentials(Credentials),
+    /// Open file descriptors (SCM_RIGHTS)
+    Rights(InFlightFds),
+}
+
+/// Message returned by `recvmsg`
+pub struct ReceivedMessage {
+    /// Bytes stored in the buffer
+    pub len: usize,
+    /// Source address, if the protocol reports one
+    pub addr: Option<SocketAddr>,
+    /// Ancillary data
+    pub control: Vec<ControlMessage>,
+    /// The datagram was longer than the buffer (MSG_TRUNC)
+    pub truncated: bool,
+}
+
 /// Socket trait
 pub trait Socket: Send + Sync {
     /// Bind socket to address
@@ -186,6 +381,62 @@ pub trait Socket: Send + Sync {
 
     /// Get peer address
     fn peer_addr(&self) -> Option<SocketAddr>;
+
+    /// Get generic socket options (timeouts, non-blocking mode)
+    fn options(&self) -> SocketOptions;
+
+    /// Get current readiness
+    fn poll(&self) -> PollEvents;
+
+    /// Get the wait queue woken when readiness may have changed
+    fn wait_queue(&self) -> Arc<WaitQueue>;
+
+    /// Take the pending asynchronous error (SO_ERROR)
+    fn take_error(&mut self) -> Option<SocketError> {
+        None
+    }
+
+    /// Descriptors in flight in messages queued on this socket
+    fn queued_rights(&self) -> Vec<FdEntry> {
+        Vec::new()
+    }
+
+    /// Take the descriptors in flight out of the queued messages
+    fn take_queued_rights(&mut self) -> Vec<InFlightFds> {
+        Vec::new()
+    }
+
+    /// Send data with ancillary data
+    fn sendmsg(
+        &mut self,
+        data: &[u8],
+        addr: Option<SocketAddr>,
+        control: &[ControlMessage],
+        flags: u32,
+    ) -> Result<usize, SocketError> {
+        if !control.is_empty() {
+            return Err(SocketError::NotSupported);
+        }
+        match addr {
+            Some(addr) => self.sendto(data, addr, flags),
+            None => self.send(data, flags),
+        }
+    }
+
+    /// Receive data with source address and ancillary data
+    fn recvmsg(&mut self, buffer: &mut [u8], flags: u32) -> Result<ReceivedMessage, SocketError> {
+        let (len, addr) = match self.recvfrom(buffer, flags) {
+            Ok((len, addr)) => (len, Some(addr)),
+            Err(SocketError::NotSupported) => (self.recv(buffer, flags)?, None),
+            Err(err) => return Err(err),
+        };
+        Ok(ReceivedMessage {
+            len,
+            addr,
+            control: Vec::new(),
+            truncated: false,
+        })
+    }
 }
 
 /// Socket errors
@@ -219,6 +470,10 @@ pub enum SocketError {
     PermissionDenied,
     /// Out of memory
     OutOfMemory,
+    /// Connection attempt still in progress
+    InProgress,
+    /// Peer closed or shut down its receiving side
+    BrokenPipe,
     /// Other error
     Other,
 }
@@ -245,6 +500,9 @@ pub enum SocketOptionType {
     SndBuf,
     RcvTimeo,
     SndTimeo,
+    NonBlocking,
+    PassCred,
+    TcpCongestion,
 }
 
 /// Socket option
@@ -258,6 +516,11 @@ pub enum SocketOption {
     SndBuf(usize),
     RcvTimeo(Option<u64>),
     SndTimeo(Option<u64>),
+    /// O_NONBLOCK file status flag
+    NonBlocking(bool),
+    /// Receive sender credentials (SO_PASSCRED)
+    PassCred(bool),
+    TcpCongestion(CongestionAlgorithm),
 }
 
 /// Socket table - maps file descriptors to sockets
@@ -326,24 +589,70 @@ pub fn socket(
 ) -> Result<i32, SocketError> {
     // Create appropriate socket implementation based on domain/type/protocol
     match (domain, socket_type, protocol) {
-        (SocketDomain::Inet, SocketType::Stream, SocketProtocol::Tcp)
-        | (SocketDomain::Inet, SocketType::Stream, SocketProtocol::Default) => {
+        (SocketDomain::Inet | SocketDomain::Inet6, SocketType::Stream, SocketProtocol::Tcp)
+        | (SocketDomain::Inet | SocketDomain::Inet6, SocketType::Stream, SocketProtocol::Default) =>
+        {
             // Create TCP socket
-            let tcp_socket = TcpSocketWrapper::new()?;
+            let tcp_socket = TcpSocketWrapper::new(domain)?;
             let fd = SOCKET_TABLE.lock().add(Arc::new(Mutex::new(tcp_socket)));
             Ok(fd)
         }
-        (SocketDomain::Inet, SocketType::Dgram, SocketProtocol::Udp)
-        | (SocketDomain::Inet, SocketType::Dgram, SocketProtocol::Default) => {
+        (SocketDomain::Inet | SocketDomain::Inet6, SocketType::Dgram, SocketProtocol::Udp)
+        | (SocketDomain::Inet | SocketDomain::Inet6, SocketType::Dgram, SocketProtocol::Default) => {
             // Create UDP socket
-            let udp_socket = UdpSocketWrapper::new()?;
+            let udp_socket = UdpSocketWrapper::new(domain)?;
             let fd = SOCKET_TABLE.lock().add(Arc::new(Mutex::new(udp_socket)));
             Ok(fd)
         }
+        (SocketDomain::Inet, SocketType::Dgram, SocketProtocol::Icmp)
+        | (SocketDomain::Inet, SocketType::Raw, SocketProtocol::Icmp) => {
+            // Create ping socket
+            let icmp_socket = IcmpSocketWrapper::new()?;
+            let fd = SOCKET_TABLE.lock().add(Arc::new(Mutex::new(icmp_socket)));
+            Ok(fd)
+        }
+        (SocketDomain::Unix, SocketType::Stream | SocketType::Dgram, SocketProtocol::Default) => {
+            let unix_socket = UnixSocket::new(socket_type);
+            let fd = SOCKET_TABLE.lock().add(Arc::new(Mutex::new(unix_socket)));
+            Ok(fd)
+        }
+        (SocketDomain::Packet, SocketType::Raw | SocketType::Dgram, SocketProtocol::Default) => {
+            let packet_socket = PacketSocket::new(socket_type, 0)?;
+            let fd = SOCKET_TABLE.lock().add(Arc::new(Mutex::new(packet_socket)));
+            Ok(fd)
+        }
+        (
+            SocketDomain::Packet,
+            SocketType::Raw | SocketType::Dgram,
+            SocketProtocol::Packet(ethertype),
+        ) => {
+            let packet_socket = PacketSocket::new(socket_type, ethertype)?;
+            let fd = SOCKET_TABLE.lock().add(Arc::new(Mutex::new(packet_socket)));
+            Ok(fd)
+        }
         _ => Err(SocketError::NotSupported),
     }
 }
 
+/// Create a pair of connected sockets
+///
+/// Only Unix domain sockets can be paired.
+pub fn socketpair(
+    domain: SocketDomain,
+    socket_type: SocketType,
+    protocol: SocketProtocol,
+) -> Result<(i32, i32), SocketError> {
+    if domain != SocketDomain::Unix || protocol != SocketProtocol::Default {
+        return Err(SocketError::NotSupported);
+    }
+    let (a, b) = UnixSocket::pair(socket_type)?;
+
+    let mut table = SOCKET_TABLE.lock();
+    let fd_a = table.add(Arc::new(Mutex::new(a)));
+    let fd_b = table.add(Arc::new(Mutex::new(b)));
+    Ok((fd_a, fd_b))
+}
+
 /// Bind socket to address
 pub fn bind(fd: i32, addr: SocketAddr) -> Result<(), SocketError> {
     let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
@@ -358,55 +667,323 @@ pub fn listen(fd: i32, backlog: u32) -> Result<(), SocketError> {
     result
 }
 
+/// Uptime at which a socket timeout expires
+fn deadline(timeout: Option<u64>) -> Option<u64> {
+    timeout.map(|ms| crate::time::uptime_ms() + ms)
+}
+
+/// Run a socket operation, sleeping while it would block
+///
+/// Gives up with `WouldBlock` when `nonblocking` is set or once `deadline`
+/// has passed (EAGAIN, as for an expired SO_RCVTIMEO/SO_SNDTIMEO).
+fn blocking<T>(
+    socket: &Arc<Mutex<dyn Socket>>,
+    nonblocking: bool,
+    deadline: Option<u64>,
+    mut op: impl FnMut(&mut dyn Socket) -> Result<T, SocketError>,
+) -> Result<T, SocketError> {
+    let result = op(&mut *socket.lock());
+    if nonblocking || !matches!(result, Err(SocketError::WouldBlock)) {
+        return result;
+    }
+
+    let queue = socket.lock().wait_queue();
+    queue
+        .wait_until(
+            || match op(&mut *socket.lock()) {
+                Err(SocketError::WouldBlock) => None,
+                result => Some(result),
+            },
+            deadline,
+        )
+        .unwrap_or(Err(SocketError::WouldBlock))
+}
+
+/// Check if a call must not block
+fn is_nonblocking(options: &SocketOptions, flags: u32) -> bool {
+    options.nonblocking || flags & MSG_DONTWAIT != 0
+}
+
 /// Accept incoming connection
+///
+/// Sleeps until a connection completes its handshake.
 pub fn accept(fd: i32) -> Result<i32, SocketError> {
     let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
-    let new_socket = {
-        let mut sock = socket.lock();
-        sock.accept()?
-    };
+    let options = socket.lock().options();
+    let new_socket = blocking(
+        &socket,
+        options.nonblocking,
+        deadline(options.rcvtimeo),
+        |sock| sock.accept(),
+    )?;
     Ok(SOCKET_TABLE.lock().add(new_socket))
 }
 
 /// Connect to remote address
+///
+/// Sleeps until a stream connection is established. A non-blocking socket
+/// returns `InProgress`; completion is reported by `poll` and the outcome
+/// by the socket's pending error.
 pub fn connect(fd: i32, addr: SocketAddr) -> Result<(), SocketError> {
     let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
-    let result = socket.lock().connect(addr);
-    result
+    let (options, queue) = {
+        let mut sock = socket.lock();
+        sock.connect(addr)?;
+        if sock.state() != SocketState::Connecting {
+            return Ok(());
+        }
+        (sock.options(), sock.wait_queue())
+    };
+    if options.nonblocking {
+        return Err(SocketError::InProgress);
+    }
+
+    queue
+        .wait_until(
+            || {
+                let mut sock = socket.lock();
+                match sock.state() {
+                    SocketState::Connecting => None,
+                    SocketState::Connected => Some(Ok(())),
+                    _ => Some(Err(sock.take_error().unwrap_or(SocketError::ConnRefused))),
+                }
+            },
+            deadline(options.sndtimeo),
+        )
+        .unwrap_or(Err(SocketError::InProgress))
+}
+
+/// Send all of `data`, sleeping while the send buffer is full
+///
+/// Returns the bytes queued so far if the call is interrupted by
+/// non-blocking mode, the send timeout or an error.
+fn send_all(
+    socket: &Arc<Mutex<dyn Socket>>,
+    data: &[u8],
+    flags: u32,
+    mut op: impl FnMut(&mut dyn Socket, &[u8]) -> Result<usize, SocketError>,
+) -> Result<usize, SocketError> {
+    let options = socket.lock().options();
+    let nonblocking = is_nonblocking(&options, flags);
+    let deadline = deadline(options.sndtimeo);
+
+    let mut sent = 0;
+    loop {
+        match blocking(socket, nonblocking, deadline, |sock| {
+            op(sock, &data[sent..])
+        }) {
+            Ok(len) => {
+                sent += len;
+                if sent >= data.len() || nonblocking {
+                    return Ok(sent);
+                }
+            }
+            Err(_) if sent > 0 => return Ok(sent),
+            Err(err) => return Err(err),
+        }
+    }
 }
 
 /// Send data
 pub fn send(fd: i32, data: &[u8], flags: u32) -> Result<usize, SocketError> {
     let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
-    let result = socket.lock().send(data, flags);
-    result
+    send_all(&socket, data, flags, |sock, data| sock.send(data, flags))
 }
 
 /// Receive data
+///
+/// Sleeps until data arrives, unless the socket is non-blocking or
+/// `MSG_DONTWAIT` is given.
 pub fn recv(fd: i32, buffer: &mut [u8], flags: u32) -> Result<usize, SocketError> {
     let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
-    let result = socket.lock().recv(buffer, flags);
-    result
+    let options = socket.lock().options();
+    blocking(
+        &socket,
+        is_nonblocking(&options, flags),
+        deadline(options.rcvtimeo),
+        |sock| sock.recv(buffer, flags),
+    )
 }
 
 /// Send data to specific address
 pub fn sendto(fd: i32, data: &[u8], addr: SocketAddr, flags: u32) -> Result<usize, SocketError> {
     let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
-    let result = socket.lock().sendto(data, addr, flags);
-    result
+    send_all(&socket, data, flags, |sock, data| {
+        sock.sendto(data, addr.clone(), flags)
+    })
 }
 
 /// Receive data with source address
+///
+/// Sleeps like `recv`.
 pub fn recvfrom(
     fd: i32,
     buffer: &mut [u8],
     flags: u32,
 ) -> Result<(usize, SocketAddr), SocketError> {
     let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
-    let result = socket.lock().recvfrom(buffer, flags);
+    let options = socket.lock().options();
+    blocking(
+        &socket,
+        is_nonblocking(&options, flags),
+        deadline(options.rcvtimeo),
+        |sock| sock.recvfrom(buffer, flags),
+    )
+}
+
+/// Send data with ancillary data
+///
+/// Sleeps like `send`. The ancillary data goes with the first bytes
+/// queued.
+pub fn sendmsg(
+    fd: i32,
+    data: &[u8],
+    addr: Option<SocketAddr>,
+    control: &[ControlMessage],
+    flags: u32,
+) -> Result<usize, SocketError> {
+    let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
+    let mut control = control;
+    send_all(&socket, data, flags, |sock, data| {
+        let result = sock.sendmsg(data, addr.clone(), control, flags);
+        if result.is_ok() {
+            control = &[];
+        }
+        result
+    })
+}
+
+/// Receive data with source address and ancillary data
+///
+/// Sleeps like `recv`.
+pub fn recvmsg(fd: i32, buffer: &mut [u8], flags: u32) -> Result<ReceivedMessage, SocketError> {
+    let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
+    let options = socket.lock().options();
+    blocking(
+        &socket,
+        is_nonblocking(&options, flags),
+        deadline(options.rcvtimeo),
+        |sock| sock.recvmsg(buffer, flags),
+    )
+}
+
+/// Set socket option
+pub fn setsockopt(fd: i32, option: SocketOption) -> Result<(), SocketError> {
+    let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
+    let result = socket.lock().setsockopt(option);
+    result
+}
+
+/// Get socket option
+pub fn getsockopt(fd: i32, option: SocketOptionType) -> Result<SocketOption, SocketError> {
+    let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
+    let result = socket.lock().getsockopt(option);
     result
 }
 
+/// Get the local address of a socket
+pub fn local_addr(fd: i32) -> Result<Option<SocketAddr>, SocketError> {
+    let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
+    let addr = socket.lock().local_addr();
+    Ok(addr)
+}
+
+/// Get the peer address of a socket
+pub fn peer_addr(fd: i32) -> Result<Option<SocketAddr>, SocketError> {
+    let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
+    let addr = socket.lock().peer_addr();
+    Ok(addr)
+}
+
+/// Take the pending asynchronous error of a socket (SO_ERROR)
+pub fn take_error(fd: i32) -> Result<Option<SocketError>, SocketError> {
+    let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
+    let error = socket.lock().take_error();
+    Ok(error)
+}
+
+/// Set or clear non-blocking mode (O_NONBLOCK)
+pub fn set_nonblocking(fd: i32, nonblocking: bool) -> Result<(), SocketError> {
+    setsockopt(fd, SocketOption::NonBlocking(nonblocking))
+}
+
+/// Check whether a socket is in non-blocking mode
+pub fn is_nonblocking_socket(fd: i32) -> Result<bool, SocketError> {
+    match getsockopt(fd, SocketOptionType::NonBlocking)? {
+        SocketOption::NonBlocking(nonblocking) => Ok(nonblocking),
+        _ => Err(SocketError::Other),
+    }
+}
+
+/// Poll request for one socket
+#[derive(Debug, Clone, Copy)]
+pub struct PollFd {
+    /// Socket file descriptor
+    pub fd: i32,
+    /// Events of interest
+    pub events: PollEvents,
+    /// Events that occurred
+    pub revents: PollEvents,
+}
+
+impl PollFd {
+    /// Create new poll request
+    pub const fn new(fd: i32, events: PollEvents) -> Self {
+        Self {
+            fd,
+            events,
+            revents: PollEvents::empty(),
+        }
+    }
+}
+
+/// Get the readiness of a socket
+pub fn poll_socket(fd: i32) -> Result<PollEvents, SocketError> {
+    let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
+    let events = socket.lock().poll();
+    Ok(events)
+}
+
+/// Wait for events on a set of sockets
+///
+/// Fills in `revents` and returns the number of ready entries. Errors and
+/// hang-ups are always reported; unknown descriptors report `NVAL`.
+/// Sleeps until an entry is ready or `timeout` (milliseconds) passes, in
+/// which case 0 is returned. A zero timeout only checks readiness.
+pub fn poll(fds: &mut [PollFd], timeout: Option<u64>) -> Result<usize, SocketError> {
+    let sockets: Vec<Option<Arc<Mutex<dyn Socket>>>> = {
+        let table = SOCKET_TABLE.lock();
+        fds.iter().map(|pfd| table.get(pfd.fd)).collect()
+    };
+    let queues: Vec<Arc<WaitQueue>> = sockets
+        .iter()
+        .flatten()
+        .map(|socket| socket.lock().wait_queue())
+        .collect();
+    let queues: Vec<&WaitQueue> = queues.iter().map(|queue| &**queue).collect();
+
+    let always = PollEvents::ERR | PollEvents::HUP | PollEvents::NVAL;
+    let ready = wait_queue::wait_any(
+        &queues,
+        || {
+            let mut ready = 0;
+            for (pfd, socket) in fds.iter_mut().zip(&sockets) {
+                let events = match socket {
+                    Some(socket) => socket.lock().poll(),
+                    None => PollEvents::NVAL,
+                };
+                pfd.revents = events.intersection(pfd.events | always);
+                if !pfd.revents.is_empty() {
+                    ready += 1;
+                }
+            }
+            (ready > 0).then_some(ready)
+        },
+        deadline(timeout),
+    );
+    Ok(ready.unwrap_or(0))
+}
+
 /// Shutdown socket
 pub fn shutdown(fd: i32, how: ShutdownHow) -> Result<(), SocketError> {
     let socket = SOCKET_TABLE.lock().get(fd).ok_or(SocketError::InvalidArg)?;
@@ -414,6 +991,27 @@ pub fn shutdown(fd: i32, how: ShutdownHow) -> Result<(), SocketError> {
     result
 }
 
+/// Descriptors in flight on a socket's queues
+///
+/// `None` if there is no such socket or it is in use.
+pub fn queued_rights(fd: i32) -> Option<Vec<FdEntry>> {
+    let socket = SOCKET_TABLE.lock().get(fd)?;
+    let rights = socket.try_lock()?.queued_rights();
+    Some(rights)
+}
+
+/// Take the descriptors in flight off a socket's queues
+pub fn take_queued_rights(fd: i32) -> Vec<InFlightFds> {
+    let Some(socket) = SOCKET_TABLE.lock().get(fd) else {
+        return Vec::new();
+    };
+    let rights = match socket.try_lock() {
+        Some(mut socket) => socket.take_queued_rights(),
+        None => Vec::new(),
+    };
+    rights
+}
+
 /// Close socket
 pub fn close_socket(fd: i32) -> Result<(), SocketError> {
     let socket = SOCKET_TABLE
@@ -424,31 +1022,163 @@ pub fn close_socket(fd: i32) -> Result<(), SocketError> {
     result
 }
 
+/// Convert a socket address given to an Internet socket to an endpoint
+///
+/// IPv6 sockets take IPv6 addresses only, IPv4-mapped ones standing for
+/// IPv4 peers.
+fn inet_endpoint(domain: SocketDomain, addr: SocketAddr) -> Result<IpEndpoint, SocketError> {
+    match (domain, addr) {
+        (SocketDomain::Inet, SocketAddr::V4(addr)) => Ok(addr.into()),
+        (SocketDomain::Inet6, SocketAddr::V6(addr)) => Ok(addr.into()),
+        _ => Err(SocketError::NotSupported),
+    }
+}
+
+/// Report an endpoint in the address family of an Internet socket
+fn inet_socket_addr(domain: SocketDomain, endpoint: IpEndpoint) -> SocketAddr {
+    match domain {
+        SocketDomain::Inet6 => SocketAddr::V6(endpoint.to_socket_addr_v6()),
+        _ => endpoint.to_socket_addr(),
+    }
+}
+
 /// UDP socket wrapper implementing Socket trait
 struct UdpSocketWrapper {
     inner: NetUdpSocket,
+    domain: SocketDomain,
     state: SocketState,
+    options: SocketOptions,
 }
 
 impl UdpSocketWrapper {
-    fn new() -> Result<Self, SocketError> {
+    fn new(domain: SocketDomain) -> Result<Self, SocketError> {
         Ok(Self {
             inner: NetUdpSocket::new()?,
+            domain,
             state: SocketState::Closed,
+            options: SocketOptions::default(),
         })
     }
 }
 
 impl Socket for UdpSocketWrapper {
     fn bind(&mut self, addr: SocketAddr) -> Result<(), SocketError> {
-        match addr {
-            SocketAddr::V4(addr_v4) => {
-                self.inner.bind(addr_v4)?;
-                self.state = SocketState::Bound;
-                Ok(())
-            }
-            _ => Err(SocketError::NotSupported),
+        self.inner.bind(inet_endpoint(self.domain, addr)?)?;
+        self.state = SocketState::Bound;
+        Ok(())
+    }
+
+    fn listen(&mut self, _backlog: u32) -> Result<(), SocketError> {
+        Err(SocketError::NotSupported)
+    }
+
+    fn accept(&mut self) -> Result<Arc<Mutex<dyn Socket>>, SocketError> {
+        Err(SocketError::NotSupported)
+    }
+
+    fn connect(&mut self, addr: SocketAddr) -> Result<(), SocketError> {
+        self.inner.connect(inet_endpoint(self.domain, addr)?)?;
+        self.state = SocketState::Connected;
+        Ok(())
+    }
+
+    fn send(&mut self, data: &[u8], _flags: u32) -> Result<usize, SocketError> {
+        self.inner.send(data)
+    }
+
+    fn recv(&mut self, buffer: &mut [u8], _flags: u32) -> Result<usize, SocketError> {
+        self.inner.recv(buffer)
+    }
+
+    fn sendto(&mut self, data: &[u8], addr: SocketAddr, _flags: u32) -> Result<usize, SocketError> {
+        self.inner.send_to(data, inet_endpoint(self.domain, addr)?)
+    }
+
+    fn recvfrom(
+        &mut self,
+        buffer: &mut [u8],
+        _flags: u32,
+    ) -> Result<(usize, SocketAddr), SocketError> {
+        let (len, addr) = self.inner.recv_from(buffer)?;
+        Ok((len, inet_socket_addr(self.domain, addr)))
+    }
+
+    fn shutdown(&mut self, _how: ShutdownHow) -> Result<(), SocketError> {
+        self.state = SocketState::Closing;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), SocketError> {
+        self.inner.close()?;
+        self.state = SocketState::Closed;
+        Ok(())
+    }
+
+    fn state(&self) -> SocketState {
+        self.state
+    }
+
+    fn setsockopt(&mut self, option: SocketOption) -> Result<(), SocketError> {
+        self.options.set(option)
+    }
+
+    fn getsockopt(&self, option: SocketOptionType) -> Result<SocketOption, SocketError> {
+        self.options.get(option)
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.inner
+            .local_addr()
+            .map(|addr| inet_socket_addr(self.domain, addr))
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.inner
+            .remote_addr()
+            .map(|addr| inet_socket_addr(self.domain, addr))
+    }
+
+    fn options(&self) -> SocketOptions {
+        self.options
+    }
+
+    fn poll(&self) -> PollEvents {
+        // Datagrams are sent straight away, so the socket is always writable
+        let mut events = PollEvents::OUT;
+        if self.inner.has_pending() {
+            events |= PollEvents::IN;
         }
+        events
+    }
+
+    fn wait_queue(&self) -> Arc<WaitQueue> {
+        self.inner.wait_queue()
+    }
+}
+
+/// Ping socket wrapper implementing Socket trait
+///
+/// Messages are complete ICMP echo requests; the echo identifier is the
+/// socket's "port".
+struct IcmpSocketWrapper {
+    inner: IcmpSocket,
+    state: SocketState,
+    options: SocketOptions,
+}
+
+impl IcmpSocketWrapper {
+    fn new() -> Result<Self, SocketError> {
+        Ok(Self {
+            inner: IcmpSocket::new()?,
+            state: SocketState::Bound,
+            options: SocketOptions::default(),
+        })
+    }
+}
+
+impl Socket for IcmpSocketWrapper {
+    fn bind(&mut self, _addr: SocketAddr) -> Result<(), SocketError> {
+        Err(SocketError::NotSupported)
     }
 
     fn listen(&mut self, _backlog: u32) -> Result<(), SocketError> {
@@ -462,7 +1192,7 @@ impl Socket for UdpSocketWrapper {
     fn connect(&mut self, addr: SocketAddr) -> Result<(), SocketError> {
         match addr {
             SocketAddr::V4(addr_v4) => {
-                self.inner.connect(addr_v4)?;
+                self.inner.connect(Ipv4Addr(addr_v4.ip));
                 self.state = SocketState::Connected;
                 Ok(())
             }
@@ -475,12 +1205,12 @@ impl Socket for UdpSocketWrapper {
     }
 
     fn recv(&mut self, buffer: &mut [u8], _flags: u32) -> Result<usize, SocketError> {
-        self.inner.recv(buffer)
+        self.inner.recv_from(buffer).map(|(len, _)| len)
     }
 
     fn sendto(&mut self, data: &[u8], addr: SocketAddr, _flags: u32) -> Result<usize, SocketError> {
         match addr {
-            SocketAddr::V4(addr_v4) => self.inner.send_to(data, addr_v4),
+            SocketAddr::V4(addr_v4) => self.inner.send_to(data, Ipv4Addr(addr_v4.ip)),
             _ => Err(SocketError::NotSupported),
         }
     }
@@ -490,8 +1220,8 @@ impl Socket for UdpSocketWrapper {
         buffer: &mut [u8],
         _flags: u32,
     ) -> Result<(usize, SocketAddr), SocketError> {
-        let (len, addr) = self.inner.recv_from(buffer)?;
-        Ok((len, SocketAddr::V4(addr)))
+        let (len, src) = self.inner.recv_from(buffer)?;
+        Ok((len, SocketAddr::V4(SocketAddrV4 { ip: src.0, port: 0 })))
     }
 
     fn shutdown(&mut self, _how: ShutdownHow) -> Result<(), SocketError> {
@@ -500,7 +1230,7 @@ impl Socket for UdpSocketWrapper {
     }
 
     fn close(&mut self) -> Result<(), SocketError> {
-        self.inner.close()?;
+        self.inner.close();
         self.state = SocketState::Closed;
         Ok(())
     }
@@ -509,63 +1239,109 @@ impl Socket for UdpSocketWrapper {
         self.state
     }
 
-    fn setsockopt(&mut self, _option: SocketOption) -> Result<(), SocketError> {
-        Err(SocketError::NotSupported)
+    fn setsockopt(&mut self, option: SocketOption) -> Result<(), SocketError> {
+        self.options.set(option)
     }
 
-    fn getsockopt(&self, _option: SocketOptionType) -> Result<SocketOption, SocketError> {
-        Err(SocketError::NotSupported)
+    fn getsockopt(&self, option: SocketOptionType) -> Result<SocketOption, SocketError> {
+        self.options.get(option)
     }
 
     fn local_addr(&self) -> Option<SocketAddr> {
-        self.inner.local_addr().map(SocketAddr::V4)
+        Some(SocketAddr::V4(SocketAddrV4 {
+            ip: Ipv4Addr::UNSPECIFIED.0,
+            port: self.inner.identifier(),
+        }))
     }
 
     fn peer_addr(&self) -> Option<SocketAddr> {
-        self.inner.remote_addr().map(SocketAddr::V4)
+        self.inner
+            .remote_addr()
+            .map(|ip| SocketAddr::V4(SocketAddrV4 { ip: ip.0, port: 0 }))
+    }
+
+    fn options(&self) -> SocketOptions {
+        self.options
+    }
+
+    fn poll(&self) -> PollEvents {
+        let mut events = PollEvents::OUT;
+        if self.inner.has_pending() {
+            events |= PollEvents::IN;
+        }
+        events
+    }
+
+    fn wait_queue(&self) -> Arc<WaitQueue> {
+        self.inner.wait_queue()
     }
 }
 
 /// TCP socket wrapper implementing Socket trait
+///
+/// The inner socket is shared so that a listening socket can be registered
+/// with the TCP connection manager and receive incoming SYNs.
 struct TcpSocketWrapper {
-    inner: NetTcpSocket,
+    inner: Arc<Mutex<NetTcpSocket>>,
+    domain: SocketDomain,
 }
 
 impl TcpSocketWrapper {
-    fn new() -> Result<Self, SocketError> {
+    fn new(domain: SocketDomain) -> Result<Self, SocketError> {
         Ok(Self {
-            inner: NetTcpSocket::new()?,
+            inner: Arc::new(Mutex::new(NetTcpSocket::new()?)),
+            domain,
         })
     }
+
+    /// Convert a socket address reported by the inner socket
+    fn report(&self, addr: SocketAddr) -> SocketAddr {
+        match IpEndpoint::try_from(addr.clone()) {
+            Ok(endpoint) => inet_socket_addr(self.domain, endpoint),
+            Err(_) => addr,
+        }
+    }
 }
 
 impl Socket for TcpSocketWrapper {
     fn bind(&mut self, addr: SocketAddr) -> Result<(), SocketError> {
-        self.inner.bind(addr)
+        let addr = inet_endpoint(self.domain, addr)?;
+        self.inner.lock().bind(addr.to_socket_addr())
     }
 
     fn listen(&mut self, backlog: u32) -> Result<(), SocketError> {
-        self.inner.listen(backlog)
+        let mut inner = self.inner.lock();
+        inner.listen(backlog)?;
+        let port = match inner.local_addr() {
+            Some(SocketAddr::V4(addr)) => addr.port,
+            Some(SocketAddr::V6(addr)) => addr.port,
+            _ => return Err(SocketError::InvalidArg),
+        };
+        drop(inner);
+
+        tcp::register_listener(port, self.inner.clone());
+        Ok(())
     }
 
     fn accept(&mut self) -> Result<Arc<Mutex<dyn Socket>>, SocketError> {
-        self.inner.accept()
+        self.inner.lock().accept()
     }
 
     fn connect(&mut self, addr: SocketAddr) -> Result<(), SocketError> {
-        self.inner.connect(addr)
+        let addr = inet_endpoint(self.domain, addr)?;
+        self.inner.lock().connect(addr.to_socket_addr())
     }
 
     fn send(&mut self, data: &[u8], flags: u32) -> Result<usize, SocketError> {
-        self.inner.send(data, flags)
+        self.inner.lock().send(data, flags)
     }
 
     fn recv(&mut self, buffer: &mut [u8], flags: u32) -> Result<usize, SocketError> {
-        self.inner.recv(buffer, flags)
+        self.inner.lock().recv(buffer, flags)
     }
 
     fn sendto(&mut self, data: &[u8], addr: SocketAddr, flags: u32) -> Result<usize, SocketError> {
-        self.inner.sendto(data, addr, flags)
+        self.inner.lock().sendto(data, addr, flags)
     }
 
     fn recvfrom(
@@ -573,35 +1349,53 @@ impl Socket for TcpSocketWrapper {
         buffer: &mut [u8],
         flags: u32,
     ) -> Result<(usize, SocketAddr), SocketError> {
-        self.inner.recvfrom(buffer, flags)
+        self.inner.lock().recvfrom(buffer, flags)
     }
 
     fn shutdown(&mut self, how: ShutdownHow) -> Result<(), SocketError> {
-        self.inner.shutdown(how)
+        self.inner.lock().shutdown(how)
     }
 
     fn close(&mut self) -> Result<(), SocketError> {
-        self.inner.close()
+        self.inner.lock().close()
     }
 
     fn state(&self) -> SocketState {
-        self.inner.state()
+        self.inner.lock().state()
     }
 
     fn setsockopt(&mut self, option: SocketOption) -> Result<(), SocketError> {
-        self.inner.setsockopt(option)
+        self.inner.lock().setsockopt(option)
     }
 
     fn getsockopt(&self, option: SocketOptionType) -> Result<SocketOption, SocketError> {
-        self.inner.getsockopt(option)
+        self.inner.lock().getsockopt(option)
     }
 
     fn local_addr(&self) -> Option<SocketAddr> {
-        self.inner.local_addr()
+        let addr = self.inner.lock().local_addr()?;
+        Some(self.report(addr))
     }
 
     fn peer_addr(&self) -> Option<SocketAddr> {
-        self.inner.peer_addr()
+        let addr = self.inner.lock().peer_addr()?;
+        Some(self.report(addr))
+    }
+
+    fn options(&self) -> SocketOptions {
+        self.inner.lock().options()
+    }
+
+    fn poll(&self) -> PollEvents {
+        self.inner.lock().poll()
+    }
+
+    fn wait_queue(&self) -> Arc<WaitQueue> {
+        self.inner.lock().wait_queue()
+    }
+
+    fn take_error(&mut self) -> Option<SocketError> {
+        self.inner.lock().take_error()
     }
 }
 
@@ -636,4 +1430,178 @@ mod tests {
     fn test_socket_table() {
         // Test would require a concrete Socket implementation
     }
+
+    /// Deliver everything queued on the loopback device
+    fn drain() {
+        let lo = crate::net::loopback::device().unwrap();
+        while crate::net::rx::poll_device(&lo) > 0 {}
+    }
+
+    fn localhost(port: u16) -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4 {
+            ip: Ipv4Addr::LOCALHOST.0,
+            port,
+        })
+    }
+
+    #[test]
+    fn test_socket_options() {
+        let mut opts = SocketOptions::default();
+        opts.set(SocketOption::RcvTimeo(Some(250))).unwrap();
+        opts.set(SocketOption::SndTimeo(Some(0))).unwrap();
+        opts.set(SocketOption::NonBlocking(true)).unwrap();
+        assert_eq!(opts.rcvtimeo, Some(250));
+        assert_eq!(opts.sndtimeo, None);
+        assert!(matches!(
+            opts.get(SocketOptionType::NonBlocking),
+            Ok(SocketOption::NonBlocking(true))
+        ));
+        assert_eq!(
+            opts.set(SocketOption::TcpCongestion(CongestionAlgorithm::default()))
+                .unwrap_err(),
+            SocketError::NotSupported
+        );
+    }
+
+    #[test]
+    fn test_udp_readiness() {
+        crate::net::loopback::init();
+
+        let server = socket(SocketDomain::Inet, SocketType::Dgram, SocketProtocol::Udp).unwrap();
+        let client = socket(SocketDomain::Inet, SocketType::Dgram, SocketProtocol::Udp).unwrap();
+        bind(server, localhost(9600)).unwrap();
+        connect(client, localhost(9600)).unwrap();
+
+        // Nothing queued: non-blocking calls and zero-timeout polls give up
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            recv(server, &mut buffer, MSG_DONTWAIT),
+            Err(SocketError::WouldBlock)
+        );
+        set_nonblocking(server, true).unwrap();
+        assert_eq!(recv(server, &mut buffer, 0), Err(SocketError::WouldBlock));
+        let mut fds = [PollFd::new(server, PollEvents::IN)];
+        assert_eq!(poll(&mut fds, Some(0)), Ok(0));
+
+        send(client, b"ready", 0).unwrap();
+        drain();
+
+        assert_eq!(poll(&mut fds, Some(0)), Ok(1));
+        assert_eq!(fds[0].revents, PollEvents::IN);
+        set_nonblocking(server, false).unwrap();
+        let len = recv(server, &mut buffer, 0).unwrap();
+        assert_eq!(&buffer[..len], b"ready");
+
+        let mut fds = [PollFd::new(-1, PollEvents::IN)];
+        assert_eq!(poll(&mut fds, Some(0)), Ok(1));
+        assert_eq!(fds[0].revents, PollEvents::NVAL);
+
+        close_socket(server).unwrap();
+        close_socket(client).unwrap();
+    }
+
+    #[test]
+    fn test_blocking_recv_polls_devices() {
+        crate::net::loopback::init();
+
+        let server = socket(SocketDomain::Inet, SocketType::Dgram, SocketProtocol::Udp).unwrap();
+        let client = socket(SocketDomain::Inet, SocketType::Dgram, SocketProtocol::Udp).unwrap();
+        bind(server, localhost(9606)).unwrap();
+        connect(client, localhost(9606)).unwrap();
+
+        // The datagram is still queued on the device when the receive blocks
+        send(client, b"queued", 0).unwrap();
+        let mut buffer = [0u8; 16];
+        let len = recv(server, &mut buffer, 0).unwrap();
+        assert_eq!(&buffer[..len], b"queued");
+
+        close_socket(server).unwrap();
+        close_socket(client).unwrap();
+    }
+
+    #[test]
+    fn test_rcvtimeo_expires() {
+        extern crate std;
+        use crate::process::{sched, task::Task};
+        use core::sync::atomic::{AtomicBool, Ordering};
+
+        crate::net::loopback::init();
+        sched::add_task(Task::new(311));
+        sched::schedule();
+
+        let server = socket(SocketDomain::Inet, SocketType::Dgram, SocketProtocol::Udp).unwrap();
+        bind(server, localhost(9607)).unwrap();
+        setsockopt(server, SocketOption::RcvTimeo(Some(50))).unwrap();
+
+        // Stand in for the timer interrupt while the receive sleeps
+        static DONE: AtomicBool = AtomicBool::new(false);
+        let ticker = std::thread::spawn(|| {
+            while !DONE.load(Ordering::Acquire) {
+                crate::time::tick(10);
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+        });
+
+        let mut buffer = [0u8; 16];
+        assert_eq!(recv(server, &mut buffer, 0), Err(SocketError::WouldBlock));
+        DONE.store(true, Ordering::Release);
+        ticker.join().unwrap();
+        assert!(!sched::is_sleeping(311));
+
+        close_socket(server).unwrap();
+        sched::remove_task(311);
+    }
+
+    #[test]
+    fn test_tcp_nonblocking_connect() {
+        crate::net::loopback::init();
+
+        let listener = socket(SocketDomain::Inet, SocketType::Stream, SocketProtocol::Tcp).unwrap();
+        bind(listener, localhost(9601)).unwrap();
+        listen(listener, 1).unwrap();
+        set_nonblocking(listener, true).unwrap();
+        assert_eq!(accept(listener), Err(SocketError::WouldBlock));
+
+        let client = socket(SocketDomain::Inet, SocketType::Stream, SocketProtocol::Tcp).unwrap();
+        set_nonblocking(client, true).unwrap();
+        assert_eq!(
+            connect(client, localhost(9601)),
+            Err(SocketError::InProgress)
+        );
+        let mut fds = [
+            PollFd::new(listener, PollEvents::IN),
+            PollFd::new(client, PollEvents::OUT),
+        ];
+        assert_eq!(poll(&mut fds, Some(0)), Ok(0));
+
+        drain();
+        assert_eq!(poll(&mut fds, Some(0)), Ok(2));
+        assert_eq!(fds[0].revents, PollEvents::IN);
+        assert_eq!(fds[1].revents, PollEvents::OUT);
+
+        let server = accept(listener).unwrap();
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            recv(server, &mut buffer, MSG_DONTWAIT),
+            Err(SocketError::WouldBlock)
+        );
+        assert_eq!(send(client, b"polled", 0), Ok(6));
+        drain();
+
+        // Blocking receive of data that has already arrived
+        let len = recv(server, &mut buffer, 0).unwrap();
+        assert_eq!(&buffer[..len], b"polled");
+
+        close_socket(client).unwrap();
+        drain();
+        assert_eq!(
+            poll_socket(server).unwrap(),
+            PollEvents::IN | PollEvents::OUT | PollEvents::RDHUP
+        );
+        assert_eq!(recv(server, &mut buffer, 0), Ok(0));
+
+        close_socket(server).unwrap();
+        close_socket(listener).unwrap();
+        drain();
+    }
 }
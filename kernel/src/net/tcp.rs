@@ -3,17 +3,26 @@
 //! TCP implementation with connection management, reliable transmission,
 //! flow control, and congestion control.
 
+pub mod congestion;
+pub mod options;
+
+use alloc::boxed::Box;
 use alloc::collections::{btree_map, BTreeMap, VecDeque};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
 use spin::Mutex;
 
-use super::ipv4::{calculate_pseudo_header_checksum, IpProtocol, Ipv4Addr};
+use congestion::{initial_window, CongestionAlgorithm, CongestionControl, CongestionWindow};
+use options::{SackBlock, TcpOptions, Timestamp, TIMESTAMP_LEN};
+
+use super::ip::{self, IpAddr, IpEndpoint};
+use super::ipv4::{IpProtocol, Ipv4Addr, Ipv4Header};
 use super::socket::{
-    ShutdownHow, Socket, SocketAddr, SocketAddrV4, SocketError, SocketOption, SocketOptionType,
-    SocketState,
+    PollEvents, ShutdownHow, Socket, SocketAddr, SocketError, SocketOption, SocketOptionType,
+    SocketOptions, SocketState,
 };
+use crate::process::wait_queue::WaitQueue;
 
 /// TCP header (20 bytes minimum)
 #[derive(Debug, Clone, Copy)]
@@ -130,6 +139,13 @@ impl TcpHeader {
         ((u16::from_be(self.data_offset_flags) >> 12) as usize) * 4
     }
 
+    /// Set data offset (header length in bytes, including options)
+    pub fn set_data_offset(&mut self, len: usize) {
+        let words = (len.clamp(Self::MIN_SIZE, Self::MAX_SIZE) / 4) as u16;
+        let flags = u16::from_be(self.data_offset_flags) & 0x1FF;
+        self.data_offset_flags = ((words << 12) | flags).to_be();
+    }
+
     /// Get window size
     pub fn window(&self) -> u16 {
         u16::from_be(self.window)
@@ -164,7 +180,12 @@ impl TcpHeader {
     }
 
     /// Calculate checksum
-    pub fn calculate_checksum(&mut self, src_ip: Ipv4Addr, dst_ip: Ipv4Addr, payload: &[u8]) {
+    pub fn calculate_checksum(
+        &mut self,
+        src_ip: impl Into<IpAddr>,
+        dst_ip: impl Into<IpAddr>,
+        payload: &[u8],
+    ) {
         // Zero out checksum field
         self.checksum = 0;
 
@@ -182,7 +203,7 @@ impl TcpHeader {
 
         // Calculate pseudo-header checksum
         let mut sum =
-            calculate_pseudo_header_checksum(src_ip, dst_ip, IpProtocol::Tcp, length as u16);
+            ip::pseudo_header_checksum(src_ip.into(), dst_ip.into(), IpProtocol::Tcp, length);
 
         // Add TCP header and payload checksum
         for chunk in buffer.chunks(2) {
@@ -205,7 +226,12 @@ impl TcpHeader {
     }
 
     /// Verify checksum
-    pub fn verify_checksum(&self, src_ip: Ipv4Addr, dst_ip: Ipv4Addr, payload: &[u8]) -> bool {
+    pub fn verify_checksum(
+        &self,
+        src_ip: impl Into<IpAddr>,
+        dst_ip: impl Into<IpAddr>,
+        payload: &[u8],
+    ) -> bool {
         let mut header = *self;
         header.calculate_checksum(src_ip, dst_ip, payload);
         header.checksum() == self.checksum()
@@ -370,9 +396,9 @@ pub struct TcpControlBlock {
     /// Connection state
     state: TcpState,
     /// Local address
-    local_addr: SocketAddrV4,
+    local_addr: IpEndpoint,
     /// Remote address
-    remote_addr: Option<SocketAddrV4>,
+    remote_addr: Option<IpEndpoint>,
     /// Send sequence variables
     send: TcpSendSequence,
     /// Receive sequence variables
@@ -381,43 +407,148 @@ pub struct TcpControlBlock {
     send_buffer: VecDeque<u8>,
     /// Receive buffer
     recv_buffer: VecDeque<u8>,
-    /// Maximum segment size
+    /// Maximum segment size accepted by the peer
     mss: u16,
-    /// Window size
-    window_size: u16,
+    /// Send buffer capacity (bytes)
+    send_buffer_size: u32,
+    /// Receive buffer capacity (bytes)
+    recv_buffer_size: u32,
+    /// Data received ahead of `recv.nxt`, by sequence number
+    out_of_order: BTreeMap<u32, Vec<u8>>,
+    /// Sequence number of the latest out-of-order segment
+    sack_recent: u32,
+    /// Shift applied to windows advertised by the peer
+    snd_wscale: u8,
+    /// Shift applied to windows we advertise
+    rcv_wscale: u8,
+    /// Window scaling in use (offered until the handshake settles it)
+    wscale_ok: bool,
+    /// Selective acknowledgments in use (offered until negotiated)
+    sack_ok: bool,
+    /// Timestamps in use (offered until negotiated)
+    ts_ok: bool,
+    /// Latest timestamp received from the peer (TS.Recent)
+    ts_recent: u32,
     /// Retransmission timeout (milliseconds)
     rto: u32,
     /// Round-trip time estimate (milliseconds)
     rtt: u32,
     /// Round-trip time variation (milliseconds)
     rtt_var: u32,
+    /// Unacknowledged segments by sequence number
+    retransmit_queue: BTreeMap<u32, RetransmitEntry>,
+    /// Retransmission timer expiry (uptime in milliseconds)
+    rto_deadline: Option<u64>,
+    /// Consecutive retransmissions of the oldest segment
+    retries: u32,
+    /// TIME_WAIT expiry (uptime in milliseconds)
+    time_wait_deadline: Option<u64>,
+    /// Release the local port once the connection is torn down
+    release_port_on_close: bool,
+    /// FIN requested but not sent yet (waits for buffered data)
+    fin_pending: bool,
+    /// Congestion control algorithm
+    congestion: Box<dyn CongestionControl>,
+    /// Congestion window state
+    cwnd: CongestionWindow,
+    /// Consecutive duplicate ACKs
+    dup_acks: u32,
+    /// Loss recovery state
+    recovery: Recovery,
+    /// Tasks waiting for the connection to make progress
+    wait: Arc<WaitQueue>,
+    /// Wait queue of the listener that created this connection
+    listener_wait: Option<Arc<WaitQueue>>,
+    /// Asynchronous error not yet reported to the socket (SO_ERROR)
+    error: Option<SocketError>,
+}
+
+/// Loss recovery state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recovery {
+    /// No loss being recovered
+    Open,
+    /// Fast recovery after three duplicate ACKs, until `recover` is acked
+    Fast { recover: u32 },
+    /// Recovery after a retransmission timeout, until `recover` is acked
+    Loss { recover: u32 },
+}
+
+/// Segment awaiting acknowledgment
+struct RetransmitEntry {
+    /// Control flags the segment was sent with
+    flags: u16,
+    /// Segment payload
+    payload: Vec<u8>,
+    /// Time of the latest transmission (uptime in milliseconds)
+    sent_at: u64,
+    /// Whether the segment has been retransmitted
+    retransmitted: bool,
+    /// Whether the peer reported the segment in a SACK block
+    sacked: bool,
+}
+
+impl RetransmitEntry {
+    /// Sequence space occupied by the segment (SYN and FIN count as one)
+    fn seq_len(&self) -> u32 {
+        let control = self.flags & (TcpFlags::SYN | TcpFlags::FIN) != 0;
+        self.payload.len() as u32 + control as u32
+    }
 }
 
 impl TcpControlBlock {
     /// Default MSS (Maximum Segment Size)
     const DEFAULT_MSS: u16 = 1460;
 
-    /// Default window size
-    const DEFAULT_WINDOW: u16 = 65535;
+    /// MSS assumed when the peer sends no MSS option (RFC 9293)
+    const DEFAULT_PEER_MSS: u16 = 536;
+
+    /// Default send and receive buffer size (bytes)
+    const DEFAULT_BUFFER_SIZE: u32 = 256 * 1024;
 
     /// Initial RTO (Retransmission Timeout) in milliseconds
     const INITIAL_RTO: u32 = 1000;
 
+    /// Maximum RTO after exponential backoff in milliseconds
+    const MAX_RTO: u32 = 120_000;
+
     /// Create new TCB
-    pub fn new(local_addr: SocketAddrV4) -> Self {
+    pub fn new(local_addr: impl Into<IpEndpoint>) -> Self {
         Self {
             state: TcpState::Closed,
-            local_addr,
+            local_addr: local_addr.into(),
             remote_addr: None,
             send: TcpSendSequence::new(),
-            recv: TcpRecvSequence::new(),
+            recv: TcpRecvSequence::new(Self::DEFAULT_BUFFER_SIZE),
             send_buffer: VecDeque::new(),
             recv_buffer: VecDeque::new(),
             mss: Self::DEFAULT_MSS,
-            window_size: Self::DEFAULT_WINDOW,
+            send_buffer_size: Self::DEFAULT_BUFFER_SIZE,
+            recv_buffer_size: Self::DEFAULT_BUFFER_SIZE,
+            out_of_order: BTreeMap::new(),
+            sack_recent: 0,
+            snd_wscale: 0,
+            rcv_wscale: window_scale_for(Self::DEFAULT_BUFFER_SIZE),
+            wscale_ok: true,
+            sack_ok: true,
+            ts_ok: true,
+            ts_recent: 0,
             rto: Self::INITIAL_RTO,
             rtt: 0,
             rtt_var: 0,
+            retransmit_queue: BTreeMap::new(),
+            rto_deadline: None,
+            retries: 0,
+            time_wait_deadline: None,
+            release_port_on_close: false,
+            fin_pending: false,
+            congestion: CongestionAlgorithm::default().create(),
+            cwnd: CongestionWindow::new(Self::DEFAULT_MSS as u32),
+            dup_acks: 0,
+            recovery: Recovery::Open,
+            wait: Arc::new(WaitQueue::new()),
+            listener_wait: None,
+            error: None,
         }
     }
 
@@ -427,46 +558,130 @@ impl TcpControlBlock {
     }
 
     /// Set connection state
+    ///
+    /// Entering TIME_WAIT (again) starts the 2*MSL timer; TIME_WAIT and
+    /// CLOSED discard anything still awaiting retransmission.
     pub fn set_state(&mut self, state: TcpState) {
         self.state = state;
+
+        match state {
+            TcpState::TimeWait => {
+                self.retransmit_queue.clear();
+                self.rto_deadline = None;
+                self.time_wait_deadline = Some(crate::time::uptime_ms() + 2 * TCP_MSL_MS);
+            }
+            TcpState::Closed => {
+                self.retransmit_queue.clear();
+                self.rto_deadline = None;
+                self.time_wait_deadline = None;
+            }
+            _ => {}
+        }
+
+        self.wake();
+    }
+
+    /// Wake tasks waiting on the connection or its listener
+    fn wake(&self) {
+        self.wait.wake_all();
+        if let Some(listener_wait) = &self.listener_wait {
+            listener_wait.wake_all();
+        }
+    }
+
+    /// Get the wait queue woken when the connection makes progress
+    pub fn wait_queue(&self) -> Arc<WaitQueue> {
+        self.wait.clone()
+    }
+
+    /// Take the pending asynchronous error
+    pub fn take_error(&mut self) -> Option<SocketError> {
+        self.error.take()
+    }
+
+    /// Get connection readiness
+    pub fn poll_events(&self) -> PollEvents {
+        let mut events = PollEvents::empty();
+        if !self.recv_buffer.is_empty() {
+            events |= PollEvents::IN;
+        }
+        if matches!(
+            self.state,
+            TcpState::CloseWait | TcpState::LastAck | TcpState::Closing | TcpState::TimeWait
+        ) {
+            // The peer's FIN makes end of stream readable
+            events |= PollEvents::IN | PollEvents::RDHUP;
+        }
+        if self.state.can_send() && self.available_window() > 0 {
+            events |= PollEvents::OUT;
+        }
+        if self.error.is_some() {
+            events |= PollEvents::ERR;
+        }
+        if self.state.is_closed() {
+            events |= PollEvents::HUP;
+        }
+        events
+    }
+
+    /// Tear down the connection
+    ///
+    /// Closes it, removes it from the connection manager and frees the
+    /// local port if the owning socket has already been closed.
+    fn terminate(&mut self) {
+        self.set_state(TcpState::Closed);
+        if let Some(id) = self.connection_id() {
+            unregister_connection(&id);
+        }
+        if self.release_port_on_close {
+            self.release_port_on_close = false;
+            release_port(self.local_addr.port);
+        }
     }
 
     /// Get local address
-    pub fn local_addr(&self) -> SocketAddrV4 {
+    pub fn local_addr(&self) -> IpEndpoint {
         self.local_addr
     }
 
     /// Get remote address
-    pub fn remote_addr(&self) -> Option<SocketAddrV4> {
+    pub fn remote_addr(&self) -> Option<IpEndpoint> {
         self.remote_addr
     }
 
     /// Set remote address
-    pub fn set_remote_addr(&mut self, addr: SocketAddrV4) {
-        self.remote_addr = Some(addr);
+    pub fn set_remote_addr(&mut self, addr: impl Into<IpEndpoint>) {
+        self.remote_addr = Some(addr.into());
     }
 
     /// Initialize send sequence
     pub fn init_send_sequence(&mut self, isn: u32) {
         self.send.una = isn;
-        self.send.nxt = isn + 1;
+        self.send.nxt = isn.wrapping_add(1);
         self.send.iss = isn;
     }
 
     /// Initialize receive sequence
     pub fn init_recv_sequence(&mut self, irs: u32) {
-        self.recv.nxt = irs + 1;
+        self.recv.nxt = irs.wrapping_add(1);
         self.recv.irs = irs;
     }
 
     /// Add data to send buffer
+    ///
+    /// Accepts as much as fits and returns the number of bytes queued.
     pub fn buffer_send(&mut self, data: &[u8]) -> Result<usize, TcpError> {
         if !self.state.can_send() {
             return Err(TcpError::InvalidState);
         }
 
-        self.send_buffer.extend(data);
-        Ok(data.len())
+        let len = data.len().min(self.available_window() as usize);
+        if len == 0 && !data.is_empty() {
+            return Err(TcpError::WouldBlock);
+        }
+
+        self.send_buffer.extend(&data[..len]);
+        Ok(len)
     }
 
     /// Get data from receive buffer
@@ -480,12 +695,21 @@ impl TcpControlBlock {
             buffer[i] = byte;
         }
 
+        self.update_recv_window();
         Ok(len)
     }
 
     /// Add data to receive buffer
     pub fn buffer_recv_data(&mut self, data: &[u8]) {
         self.recv_buffer.extend(data);
+        self.update_recv_window();
+    }
+
+    /// Recompute the receive window from free buffer space
+    fn update_recv_window(&mut self) {
+        let queued: usize = self.out_of_order.values().map(Vec::len).sum();
+        let used = (self.recv_buffer.len() + queued) as u32;
+        self.recv.wnd = self.recv_buffer_size.saturating_sub(used);
     }
 
     /// Update RTT estimate
@@ -507,6 +731,8 @@ impl TcpControlBlock {
         if self.rto < 200 {
             self.rto = 200; // Minimum RTO of 200ms
         }
+
+        self.cwnd.srtt = self.rtt;
     }
 
     /// Get current RTO
@@ -514,10 +740,627 @@ impl TcpControlBlock {
         self.rto
     }
 
-    /// Get available window size
-    pub fn available_window(&self) -> u16 {
-        let buffered = self.send_buffer.len() as u16;
-        self.window_size.saturating_sub(buffered)
+    /// Number of segments awaiting acknowledgment
+    pub fn unacked_segments(&self) -> usize {
+        self.retransmit_queue.len()
+    }
+
+    /// Get congestion control algorithm
+    pub fn congestion_algorithm(&self) -> CongestionAlgorithm {
+        self.congestion.algorithm()
+    }
+
+    /// Switch congestion control algorithm, keeping the current window
+    pub fn set_congestion_algorithm(&mut self, algorithm: CongestionAlgorithm) {
+        if self.congestion.algorithm() != algorithm {
+            self.congestion = algorithm.create();
+        }
+    }
+
+    /// Get congestion window (bytes)
+    pub fn cwnd(&self) -> u32 {
+        self.cwnd.cwnd
+    }
+
+    /// Get slow start threshold (bytes)
+    pub fn ssthresh(&self) -> u32 {
+        self.cwnd.ssthresh
+    }
+
+    /// Bytes sent but not yet acknowledged
+    pub fn flight_size(&self) -> u32 {
+        self.send.nxt.wrapping_sub(self.send.una)
+    }
+
+    /// Get free space in the send buffer
+    pub fn available_window(&self) -> u32 {
+        let buffered = self.send_buffer.len() as u32;
+        self.send_buffer_size.saturating_sub(buffered)
+    }
+
+    /// Get the peer's maximum segment size
+    pub fn mss(&self) -> u16 {
+        self.mss
+    }
+
+    /// Get the window scale shifts (peer, local) if scaling is in use
+    pub fn window_scale(&self) -> Option<(u8, u8)> {
+        self.wscale_ok.then_some((self.snd_wscale, self.rcv_wscale))
+    }
+
+    /// Check if selective acknowledgments are in use
+    pub fn sack_enabled(&self) -> bool {
+        self.sack_ok
+    }
+
+    /// Check if timestamps are in use
+    pub fn timestamps_enabled(&self) -> bool {
+        self.ts_ok
+    }
+
+    /// MSS to advertise, derived from the outgoing device's MTU
+    fn local_mss(&self) -> u16 {
+        let (mtu, ip_header) = match self.remote_addr {
+            Some(remote) => (
+                ip::route_mtu(remote.ip).unwrap_or(1500),
+                remote.ip.header_size(),
+            ),
+            None => (1500, Ipv4Header::MIN_SIZE),
+        };
+        let headers = ip_header + TcpHeader::MIN_SIZE;
+        mtu.saturating_sub(headers).min(u16::MAX as usize) as u16
+    }
+
+    /// Largest payload per segment once per-segment options are included
+    fn send_mss(&self) -> u16 {
+        let overhead = if self.ts_ok { TIMESTAMP_LEN as u16 } else { 0 };
+        self.mss.saturating_sub(overhead).max(1)
+    }
+
+    /// Apply the options of the peer's SYN or SYN-ACK
+    ///
+    /// Window scaling, SACK and timestamps stay enabled only if both sides
+    /// offered them.
+    fn negotiate(&mut self, options: &TcpOptions) {
+        let peer_mss = options.mss.unwrap_or(Self::DEFAULT_PEER_MSS);
+        self.mss = peer_mss.min(self.local_mss()).max(1);
+
+        match options.window_scale {
+            Some(shift) if self.wscale_ok => self.snd_wscale = shift,
+            _ => {
+                self.wscale_ok = false;
+                self.snd_wscale = 0;
+                self.rcv_wscale = 0;
+            }
+        }
+
+        self.sack_ok &= options.sack_permitted;
+        self.ts_ok &= options.timestamp.is_some();
+        if let Some(ts) = options.timestamp {
+            self.ts_recent = ts.value;
+        }
+
+        // Congestion control counts in full-sized segments
+        self.cwnd.mss = self.send_mss() as u32;
+        self.cwnd.cwnd = initial_window(self.cwnd.mss);
+    }
+
+    /// Peer window advertised in a segment, with scaling applied
+    fn peer_window(&self, header: &TcpHeader) -> u32 {
+        let window = header.window() as u32;
+        if self.wscale_ok && !header.flags().is_syn() {
+            window << self.snd_wscale
+        } else {
+            window
+        }
+    }
+
+    /// Window field to advertise in an outgoing segment
+    fn advertised_window(&self, flags: u16) -> u16 {
+        // Windows in SYN segments are never scaled
+        let window = if flags & TcpFlags::SYN == 0 && self.wscale_ok {
+            self.recv.wnd >> self.rcv_wscale
+        } else {
+            self.recv.wnd
+        };
+        window.min(u16::MAX as u32) as u16
+    }
+
+    /// Options to send with a segment
+    ///
+    /// SACK blocks only go on segments without data so that they never
+    /// push a full-sized segment over the MSS.
+    fn segment_options(&self, flags: u16, has_payload: bool) -> TcpOptions {
+        let mut options = TcpOptions::new();
+
+        if flags & TcpFlags::SYN != 0 {
+            options.mss = Some(self.local_mss());
+            options.window_scale = self.wscale_ok.then_some(self.rcv_wscale);
+            options.sack_permitted = self.sack_ok;
+        } else if self.sack_ok && !has_payload {
+            options.sack_blocks = self.sack_blocks();
+        }
+
+        if self.ts_ok {
+            options.timestamp = Some(Timestamp {
+                value: timestamp_now(),
+                echo: self.ts_recent,
+            });
+        }
+
+        options
+    }
+
+    /// Describe out-of-order data as SACK blocks, most recent first
+    fn sack_blocks(&self) -> Vec<SackBlock> {
+        let nxt = self.recv.nxt;
+        let mut ranges: Vec<SackBlock> = self
+            .out_of_order
+            .iter()
+            .map(|(&seq, data)| SackBlock {
+                left: seq,
+                right: seq.wrapping_add(data.len() as u32),
+            })
+            .collect();
+        ranges.sort_by_key(|block| block.left.wrapping_sub(nxt));
+
+        let mut blocks: Vec<SackBlock> = Vec::new();
+        for range in ranges {
+            match blocks.last_mut() {
+                Some(last) if seq_le(range.left, last.right) => {
+                    if seq_lt(last.right, range.right) {
+                        last.right = range.right;
+                    }
+                }
+                _ => blocks.push(range),
+            }
+        }
+
+        let recent = self.sack_recent;
+        if let Some(pos) = blocks
+            .iter()
+            .position(|b| seq_le(b.left, recent) && seq_lt(recent, b.right))
+        {
+            let block = blocks.remove(pos);
+            blocks.insert(0, block);
+        }
+
+        blocks
+    }
+
+    /// Mark queued segments covered by the peer's SACK blocks
+    fn mark_sacked(&mut self, blocks: &[SackBlock]) {
+        for (&seq, entry) in self.retransmit_queue.iter_mut() {
+            let end = seq.wrapping_add(entry.seq_len());
+            if blocks
+                .iter()
+                .any(|b| seq_le(b.left, seq) && seq_le(end, b.right))
+            {
+                entry.sacked = true;
+            }
+        }
+    }
+
+    /// Queue data received ahead of `recv.nxt`
+    fn queue_out_of_order(&mut self, seq: u32, data: &[u8]) {
+        let longer = self
+            .out_of_order
+            .get(&seq)
+            .is_none_or(|queued| queued.len() < data.len());
+        if longer {
+            self.out_of_order.insert(seq, data.to_vec());
+        }
+        self.sack_recent = seq;
+        self.update_recv_window();
+    }
+
+    /// Move queued out-of-order data that has become contiguous
+    fn merge_out_of_order(&mut self) {
+        loop {
+            let nxt = self.recv.nxt;
+            let Some(&seq) = self.out_of_order.keys().find(|&&seq| seq_le(seq, nxt)) else {
+                break;
+            };
+
+            let data = self.out_of_order.remove(&seq).unwrap_or_default();
+            let skip = nxt.wrapping_sub(seq) as usize;
+            if skip < data.len() {
+                self.recv_buffer.extend(&data[skip..]);
+                self.recv.nxt = self.recv.nxt.wrapping_add((data.len() - skip) as u32);
+            }
+        }
+
+        self.update_recv_window();
+    }
+
+    /// Get the connection 4-tuple
+    pub fn connection_id(&self) -> Option<TcpConnectionId> {
+        let remote = self.remote_addr?;
+        Some(TcpConnectionId {
+            local_ip: self.local_addr.ip,
+            local_port: self.local_addr.port,
+            remote_ip: remote.ip,
+            remote_port: remote.port,
+        })
+    }
+
+    /// Build and transmit a segment for this connection
+    fn transmit(&self, seq: u32, ack: u32, flags: u16, payload: &[u8]) -> Result<(), SocketError> {
+        let remote = self.remote_addr.ok_or(SocketError::NotConnected)?;
+
+        let mut header = TcpHeader::new(self.local_addr.port, remote.port, seq, ack);
+        header.set_flags(TcpFlags::from_bits_truncate(flags));
+        header.set_window(self.advertised_window(flags));
+
+        let options = self.segment_options(flags, !payload.is_empty());
+        transmit_segment(&header, &options, payload, self.local_addr.ip, remote.ip)
+    }
+
+    /// Transmit a segment occupying sequence space and queue it for
+    /// retransmission
+    ///
+    /// The segment is queued even if it could not be sent right away; the
+    /// retransmission timer will try again.
+    fn transmit_reliable(
+        &mut self,
+        seq: u32,
+        flags: u16,
+        payload: &[u8],
+    ) -> Result<(), SocketError> {
+        let ack = if flags & TcpFlags::ACK != 0 {
+            self.recv.nxt
+        } else {
+            0
+        };
+        let result = self.transmit(seq, ack, flags, payload);
+
+        let now = crate::time::uptime_ms();
+        self.retransmit_queue.insert(
+            seq,
+            RetransmitEntry {
+                flags,
+                payload: payload.to_vec(),
+                sent_at: now,
+                retransmitted: false,
+                sacked: false,
+            },
+        );
+        if self.rto_deadline.is_none() {
+            self.rto_deadline = Some(now + self.rto as u64);
+        }
+
+        result
+    }
+
+    /// Drop acknowledged segments from the retransmission queue
+    ///
+    /// Takes an RTT sample from the echoed timestamp if there is one, or
+    /// else from the segments themselves unless one of them was
+    /// retransmitted (Karn's algorithm). Restarts the retransmission timer
+    /// while data remains outstanding.
+    fn acknowledge(&mut self, ack: u32, ts_echo: Option<u32>) {
+        if seq_lt(self.send.una, ack) && seq_le(ack, self.send.nxt) {
+            self.send.una = ack;
+        }
+
+        let now = crate::time::uptime_ms();
+        let mut acked = false;
+        let mut ambiguous = false;
+        let mut sample = 0;
+
+        self.retransmit_queue.retain(|&seq, entry| {
+            if !seq_le(seq.wrapping_add(entry.seq_len()), ack) {
+                return true;
+            }
+
+            acked = true;
+            ambiguous |= entry.retransmitted;
+            sample = sample.max(now.saturating_sub(entry.sent_at));
+            false
+        });
+
+        if !acked {
+            return;
+        }
+
+        if let Some(echo) = ts_echo {
+            self.update_rtt(timestamp_now().wrapping_sub(echo));
+        } else if !ambiguous {
+            self.update_rtt(sample as u32);
+        }
+        self.retries = 0;
+        self.rto_deadline = if self.retransmit_queue.is_empty() {
+            None
+        } else {
+            Some(now + self.rto as u64)
+        };
+    }
+
+    /// Run the connection's timers
+    ///
+    /// Retransmits the oldest unacknowledged segment when the RTO expires,
+    /// doubling the RTO each time. Returns true once the connection should
+    /// be torn down: TIME_WAIT has lasted 2*MSL or retransmissions have
+    /// been exhausted.
+    fn on_timer(&mut self, now: u64) -> bool {
+        if let Some(deadline) = self.time_wait_deadline {
+            return now >= deadline;
+        }
+
+        match self.rto_deadline {
+            Some(deadline) if now >= deadline => {}
+            _ => return false,
+        }
+
+        if self.retransmit_queue.is_empty() {
+            self.rto_deadline = None;
+            return false;
+        }
+
+        let limit = match self.state {
+            TcpState::SynSent | TcpState::SynReceived => TCP_SYN_RETRIES,
+            _ => TCP_MAX_RETRIES,
+        };
+        if self.retries >= limit {
+            return true;
+        }
+
+        // Only the first timeout of a series reduces the threshold
+        if self.retries == 0 {
+            let flight = self.flight_size();
+            self.cwnd.ssthresh = self.congestion.ssthresh(&self.cwnd, flight, now);
+        }
+        self.cwnd.cwnd = self.cwnd.mss;
+        self.dup_acks = 0;
+        self.recovery = Recovery::Loss {
+            recover: self.send.nxt,
+        };
+
+        self.retries += 1;
+        self.rto = self.rto.saturating_mul(2).min(Self::MAX_RTO);
+        self.rto_deadline = Some(now + self.rto as u64);
+        self.retransmit_oldest(now);
+
+        false
+    }
+
+    /// Retransmit the oldest segment the peer has neither acknowledged nor
+    /// selectively acknowledged
+    fn retransmit_oldest(&mut self, now: u64) {
+        let una = self.send.una;
+        let oldest = self
+            .retransmit_queue
+            .iter_mut()
+            .filter(|(_, entry)| !entry.sacked)
+            .min_by_key(|(&seq, _)| seq.wrapping_sub(una));
+        let Some((&seq, entry)) = oldest else {
+            return;
+        };
+
+        entry.retransmitted = true;
+        entry.sent_at = now;
+        let flags = entry.flags;
+        let payload = entry.payload.clone();
+
+        let ack = if flags & TcpFlags::ACK != 0 {
+            self.recv.nxt
+        } else {
+            0
+        };
+        // A failed retransmission is retried when the timer fires again
+        let _ = self.transmit(seq, ack, flags, &payload);
+    }
+
+    /// Send buffered data and a pending FIN as the windows allow
+    ///
+    /// At most min(cwnd, peer window) bytes are kept in flight.
+    fn push_pending(&mut self) {
+        while !self.send_buffer.is_empty() {
+            let window = self.cwnd.cwnd.min(self.send.wnd);
+            let usable = window.saturating_sub(self.flight_size()) as usize;
+            let len = self
+                .send_buffer
+                .len()
+                .min(self.send_mss() as usize)
+                .min(usable);
+            if len == 0 {
+                break;
+            }
+
+            let data: Vec<u8> = self.send_buffer.drain(..len).collect();
+            let seq = self.send.nxt;
+            // Once queued, data that could not be sent is retransmitted
+            let _ = self.transmit_reliable(seq, TcpFlags::ACK | TcpFlags::PSH, &data);
+            self.send.nxt = self.send.nxt.wrapping_add(len as u32);
+        }
+
+        if self.fin_pending && self.send_buffer.is_empty() {
+            let seq = self.send.nxt;
+            let _ = self.transmit_reliable(seq, TcpFlags::FIN | TcpFlags::ACK, &[]);
+
+            // FIN occupies one sequence number
+            self.send.nxt = self.send.nxt.wrapping_add(1);
+            self.fin_pending = false;
+        }
+    }
+
+    /// Check if our FIN has been sent and acknowledged
+    fn fin_acked(&self) -> bool {
+        !self.fin_pending && self.send.una == self.send.nxt
+    }
+
+    /// Update the congestion window for an ACK of new data
+    ///
+    /// Partial ACKs during recovery retransmit the next missing segment
+    /// (NewReno, RFC 6582).
+    fn on_new_ack(&mut self, ack: u32, acked: u32) {
+        let now = crate::time::uptime_ms();
+        self.dup_acks = 0;
+
+        match self.recovery {
+            Recovery::Fast { recover } if seq_lt(ack, recover) => {
+                // Deflate by the amount acked, then add back one segment
+                self.cwnd.cwnd = self
+                    .cwnd
+                    .cwnd
+                    .saturating_sub(acked)
+                    .saturating_add(self.cwnd.mss)
+                    .max(self.cwnd.mss);
+                self.retransmit_oldest(now);
+            }
+            Recovery::Fast { .. } => {
+                let flight = self.flight_size();
+                self.cwnd.cwnd = self
+                    .cwnd
+                    .ssthresh
+                    .min(flight.max(self.cwnd.mss) + self.cwnd.mss);
+                self.recovery = Recovery::Open;
+            }
+            Recovery::Loss { recover } => {
+                self.congestion.on_ack(&mut self.cwnd, acked, now);
+                if seq_lt(ack, recover) {
+                    self.retransmit_oldest(now);
+                } else {
+                    self.recovery = Recovery::Open;
+                }
+            }
+            Recovery::Open => self.congestion.on_ack(&mut self.cwnd, acked, now),
+        }
+    }
+
+    /// Count a duplicate ACK, entering fast retransmit on the third
+    fn on_dup_ack(&mut self) {
+        self.dup_acks += 1;
+
+        match self.recovery {
+            Recovery::Fast { .. } => {
+                // Each duplicate means a segment has left the network
+                self.cwnd.cwnd = self.cwnd.cwnd.saturating_add(self.cwnd.mss);
+            }
+            Recovery::Open if self.dup_acks == TCP_DUP_ACK_THRESHOLD => {
+                let now = crate::time::uptime_ms();
+                let flight = self.flight_size();
+                self.cwnd.ssthresh = self.congestion.ssthresh(&self.cwnd, flight, now);
+                self.cwnd.cwnd = self.cwnd.ssthresh + TCP_DUP_ACK_THRESHOLD * self.cwnd.mss;
+                self.recovery = Recovery::Fast {
+                    recover: self.send.nxt,
+                };
+                self.retransmit_oldest(now);
+            }
+            _ => {}
+        }
+    }
+
+    /// Acknowledge everything received so far
+    ///
+    /// A lost ACK is recovered by the peer's retransmission, so transmit
+    /// errors are ignored.
+    fn send_ack(&self) {
+        let _ = self.transmit(self.send.nxt, self.recv.nxt, TcpFlags::ACK, &[]);
+    }
+
+    /// Whether `seq` falls inside the receive window
+    fn in_recv_window(&self, seq: u32) -> bool {
+        let end = self.recv.nxt.wrapping_add(self.recv.wnd.max(1));
+        seq_le(self.recv.nxt, seq) && seq_lt(seq, end)
+    }
+
+    /// Process a segment in a synchronized state
+    ///
+    /// Handles acknowledgments, in-order data and FIN, and answers with an
+    /// ACK whenever the segment occupied sequence space.
+    fn process_established(&mut self, header: &TcpHeader, options: &TcpOptions, payload: &[u8]) {
+        let flags = header.flags();
+        let seq = header.seq_num();
+        let mut need_ack = false;
+
+        if self.ts_ok {
+            if let Some(ts) = options.timestamp {
+                // PAWS (RFC 7323): drop segments carrying an old timestamp
+                if seq_lt(ts.value, self.ts_recent) {
+                    self.send_ack();
+                    return;
+                }
+                if seq_le(seq, self.recv.nxt) {
+                    self.ts_recent = ts.value;
+                }
+            }
+        }
+
+        if flags.is_ack() {
+            let ack = header.ack_num();
+            let una = self.send.una;
+            let window = self.peer_window(header);
+
+            if self.sack_ok && !options.sack_blocks.is_empty() {
+                self.mark_sacked(&options.sack_blocks);
+            }
+
+            if seq_lt(una, ack) && seq_le(ack, self.send.nxt) {
+                let ts_echo = options.timestamp.filter(|_| self.ts_ok).map(|ts| ts.echo);
+                self.acknowledge(ack, ts_echo);
+                self.on_new_ack(ack, ack.wrapping_sub(una));
+            } else if ack == una
+                && payload.is_empty()
+                && !flags.is_fin()
+                && window == self.send.wnd
+                && self.flight_size() > 0
+            {
+                self.on_dup_ack();
+            }
+
+            self.send.wnd = window;
+            self.push_pending();
+        }
+
+        if !payload.is_empty() {
+            need_ack = true;
+
+            // Skip any part that has already been received
+            let duplicate = self.recv.nxt.wrapping_sub(seq) as usize;
+            let (start, data) = if seq_lt(seq, self.recv.nxt) {
+                (self.recv.nxt, payload.get(duplicate..).unwrap_or(&[]))
+            } else {
+                (seq, payload)
+            };
+
+            // Keep what fits in the receive window
+            let offset = start.wrapping_sub(self.recv.nxt) as usize;
+            let room = (self.recv.wnd as usize).saturating_sub(offset);
+            let data = &data[..data.len().min(room)];
+
+            if self.state.can_recv() && !data.is_empty() {
+                if offset == 0 {
+                    self.buffer_recv_data(data);
+                    self.recv.nxt = self.recv.nxt.wrapping_add(data.len() as u32);
+                    self.merge_out_of_order();
+                } else {
+                    // Held until the gap is filled; reported in SACK blocks
+                    self.queue_out_of_order(start, data);
+                }
+            }
+        }
+
+        let fin_acked = self.fin_acked();
+        if flags.is_fin() && seq.wrapping_add(payload.len() as u32) == self.recv.nxt {
+            self.recv.nxt = self.recv.nxt.wrapping_add(1);
+            need_ack = true;
+
+            match self.state {
+                TcpState::Established => self.set_state(TcpState::CloseWait),
+                TcpState::FinWait1 if fin_acked => self.set_state(TcpState::TimeWait),
+                TcpState::FinWait1 => self.set_state(TcpState::Closing),
+                TcpState::FinWait2 => self.set_state(TcpState::TimeWait),
+                _ => {}
+            }
+        } else if self.state == TcpState::FinWait1 && fin_acked {
+            // Our FIN has been acknowledged
+            self.set_state(TcpState::FinWait2);
+        }
+
+        if need_ack {
+            self.send_ack();
+        }
     }
 }
 
@@ -529,8 +1372,8 @@ pub struct TcpSendSequence {
     una: u32,
     /// Send next
     nxt: u32,
-    /// Send window
-    wnd: u16,
+    /// Send window (scaled)
+    wnd: u32,
     /// Send urgent pointer
     up: u16,
     /// Segment sequence number used for last window update
@@ -562,7 +1405,7 @@ pub struct TcpRecvSequence {
     /// Receive next
     nxt: u32,
     /// Receive window
-    wnd: u16,
+    wnd: u32,
     /// Receive urgent pointer
     up: u16,
     /// Initial receive sequence number
@@ -570,10 +1413,10 @@ pub struct TcpRecvSequence {
 }
 
 impl TcpRecvSequence {
-    fn new() -> Self {
+    fn new(wnd: u32) -> Self {
         Self {
             nxt: 0,
-            wnd: 65535,
+            wnd,
             up: 0,
             irs: 0,
         }
@@ -590,6 +1433,10 @@ pub struct TcpSocket {
     backlog: u32,
     /// Accept queue for incoming connections
     accept_queue: VecDeque<Arc<Mutex<TcpControlBlock>>>,
+    /// Whether the local port was allocated for this socket
+    owns_port: bool,
+    /// Generic socket options
+    options: SocketOptions,
 }
 
 impl TcpSocket {
@@ -598,10 +1445,8 @@ impl TcpSocket {
         // Allocate ephemeral port
         let port = allocate_ephemeral_port().map_err(|_| SocketError::AddrNotAvail)?;
 
-        let local_addr = SocketAddrV4 {
-            ip: [0, 0, 0, 0], // Bind to any address
-            port,
-        };
+        // Bind to any address
+        let local_addr = IpEndpoint::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
 
         let tcb = Arc::new(Mutex::new(TcpControlBlock::new(local_addr)));
 
@@ -610,9 +1455,16 @@ impl TcpSocket {
             socket_state: SocketState::Closed,
             backlog: 0,
             accept_queue: VecDeque::new(),
+            owns_port: true,
+            options: SocketOptions::default(),
         })
     }
 
+    /// Get the state of the underlying connection
+    pub fn tcb_state(&self) -> TcpState {
+        self.tcb.lock().state
+    }
+
     /// Create TCP socket from existing TCB
     fn from_tcb(tcb: Arc<Mutex<TcpControlBlock>>) -> Self {
         Self {
@@ -620,35 +1472,24 @@ impl TcpSocket {
             socket_state: SocketState::Connected,
             backlog: 0,
             accept_queue: VecDeque::new(),
+            owns_port: false,
+            options: SocketOptions::default(),
         }
     }
 
     /// Send SYN packet to establish connection
     fn send_syn(&mut self) -> Result<(), SocketError> {
         let mut tcb = self.tcb.lock();
-        let remote = tcb.remote_addr.ok_or(SocketError::NotConnected)?;
 
         // Generate initial sequence number
         let isn = generate_isn();
         tcb.init_send_sequence(isn);
 
-        // Build SYN packet
-        let mut header = TcpHeader::new(tcb.local_addr.port, remote.port, tcb.send.iss, 0);
-
-        let mut flags = TcpFlags::empty();
-        flags.set(TcpFlags::SYN);
-        header.set_flags(flags);
-        header.set_window(tcb.recv.wnd);
-
-        // Calculate checksum
-        let src_ip = Ipv4Addr(tcb.local_addr.ip);
-        let dst_ip = Ipv4Addr(remote.ip);
-        header.calculate_checksum(src_ip, dst_ip, &[]);
-
-        // TODO: Send packet via network stack
-        // Integration required with ipv4::send_packet() or equivalent
-        // Example: ipv4::send_packet(dst_ip, IpProtocol::Tcp, &packet_data)?;
-        // For now, just update state
+        let iss = tcb.send.iss;
+        if let Err(err) = tcb.transmit_reliable(iss, TcpFlags::SYN, &[]) {
+            tcb.set_state(TcpState::Closed);
+            return Err(err);
+        }
         tcb.set_state(TcpState::SynSent);
 
         Ok(())
@@ -658,78 +1499,22 @@ impl TcpSocket {
     #[allow(dead_code)]
     fn send_syn_ack(&mut self, seq: u32, ack: u32) -> Result<(), SocketError> {
         let tcb = self.tcb.lock();
-        let remote = tcb.remote_addr.ok_or(SocketError::NotConnected)?;
-
-        // Build SYN-ACK packet
-        let mut header = TcpHeader::new(tcb.local_addr.port, remote.port, seq, ack);
-
-        let mut flags = TcpFlags::empty();
-        flags.set(TcpFlags::SYN);
-        flags.set(TcpFlags::ACK);
-        header.set_flags(flags);
-        header.set_window(tcb.recv.wnd);
-
-        // Calculate checksum
-        let src_ip = Ipv4Addr(tcb.local_addr.ip);
-        let dst_ip = Ipv4Addr(remote.ip);
-        header.calculate_checksum(src_ip, dst_ip, &[]);
-
-        // TODO: Send packet via network stack
-        // Integration required: Build complete IP+TCP packet and transmit
-        // This requires ipv4 layer to provide send_packet() API
-
-        Ok(())
+        tcb.transmit(seq, ack, TcpFlags::SYN | TcpFlags::ACK, &[])
     }
 
     /// Send ACK packet
     #[allow(dead_code)]
     fn send_ack(&mut self) -> Result<(), SocketError> {
         let tcb = self.tcb.lock();
-        let remote = tcb.remote_addr.ok_or(SocketError::NotConnected)?;
-
-        // Build ACK packet
-        let mut header =
-            TcpHeader::new(tcb.local_addr.port, remote.port, tcb.send.nxt, tcb.recv.nxt);
-
-        let mut flags = TcpFlags::empty();
-        flags.set(TcpFlags::ACK);
-        header.set_flags(flags);
-        header.set_window(tcb.recv.wnd);
-
-        // Calculate checksum
-        let src_ip = Ipv4Addr(tcb.local_addr.ip);
-        let dst_ip = Ipv4Addr(remote.ip);
-        header.calculate_checksum(src_ip, dst_ip, &[]);
-
-        // TODO: Send packet via network stack
-        // Integration required: Serialize and transmit TCP ACK packet
-
-        Ok(())
+        tcb.transmit(tcb.send.nxt, tcb.recv.nxt, TcpFlags::ACK, &[])
     }
 
     /// Send FIN packet to close connection
     fn send_fin(&mut self) -> Result<(), SocketError> {
-        let tcb = self.tcb.lock();
-        let remote = tcb.remote_addr.ok_or(SocketError::NotConnected)?;
-
-        // Build FIN packet
-        let mut header =
-            TcpHeader::new(tcb.local_addr.port, remote.port, tcb.send.nxt, tcb.recv.nxt);
-
-        let mut flags = TcpFlags::empty();
-        flags.set(TcpFlags::FIN);
-        flags.set(TcpFlags::ACK);
-        header.set_flags(flags);
-        header.set_window(tcb.recv.wnd);
-
-        // Calculate checksum
-        let src_ip = Ipv4Addr(tcb.local_addr.ip);
-        let dst_ip = Ipv4Addr(remote.ip);
-        header.calculate_checksum(src_ip, dst_ip, &[]);
-
-        // TODO: Send packet via network stack
-        // Integration required: Transmit FIN packet for connection termination
-
+        // The FIN follows any data still buffered
+        let mut tcb = self.tcb.lock();
+        tcb.fin_pending = true;
+        tcb.push_pending();
         Ok(())
     }
 
@@ -741,72 +1526,125 @@ impl TcpSocket {
             return Err(SocketError::NotConnected);
         }
 
-        let remote = tcb.remote_addr.ok_or(SocketError::NotConnected)?;
-
-        // Limit by MSS
-        let send_len = data.len().min(tcb.mss as usize);
-
-        // Build data packet
-        let mut header =
-            TcpHeader::new(tcb.local_addr.port, remote.port, tcb.send.nxt, tcb.recv.nxt);
+        // Buffer the data and send what the windows allow
+        let send_len = tcb.buffer_send(data).map_err(|e| match e {
+            TcpError::WouldBlock => SocketError::WouldBlock,
+            _ => SocketError::NotConnected,
+        })?;
+        tcb.push_pending();
 
-        let mut flags = TcpFlags::empty();
-        flags.set(TcpFlags::ACK);
-        flags.set(TcpFlags::PSH);
-        header.set_flags(flags);
-        header.set_window(tcb.recv.wnd);
+        Ok(send_len)
+    }
 
-        // Calculate checksum
-        let src_ip = Ipv4Addr(tcb.local_addr.ip);
-        let dst_ip = Ipv4Addr(remote.ip);
-        header.calculate_checksum(src_ip, dst_ip, &data[..send_len]);
+    /// Handle a segment arriving on a listening socket
+    ///
+    /// A SYN creates a new connection in SYN_RECEIVED, answers it with a
+    /// SYN-ACK and queues it for `accept`.
+    fn process_listen(
+        &mut self,
+        header: &TcpHeader,
+        options: &TcpOptions,
+        src_ip: IpAddr,
+        dst_ip: IpAddr,
+    ) -> Result<(), TcpError> {
+        let flags = header.flags();
+        if flags.is_rst() {
+            return Ok(());
+        }
+        if !self.tcb.lock().local_addr.accepts(dst_ip) {
+            // Bound to another address or address family
+            let _ = send_reset(header, 0, src_ip, dst_ip);
+            return Err(TcpError::ConnectionNotFound);
+        }
+        if flags.is_ack() {
+            // Nothing has been sent from this socket, so any ACK is bogus
+            let _ = send_reset(header, 0, src_ip, dst_ip);
+            return Err(TcpError::InvalidState);
+        }
+        if !flags.is_syn() {
+            return Err(TcpError::InvalidState);
+        }
 
-        // Update send sequence number
-        tcb.send.nxt = tcb.send.nxt.wrapping_add(send_len as u32);
+        // Forget connections that were reset before being accepted
+        self.accept_queue
+            .retain(|child| child.lock().state != TcpState::Closed);
+        if self.accept_queue.len() >= self.backlog.max(1) as usize {
+            return Err(TcpError::BacklogFull);
+        }
 
-        // TODO: Send packet via network stack
-        // Integration required: Serialize TCP header + payload and transmit
-        // Must integrate with IPv4 layer for packet encapsulation and routing
+        let mut child = TcpControlBlock::new(IpEndpoint::new(dst_ip, header.dst_port()));
+        child.listener_wait = Some(self.tcb.lock().wait.clone());
+        child.set_remote_addr(IpEndpoint::new(src_ip, header.src_port()));
+        child.init_recv_sequence(header.seq_num());
+        child.init_send_sequence(generate_isn());
+        child.negotiate(options);
+        child.send.wnd = header.window() as u32;
+        child.set_state(TcpState::SynReceived);
+
+        // A SYN-ACK that cannot be sent now is retried by the
+        // retransmission timer
+        let iss = child.send.iss;
+        let _ = child.transmit_reliable(iss, TcpFlags::SYN | TcpFlags::ACK, &[]);
+
+        let id = child.connection_id().ok_or(TcpError::InvalidState)?;
+        let child = Arc::new(Mutex::new(child));
+        register_connection(id, child.clone());
+        self.accept_queue.push_back(child);
 
-        Ok(send_len)
+        Ok(())
     }
 
     /// Process incoming TCP segment
+    ///
+    /// The segment checksum must already have been verified.
     pub fn process_segment(
         &mut self,
         header: &TcpHeader,
+        options: &TcpOptions,
         payload: &[u8],
-        src_ip: Ipv4Addr,
-        dst_ip: Ipv4Addr,
+        src_ip: IpAddr,
+        dst_ip: IpAddr,
     ) -> Result<(), TcpError> {
-        // Verify checksum
-        if !header.verify_checksum(src_ip, dst_ip, payload) {
-            return Err(TcpError::InvalidChecksum);
+        if self.tcb.lock().state.is_listening() {
+            return self.process_listen(header, options, src_ip, dst_ip);
         }
 
         let mut tcb = self.tcb.lock();
         let flags = header.flags();
 
+        // Only a reset that is provably for this connection aborts it
+        // (RFC 793, hardened against blind resets by RFC 5961)
+        if flags.is_rst() {
+            match tcb.state {
+                TcpState::Closed | TcpState::Listen => {}
+                TcpState::SynSent => {
+                    // It must acknowledge our SYN to refuse the connection
+                    if flags.is_ack() && header.ack_num() == tcb.send.nxt {
+                        tcb.error = Some(SocketError::ConnRefused);
+                        tcb.terminate();
+                    }
+                }
+                _ => {
+                    let seq = header.seq_num();
+                    if seq == tcb.recv.nxt {
+                        tcb.error = Some(SocketError::ConnReset);
+                        tcb.terminate();
+                    } else if tcb.in_recv_window(seq) {
+                        // Challenge ACK: a genuine peer answers it with a
+                        // reset at exactly RCV.NXT
+                        tcb.send_ack();
+                    }
+                }
+            }
+            return Ok(());
+        }
+
         match tcb.state {
             TcpState::Closed => {
-                // Send RST
+                let _ = send_reset(header, payload.len(), src_ip, dst_ip);
                 Err(TcpError::InvalidState)
             }
-            TcpState::Listen => {
-                if flags.is_syn() && !flags.is_ack() {
-                    // Received SYN, send SYN-ACK
-                    let irs = header.seq_num();
-                    tcb.init_recv_sequence(irs);
-
-                    let isn = generate_isn();
-                    tcb.init_send_sequence(isn);
-
-                    tcb.set_state(TcpState::SynReceived);
-                    Ok(())
-                } else {
-                    Err(TcpError::InvalidState)
-                }
-            }
+            TcpState::Listen => Err(TcpError::InvalidState),
             TcpState::SynSent => {
                 if flags.is_syn() && flags.is_ack() {
                     // Received SYN-ACK
@@ -814,8 +1652,12 @@ impl TcpSocket {
                     if ack == tcb.send.nxt {
                         let irs = header.seq_num();
                         tcb.init_recv_sequence(irs);
-                        tcb.send.una = ack;
+                        tcb.negotiate(options);
+                        let ts_echo = options.timestamp.filter(|_| tcb.ts_ok).map(|ts| ts.echo);
+                        tcb.acknowledge(ack, ts_echo);
+                        tcb.send.wnd = header.window() as u32;
                         tcb.set_state(TcpState::Established);
+                        tcb.send_ack();
                         Ok(())
                     } else {
                         Err(TcpError::InvalidSequence)
@@ -824,7 +1666,11 @@ impl TcpSocket {
                     // Simultaneous open
                     let irs = header.seq_num();
                     tcb.init_recv_sequence(irs);
+                    tcb.negotiate(options);
+                    tcb.send.wnd = header.window() as u32;
                     tcb.set_state(TcpState::SynReceived);
+                    let iss = tcb.send.iss;
+                    let _ = tcb.transmit_reliable(iss, TcpFlags::SYN | TcpFlags::ACK, &[]);
                     Ok(())
                 } else {
                     Err(TcpError::InvalidState)
@@ -835,8 +1681,9 @@ impl TcpSocket {
                     // Received ACK, connection established
                     let ack = header.ack_num();
                     if ack == tcb.send.nxt {
-                        tcb.send.una = ack;
+                        tcb.acknowledge(ack, None);
                         tcb.set_state(TcpState::Established);
+                        tcb.process_established(header, options, payload);
                         Ok(())
                     } else {
                         Err(TcpError::InvalidSequence)
@@ -845,86 +1692,48 @@ impl TcpSocket {
                     Err(TcpError::InvalidState)
                 }
             }
-            TcpState::Established => {
-                if flags.is_fin() {
-                    // Received FIN, go to CLOSE_WAIT
-                    tcb.recv.nxt = header.seq_num().wrapping_add(1);
-                    tcb.set_state(TcpState::CloseWait);
-                    Ok(())
-                } else if flags.is_ack() {
-                    // Update unacknowledged sequence
-                    tcb.send.una = header.ack_num();
-
-                    // Process data
-                    if !payload.is_empty() {
-                        let seq = header.seq_num();
-                        if seq == tcb.recv.nxt {
-                            tcb.buffer_recv_data(payload);
-                            tcb.recv.nxt = tcb.recv.nxt.wrapping_add(payload.len() as u32);
-                        }
-                    }
-                    Ok(())
-                } else {
-                    Ok(())
-                }
-            }
-            TcpState::FinWait1 => {
-                if flags.is_fin() && flags.is_ack() {
-                    // Received FIN+ACK
-                    tcb.recv.nxt = header.seq_num().wrapping_add(1);
-                    tcb.send.una = header.ack_num();
-                    tcb.set_state(TcpState::TimeWait);
-                    Ok(())
-                } else if flags.is_fin() {
-                    // Received FIN only
-                    tcb.recv.nxt = header.seq_num().wrapping_add(1);
-                    tcb.set_state(TcpState::Closing);
-                    Ok(())
-                } else if flags.is_ack() {
-                    // Received ACK only
-                    tcb.send.una = header.ack_num();
-                    tcb.set_state(TcpState::FinWait2);
-                    Ok(())
-                } else {
-                    Err(TcpError::InvalidState)
-                }
-            }
-            TcpState::FinWait2 => {
-                if flags.is_fin() {
-                    // Received FIN
-                    tcb.recv.nxt = header.seq_num().wrapping_add(1);
-                    tcb.set_state(TcpState::TimeWait);
-                    Ok(())
-                } else {
-                    Ok(())
-                }
-            }
-            TcpState::CloseWait => {
-                // Waiting for application to close
+            TcpState::Established
+            | TcpState::FinWait1
+            | TcpState::FinWait2
+            | TcpState::CloseWait => {
+                tcb.process_established(header, options, payload);
                 Ok(())
             }
             TcpState::Closing => {
-                if flags.is_ack() {
+                if flags.is_ack() && !tcb.fin_pending && header.ack_num() == tcb.send.nxt {
                     // Received ACK of our FIN
-                    tcb.send.una = header.ack_num();
+                    tcb.acknowledge(header.ack_num(), None);
                     tcb.set_state(TcpState::TimeWait);
                     Ok(())
+                } else if flags.is_ack() {
+                    // Buffered data still draining ahead of our FIN
+                    tcb.process_established(header, options, payload);
+                    Ok(())
                 } else {
                     Err(TcpError::InvalidState)
                 }
             }
             TcpState::LastAck => {
-                if flags.is_ack() {
+                if flags.is_ack() && !tcb.fin_pending && header.ack_num() == tcb.send.nxt {
                     // Received ACK of our FIN
-                    tcb.send.una = header.ack_num();
-                    tcb.set_state(TcpState::Closed);
+                    tcb.acknowledge(header.ack_num(), None);
+                    tcb.terminate();
+                    Ok(())
+                } else if flags.is_ack() {
+                    // Buffered data still draining ahead of our FIN
+                    tcb.process_established(header, options, payload);
                     Ok(())
                 } else {
                     Err(TcpError::InvalidState)
                 }
             }
             TcpState::TimeWait => {
-                // Wait for all packets to expire
+                // Retransmitted FIN: our final ACK was lost, so acknowledge
+                // it again and restart the 2*MSL timer
+                if flags.is_fin() {
+                    tcb.send_ack();
+                    tcb.set_state(TcpState::TimeWait);
+                }
                 Ok(())
             }
         }
@@ -933,23 +1742,22 @@ impl TcpSocket {
 
 impl Socket for TcpSocket {
     fn bind(&mut self, addr: SocketAddr) -> Result<(), SocketError> {
-        match addr {
-            SocketAddr::V4(addr_v4) => {
-                let mut tcb = self.tcb.lock();
-                if tcb.state != TcpState::Closed {
-                    return Err(SocketError::AlreadyConnected);
-                }
+        let addr = IpEndpoint::try_from(addr)?;
+        let mut tcb = self.tcb.lock();
+        if tcb.state != TcpState::Closed {
+            return Err(SocketError::AlreadyConnected);
+        }
 
-                // Check if port is available
-                bind_port(addr_v4.port).map_err(|_| SocketError::AddrInUse)?;
+        // Check if port is available
+        bind_port(addr.port).map_err(|_| SocketError::AddrInUse)?;
 
-                // Update local address
-                tcb.local_addr = addr_v4;
-                self.socket_state = SocketState::Bound;
-                Ok(())
-            }
-            _ => Err(SocketError::NotSupported),
-        }
+        // Give back the ephemeral port allocated at creation
+        release_port(tcb.local_addr.port);
+
+        // Update local address
+        tcb.local_addr = addr;
+        self.socket_state = SocketState::Bound;
+        Ok(())
     }
 
     fn listen(&mut self, backlog: u32) -> Result<(), SocketError> {
@@ -969,34 +1777,48 @@ impl Socket for TcpSocket {
             return Err(SocketError::InvalidArg);
         }
 
-        // Check accept queue
-        if let Some(new_tcb) = self.accept_queue.pop_front() {
-            let new_socket = TcpSocket::from_tcb(new_tcb);
-            Ok(Arc::new(Mutex::new(new_socket)))
-        } else {
-            Err(SocketError::WouldBlock)
-        }
+        // Hand out the first connection that completed its handshake
+        let pos = self
+            .accept_queue
+            .iter()
+            .position(|tcb| !matches!(tcb.lock().state, TcpState::SynReceived | TcpState::Closed))
+            .ok_or(SocketError::WouldBlock)?;
+
+        let new_tcb = self
+            .accept_queue
+            .remove(pos)
+            .ok_or(SocketError::WouldBlock)?;
+        let new_socket = TcpSocket::from_tcb(new_tcb);
+        Ok(Arc::new(Mutex::new(new_socket)))
     }
 
     fn connect(&mut self, addr: SocketAddr) -> Result<(), SocketError> {
-        match addr {
-            SocketAddr::V4(addr_v4) => {
-                let mut tcb = self.tcb.lock();
-                if tcb.state != TcpState::Closed {
-                    return Err(SocketError::AlreadyConnected);
-                }
+        let addr = IpEndpoint::try_from(addr)?;
+        let mut tcb = self.tcb.lock();
+        if tcb.state != TcpState::Closed {
+            return Err(SocketError::AlreadyConnected);
+        }
 
-                tcb.set_remote_addr(addr_v4);
-                drop(tcb); // Release lock before calling send_syn
+        if tcb.local_addr.ip.is_unspecified() {
+            tcb.local_addr.ip = ip::select_source(addr.ip);
+        } else if tcb.local_addr.ip.is_ipv6() != addr.ip.is_ipv6() {
+            return Err(SocketError::InvalidArg);
+        }
+        tcb.set_remote_addr(addr);
+        let id = tcb.connection_id().ok_or(SocketError::InvalidArg)?;
+        drop(tcb); // Release lock before calling send_syn
 
-                // Initiate 3-way handshake
-                self.send_syn()?;
-                self.socket_state = SocketState::Connecting;
+        // Register before the SYN goes out so the reply finds us
+        register_connection(id, self.tcb.clone());
 
-                Ok(())
-            }
-            _ => Err(SocketError::NotSupported),
+        // Initiate 3-way handshake
+        if let Err(e) = self.send_syn() {
+            unregister_connection(&id);
+            return Err(e);
         }
+        self.socket_state = SocketState::Connecting;
+
+        Ok(())
     }
 
     fn send(&mut self, data: &[u8], _flags: u32) -> Result<usize, SocketError> {
@@ -1006,11 +1828,15 @@ impl Socket for TcpSocket {
         }
         drop(tcb);
 
-        // Send data in segments
+        // Send data in segments; once the send buffer fills up the bytes
+        // queued so far are reported and the caller waits for more room
         let mut total_sent = 0;
         while total_sent < data.len() {
-            let sent = self.send_data(&data[total_sent..])?;
-            total_sent += sent;
+            match self.send_data(&data[total_sent..]) {
+                Ok(sent) => total_sent += sent,
+                Err(SocketError::WouldBlock) if total_sent > 0 => break,
+                Err(err) => return Err(err),
+            }
         }
 
         Ok(total_sent)
@@ -1019,7 +1845,19 @@ impl Socket for TcpSocket {
     fn recv(&mut self, buffer: &mut [u8], _flags: u32) -> Result<usize, SocketError> {
         let mut tcb = self.tcb.lock();
         if !tcb.state.can_recv() && tcb.recv_buffer.is_empty() {
-            return Err(SocketError::NotConnected);
+            if let Some(err) = tcb.error.take() {
+                return Err(err);
+            }
+            return match tcb.state {
+                // Data may still arrive once the handshake completes
+                TcpState::SynSent | TcpState::SynReceived => Err(SocketError::WouldBlock),
+                // The peer's FIN has been received: end of stream
+                TcpState::CloseWait
+                | TcpState::LastAck
+                | TcpState::Closing
+                | TcpState::TimeWait => Ok(0),
+                _ => Err(SocketError::NotConnected),
+            };
         }
 
         tcb.buffer_recv(buffer).map_err(|e| match e {
@@ -1091,59 +1929,116 @@ impl Socket for TcpSocket {
 
         // Close connection
         match tcb.state {
-            TcpState::Listen | TcpState::Closed => {
+            TcpState::Listen => {
+                unregister_listener(local_port);
                 tcb.set_state(TcpState::Closed);
             }
+            TcpState::Closed => {}
             TcpState::SynSent | TcpState::SynReceived => {
-                // TODO: Send RST
-                tcb.set_state(TcpState::Closed);
+                // Abort a half-open connection; in SYN_SENT the peer has
+                // nothing to forget
+                if tcb.state == TcpState::SynReceived {
+                    let _ = tcb.transmit(tcb.send.nxt, 0, TcpFlags::RST, &[]);
+                }
+                tcb.terminate();
             }
             _ => {
-                // Connection established, need proper close
+                // Connection established, need proper close. The port stays
+                // in use until the connection has been torn down.
+                tcb.release_port_on_close = self.owns_port;
                 drop(tcb);
                 self.shutdown(ShutdownHow::Both)?;
                 return Ok(());
             }
         }
 
-        // Release port
-        release_port(local_port);
+        // Release port (accepted connections share the listener's port)
+        if self.owns_port {
+            release_port(local_port);
+        }
         self.socket_state = SocketState::Closed;
 
         Ok(())
     }
 
     fn state(&self) -> SocketState {
-        self.socket_state
+        if self.socket_state != SocketState::Connecting {
+            return self.socket_state;
+        }
+
+        // Follow the handshake started by connect
+        match self.tcb.lock().state {
+            TcpState::SynSent | TcpState::SynReceived => SocketState::Connecting,
+            TcpState::Closed => SocketState::Closed,
+            _ => SocketState::Connected,
+        }
     }
 
-    fn setsockopt(&mut self, _option: SocketOption) -> Result<(), SocketError> {
-        // TODO: Implement socket options
-        Err(SocketError::NotSupported)
+    fn setsockopt(&mut self, option: SocketOption) -> Result<(), SocketError> {
+        match option {
+            SocketOption::TcpCongestion(algorithm) => {
+                self.tcb.lock().set_congestion_algorithm(algorithm);
+                Ok(())
+            }
+            option => self.options.set(option),
+        }
     }
 
-    fn getsockopt(&self, _option: SocketOptionType) -> Result<SocketOption, SocketError> {
-        // TODO: Implement socket options
-        Err(SocketError::NotSupported)
+    fn getsockopt(&self, option: SocketOptionType) -> Result<SocketOption, SocketError> {
+        match option {
+            SocketOptionType::TcpCongestion => Ok(SocketOption::TcpCongestion(
+                self.tcb.lock().congestion_algorithm(),
+            )),
+            option => self.options.get(option),
+        }
     }
 
     fn local_addr(&self) -> Option<SocketAddr> {
         let tcb = self.tcb.lock();
-        Some(SocketAddr::V4(tcb.local_addr))
+        Some(tcb.local_addr.to_socket_addr())
     }
 
     fn peer_addr(&self) -> Option<SocketAddr> {
         let tcb = self.tcb.lock();
-        tcb.remote_addr.map(SocketAddr::V4)
+        tcb.remote_addr.map(|addr| addr.to_socket_addr())
+    }
+
+    fn options(&self) -> SocketOptions {
+        self.options
+    }
+
+    fn poll(&self) -> PollEvents {
+        if self.socket_state != SocketState::Listening {
+            return self.tcb.lock().poll_events();
+        }
+
+        // A listener is readable once a connection can be accepted
+        let acceptable = self
+            .accept_queue
+            .iter()
+            .any(|tcb| !matches!(tcb.lock().state, TcpState::SynReceived | TcpState::Closed));
+        if acceptable {
+            PollEvents::IN
+        } else {
+            PollEvents::empty()
+        }
+    }
+
+    fn wait_queue(&self) -> Arc<WaitQueue> {
+        self.tcb.lock().wait_queue()
+    }
+
+    fn take_error(&mut self) -> Option<SocketError> {
+        self.tcb.lock().take_error()
     }
 }
 
 /// TCP connection identifier (4-tuple)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct TcpConnectionId {
-    pub local_ip: Ipv4Addr,
+    pub local_ip: IpAddr,
     pub local_port: u16,
-    pub remote_ip: Ipv4Addr,
+    pub remote_ip: IpAddr,
     pub remote_port: u16,
 }
 
@@ -1210,6 +2105,66 @@ static TCP_CONNECTION_MANAGER: Mutex<TcpConnectionManager> = Mutex::new(TcpConne
     listeners: BTreeMap::new(),
 });
 
+/// Register a connection with the global connection manager
+pub fn register_connection(id: TcpConnectionId, tcb: Arc<Mutex<TcpControlBlock>>) {
+    TCP_CONNECTION_MANAGER.lock().add_connection(id, tcb);
+}
+
+/// Remove a connection from the global connection manager
+pub fn unregister_connection(id: &TcpConnectionId) {
+    TCP_CONNECTION_MANAGER.lock().remove_connection(id);
+}
+
+/// Register a listening socket with the global connection manager
+pub fn register_listener(port: u16, socket: Arc<Mutex<TcpSocket>>) {
+    TCP_CONNECTION_MANAGER.lock().add_listener(port, socket);
+}
+
+/// Remove a listening socket from the global connection manager
+pub fn unregister_listener(port: u16) {
+    TCP_CONNECTION_MANAGER.lock().remove_listener(port);
+}
+
+/// Maximum segment lifetime (milliseconds)
+pub const TCP_MSL_MS: u64 = 30_000;
+
+/// Retransmissions of a SYN or SYN-ACK before giving up
+pub const TCP_SYN_RETRIES: u32 = 6;
+
+/// Retransmissions of other segments before giving up
+pub const TCP_MAX_RETRIES: u32 = 15;
+
+/// Duplicate ACKs that trigger a fast retransmit
+pub const TCP_DUP_ACK_THRESHOLD: u32 = 3;
+
+/// Interval between TCP timer runs (milliseconds)
+pub const TCP_TIMER_INTERVAL_MS: u64 = 100;
+
+/// Run retransmission and TIME_WAIT timers of all connections
+///
+/// Connections whose timers have expired for good are torn down and
+/// removed from the connection manager.
+pub fn timer_tick() {
+    let now = crate::time::uptime_ms();
+    let connections: Vec<_> = TCP_CONNECTION_MANAGER
+        .lock()
+        .connections
+        .values()
+        .cloned()
+        .collect();
+
+    for tcb in connections {
+        let mut tcb = tcb.lock();
+        if tcb.on_timer(now) {
+            if tcb.state != TcpState::TimeWait {
+                // Retransmissions gave up
+                tcb.error = Some(SocketError::TimedOut);
+            }
+            tcb.terminate();
+        }
+    }
+}
+
 /// TCP port range for ephemeral ports
 pub const TCP_PORT_MIN: u16 = 32768;
 pub const TCP_PORT_MAX: u16 = 60999;
@@ -1315,6 +2270,91 @@ pub fn is_port_bound(port: u16) -> bool {
     TCP_PORT_MANAGER.lock().is_bound(port)
 }
 
+/// Serialize a TCP segment and hand it to the IP layer
+///
+/// Fills in the header length and checksum for the given options.
+pub fn transmit_segment(
+    header: &TcpHeader,
+    options: &TcpOptions,
+    payload: &[u8],
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+) -> Result<(), SocketError> {
+    // Options and payload are checksummed together as the segment body
+    let mut body = options.to_bytes();
+    let options_len = body.len();
+    body.extend_from_slice(payload);
+
+    let mut header = *header;
+    header.set_data_offset(TcpHeader::MIN_SIZE + options_len);
+    header.calculate_checksum(src_ip, dst_ip, &body);
+
+    let mut segment = Vec::with_capacity(TcpHeader::MIN_SIZE + body.len());
+    segment.resize(TcpHeader::MIN_SIZE, 0);
+    header
+        .write_to(&mut segment)
+        .map_err(|_| SocketError::InvalidArg)?;
+    segment.extend_from_slice(&body);
+
+    ip::send_packet(src_ip, dst_ip, IpProtocol::Tcp, &segment)
+}
+
+/// Answer a segment that belongs to no connection with a reset
+///
+/// Follows RFC 793: the reset takes its sequence number from the offending
+/// segment's ACK, or acknowledges the segment when it carried none. Resets
+/// are never answered, nor is traffic sent to broadcast or multicast
+/// addresses.
+pub fn send_reset(
+    header: &TcpHeader,
+    payload_len: usize,
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+) -> Result<(), SocketError> {
+    let flags = header.flags();
+    if flags.is_rst() || dst_ip.is_broadcast() || dst_ip.is_multicast() {
+        return Ok(());
+    }
+
+    let reset = if flags.is_ack() {
+        let mut reset = TcpHeader::new(header.dst_port(), header.src_port(), header.ack_num(), 0);
+        reset.set_flags(TcpFlags::from_bits_truncate(TcpFlags::RST));
+        reset
+    } else {
+        let seg_len = payload_len as u32 + flags.is_syn() as u32 + flags.is_fin() as u32;
+        let ack = header.seq_num().wrapping_add(seg_len);
+        let mut reset = TcpHeader::new(header.dst_port(), header.src_port(), 0, ack);
+        reset.set_flags(TcpFlags::from_bits_truncate(TcpFlags::RST | TcpFlags::ACK));
+        reset
+    };
+
+    transmit_segment(&reset, &TcpOptions::new(), &[], dst_ip, src_ip)
+}
+
+/// Smallest window scale shift able to advertise `buffer` bytes
+fn window_scale_for(buffer: u32) -> u8 {
+    let mut shift = 0;
+    while shift < options::MAX_WINDOW_SCALE && buffer >> shift > u16::MAX as u32 {
+        shift += 1;
+    }
+    shift
+}
+
+/// Current value of the timestamps option clock (milliseconds)
+fn timestamp_now() -> u32 {
+    crate::time::uptime_ms() as u32
+}
+
+/// Sequence number comparison `a < b` (modulo 2^32)
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// Sequence number comparison `a <= b` (modulo 2^32)
+fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
 /// Generate initial sequence number
 ///
 /// # Security Note
@@ -1338,18 +2378,21 @@ fn generate_isn() -> u32 {
 /// Process incoming TCP packet
 pub fn process_packet(
     ip_payload: &[u8],
-    src_ip: Ipv4Addr,
-    dst_ip: Ipv4Addr,
+    src_ip: impl Into<IpAddr>,
+    dst_ip: impl Into<IpAddr>,
 ) -> Result<(), TcpError> {
+    let (src_ip, dst_ip) = (src_ip.into(), dst_ip.into());
     let header = TcpHeader::parse(ip_payload)?;
     let payload_offset = header.data_offset();
     let payload = &ip_payload[payload_offset..];
 
-    // Verify checksum
-    if !header.verify_checksum(src_ip, dst_ip, payload) {
+    // Verify checksum over options and payload
+    if !header.verify_checksum(src_ip, dst_ip, &ip_payload[TcpHeader::MIN_SIZE..]) {
         return Err(TcpError::InvalidChecksum);
     }
 
+    let options = TcpOptions::parse(&ip_payload[TcpHeader::MIN_SIZE..payload_offset])?;
+
     // Look up connection
     let conn_id = TcpConnectionId {
         local_ip: dst_ip,
@@ -1358,21 +2401,34 @@ pub fn process_packet(
         remote_port: header.src_port(),
     };
 
+    // Connections whose local address is unspecified match any destination
+    let wildcard_id = TcpConnectionId {
+        local_ip: dst_ip.unspecified(),
+        ..conn_id
+    };
+
     let manager = TCP_CONNECTION_MANAGER.lock();
 
-    if let Some(tcb) = manager.get_connection(&conn_id) {
+    if let Some(tcb) = manager
+        .get_connection(&conn_id)
+        .or_else(|| manager.get_connection(&wildcard_id))
+    {
         // Existing connection
         drop(manager);
-        let mut socket = TcpSocket::from_tcb(tcb);
-        socket.process_segment(&header, payload, src_ip, dst_ip)?;
+        let mut socket = TcpSocket::from_tcb(tcb.clone());
+        let result = socket.process_segment(&header, &options, payload, src_ip, dst_ip);
+        // Data, acknowledgments and FINs all unblock waiters
+        tcb.lock().wake();
+        result?;
     } else if let Some(listener) = manager.get_listener(header.dst_port()) {
         // Listening socket
         drop(manager);
         let mut listener = listener.lock();
-        listener.process_segment(&header, payload, src_ip, dst_ip)?;
+        listener.process_segment(&header, &options, payload, src_ip, dst_ip)?;
     } else {
-        // No matching connection or listener
-        // TODO: Send RST
+        // No matching connection or listener: refuse with a reset
+        drop(manager);
+        let _ = send_reset(&header, payload.len(), src_ip, dst_ip);
         return Err(TcpError::ConnectionNotFound);
     }
 
@@ -1388,6 +2444,8 @@ pub enum TcpError {
     BufferTooSmall,
     /// Invalid header length
     InvalidHeaderLength,
+    /// Malformed options
+    InvalidOptions,
     /// Invalid checksum
     InvalidChecksum,
     /// Invalid sequence number
@@ -1402,6 +2460,8 @@ pub enum TcpError {
     ConnectionNotFound,
     /// Operation would block
     WouldBlock,
+    /// Listen backlog is full
+    BacklogFull,
 }
 
 /// TCP subsystem initialized flag
@@ -1413,6 +2473,10 @@ pub fn init() {
         return;
     }
 
+    if crate::time::timer::create_periodic_timer(TCP_TIMER_INTERVAL_MS, timer_tick).is_err() {
+        crate::printk::printk("  Failed to start TCP timers\n");
+    }
+
     TCP_INITIALIZED.store(true, Ordering::Release);
 }
 
@@ -1424,6 +2488,8 @@ pub fn is_initialized() -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::net::socket::SocketAddrV4;
+    use alloc::vec;
 
     #[test]
     fn test_tcp_header() {
@@ -1524,19 +2590,450 @@ mod tests {
     #[test]
     fn test_connection_id() {
         let id1 = TcpConnectionId {
-            local_ip: Ipv4Addr::new(192, 168, 1, 1),
+            local_ip: Ipv4Addr::new(192, 168, 1, 1).into(),
             local_port: 12345,
-            remote_ip: Ipv4Addr::new(192, 168, 1, 2),
+            remote_ip: Ipv4Addr::new(192, 168, 1, 2).into(),
             remote_port: 80,
         };
 
         let id2 = TcpConnectionId {
-            local_ip: Ipv4Addr::new(192, 168, 1, 1),
+            local_ip: Ipv4Addr::new(192, 168, 1, 1).into(),
             local_port: 12345,
-            remote_ip: Ipv4Addr::new(192, 168, 1, 2),
+            remote_ip: Ipv4Addr::new(192, 168, 1, 2).into(),
             remote_port: 80,
         };
 
         assert_eq!(id1, id2);
     }
+
+    /// Serialize a segment as it would arrive from the network
+    fn segment(
+        src: (Ipv4Addr, u16),
+        dst: (Ipv4Addr, u16),
+        seq: u32,
+        ack: u32,
+        flags: u16,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut header = TcpHeader::new(src.1, dst.1, seq, ack);
+        header.set_flags(TcpFlags::from_bits_truncate(flags));
+        header.set_window(65535);
+        header.calculate_checksum(src.0, dst.0, payload);
+
+        let mut bytes = vec![0u8; TcpHeader::MIN_SIZE];
+        header.write_to(&mut bytes).unwrap();
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn test_passive_open() {
+        let client = (Ipv4Addr::new(10, 0, 0, 2), 40000);
+        let server = (Ipv4Addr::new(10, 0, 0, 1), 7001);
+
+        let listener = Arc::new(Mutex::new(TcpSocket::new().unwrap()));
+        {
+            let mut sock = listener.lock();
+            sock.bind(SocketAddr::V4(SocketAddrV4 {
+                ip: [0, 0, 0, 0],
+                port: server.1,
+            }))
+            .unwrap();
+            sock.listen(4).unwrap();
+        }
+        register_listener(server.1, listener.clone());
+
+        // SYN creates a half-open connection that cannot be accepted yet
+        let syn = segment(client, server, 5000, 0, TcpFlags::SYN, &[]);
+        process_packet(&syn, client.0, server.0).unwrap();
+        assert!(matches!(
+            listener.lock().accept(),
+            Err(SocketError::WouldBlock)
+        ));
+
+        let child = listener.lock().accept_queue[0].clone();
+        let iss = {
+            let tcb = child.lock();
+            assert_eq!(tcb.state(), TcpState::SynReceived);
+            assert_eq!(tcb.recv.nxt, 5001);
+            tcb.send.iss
+        };
+
+        // Final ACK of the handshake, then data
+        let ack = segment(
+            client,
+            server,
+            5001,
+            iss.wrapping_add(1),
+            TcpFlags::ACK,
+            &[],
+        );
+        process_packet(&ack, client.0, server.0).unwrap();
+        assert_eq!(child.lock().state(), TcpState::Established);
+
+        let data = segment(
+            client,
+            server,
+            5001,
+            iss.wrapping_add(1),
+            TcpFlags::ACK | TcpFlags::PSH,
+            b"hello",
+        );
+        process_packet(&data, client.0, server.0).unwrap();
+
+        let accepted = listener.lock().accept().unwrap();
+        let mut buffer = [0u8; 16];
+        let len = accepted.lock().recv(&mut buffer, 0).unwrap();
+        assert_eq!(&buffer[..len], b"hello");
+
+        // FIN moves the connection to CLOSE_WAIT
+        let fin = segment(
+            client,
+            server,
+            5006,
+            iss.wrapping_add(1),
+            TcpFlags::FIN | TcpFlags::ACK,
+            &[],
+        );
+        process_packet(&fin, client.0, server.0).unwrap();
+        assert_eq!(child.lock().state(), TcpState::CloseWait);
+
+        unregister_connection(&child.lock().connection_id().unwrap());
+        listener.lock().close().unwrap();
+    }
+
+    #[test]
+    fn test_syn_at_sequence_wrap() {
+        let client = (Ipv4Addr::new(10, 0, 0, 2), 40001);
+        let server = (Ipv4Addr::new(10, 0, 0, 1), 7002);
+
+        let listener = Arc::new(Mutex::new(TcpSocket::new().unwrap()));
+        {
+            let mut sock = listener.lock();
+            sock.bind(SocketAddr::V4(SocketAddrV4 {
+                ip: [0, 0, 0, 0],
+                port: server.1,
+            }))
+            .unwrap();
+            sock.listen(4).unwrap();
+        }
+        register_listener(server.1, listener.clone());
+
+        let syn = segment(client, server, u32::MAX, 0, TcpFlags::SYN, &[]);
+        process_packet(&syn, client.0, server.0).unwrap();
+
+        let child = listener.lock().accept_queue[0].clone();
+        assert_eq!(child.lock().recv.nxt, 0);
+
+        unregister_connection(&child.lock().connection_id().unwrap());
+        listener.lock().close().unwrap();
+    }
+
+    /// Connection over loopback whose segments are never answered
+    fn loopback_tcb(local_port: u16, remote_port: u16) -> TcpControlBlock {
+        crate::net::loopback::init();
+
+        let mut tcb = TcpControlBlock::new(SocketAddrV4 {
+            ip: Ipv4Addr::LOCALHOST.0,
+            port: local_port,
+        });
+        tcb.set_remote_addr(SocketAddrV4 {
+            ip: Ipv4Addr::LOCALHOST.0,
+            port: remote_port,
+        });
+        tcb.init_send_sequence(1000);
+        tcb
+    }
+
+    #[test]
+    fn test_retransmission_backoff() {
+        let mut tcb = loopback_tcb(9300, 9301);
+        tcb.transmit_reliable(1000, TcpFlags::SYN, &[]).unwrap();
+        tcb.set_state(TcpState::SynSent);
+        assert_eq!(tcb.unacked_segments(), 1);
+
+        // Each expiry retransmits and doubles the RTO
+        let now = crate::time::uptime_ms();
+        assert!(!tcb.on_timer(now + 1000));
+        assert_eq!(tcb.rto(), 2000);
+        assert!(!tcb.on_timer(now + 3000));
+        assert_eq!(tcb.rto(), 4000);
+
+        // Karn: no RTT sample from a retransmitted segment, backoff is kept
+        tcb.acknowledge(1001, None);
+        assert_eq!(tcb.unacked_segments(), 0);
+        assert_eq!(tcb.rto(), 4000);
+        assert_eq!(tcb.rto_deadline, None);
+    }
+
+    #[test]
+    fn test_retransmission_gives_up() {
+        let mut tcb = loopback_tcb(9302, 9303);
+        tcb.transmit_reliable(1000, TcpFlags::SYN, &[]).unwrap();
+        tcb.set_state(TcpState::SynSent);
+
+        let mut now = crate::time::uptime_ms();
+        for _ in 0..TCP_SYN_RETRIES {
+            now += tcb.rto() as u64;
+            assert!(!tcb.on_timer(now));
+        }
+        now += tcb.rto() as u64;
+        assert!(tcb.on_timer(now));
+    }
+
+    #[test]
+    fn test_rtt_sample() {
+        let mut tcb = loopback_tcb(9304, 9305);
+        tcb.set_state(TcpState::Established);
+        tcb.transmit_reliable(1001, TcpFlags::ACK | TcpFlags::PSH, b"data")
+            .unwrap();
+
+        // Partial acknowledgment keeps the segment queued
+        tcb.acknowledge(1003, None);
+        assert_eq!(tcb.unacked_segments(), 1);
+
+        tcb.send.nxt = 1005;
+        tcb.acknowledge(1005, None);
+        assert_eq!(tcb.unacked_segments(), 0);
+        assert_eq!(tcb.send.una, 1005);
+        assert_eq!(tcb.rto(), 200);
+    }
+
+    /// Established loopback connection using NewReno with a wide peer window
+    fn established_tcb(local_port: u16, remote_port: u16) -> TcpControlBlock {
+        let mut tcb = loopback_tcb(local_port, remote_port);
+        tcb.set_state(TcpState::Established);
+        tcb.set_congestion_algorithm(CongestionAlgorithm::NewReno);
+        tcb.send.una = 1001;
+        tcb.send.wnd = 65535;
+        tcb
+    }
+
+    /// ACK segment from the peer of `tcb`
+    fn peer_ack(tcb: &TcpControlBlock, ack: u32) -> TcpHeader {
+        let mut header = TcpHeader::new(tcb.remote_addr.unwrap().port, tcb.local_addr.port, 0, ack);
+        header.set_flags(TcpFlags::from_bits_truncate(TcpFlags::ACK));
+        header.set_window(65535);
+        header
+    }
+
+    #[test]
+    fn test_cwnd_limits_sending() {
+        let mut tcb = established_tcb(9310, 9311);
+        let iw = tcb.cwnd();
+
+        tcb.buffer_send(&[0u8; 20000]).unwrap();
+        tcb.push_pending();
+        assert_eq!(tcb.flight_size(), iw);
+        assert_eq!(tcb.send_buffer.len(), 20000 - iw as usize);
+
+        // Slow start: each ACK opens the window by one segment
+        tcb.process_established(&peer_ack(&tcb, 1001 + 1460), &TcpOptions::new(), &[]);
+        assert_eq!(tcb.cwnd(), iw + 1460);
+        assert_eq!(tcb.flight_size(), iw + 1460);
+    }
+
+    #[test]
+    fn test_fast_retransmit_and_recovery() {
+        let mut tcb = established_tcb(9312, 9313);
+        tcb.buffer_send(&[0u8; 5 * 1460]).unwrap();
+        tcb.push_pending();
+        assert_eq!(tcb.flight_size(), 7300);
+
+        // Three duplicate ACKs: retransmit and enter fast recovery
+        for _ in 0..TCP_DUP_ACK_THRESHOLD {
+            tcb.process_established(&peer_ack(&tcb, 1001), &TcpOptions::new(), &[]);
+        }
+        assert_eq!(tcb.ssthresh(), 3650);
+        assert_eq!(tcb.cwnd(), 3650 + 3 * 1460);
+        assert_eq!(tcb.recovery, Recovery::Fast { recover: 8301 });
+
+        // Partial ACK keeps recovering
+        tcb.process_established(&peer_ack(&tcb, 1001 + 1460), &TcpOptions::new(), &[]);
+        assert_eq!(tcb.recovery, Recovery::Fast { recover: 8301 });
+
+        // Full ACK leaves recovery with a deflated window
+        tcb.process_established(&peer_ack(&tcb, 8301), &TcpOptions::new(), &[]);
+        assert_eq!(tcb.recovery, Recovery::Open);
+        assert_eq!(tcb.cwnd(), 2 * 1460);
+    }
+
+    #[test]
+    fn test_timeout_collapses_cwnd() {
+        let mut tcb = established_tcb(9314, 9315);
+        tcb.buffer_send(&[0u8; 4 * 1460]).unwrap();
+        tcb.push_pending();
+
+        let now = crate::time::uptime_ms();
+        assert!(!tcb.on_timer(now + tcb.rto() as u64));
+        assert_eq!(tcb.cwnd(), 1460);
+        assert_eq!(tcb.ssthresh(), 2 * 1460);
+        assert_eq!(
+            tcb.recovery,
+            Recovery::Loss {
+                recover: 1001 + 4 * 1460
+            }
+        );
+    }
+
+    #[test]
+    fn test_option_negotiation() {
+        let mut tcb = loopback_tcb(9320, 9321);
+        let mut offer = TcpOptions::new();
+        offer.mss = Some(1000);
+        offer.window_scale = Some(7);
+        offer.sack_permitted = true;
+        offer.timestamp = Some(Timestamp { value: 42, echo: 0 });
+        tcb.negotiate(&offer);
+
+        assert_eq!(tcb.mss(), 1000);
+        assert_eq!(tcb.window_scale(), Some((7, 3)));
+        assert!(tcb.sack_enabled());
+        assert!(tcb.timestamps_enabled());
+        assert_eq!(tcb.send_mss(), 1000 - TIMESTAMP_LEN as u16);
+
+        let mut header = TcpHeader::new(9321, 9320, 0, 0);
+        header.set_flags(TcpFlags::from_bits_truncate(TcpFlags::ACK));
+        header.set_window(100);
+        assert_eq!(tcb.peer_window(&header), 100 << 7);
+        assert_eq!(
+            tcb.advertised_window(TcpFlags::ACK) as u32,
+            TcpControlBlock::DEFAULT_BUFFER_SIZE >> 3
+        );
+
+        // A peer offering nothing gets the RFC defaults
+        let mut plain = loopback_tcb(9322, 9323);
+        plain.negotiate(&TcpOptions::new());
+        assert_eq!(plain.mss(), TcpControlBlock::DEFAULT_PEER_MSS);
+        assert_eq!(plain.window_scale(), None);
+        assert!(!plain.sack_enabled());
+        assert!(!plain.timestamps_enabled());
+        assert_eq!(plain.peer_window(&header), 100);
+    }
+
+    #[test]
+    fn test_out_of_order_sack() {
+        let mut tcb = established_tcb(9324, 9325);
+        tcb.init_recv_sequence(4999);
+
+        let segment = |seq: u32| {
+            let mut header = TcpHeader::new(9325, 9324, seq, 1001);
+            header.set_flags(TcpFlags::from_bits_truncate(TcpFlags::ACK));
+            header.set_window(65535);
+            header
+        };
+
+        // A hole at 5000..5010 holds back the later data
+        tcb.process_established(&segment(5010), &TcpOptions::new(), &[2; 10]);
+        assert_eq!(tcb.recv.nxt, 5000);
+        assert!(tcb.recv_buffer.is_empty());
+        assert_eq!(
+            tcb.sack_blocks(),
+            vec![SackBlock {
+                left: 5010,
+                right: 5020
+            }]
+        );
+
+        // Filling the hole delivers both segments in order
+        tcb.process_established(&segment(5000), &TcpOptions::new(), &[1; 10]);
+        assert_eq!(tcb.recv.nxt, 5020);
+        assert_eq!(tcb.recv_buffer.len(), 20);
+        assert_eq!(tcb.recv_buffer[9], 1);
+        assert_eq!(tcb.recv_buffer[10], 2);
+        assert!(tcb.sack_blocks().is_empty());
+
+        // A duplicate is trimmed rather than delivered twice
+        tcb.process_established(&segment(5015), &TcpOptions::new(), &[3; 10]);
+        assert_eq!(tcb.recv.nxt, 5025);
+        assert_eq!(tcb.recv_buffer.len(), 25);
+    }
+
+    #[test]
+    fn test_time_wait_reaper() {
+        let tcb = Arc::new(Mutex::new(loopback_tcb(9306, 9307)));
+        let id = tcb.lock().connection_id().unwrap();
+        register_connection(id, tcb.clone());
+
+        tcb.lock().set_state(TcpState::TimeWait);
+        let now = crate::time::uptime_ms();
+        assert!(!tcb.lock().on_timer(now + TCP_MSL_MS));
+        assert!(tcb.lock().on_timer(now + 2 * TCP_MSL_MS));
+
+        tcb.lock().terminate();
+        assert_eq!(tcb.lock().state(), TcpState::Closed);
+        assert!(TCP_CONNECTION_MANAGER.lock().get_connection(&id).is_none());
+    }
+
+    /// RST from the peer of `tcb`
+    fn peer_reset(tcb: &TcpControlBlock, seq: u32, ack: Option<u32>) -> TcpHeader {
+        let remote = tcb.remote_addr.unwrap().port;
+        let mut header = TcpHeader::new(remote, tcb.local_addr.port, seq, ack.unwrap_or(0));
+        let flags = match ack {
+            Some(_) => TcpFlags::RST | TcpFlags::ACK,
+            None => TcpFlags::RST,
+        };
+        header.set_flags(TcpFlags::from_bits_truncate(flags));
+        header
+    }
+
+    fn deliver(socket: &mut TcpSocket, header: &TcpHeader) {
+        let tcb = socket.tcb.lock();
+        let (src, dst) = (tcb.remote_addr.unwrap().ip, tcb.local_addr.ip);
+        drop(tcb);
+        socket
+            .process_segment(header, &TcpOptions::new(), &[], src, dst)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_reset_needs_exact_sequence() {
+        let mut tcb = established_tcb(9326, 9327);
+        tcb.init_recv_sequence(4999);
+        let mut socket = TcpSocket::from_tcb(Arc::new(Mutex::new(tcb)));
+        let (nxt, wnd) = {
+            let tcb = socket.tcb.lock();
+            (tcb.recv.nxt, tcb.recv.wnd)
+        };
+        assert!(wnd > 10);
+
+        // Outside the window: dropped
+        let reset = peer_reset(&socket.tcb.lock(), nxt.wrapping_sub(1), None);
+        deliver(&mut socket, &reset);
+        let reset = peer_reset(&socket.tcb.lock(), nxt.wrapping_add(wnd), None);
+        deliver(&mut socket, &reset);
+        assert_eq!(socket.tcb.lock().state(), TcpState::Established);
+
+        // In the window but not at RCV.NXT: only challenged
+        let reset = peer_reset(&socket.tcb.lock(), nxt + 10, None);
+        deliver(&mut socket, &reset);
+        assert_eq!(socket.tcb.lock().state(), TcpState::Established);
+        assert!(socket.tcb.lock().error.is_none());
+
+        // Exactly at RCV.NXT: the connection is reset
+        let reset = peer_reset(&socket.tcb.lock(), nxt, None);
+        deliver(&mut socket, &reset);
+        assert_eq!(socket.tcb.lock().state(), TcpState::Closed);
+        assert_eq!(socket.tcb.lock().error, Some(SocketError::ConnReset));
+    }
+
+    #[test]
+    fn test_reset_in_syn_sent_needs_ack_of_syn() {
+        let mut tcb = loopback_tcb(9328, 9329);
+        tcb.transmit_reliable(1000, TcpFlags::SYN, &[]).unwrap();
+        tcb.set_state(TcpState::SynSent);
+        let mut socket = TcpSocket::from_tcb(Arc::new(Mutex::new(tcb)));
+        let snd_nxt = socket.tcb.lock().send.nxt;
+
+        for ack in [None, Some(snd_nxt + 1)] {
+            let reset = peer_reset(&socket.tcb.lock(), 0, ack);
+            deliver(&mut socket, &reset);
+            assert_eq!(socket.tcb.lock().state(), TcpState::SynSent);
+        }
+
+        let reset = peer_reset(&socket.tcb.lock(), 0, Some(snd_nxt));
+        deliver(&mut socket, &reset);
+        assert_eq!(socket.tcb.lock().state(), TcpState::Closed);
+        assert_eq!(socket.tcb.lock().error, Some(SocketError::ConnRefused));
+    }
 }
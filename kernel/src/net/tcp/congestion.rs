@@ -0,0 +1,317 @@
+//! TCP Congestion Control
+//!
+//! Pluggable congestion window growth and loss response (RFC 5681,
+//! RFC 6582, RFC 8312). Loss detection and recovery are driven by the
+//! control block; algorithms only decide how the window grows and how far
+//! it is cut.
+
+use alloc::boxed::Box;
+
+/// Congestion window state of a connection (all sizes in bytes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CongestionWindow {
+    /// Congestion window
+    pub cwnd: u32,
+    /// Slow start threshold
+    pub ssthresh: u32,
+    /// Sender maximum segment size
+    pub mss: u32,
+    /// Smoothed round-trip time (milliseconds, 0 if unknown)
+    pub srtt: u32,
+}
+
+impl CongestionWindow {
+    /// Create window state with the RFC 6928 initial window
+    pub fn new(mss: u32) -> Self {
+        Self {
+            cwnd: initial_window(mss),
+            ssthresh: u32::MAX,
+            mss,
+            srtt: 0,
+        }
+    }
+
+    /// Check if the connection is in slow start
+    pub fn in_slow_start(&self) -> bool {
+        self.cwnd < self.ssthresh
+    }
+
+    /// Grow the window by at most one segment per ACK (RFC 5681)
+    pub fn slow_start(&mut self, acked: u32) {
+        let increase = acked.min(self.mss);
+        self.cwnd = self
+            .cwnd
+            .saturating_add(increase)
+            .min(self.ssthresh.max(self.cwnd));
+    }
+}
+
+/// Initial window (RFC 6928)
+pub fn initial_window(mss: u32) -> u32 {
+    (10 * mss).min((2 * mss).max(14600))
+}
+
+/// Congestion control algorithm
+pub trait CongestionControl: Send {
+    /// Get algorithm identifier
+    fn algorithm(&self) -> CongestionAlgorithm;
+
+    /// Grow the window after `acked` bytes of new data were acknowledged
+    /// outside fast recovery
+    fn on_ack(&mut self, window: &mut CongestionWindow, acked: u32, now: u64);
+
+    /// Slow start threshold after a loss was detected
+    fn ssthresh(&mut self, window: &CongestionWindow, flight_size: u32, now: u64) -> u32;
+}
+
+/// Available congestion control algorithms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CongestionAlgorithm {
+    /// NewReno (RFC 5681, RFC 6582)
+    NewReno,
+    /// CUBIC (RFC 8312)
+    #[default]
+    Cubic,
+}
+
+impl CongestionAlgorithm {
+    /// Get algorithm name
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::NewReno => "reno",
+            Self::Cubic => "cubic",
+        }
+    }
+
+    /// Look up algorithm by name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "reno" | "newreno" => Some(Self::NewReno),
+            "cubic" => Some(Self::Cubic),
+            _ => None,
+        }
+    }
+
+    /// Create a fresh instance of the algorithm
+    pub fn create(self) -> Box<dyn CongestionControl> {
+        match self {
+            Self::NewReno => Box::new(NewReno),
+            Self::Cubic => Box::new(Cubic::new()),
+        }
+    }
+}
+
+/// NewReno congestion control
+///
+/// Additive increase of one segment per RTT, window halved on loss.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NewReno;
+
+impl CongestionControl for NewReno {
+    fn algorithm(&self) -> CongestionAlgorithm {
+        CongestionAlgorithm::NewReno
+    }
+
+    fn on_ack(&mut self, window: &mut CongestionWindow, acked: u32, _now: u64) {
+        if window.in_slow_start() {
+            window.slow_start(acked);
+        } else {
+            let increase = (window.mss * window.mss / window.cwnd.max(1)).max(1);
+            window.cwnd = window.cwnd.saturating_add(increase);
+        }
+    }
+
+    fn ssthresh(&mut self, window: &CongestionWindow, flight_size: u32, _now: u64) -> u32 {
+        (flight_size / 2).max(2 * window.mss)
+    }
+}
+
+/// CUBIC congestion control
+///
+/// The window follows a cubic function of the time since the last loss,
+/// centred on the window size at which that loss happened.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cubic {
+    /// Window before the last reduction (bytes)
+    w_max: u32,
+    /// Start of the current congestion avoidance epoch (milliseconds)
+    epoch_start: Option<u64>,
+    /// Time to reach `w_max` from the epoch start (milliseconds)
+    k: u64,
+    /// Window at the epoch start (bytes)
+    origin: u32,
+}
+
+impl Cubic {
+    /// Multiplicative decrease factor (x10)
+    const BETA: u64 = 7;
+
+    /// Cubic scaling constant C (x10)
+    const C: u64 = 4;
+
+    /// Create new CUBIC state
+    pub const fn new() -> Self {
+        Self {
+            w_max: 0,
+            epoch_start: None,
+            k: 0,
+            origin: 0,
+        }
+    }
+
+    /// Window given by the cubic function `t` ms into the epoch (bytes)
+    fn cubic_window(&self, t: u64, mss: u32) -> u64 {
+        // W(t) = C * (t - K)^3 + W_max, with t and K in seconds and W in
+        // segments
+        let delta = t.abs_diff(self.k) as u128;
+        let offset = Self::C as u128 * mss as u128 * delta * delta * delta / 10_000_000_000;
+        let offset = offset.min(u32::MAX as u128) as u64;
+
+        if t >= self.k {
+            self.origin as u64 + offset
+        } else {
+            (self.origin as u64).saturating_sub(offset)
+        }
+    }
+
+    /// Window a Reno flow would have reached `t` ms into the epoch (bytes)
+    fn reno_window(&self, t: u64, window: &CongestionWindow) -> u64 {
+        if window.srtt == 0 {
+            return 0;
+        }
+
+        // W_est = W_max * beta + 3 * (1 - beta) / (1 + beta) * t / RTT
+        let base = self.w_max as u64 * Self::BETA / 10;
+        base + 9 * window.mss as u64 * t / (17 * window.srtt as u64)
+    }
+}
+
+impl CongestionControl for Cubic {
+    fn algorithm(&self) -> CongestionAlgorithm {
+        CongestionAlgorithm::Cubic
+    }
+
+    fn on_ack(&mut self, window: &mut CongestionWindow, acked: u32, now: u64) {
+        if window.in_slow_start() {
+            window.slow_start(acked);
+            return;
+        }
+
+        let epoch_start = match self.epoch_start {
+            Some(start) => start,
+            None => {
+                if window.cwnd < self.w_max {
+                    // K = cbrt(W_max * (1 - beta) / C), in milliseconds
+                    let segments = (self.w_max - window.cwnd) as u64 * 1_000_000_000
+                        / (window.mss as u64 * Self::C / 10).max(1);
+                    self.k = cbrt(segments);
+                    self.origin = self.w_max;
+                } else {
+                    self.k = 0;
+                    self.origin = window.cwnd;
+                }
+                self.epoch_start = Some(now);
+                now
+            }
+        };
+
+        let t = now.saturating_sub(epoch_start) + window.srtt as u64;
+        let target = self
+            .cubic_window(t, window.mss)
+            .max(self.reno_window(t, window));
+        let cwnd = window.cwnd as u64;
+
+        // Move towards the target over one RTT, or probe slowly beyond it
+        let increase = if target > cwnd {
+            (target - cwnd) * acked as u64 / cwnd
+        } else {
+            window.mss as u64 * acked as u64 / (100 * cwnd)
+        };
+        window.cwnd = (cwnd + increase.max(1)).min(u32::MAX as u64) as u32;
+    }
+
+    fn ssthresh(&mut self, window: &CongestionWindow, _flight_size: u32, _now: u64) -> u32 {
+        self.epoch_start = None;
+
+        // Fast convergence: yield to newer flows when the window shrinks
+        let cwnd = window.cwnd as u64;
+        self.w_max = if cwnd < self.w_max as u64 {
+            (cwnd * (10 + Self::BETA) / 20) as u32
+        } else {
+            window.cwnd
+        };
+
+        ((cwnd * Self::BETA / 10) as u32).max(2 * window.mss)
+    }
+}
+
+/// Integer cube root
+fn cbrt(value: u64) -> u64 {
+    let mut low = 0u64;
+    let mut high = 1u64 << 22;
+
+    while low < high {
+        let mid = (low + high).div_ceil(2);
+        if (mid as u128).pow(3) <= value as u128 {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+
+    low
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cbrt() {
+        assert_eq!(cbrt(0), 0);
+        assert_eq!(cbrt(27), 3);
+        assert_eq!(cbrt(1_000_000_000), 1000);
+        assert_eq!(cbrt(1_000_000_001), 1000);
+    }
+
+    #[test]
+    fn test_newreno() {
+        let mut reno = NewReno;
+        let mut window = CongestionWindow::new(1000);
+        assert_eq!(window.cwnd, 10_000);
+
+        // Slow start grows by one segment per ACK
+        reno.on_ack(&mut window, 1000, 0);
+        assert_eq!(window.cwnd, 11_000);
+
+        // Loss halves the flight, then additive increase
+        window.ssthresh = reno.ssthresh(&window, 11_000, 0);
+        assert_eq!(window.ssthresh, 5500);
+        window.cwnd = window.ssthresh;
+        reno.on_ack(&mut window, 1000, 0);
+        assert_eq!(window.cwnd, 5500 + 1000 * 1000 / 5500);
+    }
+
+    #[test]
+    fn test_cubic() {
+        let mut cubic = Cubic::new();
+        let mut window = CongestionWindow::new(1000);
+        window.cwnd = 100_000;
+        window.srtt = 100;
+
+        window.ssthresh = cubic.ssthresh(&window, 100_000, 0);
+        assert_eq!(window.ssthresh, 70_000);
+        window.cwnd = window.ssthresh;
+
+        // Concave region: growth slows down approaching W_max
+        cubic.on_ack(&mut window, 1000, 0);
+        assert!(window.cwnd > 70_000 && window.cwnd < 100_000);
+        assert!(cubic.k > 0);
+
+        // Well past K, the window exceeds the previous maximum
+        for i in 1..200 {
+            cubic.on_ack(&mut window, 1000, i * 100);
+        }
+        assert!(window.cwnd > 100_000);
+    }
+}
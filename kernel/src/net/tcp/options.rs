@@ -0,0 +1,230 @@
+//! TCP Options
+//!
+//! Parsing and serialization of the options following the fixed TCP
+//! header: MSS (RFC 9293), window scale and timestamps (RFC 7323) and
+//! selective acknowledgments (RFC 2018).
+
+use alloc::vec::Vec;
+
+use super::TcpError;
+
+/// End of option list
+pub const KIND_END: u8 = 0;
+/// No operation (padding)
+pub const KIND_NOP: u8 = 1;
+/// Maximum segment size
+pub const KIND_MSS: u8 = 2;
+/// Window scale
+pub const KIND_WINDOW_SCALE: u8 = 3;
+/// SACK permitted
+pub const KIND_SACK_PERMITTED: u8 = 4;
+/// SACK blocks
+pub const KIND_SACK: u8 = 5;
+/// Timestamps
+pub const KIND_TIMESTAMP: u8 = 8;
+
+/// Largest window scale shift (RFC 7323)
+pub const MAX_WINDOW_SCALE: u8 = 14;
+
+/// Largest number of SACK blocks in one segment
+pub const MAX_SACK_BLOCKS: usize = 4;
+
+/// Maximum length of the options area
+pub const MAX_OPTIONS_LEN: usize = 40;
+
+/// Encoded length of the timestamps option, including padding
+pub const TIMESTAMP_LEN: usize = 12;
+
+/// Block of data received out of order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SackBlock {
+    /// First sequence number of the block
+    pub left: u32,
+    /// Sequence number following the block
+    pub right: u32,
+}
+
+/// Timestamps option values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    /// Sender's timestamp clock (TSval)
+    pub value: u32,
+    /// Most recent timestamp received from the peer (TSecr)
+    pub echo: u32,
+}
+
+/// Options carried by a TCP segment
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TcpOptions {
+    /// Maximum segment size (SYN only)
+    pub mss: Option<u16>,
+    /// Window scale shift (SYN only)
+    pub window_scale: Option<u8>,
+    /// SACK permitted (SYN only)
+    pub sack_permitted: bool,
+    /// SACK blocks
+    pub sack_blocks: Vec<SackBlock>,
+    /// Timestamps
+    pub timestamp: Option<Timestamp>,
+}
+
+impl TcpOptions {
+    /// Create empty options
+    pub const fn new() -> Self {
+        Self {
+            mss: None,
+            window_scale: None,
+            sack_permitted: false,
+            sack_blocks: Vec::new(),
+            timestamp: None,
+        }
+    }
+
+    /// Parse the options area of a segment
+    ///
+    /// Unknown options and known options with an unexpected length are
+    /// skipped; an option running past the end of the area is an error.
+    pub fn parse(data: &[u8]) -> Result<Self, TcpError> {
+        let mut options = Self::new();
+        let mut i = 0;
+
+        while i < data.len() {
+            match data[i] {
+                KIND_END => break,
+                KIND_NOP => {
+                    i += 1;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let kind = data[i];
+            let len = *data.get(i + 1).ok_or(TcpError::InvalidOptions)? as usize;
+            if len < 2 || i + len > data.len() {
+                return Err(TcpError::InvalidOptions);
+            }
+            let value = &data[i + 2..i + len];
+
+            match (kind, value.len()) {
+                (KIND_MSS, 2) => {
+                    options.mss = Some(u16::from_be_bytes([value[0], value[1]]));
+                }
+                (KIND_WINDOW_SCALE, 1) => {
+                    options.window_scale = Some(value[0].min(MAX_WINDOW_SCALE));
+                }
+                (KIND_SACK_PERMITTED, 0) => options.sack_permitted = true,
+                (KIND_SACK, n) if n > 0 && n % 8 == 0 && n / 8 <= MAX_SACK_BLOCKS => {
+                    options.sack_blocks = value
+                        .chunks_exact(8)
+                        .map(|block| SackBlock {
+                            left: u32::from_be_bytes([block[0], block[1], block[2], block[3]]),
+                            right: u32::from_be_bytes([block[4], block[5], block[6], block[7]]),
+                        })
+                        .collect();
+                }
+                (KIND_TIMESTAMP, 8) => {
+                    options.timestamp = Some(Timestamp {
+                        value: u32::from_be_bytes([value[0], value[1], value[2], value[3]]),
+                        echo: u32::from_be_bytes([value[4], value[5], value[6], value[7]]),
+                    });
+                }
+                _ => {}
+            }
+
+            i += len;
+        }
+
+        Ok(options)
+    }
+
+    /// Serialize options, each padded with NOPs to a 4-byte boundary
+    ///
+    /// SACK blocks that do not fit in the options area are left out.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MAX_OPTIONS_LEN);
+
+        if let Some(mss) = self.mss {
+            bytes.extend_from_slice(&[KIND_MSS, 4]);
+            bytes.extend_from_slice(&mss.to_be_bytes());
+        }
+        if let Some(shift) = self.window_scale {
+            bytes.extend_from_slice(&[KIND_NOP, KIND_WINDOW_SCALE, 3, shift]);
+        }
+        if self.sack_permitted {
+            bytes.extend_from_slice(&[KIND_NOP, KIND_NOP, KIND_SACK_PERMITTED, 2]);
+        }
+        if let Some(ts) = self.timestamp {
+            bytes.extend_from_slice(&[KIND_NOP, KIND_NOP, KIND_TIMESTAMP, 10]);
+            bytes.extend_from_slice(&ts.value.to_be_bytes());
+            bytes.extend_from_slice(&ts.echo.to_be_bytes());
+        }
+
+        let room = (MAX_OPTIONS_LEN - bytes.len()).saturating_sub(4) / 8;
+        let blocks = self.sack_blocks.len().min(room).min(MAX_SACK_BLOCKS);
+        if blocks > 0 {
+            bytes.extend_from_slice(&[KIND_NOP, KIND_NOP, KIND_SACK, (2 + 8 * blocks) as u8]);
+            for block in &self.sack_blocks[..blocks] {
+                bytes.extend_from_slice(&block.left.to_be_bytes());
+                bytes.extend_from_slice(&block.right.to_be_bytes());
+            }
+        }
+
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_syn_options_roundtrip() {
+        let options = TcpOptions {
+            mss: Some(1460),
+            window_scale: Some(7),
+            sack_permitted: true,
+            sack_blocks: Vec::new(),
+            timestamp: Some(Timestamp {
+                value: 12345,
+                echo: 0,
+            }),
+        };
+
+        let bytes = options.to_bytes();
+        assert_eq!(bytes.len(), 24);
+        assert_eq!(bytes.len() % 4, 0);
+        assert_eq!(TcpOptions::parse(&bytes).unwrap(), options);
+    }
+
+    #[test]
+    fn test_sack_blocks_limited_by_space() {
+        let block = SackBlock {
+            left: 100,
+            right: 200,
+        };
+        let options = TcpOptions {
+            sack_blocks: vec![block; 4],
+            timestamp: Some(Timestamp { value: 1, echo: 2 }),
+            ..TcpOptions::new()
+        };
+
+        // Timestamps leave room for three blocks
+        let bytes = options.to_bytes();
+        assert!(bytes.len() <= MAX_OPTIONS_LEN);
+        assert_eq!(TcpOptions::parse(&bytes).unwrap().sack_blocks.len(), 3);
+    }
+
+    #[test]
+    fn test_malformed_options() {
+        // Length runs past the end of the options area
+        assert_eq!(
+            TcpOptions::parse(&[KIND_MSS, 4, 5]),
+            Err(TcpError::InvalidOptions)
+        );
+
+        // Unknown option is skipped, END stops parsing
+        let options = TcpOptions::parse(&[30, 3, 0, KIND_END, KIND_MSS]).unwrap();
+        assert_eq!(options, TcpOptions::new());
+    }
+}
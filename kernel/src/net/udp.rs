@@ -2,13 +2,17 @@
 //!
 //! UDP packet handling and port management.
 
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use spin::Mutex;
 
-use super::ipv4::{calculate_pseudo_header_checksum, IpProtocol, Ipv4Addr};
-use super::socket::{SocketAddrV4, SocketError};
+use super::ip::{self, IpAddr, IpEndpoint};
+use super::ipv4::IpProtocol;
+use super::socket::SocketError;
+use crate::process::wait_queue::WaitQueue;
 
 /// UDP header (8 bytes)
 #[derive(Debug, Clone, Copy)]
@@ -87,7 +91,12 @@ impl UdpHeader {
     }
 
     /// Calculate checksum
-    pub fn calculate_checksum(&mut self, src_ip: Ipv4Addr, dst_ip: Ipv4Addr, payload: &[u8]) {
+    pub fn calculate_checksum(
+        &mut self,
+        src_ip: impl Into<IpAddr>,
+        dst_ip: impl Into<IpAddr>,
+        payload: &[u8],
+    ) {
         // Zero out checksum field
         self.checksum = 0;
 
@@ -105,7 +114,7 @@ impl UdpHeader {
 
         // Calculate pseudo-header checksum
         let mut sum =
-            calculate_pseudo_header_checksum(src_ip, dst_ip, IpProtocol::Udp, length as u16);
+            ip::pseudo_header_checksum(src_ip.into(), dst_ip.into(), IpProtocol::Udp, length);
 
         // Add UDP header and payload checksum
         for chunk in buffer.chunks(2) {
@@ -130,10 +139,16 @@ impl UdpHeader {
     }
 
     /// Verify checksum
-    pub fn verify_checksum(&self, src_ip: Ipv4Addr, dst_ip: Ipv4Addr, payload: &[u8]) -> bool {
+    pub fn verify_checksum(
+        &self,
+        src_ip: impl Into<IpAddr>,
+        dst_ip: impl Into<IpAddr>,
+        payload: &[u8],
+    ) -> bool {
+        let src_ip = src_ip.into();
         if self.checksum() == 0 {
-            // Checksum is optional in UDP; 0 means no checksum
-            return true;
+            // Checksum is optional in UDP over IPv4 only (RFC 8200 8.1)
+            return !src_ip.is_ipv6();
         }
 
         let mut header = *self;
@@ -284,15 +299,68 @@ pub fn is_port_bound(port: u16) -> bool {
     UDP_PORT_MANAGER.lock().is_bound(port)
 }
 
+/// Maximum number of datagrams queued on a socket
+pub const UDP_RECV_QUEUE_LEN: usize = 64;
+
+/// Datagrams waiting to be read, with their source addresses
+pub type DatagramQueue = Arc<Mutex<VecDeque<(Vec<u8>, IpEndpoint)>>>;
+
+/// Receive endpoint of a bound socket
+struct UdpBinding {
+    /// Local address the socket accepts datagrams for
+    local: IpEndpoint,
+    /// Socket receive queue
+    queue: DatagramQueue,
+    /// Tasks waiting for a datagram
+    wait: Arc<WaitQueue>,
+}
+
+/// Bound sockets by local port
+static UDP_BINDINGS: Mutex<BTreeMap<u16, UdpBinding>> = Mutex::new(BTreeMap::new());
+
+/// Register a socket receive queue for a local address
+fn register_binding(local: IpEndpoint, queue: DatagramQueue, wait: Arc<WaitQueue>) {
+    UDP_BINDINGS
+        .lock()
+        .insert(local.port, UdpBinding { local, queue, wait });
+}
+
+/// Remove the receive queue registered for a local port
+fn unregister_binding(port: u16) {
+    UDP_BINDINGS.lock().remove(&port);
+}
+
+/// Queue a datagram on the socket bound to its destination
+fn deliver(dst_ip: IpAddr, dst_port: u16, src: IpEndpoint, payload: &[u8]) -> Result<(), UdpError> {
+    let bindings = UDP_BINDINGS.lock();
+    let binding = bindings.get(&dst_port).ok_or(UdpError::NotBound)?;
+
+    if !binding.local.accepts(dst_ip) {
+        return Err(UdpError::NotBound);
+    }
+
+    let mut queue = binding.queue.lock();
+    if queue.len() >= UDP_RECV_QUEUE_LEN {
+        return Err(UdpError::QueueFull);
+    }
+
+    queue.push_back((payload.to_vec(), src));
+    drop(queue);
+    binding.wait.wake_all();
+    Ok(())
+}
+
 /// UDP socket implementation
 #[derive(Default)]
 pub struct UdpSocket {
     /// Local address
-    local_addr: Option<SocketAddrV4>,
+    local_addr: Option<IpEndpoint>,
     /// Remote address
-    remote_addr: Option<SocketAddrV4>,
-    /// Receive buffer
-    recv_buffer: Vec<(Vec<u8>, SocketAddrV4)>,
+    remote_addr: Option<IpEndpoint>,
+    /// Receive buffer (shared with the receive path)
+    recv_buffer: DatagramQueue,
+    /// Tasks waiting for a datagram (woken by the receive path)
+    wait: Arc<WaitQueue>,
 }
 
 impl UdpSocket {
@@ -301,56 +369,63 @@ impl UdpSocket {
         Ok(Self {
             local_addr: None,
             remote_addr: None,
-            recv_buffer: Vec::new(),
+            recv_buffer: Arc::new(Mutex::new(VecDeque::new())),
+            wait: Arc::new(WaitQueue::new()),
         })
     }
 
     /// Bind socket to local address
-    pub fn bind(&mut self, addr: SocketAddrV4) -> Result<(), SocketError> {
+    pub fn bind(&mut self, addr: impl Into<IpEndpoint>) -> Result<(), SocketError> {
+        let addr = addr.into();
         if self.local_addr.is_some() {
             return Err(SocketError::AlreadyConnected);
         }
 
         bind_port(addr.port).map_err(|_| SocketError::AddrInUse)?;
         self.local_addr = Some(addr);
+        register_binding(addr, self.recv_buffer.clone(), self.wait.clone());
         Ok(())
     }
 
     /// Connect socket to remote address
-    pub fn connect(&mut self, addr: SocketAddrV4) -> Result<(), SocketError> {
+    pub fn connect(&mut self, addr: impl Into<IpEndpoint>) -> Result<(), SocketError> {
+        let addr = addr.into();
+
         // UDP connect is just setting the default destination
         self.remote_addr = Some(addr);
 
         // If not bound, bind to ephemeral port
         if self.local_addr.is_none() {
             let port = allocate_ephemeral_port().map_err(|_| SocketError::AddrNotAvail)?;
-            self.local_addr = Some(SocketAddrV4 {
-                ip: [0, 0, 0, 0], // Bind to any address
-                port,
-            });
+            // Bind to any address of the remote's family
+            let local = IpEndpoint::new(addr.ip.unspecified(), port);
+            self.local_addr = Some(local);
+            register_binding(local, self.recv_buffer.clone(), self.wait.clone());
         }
 
         Ok(())
     }
 
     /// Send data to address
-    pub fn send_to(&mut self, data: &[u8], dst: SocketAddrV4) -> Result<usize, SocketError> {
+    pub fn send_to(
+        &mut self,
+        data: &[u8],
+        dst: impl Into<IpEndpoint>,
+    ) -> Result<usize, SocketError> {
+        let dst = dst.into();
         let local = self.local_addr.ok_or(SocketError::NotConnected)?;
+        let src_ip = match local.ip {
+            ip if ip.is_unspecified() => ip::select_source(dst.ip),
+            ip => ip,
+        };
 
         // Build UDP packet
-        let mut buffer = [0u8; 1600];
-        let _packet_len = build_udp_packet(
-            &mut buffer,
-            Ipv4Addr(local.ip),
-            local.port,
-            Ipv4Addr(dst.ip),
-            dst.port,
-            data,
-        )
-        .map_err(|_| SocketError::InvalidArg)?;
-
-        // TODO: Send packet via network stack
-        // For now, just return success
+        let mut buffer = vec![0u8; UdpHeader::SIZE + data.len()];
+        let packet_len = build_udp_packet(&mut buffer, src_ip, local.port, dst.ip, dst.port, data)
+            .map_err(|_| SocketError::InvalidArg)?;
+
+        ip::send_packet(src_ip, dst.ip, IpProtocol::Udp, &buffer[..packet_len])?;
+
         Ok(data.len())
     }
 
@@ -361,12 +436,12 @@ impl UdpSocket {
     }
 
     /// Receive data from any address
-    pub fn recv_from(&mut self, buffer: &mut [u8]) -> Result<(usize, SocketAddrV4), SocketError> {
-        if self.recv_buffer.is_empty() {
-            return Err(SocketError::WouldBlock);
-        }
-
-        let (data, addr) = self.recv_buffer.remove(0);
+    pub fn recv_from(&mut self, buffer: &mut [u8]) -> Result<(usize, IpEndpoint), SocketError> {
+        let (data, addr) = self
+            .recv_buffer
+            .lock()
+            .pop_front()
+            .ok_or(SocketError::WouldBlock)?;
         let len = data.len().min(buffer.len());
         buffer[..len].copy_from_slice(&data[..len]);
         Ok((len, addr))
@@ -378,24 +453,35 @@ impl UdpSocket {
         Ok(len)
     }
 
+    /// Check if a datagram is waiting to be read
+    pub fn has_pending(&self) -> bool {
+        !self.recv_buffer.lock().is_empty()
+    }
+
+    /// Get the wait queue woken when a datagram arrives
+    pub fn wait_queue(&self) -> Arc<WaitQueue> {
+        self.wait.clone()
+    }
+
     /// Get local address
-    pub fn local_addr(&self) -> Option<SocketAddrV4> {
+    pub fn local_addr(&self) -> Option<IpEndpoint> {
         self.local_addr
     }
 
     /// Get remote address
-    pub fn remote_addr(&self) -> Option<SocketAddrV4> {
+    pub fn remote_addr(&self) -> Option<IpEndpoint> {
         self.remote_addr
     }
 
     /// Close socket
     pub fn close(&mut self) -> Result<(), SocketError> {
         if let Some(addr) = self.local_addr {
+            unregister_binding(addr.port);
             release_port(addr.port);
         }
         self.local_addr = None;
         self.remote_addr = None;
-        self.recv_buffer.clear();
+        self.recv_buffer.lock().clear();
         Ok(())
     }
 }
@@ -403,9 +489,9 @@ impl UdpSocket {
 /// Build UDP packet
 pub fn build_udp_packet(
     buffer: &mut [u8],
-    src_ip: Ipv4Addr,
+    src_ip: impl Into<IpAddr>,
     src_port: u16,
-    dst_ip: Ipv4Addr,
+    dst_ip: impl Into<IpAddr>,
     dst_port: u16,
     payload: &[u8],
 ) -> Result<usize, UdpError> {
@@ -430,20 +516,27 @@ pub fn build_udp_packet(
 /// Process incoming UDP packet
 pub fn process_packet(
     ip_payload: &[u8],
-    src_ip: Ipv4Addr,
-    dst_ip: Ipv4Addr,
+    src_ip: impl Into<IpAddr>,
+    dst_ip: impl Into<IpAddr>,
 ) -> Result<(), UdpError> {
+    let (src_ip, dst_ip) = (src_ip.into(), dst_ip.into());
     let packet = UdpPacket::new(ip_payload)?;
     let header = packet.header()?;
 
+    // Trim to the UDP length field
+    let length = header.length() as usize;
+    if length < UdpHeader::SIZE || length > ip_payload.len() {
+        return Err(UdpError::TooShort);
+    }
+    let payload = &ip_payload[UdpHeader::SIZE..length];
+
     // Verify checksum
-    if !header.verify_checksum(src_ip, dst_ip, packet.payload()) {
+    if !header.verify_checksum(src_ip, dst_ip, payload) {
         return Err(UdpError::InvalidChecksum);
     }
 
-    // TODO: Deliver to appropriate socket
-    // For now, just validate the packet
-    Ok(())
+    let src = IpEndpoint::new(src_ip, header.src_port());
+    deliver(dst_ip, header.dst_port(), src, payload)
 }
 
 /// UDP errors
@@ -461,6 +554,8 @@ pub enum UdpError {
     NoPortsAvailable,
     /// Socket not bound
     NotBound,
+    /// Socket receive queue is full
+    QueueFull,
 }
 
 /// UDP subsystem initialized flag
@@ -483,6 +578,8 @@ pub fn is_initialized() -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::net::ipv4::Ipv4Addr;
+    use crate::net::socket::SocketAddrV4;
 
     #[test]
     fn test_udp_header() {
@@ -526,4 +623,32 @@ mod tests {
         assert_ne!(header.checksum(), 0);
         assert!(header.verify_checksum(src_ip, dst_ip, payload));
     }
+
+    #[test]
+    fn test_deliver_to_bound_socket() {
+        let mut socket = UdpSocket::new().unwrap();
+        let local = SocketAddrV4 {
+            ip: [0, 0, 0, 0],
+            port: 5353,
+        };
+        socket.bind(local).unwrap();
+
+        let src_ip = Ipv4Addr::new(10, 0, 0, 2);
+        let dst_ip = Ipv4Addr::new(10, 0, 0, 1);
+        let mut packet = [0u8; 64];
+        let len = build_udp_packet(&mut packet, src_ip, 4000, dst_ip, 5353, b"query").unwrap();
+        process_packet(&packet[..len], src_ip, dst_ip).unwrap();
+
+        let mut buffer = [0u8; 16];
+        let (received, from) = socket.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..received], b"query");
+        assert_eq!(from.ip, IpAddr::V4(src_ip));
+        assert_eq!(from.port, 4000);
+
+        socket.close().unwrap();
+        assert_eq!(
+            process_packet(&packet[..len], src_ip, dst_ip),
+            Err(UdpError::NotBound)
+        );
+    }
 }
@@ -0,0 +1,845 @@
+//! Unix Domain Sockets
+//!
+//! Local stream and datagram sockets that do not touch a network device.
+//! Named sockets are bound to socket inodes in tmpfs; peers find them by
+//! looking the path up. Messages carry the sender's credentials
+//! (SCM_CREDENTIALS) and may carry open descriptors (SCM_RIGHTS).
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::socket::{
+    ControlMessage, Credentials, PollEvents, ReceivedMessage, ShutdownHow, Socket, SocketAddr,
+    SocketAddrUnix, SocketError, SocketOption, SocketOptionType, SocketOptions, SocketState,
+    SocketType,
+};
+use crate::fs::fd::{FdEntry, InFlightFds};
+use crate::fs::filesystems::tmpfs::{self, FileType, InodeNumber};
+use crate::process::wait_queue::WaitQueue;
+
+/// Bytes a socket can have queued for reading
+const QUEUE_CAPACITY: usize = 65536;
+
+/// Most connections waiting to be accepted
+const MAX_BACKLOG: usize = 128;
+
+/// Queued message (stream segment or datagram)
+struct Message {
+    data: Vec<u8>,
+    /// Bound path of the sender
+    from: Option<String>,
+    credentials: Credentials,
+    rights: Option<InFlightFds>,
+}
+
+/// Receive side of a socket
+#[derive(Default)]
+struct Queue {
+    messages: VecDeque<Message>,
+    /// Bytes queued
+    bytes: usize,
+    /// The peer will send no more data
+    eof: bool,
+    /// Receiving was shut down
+    shut_rd: bool,
+    /// Wait queues of senders waiting for room
+    blocked: Vec<Arc<WaitQueue>>,
+    /// Listening for connections
+    listening: bool,
+    /// Connections waiting to be accepted
+    pending: VecDeque<Arc<Mutex<UnixSocket>>>,
+    /// Most pending connections
+    backlog: usize,
+}
+
+/// Receive queue and wait queue of one socket
+///
+/// Shared with connected peers and the binding table, which deliver into
+/// it.
+struct Endpoint {
+    queue: Mutex<Queue>,
+    wait: Arc<WaitQueue>,
+}
+
+impl Endpoint {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            queue: Mutex::new(Queue::default()),
+            wait: Arc::new(WaitQueue::new()),
+        })
+    }
+
+    /// Queue a message
+    ///
+    /// Stream data is accepted up to the free space; a datagram must fit
+    /// entirely. `sender` is woken once room is available again.
+    fn deliver(
+        &self,
+        mut message: Message,
+        stream: bool,
+        sender: &Arc<WaitQueue>,
+    ) -> Result<usize, SocketError> {
+        let mut queue = self.queue.lock();
+        if queue.shut_rd {
+            return Err(if stream {
+                SocketError::BrokenPipe
+            } else {
+                SocketError::ConnRefused
+            });
+        }
+        if !stream && message.data.len() > QUEUE_CAPACITY {
+            return Err(SocketError::InvalidArg);
+        }
+
+        let room = QUEUE_CAPACITY.saturating_sub(queue.bytes);
+        let fits = if stream {
+            room > 0 || message.data.is_empty()
+        } else {
+            message.data.len() <= room
+        };
+        if !fits {
+            if !queue.blocked.iter().any(|wait| Arc::ptr_eq(wait, sender)) {
+                queue.blocked.push(sender.clone());
+            }
+            return Err(SocketError::WouldBlock);
+        }
+
+        if stream {
+            message.data.truncate(room);
+        }
+        let len = message.data.len();
+        queue.bytes += len;
+        queue.messages.push_back(message);
+        drop(queue);
+
+        self.wait.wake_all();
+        Ok(len)
+    }
+
+    /// Mark the end of the peer's data
+    fn set_eof(&self) {
+        self.queue.lock().eof = true;
+        self.wait.wake_all();
+    }
+
+    /// Wake senders waiting for room
+    fn wake_senders(queue: &mut Queue) {
+        for wait in queue.blocked.drain(..) {
+            wait.wake_all();
+        }
+    }
+}
+
+/// Bound socket in the binding table
+struct Binding {
+    socket_type: SocketType,
+    endpoint: Arc<Endpoint>,
+    path: String,
+}
+
+/// Bound sockets by inode
+static BINDINGS: Mutex<BTreeMap<InodeNumber, Binding>> = Mutex::new(BTreeMap::new());
+
+/// Find the socket bound to a path
+fn lookup(path: &str) -> Result<(Arc<Endpoint>, SocketType, String), SocketError> {
+    let inode = tmpfs::global_lookup_path(path).map_err(|_| SocketError::ConnRefused)?;
+    match tmpfs::global_stat_inode(inode) {
+        Some(stat) if stat.file_type == FileType::Socket => {}
+        _ => return Err(SocketError::ConnRefused),
+    }
+
+    let bindings = BINDINGS.lock();
+    let binding = bindings.get(&inode).ok_or(SocketError::ConnRefused)?;
+    Ok((
+        binding.endpoint.clone(),
+        binding.socket_type,
+        binding.path.clone(),
+    ))
+}
+
+/// Convert a socket address to a Unix path
+fn unix_path(addr: SocketAddr) -> Result<String, SocketError> {
+    match addr {
+        SocketAddr::Unix(SocketAddrUnix { path }) if !path.is_empty() => Ok(path),
+        SocketAddr::Unix(_) => Err(SocketError::InvalidArg),
+        _ => Err(SocketError::NotSupported),
+    }
+}
+
+/// Report a Unix path as a socket address (empty for unnamed sockets)
+fn unix_addr(path: Option<&String>) -> SocketAddr {
+    SocketAddr::Unix(SocketAddrUnix {
+        path: path.cloned().unwrap_or_default(),
+    })
+}
+
+/// Unix domain socket
+pub struct UnixSocket {
+    socket_type: SocketType,
+    state: SocketState,
+    options: SocketOptions,
+    /// Receive side
+    endpoint: Arc<Endpoint>,
+    /// Receive side of the connected stream peer
+    peer: Option<Arc<Endpoint>>,
+    /// Local path (bound, or the listener's for accepted sockets)
+    path: Option<String>,
+    /// Inode of the bound path
+    bound: Option<InodeNumber>,
+    /// Path of the peer (stream) or default destination (datagram)
+    peer_path: Option<String>,
+    /// Sending was shut down
+    shut_wr: bool,
+}
+
+impl UnixSocket {
+    /// Create an unbound socket
+    pub fn new(socket_type: SocketType) -> Self {
+        Self {
+            socket_type,
+            state: SocketState::Closed,
+            options: SocketOptions::default(),
+            endpoint: Endpoint::new(),
+            peer: None,
+            path: None,
+            bound: None,
+            peer_path: None,
+            shut_wr: false,
+        }
+    }
+
+    /// Create a pair of connected sockets (`socketpair`)
+    pub fn pair(socket_type: SocketType) -> Result<(Self, Self), SocketError> {
+        if !matches!(socket_type, SocketType::Stream | SocketType::Dgram) {
+            return Err(SocketError::NotSupported);
+        }
+
+        let mut a = Self::new(socket_type);
+        let mut b = Self::new(socket_type);
+        a.peer = Some(b.endpoint.clone());
+        b.peer = Some(a.endpoint.clone());
+        a.state = SocketState::Connected;
+        b.state = SocketState::Connected;
+        Ok((a, b))
+    }
+
+    fn is_stream(&self) -> bool {
+        self.socket_type == SocketType::Stream
+    }
+
+    fn path(&self) -> Option<&String> {
+        self.path.as_ref()
+    }
+
+    /// Check ancillary data and split it into credentials and rights
+    fn check_control(
+        control: &[ControlMessage],
+    ) -> Result<(Credentials, Option<InFlightFds>), SocketError> {
+        let current = Credentials::current();
+        let mut credentials = current;
+        let mut rights: Option<InFlightFds> = None;
+
+        for message in control {
+            match message {
+                ControlMessage::Credentials(creds) => {
+                    // Only root may claim to be someone else
+                    if *creds != current && current.uid != 0 {
+                        return Err(SocketError::PermissionDenied);
+                    }
+                    credentials = *creds;
+                }
+                ControlMessage::Rights(fds) => {
+                    let mut entries = rights
+                        .take()
+                        .map(|r| r.entries().to_vec())
+                        .unwrap_or_default();
+                    entries.extend_from_slice(fds.entries());
+                    rights = Some(InFlightFds::new(entries));
+                }
+            }
+        }
+        Ok((credentials, rights))
+    }
+
+    /// Send a message to a datagram socket bound to `path`
+    fn send_datagram(&self, message: Message, path: &str) -> Result<usize, SocketError> {
+        let (endpoint, socket_type, _) = lookup(path)?;
+        if socket_type != SocketType::Dgram {
+            return Err(SocketError::ConnRefused);
+        }
+        endpoint.deliver(message, false, &self.endpoint.wait)
+    }
+
+    /// Receive from a stream
+    ///
+    /// Reads across queued segments but never past one carrying
+    /// descriptors, so they arrive with the bytes they were sent with.
+    fn recv_stream(&mut self, buffer: &mut [u8]) -> Result<ReceivedMessage, SocketError> {
+        let mut queue = self.endpoint.queue.lock();
+        if queue.messages.is_empty() {
+            if queue.eof || queue.shut_rd {
+                return Ok(ReceivedMessage {
+                    len: 0,
+                    addr: None,
+                    control: Vec::new(),
+                    truncated: false,
+                });
+            }
+            return Err(match self.state {
+                SocketState::Connected => SocketError::WouldBlock,
+                _ => SocketError::NotConnected,
+            });
+        }
+
+        let mut len = 0;
+        let mut credentials = None;
+        let mut rights = None;
+        while len < buffer.len() && rights.is_none() {
+            let Some(message) = queue.messages.front_mut() else {
+                break;
+            };
+            if len > 0
+                && (message.rights.is_some()
+                    || (self.options.passcred && credentials != Some(message.credentials)))
+            {
+                break;
+            }
+
+            credentials.get_or_insert(message.credentials);
+            rights = message.rights.take();
+            let n = message.data.len().min(buffer.len() - len);
+            buffer[len..len + n].copy_from_slice(&message.data[..n]);
+            message.data.drain(..n);
+            len += n;
+            if message.data.is_empty() {
+                queue.messages.pop_front();
+            }
+        }
+        queue.bytes -= len;
+        Endpoint::wake_senders(&mut queue);
+        drop(queue);
+
+        let mut control = Vec::new();
+        if let Some(credentials) = credentials.filter(|_| self.options.passcred) {
+            control.push(ControlMessage::Credentials(credentials));
+        }
+        if let Some(rights) = rights {
+            control.push(ControlMessage::Rights(rights));
+        }
+        Ok(ReceivedMessage {
+            len,
+            addr: self.peer_path.as_ref().map(|path| unix_addr(Some(path))),
+            control,
+            truncated: false,
+        })
+    }
+
+    /// Receive one datagram
+    ///
+    /// The part that does not fit in the buffer is discarded.
+    fn recv_datagram(&mut self, buffer: &mut [u8]) -> Result<ReceivedMessage, SocketError> {
+        let mut queue = self.endpoint.queue.lock();
+        let Some(message) = queue.messages.pop_front() else {
+            return Err(if queue.shut_rd {
+                SocketError::NotConnected
+            } else {
+                SocketError::WouldBlock
+            });
+        };
+        queue.bytes -= message.data.len();
+        Endpoint::wake_senders(&mut queue);
+        drop(queue);
+
+        let len = message.data.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&message.data[..len]);
+
+        let mut control = Vec::new();
+        if self.options.passcred {
+            control.push(ControlMessage::Credentials(message.credentials));
+        }
+        if let Some(rights) = message.rights {
+            control.push(ControlMessage::Rights(rights));
+        }
+        Ok(ReceivedMessage {
+            len,
+            addr: Some(unix_addr(message.from.as_ref())),
+            control,
+            truncated: message.data.len() > len,
+        })
+    }
+
+    /// Drop queued messages and pending connections
+    fn flush(&mut self) {
+        let (messages, pending) = {
+            let mut queue = self.endpoint.queue.lock();
+            queue.shut_rd = true;
+            queue.bytes = 0;
+            Endpoint::wake_senders(&mut queue);
+            (
+                core::mem::take(&mut queue.messages),
+                core::mem::take(&mut queue.pending),
+            )
+        };
+
+        // Queued descriptors are released outside the queue lock
+        drop(messages);
+        for connection in pending {
+            let _ = connection.lock().close();
+        }
+    }
+}
+
+impl Socket for UnixSocket {
+    fn bind(&mut self, addr: SocketAddr) -> Result<(), SocketError> {
+        let path = unix_path(addr)?;
+        if self.path.is_some() {
+            return Err(SocketError::InvalidArg);
+        }
+
+        // The socket file stays behind after close, as on Linux
+        if tmpfs::global_lookup_path(&path).is_ok() {
+            return Err(SocketError::AddrInUse);
+        }
+        let inode = tmpfs::global_create_socket(&path).map_err(|_| SocketError::AddrNotAvail)?;
+
+        BINDINGS.lock().insert(
+            inode,
+            Binding {
+                socket_type: self.socket_type,
+                endpoint: self.endpoint.clone(),
+                path: path.clone(),
+            },
+        );
+        self.path = Some(path);
+        self.bound = Some(inode);
+        if self.state == SocketState::Closed {
+            self.state = SocketState::Bound;
+        }
+        Ok(())
+    }
+
+    fn listen(&mut self, backlog: u32) -> Result<(), SocketError> {
+        if !self.is_stream() {
+            return Err(SocketError::NotSupported);
+        }
+        if self.bound.is_none() || self.peer.is_some() {
+            return Err(SocketError::InvalidArg);
+        }
+
+        let mut queue = self.endpoint.queue.lock();
+        queue.listening = true;
+        queue.backlog = (backlog as usize).clamp(1, MAX_BACKLOG);
+        drop(queue);
+
+        self.state = SocketState::Listening;
+        Ok(())
+    }
+
+    fn accept(&mut self) -> Result<Arc<Mutex<dyn Socket>>, SocketError> {
+        if self.state != SocketState::Listening {
+            return Err(SocketError::InvalidArg);
+        }
+        let connection = self.endpoint.queue.lock().pending.pop_front();
+        match connection {
+            Some(connection) => Ok(connection),
+            None => Err(SocketError::WouldBlock),
+        }
+    }
+
+    fn connect(&mut self, addr: SocketAddr) -> Result<(), SocketError> {
+        let path = unix_path(addr)?;
+
+        if !self.is_stream() {
+            // Datagram sockets just set their default destination
+            lookup(&path)?;
+            self.peer_path = Some(path);
+            self.state = SocketState::Connected;
+            return Ok(());
+        }
+
+        match self.state {
+            SocketState::Connected => return Err(SocketError::AlreadyConnected),
+            SocketState::Listening => return Err(SocketError::InvalidArg),
+            _ => {}
+        }
+
+        let (listener, socket_type, path) = lookup(&path)?;
+        if socket_type != SocketType::Stream {
+            return Err(SocketError::ConnRefused);
+        }
+
+        // The accepted socket is created here and handed to the listener
+        let mut server = UnixSocket::new(SocketType::Stream);
+        server.state = SocketState::Connected;
+        server.peer = Some(self.endpoint.clone());
+        server.path = Some(path.clone());
+        server.peer_path = self.path().cloned();
+        let server_endpoint = server.endpoint.clone();
+        {
+            let mut queue = listener.queue.lock();
+            if !queue.listening || queue.shut_rd {
+                return Err(SocketError::ConnRefused);
+            }
+            // A full backlog fails like a non-blocking connect (EAGAIN)
+            if queue.pending.len() >= queue.backlog {
+                return Err(SocketError::WouldBlock);
+            }
+            queue.pending.push_back(Arc::new(Mutex::new(server)));
+        }
+        listener.wait.wake_all();
+
+        self.peer = Some(server_endpoint);
+        self.peer_path = Some(path);
+        self.state = SocketState::Connected;
+        Ok(())
+    }
+
+    fn send(&mut self, data: &[u8], flags: u32) -> Result<usize, SocketError> {
+        self.sendmsg(data, None, &[], flags)
+    }
+
+    fn recv(&mut self, buffer: &mut [u8], flags: u32) -> Result<usize, SocketError> {
+        self.recvmsg(buffer, flags).map(|message| message.len)
+    }
+
+    fn sendto(&mut self, data: &[u8], addr: SocketAddr, flags: u32) -> Result<usize, SocketError> {
+        self.sendmsg(data, Some(addr), &[], flags)
+    }
+
+    fn recvfrom(
+        &mut self,
+        buffer: &mut [u8],
+        flags: u32,
+    ) -> Result<(usize, SocketAddr), SocketError> {
+        let message = self.recvmsg(buffer, flags)?;
+        Ok((message.len, message.addr.unwrap_or(unix_addr(None))))
+    }
+
+    fn shutdown(&mut self, how: ShutdownHow) -> Result<(), SocketError> {
+        if self.state != SocketState::Connected {
+            return Err(SocketError::NotConnected);
+        }
+        if matches!(how, ShutdownHow::Read | ShutdownHow::Both) {
+            self.endpoint.queue.lock().shut_rd = true;
+            self.endpoint.wait.wake_all();
+        }
+        if matches!(how, ShutdownHow::Write | ShutdownHow::Both) {
+            self.shut_wr = true;
+            if let Some(peer) = &self.peer {
+                peer.set_eof();
+            }
+        }
+        Ok(())
+    }
+
+    fn queued_rights(&self) -> Vec<FdEntry> {
+        let (mut entries, pending) = {
+            let queue = self.endpoint.queue.lock();
+            let entries: Vec<FdEntry> = queue
+                .messages
+                .iter()
+                .filter_map(|message| message.rights.as_ref())
+                .flat_map(|rights| rights.entries().iter().cloned())
+                .collect();
+            (entries, queue.pending.clone())
+        };
+
+        // Connections not yet accepted belong to the listener
+        for connection in pending {
+            entries.extend(connection.lock().queued_rights());
+        }
+        entries
+    }
+
+    fn take_queued_rights(&mut self) -> Vec<InFlightFds> {
+        let (mut rights, pending) = {
+            let mut queue = self.endpoint.queue.lock();
+            let rights: Vec<InFlightFds> = queue
+                .messages
+                .iter_mut()
+                .filter_map(|message| message.rights.take())
+                .collect();
+            (rights, queue.pending.clone())
+        };
+
+        for connection in pending {
+            rights.extend(connection.lock().take_queued_rights());
+        }
+        rights
+    }
+
+    fn close(&mut self) -> Result<(), SocketError> {
+        if let Some(inode) = self.bound.take() {
+            BINDINGS.lock().remove(&inode);
+        }
+        if let Some(peer) = self.peer.take() {
+            peer.set_eof();
+        }
+        self.flush();
+        self.state = SocketState::Closed;
+        Ok(())
+    }
+
+    fn state(&self) -> SocketState {
+        self.state
+    }
+
+    fn setsockopt(&mut self, option: SocketOption) -> Result<(), SocketError> {
+        self.options.set(option)
+    }
+
+    fn getsockopt(&self, option: SocketOptionType) -> Result<SocketOption, SocketError> {
+        self.options.get(option)
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        Some(unix_addr(self.path()))
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        match self.state {
+            SocketState::Connected => Some(unix_addr(self.peer_path.as_ref())),
+            _ => None,
+        }
+    }
+
+    fn options(&self) -> SocketOptions {
+        self.options
+    }
+
+    fn poll(&self) -> PollEvents {
+        let queue = self.endpoint.queue.lock();
+        let mut events = PollEvents::empty();
+        if queue.listening {
+            if !queue.pending.is_empty() {
+                events |= PollEvents::IN;
+            }
+            return events;
+        }
+        if !queue.messages.is_empty() {
+            events |= PollEvents::IN;
+        }
+        if queue.shut_rd || (self.is_stream() && queue.eof) {
+            events |= PollEvents::IN | PollEvents::RDHUP;
+        }
+        let shut_rd = queue.shut_rd;
+        drop(queue);
+
+        if !self.is_stream() {
+            return events | PollEvents::OUT;
+        }
+        match &self.peer {
+            Some(peer) => {
+                let peer = peer.queue.lock();
+                if peer.shut_rd {
+                    events |= PollEvents::ERR;
+                } else if !self.shut_wr && peer.bytes < QUEUE_CAPACITY {
+                    events |= PollEvents::OUT;
+                }
+                if peer.shut_rd && shut_rd {
+                    events |= PollEvents::HUP;
+                }
+            }
+            None if self.state == SocketState::Connected => events |= PollEvents::HUP,
+            None => {}
+        }
+        events
+    }
+
+    fn wait_queue(&self) -> Arc<WaitQueue> {
+        self.endpoint.wait.clone()
+    }
+
+    fn sendmsg(
+        &mut self,
+        data: &[u8],
+        addr: Option<SocketAddr>,
+        control: &[ControlMessage],
+        _flags: u32,
+    ) -> Result<usize, SocketError> {
+        let (credentials, rights) = Self::check_control(control)?;
+        let message = Message {
+            data: data.to_vec(),
+            from: self.path().cloned(),
+            credentials,
+            rights,
+        };
+
+        if !self.is_stream() {
+            return match (addr, &self.peer, &self.peer_path) {
+                (Some(addr), _, _) => self.send_datagram(message, &unix_path(addr)?),
+                (None, Some(peer), _) => peer.deliver(message, false, &self.endpoint.wait),
+                (None, None, Some(path)) => self.send_datagram(message, path),
+                (None, None, None) => Err(SocketError::NotConnected),
+            };
+        }
+
+        if addr.is_some() && self.state == SocketState::Connected {
+            return Err(SocketError::AlreadyConnected);
+        }
+        if self.shut_wr {
+            return Err(SocketError::BrokenPipe);
+        }
+        match &self.peer {
+            Some(peer) => peer.deliver(message, true, &self.endpoint.wait),
+            None if self.state == SocketState::Connected => Err(SocketError::BrokenPipe),
+            None => Err(SocketError::NotConnected),
+        }
+    }
+
+    fn recvmsg(&mut self, buffer: &mut [u8], _flags: u32) -> Result<ReceivedMessage, SocketError> {
+        if self.is_stream() {
+            self.recv_stream(buffer)
+        } else {
+            self.recv_datagram(buffer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::fd::FdEntry;
+    use crate::net::socket::{self, SocketDomain, SocketProtocol, MSG_DONTWAIT};
+    use alloc::vec;
+
+    fn unix(path: &str) -> SocketAddr {
+        SocketAddr::Unix(SocketAddrUnix {
+            path: String::from(path),
+        })
+    }
+
+    fn new_socket(socket_type: SocketType) -> i32 {
+        socket::socket(SocketDomain::Unix, socket_type, SocketProtocol::Default).unwrap()
+    }
+
+    #[test]
+    fn test_unix_bound_sockets() {
+        static TMPFS: spin::Once = spin::Once::new();
+        TMPFS.call_once(|| {
+            if !tmpfs::is_initialized() {
+                tmpfs::init();
+            }
+        });
+
+        let listener = new_socket(SocketType::Stream);
+        socket::bind(listener, unix("/unix-test-stream")).unwrap();
+        socket::listen(listener, 4).unwrap();
+
+        let client = new_socket(SocketType::Stream);
+        assert_eq!(
+            socket::connect(client, unix("/unix-test-missing")),
+            Err(SocketError::ConnRefused)
+        );
+        socket::connect(client, unix("/unix-test-stream")).unwrap();
+        let server = socket::accept(listener).unwrap();
+        match socket::peer_addr(client).unwrap() {
+            Some(SocketAddr::Unix(addr)) => assert_eq!(addr.path, "/unix-test-stream"),
+            other => panic!("unexpected peer address {:?}", other),
+        }
+
+        let mut buffer = [0u8; 16];
+        assert_eq!(socket::send(client, b"hello", 0), Ok(5));
+        assert_eq!(socket::recv(server, &mut buffer, 0), Ok(5));
+        assert_eq!(&buffer[..5], b"hello");
+
+        // Shutting down the write side is seen as end of stream
+        socket::shutdown(client, ShutdownHow::Write).unwrap();
+        assert_eq!(socket::recv(server, &mut buffer, 0), Ok(0));
+        assert_eq!(
+            socket::send(client, b"late", 0),
+            Err(SocketError::BrokenPipe)
+        );
+        assert_eq!(socket::send(server, b"bye", 0), Ok(3));
+        socket::close_socket(server).unwrap();
+        assert_eq!(socket::recv(client, &mut buffer, 0), Ok(3));
+        assert_eq!(socket::recv(client, &mut buffer, 0), Ok(0));
+
+        // The socket file keeps the path taken
+        let other = new_socket(SocketType::Stream);
+        assert_eq!(
+            socket::bind(other, unix("/unix-test-stream")),
+            Err(SocketError::AddrInUse)
+        );
+
+        let a = new_socket(SocketType::Dgram);
+        let b = new_socket(SocketType::Dgram);
+        socket::bind(a, unix("/unix-test-dgram-a")).unwrap();
+        socket::bind(b, unix("/unix-test-dgram-b")).unwrap();
+        assert_eq!(
+            socket::sendto(a, b"ping", unix("/unix-test-dgram-b"), 0),
+            Ok(4)
+        );
+        let (len, from) = socket::recvfrom(b, &mut buffer, MSG_DONTWAIT).unwrap();
+        assert_eq!(&buffer[..len], b"ping");
+        match from {
+            SocketAddr::Unix(addr) => assert_eq!(addr.path, "/unix-test-dgram-a"),
+            other => panic!("unexpected source address {:?}", other),
+        }
+        assert_eq!(
+            socket::recvfrom(b, &mut buffer, MSG_DONTWAIT).unwrap_err(),
+            SocketError::WouldBlock
+        );
+
+        // Datagrams cannot be sent to a stream socket
+        assert_eq!(
+            socket::sendto(a, b"ping", unix("/unix-test-stream"), 0),
+            Err(SocketError::ConnRefused)
+        );
+
+        for fd in [listener, client, other, a, b] {
+            socket::close_socket(fd).unwrap();
+        }
+        assert_eq!(
+            socket::sendto(a, b"ping", unix("/unix-test-dgram-b"), 0),
+            Err(SocketError::InvalidArg)
+        );
+    }
+
+    #[test]
+    fn test_unix_ancillary_data() {
+        let (a, b) = socket::socketpair(
+            SocketDomain::Unix,
+            SocketType::Stream,
+            SocketProtocol::Default,
+        )
+        .unwrap();
+        let (c, d) = socket::socketpair(
+            SocketDomain::Unix,
+            SocketType::Dgram,
+            SocketProtocol::Default,
+        )
+        .unwrap();
+        socket::setsockopt(b, SocketOption::PassCred(true)).unwrap();
+
+        let rights = ControlMessage::Rights(InFlightFds::new(vec![FdEntry::Socket(c)]));
+        assert_eq!(socket::sendmsg(a, b"fd", None, &[rights], 0), Ok(2));
+        assert_eq!(socket::send(a, b"more", 0), Ok(4));
+
+        // Descriptors arrive with the bytes they were sent with
+        let mut buffer = [0u8; 16];
+        let message = socket::recvmsg(b, &mut buffer, MSG_DONTWAIT).unwrap();
+        assert_eq!(&buffer[..message.len], b"fd");
+        match message.control.as_slice() {
+            [ControlMessage::Credentials(creds), ControlMessage::Rights(rights)] => {
+                assert_eq!(*creds, Credentials::current());
+                assert!(matches!(rights.entries(), [FdEntry::Socket(h)] if *h == c));
+            }
+            _ => panic!("unexpected control messages"),
+        }
+
+        // The passed socket lives as long as a reference to it does
+        assert_eq!(socket::send(c, b"x", 0), Ok(1));
+        drop(message);
+        assert_eq!(socket::send(c, b"x", 0), Err(SocketError::InvalidArg));
+
+        assert_eq!(socket::recv(b, &mut buffer, MSG_DONTWAIT), Ok(4));
+        assert_eq!(&buffer[..4], b"more");
+        for fd in [a, b, d] {
+            socket::close_socket(fd).unwrap();
+        }
+    }
+}
@@ -9,3 +9,4 @@ pub mod pid;
 pub mod sched;
 pub mod task;
 pub mod wait;
+pub mod wait_queue;
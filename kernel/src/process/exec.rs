@@ -159,8 +159,10 @@ pub fn do_exec(
     // assuming we have the file data
 
     // TODO: Read file from filesystem
-    // For now, return error indicating file system not implemented
-    let _ = (task, path);
+    let _ = path;
+
+    // The new image does not see descriptors marked close-on-exec
+    task.fd_table.lock().close_on_exec();
 
     // Stub: Create execution context
     let mut ctx = ExecContext::new(0x400000, 0x7FFFFFFFE000);
@@ -297,6 +299,26 @@ mod tests {
         assert_eq!(ctx.argv.len(), 2);
     }
 
+    #[test]
+    fn test_exec_closes_cloexec_descriptors() {
+        use crate::fs::file::{File, FileMode, FileType};
+
+        let mut task = Task::new(7);
+        let (kept, closed) = {
+            let mut table = task.fd_table.lock();
+            let file = File::new(1, FileType::Regular, FileMode::read_only());
+            (
+                table.allocate_fd(file.clone(), false).unwrap(),
+                table.allocate_fd(file, true).unwrap(),
+            )
+        };
+
+        do_exec(&mut task, "/bin/init", Vec::new(), Vec::new()).unwrap();
+        let table = task.fd_table.lock();
+        assert!(table.get_file(kept).is_some());
+        assert!(table.get_file(closed).is_none());
+    }
+
     #[test]
     fn test_parse_elf_header_invalid() {
         let data = [0u8; 64];
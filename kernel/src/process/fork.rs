@@ -2,7 +2,8 @@
 //!
 //! Implementation of process forking (clone system call).
 
-use super::task::{Task, TaskState};
+use super::sched;
+use super::task::Task;
 use crate::types::Pid;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicI32, Ordering};
@@ -53,6 +54,11 @@ impl MemoryContext {
     pub fn clone_for_fork(&self) -> Result<Self, &'static str> {
         use rinux_mm::frame;
 
+        // Kernel tasks have no address space of their own to copy
+        if self.page_table == 0 {
+            return Ok(self.clone());
+        }
+
         // Create a new page table for the child
         let new_page_table_frame =
             frame::allocate_frame().ok_or("Failed to allocate page table")?;
@@ -175,6 +181,9 @@ impl ExtendedTask {
         child.task.uid = self.task.uid;
         child.task.gid = self.task.gid;
 
+        // Inherit open files
+        child.task.fd_table = self.task.clone_fd_table();
+
         Ok(child)
     }
 }
@@ -183,19 +192,29 @@ impl ExtendedTask {
 static EXTENDED_TASKS: Mutex<Vec<ExtendedTask>> = Mutex::new(Vec::new());
 
 /// Fork the current process
+///
+/// The child is forked from the scheduler's copy of the current task, so
+/// it inherits the live descriptor table, and is made runnable.
 pub fn do_fork() -> Result<Pid, &'static str> {
+    let parent = sched::current_task().ok_or("No running process")?;
     let mut tasks = EXTENDED_TASKS.lock();
 
-    // Get current process (simplified - in reality would use scheduler's current)
-    let current_idx = tasks
-        .iter()
-        .position(|t| t.task.state == TaskState::Running)
-        .ok_or("No running process")?;
-
-    let current = &tasks[current_idx];
-    let child = current.fork()?;
+    let child = match tasks.iter_mut().find(|t| t.task.pid == parent.pid) {
+        Some(current) => {
+            current.task = parent;
+            current.fork()?
+        }
+        // Tasks started by the scheduler alone have no saved context
+        None => ExtendedTask {
+            task: parent,
+            memory: MemoryContext::new(),
+            registers: RegisterState::new(),
+        }
+        .fork()?,
+    };
     let child_pid = child.task.pid;
 
+    sched::add_task(child.task.clone());
     tasks.push(child);
 
     Ok(child_pid)
@@ -212,6 +231,7 @@ pub fn init() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::process::task::TaskState;
 
     #[test]
     fn test_pid_allocation() {
@@ -239,4 +259,31 @@ mod tests {
         let task = ExtendedTask::new(42);
         assert_eq!(task.task.pid, 42);
     }
+
+    #[test]
+    fn test_fork_inherits_descriptors() {
+        use crate::syscall::handle_syscall;
+        use crate::syscall::socket::{AF_UNIX, SOCK_DGRAM};
+
+        sched::add_task(Task::new(401));
+        sched::schedule();
+        assert_eq!(sched::current_pid(), Some(401));
+
+        let fd = handle_syscall(41, AF_UNIX as usize, SOCK_DGRAM, 0, 0, 0, 0).unwrap() as i32;
+        let child_pid = handle_syscall(57, 0, 0, 0, 0, 0, 0).unwrap() as Pid;
+
+        let child = sched::get_task(child_pid).expect("child is scheduled");
+        assert_eq!(child.parent_pid, Some(401));
+        assert_eq!(child.state, TaskState::Running);
+        let handle = child.fd_table.lock().get_socket(fd);
+        assert!(handle.is_some());
+
+        // The child has its own copy of the descriptor
+        handle_syscall(3, fd as usize, 0, 0, 0, 0, 0).unwrap();
+        assert_eq!(child.fd_table.lock().get_socket(fd), handle);
+        child.fd_table.lock().free_fd(fd).unwrap();
+
+        sched::remove_task(child_pid);
+        sched::remove_task(401);
+    }
 }
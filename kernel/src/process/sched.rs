@@ -3,8 +3,10 @@
 //! Basic round-robin process scheduler implementation.
 
 use super::task::{Task, TaskState};
+use crate::fs::fd::FileDescriptorTable;
 use crate::types::Pid;
 use alloc::collections::VecDeque;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
@@ -14,8 +16,28 @@ use spin::Mutex;
 const MAX_TASKS: usize = 256;
 
 /// Global scheduler state
+#[cfg(not(test))]
 static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
 
+#[cfg(test)]
+extern crate std;
+
+// Every test thread schedules its own tasks, so a test can make a task
+// current without changing what tests running alongside it see
+#[cfg(test)]
+std::thread_local! {
+    static SCHEDULER: &'static Mutex<Scheduler> =
+        alloc::boxed::Box::leak(alloc::boxed::Box::new(Mutex::new(Scheduler::new())));
+}
+
+/// The scheduler state
+fn scheduler() -> &'static Mutex<Scheduler> {
+    #[cfg(not(test))]
+    return &SCHEDULER;
+    #[cfg(test)]
+    return SCHEDULER.with(|scheduler| *scheduler);
+}
+
 /// Scheduler initialization flag
 static SCHEDULER_INITIALIZED: AtomicBool = AtomicBool::new(false);
 
@@ -118,6 +140,30 @@ impl Scheduler {
         }
     }
 
+    /// Put a running task to sleep
+    ///
+    /// The task is skipped by the scheduler until it is woken.
+    pub fn sleep_task(&mut self, pid: Pid) {
+        if let Some(task) = self.get_task_mut(pid) {
+            if task.state == TaskState::Running {
+                task.state = TaskState::Sleeping;
+            }
+        }
+        self.ready_queue.retain(|&p| p != pid);
+    }
+
+    /// Make a sleeping task runnable again
+    pub fn wake_task(&mut self, pid: Pid) -> bool {
+        match self.get_task_mut(pid) {
+            Some(task) if task.state == TaskState::Sleeping => task.state = TaskState::Running,
+            _ => return false,
+        }
+        if self.current != Some(pid) && !self.ready_queue.contains(&pid) {
+            self.ready_queue.push_back(pid);
+        }
+        true
+    }
+
     /// Get number of tasks
     pub fn task_count(&self) -> usize {
         self.tasks.iter().filter(|t| t.is_some()).count()
@@ -135,20 +181,23 @@ pub fn init() {
         return;
     }
 
-    let mut sched = SCHEDULER.lock();
+    let mut sched = scheduler().lock();
 
     // Create idle task (PID 0)
     let idle_task = Task::new(0);
     sched.add_task(idle_task);
 
     SCHEDULER_INITIALIZED.store(true, Ordering::Release);
+    drop(sched);
+
+    super::wait_queue::init();
 
     crate::printk::printk("  Scheduler initialized (round-robin)\n");
 }
 
 /// Schedule next task
 pub fn schedule() {
-    let mut sched = SCHEDULER.lock();
+    let mut sched = scheduler().lock();
     if let Some(_next_pid) = sched.schedule_next() {
         // TODO: Perform actual context switch using arch-specific code
         // For now, we're updating the scheduler state
@@ -172,40 +221,84 @@ pub fn schedule() {
 
 /// Yield CPU to another task
 pub fn yield_now() {
-    let mut sched = SCHEDULER.lock();
+    let mut sched = scheduler().lock();
     sched.yield_current();
     drop(sched);
     schedule();
 }
 
+/// Put the current task to sleep until it is woken
+pub fn sleep_current() {
+    let mut sched = scheduler().lock();
+    if let Some(pid) = sched.current_pid() {
+        sched.sleep_task(pid);
+    }
+}
+
+/// Check whether a task is asleep
+pub fn is_sleeping(pid: Pid) -> bool {
+    let sched = scheduler().lock();
+    sched
+        .get_task(pid)
+        .is_some_and(|task| task.state == TaskState::Sleeping)
+}
+
+/// Wake a sleeping task
+pub fn wake_up(pid: Pid) -> bool {
+    let mut sched = scheduler().lock();
+    sched.wake_task(pid)
+}
+
 /// Add a task to the scheduler
 pub fn add_task(task: Task) {
-    let mut sched = SCHEDULER.lock();
+    let mut sched = scheduler().lock();
     sched.add_task(task);
 }
 
 /// Remove a task from the scheduler
 pub fn remove_task(pid: Pid) {
-    let mut sched = SCHEDULER.lock();
+    let mut sched = scheduler().lock();
     sched.remove_task(pid);
 }
 
 /// Get current task PID
 pub fn current_pid() -> Option<Pid> {
-    let sched = SCHEDULER.lock();
+    let sched = scheduler().lock();
     sched.current_pid()
 }
 
+/// Get a copy of a task
+///
+/// The copy shares the task's descriptor table and capabilities.
+pub fn get_task(pid: Pid) -> Option<Task> {
+    let sched = scheduler().lock();
+    sched.get_task(pid).cloned()
+}
+
+/// Get a copy of the current task
+pub fn current_task() -> Option<Task> {
+    let sched = scheduler().lock();
+    let current = sched.current_pid()?;
+    sched.get_task(current).cloned()
+}
+
 /// Get the parent PID of the current task
 pub fn current_ppid() -> Option<Pid> {
-    let sched = SCHEDULER.lock();
+    let sched = scheduler().lock();
     let current = sched.current_pid()?;
     sched.get_task(current)?.parent_pid
 }
 
+/// Get the file descriptor table of the current task
+pub fn current_fd_table() -> Option<Arc<Mutex<FileDescriptorTable>>> {
+    let sched = scheduler().lock();
+    let current = sched.current_pid()?;
+    Some(sched.get_task(current)?.fd_table.clone())
+}
+
 /// Get the user ID of the current task
 pub fn current_uid() -> u32 {
-    let sched = SCHEDULER.lock();
+    let sched = scheduler().lock();
     sched
         .current_pid()
         .and_then(|pid| sched.get_task(pid))
@@ -215,7 +308,7 @@ pub fn current_uid() -> u32 {
 
 /// Get the group ID of the current task
 pub fn current_gid() -> u32 {
-    let sched = SCHEDULER.lock();
+    let sched = scheduler().lock();
     sched
         .current_pid()
         .and_then(|pid| sched.get_task(pid))
@@ -225,7 +318,7 @@ pub fn current_gid() -> u32 {
 
 /// Set the user ID of the current task, returns `Err` if no current task
 pub fn set_current_uid(uid: u32) -> Result<(), ()> {
-    let mut sched = SCHEDULER.lock();
+    let mut sched = scheduler().lock();
     let pid = sched.current_pid().ok_or(())?;
     let task = sched.get_task_mut(pid).ok_or(())?;
     task.uid = uid;
@@ -234,7 +327,7 @@ pub fn set_current_uid(uid: u32) -> Result<(), ()> {
 
 /// Set the group ID of the current task, returns `Err` if no current task
 pub fn set_current_gid(gid: u32) -> Result<(), ()> {
-    let mut sched = SCHEDULER.lock();
+    let mut sched = scheduler().lock();
     let pid = sched.current_pid().ok_or(())?;
     let task = sched.get_task_mut(pid).ok_or(())?;
     task.gid = gid;
@@ -243,12 +336,12 @@ pub fn set_current_gid(gid: u32) -> Result<(), ()> {
 
 /// Get task count
 pub fn task_count() -> usize {
-    let sched = SCHEDULER.lock();
+    let sched = scheduler().lock();
     sched.task_count()
 }
 
 /// Get ready task count
 pub fn ready_count() -> usize {
-    let sched = SCHEDULER.lock();
+    let sched = scheduler().lock();
     sched.ready_count()
 }
@@ -81,11 +81,12 @@ impl Task {
         }
     }
 
-    /// Clone the file descriptor table (for fork)
+    /// Duplicate the file descriptor table (for fork)
+    ///
+    /// The copy has its own descriptors, which share open files (and so
+    /// their offsets) with this task's.
     pub fn clone_fd_table(&self) -> Arc<Mutex<FileDescriptorTable>> {
-        // For now, just share the same FD table (like Linux with CLONE_FILES)
-        // A full fork would clone the table
-        Arc::clone(&self.fd_table)
+        Arc::new(Mutex::new(self.fd_table.lock().fork()))
     }
 
     /// Set task state
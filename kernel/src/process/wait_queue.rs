@@ -0,0 +1,253 @@
+//! Wait Queues
+//!
+//! Tasks sleep on a wait queue until an event source wakes them. Waiters
+//! re-check their condition after every wakeup, so spurious wakeups are
+//! harmless.
+
+use super::sched;
+use crate::types::Pid;
+use alloc::collections::{BTreeMap, VecDeque};
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+/// Interval at which sleep deadlines are checked (milliseconds)
+const TIMEOUT_CHECK_INTERVAL_MS: u64 = 10;
+
+/// Deadlines of sleeping tasks (uptime in milliseconds)
+static TIMEOUTS: Mutex<BTreeMap<Pid, u64>> = Mutex::new(BTreeMap::new());
+
+/// Wait queue initialization flag
+static WAIT_QUEUE_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Queue of tasks waiting for an event
+pub struct WaitQueue {
+    /// Sleeping tasks, in arrival order
+    waiters: Mutex<VecDeque<Pid>>,
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WaitQueue {
+    /// Create an empty wait queue
+    pub const fn new() -> Self {
+        Self {
+            waiters: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Add a task to the queue
+    fn enqueue(&self, pid: Pid) {
+        let mut waiters = self.waiters.lock();
+        if !waiters.contains(&pid) {
+            waiters.push_back(pid);
+        }
+    }
+
+    /// Remove a task from the queue
+    fn dequeue(&self, pid: Pid) {
+        self.waiters.lock().retain(|&p| p != pid);
+    }
+
+    /// Wake the task that has been waiting longest
+    pub fn wake_one(&self) -> bool {
+        let pid = self.waiters.lock().pop_front();
+        match pid {
+            Some(pid) => {
+                sched::wake_up(pid);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wake all waiting tasks
+    pub fn wake_all(&self) -> usize {
+        let waiters: VecDeque<Pid> = core::mem::take(&mut *self.waiters.lock());
+        for &pid in &waiters {
+            sched::wake_up(pid);
+        }
+        waiters.len()
+    }
+
+    /// Get number of waiting tasks
+    pub fn len(&self) -> usize {
+        self.waiters.lock().len()
+    }
+
+    /// Check if no task is waiting
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().is_empty()
+    }
+
+    /// Sleep until `condition` yields a value
+    ///
+    /// `deadline` is an uptime in milliseconds; `None` is returned once it
+    /// has passed.
+    pub fn wait_until<T>(
+        &self,
+        condition: impl FnMut() -> Option<T>,
+        deadline: Option<u64>,
+    ) -> Option<T> {
+        wait_any(&[self], condition, deadline)
+    }
+}
+
+/// Service event sources while a waiter holds the CPU
+///
+/// Interrupts stay masked inside a system call, so nothing else delivers
+/// received frames or runs expired timers until the waiter returns.
+fn poll_events() {
+    crate::net::rx::poll();
+    crate::time::run_timers();
+}
+
+/// Sleep on several queues until `condition` yields a value
+///
+/// The calling task is woken by any of the queues. Without a current task
+/// (early boot) the caller spins instead of sleeping.
+pub fn wait_any<T>(
+    queues: &[&WaitQueue],
+    mut condition: impl FnMut() -> Option<T>,
+    deadline: Option<u64>,
+) -> Option<T> {
+    loop {
+        if let Some(value) = condition() {
+            return Some(value);
+        }
+        if deadline.is_some_and(|deadline| crate::time::uptime_ms() >= deadline) {
+            return None;
+        }
+
+        let Some(pid) = sched::current_pid() else {
+            poll_events();
+            core::hint::spin_loop();
+            continue;
+        };
+
+        for queue in queues {
+            queue.enqueue(pid);
+        }
+        if let Some(deadline) = deadline {
+            TIMEOUTS.lock().insert(pid, deadline);
+        }
+        sched::sleep_current();
+
+        // There is no context switch yet, so the sleeping task keeps the
+        // CPU until it is woken. The condition is checked as well, since
+        // the event may have fired before the task went to sleep.
+        let value = loop {
+            let value = condition();
+            let expired = deadline.is_some_and(|deadline| crate::time::uptime_ms() >= deadline);
+            if value.is_some() || expired || !sched::is_sleeping(pid) {
+                break value;
+            }
+            poll_events();
+            core::hint::spin_loop();
+        };
+
+        for queue in queues {
+            queue.dequeue(pid);
+        }
+        TIMEOUTS.lock().remove(&pid);
+        sched::wake_up(pid);
+
+        if value.is_some() {
+            return value;
+        }
+    }
+}
+
+/// Wake tasks whose sleep deadline has passed
+fn expire_timeouts() {
+    let now = crate::time::uptime_ms();
+    let expired: alloc::vec::Vec<Pid> = {
+        let mut timeouts = TIMEOUTS.lock();
+        let expired = timeouts
+            .iter()
+            .filter(|(_, &deadline)| now >= deadline)
+            .map(|(&pid, _)| pid)
+            .collect();
+        timeouts.retain(|_, &mut deadline| now < deadline);
+        expired
+    };
+
+    for pid in expired {
+        sched::wake_up(pid);
+    }
+}
+
+/// Initialize wait queue timeouts
+pub fn init() {
+    if WAIT_QUEUE_INITIALIZED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    let _ = crate::time::timer::create_periodic_timer(TIMEOUT_CHECK_INTERVAL_MS, expire_timeouts);
+}
+
+/// Check if wait queue timeouts are initialized
+pub fn is_initialized() -> bool {
+    WAIT_QUEUE_INITIALIZED.load(Ordering::Acquire)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_until() {
+        let queue = WaitQueue::new();
+        let mut polls = 0;
+        let value = queue.wait_until(
+            || {
+                polls += 1;
+                (polls == 3).then_some(polls)
+            },
+            None,
+        );
+        assert_eq!(value, Some(3));
+        assert!(queue.is_empty());
+
+        // A deadline in the past gives up after one check
+        let now = crate::time::uptime_ms();
+        assert_eq!(queue.wait_until(|| None::<()>, Some(now)), None);
+        assert_eq!(queue.wake_all(), 0);
+        assert!(!queue.wake_one());
+    }
+
+    #[test]
+    fn test_wait_keeps_current_task() {
+        use crate::process::task::Task;
+
+        sched::add_task(Task::new(301));
+        sched::add_task(Task::new(302));
+        sched::schedule();
+        assert_eq!(sched::current_pid(), Some(301));
+
+        // Woken by the queue rather than by the condition
+        let queue = WaitQueue::new();
+        let mut polls = 0;
+        let value = queue.wait_until(
+            || {
+                polls += 1;
+                if polls == 2 {
+                    assert!(sched::is_sleeping(301));
+                    queue.wake_all();
+                }
+                (polls >= 4).then_some(polls)
+            },
+            None,
+        );
+        assert_eq!(value, Some(4));
+        assert_eq!(sched::current_pid(), Some(301));
+        assert!(!sched::is_sleeping(301));
+        assert!(queue.is_empty());
+
+        sched::remove_task(301);
+        sched::remove_task(302);
+    }
+}
@@ -2,6 +2,8 @@
 //!
 //! System call numbers and handler framework.
 
+pub mod socket;
+
 /// System call numbers
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u64)]
@@ -56,6 +58,8 @@ pub enum SyscallNumber {
     Dup = 32,
     /// Duplicate file descriptor to specific fd
     Dup2 = 33,
+    /// Manipulate file descriptor
+    Fcntl = 72,
     /// Get current working directory
     Getcwd = 79,
     /// Change directory
@@ -68,6 +72,36 @@ pub enum SyscallNumber {
     Unlink = 87,
     /// Rename file
     Rename = 82,
+    /// Create socket
+    Socket = 41,
+    /// Connect socket
+    Connect = 42,
+    /// Accept connection
+    Accept = 43,
+    /// Send message
+    Sendto = 44,
+    /// Receive message
+    Recvfrom = 45,
+    /// Send message with ancillary data
+    Sendmsg = 46,
+    /// Receive message with ancillary data
+    Recvmsg = 47,
+    /// Shut down socket
+    Shutdown = 48,
+    /// Bind socket to address
+    Bind = 49,
+    /// Listen for connections
+    Listen = 50,
+    /// Get socket address
+    Getsockname = 51,
+    /// Get peer address
+    Getpeername = 52,
+    /// Create pair of connected sockets
+    Socketpair = 53,
+    /// Set socket option
+    Setsockopt = 54,
+    /// Get socket option
+    Getsockopt = 55,
     /// Unknown/invalid syscall
     Unknown = 0xFFFFFFFF,
 }
@@ -100,12 +134,28 @@ impl From<u64> for SyscallNumber {
             8 => SyscallNumber::Lseek,
             32 => SyscallNumber::Dup,
             33 => SyscallNumber::Dup2,
+            72 => SyscallNumber::Fcntl,
             79 => SyscallNumber::Getcwd,
             80 => SyscallNumber::Chdir,
             83 => SyscallNumber::Mkdir,
             84 => SyscallNumber::Rmdir,
             87 => SyscallNumber::Unlink,
             82 => SyscallNumber::Rename,
+            41 => SyscallNumber::Socket,
+            42 => SyscallNumber::Connect,
+            43 => SyscallNumber::Accept,
+            44 => SyscallNumber::Sendto,
+            45 => SyscallNumber::Recvfrom,
+            46 => SyscallNumber::Sendmsg,
+            47 => SyscallNumber::Recvmsg,
+            48 => SyscallNumber::Shutdown,
+            49 => SyscallNumber::Bind,
+            50 => SyscallNumber::Listen,
+            51 => SyscallNumber::Getsockname,
+            52 => SyscallNumber::Getpeername,
+            53 => SyscallNumber::Socketpair,
+            54 => SyscallNumber::Setsockopt,
+            55 => SyscallNumber::Getsockopt,
             _ => SyscallNumber::Unknown,
         }
     }
@@ -138,8 +188,12 @@ pub mod errno {
     pub const EACCES: isize = -13;
     /// Bad address
     pub const EFAULT: isize = -14;
+    /// Device or resource busy
+    pub const EBUSY: isize = -16;
     /// File exists
     pub const EEXIST: isize = -17;
+    /// Cross-device link
+    pub const EXDEV: isize = -18;
     /// Not a directory
     pub const ENOTDIR: isize = -20;
     /// Is a directory
@@ -148,10 +202,54 @@ pub mod errno {
     pub const EINVAL: isize = -22;
     /// Too many open files
     pub const EMFILE: isize = -24;
+    /// No space left on device
+    pub const ENOSPC: isize = -28;
+    /// Illegal seek
+    pub const ESPIPE: isize = -29;
+    /// Read-only file system
+    pub const EROFS: isize = -30;
+    /// Broken pipe
+    pub const EPIPE: isize = -32;
     /// Out of range
     pub const ERANGE: isize = -34;
     /// Function not implemented
     pub const ENOSYS: isize = -38;
+    /// Directory not empty
+    pub const ENOTEMPTY: isize = -39;
+    /// Too many levels of symbolic links
+    pub const ELOOP: isize = -40;
+    /// Socket operation on non-socket
+    pub const ENOTSOCK: isize = -88;
+    /// Message too long
+    pub const EMSGSIZE: isize = -90;
+    /// Protocol not available
+    pub const ENOPROTOOPT: isize = -92;
+    /// Protocol not supported
+    pub const EPROTONOSUPPORT: isize = -93;
+    /// Operation not supported
+    pub const EOPNOTSUPP: isize = -95;
+    /// Address family not supported
+    pub const EAFNOSUPPORT: isize = -97;
+    /// Address already in use
+    pub const EADDRINUSE: isize = -98;
+    /// Cannot assign requested address
+    pub const EADDRNOTAVAIL: isize = -99;
+    /// Network is unreachable
+    pub const ENETUNREACH: isize = -101;
+    /// Connection reset by peer
+    pub const ECONNRESET: isize = -104;
+    /// Transport endpoint is already connected
+    pub const EISCONN: isize = -106;
+    /// Transport endpoint is not connected
+    pub const ENOTCONN: isize = -107;
+    /// Connection timed out
+    pub const ETIMEDOUT: isize = -110;
+    /// Connection refused
+    pub const ECONNREFUSED: isize = -111;
+    /// No route to host
+    pub const EHOSTUNREACH: isize = -113;
+    /// Operation now in progress
+    pub const EINPROGRESS: isize = -115;
 }
 
 /// Kernel stat structure (subset of POSIX struct stat)
@@ -177,6 +275,41 @@ pub struct KernelStat {
     pub st_ctime: i64,
 }
 
+/// Socket file type bits of `st_mode`
+const S_IFSOCK: u32 = 0o140000;
+
+/// File type bits of `st_mode`
+fn file_type_bits(file_type: crate::fs::FileType) -> u32 {
+    use crate::fs::FileType;
+
+    match file_type {
+        FileType::Fifo => 0o010000,
+        FileType::CharDevice => 0o020000,
+        FileType::Directory => 0o040000,
+        FileType::BlockDevice => 0o060000,
+        FileType::Regular => 0o100000,
+        FileType::Symlink => 0o120000,
+        FileType::Socket => S_IFSOCK,
+    }
+}
+
+impl KernelStat {
+    /// Build a stat record from VFS attributes
+    pub fn from_attr(attr: &crate::fs::FileAttr) -> Self {
+        KernelStat {
+            st_ino: attr.ino,
+            st_mode: file_type_bits(attr.file_type) | attr.mode.0,
+            st_nlink: attr.nlink,
+            st_uid: attr.uid,
+            st_gid: attr.gid,
+            st_size: attr.size as i64,
+            st_atime: attr.atime as i64,
+            st_mtime: attr.mtime as i64,
+            st_ctime: attr.ctime as i64,
+        }
+    }
+}
+
 /// Handle a system call
 ///
 /// # Arguments
@@ -193,9 +326,9 @@ pub fn handle_syscall(
     arg1: usize,
     arg2: usize,
     arg3: usize,
-    _arg4: usize,
-    _arg5: usize,
-    _arg6: usize,
+    arg4: usize,
+    arg5: usize,
+    arg6: usize,
 ) -> SyscallResult {
     let syscall = SyscallNumber::from(syscall_num);
 
@@ -211,11 +344,12 @@ pub fn handle_syscall(
                 return Err(errno::EINVAL);
             }
 
-            // Read from file descriptor
-            match crate::fs::fd::read_fd(fd, buf, count) {
-                Ok(bytes_read) => Ok(bytes_read),
-                Err(_) => Err(errno::EBADF),
+            if let Some(handle) = crate::fs::fd::get_socket(fd) {
+                return socket::read(handle, arg2, count);
             }
+
+            // Read from file descriptor
+            crate::fs::fd::read_fd(fd, buf, count)
         }
         SyscallNumber::Write => {
             // arg1: fd, arg2: buf ptr, arg3: count
@@ -228,11 +362,12 @@ pub fn handle_syscall(
                 return Err(errno::EINVAL);
             }
 
-            // Write to file descriptor
-            match crate::fs::fd::write_fd(fd, buf, count) {
-                Ok(bytes_written) => Ok(bytes_written),
-                Err(_) => Err(errno::EBADF),
+            if let Some(handle) = crate::fs::fd::get_socket(fd) {
+                return socket::write(handle, arg2, count);
             }
+
+            // Write to file descriptor
+            crate::fs::fd::write_fd(fd, buf, count)
         }
         SyscallNumber::Open => {
             // arg1: pathname ptr, arg2: flags, arg3: mode
@@ -384,10 +519,11 @@ pub fn handle_syscall(
             let offset = arg2 as i64;
             let whence = arg3 as i32;
 
-            match crate::fs::fd::seek_fd(fd, offset, whence) {
-                Ok(new_pos) => Ok(new_pos as usize),
-                Err(_) => Err(errno::EBADF),
+            if crate::fs::fd::get_socket(fd).is_some() {
+                return Err(errno::ESPIPE);
             }
+
+            crate::fs::fd::seek_fd(fd, offset, whence).map(|pos| pos as usize)
         }
         SyscallNumber::Dup => {
             let fd = arg1 as i32;
@@ -404,6 +540,10 @@ pub fn handle_syscall(
                 Err(_) => Err(errno::EBADF),
             }
         }
+        SyscallNumber::Fcntl => {
+            // arg1: fd, arg2: cmd, arg3: arg
+            crate::fs::fd::fcntl(arg1 as i32, arg2 as i32, arg3)
+        }
         SyscallNumber::Getcwd => {
             let buf = arg1 as *mut u8;
             let size = arg2;
@@ -447,10 +587,11 @@ pub fn handle_syscall(
                 core::str::from_utf8(slice).map_err(|_| errno::EINVAL)?
             };
 
-            // Verify the directory exists in tmpfs
-            match crate::fs::filesystems::tmpfs::global_lookup_path(path) {
-                Ok(_) => Ok(0),
-                Err(_) => Err(errno::ENOENT),
+            // Verify the directory exists
+            match crate::fs::stat_file(path) {
+                Ok(attr) if attr.file_type == crate::fs::FileType::Directory => Ok(0),
+                Ok(_) => Err(errno::ENOTDIR),
+                Err(e) => Err(e),
             }
         }
         SyscallNumber::Mkdir => {
@@ -573,9 +714,9 @@ pub fn handle_syscall(
             let addr = if arg1 == 0 { None } else { Some(arg1) };
             let length = arg2;
             let prot = arg3 as i32;
-            let flags = _arg4 as i32;
-            let fd = _arg5 as i32;
-            let offset = _arg6;
+            let flags = arg4 as i32;
+            let fd = arg5 as i32;
+            let offset = arg6;
 
             // Use rinux_mm crate's mmap
             match rinux_mm::mmap::mmap(addr, length, prot, flags, fd, offset) {
@@ -645,18 +786,10 @@ pub fn handle_syscall(
             };
 
             match crate::fs::stat_file(pathname) {
-                Ok(info) => {
+                Ok(attr) => {
                     if !stat_buf.is_null() {
                         unsafe {
-                            (*stat_buf).st_ino = info.inode;
-                            (*stat_buf).st_mode = info.mode as u32;
-                            (*stat_buf).st_nlink = info.link_count;
-                            (*stat_buf).st_uid = info.uid;
-                            (*stat_buf).st_gid = info.gid;
-                            (*stat_buf).st_size = info.size as i64;
-                            (*stat_buf).st_atime = info.accessed_time as i64;
-                            (*stat_buf).st_mtime = info.modified_time as i64;
-                            (*stat_buf).st_ctime = info.created_time as i64;
+                            stat_buf.write(KernelStat::from_attr(&attr));
                         }
                     }
                     Ok(0)
@@ -669,34 +802,56 @@ pub fn handle_syscall(
             let fd = arg1 as i32;
             let stat_buf = arg2 as *mut KernelStat;
 
+            if crate::fs::fd::get_socket(fd).is_some() {
+                if !stat_buf.is_null() {
+                    unsafe {
+                        stat_buf.write(KernelStat {
+                            st_ino: 0,
+                            st_mode: S_IFSOCK | 0o777,
+                            st_nlink: 1,
+                            st_uid: 0,
+                            st_gid: 0,
+                            st_size: 0,
+                            st_atime: 0,
+                            st_mtime: 0,
+                            st_ctime: 0,
+                        });
+                    }
+                }
+                return Ok(0);
+            }
+
             let file = match crate::fs::fd::get_file(fd) {
                 Some(f) => f,
                 None => return Err(errno::EBADF),
             };
 
             if !stat_buf.is_null() {
-                // Try to look up actual inode metadata from tmpfs
-                let info = crate::fs::filesystems::tmpfs::global_stat_inode(file.inode);
+                let attr = match &file.vnode {
+                    Some(vnode) => vnode.getattr().map_err(crate::fs::FsError::to_errno)?,
+                    None => return Err(errno::EBADF),
+                };
                 unsafe {
-                    (*stat_buf).st_ino = file.inode;
-                    (*stat_buf).st_mode = info.as_ref().map(|s| s.mode as u32).unwrap_or(0o644);
-                    (*stat_buf).st_nlink = info.as_ref().map(|s| s.link_count).unwrap_or(1);
-                    (*stat_buf).st_uid = info.as_ref().map(|s| s.uid).unwrap_or(0);
-                    (*stat_buf).st_gid = info.as_ref().map(|s| s.gid).unwrap_or(0);
-                    (*stat_buf).st_size = info
-                        .as_ref()
-                        .map(|s| s.size as i64)
-                        .unwrap_or(file.size as i64);
-                    (*stat_buf).st_atime =
-                        info.as_ref().map(|s| s.accessed_time as i64).unwrap_or(0);
-                    (*stat_buf).st_mtime =
-                        info.as_ref().map(|s| s.modified_time as i64).unwrap_or(0);
-                    (*stat_buf).st_ctime =
-                        info.as_ref().map(|s| s.created_time as i64).unwrap_or(0);
+                    stat_buf.write(KernelStat::from_attr(&attr));
                 }
             }
             Ok(0)
         }
+        SyscallNumber::Socket => socket::sys_socket(arg1, arg2, arg3),
+        SyscallNumber::Connect => socket::sys_connect(arg1 as i32, arg2, arg3),
+        SyscallNumber::Accept => socket::sys_accept(arg1 as i32, arg2, arg3),
+        SyscallNumber::Sendto => socket::sys_sendto(arg1 as i32, arg2, arg3, arg4, arg5, arg6),
+        SyscallNumber::Recvfrom => socket::sys_recvfrom(arg1 as i32, arg2, arg3, arg4, arg5, arg6),
+        SyscallNumber::Sendmsg => socket::sys_sendmsg(arg1 as i32, arg2, arg3),
+        SyscallNumber::Recvmsg => socket::sys_recvmsg(arg1 as i32, arg2, arg3),
+        SyscallNumber::Shutdown => socket::sys_shutdown(arg1 as i32, arg2),
+        SyscallNumber::Bind => socket::sys_bind(arg1 as i32, arg2, arg3),
+        SyscallNumber::Listen => socket::sys_listen(arg1 as i32, arg2),
+        SyscallNumber::Getsockname => socket::sys_getsockname(arg1 as i32, arg2, arg3),
+        SyscallNumber::Getpeername => socket::sys_getpeername(arg1 as i32, arg2, arg3),
+        SyscallNumber::Socketpair => socket::sys_socketpair(arg1, arg2, arg3, arg4),
+        SyscallNumber::Setsockopt => socket::sys_setsockopt(arg1 as i32, arg2, arg3, arg4, arg5),
+        SyscallNumber::Getsockopt => socket::sys_getsockopt(arg1 as i32, arg2, arg3, arg4, arg5),
         SyscallNumber::Unknown => {
             crate::printk::printk("Unknown syscall\n");
             Err(errno::ENOSYS)
@@ -0,0 +1,1299 @@
+//! Socket System Calls
+//!
+//! Linux socket ABI on top of `net::socket`: address families, `sockaddr`
+//! layouts and socket option encodings. Socket descriptors live in the
+//! file descriptor table next to files.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{errno, SyscallResult};
+use crate::fs::fd::{self, InFlightFds};
+use crate::net::ethernet::MacAddress;
+use crate::net::socket::{
+    self, ControlMessage, Credentials, ShutdownHow, SocketAddr, SocketAddrLl, SocketAddrUnix,
+    SocketAddrV4, SocketAddrV6, SocketDomain, SocketError, SocketOption, SocketOptionType,
+    SocketProtocol, SocketType,
+};
+use crate::net::tcp::congestion::CongestionAlgorithm;
+use crate::security::validation::{
+    copy_from_user, copy_to_user, validate_user_buffer, validate_user_buffer_mut, MAX_BUFFER_SIZE,
+};
+
+/// Unix domain sockets
+pub const AF_UNIX: u16 = 1;
+/// IPv4
+pub const AF_INET: u16 = 2;
+/// IPv6
+pub const AF_INET6: u16 = 10;
+/// Netlink
+pub const AF_NETLINK: u16 = 16;
+/// Link-layer packets
+pub const AF_PACKET: u16 = 17;
+
+/// Stream socket
+pub const SOCK_STREAM: usize = 1;
+/// Datagram socket
+pub const SOCK_DGRAM: usize = 2;
+/// Raw socket
+pub const SOCK_RAW: usize = 3;
+/// Sequenced packet socket
+pub const SOCK_SEQPACKET: usize = 5;
+/// Socket type bits of the type argument
+const SOCK_TYPE_MASK: usize = 0xf;
+/// Create the socket in non-blocking mode
+pub const SOCK_NONBLOCK: usize = 0o4000;
+/// Close the socket on exec
+pub const SOCK_CLOEXEC: usize = 0o2000000;
+
+/// Default protocol of the socket type
+pub const IPPROTO_IP: usize = 0;
+/// ICMP
+pub const IPPROTO_ICMP: usize = 1;
+/// TCP
+pub const IPPROTO_TCP: usize = 6;
+/// UDP
+pub const IPPROTO_UDP: usize = 17;
+/// Raw IP
+pub const IPPROTO_RAW: usize = 255;
+
+/// Socket-level options
+pub const SOL_SOCKET: usize = 1;
+pub const SO_REUSEADDR: usize = 2;
+pub const SO_TYPE: usize = 3;
+pub const SO_ERROR: usize = 4;
+pub const SO_SNDBUF: usize = 7;
+pub const SO_RCVBUF: usize = 8;
+pub const SO_KEEPALIVE: usize = 9;
+pub const SO_LINGER: usize = 13;
+pub const SO_REUSEPORT: usize = 15;
+pub const SO_PASSCRED: usize = 16;
+pub const SO_RCVTIMEO: usize = 20;
+pub const SO_SNDTIMEO: usize = 21;
+/// TCP-level congestion control algorithm (name string)
+pub const TCP_CONGESTION: usize = 13;
+
+/// Shutdown modes
+pub const SHUT_RD: usize = 0;
+pub const SHUT_WR: usize = 1;
+pub const SHUT_RDWR: usize = 2;
+
+/// Ancillary data: file descriptors
+pub const SCM_RIGHTS: usize = 1;
+/// Ancillary data: process credentials
+pub const SCM_CREDENTIALS: usize = 2;
+
+/// Control data was truncated
+pub const MSG_CTRUNC: u32 = 0x8;
+/// Datagram was truncated
+pub const MSG_TRUNC: u32 = 0x20;
+
+/// Most descriptors in one SCM_RIGHTS message
+const SCM_MAX_FD: usize = 253;
+/// Most entries in an iovec array
+const UIO_MAXIOV: usize = 1024;
+/// Size of `struct msghdr`
+const MSGHDR_LEN: usize = 56;
+/// Size of `struct cmsghdr`
+const CMSGHDR_LEN: usize = 16;
+/// Size of `struct ucred`
+const UCRED_LEN: usize = 12;
+/// Most control data accepted by sendmsg
+const CONTROL_MAX: usize = 4096;
+
+/// Size of `struct sockaddr_in`
+const SOCKADDR_IN_LEN: usize = 16;
+/// Size of `struct sockaddr_in6`
+const SOCKADDR_IN6_LEN: usize = 28;
+/// Size of `struct sockaddr_un`
+const SOCKADDR_UN_LEN: usize = 110;
+/// Size of `struct sockaddr_ll`
+const SOCKADDR_LL_LEN: usize = 20;
+/// Size of `struct sockaddr_storage`, the largest address accepted
+const SOCKADDR_STORAGE_LEN: usize = 128;
+/// Longest congestion control algorithm name
+const TCP_CA_NAME_MAX: usize = 16;
+
+/// Translate a socket error to errno
+pub fn socket_errno(err: SocketError) -> isize {
+    match err {
+        SocketError::AddrInUse => errno::EADDRINUSE,
+        SocketError::AddrNotAvail => errno::EADDRNOTAVAIL,
+        SocketError::ConnRefused => errno::ECONNREFUSED,
+        SocketError::NotConnected => errno::ENOTCONN,
+        SocketError::AlreadyConnected => errno::EISCONN,
+        SocketError::WouldBlock => errno::EAGAIN,
+        SocketError::ConnReset => errno::ECONNRESET,
+        SocketError::TimedOut => errno::ETIMEDOUT,
+        SocketError::NetUnreachable => errno::ENETUNREACH,
+        SocketError::HostUnreachable => errno::EHOSTUNREACH,
+        SocketError::InvalidArg => errno::EINVAL,
+        SocketError::NotSupported => errno::EOPNOTSUPP,
+        SocketError::PermissionDenied => errno::EACCES,
+        SocketError::OutOfMemory => errno::ENOMEM,
+        SocketError::InProgress => errno::EINPROGRESS,
+        SocketError::BrokenPipe => errno::EPIPE,
+        SocketError::Other => errno::EIO,
+    }
+}
+
+/// Copy a user buffer into `buffer`
+fn copy_in(ptr: usize, buffer: &mut [u8]) -> Result<(), isize> {
+    // SAFETY: copy_from_user checks that the range lies in user space
+    unsafe { copy_from_user(ptr as *const u8, buffer) }.map_err(|_| errno::EFAULT)
+}
+
+/// Copy `len` bytes of user memory into a kernel buffer
+///
+/// The range is checked before anything is allocated.
+fn read_user_bytes(ptr: usize, len: usize) -> Result<Vec<u8>, isize> {
+    validate_user_buffer(ptr as *const u8, len).map_err(|_| errno::EFAULT)?;
+    let mut buffer = vec![0u8; len];
+    copy_in(ptr, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Copy in the data of a send call
+///
+/// Messages longer than `MAX_BUFFER_SIZE` are refused with EMSGSIZE.
+fn read_send_data(ptr: usize, len: usize) -> Result<Vec<u8>, isize> {
+    if len > MAX_BUFFER_SIZE {
+        return Err(errno::EMSGSIZE);
+    }
+    read_user_bytes(ptr, len)
+}
+
+/// Copy `bytes` out to user memory
+fn write_user_bytes(ptr: usize, bytes: &[u8]) -> Result<(), isize> {
+    // SAFETY: copy_to_user checks that the range lies in user space
+    unsafe { copy_to_user(bytes, ptr as *mut u8) }.map_err(|_| errno::EFAULT)
+}
+
+/// Check that a user buffer can be written before consuming any data
+fn check_user_buffer_mut(ptr: usize, len: usize) -> Result<(), isize> {
+    if len == 0 {
+        return Ok(());
+    }
+    validate_user_buffer_mut(ptr as *mut u8, len).map_err(|_| errno::EFAULT)
+}
+
+/// Read a native-endian `u32` from user space
+fn read_user_u32(ptr: usize) -> Result<u32, isize> {
+    let mut bytes = [0u8; 4];
+    copy_in(ptr, &mut bytes)?;
+    Ok(u32::from_ne_bytes(bytes))
+}
+
+/// Write a native-endian `u32` to user space
+fn write_user_u32(ptr: usize, value: u32) -> Result<(), isize> {
+    write_user_bytes(ptr, &value.to_ne_bytes())
+}
+
+/// Write a native-endian `usize` to user space
+fn write_user_usize(ptr: usize, value: usize) -> Result<(), isize> {
+    write_user_bytes(ptr, &value.to_ne_bytes())
+}
+
+/// Look up the socket behind a file descriptor
+fn socket_handle(fd: i32) -> Result<i32, isize> {
+    match fd::get_socket(fd) {
+        Some(handle) => Ok(handle),
+        None if fd::get_file(fd).is_some() => Err(errno::ENOTSOCK),
+        None => Err(errno::EBADF),
+    }
+}
+
+/// Decode a `sockaddr` from user space
+fn read_sockaddr(ptr: usize, len: usize) -> Result<SocketAddr, isize> {
+    if !(2..=SOCKADDR_STORAGE_LEN).contains(&len) {
+        return Err(errno::EINVAL);
+    }
+    let bytes = read_user_bytes(ptr, len)?;
+    let port = u16::from_be_bytes([
+        bytes.get(2).copied().unwrap_or(0),
+        bytes.get(3).copied().unwrap_or(0),
+    ]);
+
+    match u16::from_ne_bytes([bytes[0], bytes[1]]) {
+        AF_INET if len >= SOCKADDR_IN_LEN => {
+            let mut ip = [0u8; 4];
+            ip.copy_from_slice(&bytes[4..8]);
+            Ok(SocketAddr::V4(SocketAddrV4 { ip, port }))
+        }
+        AF_INET6 if len >= SOCKADDR_IN6_LEN => {
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(&bytes[8..24]);
+            Ok(SocketAddr::V6(SocketAddrV6 {
+                ip,
+                port,
+                flowinfo: u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+                scope_id: u32::from_ne_bytes([bytes[24], bytes[25], bytes[26], bytes[27]]),
+            }))
+        }
+        AF_UNIX => {
+            let path = &bytes[2..len.min(SOCKADDR_UN_LEN)];
+            let end = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+            let path = core::str::from_utf8(&path[..end]).map_err(|_| errno::EINVAL)?;
+            Ok(SocketAddr::Unix(SocketAddrUnix {
+                path: String::from(path),
+            }))
+        }
+        AF_PACKET if len >= SOCKADDR_LL_LEN => {
+            let mut addr = [0u8; 6];
+            addr.copy_from_slice(&bytes[12..18]);
+            Ok(SocketAddr::Packet(SocketAddrLl {
+                protocol: port,
+                ifindex: u32::from_ne_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+                hatype: u16::from_ne_bytes([bytes[8], bytes[9]]),
+                pkttype: bytes[10],
+                addr: MacAddress::new(addr),
+            }))
+        }
+        AF_INET | AF_INET6 | AF_PACKET => Err(errno::EINVAL),
+        _ => Err(errno::EAFNOSUPPORT),
+    }
+}
+
+/// Encode a `sockaddr` in its Linux layout
+fn encode_sockaddr(addr: &SocketAddr) -> ([u8; SOCKADDR_UN_LEN], usize) {
+    let mut bytes = [0u8; SOCKADDR_UN_LEN];
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            bytes[0..2].copy_from_slice(&AF_INET.to_ne_bytes());
+            bytes[2..4].copy_from_slice(&addr.port.to_be_bytes());
+            bytes[4..8].copy_from_slice(&addr.ip);
+            SOCKADDR_IN_LEN
+        }
+        SocketAddr::V6(addr) => {
+            bytes[0..2].copy_from_slice(&AF_INET6.to_ne_bytes());
+            bytes[2..4].copy_from_slice(&addr.port.to_be_bytes());
+            bytes[4..8].copy_from_slice(&addr.flowinfo.to_be_bytes());
+            bytes[8..24].copy_from_slice(&addr.ip);
+            bytes[24..28].copy_from_slice(&addr.scope_id.to_ne_bytes());
+            SOCKADDR_IN6_LEN
+        }
+        SocketAddr::Unix(addr) => {
+            bytes[0..2].copy_from_slice(&AF_UNIX.to_ne_bytes());
+            let path = addr.path.as_bytes();
+            let len = path.len().min(SOCKADDR_UN_LEN - 3);
+            bytes[2..2 + len].copy_from_slice(&path[..len]);
+            // Unnamed sockets report just the family
+            if len == 0 {
+                2
+            } else {
+                2 + len + 1
+            }
+        }
+        SocketAddr::Packet(addr) => {
+            bytes[0..2].copy_from_slice(&AF_PACKET.to_ne_bytes());
+            bytes[2..4].copy_from_slice(&addr.protocol.to_be_bytes());
+            bytes[4..8].copy_from_slice(&addr.ifindex.to_ne_bytes());
+            bytes[8..10].copy_from_slice(&addr.hatype.to_ne_bytes());
+            bytes[10] = addr.pkttype;
+            bytes[11] = 6;
+            bytes[12..18].copy_from_slice(&addr.addr.octets());
+            SOCKADDR_LL_LEN
+        }
+    };
+    (bytes, len)
+}
+
+/// Store a `sockaddr` to user space
+///
+/// Like Linux, the address is truncated to the buffer and `*addrlen` is set
+/// to its full length.
+fn write_sockaddr(addr: Option<&SocketAddr>, ptr: usize, addrlen_ptr: usize) -> Result<(), isize> {
+    if ptr == 0 || addrlen_ptr == 0 {
+        return Ok(());
+    }
+
+    let capacity = read_user_u32(addrlen_ptr)? as usize;
+    let (bytes, len) = match addr {
+        Some(addr) => encode_sockaddr(addr),
+        None => ([0u8; SOCKADDR_UN_LEN], 0),
+    };
+    let copy = len.min(capacity);
+    write_user_bytes(ptr, &bytes[..copy])?;
+    write_user_u32(addrlen_ptr, len as u32)
+}
+
+/// Socket option value as a boolean
+fn read_opt_bool(optval: usize, optlen: usize) -> Result<bool, isize> {
+    Ok(read_opt_int(optval, optlen)? != 0)
+}
+
+/// Socket option value as an `int`
+fn read_opt_int(optval: usize, optlen: usize) -> Result<u32, isize> {
+    if optlen < 4 {
+        return Err(errno::EINVAL);
+    }
+    read_user_u32(optval)
+}
+
+/// Socket option value as a `struct timeval`, in milliseconds
+fn read_opt_timeval(optval: usize, optlen: usize) -> Result<Option<u64>, isize> {
+    if optlen < 16 {
+        return Err(errno::EINVAL);
+    }
+    let mut bytes = [0u8; 16];
+    copy_in(optval, &mut bytes)?;
+    let sec = i64::from_ne_bytes(bytes[0..8].try_into().unwrap());
+    let usec = i64::from_ne_bytes(bytes[8..16].try_into().unwrap());
+    if sec < 0 || !(0..1_000_000).contains(&usec) {
+        return Err(errno::EINVAL);
+    }
+
+    let ms = (sec as u64).saturating_mul(1000) + (usec as u64).div_ceil(1000);
+    Ok((ms > 0).then_some(ms))
+}
+
+/// Decode a socket option from user space
+fn read_sockopt(
+    level: usize,
+    optname: usize,
+    optval: usize,
+    optlen: usize,
+) -> Result<SocketOption, isize> {
+    let option = match (level, optname) {
+        (SOL_SOCKET, SO_REUSEADDR) => SocketOption::ReuseAddr(read_opt_bool(optval, optlen)?),
+        (SOL_SOCKET, SO_REUSEPORT) => SocketOption::ReusePort(read_opt_bool(optval, optlen)?),
+        (SOL_SOCKET, SO_KEEPALIVE) => SocketOption::KeepAlive(read_opt_bool(optval, optlen)?),
+        (SOL_SOCKET, SO_PASSCRED) => SocketOption::PassCred(read_opt_bool(optval, optlen)?),
+        (SOL_SOCKET, SO_RCVBUF) => SocketOption::RcvBuf(read_opt_int(optval, optlen)? as usize),
+        (SOL_SOCKET, SO_SNDBUF) => SocketOption::SndBuf(read_opt_int(optval, optlen)? as usize),
+        (SOL_SOCKET, SO_LINGER) => {
+            if optlen < 8 {
+                return Err(errno::EINVAL);
+            }
+            let onoff = read_user_u32(optval)?;
+            let seconds = read_user_u32(optval + 4)?;
+            SocketOption::Linger((onoff != 0).then_some(seconds))
+        }
+        (SOL_SOCKET, SO_RCVTIMEO) => SocketOption::RcvTimeo(read_opt_timeval(optval, optlen)?),
+        (SOL_SOCKET, SO_SNDTIMEO) => SocketOption::SndTimeo(read_opt_timeval(optval, optlen)?),
+        (IPPROTO_TCP, TCP_CONGESTION) => {
+            let bytes = read_user_bytes(optval, optlen.min(TCP_CA_NAME_MAX))?;
+            let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            let name = core::str::from_utf8(&bytes[..end]).map_err(|_| errno::EINVAL)?;
+            SocketOption::TcpCongestion(CongestionAlgorithm::from_name(name).ok_or(errno::ENOENT)?)
+        }
+        _ => return Err(errno::ENOPROTOOPT),
+    };
+    Ok(option)
+}
+
+/// Map a socket option name to its type
+fn sockopt_type(level: usize, optname: usize) -> Result<SocketOptionType, isize> {
+    match (level, optname) {
+        (SOL_SOCKET, SO_REUSEADDR) => Ok(SocketOptionType::ReuseAddr),
+        (SOL_SOCKET, SO_REUSEPORT) => Ok(SocketOptionType::ReusePort),
+        (SOL_SOCKET, SO_KEEPALIVE) => Ok(SocketOptionType::KeepAlive),
+        (SOL_SOCKET, SO_PASSCRED) => Ok(SocketOptionType::PassCred),
+        (SOL_SOCKET, SO_RCVBUF) => Ok(SocketOptionType::RcvBuf),
+        (SOL_SOCKET, SO_SNDBUF) => Ok(SocketOptionType::SndBuf),
+        (SOL_SOCKET, SO_LINGER) => Ok(SocketOptionType::Linger),
+        (SOL_SOCKET, SO_RCVTIMEO) => Ok(SocketOptionType::RcvTimeo),
+        (SOL_SOCKET, SO_SNDTIMEO) => Ok(SocketOptionType::SndTimeo),
+        (IPPROTO_TCP, TCP_CONGESTION) => Ok(SocketOptionType::TcpCongestion),
+        _ => Err(errno::ENOPROTOOPT),
+    }
+}
+
+/// Encode a socket option value in its Linux layout
+fn encode_sockopt(option: SocketOption) -> ([u8; 16], usize) {
+    let mut bytes = [0u8; 16];
+    let int = |bytes: &mut [u8; 16], value: u32| {
+        bytes[..4].copy_from_slice(&value.to_ne_bytes());
+        4
+    };
+    let timeval = |bytes: &mut [u8; 16], ms: Option<u64>| {
+        let ms = ms.unwrap_or(0);
+        bytes[..8].copy_from_slice(&((ms / 1000) as i64).to_ne_bytes());
+        bytes[8..].copy_from_slice(&(((ms % 1000) * 1000) as i64).to_ne_bytes());
+        16
+    };
+
+    let len = match option {
+        SocketOption::ReuseAddr(value)
+        | SocketOption::ReusePort(value)
+        | SocketOption::KeepAlive(value)
+        | SocketOption::NonBlocking(value)
+        | SocketOption::PassCred(value) => int(&mut bytes, value as u32),
+        SocketOption::RcvBuf(size) | SocketOption::SndBuf(size) => int(&mut bytes, size as u32),
+        SocketOption::Linger(linger) => {
+            bytes[..4].copy_from_slice(&(linger.is_some() as u32).to_ne_bytes());
+            bytes[4..8].copy_from_slice(&linger.unwrap_or(0).to_ne_bytes());
+            8
+        }
+        SocketOption::RcvTimeo(ms) | SocketOption::SndTimeo(ms) => timeval(&mut bytes, ms),
+        SocketOption::TcpCongestion(algorithm) => {
+            let name = algorithm.name().as_bytes();
+            bytes[..name.len()].copy_from_slice(name);
+            name.len() + 1
+        }
+    };
+    (bytes, len)
+}
+
+/// Decode the arguments of socket(2) and socketpair(2)
+fn socket_args(
+    domain: usize,
+    socket_type: usize,
+    protocol: usize,
+) -> Result<(SocketDomain, SocketType, SocketProtocol), isize> {
+    let domain = u16::try_from(domain).map_err(|_| errno::EAFNOSUPPORT)?;
+    let domain = match domain {
+        AF_INET => SocketDomain::Inet,
+        AF_INET6 => SocketDomain::Inet6,
+        AF_UNIX => SocketDomain::Unix,
+        AF_NETLINK => SocketDomain::Netlink,
+        AF_PACKET => SocketDomain::Packet,
+        _ => return Err(errno::EAFNOSUPPORT),
+    };
+    if socket_type & !(SOCK_TYPE_MASK | SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(errno::EINVAL);
+    }
+    let kind = match socket_type & SOCK_TYPE_MASK {
+        SOCK_STREAM => SocketType::Stream,
+        SOCK_DGRAM => SocketType::Dgram,
+        SOCK_RAW => SocketType::Raw,
+        SOCK_SEQPACKET => SocketType::SeqPacket,
+        _ => return Err(errno::EINVAL),
+    };
+    let protocol = match protocol {
+        // Packet sockets take an EtherType in network byte order
+        _ if domain == SocketDomain::Packet => {
+            let ethertype = u16::try_from(protocol).map_err(|_| errno::EPROTONOSUPPORT)?;
+            match u16::from_be(ethertype) {
+                0 => SocketProtocol::Default,
+                ethertype => SocketProtocol::Packet(ethertype),
+            }
+        }
+        IPPROTO_IP => SocketProtocol::Default,
+        IPPROTO_ICMP => SocketProtocol::Icmp,
+        IPPROTO_TCP => SocketProtocol::Tcp,
+        IPPROTO_UDP => SocketProtocol::Udp,
+        IPPROTO_RAW => SocketProtocol::Raw,
+        _ => return Err(errno::EPROTONOSUPPORT),
+    };
+    Ok((domain, kind, protocol))
+}
+
+/// socket(domain, type, protocol)
+pub fn sys_socket(domain: usize, socket_type: usize, protocol: usize) -> SyscallResult {
+    let (domain, kind, protocol) = socket_args(domain, socket_type, protocol)?;
+    let handle = socket::socket(domain, kind, protocol).map_err(|err| match err {
+        SocketError::NotSupported => errno::EPROTONOSUPPORT,
+        err => socket_errno(err),
+    })?;
+    if socket_type & SOCK_NONBLOCK != 0 {
+        let _ = socket::set_nonblocking(handle, true);
+    }
+
+    match fd::allocate_socket_fd(handle, socket_type & SOCK_CLOEXEC != 0) {
+        Ok(fd) => Ok(fd as usize),
+        Err(()) => {
+            let _ = socket::close_socket(handle);
+            Err(errno::EMFILE)
+        }
+    }
+}
+
+/// socketpair(domain, type, protocol, sv)
+pub fn sys_socketpair(
+    domain: usize,
+    socket_type: usize,
+    protocol: usize,
+    sv: usize,
+) -> SyscallResult {
+    let (domain, kind, protocol) = socket_args(domain, socket_type, protocol)?;
+    check_user_buffer_mut(sv, 8)?;
+
+    let (a, b) = socket::socketpair(domain, kind, protocol).map_err(|err| match err {
+        SocketError::NotSupported => errno::EOPNOTSUPP,
+        err => socket_errno(err),
+    })?;
+    if socket_type & SOCK_NONBLOCK != 0 {
+        let _ = socket::set_nonblocking(a, true);
+        let _ = socket::set_nonblocking(b, true);
+    }
+
+    let cloexec = socket_type & SOCK_CLOEXEC != 0;
+    let Ok(fd_a) = fd::allocate_socket_fd(a, cloexec) else {
+        let _ = socket::close_socket(a);
+        let _ = socket::close_socket(b);
+        return Err(errno::EMFILE);
+    };
+    let Ok(fd_b) = fd::allocate_socket_fd(b, cloexec) else {
+        let _ = fd::free_fd(fd_a);
+        let _ = socket::close_socket(b);
+        return Err(errno::EMFILE);
+    };
+
+    write_user_u32(sv, fd_a as u32)?;
+    write_user_u32(sv + 4, fd_b as u32)?;
+    Ok(0)
+}
+
+/// bind(fd, addr, addrlen)
+pub fn sys_bind(fd: i32, addr: usize, addrlen: usize) -> SyscallResult {
+    let handle = socket_handle(fd)?;
+    let addr = read_sockaddr(addr, addrlen)?;
+    socket::bind(handle, addr).map_err(socket_errno)?;
+    Ok(0)
+}
+
+/// listen(fd, backlog)
+pub fn sys_listen(fd: i32, backlog: usize) -> SyscallResult {
+    let handle = socket_handle(fd)?;
+    socket::listen(handle, backlog.min(u32::MAX as usize) as u32).map_err(socket_errno)?;
+    Ok(0)
+}
+
+/// accept(fd, addr, addrlen)
+pub fn sys_accept(fd: i32, addr: usize, addrlen: usize) -> SyscallResult {
+    let handle = socket_handle(fd)?;
+    let new_handle = socket::accept(handle).map_err(socket_errno)?;
+
+    let peer = socket::peer_addr(new_handle).ok().flatten();
+    let result = write_sockaddr(peer.as_ref(), addr, addrlen)
+        .and_then(|()| fd::allocate_socket_fd(new_handle, false).map_err(|()| errno::EMFILE));
+    match result {
+        Ok(new_fd) => Ok(new_fd as usize),
+        Err(err) => {
+            let _ = socket::close_socket(new_handle);
+            Err(err)
+        }
+    }
+}
+
+/// connect(fd, addr, addrlen)
+pub fn sys_connect(fd: i32, addr: usize, addrlen: usize) -> SyscallResult {
+    let handle = socket_handle(fd)?;
+    let addr = read_sockaddr(addr, addrlen)?;
+    socket::connect(handle, addr).map_err(socket_errno)?;
+    Ok(0)
+}
+
+/// sendto(fd, buf, len, flags, dest_addr, addrlen)
+pub fn sys_sendto(
+    fd: i32,
+    buf: usize,
+    len: usize,
+    flags: usize,
+    addr: usize,
+    addrlen: usize,
+) -> SyscallResult {
+    let handle = socket_handle(fd)?;
+    let data = read_send_data(buf, len)?;
+    let flags = flags as u32;
+
+    let sent = if addr == 0 {
+        socket::send(handle, &data, flags)
+    } else {
+        socket::sendto(handle, &data, read_sockaddr(addr, addrlen)?, flags)
+    };
+    sent.map_err(socket_errno)
+}
+
+/// recvfrom(fd, buf, len, flags, src_addr, addrlen)
+pub fn sys_recvfrom(
+    fd: i32,
+    buf: usize,
+    len: usize,
+    flags: usize,
+    addr: usize,
+    addrlen: usize,
+) -> SyscallResult {
+    let handle = socket_handle(fd)?;
+    let len = len.min(MAX_BUFFER_SIZE);
+    check_user_buffer_mut(buf, len)?;
+    let mut buffer = vec![0u8; len];
+    let flags = flags as u32;
+
+    let (received, src) = if addr == 0 {
+        (
+            socket::recv(handle, &mut buffer, flags).map_err(socket_errno)?,
+            None,
+        )
+    } else {
+        match socket::recvfrom(handle, &mut buffer, flags) {
+            Ok((received, src)) => (received, Some(src)),
+            // Connected stream sockets have no per-message source
+            Err(SocketError::NotSupported) => (
+                socket::recv(handle, &mut buffer, flags).map_err(socket_errno)?,
+                None,
+            ),
+            Err(err) => return Err(socket_errno(err)),
+        }
+    };
+
+    write_user_bytes(buf, &buffer[..received.min(len)])?;
+    if addr != 0 {
+        write_sockaddr(src.as_ref(), addr, addrlen)?;
+    }
+    Ok(received)
+}
+
+/// shutdown(fd, how)
+pub fn sys_shutdown(fd: i32, how: usize) -> SyscallResult {
+    let handle = socket_handle(fd)?;
+    let how = match how {
+        SHUT_RD => ShutdownHow::Read,
+        SHUT_WR => ShutdownHow::Write,
+        SHUT_RDWR => ShutdownHow::Both,
+        _ => return Err(errno::EINVAL),
+    };
+    socket::shutdown(handle, how).map_err(socket_errno)?;
+    Ok(0)
+}
+
+/// setsockopt(fd, level, optname, optval, optlen)
+pub fn sys_setsockopt(
+    fd: i32,
+    level: usize,
+    optname: usize,
+    optval: usize,
+    optlen: usize,
+) -> SyscallResult {
+    let handle = socket_handle(fd)?;
+    let option = read_sockopt(level, optname, optval, optlen)?;
+    socket::setsockopt(handle, option).map_err(|err| match err {
+        SocketError::NotSupported => errno::ENOPROTOOPT,
+        err => socket_errno(err),
+    })?;
+    Ok(0)
+}
+
+/// getsockopt(fd, level, optname, optval, optlen)
+pub fn sys_getsockopt(
+    fd: i32,
+    level: usize,
+    optname: usize,
+    optval: usize,
+    optlen: usize,
+) -> SyscallResult {
+    let handle = socket_handle(fd)?;
+    let capacity = read_user_u32(optlen)? as usize;
+
+    let (bytes, len) = match (level, optname) {
+        (SOL_SOCKET, SO_ERROR) => {
+            let error = socket::take_error(handle).map_err(socket_errno)?;
+            let value = error.map(|err| -socket_errno(err) as u32).unwrap_or(0);
+            let mut bytes = [0u8; 16];
+            bytes[..4].copy_from_slice(&value.to_ne_bytes());
+            (bytes, 4)
+        }
+        _ => {
+            let option = sockopt_type(level, optname)?;
+            let option = socket::getsockopt(handle, option).map_err(|err| match err {
+                SocketError::NotSupported => errno::ENOPROTOOPT,
+                err => socket_errno(err),
+            })?;
+            encode_sockopt(option)
+        }
+    };
+
+    let copy = len.min(capacity);
+    write_user_bytes(optval, &bytes[..copy])?;
+    write_user_u32(optlen, copy as u32)?;
+    Ok(0)
+}
+
+/// User `struct msghdr`
+struct MsgHdr {
+    name: usize,
+    namelen: usize,
+    iov: usize,
+    iovlen: usize,
+    control: usize,
+    controllen: usize,
+}
+
+/// Offset of `msg_namelen` in `struct msghdr`
+const MSGHDR_NAMELEN: usize = 8;
+/// Offset of `msg_controllen` in `struct msghdr`
+const MSGHDR_CONTROLLEN: usize = 40;
+/// Offset of `msg_flags` in `struct msghdr`
+const MSGHDR_FLAGS: usize = 48;
+
+/// Read a `struct msghdr` from user space
+fn read_msghdr(ptr: usize) -> Result<MsgHdr, isize> {
+    let mut bytes = [0u8; MSGHDR_LEN];
+    copy_in(ptr, &mut bytes)?;
+    let word = |offset: usize| usize::from_ne_bytes(bytes[offset..offset + 8].try_into().unwrap());
+    Ok(MsgHdr {
+        name: word(0),
+        namelen: u32::from_ne_bytes(bytes[8..12].try_into().unwrap()) as usize,
+        iov: word(16),
+        iovlen: word(24),
+        control: word(32),
+        controllen: word(MSGHDR_CONTROLLEN),
+    })
+}
+
+/// Read an iovec array as (base, length) pairs
+fn read_iovecs(ptr: usize, count: usize) -> Result<Vec<(usize, usize)>, isize> {
+    if count > UIO_MAXIOV {
+        return Err(errno::EINVAL);
+    }
+    let bytes = read_user_bytes(ptr, count * 16)?;
+    let iovecs: Vec<(usize, usize)> = bytes
+        .chunks_exact(16)
+        .map(|iov| {
+            (
+                usize::from_ne_bytes(iov[0..8].try_into().unwrap()),
+                usize::from_ne_bytes(iov[8..16].try_into().unwrap()),
+            )
+        })
+        .collect();
+
+    let total = iovecs
+        .iter()
+        .try_fold(0usize, |total, &(_, len)| total.checked_add(len));
+    match total {
+        Some(total) if total <= isize::MAX as usize => Ok(iovecs),
+        _ => Err(errno::EINVAL),
+    }
+}
+
+/// Round a control message length up to the next header boundary
+const fn cmsg_align(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+/// Decode the control messages of sendmsg
+fn read_control(ptr: usize, len: usize) -> Result<Vec<ControlMessage>, isize> {
+    if len > CONTROL_MAX {
+        return Err(errno::EINVAL);
+    }
+    let bytes = read_user_bytes(ptr, len)?;
+    let mut control = Vec::new();
+    let mut rights = Vec::new();
+
+    let mut offset = 0;
+    while offset + CMSGHDR_LEN <= bytes.len() {
+        let header = &bytes[offset..offset + CMSGHDR_LEN];
+        let cmsg_len = usize::from_ne_bytes(header[0..8].try_into().unwrap());
+        let level = u32::from_ne_bytes(header[8..12].try_into().unwrap()) as usize;
+        let kind = u32::from_ne_bytes(header[12..16].try_into().unwrap()) as usize;
+        if cmsg_len < CMSGHDR_LEN || cmsg_len > bytes.len() - offset {
+            return Err(errno::EINVAL);
+        }
+        let data = &bytes[offset + CMSGHDR_LEN..offset + cmsg_len];
+
+        match (level, kind) {
+            (SOL_SOCKET, SCM_RIGHTS) => {
+                if data.len() % 4 != 0 || rights.len() + data.len() / 4 > SCM_MAX_FD {
+                    return Err(errno::EINVAL);
+                }
+                for fd in data.chunks_exact(4) {
+                    let fd = i32::from_ne_bytes(fd.try_into().unwrap());
+                    rights.push(fd::get_entry(fd).ok_or(errno::EBADF)?);
+                }
+            }
+            (SOL_SOCKET, SCM_CREDENTIALS) => {
+                if data.len() < UCRED_LEN {
+                    return Err(errno::EINVAL);
+                }
+                control.push(ControlMessage::Credentials(Credentials {
+                    pid: i32::from_ne_bytes(data[0..4].try_into().unwrap()),
+                    uid: u32::from_ne_bytes(data[4..8].try_into().unwrap()),
+                    gid: u32::from_ne_bytes(data[8..12].try_into().unwrap()),
+                }));
+            }
+            _ => return Err(errno::EINVAL),
+        }
+        offset += cmsg_align(cmsg_len);
+    }
+
+    if !rights.is_empty() {
+        control.push(ControlMessage::Rights(InFlightFds::new(rights)));
+    }
+    Ok(control)
+}
+
+/// Append one control message to `buffer`
+///
+/// The padding is added as far as it fits in `capacity`.
+fn put_cmsg(buffer: &mut Vec<u8>, capacity: usize, kind: usize, data: &[u8]) {
+    let start = buffer.len();
+    let len = CMSGHDR_LEN + data.len();
+    buffer.extend_from_slice(&len.to_ne_bytes());
+    buffer.extend_from_slice(&(SOL_SOCKET as u32).to_ne_bytes());
+    buffer.extend_from_slice(&(kind as u32).to_ne_bytes());
+    buffer.extend_from_slice(data);
+    buffer.resize((start + cmsg_align(len)).min(capacity), 0);
+}
+
+/// Store received control messages in a user buffer
+///
+/// Received descriptors are installed in the descriptor table. Returns
+/// the bytes used and whether anything was dropped for lack of space;
+/// descriptors that do not fit are closed.
+fn write_control(
+    ptr: usize,
+    capacity: usize,
+    control: Vec<ControlMessage>,
+) -> Result<(usize, bool), isize> {
+    check_user_buffer_mut(ptr, capacity)?;
+    let mut buffer = Vec::new();
+    let mut truncated = false;
+
+    for message in control {
+        let room = capacity - buffer.len();
+        match message {
+            ControlMessage::Credentials(creds) => {
+                if room < CMSGHDR_LEN + UCRED_LEN {
+                    truncated = true;
+                    continue;
+                }
+                let mut data = [0u8; UCRED_LEN];
+                data[0..4].copy_from_slice(&creds.pid.to_ne_bytes());
+                data[4..8].copy_from_slice(&creds.uid.to_ne_bytes());
+                data[8..12].copy_from_slice(&creds.gid.to_ne_bytes());
+                put_cmsg(&mut buffer, capacity, SCM_CREDENTIALS, &data);
+            }
+            ControlMessage::Rights(rights) => {
+                let fits = room.saturating_sub(CMSGHDR_LEN) / 4;
+                let mut data = Vec::new();
+                for entry in rights.entries().iter().take(fits) {
+                    let Ok(fd) = fd::install_entry(entry.clone()) else {
+                        break;
+                    };
+                    data.extend_from_slice(&fd.to_ne_bytes());
+                }
+                if data.len() / 4 < rights.entries().len() {
+                    truncated = true;
+                }
+                if !data.is_empty() {
+                    put_cmsg(&mut buffer, capacity, SCM_RIGHTS, &data);
+                }
+                // Dropping `rights` releases the references held in flight
+            }
+        }
+    }
+    write_user_bytes(ptr, &buffer)?;
+    Ok((buffer.len(), truncated))
+}
+
+/// sendmsg(fd, msg, flags)
+pub fn sys_sendmsg(fd: i32, msg: usize, flags: usize) -> SyscallResult {
+    let handle = socket_handle(fd)?;
+    let header = read_msghdr(msg)?;
+
+    let addr = match header.name {
+        0 => None,
+        name => Some(read_sockaddr(name, header.namelen)?),
+    };
+    let iovecs = read_iovecs(header.iov, header.iovlen)?;
+    let total: usize = iovecs.iter().map(|&(_, len)| len).sum();
+    if total > MAX_BUFFER_SIZE {
+        return Err(errno::EMSGSIZE);
+    }
+    let mut data = vec![0u8; total];
+    let mut start = 0;
+    for (base, len) in iovecs {
+        copy_in(base, &mut data[start..start + len])?;
+        start += len;
+    }
+    let control = match header.controllen {
+        0 => Vec::new(),
+        len => read_control(header.control, len)?,
+    };
+
+    socket::sendmsg(handle, &data, addr, &control, flags as u32).map_err(socket_errno)
+}
+
+/// recvmsg(fd, msg, flags)
+pub fn sys_recvmsg(fd: i32, msg: usize, flags: usize) -> SyscallResult {
+    let handle = socket_handle(fd)?;
+    let header = read_msghdr(msg)?;
+    let iovecs = read_iovecs(header.iov, header.iovlen)?;
+
+    // Check every iovec before consuming a message; at most
+    // MAX_BUFFER_SIZE bytes are received
+    let mut total = 0;
+    for &(base, len) in &iovecs {
+        let len = len.min(MAX_BUFFER_SIZE - total);
+        check_user_buffer_mut(base, len)?;
+        total += len;
+    }
+    let mut buffer = vec![0u8; total];
+    let message = socket::recvmsg(handle, &mut buffer, flags as u32).map_err(socket_errno)?;
+
+    let mut copied = 0;
+    for (base, len) in iovecs {
+        if copied >= message.len {
+            break;
+        }
+        let len = len.min(message.len - copied);
+        write_user_bytes(base, &buffer[copied..copied + len])?;
+        copied += len;
+    }
+
+    if header.name != 0 {
+        write_sockaddr(message.addr.as_ref(), header.name, msg + MSGHDR_NAMELEN)?;
+    }
+    let has_control = !message.control.is_empty();
+    let (used, ctrunc) = match header.control {
+        0 => (0, has_control),
+        control => write_control(control, header.controllen, message.control)?,
+    };
+    write_user_usize(msg + MSGHDR_CONTROLLEN, used)?;
+
+    let mut msg_flags = 0;
+    if message.truncated {
+        msg_flags |= MSG_TRUNC;
+    }
+    if ctrunc {
+        msg_flags |= MSG_CTRUNC;
+    }
+    write_user_u32(msg + MSGHDR_FLAGS, msg_flags)?;
+    Ok(message.len)
+}
+
+/// getsockname(fd, addr, addrlen)
+pub fn sys_getsockname(fd: i32, addr: usize, addrlen: usize) -> SyscallResult {
+    let handle = socket_handle(fd)?;
+    let local = socket::local_addr(handle).map_err(socket_errno)?;
+    write_sockaddr(local.as_ref(), addr, addrlen)?;
+    Ok(0)
+}
+
+/// getpeername(fd, addr, addrlen)
+pub fn sys_getpeername(fd: i32, addr: usize, addrlen: usize) -> SyscallResult {
+    let handle = socket_handle(fd)?;
+    let peer = socket::peer_addr(handle)
+        .map_err(socket_errno)?
+        .ok_or(errno::ENOTCONN)?;
+    write_sockaddr(Some(&peer), addr, addrlen)?;
+    Ok(0)
+}
+
+/// read(2) on a socket descriptor
+pub fn read(handle: i32, buf: usize, count: usize) -> SyscallResult {
+    let count = count.min(MAX_BUFFER_SIZE);
+    check_user_buffer_mut(buf, count)?;
+    let mut buffer = vec![0u8; count];
+    let received = socket::recv(handle, &mut buffer, 0).map_err(socket_errno)?;
+    write_user_bytes(buf, &buffer[..received.min(count)])?;
+    Ok(received)
+}
+
+/// write(2) on a socket descriptor
+pub fn write(handle: i32, buf: usize, count: usize) -> SyscallResult {
+    let data = read_send_data(buf, count)?;
+    socket::send(handle, &data, 0).map_err(socket_errno)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syscall::handle_syscall;
+
+    fn setup() {
+        static FD_TABLE: spin::Once = spin::Once::new();
+        FD_TABLE.call_once(|| {
+            if !fd::is_initialized() {
+                fd::init();
+            }
+        });
+    }
+
+    /// `struct sockaddr_in` for 127.0.0.1
+    fn localhost(port: u16) -> [u8; SOCKADDR_IN_LEN] {
+        let (bytes, len) = encode_sockaddr(&SocketAddr::V4(SocketAddrV4 {
+            ip: [127, 0, 0, 1],
+            port,
+        }));
+        assert_eq!(len, SOCKADDR_IN_LEN);
+        bytes[..SOCKADDR_IN_LEN].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_sockaddr_layout() {
+        let raw = localhost(9602);
+        assert_eq!(&raw[..8], &[2, 0, 0x25, 0x82, 127, 0, 0, 1]);
+        match read_sockaddr(raw.as_ptr() as usize, raw.len()).unwrap() {
+            SocketAddr::V4(addr) => {
+                assert_eq!(addr.ip, [127, 0, 0, 1]);
+                assert_eq!(addr.port, 9602);
+            }
+            _ => panic!("expected an IPv4 address"),
+        }
+        assert_eq!(
+            read_sockaddr(raw.as_ptr() as usize, 8).unwrap_err(),
+            errno::EINVAL
+        );
+        assert_eq!(socket_errno(SocketError::WouldBlock), errno::EAGAIN);
+
+        // sockaddr_ll carries the EtherType in network byte order
+        let (raw, len) = encode_sockaddr(&SocketAddr::Packet(SocketAddrLl {
+            protocol: 0x0806,
+            ifindex: 2,
+            hatype: 1,
+            pkttype: 1,
+            addr: MacAddress::BROADCAST,
+        }));
+        assert_eq!(len, SOCKADDR_LL_LEN);
+        assert_eq!(&raw[..12], &[17, 0, 0x08, 0x06, 2, 0, 0, 0, 1, 0, 1, 6]);
+        match read_sockaddr(raw.as_ptr() as usize, len).unwrap() {
+            SocketAddr::Packet(addr) => {
+                assert_eq!(addr.protocol, 0x0806);
+                assert_eq!(addr.ifindex, 2);
+                assert_eq!(addr.addr, MacAddress::BROADCAST);
+            }
+            _ => panic!("expected a link-layer address"),
+        }
+    }
+
+    #[test]
+    fn test_socket_args_not_truncated() {
+        // AF_INET and ETH_P_ALL in the low 16 bits
+        let domain = (1 << 16) | AF_INET as usize;
+        assert_eq!(
+            socket_args(domain, SOCK_DGRAM, 0).unwrap_err(),
+            errno::EAFNOSUPPORT
+        );
+        let protocol = (1 << 16) | 0x0300;
+        assert_eq!(
+            socket_args(AF_PACKET as usize, SOCK_RAW, protocol).unwrap_err(),
+            errno::EPROTONOSUPPORT
+        );
+        assert!(socket_args(AF_PACKET as usize, SOCK_RAW, 0x0300).is_ok());
+    }
+
+    #[test]
+    fn test_udp_socket_syscalls() {
+        crate::net::loopback::init();
+        setup();
+
+        let server = handle_syscall(41, 2, SOCK_DGRAM, 0, 0, 0, 0).unwrap();
+        let client = handle_syscall(41, 2, SOCK_DGRAM | SOCK_NONBLOCK, 0, 0, 0, 0).unwrap();
+        let addr = localhost(9602);
+        handle_syscall(49, server, addr.as_ptr() as usize, addr.len(), 0, 0, 0).unwrap();
+        let client_addr = localhost(9603);
+        handle_syscall(49, client, client_addr.as_ptr() as usize, 16, 0, 0, 0).unwrap();
+
+        // A zero receive timeout is stored as "wait forever"
+        let timeout = [0u8; 16];
+        handle_syscall(
+            54,
+            server,
+            SOL_SOCKET,
+            SO_RCVTIMEO,
+            timeout.as_ptr() as usize,
+            16,
+            0,
+        )
+        .unwrap();
+        let mut value = [0xffu8; 16];
+        let mut len = 16u32;
+        handle_syscall(
+            55,
+            server,
+            SOL_SOCKET,
+            SO_RCVTIMEO,
+            value.as_mut_ptr() as usize,
+            &mut len as *mut u32 as usize,
+            0,
+        )
+        .unwrap();
+        assert_eq!((value, len), ([0u8; 16], 16));
+
+        // Non-blocking client: nothing to read yet
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            handle_syscall(0, client, buffer.as_mut_ptr() as usize, 16, 0, 0, 0),
+            Err(errno::EAGAIN)
+        );
+
+        let message = b"syscall";
+        let sent = handle_syscall(
+            44,
+            client,
+            message.as_ptr() as usize,
+            message.len(),
+            0,
+            addr.as_ptr() as usize,
+            addr.len(),
+        );
+        assert_eq!(sent, Ok(message.len()));
+        let lo = crate::net::loopback::device().unwrap();
+        while crate::net::rx::poll_device(&lo) > 0 {}
+
+        let mut src = [0u8; SOCKADDR_IN_LEN];
+        let mut src_len = src.len() as u32;
+        let received = handle_syscall(
+            45,
+            server,
+            buffer.as_mut_ptr() as usize,
+            buffer.len(),
+            0,
+            src.as_mut_ptr() as usize,
+            &mut src_len as *mut u32 as usize,
+        )
+        .unwrap();
+        assert_eq!(&buffer[..received], message);
+        assert_eq!(src_len as usize, SOCKADDR_IN_LEN);
+        assert_eq!(src, client_addr);
+
+        // Kernel pointers are rejected
+        assert_eq!(
+            handle_syscall(1, client, 0xffff_8000_0000_1000, 4, 0, 0, 0),
+            Err(errno::EFAULT)
+        );
+
+        handle_syscall(3, server, 0, 0, 0, 0, 0).unwrap();
+        handle_syscall(3, client, 0, 0, 0, 0, 0).unwrap();
+        assert_eq!(handle_syscall(50, server, 1, 0, 0, 0, 0), Err(errno::EBADF));
+    }
+
+    #[test]
+    fn test_unix_rights_syscalls() {
+        setup();
+
+        let mut sv = [0i32; 2];
+        let sv_ptr = sv.as_mut_ptr() as usize;
+        handle_syscall(53, AF_UNIX as usize, SOCK_STREAM, 0, sv_ptr, 0, 0).unwrap();
+        let (a, b) = (sv[0] as usize, sv[1] as usize);
+        let udp = handle_syscall(41, 2, SOCK_DGRAM, 0, 0, 0, 0).unwrap();
+        let udp_addr = localhost(9604);
+        handle_syscall(49, udp, udp_addr.as_ptr() as usize, 16, 0, 0, 0).unwrap();
+
+        // sendmsg(a, { "fd", SCM_RIGHTS [udp] }), then close the original
+        let data = *b"fd";
+        let iov = [data.as_ptr() as u64, data.len() as u64];
+        let control = [
+            20u64,
+            (SCM_RIGHTS as u64) << 32 | SOL_SOCKET as u64,
+            udp as u64,
+        ];
+        let msg = [
+            0,
+            0,
+            iov.as_ptr() as u64,
+            1,
+            control.as_ptr() as u64,
+            24,
+            0u64,
+        ];
+        assert_eq!(
+            handle_syscall(46, a, msg.as_ptr() as usize, 0, 0, 0, 0),
+            Ok(2)
+        );
+        handle_syscall(3, udp, 0, 0, 0, 0, 0).unwrap();
+
+        let mut buffer = [0u8; 8];
+        let iov = [buffer.as_mut_ptr() as u64, buffer.len() as u64];
+        let mut control = [0u64; 4];
+        let mut msg = [
+            0,
+            0,
+            iov.as_ptr() as u64,
+            1,
+            control.as_mut_ptr() as u64,
+            32,
+            0u64,
+        ];
+        let flags = socket::MSG_DONTWAIT as usize;
+        assert_eq!(
+            handle_syscall(47, b, msg.as_mut_ptr() as usize, flags, 0, 0, 0),
+            Ok(2)
+        );
+        assert_eq!(&buffer[..2], b"fd");
+        assert_eq!((msg[5], msg[6]), (24, 0));
+        assert_eq!(control[0], 20);
+
+        // The received descriptor refers to the UDP socket
+        let received = control[2] as i32;
+        let mut addr = [0u8; SOCKADDR_IN_LEN];
+        let mut addr_len = addr.len() as u32;
+        handle_syscall(
+            51,
+            received as usize,
+            addr.as_mut_ptr() as usize,
+            &mut addr_len as *mut u32 as usize,
+            0,
+            0,
+            0,
+        )
+        .unwrap();
+        assert_eq!(addr, udp_addr);
+
+        for fd in [received as usize, a, b] {
+            handle_syscall(3, fd, 0, 0, 0, 0, 0).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_oversized_lengths() {
+        crate::net::loopback::init();
+        setup();
+
+        let server = handle_syscall(41, 2, SOCK_DGRAM | SOCK_NONBLOCK, 0, 0, 0, 0).unwrap();
+        let client = handle_syscall(41, 2, SOCK_DGRAM, 0, 0, 0, 0).unwrap();
+        let addr = localhost(9608);
+        handle_syscall(49, server, addr.as_ptr() as usize, addr.len(), 0, 0, 0).unwrap();
+        handle_syscall(42, client, addr.as_ptr() as usize, addr.len(), 0, 0, 0).unwrap();
+
+        // Send lengths are refused before anything is allocated
+        let data = *b"big";
+        let ptr = data.as_ptr() as usize;
+        assert_eq!(
+            handle_syscall(44, client, ptr, usize::MAX, 0, 0, 0),
+            Err(errno::EMSGSIZE)
+        );
+        assert_eq!(
+            handle_syscall(1, client, ptr, usize::MAX, 0, 0, 0),
+            Err(errno::EMSGSIZE)
+        );
+        let iov = [ptr as u64, MAX_BUFFER_SIZE as u64, ptr as u64, 1];
+        let msg = [0, 0, iov.as_ptr() as u64, 2, 0, 0, 0u64];
+        assert_eq!(
+            handle_syscall(46, client, msg.as_ptr() as usize, 0, 0, 0, 0),
+            Err(errno::EMSGSIZE)
+        );
+        let iov = [ptr as u64, data.len() as u64];
+        let control = [0u64; 2];
+        let msg = [
+            0,
+            0,
+            iov.as_ptr() as u64,
+            1,
+            control.as_ptr() as u64,
+            u64::MAX,
+            0u64,
+        ];
+        assert_eq!(
+            handle_syscall(46, client, msg.as_ptr() as usize, 0, 0, 0, 0),
+            Err(errno::EINVAL)
+        );
+
+        // Receive lengths are clamped rather than allocated in full
+        let mut buffer = alloc::vec![0u8; 16];
+        let buf = buffer.as_mut_ptr() as usize;
+        assert_eq!(
+            handle_syscall(0, server, buf, usize::MAX, 0, 0, 0),
+            Err(errno::EAGAIN)
+        );
+
+        // A bad iovec fails before the datagram is consumed
+        assert_eq!(handle_syscall(1, client, ptr, data.len(), 0, 0, 0), Ok(3));
+        let lo = crate::net::loopback::device().unwrap();
+        while crate::net::rx::poll_device(&lo) > 0 {}
+        let iov = [buf as u64, 4, 0xffff_8000_0000_1000, 4];
+        let msg = [0, 0, iov.as_ptr() as u64, 2, 0, 0, 0u64];
+        assert_eq!(
+            handle_syscall(47, server, msg.as_ptr() as usize, 0, 0, 0, 0),
+            Err(errno::EFAULT)
+        );
+        assert_eq!(handle_syscall(0, server, buf, 16, 0, 0, 0), Ok(3));
+        assert_eq!(&buffer[..3], b"big");
+
+        handle_syscall(3, server, 0, 0, 0, 0, 0).unwrap();
+        handle_syscall(3, client, 0, 0, 0, 0, 0).unwrap();
+    }
+}
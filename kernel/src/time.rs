@@ -11,6 +11,9 @@ use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 /// System uptime in milliseconds
 static UPTIME_MS: AtomicU64 = AtomicU64::new(0);
 
+/// Set by the timer interrupt when kernel timers may be due
+static TIMERS_PENDING: AtomicBool = AtomicBool::new(false);
+
 /// Time subsystem initialized flag
 static TIME_INITIALIZED: AtomicBool = AtomicBool::new(false);
 
@@ -37,11 +40,21 @@ pub fn uptime_ms() -> u64 {
 }
 
 /// Increment uptime (called by timer interrupt)
+///
+/// Expired timers are only flagged here; their callbacks take locks the
+/// interrupted code may hold, so they run later from [`run_timers`].
 pub fn tick(ms: u64) {
     UPTIME_MS.fetch_add(ms, Ordering::Relaxed);
-    // Run timer processing
-    // TODO: Add interrupt disable wrapper once arch crate is properly linked
-    timer::tick();
+    TIMERS_PENDING.store(true, Ordering::Release);
+}
+
+/// Run expired timers outside interrupt context
+///
+/// Called from the kernel idle loop after each interrupt.
+pub fn run_timers() {
+    if TIMERS_PENDING.swap(false, Ordering::AcqRel) {
+        timer::tick();
+    }
 }
 
 /// Get system uptime in seconds
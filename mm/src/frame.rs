@@ -154,6 +154,38 @@ impl FrameAllocator {
         None
     }
 
+    /// Allocate physically contiguous frames
+    ///
+    /// Returns the first frame of the run, for devices that need a DMA
+    /// region larger than a frame.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<Frame> {
+        if count == 0 {
+            return None;
+        }
+
+        let mut run = 0;
+        for i in 0..self.total_frames as usize {
+            if (self.bitmap[i / 64] & (1 << (i % 64))) != 0 {
+                run = 0;
+                continue;
+            }
+
+            run += 1;
+            if run == count {
+                let first = i + 1 - count;
+                for j in first..=i {
+                    self.bitmap[j / 64] |= 1 << (j % 64);
+                }
+                self.allocated_frames += count as u64;
+                return Some(Frame {
+                    number: self.start_frame + first as u64,
+                });
+            }
+        }
+
+        None
+    }
+
     /// Deallocate a frame
     pub fn deallocate_frame(&mut self, frame: Frame) {
         self.mark_free(frame.number);
@@ -194,6 +226,19 @@ pub fn deallocate_frame(frame: Frame) {
     FRAME_ALLOCATOR.lock().deallocate_frame(frame);
 }
 
+/// Allocate physically contiguous frames
+pub fn allocate_contiguous(count: usize) -> Option<Frame> {
+    FRAME_ALLOCATOR.lock().allocate_contiguous(count)
+}
+
+/// Deallocate a run of contiguous frames
+pub fn deallocate_contiguous(first: Frame, count: usize) {
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    for number in first.number()..first.number() + count as u64 {
+        allocator.deallocate_frame(Frame { number });
+    }
+}
+
 /// Get memory statistics
 pub fn get_stats() -> (u64, u64, u64) {
     let allocator = FRAME_ALLOCATOR.lock();
@@ -28,12 +28,15 @@ pub extern "C" fn _start() -> ! {
     // Initialize device drivers
     rinux_drivers::init();
 
+    // Configure networking now that devices are registered
+    rinux_kernel::net::autoconfigure();
+
     rinux_kernel::printk::printk("Rinux kernel initialization complete!\n");
 
-    // Enter main kernel loop
-    #[allow(clippy::never_loop)]
+    // Enter main kernel loop: run timers each time an interrupt wakes us
     loop {
-        rinux_arch_x86::halt();
+        rinux_kernel::time::run_timers();
+        rinux_arch_x86::wait_for_interrupt();
     }
 }
 